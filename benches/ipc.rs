@@ -0,0 +1,399 @@
+// hinix/benches/ipc.rs
+//
+// Benchmarks of the IPC transports.
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Compares the latency and throughput of the IPC transports for a range
+//! of message sizes:
+//!
+//! - `msgqueue` - A Posix message queue
+//! - `pipe` - An unnamed pipe, as a byte stream
+//! - `packet_pipe` - An unnamed pipe in packet mode
+//! - `seqpacket` - A pair of connected Unix seqpacket sockets
+//! - `shm_ring` - A ring buffer in shared memory, with an eventfd to wake
+//!   the reader
+//!
+//! The latency is the time for a round trip to an echo thread. The
+//! throughput is for a stream of messages to a reader thread.
+//!
+//! Run with:
+//!
+//! ```text
+//! $ cargo bench --bench ipc
+//! ```
+//!
+//! A subset can be picked with a filter, like `cargo bench -- latency/shm`.
+//!
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    println!("The IPC benchmarks only run on Linux");
+}
+
+#[cfg(target_os = "linux")]
+criterion::criterion_main!(linux::benches);
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use criterion::{criterion_group, BenchmarkId, Criterion, Throughput};
+    use hinix::{
+        eventfd::EventFd, mmap::MmapMut, msgqueue::MsgQueue, pipe, seqpacket::SeqPacket,
+        shm::SharedMemory, Timeout,
+    };
+    use std::{
+        io::{self, Read, Write},
+        process,
+        sync::{
+            atomic::{AtomicU64, AtomicUsize, Ordering},
+            mpsc,
+        },
+        thread,
+        time::Instant,
+    };
+
+    /// The message sizes to test. The largest is the most that a packet
+    /// pipe can send in one message.
+    const SIZES: &[usize] = &[16, 256, 4096];
+
+    /// The number of messages a queue can hold
+    const MQ_DEPTH: usize = 8;
+
+    /// The sending end of a transport.
+    ///
+    /// Dropping it tells the receiver that the stream is done.
+    trait Sender: Send {
+        fn send(&mut self, msg: &[u8]);
+    }
+
+    /// The receiving end of a transport.
+    trait Receiver: Send {
+        /// Receives a message into the buffer, returning its size, or
+        /// zero when the sender is done.
+        fn recv(&mut self, buf: &mut [u8]) -> usize;
+    }
+
+    type Channel = (Box<dyn Sender>, Box<dyn Receiver>);
+
+    /// Opens a channel that can carry messages up to a size.
+    type OpenFn = fn(usize) -> Channel;
+
+    /// The transports, by name
+    const TRANSPORTS: &[(&str, OpenFn)] = &[
+        ("msgqueue", msgqueue),
+        ("pipe", stream_pipe),
+        ("packet_pipe", packet_pipe),
+        ("seqpacket", seqpacket),
+        ("shm_ring", shm_ring),
+    ];
+
+    // ----- Message queue -----
+
+    struct MqSender(MsgQueue);
+
+    impl Sender for MqSender {
+        fn send(&mut self, msg: &[u8]) {
+            self.0.send(msg).unwrap();
+        }
+    }
+
+    impl Drop for MqSender {
+        fn drop(&mut self) {
+            // A queue has no EOF, so an empty message ends the stream
+            let _ = self.0.send_timeout(b"", 0, Timeout::ZERO);
+        }
+    }
+
+    struct MqReceiver(MsgQueue);
+
+    impl Receiver for MqReceiver {
+        fn recv(&mut self, buf: &mut [u8]) -> usize {
+            self.0.receive(buf).unwrap()
+        }
+    }
+
+    fn msgqueue(size: usize) -> Channel {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNT.fetch_add(1, Ordering::Relaxed);
+        let name = format!("/hinix-bench-{}-{}", process::id(), n);
+
+        let rx = MsgQueue::create(&name, MQ_DEPTH, size).unwrap();
+        let tx = MsgQueue::open(&name).unwrap();
+        MsgQueue::unlink(&name).unwrap();
+        (Box::new(MqSender(tx)), Box::new(MqReceiver(rx)))
+    }
+
+    // ----- Pipes -----
+
+    struct PipeSender(pipe::WritePipe);
+
+    impl Sender for PipeSender {
+        fn send(&mut self, msg: &[u8]) {
+            self.0.write_all(msg).unwrap();
+        }
+    }
+
+    /// The reading end of a pipe used as a stream, which relies on the
+    /// reader's buffer being the size of the message.
+    struct StreamReceiver(pipe::ReadPipe);
+
+    impl Receiver for StreamReceiver {
+        fn recv(&mut self, buf: &mut [u8]) -> usize {
+            match self.0.read_exact(buf) {
+                Ok(()) => buf.len(),
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => 0,
+                Err(err) => panic!("{}", err),
+            }
+        }
+    }
+
+    fn stream_pipe(_size: usize) -> Channel {
+        let (wr, rd) = pipe::pipe().unwrap();
+        (Box::new(PipeSender(wr)), Box::new(StreamReceiver(rd)))
+    }
+
+    struct PacketReceiver(pipe::ReadPipe);
+
+    impl Receiver for PacketReceiver {
+        fn recv(&mut self, buf: &mut [u8]) -> usize {
+            self.0.read(buf).unwrap()
+        }
+    }
+
+    fn packet_pipe(_size: usize) -> Channel {
+        let (wr, rd) = pipe::packet_pipe().unwrap();
+        (Box::new(PipeSender(wr)), Box::new(PacketReceiver(rd)))
+    }
+
+    // ----- Seqpacket sockets -----
+
+    struct SockSender(SeqPacket);
+
+    impl Sender for SockSender {
+        fn send(&mut self, msg: &[u8]) {
+            self.0.send(msg).unwrap();
+        }
+    }
+
+    struct SockReceiver(SeqPacket);
+
+    impl Receiver for SockReceiver {
+        fn recv(&mut self, buf: &mut [u8]) -> usize {
+            self.0.recv(buf).unwrap()
+        }
+    }
+
+    fn seqpacket(_size: usize) -> Channel {
+        let (tx, rx) = SeqPacket::pair().unwrap();
+        (Box::new(SockSender(tx)), Box::new(SockReceiver(rx)))
+    }
+
+    // ----- Shared memory ring -----
+
+    /// The size of the ring header, with the write and read positions on
+    /// separate cache lines.
+    const RING_HDR: usize = 128;
+
+    /// The size of the data in the ring
+    const RING_SIZE: usize = 64 * 1024;
+
+    /// One end of a single-producer, single-consumer ring of messages in
+    /// shared memory.
+    ///
+    /// Each message is a native-endian u32 length, followed by the data,
+    /// and can wrap around the end of the ring. The positions only ever
+    /// increase, so the ring is empty when they're equal. Each end has
+    /// its own mapping of the memory, like it would in separate processes.
+    struct Ring {
+        map: MmapMut,
+        evfd: EventFd,
+    }
+
+    impl Ring {
+        /// The total number of bytes written to the ring
+        fn head(&self) -> &AtomicU64 {
+            unsafe { &*(self.map.as_ptr() as *const AtomicU64) }
+        }
+
+        /// The total number of bytes read from the ring
+        fn tail(&self) -> &AtomicU64 {
+            unsafe { &*(self.map.as_ptr().add(64) as *const AtomicU64) }
+        }
+
+        fn copy_in(&mut self, pos: u64, src: &[u8]) {
+            let off = (pos % RING_SIZE as u64) as usize;
+            let n = src.len().min(RING_SIZE - off);
+            let data = &mut self.map[RING_HDR..];
+            data[off..off + n].copy_from_slice(&src[..n]);
+            data[..src.len() - n].copy_from_slice(&src[n..]);
+        }
+
+        fn copy_out(&self, pos: u64, dst: &mut [u8]) {
+            let off = (pos % RING_SIZE as u64) as usize;
+            let n = dst.len().min(RING_SIZE - off);
+            let data = &self.map[RING_HDR..];
+            dst[..n].copy_from_slice(&data[off..off + n]);
+            let len = dst.len();
+            dst[n..].copy_from_slice(&data[..len - n]);
+        }
+    }
+
+    struct RingSender(Ring);
+
+    impl RingSender {
+        /// Writes the message if there's room for it.
+        fn try_send(&mut self, msg: &[u8]) -> bool {
+            let ring = &mut self.0;
+            let head = ring.head().load(Ordering::Relaxed);
+            let tail = ring.tail().load(Ordering::Acquire);
+            let need = 4 + msg.len() as u64;
+            if RING_SIZE as u64 - (head - tail) < need {
+                return false;
+            }
+            ring.copy_in(head, &(msg.len() as u32).to_ne_bytes());
+            ring.copy_in(head + 4, msg);
+            ring.head().store(head + need, Ordering::Release);
+            ring.evfd.write(1).unwrap();
+            true
+        }
+    }
+
+    impl Sender for RingSender {
+        fn send(&mut self, msg: &[u8]) {
+            // The reader doesn't signal when it makes room
+            while !self.try_send(msg) {
+                thread::yield_now();
+            }
+        }
+    }
+
+    impl Drop for RingSender {
+        fn drop(&mut self) {
+            self.try_send(&[]);
+        }
+    }
+
+    struct RingReceiver(Ring);
+
+    impl Receiver for RingReceiver {
+        fn recv(&mut self, buf: &mut [u8]) -> usize {
+            let ring = &self.0;
+            let tail = ring.tail().load(Ordering::Relaxed);
+            while ring.head().load(Ordering::Acquire) == tail {
+                ring.evfd.read().unwrap();
+            }
+            let mut len = [0u8; 4];
+            ring.copy_out(tail, &mut len);
+            let len = u32::from_ne_bytes(len) as usize;
+            ring.copy_out(tail + 4, &mut buf[..len]);
+            ring.tail().store(tail + 4 + len as u64, Ordering::Release);
+            len
+        }
+    }
+
+    fn shm_ring(_size: usize) -> Channel {
+        let shm = SharedMemory::create("hinix-bench", RING_HDR + RING_SIZE).unwrap();
+        let evfd = EventFd::new(0).unwrap();
+        // The head and tail hand each part of the ring from one side to
+        // the other, so they never use the same bytes at the same time.
+        let tx = Ring {
+            map: unsafe { shm.map() }.unwrap(),
+            evfd: evfd.try_clone().unwrap(),
+        };
+        let rx = Ring {
+            map: unsafe { shm.map() }.unwrap(),
+            evfd,
+        };
+        (Box::new(RingSender(tx)), Box::new(RingReceiver(rx)))
+    }
+
+    // ----- Benchmarks -----
+
+    /// Measures the round trip of a message to a thread that echoes it
+    /// back.
+    fn latency(c: &mut Criterion) {
+        let mut group = c.benchmark_group("latency");
+        for &size in SIZES {
+            for (name, open) in TRANSPORTS {
+                let (mut tx, mut rx) = open(size);
+                let (mut echo_tx, mut echo_rx) = open(size);
+
+                let echo = thread::spawn(move || {
+                    let mut buf = vec![0u8; size];
+                    loop {
+                        match rx.recv(&mut buf) {
+                            0 => break,
+                            n => echo_tx.send(&buf[..n]),
+                        }
+                    }
+                });
+
+                let msg = vec![0x55u8; size];
+                let mut buf = vec![0u8; size];
+                group.bench_function(BenchmarkId::new(*name, size), |b| {
+                    b.iter(|| {
+                        tx.send(&msg);
+                        echo_rx.recv(&mut buf)
+                    })
+                });
+
+                drop(tx);
+                echo.join().unwrap();
+            }
+        }
+        group.finish();
+    }
+
+    /// Measures a stream of messages to a thread that reads them.
+    fn throughput(c: &mut Criterion) {
+        let mut group = c.benchmark_group("throughput");
+        for &size in SIZES {
+            group.throughput(Throughput::Bytes(size as u64));
+            for (name, open) in TRANSPORTS {
+                let (mut tx, mut rx) = open(size);
+
+                // The reader is told how many messages to expect, and
+                // reports back when it has them all.
+                let (count_tx, count_rx) = mpsc::channel::<u64>();
+                let (done_tx, done_rx) = mpsc::channel();
+
+                let reader = thread::spawn(move || {
+                    let mut buf = vec![0u8; size];
+                    while let Ok(n) = count_rx.recv() {
+                        for _ in 0..n {
+                            rx.recv(&mut buf);
+                        }
+                        done_tx.send(()).unwrap();
+                    }
+                });
+
+                let msg = vec![0x55u8; size];
+                group.bench_function(BenchmarkId::new(*name, size), |b| {
+                    b.iter_custom(|iters| {
+                        count_tx.send(iters).unwrap();
+                        let start = Instant::now();
+                        for _ in 0..iters {
+                            tx.send(&msg);
+                        }
+                        done_rx.recv().unwrap();
+                        start.elapsed()
+                    })
+                });
+
+                drop(count_tx);
+                reader.join().unwrap();
+            }
+        }
+        group.finish();
+    }
+
+    criterion_group!(benches, latency, throughput);
+}
@@ -0,0 +1,836 @@
+// hinix/src/async_io.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Runtime-agnostic async wrappers using async-io.
+//!
+//! This provides the same set of types as the `tokio` module, but built
+//! on `async_io::Async<T>`, which is the reactor used by smol and
+//! async-std. It doesn't require any particular executor, so the futures
+//! can be run with any of them, or with `async_io::block_on()`.
+//!
+//! Each wrapper takes ownership of one of the crate's handle types and
+//! puts it into non-blocking mode. The original object can be reached
+//! with `get_ref()` to use the calls that don't block, or taken back
+//! with `into_inner()`.
+//!
+//! The pipes and FIFO are used directly as `Async<T>`, which implements
+//! the futures `AsyncRead` and `AsyncWrite` traits for them. The event
+//! sources, like timers, signals, filesystem watches, and message queues,
+//! implement the futures `Stream` trait. In the other direction, the
+//! message queue and the [`AsyncPacketWriter`] implement the futures
+//! `Sink` trait.
+//!
+//! This requires the `async-io` feature.
+//!
+
+use crate::{
+    codec::PacketSender,
+    eventfd::EventFd,
+    fifo::Fifo,
+    inotify::{Event, Inotify, WatchMask},
+    pidfd::PidFd,
+    pipe::{self, ReadPipe, WritePipe},
+    shutdown::ShutdownSignal,
+    signalfd::{SigInfo, SignalFd},
+    timerfd::TimerFd,
+    Error, Result,
+};
+use async_io::{Async, IoSafe};
+use futures_core::Stream;
+use futures_sink::Sink;
+use nix::{
+    sys::{inotify::WatchDescriptor, signal::SigSet, wait::WaitStatus},
+    unistd::Pid,
+};
+use std::{
+    collections::VecDeque,
+    future,
+    os::unix::io::AsFd,
+    path::Path,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+#[cfg(target_os = "linux")]
+use crate::msgqueue::MsgQueue;
+
+// The pipe types never give out mutable access to their handles, so they
+// can't be closed or replaced out from under the reactor.
+unsafe impl IoSafe for ReadPipe {}
+unsafe impl IoSafe for WritePipe {}
+unsafe impl IoSafe for Fifo {}
+
+/// The async read-end of a pipe.
+pub type AsyncReadPipe = Async<ReadPipe>;
+
+/// The async write-end of a pipe.
+pub type AsyncWritePipe = Async<WritePipe>;
+
+/// An async FIFO (named pipe).
+pub type AsyncFifo = Async<Fifo>;
+
+/// Creates a pipe with async ends.
+pub fn pipe() -> Result<(AsyncWritePipe, AsyncReadPipe)> {
+    let (wr, rd) = pipe::pipe()?;
+    Ok((register(wr)?, register(rd)?))
+}
+
+/// Creates a packet pipe with an async reader, and a writer that's a
+/// `Sink` of messages.
+///
+/// Each message sent through the writer is read back as a single packet.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub fn packet_pipe() -> Result<(AsyncPacketWriter, AsyncReadPipe)> {
+    let (wr, rd) = pipe::packet_pipe()?;
+    Ok((AsyncPacketWriter::new(wr)?, register(rd)?))
+}
+
+/// The async write-end of a packet pipe, as a `Sink` of messages.
+///
+/// Each message is written as a single packet, so it can be no larger
+/// than [`PIPE_BUF`](crate::pipe::PIPE_BUF).
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(Debug)]
+pub struct AsyncPacketWriter {
+    /// The registered write end of the pipe
+    pipe: AsyncWritePipe,
+    /// A message accepted by the sink, but not yet written
+    pending: Option<Vec<u8>>,
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl AsyncPacketWriter {
+    /// Creates an async packet writer from the write end of a packet
+    /// pipe.
+    ///
+    /// This puts the handle into non-blocking mode.
+    pub fn new(wr: WritePipe) -> Result<Self> {
+        Ok(Self {
+            pipe: register(wr)?,
+            pending: None,
+        })
+    }
+
+    /// Gets a reference to the pipe.
+    pub fn get_ref(&self) -> &WritePipe {
+        self.pipe.get_ref()
+    }
+
+    /// Unregisters the pipe, and returns it.
+    ///
+    /// It is left in non-blocking mode. A message given to the sink that
+    /// wasn't flushed is dropped.
+    pub fn into_inner(self) -> Result<WritePipe> {
+        self.pipe.into_inner().map_err(Error::from)
+    }
+}
+
+/// Puts the handle into non-blocking mode, and registers it with the
+/// reactor.
+fn register<T: AsFd>(inner: T) -> Result<Async<T>> {
+    Async::new(inner).map_err(Error::from)
+}
+
+/// Runs the operation on the handle, waiting for it to be readable
+/// whenever it would block.
+fn poll_read_with<T, R, F>(io: &Async<T>, cx: &mut Context<'_>, mut f: F) -> Poll<Result<R>>
+where
+    F: FnMut(&T) -> Result<R>,
+{
+    loop {
+        match f(io.get_ref()) {
+            Err(err) if err == Error::EAGAIN => (),
+            res => return Poll::Ready(res),
+        }
+        ready!(io.poll_readable(cx)).map_err(Error::from)?;
+    }
+}
+
+/// Runs an operation that needs mutable access to the handle, waiting
+/// for it to be readable whenever it would block.
+///
+/// # Safety
+///
+/// The operation must not close or replace the handle.
+unsafe fn poll_read_with_mut<T, R, F>(
+    io: &mut Async<T>,
+    cx: &mut Context<'_>,
+    mut f: F,
+) -> Poll<Result<R>>
+where
+    F: FnMut(&mut T) -> Result<R>,
+{
+    loop {
+        match f(io.get_mut()) {
+            Err(err) if err == Error::EAGAIN => (),
+            res => return Poll::Ready(res),
+        }
+        ready!(io.poll_readable(cx)).map_err(Error::from)?;
+    }
+}
+
+/// Runs the operation on the handle, waiting for it to be writable
+/// whenever it would block.
+fn poll_write_with<T, R, F>(io: &Async<T>, cx: &mut Context<'_>, mut f: F) -> Poll<Result<R>>
+where
+    F: FnMut(&T) -> Result<R>,
+{
+    loop {
+        match f(io.get_ref()) {
+            Err(err) if err == Error::EAGAIN => (),
+            res => return Poll::Ready(res),
+        }
+        ready!(io.poll_writable(cx)).map_err(Error::from)?;
+    }
+}
+
+/// Sends the message held by a sink, if there is one, waiting for the
+/// handle to be writable.
+///
+/// The message is dropped once it's sent, or if sending it fails.
+fn poll_send_pending<T: PacketSender>(
+    io: &Async<T>,
+    pending: &mut Option<Vec<u8>>,
+    cx: &mut Context<'_>,
+) -> Poll<Result<()>> {
+    if let Some(msg) = pending.as_deref() {
+        let res = ready!(poll_write_with(io, cx, |inner| inner.send_packet(msg)));
+        *pending = None;
+        res?;
+    }
+    Poll::Ready(Ok(()))
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// An async event object.
+#[derive(Debug)]
+pub struct AsyncEventFd(Async<EventFd>);
+
+impl AsyncEventFd {
+    /// Creates an async event object from an existing one.
+    ///
+    /// This puts the handle into non-blocking mode.
+    pub fn new(evtfd: EventFd) -> Result<Self> {
+        Ok(Self(register(evtfd)?))
+    }
+
+    /// Waits for the event object to have a value, and reads it.
+    pub async fn read(&self) -> Result<u64> {
+        future::poll_fn(|cx| self.poll_read(cx)).await
+    }
+
+    /// Polls for a value from the event object.
+    pub fn poll_read(&self, cx: &mut Context<'_>) -> Poll<Result<u64>> {
+        poll_read_with(&self.0, cx, EventFd::read)
+    }
+
+    /// Writes a value to the event object.
+    ///
+    /// This only waits if the value would overflow the counter.
+    pub async fn write(&self, val: u64) -> Result<()> {
+        future::poll_fn(|cx| poll_write_with(&self.0, cx, |evtfd| evtfd.write(val))).await
+    }
+
+    /// Gets a reference to the event object.
+    pub fn get_ref(&self) -> &EventFd {
+        self.0.get_ref()
+    }
+
+    /// Unregisters the event object, and returns it.
+    ///
+    /// It is left in non-blocking mode.
+    pub fn into_inner(self) -> Result<EventFd> {
+        self.0.into_inner().map_err(Error::from)
+    }
+}
+
+/// An async timer.
+#[derive(Debug)]
+pub struct AsyncTimerFd(Async<TimerFd>);
+
+impl AsyncTimerFd {
+    /// Creates an async timer from an existing one.
+    ///
+    /// This puts the handle into non-blocking mode. The timer can be
+    /// armed before or after, through [`AsyncTimerFd::get_ref()`].
+    pub fn new(timer: TimerFd) -> Result<Self> {
+        Ok(Self(register(timer)?))
+    }
+
+    /// Waits for the timer to expire, and returns the number of times it
+    /// expired since the last wait.
+    pub async fn wait(&self) -> Result<u64> {
+        future::poll_fn(|cx| self.poll_wait(cx)).await
+    }
+
+    /// Polls for the timer to expire.
+    pub fn poll_wait(&self, cx: &mut Context<'_>) -> Poll<Result<u64>> {
+        poll_read_with(&self.0, cx, TimerFd::wait)
+    }
+
+    /// Gets a reference to the timer.
+    pub fn get_ref(&self) -> &TimerFd {
+        self.0.get_ref()
+    }
+
+    /// Unregisters the timer, and returns it.
+    ///
+    /// It is left in non-blocking mode.
+    pub fn into_inner(self) -> Result<TimerFd> {
+        self.0.into_inner().map_err(Error::from)
+    }
+}
+
+/// An async signal handle.
+#[derive(Debug)]
+pub struct AsyncSignalFd(Async<SignalFd>);
+
+impl AsyncSignalFd {
+    /// Creates an async signal handle from an existing one.
+    ///
+    /// This puts the handle into non-blocking mode. Note that the signals
+    /// must be blocked in all the threads of the process, including the
+    /// reactor thread, not just the one that created the handle.
+    pub fn new(sfd: SignalFd) -> Result<Self> {
+        Ok(Self(register(sfd)?))
+    }
+
+    /// Waits for, and reads, the next signal.
+    pub async fn read(&self) -> Result<SigInfo> {
+        future::poll_fn(|cx| self.poll_read(cx)).await
+    }
+
+    /// Polls for the next signal.
+    pub fn poll_read(&self, cx: &mut Context<'_>) -> Poll<Result<SigInfo>> {
+        poll_read_with(&self.0, cx, SignalFd::read)
+    }
+
+    /// Changes the set of signals received by the handle.
+    pub fn set_mask(&mut self, signals: &SigSet) -> Result<()> {
+        // Changing the mask keeps the same handle
+        unsafe { self.0.get_mut() }.set_mask(signals)
+    }
+
+    /// Gets a reference to the signal handle.
+    pub fn get_ref(&self) -> &SignalFd {
+        self.0.get_ref()
+    }
+
+    /// Unregisters the signal handle, and returns it.
+    ///
+    /// It is left in non-blocking mode.
+    pub fn into_inner(self) -> Result<SignalFd> {
+        self.0.into_inner().map_err(Error::from)
+    }
+}
+
+/// An async set of filesystem watches.
+#[derive(Debug)]
+pub struct AsyncInotify {
+    /// The registered watches
+    ino: Async<Inotify>,
+    /// Events read but not yet returned by the stream
+    pending: VecDeque<Event>,
+}
+
+impl AsyncInotify {
+    /// Creates an async set of watches from an existing one.
+    ///
+    /// This puts the handle into non-blocking mode.
+    pub fn new(ino: Inotify) -> Result<Self> {
+        Ok(Self {
+            ino: register(ino)?,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Adds a watch on the file or directory, for the events in the mask.
+    pub fn add_watch<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        mask: WatchMask,
+    ) -> Result<WatchDescriptor> {
+        unsafe { self.ino.get_mut() }.add_watch(path, mask)
+    }
+
+    /// Adds a watch on the directory, all of its subdirectories, and any
+    /// directories later created under it.
+    pub fn add_watch_recursive<P: AsRef<Path>>(&mut self, path: P, mask: WatchMask) -> Result<()> {
+        unsafe { self.ino.get_mut() }.add_watch_recursive(path, mask)
+    }
+
+    /// Removes a watch.
+    pub fn remove_watch(&mut self, wd: WatchDescriptor) -> Result<()> {
+        unsafe { self.ino.get_mut() }.remove_watch(wd)
+    }
+
+    /// Waits for, and reads, the next batch of events.
+    ///
+    /// As with [`Inotify::read_events()`], the batch can be empty.
+    pub async fn read_events(&mut self) -> Result<Vec<Event>> {
+        future::poll_fn(|cx| self.poll_read_events(cx)).await
+    }
+
+    /// Polls for the next batch of events.
+    pub fn poll_read_events(&mut self, cx: &mut Context<'_>) -> Poll<Result<Vec<Event>>> {
+        // Any left over from the stream come first
+        if !self.pending.is_empty() {
+            return Poll::Ready(Ok(self.pending.drain(..).collect()));
+        }
+        // Reading events keeps the same handle
+        unsafe { poll_read_with_mut(&mut self.ino, cx, Inotify::read_events) }
+    }
+
+    /// Gets a reference to the watches.
+    pub fn get_ref(&self) -> &Inotify {
+        self.ino.get_ref()
+    }
+
+    /// Unregisters the watches, and returns them.
+    ///
+    /// The handle is left in non-blocking mode.
+    pub fn into_inner(self) -> Result<Inotify> {
+        self.ino.into_inner().map_err(Error::from)
+    }
+}
+
+/// An async process handle.
+#[derive(Debug)]
+pub struct AsyncPidFd(Async<PidFd>);
+
+impl AsyncPidFd {
+    /// Creates an async process handle from an existing one.
+    pub fn new(pidfd: PidFd) -> Result<Self> {
+        Ok(Self(register(pidfd)?))
+    }
+
+    /// Opens an async handle to an existing process.
+    pub fn open(pid: Pid) -> Result<Self> {
+        Self::new(PidFd::open(pid)?)
+    }
+
+    /// Waits for the process to exit, without reaping it.
+    ///
+    /// This works for any process, not just children of the caller.
+    pub async fn exited(&self) -> Result<()> {
+        future::poll_fn(|cx| self.poll_exited(cx)).await
+    }
+
+    /// Polls for the process to exit.
+    pub fn poll_exited(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.0.poll_readable(cx).map_err(Error::from)
+    }
+
+    /// Waits for the process to exit and reaps it, returning its status.
+    ///
+    /// The process must be a child of the caller, otherwise this fails
+    /// with `ECHILD`.
+    pub async fn wait(&self) -> Result<WaitStatus> {
+        self.exited().await?;
+        self.0.get_ref().wait()
+    }
+
+    /// Gets a reference to the process handle.
+    pub fn get_ref(&self) -> &PidFd {
+        self.0.get_ref()
+    }
+
+    /// Unregisters the process handle, and returns it.
+    pub fn into_inner(self) -> Result<PidFd> {
+        self.0.into_inner().map_err(Error::from)
+    }
+}
+
+/// An async signal to shut down.
+#[derive(Debug)]
+pub struct AsyncShutdownSignal(Async<ShutdownSignal>);
+
+impl AsyncShutdownSignal {
+    /// Creates an async shutdown signal from an existing one.
+    ///
+    /// A clone of the signal can be kept to trigger it.
+    pub fn new(shutdown: ShutdownSignal) -> Result<Self> {
+        Ok(Self(register(shutdown)?))
+    }
+
+    /// Waits for the signal to be triggered.
+    pub async fn wait(&self) -> Result<()> {
+        future::poll_fn(|cx| self.poll_wait(cx)).await
+    }
+
+    /// Polls for the signal to be triggered.
+    pub fn poll_wait(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.0.poll_readable(cx).map_err(Error::from)
+    }
+
+    /// Gets a reference to the shutdown signal.
+    pub fn get_ref(&self) -> &ShutdownSignal {
+        self.0.get_ref()
+    }
+
+    /// Unregisters the shutdown signal, and returns it.
+    pub fn into_inner(self) -> Result<ShutdownSignal> {
+        self.0.into_inner().map_err(Error::from)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// An async Posix message queue.
+///
+/// This relies on the queue descriptor being a file handle, which is
+/// only the case on Linux.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct AsyncMsgQueue {
+    /// The registered queue
+    mq: Async<MsgQueue>,
+    /// A message accepted by the sink, but not yet sent
+    pending: Option<Vec<u8>>,
+}
+
+#[cfg(target_os = "linux")]
+impl AsyncMsgQueue {
+    /// Creates an async message queue from one that's already open.
+    ///
+    /// This puts the queue into non-blocking mode.
+    pub fn new(mq: MsgQueue) -> Result<Self> {
+        Ok(Self {
+            mq: register(mq)?,
+            pending: None,
+        })
+    }
+
+    /// Sends a message to the queue with the default priority, waiting
+    /// for space if the queue is full.
+    pub async fn send<M: AsRef<[u8]>>(&self, msg: M) -> Result<()> {
+        self.send_with_priority(msg, crate::msgqueue::DEFAULT_PRIO)
+            .await
+    }
+
+    /// Sends a message to the queue, waiting for space if the queue is
+    /// full.
+    pub async fn send_with_priority<M: AsRef<[u8]>>(&self, msg: M, prio: u32) -> Result<()> {
+        let msg = msg.as_ref();
+        future::poll_fn(|cx| poll_write_with(&self.mq, cx, |mq| mq.send_with_priority(msg, prio)))
+            .await
+    }
+
+    /// Waits for, and receives, a message into the buffer.
+    pub async fn receive(&self, msg: &mut [u8]) -> Result<usize> {
+        let mut prio = 0;
+        self.receive_with_priority(msg, &mut prio).await
+    }
+
+    /// Waits for, and receives, a message and its priority.
+    pub async fn receive_with_priority(&self, msg: &mut [u8], prio: &mut u32) -> Result<usize> {
+        future::poll_fn(|cx| poll_read_with(&self.mq, cx, |mq| mq.receive_with_priority(msg, prio)))
+            .await
+    }
+
+    /// Waits for, and receives, a message as a byte vector.
+    pub async fn receive_bytes(&self) -> Result<Vec<u8>> {
+        future::poll_fn(|cx| self.poll_receive_bytes(cx)).await
+    }
+
+    /// Polls for a message as a byte vector.
+    pub fn poll_receive_bytes(&self, cx: &mut Context<'_>) -> Poll<Result<Vec<u8>>> {
+        poll_read_with(&self.mq, cx, MsgQueue::receive_bytes)
+    }
+
+    /// Gets a reference to the queue.
+    pub fn get_ref(&self) -> &MsgQueue {
+        self.mq.get_ref()
+    }
+
+    /// Unregisters the queue, and returns it.
+    ///
+    /// It is left in non-blocking mode. A message given to the sink that
+    /// wasn't flushed is dropped.
+    pub fn into_inner(self) -> Result<MsgQueue> {
+        self.mq.into_inner().map_err(Error::from)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Streams
+
+impl Stream for AsyncTimerFd {
+    /// The number of times the timer expired since the last item.
+    type Item = Result<u64>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_wait(cx).map(Some)
+    }
+}
+
+impl Stream for AsyncSignalFd {
+    type Item = Result<SigInfo>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_read(cx).map(Some)
+    }
+}
+
+impl Stream for AsyncInotify {
+    /// The events, one at a time, rather than in batches.
+    type Item = Result<Event>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        while this.pending.is_empty() {
+            match ready!(this.poll_read_events(cx)) {
+                Ok(events) => this.pending.extend(events),
+                Err(err) => return Poll::Ready(Some(Err(err))),
+            }
+        }
+        Poll::Ready(this.pending.pop_front().map(Ok))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Stream for AsyncMsgQueue {
+    /// The messages received from the queue.
+    type Item = Result<Vec<u8>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_receive_bytes(cx).map(Some)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Sinks
+//
+// A sink holds one message at a time. It's copied when it's accepted,
+// then sent when the sink is flushed, or before the next one is taken.
+
+#[cfg(target_os = "linux")]
+impl<M: AsRef<[u8]>> Sink<M> for AsyncMsgQueue {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        poll_send_pending(&this.mq, &mut this.pending, cx)
+    }
+
+    /// Accepts a message to send with the default priority.
+    ///
+    /// This fails with `EMSGSIZE` if the message is larger than the
+    /// queue allows.
+    fn start_send(self: Pin<&mut Self>, msg: M) -> Result<()> {
+        let msg = msg.as_ref();
+        if msg.len() > self.mq.get_ref().max_send_size() {
+            return Err(Error::EMSGSIZE);
+        }
+        self.get_mut().pending = Some(msg.to_vec());
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        poll_send_pending(&this.mq, &mut this.pending, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        poll_send_pending(&this.mq, &mut this.pending, cx)
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl<M: AsRef<[u8]>> Sink<M> for AsyncPacketWriter {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        poll_send_pending(&this.pipe, &mut this.pending, cx)
+    }
+
+    /// Accepts a message to write as a single packet.
+    ///
+    /// This fails with `EMSGSIZE` if the message is larger than
+    /// `PIPE_BUF`.
+    fn start_send(self: Pin<&mut Self>, msg: M) -> Result<()> {
+        let msg = msg.as_ref();
+        if msg.len() > self.pipe.get_ref().max_send_size() {
+            return Err(Error::EMSGSIZE);
+        }
+        self.get_mut().pending = Some(msg.to_vec());
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        poll_send_pending(&this.pipe, &mut this.pending, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        poll_send_pending(&this.pipe, &mut this.pending, cx)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ClockId;
+    use futures_lite::{AsyncReadExt, AsyncWriteExt, StreamExt};
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn test_eventfd() {
+        async_io::block_on(async {
+            let evtfd = AsyncEventFd::new(EventFd::new(0).unwrap()).unwrap();
+            let other = evtfd.get_ref().try_clone().unwrap();
+
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                other.write(5).unwrap();
+            });
+            assert_eq!(5, evtfd.read().await.unwrap());
+
+            evtfd.write(2).await.unwrap();
+            assert_eq!(2, evtfd.read().await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_timerfd() {
+        async_io::block_on(async {
+            let timer = AsyncTimerFd::new(TimerFd::new(ClockId::CLOCK_MONOTONIC).unwrap()).unwrap();
+            let start = Instant::now();
+            timer
+                .get_ref()
+                .set_oneshot(Duration::from_millis(20))
+                .unwrap();
+            assert_eq!(1, timer.wait().await.unwrap());
+            assert!(start.elapsed() >= Duration::from_millis(20));
+        });
+    }
+
+    #[test]
+    fn test_shutdown() {
+        async_io::block_on(async {
+            let shutdown = ShutdownSignal::with_signals(&[]).unwrap();
+            let other = shutdown.clone();
+            let shutdown = AsyncShutdownSignal::new(shutdown).unwrap();
+
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                other.trigger();
+            });
+            shutdown.wait().await.unwrap();
+            assert!(shutdown.get_ref().is_triggered());
+        });
+    }
+
+    #[test]
+    fn test_pipe() {
+        async_io::block_on(async {
+            let (mut wr, mut rd) = pipe().unwrap();
+
+            wr.write_all(b"hello").await.unwrap();
+            drop(wr);
+
+            let mut buf = Vec::new();
+            rd.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(b"hello", buf.as_slice());
+        });
+    }
+
+    #[test]
+    fn test_msgqueue() {
+        async_io::block_on(async {
+            const NAME: &str = "/hinix-async-io-test";
+            let mq = AsyncMsgQueue::new(MsgQueue::create(NAME, 2, 64).unwrap()).unwrap();
+            MsgQueue::unlink(NAME).unwrap();
+
+            mq.send(b"one").await.unwrap();
+            mq.send_with_priority(b"two", 1).await.unwrap();
+
+            let mut prio = 0;
+            let mut buf = [0u8; 64];
+            let n = mq.receive_with_priority(&mut buf, &mut prio).await.unwrap();
+            assert_eq!((b"two".as_slice(), 1), (&buf[..n], prio));
+            assert_eq!(b"one", mq.receive_bytes().await.unwrap().as_slice());
+        });
+    }
+
+    #[test]
+    fn test_msgqueue_stream() {
+        async_io::block_on(async {
+            const NAME: &str = "/hinix-async-io-stream-test";
+            let mq = AsyncMsgQueue::new(MsgQueue::create(NAME, 4, 64).unwrap()).unwrap();
+            MsgQueue::unlink(NAME).unwrap();
+
+            for msg in ["a", "b", "c"] {
+                mq.send(msg).await.unwrap();
+            }
+
+            let msgs: Vec<_> = mq.take(3).map(|msg| msg.unwrap()).collect().await;
+            assert_eq!(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()], msgs);
+        });
+    }
+
+    #[test]
+    fn test_msgqueue_sink() {
+        use futures_util::SinkExt;
+
+        async_io::block_on(async {
+            const NAME: &str = "/hinix-async-io-sink-test";
+            let mut mq = AsyncMsgQueue::new(MsgQueue::create(NAME, 2, 8).unwrap()).unwrap();
+            let rx = MsgQueue::open(NAME).unwrap();
+            MsgQueue::unlink(NAME).unwrap();
+
+            // More messages than the queue holds, so the sink has to wait
+            let reader = thread::spawn(move || {
+                (0..5)
+                    .map(|_| rx.receive_string().unwrap())
+                    .collect::<Vec<_>>()
+            });
+
+            let mut msgs = futures_lite::stream::iter(["a", "b", "c", "d", "e"].map(Ok));
+            mq.send_all(&mut msgs).await.unwrap();
+            assert_eq!(vec!["a", "b", "c", "d", "e"], reader.join().unwrap());
+
+            assert_eq!(Error::EMSGSIZE, mq.send("too long!").await.unwrap_err());
+        });
+    }
+
+    #[test]
+    fn test_packet_pipe_sink() {
+        use futures_util::SinkExt;
+
+        async_io::block_on(async {
+            let (mut wr, mut rd) = packet_pipe().unwrap();
+            wr.send(b"one").await.unwrap();
+            wr.send(b"two").await.unwrap();
+
+            // Each message comes back as its own packet
+            let mut buf = [0u8; 16];
+            assert_eq!(3, rd.read(&mut buf).await.unwrap());
+            assert_eq!(b"one", &buf[..3]);
+            assert_eq!(3, rd.read(&mut buf).await.unwrap());
+            assert_eq!(b"two", &buf[..3]);
+
+            let big = vec![0u8; pipe::PIPE_BUF + 1];
+            assert_eq!(Error::EMSGSIZE, wr.send(big).await.unwrap_err());
+        });
+    }
+}
@@ -0,0 +1,34 @@
+// hinix/src/bin/daemonize.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! This CLI application runs a program as a daemon.
+//!
+//! This is the same as `hinix proc daemon`, under the utility's own name.
+
+#[path = "hinix/daemonize.rs"]
+mod daemonize;
+
+use clap::Parser;
+use hinix::Result;
+
+#[derive(Parser)]
+#[command(name = "daemonize", version)]
+struct Cli {
+    #[command(flatten)]
+    opts: daemonize::Opts,
+}
+
+// --------------------------------------------------------------------------
+
+fn main() -> Result<()> {
+    daemonize::run(Cli::parse().opts)
+}
@@ -0,0 +1,44 @@
+// hinix/src/bin/evtool.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! This CLI application lets shell scripts use an eventfd to signal
+//! between processes.
+//!
+//! This is the same as `hinix ev`, under the utility's own name.
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[path = "hinix/evtool.rs"]
+mod evtool;
+
+use hinix::Result;
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(clap::Parser)]
+#[command(name = "evtool", version)]
+struct Cli {
+    #[command(flatten)]
+    opts: evtool::Opts,
+}
+
+// --------------------------------------------------------------------------
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn main() -> Result<()> {
+    use clap::Parser;
+    evtool::run(Cli::parse().opts)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn main() -> Result<()> {
+    println!("eventfd is only supported on Linux");
+    Ok(())
+}
@@ -0,0 +1,43 @@
+// hinix/src/bin/fdinfo.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! This CLI application lists the open file handles of a process.
+//!
+//! This is the same as `hinix proc fds`, under the utility's own name.
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[path = "hinix/fdinfo.rs"]
+mod fdinfo;
+
+use hinix::Result;
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(clap::Parser)]
+#[command(name = "fdinfo", version)]
+struct Cli {
+    #[command(flatten)]
+    opts: fdinfo::Opts,
+}
+
+// --------------------------------------------------------------------------
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn main() -> Result<()> {
+    use clap::Parser;
+    fdinfo::run(Cli::parse().opts)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn main() -> Result<()> {
+    println!("fdinfo is only supported on Linux");
+    Ok(())
+}
@@ -0,0 +1,34 @@
+// hinix/src/bin/fifocat.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! This CLI application can create, read, and write named pipes (FIFOs).
+//!
+//! This is the same as `hinix fifo cat`, under the utility's own name.
+
+#[path = "hinix/fifocat.rs"]
+mod fifocat;
+
+use clap::Parser;
+use hinix::Result;
+
+#[derive(Parser)]
+#[command(name = "fifocat", version)]
+struct Cli {
+    #[command(flatten)]
+    opts: fifocat::Opts,
+}
+
+// --------------------------------------------------------------------------
+
+fn main() -> Result<()> {
+    fifocat::run(Cli::parse().opts)
+}
@@ -0,0 +1,44 @@
+// hinix/src/bin/fswatch.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! This CLI application watches files and directories for changes, and
+//! prints the events as they occur.
+//!
+//! This is the same as `hinix fs watch`, under the utility's own name.
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[path = "hinix/fswatch.rs"]
+mod fswatch;
+
+use hinix::Result;
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(clap::Parser)]
+#[command(name = "fswatch", version)]
+struct Cli {
+    #[command(flatten)]
+    opts: fswatch::Opts,
+}
+
+// --------------------------------------------------------------------------
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn main() -> Result<()> {
+    use clap::Parser;
+    fswatch::run(Cli::parse().opts)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn main() -> Result<()> {
+    println!("inotify is only supported on Linux");
+    Ok(())
+}
@@ -0,0 +1,141 @@
+// hinix/src/bin/hinix/daemonize.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Runs a program as a daemon.
+//!
+//! The program is detached from the terminal and session, and can be
+//! given a locked pidfile that it holds for as long as it runs. Any
+//! failure to set up the daemon is reported before this exits.
+//!
+//! This is `hinix proc daemon`, and the `daemonize` utility.
+
+use clap::Args;
+use hinix::{
+    daemon::{Daemon, Outcome},
+    fd::FdExt,
+    Error, Result,
+};
+use nix::sys::stat::Mode;
+use std::{
+    os::unix::process::CommandExt,
+    process::{self, Command},
+};
+
+/// Parses the file mode mask, in octal.
+fn parse_umask(s: &str) -> std::result::Result<u32, String> {
+    match u32::from_str_radix(s, 8) {
+        Ok(m) if m <= 0o777 => Ok(m),
+        _ => Err("the umask must be an octal mode, like 022".into()),
+    }
+}
+
+/// Run a program as a daemon
+#[derive(Debug, Args)]
+#[command(
+    after_help = "The program inherits the locked pidfile, so it stays locked for as \
+                  long as the program runs."
+)]
+pub struct Opts {
+    /// Create a locked pidfile for the daemon
+    #[arg(short, long, value_name = "PATH")]
+    pidfile: Option<String>,
+
+    /// Append the daemon's standard output to a file
+    #[arg(short = 'o', long, value_name = "PATH")]
+    stdout: Option<String>,
+
+    /// Append the daemon's standard error to a file
+    #[arg(short, long, value_name = "PATH")]
+    stderr: Option<String>,
+
+    /// The working directory for the daemon
+    #[arg(short = 'd', long, value_name = "DIR", default_value = "/")]
+    chdir: String,
+
+    /// The file mode mask for the daemon, in octal
+    #[arg(short = 'm', long, default_value = "0", value_parser = parse_umask)]
+    umask: u32,
+
+    /// Run the daemon as this user
+    #[arg(short, long)]
+    user: Option<String>,
+
+    /// Run the daemon as this group, rather than the user's own
+    #[arg(short, long, requires = "user")]
+    group: Option<String>,
+
+    /// Print the PID of the daemon once it's started
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// The program to run, and its arguments
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    cmd: Vec<String>,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let mut daemon = Daemon::new()
+        .working_dir(&opts.chdir)
+        .umask(Mode::from_bits_truncate(opts.umask as _));
+
+    if let Some(path) = &opts.pidfile {
+        daemon = daemon.pidfile(path);
+    }
+    if let Some(path) = &opts.stdout {
+        daemon = daemon.stdout(path);
+    }
+    if let Some(path) = &opts.stderr {
+        daemon = daemon.stderr(path);
+    }
+
+    if let Some(user) = &opts.user {
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            daemon = daemon.user(user, opts.group.as_deref());
+        }
+        #[cfg(not(any(target_os = "android", target_os = "linux")))]
+        {
+            eprintln!(
+                "daemonize: can't run as '{}': not supported on this OS",
+                user
+            );
+            return Err(Error::ENOTSUP);
+        }
+    }
+
+    match daemon.start() {
+        Ok(Outcome::Parent(pid)) => {
+            if opts.verbose {
+                println!("{}", pid);
+            }
+        }
+        Ok(Outcome::Daemon(pidfile)) => {
+            // Keep the pidfile open and locked across the exec
+            if let Some(ref pidfile) = pidfile {
+                pidfile.set_cloexec(false)?;
+            }
+
+            let err = Command::new(&opts.cmd[0]).args(&opts.cmd[1..]).exec();
+            eprintln!("daemonize: unable to run the program: {}", err);
+            process::exit(127);
+        }
+        Err(err) if err == Error::EWOULDBLOCK && opts.pidfile.is_some() => {
+            eprintln!("daemonize: the pidfile is held by another process");
+            process::exit(1);
+        }
+        Err(err) => return Err(err),
+    }
+    Ok(())
+}
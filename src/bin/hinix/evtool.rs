@@ -0,0 +1,187 @@
+// hinix/src/bin/hinix/evtool.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Lets shell scripts use an eventfd to signal between processes.
+//!
+//! The `run` command creates an eventfd on a known file descriptor
+//! number, then execs a program which, along with its children,
+//! inherits it. The `signal` and `wait` commands then operate on that
+//! inherited descriptor:
+//!
+//! ```text
+//! evtool run --fd 3 -- sh -c '(sleep 1; evtool signal 3) & evtool wait 3'
+//! ```
+//!
+//! This is `hinix ev`, and the `evtool` utility.
+
+use clap::{Args, Subcommand};
+use hinix::{
+    eventfd::{EfdFlags, EventFd},
+    fd::FdExt,
+    Error, Result,
+};
+use nix::poll::{self, PollFd, PollFlags};
+use std::{
+    env, fs,
+    os::unix::{
+        io::{AsRawFd, FromRawFd, RawFd},
+        process::CommandExt,
+    },
+    process,
+    time::Duration,
+};
+
+/// The environment variable set to the descriptor number by `run`.
+const ENV_FD: &str = "EVTOOL_FD";
+
+/// The exit code when `wait` times out. Errors exit with 1.
+const EXIT_TIMEOUT: i32 = 2;
+
+/// Gets the descriptor from the arguments, or the environment.
+fn get_fd(fd: Option<RawFd>) -> Result<RawFd> {
+    fd.or_else(|| env::var(ENV_FD).ok().and_then(|s| s.parse().ok()))
+        .ok_or(Error::EINVAL)
+}
+
+/// Takes the inherited eventfd, after checking that it is one.
+fn inherited(fd: RawFd) -> Result<EventFd> {
+    let target = fs::read_link(format!("/proc/self/fd/{}", fd)).map_err(|_| Error::EBADF)?;
+    if target.to_str() != Some("anon_inode:[eventfd]") {
+        return Err(Error::EBADF);
+    }
+    Ok(unsafe { EventFd::from_raw_fd(fd) })
+}
+
+/// Parses a timeout in (possibly fractional) seconds.
+fn parse_secs(s: &str) -> std::result::Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(t) if t >= 0.0 && t.is_finite() => Ok(Duration::from_secs_f64(t)),
+        _ => Err("the timeout must be a non-negative number".into()),
+    }
+}
+
+/// Signal between processes with an eventfd
+#[derive(Debug, Args)]
+pub struct Opts {
+    #[command(subcommand)]
+    cmd: Command,
+}
+
+/// The eventfd operations.
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Create an eventfd, and exec a program that inherits it
+    Run {
+        /// The descriptor number for the eventfd
+        #[arg(short, long, default_value_t = 3,
+              value_parser = clap::value_parser!(RawFd).range(3..))]
+        fd: RawFd,
+
+        /// The initial value of the counter
+        #[arg(short, long, default_value_t = 0)]
+        init: u64,
+
+        /// Use semaphore semantics, so each wait takes one count
+        #[arg(short, long)]
+        semaphore: bool,
+
+        /// The program to run, and its arguments
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        cmd: Vec<String>,
+    },
+    /// Add to the counter of an inherited eventfd
+    Signal {
+        /// The eventfd descriptor number (default: $EVTOOL_FD)
+        fd: Option<RawFd>,
+
+        /// The value to add
+        #[arg(short, long, default_value_t = 1,
+              value_parser = clap::value_parser!(u64).range(1..))]
+        value: u64,
+    },
+    /// Wait for an inherited eventfd to be signaled, and print its value
+    #[command(after_help = "Exits with 2 if the timeout expires.")]
+    Wait {
+        /// The eventfd descriptor number (default: $EVTOOL_FD)
+        fd: Option<RawFd>,
+
+        /// Seconds to wait before giving up
+        #[arg(short, long, value_name = "SECS", value_parser = parse_secs)]
+        timeout: Option<Duration>,
+    },
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    match opts.cmd {
+        Command::Run {
+            fd,
+            init,
+            semaphore,
+            cmd,
+        } => {
+            let flags = if semaphore {
+                EfdFlags::EFD_SEMAPHORE
+            }
+            else {
+                EfdFlags::empty()
+            };
+            let evt = EventFd::with_flags(init, flags)?;
+
+            // Move it into place, leaving it open across the exec
+            evt.dup_to(fd)?;
+            if evt.as_raw_fd() == fd {
+                std::mem::forget(evt);
+            }
+
+            let err = process::Command::new(&cmd[0])
+                .args(&cmd[1..])
+                .env(ENV_FD, fd.to_string())
+                .exec();
+            eprintln!("evtool: unable to run the program: {}", err);
+            process::exit(127);
+        }
+        Command::Signal { fd, value } => {
+            inherited(get_fd(fd)?)?.write(value)?;
+        }
+        Command::Wait { fd, timeout } => {
+            let evt = inherited(get_fd(fd)?)?;
+
+            let timeout = match timeout {
+                Some(t) => t.as_millis().min(i32::MAX as u128) as i32,
+                None => -1,
+            };
+
+            let mut fds = [PollFd::new(evt.as_raw_fd(), PollFlags::POLLIN)];
+            loop {
+                match poll::poll(&mut fds, timeout) {
+                    Ok(0) => process::exit(EXIT_TIMEOUT),
+                    Ok(_) => break,
+                    Err(err) if err == Error::EINTR => continue,
+                    Err(err) => return Err(err.into()),
+                }
+            }
+
+            // Another waiter might have taken it first
+            evt.set_nonblocking(true)?;
+            match evt.read() {
+                Ok(val) => println!("{}", val),
+                Err(err) if err == Error::EAGAIN => process::exit(EXIT_TIMEOUT),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    Ok(())
+}
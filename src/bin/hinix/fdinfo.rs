@@ -0,0 +1,153 @@
+// hinix/src/bin/hinix/fdinfo.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Lists the open file handles of a process.
+//!
+//! It reads /proc to show what each handle refers to, the flags it was
+//! opened with, and the type of object behind it, which makes it a
+//! quick way to track down leaked handles.
+//!
+//! This is `hinix proc fds`, and the `fdinfo` utility.
+
+use clap::Args;
+use hinix::{
+    fdinfo::{self, FdInfo, FdType},
+    Result,
+};
+use nix::{fcntl::OFlag, unistd::Pid};
+use std::collections::BTreeMap;
+
+/// All the handle types, for parsing and validating the type filter.
+const TYPES: &[FdType] = &[
+    FdType::File,
+    FdType::Dir,
+    FdType::CharDevice,
+    FdType::BlockDevice,
+    FdType::Pipe,
+    FdType::Socket,
+    FdType::EventFd,
+    FdType::TimerFd,
+    FdType::SignalFd,
+    FdType::Epoll,
+    FdType::Inotify,
+    FdType::PidFd,
+    FdType::MsgQueue,
+    FdType::MemFd,
+    FdType::AnonInode,
+    FdType::Unknown,
+];
+
+/// Parses the name of a handle type, as it's displayed.
+fn parse_type(s: &str) -> Option<FdType> {
+    TYPES.iter().copied().find(|t| t.to_string() == s)
+}
+
+/// Gets a short description of the flags of a handle.
+fn flags_str(info: &FdInfo) -> String {
+    let mut v = vec![match info.flags & OFlag::O_ACCMODE {
+        OFlag::O_WRONLY => "w",
+        OFlag::O_RDWR => "rw",
+        _ => "r",
+    }];
+    if info.is_cloexec() {
+        v.push("cloexec");
+    }
+    if info.flags.contains(OFlag::O_NONBLOCK) {
+        v.push("nonblock");
+    }
+    if info.flags.contains(OFlag::O_APPEND) {
+        v.push("append");
+    }
+    v.join(",")
+}
+
+/// Parses the type filter option for clap.
+fn parse_type_arg(s: &str) -> std::result::Result<FdType, String> {
+    parse_type(s).ok_or_else(|| format!("unknown handle type '{}'", s))
+}
+
+/// Parses a process ID.
+fn parse_pid(s: &str) -> std::result::Result<Pid, String> {
+    match s.parse::<i32>() {
+        Ok(pid) if pid > 0 => Ok(Pid::from_raw(pid)),
+        _ => Err("the PID must be a positive integer".into()),
+    }
+}
+
+/// List the open file handles of a process
+#[derive(Debug, Args)]
+#[command(
+    after_help = "Handle types: file, dir, chr, blk, pipe, socket, eventfd, timerfd, \
+                  signalfd, epoll, inotify, pidfd, mqueue, memfd, anon, unknown"
+)]
+pub struct Opts {
+    /// Only list handles of the given types (comma separated)
+    #[arg(short, long = "type", value_name = "TYPES", value_delimiter = ',',
+          value_parser = parse_type_arg)]
+    types: Vec<FdType>,
+
+    /// Only list handles that would be inherited across an exec()
+    #[arg(short, long)]
+    inherit: bool,
+
+    /// Print the number of handles of each type, instead of the list
+    #[arg(short, long)]
+    count: bool,
+
+    /// The process to inspect [default: the parent process]
+    #[arg(value_parser = parse_pid)]
+    pid: Option<Pid>,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let pid = opts.pid.unwrap_or_else(nix::unistd::getppid);
+    let types = &opts.types;
+    let inherit = opts.inherit;
+
+    let fds: Vec<FdInfo> = fdinfo::open_fds_of(pid)?
+        .into_iter()
+        .filter(|info| types.is_empty() || types.contains(&info.fd_type))
+        .filter(|info| !inherit || !info.is_cloexec())
+        .collect();
+
+    if opts.count {
+        let mut counts = BTreeMap::new();
+        for info in &fds {
+            *counts.entry(info.fd_type.to_string()).or_insert(0) += 1;
+        }
+        for (fd_type, n) in &counts {
+            println!("{:<9} {}", fd_type, n);
+        }
+        println!("{:<9} {}", "total", fds.len());
+    }
+    else {
+        println!(
+            "{:>5} {:<9} {:<20} {:>10} TARGET",
+            "FD", "TYPE", "FLAGS", "POS"
+        );
+        for info in &fds {
+            println!(
+                "{:>5} {:<9} {:<20} {:>10} {}",
+                info.fd,
+                info.fd_type,
+                flags_str(info),
+                info.pos,
+                info.target.display()
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,243 @@
+// hinix/src/bin/hinix/fifocat.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Creates, reads, and writes named pipes (FIFOs).
+//!
+//! By default, it reads from the FIFO and copies the data to stdout.
+//! With `--write`, it copies stdin to the FIFO.
+//!
+//! This is `hinix fifo cat`, and the `fifocat` utility.
+
+use clap::Args;
+use hinix::{fifo, prelude::*};
+use nix::{
+    fcntl::OFlag,
+    poll::{self, PollFd, PollFlags},
+    sys::stat::Mode,
+};
+use std::{
+    io::{self, BufRead, Read, Write},
+    os::unix::io::AsRawFd,
+    process, thread,
+    time::{Duration, Instant},
+};
+
+/// The exit code when the timeout expires. Errors exit with 1.
+const EXIT_TIMEOUT: i32 = 2;
+
+/// Waits for the FIFO to be ready, exiting if the timeout expires.
+fn wait_ready(fifo: &Fifo, flags: PollFlags, timeout: Option<Duration>) -> Result<()> {
+    let ms = timeout.map(|t| t.as_millis() as i32).unwrap_or(-1);
+    let mut fds = [PollFd::new(fifo.as_raw_fd(), flags)];
+    loop {
+        match poll::poll(&mut fds, ms) {
+            Ok(0) => process::exit(EXIT_TIMEOUT),
+            Ok(_) => return Ok(()),
+            Err(err) if err == Error::EINTR => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Opens the FIFO for writing, retrying until a reader opens it, or the
+/// timeout expires.
+fn open_write_timeout(path: &str, timeout: Duration) -> Result<Fifo> {
+    let start = Instant::now();
+    loop {
+        match Fifo::open_with_flags(path, OFlag::O_WRONLY | OFlag::O_NONBLOCK) {
+            Err(err) if err == Error::ENXIO && start.elapsed() < timeout => {
+                thread::sleep(Duration::from_millis(10))
+            }
+            Err(err) if err == Error::ENXIO => process::exit(EXIT_TIMEOUT),
+            res => return res,
+        }
+    }
+}
+
+/// Copies from the FIFO to stdout.
+fn read_fifo(path: &str, nonblock: bool, timeout: Option<Duration>, lines: bool) -> Result<()> {
+    // Opening non-blocking doesn't wait for a writer, so we can time out.
+    let mut fifo = if nonblock || timeout.is_some() {
+        Fifo::open_read_nonblocking(path)?
+    }
+    else {
+        Fifo::open_read(path)?
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut buf = vec![0u8; 4096];
+    let mut pending = Vec::new();
+    let mut connected = false;
+
+    loop {
+        if !nonblock {
+            wait_ready(&fifo, PollFlags::POLLIN, timeout)?;
+        }
+        let n = match fifo.read(&mut buf) {
+            Ok(n) => n,
+            Err(err) if err.raw_os_error() == Some(libc::EAGAIN) => {
+                connected = true;
+                if nonblock {
+                    break;
+                }
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        if n == 0 {
+            // Without a writer, a non-blocking read is at EOF right away,
+            // so keep waiting if one has yet to connect.
+            if timeout.is_some() && !nonblock && !connected {
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+            break;
+        }
+        connected = true;
+
+        if lines {
+            // Only write out complete lines, as they arrive
+            pending.extend_from_slice(&buf[..n]);
+            if let Some(pos) = pending.iter().rposition(|&b| b == b'\n') {
+                out.write_all(&pending[..=pos])?;
+                out.flush()?;
+                pending.drain(..=pos);
+            }
+        }
+        else {
+            out.write_all(&buf[..n])?;
+            out.flush()?;
+        }
+    }
+
+    if !pending.is_empty() {
+        pending.push(b'\n');
+        out.write_all(&pending)?;
+    }
+    Ok(())
+}
+
+/// Copies stdin to the FIFO.
+fn write_fifo(path: &str, nonblock: bool, timeout: Option<Duration>, lines: bool) -> Result<()> {
+    let mut fifo = match (nonblock, timeout) {
+        // Fails with ENXIO if there's no reader
+        (true, _) => Fifo::open_with_flags(path, OFlag::O_WRONLY | OFlag::O_NONBLOCK)?,
+        (false, Some(timeout)) => open_write_timeout(path, timeout)?,
+        (false, None) => Fifo::open_write(path)?,
+    };
+
+    let stdin = io::stdin();
+    let mut inp = stdin.lock();
+
+    if lines {
+        // Each line is written with a single call, so lines from
+        // multiple writers won't be interleaved (up to PIPE_BUF).
+        let mut line = Vec::new();
+        while inp.read_until(b'\n', &mut line)? != 0 {
+            if !nonblock {
+                wait_ready(&fifo, PollFlags::POLLOUT, timeout)?;
+            }
+            fifo.write_all(&line)?;
+            line.clear();
+        }
+    }
+    else {
+        let mut buf = vec![0u8; 4096];
+        loop {
+            let n = inp.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            if !nonblock {
+                wait_ready(&fifo, PollFlags::POLLOUT, timeout)?;
+            }
+            fifo.write_all(&buf[..n])?;
+        }
+    }
+    Ok(())
+}
+
+/// Parses the permission bits, in octal.
+fn parse_mode(s: &str) -> std::result::Result<u32, String> {
+    match u32::from_str_radix(s, 8) {
+        Ok(m) if m <= 0o7777 => Ok(m),
+        _ => Err("the mode must be in octal, like 660".into()),
+    }
+}
+
+/// Parses a timeout in (possibly fractional) seconds.
+fn parse_secs(s: &str) -> std::result::Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(t) if t >= 0.0 && t.is_finite() => Ok(Duration::from_secs_f64(t)),
+        _ => Err("the timeout must be a non-negative number".into()),
+    }
+}
+
+/// Read or write a named pipe (FIFO)
+#[derive(Debug, Args)]
+#[command(after_help = "Exits with 2 if the timeout expires.")]
+pub struct Opts {
+    /// Copy stdin to the FIFO, rather than the FIFO to stdout
+    #[arg(short, long)]
+    write: bool,
+
+    /// Create the FIFO if it doesn't exist
+    #[arg(short, long)]
+    create: bool,
+
+    /// The permissions for a new FIFO, in octal
+    #[arg(short, long, default_value = "660", value_parser = parse_mode)]
+    mode: u32,
+
+    /// Don't wait for the other end, or for data
+    #[arg(short, long)]
+    nonblock: bool,
+
+    /// Seconds to wait for the other end, or for data
+    #[arg(short, long, value_name = "SECS", value_parser = parse_secs)]
+    timeout: Option<Duration>,
+
+    /// Copy the data as it arrives (the default)
+    #[arg(short, long, conflicts_with = "lines")]
+    raw: bool,
+
+    /// Copy whole lines at a time
+    #[arg(short, long)]
+    lines: bool,
+
+    /// Path to the FIFO
+    path: String,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let path = opts.path.as_str();
+
+    if opts.create {
+        fifo::mkfifo(path, Mode::from_bits_truncate(opts.mode as _))?;
+    }
+
+    let nonblock = opts.nonblock;
+    let lines = opts.lines;
+    let timeout = opts.timeout;
+
+    if opts.write {
+        write_fifo(path, nonblock, timeout, lines)
+    }
+    else {
+        read_fifo(path, nonblock, timeout, lines)
+    }
+}
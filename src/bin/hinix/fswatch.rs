@@ -0,0 +1,174 @@
+// hinix/src/bin/hinix/fswatch.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Watches files and directories for changes, and prints the events as
+//! they occur.
+//!
+//! This is `hinix fs watch`, and the `fswatch` utility.
+
+use clap::Args;
+use hinix::{
+    inotify::{Inotify, WatchMask},
+    Error, Result,
+};
+
+/// Escapes a string for use as a JSON value.
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The event names, for the command line and the output.
+const EVENTS: &[(&str, WatchMask)] = &[
+    ("access", WatchMask::IN_ACCESS),
+    ("modify", WatchMask::IN_MODIFY),
+    ("attrib", WatchMask::IN_ATTRIB),
+    ("close_write", WatchMask::IN_CLOSE_WRITE),
+    ("close_nowrite", WatchMask::IN_CLOSE_NOWRITE),
+    ("open", WatchMask::IN_OPEN),
+    ("moved_from", WatchMask::IN_MOVED_FROM),
+    ("moved_to", WatchMask::IN_MOVED_TO),
+    ("create", WatchMask::IN_CREATE),
+    ("delete", WatchMask::IN_DELETE),
+    ("delete_self", WatchMask::IN_DELETE_SELF),
+    ("move_self", WatchMask::IN_MOVE_SELF),
+    ("unmount", WatchMask::IN_UNMOUNT),
+    ("overflow", WatchMask::IN_Q_OVERFLOW),
+    ("ignored", WatchMask::IN_IGNORED),
+    ("isdir", WatchMask::IN_ISDIR),
+];
+
+/// Shorthand names for groups of events.
+const GROUPS: &[(&str, WatchMask)] = &[
+    ("close", WatchMask::IN_CLOSE),
+    ("move", WatchMask::IN_MOVE),
+    ("all", WatchMask::IN_ALL_EVENTS),
+];
+
+/// Parses a comma-separated list of event names into a mask.
+fn parse_mask(s: &str) -> Option<WatchMask> {
+    let mut mask = WatchMask::empty();
+    for name in s.split(',').map(|n| n.trim().to_lowercase()) {
+        let flag = EVENTS
+            .iter()
+            .chain(GROUPS)
+            .find(|(n, _)| *n == name)
+            .map(|(_, flag)| *flag)?;
+        mask |= flag;
+    }
+    Some(mask)
+}
+
+/// Gets the names of the events in the mask.
+fn event_names(mask: WatchMask) -> Vec<&'static str> {
+    EVENTS
+        .iter()
+        .filter(|(_, flag)| mask.contains(*flag))
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Parses the events option for clap.
+fn parse_events(s: &str) -> std::result::Result<WatchMask, String> {
+    parse_mask(s).ok_or_else(|| "unknown event name".into())
+}
+
+/// Watch files and directories for changes
+#[derive(Debug, Args)]
+pub struct Opts {
+    /// Watch directories, and all the directories under them
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Comma-separated events to report: access, modify, attrib,
+    /// close_write, close_nowrite, close, open, moved_from, moved_to,
+    /// move, create, delete, delete_self, move_self, or all
+    #[arg(
+        short,
+        long,
+        default_value = "create,delete,modify,move,close_write,delete_self,move_self",
+        value_parser = parse_events
+    )]
+    events: WatchMask,
+
+    /// Print each event as a line of JSON
+    #[arg(short, long)]
+    json: bool,
+
+    /// The paths to watch
+    #[arg(required = true)]
+    path: Vec<String>,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let mask = opts.events;
+    let recursive = opts.recursive;
+    let json = opts.json;
+
+    let mut ino = Inotify::new()?;
+    for path in &opts.path {
+        if recursive {
+            ino.add_watch_recursive(path, mask)?;
+        }
+        else {
+            ino.add_watch(path, mask)?;
+        }
+    }
+
+    while !ino.is_empty() {
+        let events = match ino.read_events() {
+            Ok(events) => events,
+            Err(err) if err == Error::EINTR => continue,
+            Err(err) => return Err(err),
+        };
+
+        for ev in events {
+            let names = event_names(ev.mask);
+            let path = ev.path.to_string_lossy();
+
+            if json {
+                let names: Vec<_> = names.iter().map(|n| json_str(n)).collect();
+                println!(
+                    "{{\"path\":{},\"events\":[{}],\"cookie\":{}}}",
+                    json_str(&path),
+                    names.join(","),
+                    ev.cookie
+                );
+            }
+            else if ev.cookie != 0 {
+                println!(
+                    "{} {} ({})",
+                    names.join(",").to_uppercase(),
+                    path,
+                    ev.cookie
+                );
+            }
+            else {
+                println!("{} {}", names.join(",").to_uppercase(), path);
+            }
+        }
+    }
+
+    Ok(())
+}
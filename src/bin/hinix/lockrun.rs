@@ -0,0 +1,118 @@
+// hinix/src/bin/hinix/lockrun.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Runs a command while holding a lock on a file.
+//!
+//! It takes an flock(2) on the lock file, then execs the command, which
+//! inherits the locked file. The lock is held until the command, and any
+//! of its children that kept the file open, exit. This is handy for
+//! making sure that cron jobs don't overlap:
+//!
+//! ```text
+//! */5 * * * * lockrun -n /run/lock/backup.lock backup.sh
+//! ```
+//!
+//! This is `hinix fs lock`, and the `lockrun` utility.
+
+use clap::Args;
+use hinix::{lock::FileLock, prelude::*};
+use std::{
+    mem,
+    os::unix::process::CommandExt,
+    process::{self, Command},
+    time::Duration,
+};
+
+/// The exit code when the lock is busy. Errors exit with 1.
+const EXIT_BUSY: i32 = 2;
+
+/// The exit code if the command can't be run.
+const EXIT_EXEC_FAILED: i32 = 127;
+
+/// Parses a timeout in (possibly fractional) seconds.
+fn parse_secs(s: &str) -> std::result::Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(t) if t >= 0.0 && t.is_finite() => Ok(Duration::from_secs_f64(t)),
+        _ => Err("the timeout must be a non-negative number".into()),
+    }
+}
+
+/// Run a command while holding a lock on a file
+#[derive(Debug, Args)]
+#[command(
+    after_help = "Exits with 2 if the lock is held by someone else (with --nonblock or \
+                  --timeout), 1 on error, or 127 if the command can't be run. \
+                  Otherwise the exit code is that of the command."
+)]
+pub struct Opts {
+    /// Take a shared lock, rather than an exclusive one
+    #[arg(short, long)]
+    shared: bool,
+
+    /// Fail rather than wait if the lock is busy
+    #[arg(short, long, conflicts_with = "timeout")]
+    nonblock: bool,
+
+    /// Seconds to wait for the lock before giving up
+    #[arg(short = 'w', long, value_name = "SECS", value_parser = parse_secs)]
+    timeout: Option<Duration>,
+
+    /// Report when the lock is busy
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// The file to lock, which is created if it doesn't exist
+    lockfile: String,
+
+    /// The program to run, and its arguments
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    cmd: Vec<String>,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let shared = opts.shared;
+    let timeout = opts.timeout;
+
+    let path = opts.lockfile.as_str();
+    let lock = FileLock::open(path)?;
+
+    let res = match (shared, opts.nonblock, timeout) {
+        (false, true, _) => lock.try_lock_exclusive(),
+        (true, true, _) => lock.try_lock_shared(),
+        (false, false, Some(timeout)) => lock.lock_exclusive_timeout(timeout),
+        (true, false, Some(timeout)) => lock.lock_shared_timeout(timeout),
+        (false, false, None) => lock.lock_exclusive(),
+        (true, false, None) => lock.lock_shared(),
+    };
+
+    let guard = match res {
+        Ok(guard) => guard,
+        Err(err) if err == Error::EWOULDBLOCK || err == Error::ETIMEDOUT => {
+            if opts.verbose {
+                eprintln!("lockrun: '{}' is locked", path);
+            }
+            process::exit(EXIT_BUSY);
+        }
+        Err(err) => return Err(err),
+    };
+
+    // Hand the locked file to the command
+    lock.set_cloexec(false)?;
+    mem::forget(guard);
+
+    let err = Command::new(&opts.cmd[0]).args(&opts.cmd[1..]).exec();
+    eprintln!("lockrun: unable to run the program: {}", err);
+    process::exit(EXIT_EXEC_FAILED);
+}
@@ -0,0 +1,564 @@
+// hinix/src/bin/hinix/main.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! A single front end for all the hinix utilities.
+//!
+//! The tools are grouped into subcommands, like `hinix mq send`. Each
+//! one is a module in this directory, which is also built as a small
+//! stand-alone program under the utility's traditional name, like
+//! `mqsend`, so the two always take the same options.
+//!
+//! The subcommands that are available depend on the features that the
+//! package was built with, and the target OS.
+//!
+//! It can also generate shell completions and man pages for itself and
+//! all the utilities, for packaging:
+//!
+//! ```text
+//! $ hinix completions bash > /usr/share/bash-completion/completions/hinix
+//! $ hinix completions bash mqsend > /usr/share/bash-completion/completions/mqsend
+//! $ hinix man --dir /usr/share/man/man1
+//! ```
+//!
+//! The `xtask` package does this for all the shells, with
+//! `cargo xtask dist`.
+
+use clap::{Command, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use hinix::Result;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+#[cfg(all(
+    feature = "msgqueue",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "netbsd"
+    )
+))]
+mod mqdump;
+#[cfg(all(
+    feature = "msgqueue",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "netbsd"
+    )
+))]
+mod mqinfo;
+#[cfg(all(
+    feature = "msgqueue",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "netbsd"
+    )
+))]
+mod mqrecv;
+#[cfg(all(
+    feature = "msgqueue",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "netbsd"
+    )
+))]
+mod mqrelay;
+#[cfg(all(
+    feature = "msgqueue",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "netbsd"
+    )
+))]
+mod mqsend;
+#[cfg(all(
+    feature = "msgqueue",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "netbsd"
+    )
+))]
+mod mqunlink;
+
+#[cfg(all(feature = "msgqueue", feature = "signalfd", target_os = "linux"))]
+mod mqctl;
+#[cfg(all(feature = "msgqueue", target_os = "linux"))]
+mod mqmon;
+
+#[cfg(feature = "daemon")]
+mod daemonize;
+#[cfg(feature = "fifo")]
+mod fifocat;
+#[cfg(feature = "lock")]
+mod lockrun;
+#[cfg(all(feature = "pty", feature = "term"))]
+mod ptyrun;
+
+#[cfg(all(feature = "eventfd", any(target_os = "android", target_os = "linux")))]
+mod evtool;
+#[cfg(all(feature = "fdinfo", any(target_os = "android", target_os = "linux")))]
+mod fdinfo;
+#[cfg(all(feature = "inotify", any(target_os = "android", target_os = "linux")))]
+mod fswatch;
+#[cfg(all(
+    feature = "mount",
+    feature = "ns",
+    any(target_os = "android", target_os = "linux")
+))]
+mod ns_run;
+#[cfg(all(feature = "pidfd", any(target_os = "android", target_os = "linux")))]
+mod pidwait;
+#[cfg(all(feature = "sched", any(target_os = "android", target_os = "linux")))]
+mod rtrun;
+#[cfg(all(feature = "systemd", any(target_os = "android", target_os = "linux")))]
+mod sdnotify;
+#[cfg(all(feature = "signalfd", any(target_os = "android", target_os = "linux")))]
+mod sigwait;
+#[cfg(all(feature = "timerfd", any(target_os = "android", target_os = "linux")))]
+mod tick;
+#[cfg(all(feature = "fdpass", any(target_os = "android", target_os = "linux")))]
+mod uds_recv;
+#[cfg(all(feature = "fdpass", any(target_os = "android", target_os = "linux")))]
+mod uds_send;
+#[cfg(all(
+    feature = "signalfd",
+    feature = "watchdog",
+    any(target_os = "android", target_os = "linux")
+))]
+mod wdog;
+
+/// Linux/Unix system utilities
+#[derive(Parser)]
+#[command(name = "hinix", version)]
+struct Cli {
+    #[command(subcommand)]
+    group: Group,
+}
+
+/// The groups of utilities.
+#[derive(Subcommand)]
+enum Group {
+    /// Posix Message Queues
+    #[cfg(all(
+        feature = "msgqueue",
+        any(
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "linux",
+            target_os = "netbsd"
+        )
+    ))]
+    #[command(subcommand)]
+    Mq(Mq),
+
+    /// Event objects (eventfd)
+    #[cfg(all(feature = "eventfd", any(target_os = "android", target_os = "linux")))]
+    Ev(evtool::Opts),
+
+    /// Named pipes (FIFOs)
+    #[cfg(feature = "fifo")]
+    #[command(subcommand)]
+    Fifo(Fifo),
+
+    /// Files and filesystem events
+    #[cfg(any(
+        feature = "lock",
+        all(feature = "inotify", any(target_os = "android", target_os = "linux"))
+    ))]
+    #[command(subcommand)]
+    Fs(Fs),
+
+    /// Processes
+    #[cfg(any(
+        feature = "daemon",
+        all(
+            any(
+                feature = "fdinfo",
+                all(feature = "mount", feature = "ns"),
+                feature = "pidfd",
+                feature = "sched"
+            ),
+            any(target_os = "android", target_os = "linux")
+        )
+    ))]
+    #[command(subcommand)]
+    Proc(Proc),
+
+    /// Pseudo-terminals
+    #[cfg(all(feature = "pty", feature = "term"))]
+    #[command(subcommand)]
+    Pty(Pty),
+
+    /// systemd integration
+    #[cfg(all(feature = "systemd", any(target_os = "android", target_os = "linux")))]
+    #[command(subcommand)]
+    Sd(Sd),
+
+    /// Signals
+    #[cfg(all(feature = "signalfd", any(target_os = "android", target_os = "linux")))]
+    #[command(subcommand)]
+    Sig(Sig),
+
+    /// Timers (timerfd)
+    #[cfg(all(feature = "timerfd", any(target_os = "android", target_os = "linux")))]
+    #[command(subcommand)]
+    Timer(Timer),
+
+    /// Unix-domain sockets
+    #[cfg(all(feature = "fdpass", any(target_os = "android", target_os = "linux")))]
+    #[command(subcommand)]
+    Uds(Uds),
+
+    /// Hardware watchdog timers
+    #[cfg(all(
+        feature = "signalfd",
+        feature = "watchdog",
+        any(target_os = "android", target_os = "linux")
+    ))]
+    #[command(subcommand)]
+    Wdog(Wdog),
+
+    /// Print the shell completions for hinix, or one of the utilities
+    Completions {
+        /// The shell to generate the completions for
+        shell: Shell,
+
+        /// The program to complete [default: hinix]
+        prog: Option<String>,
+    },
+
+    /// Print the man page for hinix or one of the utilities
+    Man {
+        /// Write the pages for hinix, its subcommands, and all the
+        /// utilities into the directory
+        #[arg(short, long, value_name = "DIR", conflicts_with = "prog")]
+        dir: Option<PathBuf>,
+
+        /// The program to document [default: hinix]
+        prog: Option<String>,
+    },
+}
+
+/// The Posix Message Queue utilities.
+#[cfg(all(
+    feature = "msgqueue",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "netbsd"
+    )
+))]
+#[derive(Subcommand)]
+enum Mq {
+    Send(mqsend::Opts),
+    Recv(mqrecv::Opts),
+    Info(mqinfo::Opts),
+    #[cfg(all(feature = "signalfd", target_os = "linux"))]
+    Ctl(mqctl::Opts),
+    Dump(mqdump::Opts),
+    #[cfg(target_os = "linux")]
+    Mon(mqmon::Opts),
+    Relay(mqrelay::Opts),
+    Unlink(mqunlink::Opts),
+}
+
+/// The named pipe utilities.
+#[cfg(feature = "fifo")]
+#[derive(Subcommand)]
+enum Fifo {
+    Cat(fifocat::Opts),
+}
+
+/// The file and filesystem utilities.
+#[cfg(any(
+    feature = "lock",
+    all(feature = "inotify", any(target_os = "android", target_os = "linux"))
+))]
+#[derive(Subcommand)]
+enum Fs {
+    #[cfg(all(feature = "inotify", any(target_os = "android", target_os = "linux")))]
+    Watch(fswatch::Opts),
+    #[cfg(feature = "lock")]
+    Lock(lockrun::Opts),
+}
+
+/// The process utilities.
+#[cfg(any(
+    feature = "daemon",
+    all(
+        any(
+            feature = "fdinfo",
+            all(feature = "mount", feature = "ns"),
+            feature = "pidfd",
+            feature = "sched"
+        ),
+        any(target_os = "android", target_os = "linux")
+    )
+))]
+#[derive(Subcommand)]
+enum Proc {
+    #[cfg(all(feature = "pidfd", any(target_os = "android", target_os = "linux")))]
+    Wait(pidwait::Opts),
+    #[cfg(feature = "daemon")]
+    Daemon(daemonize::Opts),
+    #[cfg(all(feature = "sched", any(target_os = "android", target_os = "linux")))]
+    Rt(rtrun::Opts),
+    #[cfg(all(
+        feature = "mount",
+        feature = "ns",
+        any(target_os = "android", target_os = "linux")
+    ))]
+    Ns(ns_run::Opts),
+    #[cfg(all(feature = "fdinfo", any(target_os = "android", target_os = "linux")))]
+    Fds(fdinfo::Opts),
+}
+
+/// The pseudo-terminal utilities.
+#[cfg(all(feature = "pty", feature = "term"))]
+#[derive(Subcommand)]
+enum Pty {
+    Run(ptyrun::Opts),
+}
+
+/// The systemd utilities.
+#[cfg(all(feature = "systemd", any(target_os = "android", target_os = "linux")))]
+#[derive(Subcommand)]
+enum Sd {
+    Notify(sdnotify::Opts),
+}
+
+/// The signal utilities.
+#[cfg(all(feature = "signalfd", any(target_os = "android", target_os = "linux")))]
+#[derive(Subcommand)]
+enum Sig {
+    Wait(sigwait::Opts),
+}
+
+/// The timer utilities.
+#[cfg(all(feature = "timerfd", any(target_os = "android", target_os = "linux")))]
+#[derive(Subcommand)]
+enum Timer {
+    Tick(tick::Opts),
+}
+
+/// The Unix-domain socket utilities.
+#[cfg(all(feature = "fdpass", any(target_os = "android", target_os = "linux")))]
+#[derive(Subcommand)]
+enum Uds {
+    Send(uds_send::Opts),
+    Recv(uds_recv::Opts),
+}
+
+/// The watchdog utilities.
+#[cfg(all(
+    feature = "signalfd",
+    feature = "watchdog",
+    any(target_os = "android", target_os = "linux")
+))]
+#[derive(Subcommand)]
+enum Wdog {
+    Pet(wdog::Opts),
+}
+
+/// The utilities that are built under their own names, and the
+/// subcommands that they're the same as.
+const TOOLS: &[(&str, &[&str])] = &[
+    ("mqsend", &["mq", "send"]),
+    ("mqrecv", &["mq", "recv"]),
+    ("mqinfo", &["mq", "info"]),
+    ("mqctl", &["mq", "ctl"]),
+    ("mqdump", &["mq", "dump"]),
+    ("mqmon", &["mq", "mon"]),
+    ("mqrelay", &["mq", "relay"]),
+    ("mqunlink", &["mq", "unlink"]),
+    ("evtool", &["ev"]),
+    ("fifocat", &["fifo", "cat"]),
+    ("fswatch", &["fs", "watch"]),
+    ("lockrun", &["fs", "lock"]),
+    ("pidwait", &["proc", "wait"]),
+    ("daemonize", &["proc", "daemon"]),
+    ("rtrun", &["proc", "rt"]),
+    ("ns-run", &["proc", "ns"]),
+    ("fdinfo", &["proc", "fds"]),
+    ("ptyrun", &["pty", "run"]),
+    ("sdnotify", &["sd", "notify"]),
+    ("sigwait", &["sig", "wait"]),
+    ("tick", &["timer", "tick"]),
+    ("uds-send", &["uds", "send"]),
+    ("uds-recv", &["uds", "recv"]),
+    ("wdog", &["wdog", "pet"]),
+];
+
+/// Gets the command line definition of hinix, or of one of the
+/// utilities, or `None` if it's unknown or wasn't built.
+///
+/// The utilities take the same arguments as their subcommands, so their
+/// definitions are just the subcommands under a different name.
+fn command(prog: &str) -> Option<Command> {
+    if prog == "hinix" {
+        return Some(Cli::command());
+    }
+
+    let (name, path) = TOOLS.iter().find(|(name, _)| *name == prog)?;
+    let cmd = path
+        .iter()
+        .try_fold(Cli::command(), |cmd, sub| cmd.find_subcommand(sub).cloned())?;
+    Some(cmd.name(*name).version(env!("CARGO_PKG_VERSION")))
+}
+
+/// Writes the man pages for hinix, all of its subcommands, and all the
+/// utilities into the directory.
+fn man_all(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    clap_mangen::generate_to(Cli::command(), dir)?;
+
+    for cmd in TOOLS.iter().filter_map(|(name, _)| command(name)) {
+        clap_mangen::generate_to(cmd, dir)?;
+    }
+    Ok(())
+}
+
+// --------------------------------------------------------------------------
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    match cli.group {
+        #[cfg(all(
+            feature = "msgqueue",
+            any(
+                target_os = "dragonfly",
+                target_os = "freebsd",
+                target_os = "linux",
+                target_os = "netbsd"
+            )
+        ))]
+        Group::Mq(cmd) => match cmd {
+            Mq::Send(opts) => mqsend::run(opts),
+            Mq::Recv(opts) => mqrecv::run(opts),
+            Mq::Info(opts) => mqinfo::run(opts),
+            #[cfg(all(feature = "signalfd", target_os = "linux"))]
+            Mq::Ctl(opts) => mqctl::run(opts),
+            Mq::Dump(opts) => mqdump::run(opts),
+            #[cfg(target_os = "linux")]
+            Mq::Mon(opts) => mqmon::run(opts),
+            Mq::Relay(opts) => mqrelay::run(opts),
+            Mq::Unlink(opts) => mqunlink::run(opts),
+        },
+        #[cfg(all(feature = "eventfd", any(target_os = "android", target_os = "linux")))]
+        Group::Ev(opts) => evtool::run(opts),
+        #[cfg(feature = "fifo")]
+        Group::Fifo(Fifo::Cat(opts)) => fifocat::run(opts),
+        #[cfg(any(
+            feature = "lock",
+            all(feature = "inotify", any(target_os = "android", target_os = "linux"))
+        ))]
+        Group::Fs(cmd) => match cmd {
+            #[cfg(all(feature = "inotify", any(target_os = "android", target_os = "linux")))]
+            Fs::Watch(opts) => fswatch::run(opts),
+            #[cfg(feature = "lock")]
+            Fs::Lock(opts) => lockrun::run(opts),
+        },
+        #[cfg(any(
+            feature = "daemon",
+            all(
+                any(
+                    feature = "fdinfo",
+                    all(feature = "mount", feature = "ns"),
+                    feature = "pidfd",
+                    feature = "sched"
+                ),
+                any(target_os = "android", target_os = "linux")
+            )
+        ))]
+        Group::Proc(cmd) => match cmd {
+            #[cfg(all(feature = "pidfd", any(target_os = "android", target_os = "linux")))]
+            Proc::Wait(opts) => pidwait::run(opts),
+            #[cfg(feature = "daemon")]
+            Proc::Daemon(opts) => daemonize::run(opts),
+            #[cfg(all(feature = "sched", any(target_os = "android", target_os = "linux")))]
+            Proc::Rt(opts) => rtrun::run(opts),
+            #[cfg(all(
+                feature = "mount",
+                feature = "ns",
+                any(target_os = "android", target_os = "linux")
+            ))]
+            Proc::Ns(opts) => ns_run::run(opts),
+            #[cfg(all(feature = "fdinfo", any(target_os = "android", target_os = "linux")))]
+            Proc::Fds(opts) => fdinfo::run(opts),
+        },
+        #[cfg(all(feature = "pty", feature = "term"))]
+        Group::Pty(Pty::Run(opts)) => ptyrun::run(opts),
+        #[cfg(all(feature = "systemd", any(target_os = "android", target_os = "linux")))]
+        Group::Sd(Sd::Notify(opts)) => sdnotify::run(opts),
+        #[cfg(all(feature = "signalfd", any(target_os = "android", target_os = "linux")))]
+        Group::Sig(Sig::Wait(opts)) => sigwait::run(opts),
+        #[cfg(all(feature = "timerfd", any(target_os = "android", target_os = "linux")))]
+        Group::Timer(Timer::Tick(opts)) => tick::run(opts),
+        #[cfg(all(feature = "fdpass", any(target_os = "android", target_os = "linux")))]
+        Group::Uds(cmd) => match cmd {
+            Uds::Send(opts) => uds_send::run(opts),
+            Uds::Recv(opts) => uds_recv::run(opts),
+        },
+        #[cfg(all(
+            feature = "signalfd",
+            feature = "watchdog",
+            any(target_os = "android", target_os = "linux")
+        ))]
+        Group::Wdog(Wdog::Pet(opts)) => wdog::run(opts),
+        Group::Completions { shell, prog } => {
+            let prog = prog.as_deref().unwrap_or("hinix");
+            let Some(mut cmd) = command(prog) else {
+                eprintln!("hinix: no such utility '{}'", prog);
+                process::exit(1);
+            };
+            // The generator panics on a write error, like a closed pipe
+            let mut buf = Vec::new();
+            clap_complete::generate(shell, &mut cmd, prog, &mut buf);
+            let _ = io::stdout().write_all(&buf);
+            Ok(())
+        }
+        Group::Man { dir, prog } => {
+            let res = match dir {
+                Some(dir) => man_all(&dir),
+                None => {
+                    let prog = prog.as_deref().unwrap_or("hinix");
+                    let Some(cmd) = command(prog) else {
+                        eprintln!("hinix: no such utility '{}'", prog);
+                        process::exit(1);
+                    };
+                    clap_mangen::Man::new(cmd).render(&mut io::stdout())
+                }
+            };
+            if let Err(err) = res {
+                eprintln!("hinix: unable to generate the man page: {}", err);
+                process::exit(1);
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,194 @@
+// hinix/src/bin/hinix/mqctl.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Modifies an existing Posix message queue.
+//!
+//! It can change the permissions or ownership of the queue, and wait
+//! for a notification that a message arrived on an empty queue.
+//!
+//! Note that the non-blocking flag belongs to each open handle, not the
+//! queue, so it can't be changed for other processes. Use the
+//! `--nonblock` option of `mqrecv` instead.
+//!
+//! This is `hinix mq ctl`, and the `mqctl` utility.
+
+use clap::{Args, Subcommand};
+use hinix::{msgqueue::MsgQueue, signalfd::SignalFd, Error, Result};
+use nix::{
+    mqueue::MQ_OFlag,
+    sys::{
+        signal::{SigSet, Signal},
+        stat::Mode,
+    },
+    unistd::{Gid, Group, Uid, User},
+};
+use std::{process, time::Duration};
+
+/// The exit code when the timeout expires. Errors exit with 1.
+const EXIT_TIMEOUT: i32 = 2;
+
+/// Parses a user or group, by name or number.
+fn parse_owner(s: &str) -> Result<(Option<Uid>, Option<Gid>)> {
+    let (user, group) = match s.split_once(':') {
+        Some((user, group)) => (user, group),
+        None => (s, ""),
+    };
+
+    let uid = match user {
+        "" => None,
+        user => match user.parse() {
+            Ok(n) => Some(Uid::from_raw(n)),
+            Err(_) => Some(User::from_name(user)?.ok_or(Error::EINVAL)?.uid),
+        },
+    };
+
+    let gid = match group {
+        "" => None,
+        group => match group.parse() {
+            Ok(n) => Some(Gid::from_raw(n)),
+            Err(_) => Some(Group::from_name(group)?.ok_or(Error::EINVAL)?.gid),
+        },
+    };
+    Ok((uid, gid))
+}
+
+/// Parses the permission bits, in octal.
+fn parse_mode(s: &str) -> std::result::Result<u32, String> {
+    match u32::from_str_radix(s, 8) {
+        Ok(m) if m <= 0o777 => Ok(m),
+        _ => Err("the mode must be in octal, like 660".into()),
+    }
+}
+
+/// Parses a timeout in (possibly fractional) seconds.
+fn parse_secs(s: &str) -> std::result::Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(t) if t >= 0.0 && t.is_finite() => Ok(Duration::from_secs_f64(t)),
+        _ => Err("the timeout must be a non-negative number".into()),
+    }
+}
+
+/// Modify an existing Posix Message Queue
+#[derive(Debug, Args)]
+pub struct Opts {
+    #[command(subcommand)]
+    cmd: Command,
+}
+
+/// The operations on the queue.
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Change the permissions of a queue
+    Chmod {
+        /// The permissions, in octal, like 660
+        #[arg(value_parser = parse_mode)]
+        mode: u32,
+
+        /// Name of the message queue
+        name: String,
+    },
+    /// Change the owner and/or group of a queue
+    Chown {
+        /// The new owner, as user, user:group, or :group
+        owner: String,
+
+        /// Name of the message queue
+        name: String,
+    },
+    /// Register for notification, and wait for a message to arrive
+    #[command(
+        after_help = "The notification is only sent when a message arrives on an empty \
+                      queue, so this returns immediately if the queue already has \
+                      messages. The registration only lasts while this program runs. \
+                      Exits with 2 if the timeout expires."
+    )]
+    Notify {
+        /// Seconds to wait before giving up
+        #[arg(short, long, value_name = "SECS", value_parser = parse_secs)]
+        timeout: Option<Duration>,
+
+        /// Name of the message queue
+        name: String,
+    },
+}
+
+impl Command {
+    /// Gets the name of the queue for any command.
+    fn name(&self) -> &str {
+        match self {
+            Command::Chmod { name, .. }
+            | Command::Chown { name, .. }
+            | Command::Notify { name, .. } => name,
+        }
+    }
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let mut name = opts.cmd.name().to_string();
+
+    if !name.starts_with('/') {
+        name = format!("/{}", name);
+    }
+
+    // Only notification needs to read the queue
+    let flags = if matches!(opts.cmd, Command::Notify { .. }) {
+        MQ_OFlag::O_RDONLY
+    }
+    else {
+        MQ_OFlag::O_WRONLY
+    };
+    let mq = MsgQueue::open_with_flags(&name, flags)?;
+
+    match opts.cmd {
+        Command::Chmod { mode, .. } => {
+            mq.chmod(Mode::from_bits_truncate(mode as _))?;
+        }
+        Command::Chown { owner, .. } => {
+            let (uid, gid) = parse_owner(&owner)?;
+            mq.chown(uid, gid)?;
+        }
+        Command::Notify { timeout, .. } => {
+            let mut mask = SigSet::empty();
+            mask.add(Signal::SIGUSR1);
+            let sfd = SignalFd::new(&mask)?;
+
+            mq.notify(Signal::SIGUSR1)?;
+
+            // A message that's already there won't trigger a notification
+            let n = mq.get_attr()?.curmsgs();
+            if n > 0 {
+                mq.remove_notify()?;
+                println!("{} message(s) waiting", n);
+                return Ok(());
+            }
+
+            let info = loop {
+                let res = match timeout {
+                    Some(timeout) => sfd.read_timeout(timeout),
+                    None => sfd.read().map(Some),
+                };
+                match res {
+                    Ok(Some(info)) => break info,
+                    Ok(None) => process::exit(EXIT_TIMEOUT),
+                    Err(err) if err == Error::EINTR => continue,
+                    Err(err) => return Err(err),
+                }
+            };
+            println!("Message arrived from pid {} (uid {})", info.pid, info.uid);
+        }
+    }
+
+    Ok(())
+}
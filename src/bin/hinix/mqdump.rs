@@ -0,0 +1,161 @@
+// hinix/src/bin/hinix/mqdump.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Drains all the messages from a Posix message queue, printing them or
+//! saving them to files.
+//!
+//! This is `hinix mq dump`, and the `mqdump` utility.
+
+use clap::Args;
+use hinix::{msgqueue::MsgQueue, Error, Result};
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+/// How each message is written out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    /// As a line of text, with non-UTF-8 data shown as bytes
+    Text,
+    /// As a hex dump
+    Hex,
+    /// The raw bytes, unchanged
+    Raw,
+}
+
+/// Writes a hex dump of the message, 16 bytes per line, with the
+/// printable characters to the right.
+fn hex_dump<W: Write>(out: &mut W, buf: &[u8]) -> io::Result<()> {
+    for (i, chunk) in buf.chunks(16).enumerate() {
+        write!(out, "{:08x} ", i * 16)?;
+        for j in 0..16 {
+            match chunk.get(j) {
+                Some(b) => write!(out, " {:02x}", b)?,
+                None => write!(out, "   ")?,
+            }
+        }
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                }
+                else {
+                    '.'
+                }
+            })
+            .collect();
+        writeln!(out, "  |{}|", ascii)?;
+    }
+    Ok(())
+}
+
+/// Writes a single message in the requested mode.
+fn write_msg<W: Write>(out: &mut W, buf: &[u8], mode: Mode) -> io::Result<()> {
+    match mode {
+        Mode::Text => match std::str::from_utf8(buf) {
+            Ok(s) => writeln!(out, "{}", s),
+            Err(_) => writeln!(out, "{:?}", buf),
+        },
+        Mode::Hex => hex_dump(out, buf),
+        Mode::Raw => out.write_all(buf),
+    }
+}
+
+/// Drain all the messages from a Posix Message Queue
+#[derive(Debug, Args)]
+pub struct Opts {
+    /// Write each message as a hex dump
+    #[arg(short = 'x', long, conflicts_with = "raw")]
+    hex: bool,
+
+    /// Write the raw bytes of each message
+    #[arg(short, long)]
+    raw: bool,
+
+    /// Write each message to a numbered file in the directory
+    #[arg(short, long, value_name = "PATH")]
+    dir: Option<PathBuf>,
+
+    /// Name of the message queue
+    name: String,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let mut name = opts.name;
+
+    if cfg!(target_os = "linux") && !name.starts_with('/') {
+        name = format!("/{}", name);
+    }
+
+    let mode = if opts.hex {
+        Mode::Hex
+    }
+    else if opts.raw {
+        Mode::Raw
+    }
+    else {
+        Mode::Text
+    };
+
+    let dir = opts.dir.as_deref();
+    if let Some(dir) = dir {
+        fs::create_dir_all(dir)?;
+    }
+
+    // Don't wait once the queue is empty
+    let mq = MsgQueue::open(&name)?;
+    mq.set_nonblock()?;
+
+    let mut buf = vec![0u8; mq.msg_size()];
+    let stdout = io::stdout();
+    let mut count = 0;
+
+    loop {
+        let mut prio = 0;
+        let n = match mq.receive_with_priority(&mut buf, &mut prio) {
+            Ok(n) => n,
+            Err(err) if err == Error::EAGAIN => break,
+            Err(err) => return Err(err),
+        };
+        count += 1;
+
+        let res = match dir {
+            Some(dir) => {
+                let ext = if mode == Mode::Hex { "hex" } else { "bin" };
+                let path = dir.join(format!("msg-{:04}.{}", count, ext));
+                fs::File::create(path).and_then(|mut f| {
+                    // Text is only meaningful on the terminal, so it's
+                    // saved as the raw message.
+                    let mode = if mode == Mode::Text { Mode::Raw } else { mode };
+                    write_msg(&mut f, &buf[..n], mode)
+                })
+            }
+            None => {
+                let mut out = stdout.lock();
+                if mode != Mode::Raw {
+                    let _ = writeln!(out, "# {}: prio {}, {} bytes", count, prio, n);
+                }
+                write_msg(&mut out, &buf[..n], mode)
+            }
+        };
+        res?;
+    }
+
+    eprintln!("Drained {} message(s) from {}", count, name);
+    Ok(())
+}
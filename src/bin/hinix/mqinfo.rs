@@ -0,0 +1,160 @@
+// hinix/src/bin/hinix/mqinfo.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Shows information about a Posix message queue.
+//!
+//! This is `hinix mq info`, and the `mqinfo` utility.
+
+use clap::Args;
+use hinix::{msgqueue::MsgQueue, Result};
+use nix::mqueue::MQ_OFlag;
+
+/// The details of a queue from the mqueue filesystem (Linux only).
+#[derive(Debug, Default)]
+struct FsInfo {
+    /// Total bytes of the messages in the queue
+    qsize: u64,
+    /// The notification type
+    notify: u64,
+    /// The signal used for notification
+    signo: u64,
+    /// The process registered for notification, if any
+    notify_pid: u64,
+    /// The owner of the queue
+    uid: u32,
+    /// The group of the queue
+    gid: u32,
+    /// The permission bits
+    mode: u32,
+}
+
+impl FsInfo {
+    /// Reads the details for the named queue from /dev/mqueue, if that
+    /// filesystem is mounted.
+    fn read(name: &str) -> Option<Self> {
+        use std::{fs, os::unix::fs::MetadataExt};
+
+        let path = format!("/dev/mqueue/{}", name.trim_start_matches('/'));
+        let meta = fs::metadata(&path).ok()?;
+        let status = fs::read_to_string(&path).ok()?;
+
+        let mut info = Self {
+            uid: meta.uid(),
+            gid: meta.gid(),
+            mode: meta.mode() & 0o7777,
+            ..Self::default()
+        };
+
+        // Like: "QSIZE:0 NOTIFY:0 SIGNO:0 NOTIFY_PID:0"
+        for field in status.split_whitespace() {
+            if let Some((key, val)) = field.split_once(':') {
+                let val = val.parse().unwrap_or(0);
+                match key {
+                    "QSIZE" => info.qsize = val,
+                    "NOTIFY" => info.notify = val,
+                    "SIGNO" => info.signo = val,
+                    "NOTIFY_PID" => info.notify_pid = val,
+                    _ => (),
+                }
+            }
+        }
+        Some(info)
+    }
+}
+
+/// Escapes a string for use as a JSON value.
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Show the attributes of a Posix Message Queue
+#[derive(Debug, Args)]
+pub struct Opts {
+    /// Print the information as JSON
+    #[arg(short, long)]
+    json: bool,
+
+    /// Name of the message queue
+    name: String,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let mut name = opts.name;
+
+    if cfg!(target_os = "linux") && !name.starts_with('/') {
+        name = format!("/{}", name);
+    }
+
+    // Read-only is enough to get the attributes
+    let mq = MsgQueue::open_with_flags(&name, MQ_OFlag::O_RDONLY)?;
+    let attr = mq.get_attr()?;
+
+    let nonblock = (attr.flags() as i32 & MQ_OFlag::O_NONBLOCK.bits()) != 0;
+    let flags = if nonblock { "O_NONBLOCK" } else { "" };
+
+    let fs_info = if cfg!(target_os = "linux") {
+        FsInfo::read(&name)
+    }
+    else {
+        None
+    };
+
+    if opts.json {
+        let mut fields = vec![
+            format!("\"name\":{}", json_str(&name)),
+            format!("\"maxmsg\":{}", attr.maxmsg()),
+            format!("\"msgsize\":{}", attr.msgsize()),
+            format!("\"curmsgs\":{}", attr.curmsgs()),
+            format!("\"flags\":{}", attr.flags()),
+        ];
+        if let Some(info) = fs_info {
+            fields.push(format!("\"qsize\":{}", info.qsize));
+            fields.push(format!("\"notify\":{}", info.notify));
+            fields.push(format!("\"signo\":{}", info.signo));
+            fields.push(format!("\"notify_pid\":{}", info.notify_pid));
+            fields.push(format!("\"uid\":{}", info.uid));
+            fields.push(format!("\"gid\":{}", info.gid));
+            fields.push(format!("\"mode\":\"{:04o}\"", info.mode));
+        }
+        println!("{{{}}}", fields.join(","));
+    }
+    else {
+        println!("name:       {}", name);
+        println!("maxmsg:     {}", attr.maxmsg());
+        println!("msgsize:    {}", attr.msgsize());
+        println!("curmsgs:    {}", attr.curmsgs());
+        println!("flags:      {:#x} {}", attr.flags(), flags);
+        if let Some(info) = fs_info {
+            println!("qsize:      {}", info.qsize);
+            println!("notify:     {}", info.notify);
+            println!("signo:      {}", info.signo);
+            println!("notify_pid: {}", info.notify_pid);
+            println!("owner:      {}:{}", info.uid, info.gid);
+            println!("mode:       {:04o}", info.mode);
+        }
+    }
+
+    Ok(())
+}
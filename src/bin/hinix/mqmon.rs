@@ -0,0 +1,178 @@
+// hinix/src/bin/hinix/mqmon.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Monitors messages arriving on several Posix message queues at once,
+//! like `iostat` for queues.
+//!
+//! Note that the monitor consumes the messages that it reports.
+//!
+//! This is `hinix mq mon`, and the `mqmon` utility.
+
+use clap::Args;
+use hinix::prelude::*;
+use nix::{
+    mqueue::MQ_OFlag,
+    sys::epoll::{self, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp},
+};
+use std::{
+    io::{self, Write},
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd},
+    time::{Duration, Instant},
+};
+
+/// The counters for a single queue.
+#[derive(Debug, Default, Clone, Copy)]
+struct Counters {
+    /// The number of messages
+    msgs: u64,
+    /// The total size of the messages
+    bytes: u64,
+}
+
+/// Parses the report interval in (possibly fractional) seconds.
+fn parse_interval(s: &str) -> std::result::Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(t) if t > 0.0 && t.is_finite() => Ok(Duration::from_secs_f64(t)),
+        _ => Err("the interval must be a positive number".into()),
+    }
+}
+
+/// Monitor messages arriving on Posix Message Queues
+#[derive(Debug, Args)]
+pub struct Opts {
+    /// Seconds between rate reports
+    #[arg(short, long, value_name = "SECS", value_parser = parse_interval,
+          default_value = "5")]
+    interval: Duration,
+
+    /// Only print the rate reports, not each message
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Names of the message queues
+    #[arg(required = true)]
+    name: Vec<String>,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let interval = opts.interval;
+    let quiet = opts.quiet;
+
+    let names: Vec<String> = opts
+        .name
+        .into_iter()
+        .map(|name| {
+            if name.starts_with('/') {
+                name
+            }
+            else {
+                format!("/{}", name)
+            }
+        })
+        .collect();
+
+    let epfd = epoll::epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC)?;
+    let epfd = unsafe { OwnedFd::from_raw_fd(epfd) };
+
+    // Each queue is registered with its index as the event data
+    let mut queues = Vec::with_capacity(names.len());
+    for (i, name) in names.iter().enumerate() {
+        let mq = MsgQueue::open_with_flags(name, MQ_OFlag::O_RDONLY | MQ_OFlag::O_NONBLOCK)?;
+        let mut ev = EpollEvent::new(EpollFlags::EPOLLIN, i as u64);
+        epoll::epoll_ctl(
+            epfd.as_raw_fd(),
+            EpollOp::EpollCtlAdd,
+            mq.as_raw_fd(),
+            &mut ev,
+        )?;
+        let buf = vec![0u8; mq.msg_size()];
+        queues.push((mq, buf));
+    }
+
+    let width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+
+    let mut totals = vec![Counters::default(); names.len()];
+    let mut period = totals.clone();
+    let mut last_report = Instant::now();
+    let mut events = vec![EpollEvent::empty(); names.len()];
+
+    loop {
+        let elapsed = last_report.elapsed();
+        let timeout = interval.saturating_sub(elapsed).as_millis() as isize;
+
+        let n = match epoll::epoll_wait(epfd.as_raw_fd(), &mut events, timeout) {
+            Ok(n) => n,
+            Err(err) if err == Error::EINTR => 0,
+            Err(err) => return Err(err.into()),
+        };
+
+        for ev in &events[..n] {
+            let i = ev.data() as usize;
+            let (mq, buf) = &mut queues[i];
+
+            // Drain everything that's arrived
+            loop {
+                let mut prio = 0;
+                let len = match mq.receive_with_priority(buf, &mut prio) {
+                    Ok(len) => len,
+                    Err(err) if err == Error::EAGAIN => break,
+                    Err(err) => return Err(err),
+                };
+                period[i].msgs += 1;
+                period[i].bytes += len as u64;
+                if !quiet {
+                    println!(
+                        "{:w$}  prio {:3}  {:6} bytes",
+                        names[i],
+                        prio,
+                        len,
+                        w = width
+                    );
+                }
+            }
+        }
+
+        let elapsed = last_report.elapsed();
+        if elapsed >= interval {
+            let secs = elapsed.as_secs_f64();
+            println!(
+                "{:w$}  {:>10} {:>12} {:>10} {:>12}",
+                "queue",
+                "msg/s",
+                "bytes/s",
+                "msgs",
+                "bytes",
+                w = width
+            );
+            for (i, name) in names.iter().enumerate() {
+                totals[i].msgs += period[i].msgs;
+                totals[i].bytes += period[i].bytes;
+                println!(
+                    "{:w$}  {:>10.1} {:>12.1} {:>10} {:>12}",
+                    name,
+                    period[i].msgs as f64 / secs,
+                    period[i].bytes as f64 / secs,
+                    totals[i].msgs,
+                    totals[i].bytes,
+                    w = width
+                );
+                period[i] = Counters::default();
+            }
+            println!();
+            last_report = Instant::now();
+        }
+        io::stdout().flush().ok();
+    }
+}
@@ -0,0 +1,286 @@
+// hinix/src/bin/hinix/mqrecv.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Receives messages from a Posix message queue.
+//!
+//! This is `hinix mq recv`, and the `mqrecv` utility.
+
+use clap::Args;
+use hinix::prelude::*;
+use std::{
+    io::{self, Write},
+    process,
+    time::Duration,
+};
+
+/// The exit code when no message was available, either because the
+/// queue was empty in non-blocking mode, or the timeout expired.
+/// Errors exit with 1.
+const EXIT_NO_MSG: i32 = 2;
+
+/// Receive messages from a Posix Message Queue
+#[derive(Debug, Args)]
+#[command(
+    after_help = "Exits with 0 if a message was received, 2 if no message was available \
+                  (empty queue with --nonblock or --timeout expired), and 1 on error. \
+                  With --count, the messages are received as with --follow, up to \
+                  the count. Draining an empty queue is not an error."
+)]
+pub struct Opts {
+    /// Keep receiving and printing messages as they arrive
+    #[arg(short, long)]
+    follow: bool,
+
+    /// Print the time each message was received
+    #[arg(short, long)]
+    timestamps: bool,
+
+    /// Print the priority of each message
+    #[arg(short, long)]
+    priority: bool,
+
+    /// Print each message as hex digits
+    #[arg(short = 'x', long, conflicts_with_all = ["base64", "json"])]
+    hex: bool,
+
+    /// Print each message as Base64
+    #[arg(short, long, conflicts_with = "json")]
+    base64: bool,
+
+    /// Print each message as a JSON object with its priority, timestamp, and Base64 data
+    #[arg(short, long)]
+    json: bool,
+
+    /// Seconds to wait for a message before giving up
+    #[arg(long, value_name = "SECS", value_parser = parse_secs)]
+    timeout: Option<Duration>,
+
+    /// Stop after receiving this many messages
+    #[arg(short, long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    count: Option<u64>,
+
+    /// Receive all the messages currently in the queue, then exit
+    #[arg(short, long, conflicts_with_all = ["follow", "timeout", "nonblock"])]
+    drain: bool,
+
+    /// Print this after each message instead of a newline (escapes like \t and \0 are expanded)
+    #[arg(short = 'D', long, value_name = "STR", conflicts_with = "null")]
+    delimiter: Option<String>,
+
+    /// Terminate each message with a NUL, for xargs -0
+    #[arg(short = '0', long)]
+    null: bool,
+
+    /// Don't wait if there's no message in the queue
+    #[arg(short, long)]
+    nonblock: bool,
+
+    /// Name of the message queue
+    name: String,
+}
+
+/// Parses a non-negative number of seconds.
+fn parse_secs(s: &str) -> std::result::Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(t) if t >= 0.0 && t.is_finite() => Ok(Duration::from_secs_f64(t)),
+        _ => Err("the timeout must be a non-negative number".into()),
+    }
+}
+
+/// How each message is printed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    /// As a line of text, with non-UTF-8 data shown as bytes
+    Text,
+    /// As a line of lowercase hex digits
+    Hex,
+    /// As a line of standard Base64
+    Base64,
+    /// As a line of JSON, with the priority, timestamp, and Base64 data
+    Json,
+}
+
+/// The standard Base64 alphabet (RFC 4648).
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes a buffer as lowercase hex.
+fn to_hex(buf: &[u8]) -> String {
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Encodes a buffer as standard, padded, Base64.
+fn to_base64(buf: &[u8]) -> String {
+    let mut s = String::with_capacity(buf.len() * 4 / 3 + 4);
+    for chunk in buf.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                s.push(BASE64_CHARS[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            }
+            else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+/// Expands the backslash escapes in a delimiter given on the command
+/// line, like "\n", "\t", or "\0".
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('0') => out.push('\0'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Prints a message received from the queue, with optional timestamp
+/// and priority prefixes, followed by the delimiter.
+fn print_msg(buf: &[u8], prio: u32, timestamp: bool, priority: bool, enc: Encoding, delim: &str) {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    if enc == Encoding::Json {
+        print!(
+            "{{\"time\":{}.{:06},\"prio\":{},\"size\":{},\"data\":\"{}\"}}{}",
+            ts.as_secs(),
+            ts.subsec_micros(),
+            prio,
+            buf.len(),
+            to_base64(buf),
+            delim
+        );
+        return;
+    }
+
+    if timestamp {
+        print!("[{}.{:06}] ", ts.as_secs(), ts.subsec_micros());
+    }
+    if priority {
+        print!("<{}> ", prio);
+    }
+    match enc {
+        Encoding::Hex => print!("{}{}", to_hex(buf), delim),
+        Encoding::Base64 => print!("{}{}", to_base64(buf), delim),
+        _ => match std::str::from_utf8(buf) {
+            Ok(s) => print!("{}{}", s, delim),
+            Err(_) => print!("{:?}{}", buf, delim),
+        },
+    }
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let mut name = opts.name;
+
+    if cfg!(target_os = "linux") && !name.starts_with('/') {
+        name = format!("/{}", name);
+    }
+
+    // Create the queue if it doesn't already exist.
+    let mq = MsgQueue::open(&name)?;
+
+    let drain = opts.drain;
+
+    if drain || opts.nonblock {
+        mq.set_nonblock()?;
+    }
+
+    let timeout = opts.timeout;
+    let count = opts.count;
+    let follow = drain || count.is_some() || opts.follow;
+    let timestamps = opts.timestamps;
+    let priority = opts.priority;
+
+    let enc = if opts.hex {
+        Encoding::Hex
+    }
+    else if opts.base64 {
+        Encoding::Base64
+    }
+    else if opts.json {
+        Encoding::Json
+    }
+    else {
+        Encoding::Text
+    };
+
+    let delim = if opts.null {
+        "\0".to_string()
+    }
+    else {
+        opts.delimiter
+            .as_deref()
+            .map(unescape)
+            .unwrap_or_else(|| "\n".to_string())
+    };
+
+    let mut buf = vec![0u8; mq.msg_size()];
+    let mut received = 0;
+
+    loop {
+        // Read the message
+        let mut prio = 0;
+        let res = match timeout {
+            Some(timeout) => mq.receive_timeout(&mut buf, &mut prio, timeout),
+            None => mq.receive_with_priority(&mut buf, &mut prio),
+        };
+
+        let n = match res {
+            Ok(n) => n,
+            // When following, running out of messages after getting some
+            // is a normal way to finish.
+            Err(err) if err == Error::EAGAIN || err == Error::ETIMEDOUT => {
+                if received > 0 || drain {
+                    break;
+                }
+                process::exit(EXIT_NO_MSG);
+            }
+            Err(err) => return Err(err),
+        };
+        received += 1;
+
+        // Print it
+        print_msg(&buf[..n], prio, timestamps, priority, enc, &delim);
+
+        if !follow || count == Some(received) {
+            break;
+        }
+        // Don't let messages sit in the buffer when piped
+        io::stdout().flush().ok();
+    }
+
+    Ok(())
+}
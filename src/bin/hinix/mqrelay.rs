@@ -0,0 +1,244 @@
+// hinix/src/bin/hinix/mqrelay.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Forwards messages between a Posix message queue and another transport.
+//!
+//! The other end is given as one of:
+//!
+//! - `-` for stdin/stdout
+//! - `mq:NAME` for another message queue
+//! - `unix:PATH` for a Unix-domain stream socket
+//! - `unixdg:PATH` for a Unix-domain datagram socket
+//! - `fifo:PATH` for a named pipe (created if it doesn't exist)
+//!
+//! Message queues and datagram sockets keep the message boundaries. For
+//! the stream transports, the messages are framed as lines of text, with
+//! a 4-byte big-endian length prefix, or as raw chunks of data.
+//!
+//! This is `hinix mq relay`, and the `mqrelay` utility.
+
+use clap::{Args, ValueEnum};
+use hinix::{msgqueue::MsgQueue, Error, Result};
+use nix::{sys::stat::Mode, unistd};
+use std::{
+    fs::OpenOptions,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::{UnixDatagram, UnixStream},
+    path::Path,
+};
+
+/// How messages are delimited on stream transports.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Framing {
+    /// Each message is a line of text
+    Line,
+    /// Each message is preceded by its length as a 32-bit big-endian int
+    Length,
+    /// No framing. On input, each read is a message.
+    Raw,
+}
+
+/// Writes a message to a stream with the framing.
+fn write_framed<W: Write + ?Sized>(out: &mut W, msg: &[u8], framing: Framing) -> io::Result<()> {
+    match framing {
+        Framing::Line => {
+            out.write_all(msg)?;
+            out.write_all(b"\n")?;
+        }
+        Framing::Length => {
+            out.write_all(&(msg.len() as u32).to_be_bytes())?;
+            out.write_all(msg)?;
+        }
+        Framing::Raw => out.write_all(msg)?,
+    }
+    out.flush()
+}
+
+/// Reads a message from a stream with the framing, returning `None` at
+/// the end of the stream. Messages are limited to `max` bytes.
+fn read_framed<R: BufRead + ?Sized>(
+    inp: &mut R,
+    framing: Framing,
+    max: usize,
+) -> io::Result<Option<Vec<u8>>> {
+    let too_big = || io::Error::from_raw_os_error(libc::EMSGSIZE);
+
+    match framing {
+        Framing::Line => {
+            let mut buf = Vec::new();
+            if inp.read_until(b'\n', &mut buf)? == 0 {
+                return Ok(None);
+            }
+            if buf.last() == Some(&b'\n') {
+                buf.pop();
+            }
+            if buf.len() > max {
+                return Err(too_big());
+            }
+            Ok(Some(buf))
+        }
+        Framing::Length => {
+            let mut len = [0u8; 4];
+            match inp.read_exact(&mut len) {
+                Ok(()) => (),
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(err) => return Err(err),
+            }
+            let len = u32::from_be_bytes(len) as usize;
+            if len > max {
+                return Err(too_big());
+            }
+            let mut buf = vec![0u8; len];
+            inp.read_exact(&mut buf)?;
+            Ok(Some(buf))
+        }
+        Framing::Raw => {
+            let mut buf = vec![0u8; max];
+            let n = inp.read(&mut buf)?;
+            if n == 0 {
+                return Ok(None);
+            }
+            buf.truncate(n);
+            Ok(Some(buf))
+        }
+    }
+}
+
+/// The other end of the relay.
+enum Endpoint {
+    /// A message queue
+    Queue(MsgQueue),
+    /// A datagram socket, connected to the peer
+    Datagram(UnixDatagram),
+    /// A stream: socket, FIFO, or stdio
+    Stream(Box<dyn BufRead>, Box<dyn Write>),
+}
+
+/// Normalizes a queue name for the OS.
+fn queue_name(name: &str) -> String {
+    if cfg!(target_os = "linux") && !name.starts_with('/') {
+        format!("/{}", name)
+    }
+    else {
+        name.to_string()
+    }
+}
+
+/// Opens the endpoint described by the spec. When `input` is true,
+/// messages will be read from it, otherwise they will be written.
+fn open_endpoint(spec: &str, input: bool) -> Result<Endpoint> {
+    let (kind, arg) = spec.split_once(':').unwrap_or((spec, ""));
+    let ep = match kind {
+        "-" => Endpoint::Stream(
+            Box::new(BufReader::new(io::stdin())),
+            Box::new(io::stdout()),
+        ),
+        "mq" => Endpoint::Queue(MsgQueue::open(&queue_name(arg))?),
+        "unix" => {
+            let sock = UnixStream::connect(arg)?;
+            let rd = sock.try_clone()?;
+            Endpoint::Stream(Box::new(BufReader::new(rd)), Box::new(sock))
+        }
+        "unixdg" => {
+            let sock = UnixDatagram::unbound()?;
+            sock.connect(arg)?;
+            Endpoint::Datagram(sock)
+        }
+        "fifo" => {
+            if !Path::new(arg).exists() {
+                unistd::mkfifo(arg, Mode::from_bits_truncate(0o660))?;
+            }
+            // Opening blocks until the other side is opened
+            let file = OpenOptions::new().read(input).write(!input).open(arg)?;
+            let wr = file.try_clone()?;
+            Endpoint::Stream(Box::new(BufReader::new(file)), Box::new(wr))
+        }
+        _ => return Err(Error::EINVAL),
+    };
+    Ok(ep)
+}
+
+/// Reads the next message from the endpoint, or `None` at the end.
+fn recv(ep: &mut Endpoint, framing: Framing, max: usize) -> Result<Option<Vec<u8>>> {
+    match ep {
+        Endpoint::Queue(mq) => {
+            let mut buf = vec![0u8; mq.msg_size()];
+            let n = mq.receive(&mut buf)?;
+            buf.truncate(n);
+            Ok(Some(buf))
+        }
+        Endpoint::Datagram(sock) => {
+            let mut buf = vec![0u8; max];
+            let n = sock.recv(&mut buf)?;
+            buf.truncate(n);
+            Ok(Some(buf))
+        }
+        Endpoint::Stream(rd, _) => read_framed(rd.as_mut(), framing, max).map_err(Error::from),
+    }
+}
+
+/// Writes a message to the endpoint.
+fn send(ep: &mut Endpoint, msg: &[u8], framing: Framing) -> Result<()> {
+    match ep {
+        Endpoint::Queue(mq) => mq.send(msg),
+        Endpoint::Datagram(sock) => sock.send(msg).map(|_| ()).map_err(Error::from),
+        Endpoint::Stream(_, wr) => write_framed(wr.as_mut(), msg, framing).map_err(Error::from),
+    }
+}
+
+/// Forward messages between a Posix Message Queue and another transport
+#[derive(Debug, Args)]
+pub struct Opts {
+    /// Relay from the target into the queue, rather than out of it
+    #[arg(short, long = "in")]
+    input: bool,
+
+    /// How messages are delimited on stream transports
+    #[arg(short = 'F', long, value_enum, default_value_t = Framing::Line)]
+    framing: Framing,
+
+    /// Name of the message queue
+    name: String,
+
+    /// The other end: '-', mq:NAME, unix:PATH, unixdg:PATH, or fifo:PATH
+    target: String,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let name = queue_name(&opts.name);
+    let input = opts.input;
+    let framing = opts.framing;
+
+    let mut queue = Endpoint::Queue(MsgQueue::open(&name)?);
+    let max = match &queue {
+        Endpoint::Queue(mq) => mq.msg_size(),
+        _ => unreachable!(),
+    };
+
+    let mut target = open_endpoint(&opts.target, input)?;
+
+    let (src, dst) = if input {
+        (&mut target, &mut queue)
+    }
+    else {
+        (&mut queue, &mut target)
+    };
+
+    while let Some(msg) = recv(src, framing, max)? {
+        send(dst, &msg, framing)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,256 @@
+// hinix/src/bin/hinix/mqsend.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Sends messages to a Posix message queue.
+//!
+//! This is `hinix mq send`, and the `mqsend` utility.
+
+use clap::Args;
+use hinix::{
+    msgqueue::{MsgQueue, DEFAULT_PRIO},
+    Result,
+};
+use std::{
+    fs,
+    io::{self, Read},
+    path::PathBuf,
+};
+
+/// The number of messages the queue can hold.
+const N_MSG: usize = 4;
+
+/// The maximum size of each message
+const MAX_SZ: usize = 512;
+
+/// Send messages to a Posix Message Queue
+#[derive(Debug, Args)]
+pub struct Opts {
+    /// Whether to try to create the queue
+    #[arg(short, long)]
+    create: bool,
+
+    /// The number of messages the queue can hold
+    #[arg(short, long, default_value_t = N_MSG)]
+    nmsg: usize,
+
+    /// The maximum size of each messages
+    #[arg(short = 's', long, default_value_t = MAX_SZ)]
+    maxsz: usize,
+
+    /// The priority of the message(s)
+    #[arg(short, long, default_value_t = DEFAULT_PRIO)]
+    prio: u32,
+
+    /// Read the message from a file
+    #[arg(short, long, value_name = "PATH", conflicts_with = "msg")]
+    file: Option<PathBuf>,
+
+    /// Send each line of the input as a separate message
+    #[arg(short, long)]
+    lines: bool,
+
+    /// The input is hex digits, to be decoded before sending
+    #[arg(short = 'x', long, conflicts_with_all = ["base64", "json"])]
+    hex: bool,
+
+    /// The input is Base64, to be decoded before sending
+    #[arg(short, long, conflicts_with = "json")]
+    base64: bool,
+
+    /// Each line of input is a JSON object, as printed by 'mqrecv --json'
+    #[arg(short, long)]
+    json: bool,
+
+    /// Name of the message queue
+    name: String,
+
+    /// The message to send to the queue, or '-' to read it from stdin
+    #[arg(required_unless_present = "file")]
+    msg: Option<String>,
+}
+
+/// How the input messages are encoded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    /// Sent as-is
+    Raw,
+    /// Hex digits, which are decoded before sending
+    Hex,
+    /// Standard Base64, which is decoded before sending
+    Base64,
+    /// A JSON object, as printed by `mqrecv --json`
+    Json,
+}
+
+/// Decodes a string of hex digits, ignoring any whitespace.
+fn from_hex(s: &[u8]) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = s
+        .iter()
+        .filter(|b| !b.is_ascii_whitespace())
+        .map(|&b| (b as char).to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()?;
+
+    if digits.len() & 1 != 0 {
+        return None;
+    }
+    Some(digits.chunks(2).map(|d| (d[0] << 4) | d[1]).collect())
+}
+
+/// Decodes standard Base64, ignoring any whitespace. Padding is optional.
+fn from_base64(s: &[u8]) -> Option<Vec<u8>> {
+    fn value(b: u8) -> Option<u32> {
+        let v = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        Some(u32::from(v))
+    }
+
+    let mut buf = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut acc, mut nbits) = (0u32, 0);
+    let mut padded = false;
+
+    for &b in s.iter().filter(|b| !b.is_ascii_whitespace()) {
+        if b == b'=' {
+            padded = true;
+            continue;
+        }
+        if padded {
+            return None;
+        }
+        acc = (acc << 6) | value(b)?;
+        nbits += 6;
+        if nbits >= 8 {
+            nbits -= 8;
+            buf.push((acc >> nbits) as u8);
+            acc &= (1 << nbits) - 1;
+        }
+    }
+    Some(buf)
+}
+
+/// Gets the value of a field from a flat JSON object, like the ones
+/// printed by `mqrecv --json`.
+///
+/// This only handles numbers and strings without escapes, which is
+/// all that those objects contain.
+fn json_field<'a>(obj: &'a str, key: &str) -> Option<&'a str> {
+    let pat = format!("\"{}\"", key);
+    let rest = obj[obj.find(&pat)? + pat.len()..].trim_start();
+    let rest = rest.strip_prefix(':')?.trim_start();
+
+    match rest.strip_prefix('"') {
+        Some(s) => s.split('"').next(),
+        None => rest.split([',', '}']).next().map(str::trim),
+    }
+}
+
+/// Decodes a JSON envelope into the message and its priority, if given.
+fn from_json(s: &[u8]) -> Option<(Vec<u8>, Option<u32>)> {
+    let obj = std::str::from_utf8(s).ok()?;
+    let data = from_base64(json_field(obj, "data")?.as_bytes())?;
+    let prio = match json_field(obj, "prio") {
+        Some(prio) => Some(prio.parse().ok()?),
+        None => None,
+    };
+    Some((data, prio))
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let mut name = opts.name;
+
+    if cfg!(target_os = "linux") && !name.starts_with('/') {
+        name = format!("/{}", name);
+    }
+
+    let prio = opts.prio;
+
+    // Get the input, keeping binary data intact
+    let input = match (opts.file, opts.msg.as_deref()) {
+        (Some(path), _) => fs::read(path)?,
+        (None, Some("-")) => {
+            let mut buf = Vec::new();
+            io::stdin().read_to_end(&mut buf)?;
+            buf
+        }
+        (None, msg) => msg.unwrap_or_default().as_bytes().to_vec(),
+    };
+
+    let enc = if opts.hex {
+        Encoding::Hex
+    }
+    else if opts.base64 {
+        Encoding::Base64
+    }
+    else if opts.json {
+        Encoding::Json
+    }
+    else {
+        Encoding::Raw
+    };
+
+    // JSON objects are always one per line
+    let chunks: Vec<&[u8]> = if opts.lines || enc == Encoding::Json {
+        input
+            .split(|&b| b == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
+    else {
+        vec![&input[..]]
+    };
+
+    // Decode the messages, each with its priority
+    let mut msgs = Vec::with_capacity(chunks.len());
+    for chunk in chunks {
+        let msg = match enc {
+            Encoding::Raw => Some((chunk.to_vec(), prio)),
+            Encoding::Hex => from_hex(chunk).map(|msg| (msg, prio)),
+            Encoding::Base64 => from_base64(chunk).map(|msg| (msg, prio)),
+            Encoding::Json => from_json(chunk).map(|(msg, p)| (msg, p.unwrap_or(prio))),
+        };
+        match msg {
+            Some(msg) => msgs.push(msg),
+            None => {
+                eprintln!(
+                    "mqsend: invalid {:?} input: {}",
+                    enc,
+                    String::from_utf8_lossy(chunk)
+                );
+                return Err(hinix::Error::EINVAL);
+            }
+        }
+    }
+
+    // Create the queue if it doesn't already exist.
+    let mq = if opts.create {
+        MsgQueue::create(&name, opts.nmsg, opts.maxsz)
+    }
+    else {
+        MsgQueue::open(&name)
+    }?;
+
+    // Send the message(s)
+    for (msg, prio) in msgs {
+        mq.send_with_priority(&msg, prio)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,45 @@
+// hinix/src/bin/hinix/mqunlink.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Removes a Posix message queue.
+//!
+//! This is `hinix mq unlink`, and the `mqunlink` utility.
+
+use clap::Args;
+use hinix::{msgqueue::MsgQueue, Error, Result};
+
+/// Remove a Posix Message Queue
+#[derive(Debug, Args)]
+pub struct Opts {
+    /// Ignore a queue that doesn't exist
+    #[arg(short, long)]
+    force: bool,
+
+    /// Name of the message queue
+    name: String,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let mut name = opts.name;
+
+    if cfg!(target_os = "linux") && !name.starts_with('/') {
+        name = format!("/{}", name);
+    }
+
+    match MsgQueue::unlink(&name) {
+        Err(err) if err == Error::ENOENT && opts.force => Ok(()),
+        res => res,
+    }
+}
@@ -0,0 +1,178 @@
+// hinix/src/bin/hinix/ns_run.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Runs a command in new Linux namespaces.
+//!
+//! It's a simple sandbox, similar to unshare(1). The command can get its
+//! own host name, private mounts with scratch tmpfs directories, its own
+//! process tree, and an empty network.
+//!
+//! With a new PID namespace, the command runs as PID 1 in a child
+//! process, and this waits for it and exits with its status.
+//!
+//! This is `hinix proc ns`, and the `ns-run` utility.
+
+use clap::Args;
+use hinix::{
+    mount::{self, MsFlags},
+    ns::{self, Namespaces},
+    Result,
+};
+use nix::{
+    errno::Errno,
+    sys::wait::{self, WaitStatus},
+    unistd::{self, ForkResult},
+};
+use std::{
+    env,
+    os::unix::process::CommandExt,
+    path::Path,
+    process::{self, Command},
+};
+
+/// The exit code if the command can't be run.
+const EXIT_EXEC_FAILED: i32 = 127;
+
+/// Sets up the inside of the namespaces, and runs the command.
+/// This only returns on error.
+fn exec_inside(opts: &Opts, cmd: &[String], new_pid: bool) -> Result<()> {
+    if let Some(name) = &opts.hostname {
+        unistd::sethostname(name)?;
+    }
+
+    // A new PID namespace needs its own /proc to show its processes
+    if new_pid {
+        mount::mount(
+            Some("proc"),
+            "/proc",
+            Some("proc"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+            None,
+        )?;
+    }
+
+    for dir in &opts.tmpfs {
+        mount::mount_tmpfs(Path::new(dir), None, None)?;
+    }
+
+    let err = Command::new(&cmd[0]).args(&cmd[1..]).exec();
+    eprintln!("ns-run: unable to run the program: {}", err);
+    process::exit(EXIT_EXEC_FAILED);
+}
+
+/// Run a command in new namespaces
+#[derive(Debug, Args)]
+#[command(
+    after_help = "Most namespaces need CAP_SYS_ADMIN, unless --user is also given. \
+                  If no command is given, runs $SHELL."
+)]
+pub struct Opts {
+    /// New mount namespace, with all mounts made private
+    #[arg(short, long)]
+    mount: bool,
+
+    /// New UTS namespace, for the host and domain names
+    #[arg(short, long)]
+    uts: bool,
+
+    /// New IPC namespace, for System V IPC and Posix message queues
+    #[arg(short, long)]
+    ipc: bool,
+
+    /// New PID namespace, with /proc remounted (implies --mount)
+    #[arg(short, long)]
+    pid: bool,
+
+    /// New network namespace, with only a loopback interface
+    #[arg(short, long)]
+    net: bool,
+
+    /// New user namespace, mapping the current user to root
+    #[arg(short = 'U', long)]
+    user: bool,
+
+    /// Set the host name (implies --uts)
+    #[arg(short = 'H', long, value_name = "NAME")]
+    hostname: Option<String>,
+
+    /// Mount an empty tmpfs on a directory (implies --mount)
+    #[arg(short, long, value_name = "DIR")]
+    tmpfs: Vec<String>,
+
+    /// The program to run, and its arguments
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    cmd: Vec<String>,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let mut namespaces = Namespaces::empty();
+    for (set, ns) in [
+        (opts.mount, Namespaces::MOUNT),
+        (opts.uts, Namespaces::UTS),
+        (opts.ipc, Namespaces::IPC),
+        (opts.pid, Namespaces::PID),
+        (opts.net, Namespaces::NET),
+        (opts.user, Namespaces::USER),
+    ] {
+        if set {
+            namespaces |= ns;
+        }
+    }
+    if opts.hostname.is_some() {
+        namespaces |= Namespaces::UTS;
+    }
+    if !opts.tmpfs.is_empty() || opts.pid {
+        namespaces |= Namespaces::MOUNT;
+    }
+
+    let cmd = match opts.cmd.is_empty() {
+        false => opts.cmd.clone(),
+        true => vec![env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string())],
+    };
+
+    let (uid, gid) = (unistd::getuid(), unistd::getgid());
+    ns::unshare(namespaces)?;
+
+    if namespaces.contains(Namespaces::USER) {
+        ns::map_root(uid, gid)?;
+    }
+
+    // Keep our mounts from leaking back out to the parent namespace
+    if namespaces.contains(Namespaces::MOUNT) {
+        mount::make_private("/", true)?;
+    }
+
+    if !namespaces.contains(Namespaces::PID) {
+        return exec_inside(&opts, &cmd, false);
+    }
+
+    // Only our children are in the new PID namespace
+    match unsafe { unistd::fork() }? {
+        ForkResult::Child => {
+            if let Err(err) = exec_inside(&opts, &cmd, true) {
+                eprintln!("ns-run: {}", err);
+            }
+            process::exit(1);
+        }
+        ForkResult::Parent { child } => loop {
+            match wait::waitpid(child, None) {
+                Ok(WaitStatus::Exited(_, code)) => process::exit(code),
+                Ok(WaitStatus::Signaled(_, sig, _)) => process::exit(128 + sig as i32),
+                Ok(_) | Err(Errno::EINTR) => continue,
+                Err(err) => return Err(err.into()),
+            }
+        },
+    }
+}
@@ -0,0 +1,183 @@
+// hinix/src/bin/hinix/pidwait.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Waits for arbitrary processes to exit.
+//!
+//! It uses a pidfd for each process, so unlike a `while kill -0` loop,
+//! it wakes up as soon as the process exits, and can't be fooled by
+//! the PID being reused.
+//!
+//! This is `hinix proc wait`, and the `pidwait` utility.
+
+use clap::Args;
+use hinix::{pidfd::PidFd, Error, Result};
+use nix::{
+    poll::{self, PollFd, PollFlags},
+    sys::signal::Signal,
+    unistd::Pid,
+};
+use std::{
+    convert::TryFrom,
+    os::unix::io::AsRawFd,
+    process,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+/// The exit code when the timeout expires. Errors exit with 1.
+const EXIT_TIMEOUT: i32 = 2;
+
+/// Parses a signal name, like "TERM" or "SIGTERM", or a number.
+fn parse_signal(s: &str) -> Option<Signal> {
+    if let Ok(n) = s.parse::<i32>() {
+        return Signal::try_from(n).ok();
+    }
+    let name = s.to_uppercase();
+    if name.starts_with("SIG") {
+        Signal::from_str(&name).ok()
+    }
+    else {
+        Signal::from_str(&format!("SIG{}", name)).ok()
+    }
+}
+
+/// Parses the signal option for clap.
+fn parse_signal_arg(s: &str) -> std::result::Result<Signal, String> {
+    parse_signal(s).ok_or_else(|| format!("unknown signal '{}'", s))
+}
+
+/// Parses a timeout in (possibly fractional) seconds.
+fn parse_secs(s: &str) -> std::result::Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(t) if t >= 0.0 && t.is_finite() => Ok(Duration::from_secs_f64(t)),
+        _ => Err("the timeout must be a non-negative number".into()),
+    }
+}
+
+/// Parses a process ID.
+fn parse_pid(s: &str) -> std::result::Result<Pid, String> {
+    match s.parse::<i32>() {
+        Ok(n) if n > 0 => Ok(Pid::from_raw(n)),
+        _ => Err(format!("invalid PID '{}'", s)),
+    }
+}
+
+/// Wait for processes to exit
+#[derive(Debug, Args)]
+#[command(
+    after_help = "Exits with 0 once the processes have exited, 2 if the timeout \
+                  expires, and 1 on error. A PID that doesn't exist is considered \
+                  to have already exited."
+)]
+pub struct Opts {
+    /// Seconds to wait before giving up
+    #[arg(short, long, value_name = "SECS", value_parser = parse_secs)]
+    timeout: Option<Duration>,
+
+    /// Send a signal to the processes before waiting (TERM, SIGINT, 9)
+    #[arg(short, long, value_name = "SIG", value_parser = parse_signal_arg)]
+    signal: Option<Signal>,
+
+    /// Return when any of the processes exits, rather than all
+    #[arg(short, long)]
+    any: bool,
+
+    /// Print the PID of each process as it exits
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// The processes to wait for
+    #[arg(required = true, value_parser = parse_pid)]
+    pid: Vec<Pid>,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let verbose = opts.verbose;
+    let any = opts.any;
+    let sig = opts.signal;
+
+    let deadline = opts.timeout.map(|t| Instant::now() + t);
+
+    // Open all the handles first, so a PID can't be recycled between
+    // signaling the process and waiting on it.
+    let mut pids = Vec::new();
+    let mut nexited = 0;
+
+    for pid in opts.pid {
+        match PidFd::open(pid) {
+            Ok(pidfd) => pids.push((pid, pidfd)),
+            Err(err) if err == Error::ESRCH => {
+                if verbose {
+                    println!("{}", pid);
+                }
+                nexited += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    if let Some(sig) = sig {
+        for (_, pidfd) in &pids {
+            match pidfd.send_signal(sig) {
+                Ok(()) => (),
+                Err(err) if err == Error::ESRCH => (),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    while !(pids.is_empty() || (any && nexited > 0)) {
+        let timeout = match deadline {
+            Some(deadline) => {
+                let rem = deadline.saturating_duration_since(Instant::now());
+                // Round up so we don't spin on the last partial millisecond
+                let ms = rem.as_millis() + u128::from(rem.subsec_nanos() % 1_000_000 != 0);
+                i32::try_from(ms).unwrap_or(i32::MAX)
+            }
+            None => -1,
+        };
+
+        let mut fds: Vec<_> = pids
+            .iter()
+            .map(|(_, pidfd)| PollFd::new(pidfd.as_raw_fd(), PollFlags::POLLIN))
+            .collect();
+
+        match poll::poll(&mut fds, timeout) {
+            Ok(0) => process::exit(EXIT_TIMEOUT),
+            Ok(_) => (),
+            Err(err) if err == Error::EINTR => continue,
+            Err(err) => return Err(err.into()),
+        }
+
+        let ready: Vec<bool> = fds
+            .iter()
+            .map(|fd| matches!(fd.revents(), Some(ev) if !ev.is_empty()))
+            .collect();
+
+        let mut ready = ready.into_iter();
+        pids.retain(|(pid, _)| {
+            let exited = ready.next().unwrap_or(false);
+            if exited {
+                if verbose {
+                    println!("{}", pid);
+                }
+                nexited += 1;
+            }
+            !exited
+        });
+    }
+
+    Ok(())
+}
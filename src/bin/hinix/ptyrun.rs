@@ -0,0 +1,231 @@
+// hinix/src/bin/hinix/ptyrun.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Runs a command under a pseudo-terminal.
+//!
+//! The command sees a real terminal, so it behaves as it would
+//! interactively, even if this program's input or output is a pipe. When
+//! run from a terminal, the input is passed through in raw mode, and
+//! window size changes are forwarded to the command.
+//!
+//! Like script(1), the session can be recorded to a file, along with a
+//! timing file that can be used to play it back with scriptreplay(1).
+//!
+//! This is `hinix pty run`, and the `ptyrun` utility.
+
+use clap::Args;
+use hinix::{
+    pty::{self, ForkPty, WinchForwarder},
+    term::{self, RawModeGuard},
+    Error, Result,
+};
+use nix::{
+    poll::{self, PollFd, PollFlags},
+    sys::{
+        termios,
+        wait::{self, WaitStatus},
+    },
+    unistd,
+};
+use std::{
+    env,
+    fs::File,
+    io::{self, Read, Write},
+    os::unix::{io::AsRawFd, process::CommandExt},
+    process::{self, Command},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// The exit code if the command can't be run.
+const EXIT_EXEC_FAILED: i32 = 127;
+
+/// A recording of the session's output, in the format of script(1).
+struct Recorder {
+    /// The output of the session
+    out: File,
+    /// The time and size of each chunk of output, if requested
+    timing: Option<File>,
+    /// The time of the last chunk of output
+    last: Instant,
+}
+
+impl Recorder {
+    /// Creates a recording, writing the header line to the output.
+    fn new(path: &str, timing: Option<&str>, cmd: &[&str]) -> io::Result<Self> {
+        let mut out = File::create(path)?;
+        let timing = timing.map(File::create).transpose()?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        writeln!(
+            out,
+            "Script started on {} [COMMAND=\"{}\"]",
+            now.as_secs(),
+            cmd.join(" ")
+        )?;
+
+        Ok(Self {
+            out,
+            timing,
+            last: Instant::now(),
+        })
+    }
+
+    /// Records a chunk of output.
+    fn record(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.out.write_all(buf)?;
+        if let Some(ref mut timing) = self.timing {
+            let now = Instant::now();
+            let dt = now.duration_since(self.last);
+            writeln!(
+                timing,
+                "{}.{:06} {}",
+                dt.as_secs(),
+                dt.subsec_micros(),
+                buf.len()
+            )?;
+            self.last = now;
+        }
+        Ok(())
+    }
+}
+
+/// Run a command under a pseudo-terminal
+#[derive(Debug, Args)]
+#[command(
+    after_help = "Exits with the exit code of the command, or 128 plus the signal \
+                  that killed it. If no command is given, runs $SHELL."
+)]
+pub struct Opts {
+    /// Record the session output to a file
+    #[arg(short, long, value_name = "PATH")]
+    record: Option<String>,
+
+    /// Write the timing of the recorded output to a file, for scriptreplay(1)
+    #[arg(short = 'T', long, value_name = "PATH", requires = "record")]
+    timing: Option<String>,
+
+    /// The program to run, and its arguments
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    cmd: Vec<String>,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let shell = env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+    let cmd: Vec<&str> = match opts.cmd.is_empty() {
+        false => opts.cmd.iter().map(String::as_str).collect(),
+        true => vec![&shell],
+    };
+
+    let mut recorder = match &opts.record {
+        Some(path) => Some(Recorder::new(path, opts.timing.as_deref(), &cmd)?),
+        None => None,
+    };
+
+    // Give the pty the same size and settings as our terminal, if any
+    let stdin = io::stdin();
+    let is_tty = term::is_tty(&stdin);
+    let (winsize, tio) = if is_tty {
+        (
+            Some(term::size(&stdin)?),
+            Some(termios::tcgetattr(stdin.as_raw_fd())?),
+        )
+    }
+    else {
+        (None, None)
+    };
+
+    let (child, mut master) = match unsafe { pty::fork_pty(winsize.as_ref(), tio.as_ref()) }? {
+        ForkPty::Child => {
+            let err = Command::new(cmd[0]).args(&cmd[1..]).exec();
+            eprintln!("ptyrun: unable to run the program: {}", err);
+            process::exit(EXIT_EXEC_FAILED);
+        }
+        ForkPty::Parent { child, master } => (child, master),
+    };
+
+    // Pass keystrokes straight through, and keep the size in sync
+    let (raw_mode, winch) = if is_tty {
+        (
+            Some(RawModeGuard::new()?),
+            Some(WinchForwarder::new(&stdin, &master)?),
+        )
+    }
+    else {
+        (None, None)
+    };
+
+    let mut stdout = io::stdout();
+    let mut buf = [0u8; 4096];
+    let mut stdin_open = true;
+
+    loop {
+        let mut fds = vec![PollFd::new(master.as_raw_fd(), PollFlags::POLLIN)];
+        if stdin_open {
+            fds.push(PollFd::new(libc::STDIN_FILENO, PollFlags::POLLIN));
+        }
+
+        match poll::poll(&mut fds, -1) {
+            Ok(_) => (),
+            Err(err) if err == Error::EINTR => continue,
+            Err(err) => return Err(err.into()),
+        }
+
+        let ready = |fd: &PollFd| matches!(fd.revents(), Some(ev) if !ev.is_empty());
+
+        if stdin_open && ready(&fds[1]) {
+            match unistd::read(libc::STDIN_FILENO, &mut buf) {
+                Ok(0) | Err(_) => {
+                    // Pass the EOF along, like typing Ctrl-D
+                    stdin_open = false;
+                    if !is_tty {
+                        let eof = termios::tcgetattr(master.as_raw_fd())
+                            .map(|tio| tio.control_chars[libc::VEOF])
+                            .unwrap_or(4);
+                        let _ = master.write_all(&[eof]);
+                    }
+                }
+                Ok(n) => master.write_all(&buf[..n])?,
+            }
+        }
+
+        if ready(&fds[0]) {
+            let n = master.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            stdout.write_all(&buf[..n]).and_then(|_| stdout.flush())?;
+            if let Some(ref mut rec) = recorder {
+                rec.record(&buf[..n])?;
+            }
+        }
+    }
+
+    let code = loop {
+        match wait::waitpid(child, None) {
+            Ok(WaitStatus::Exited(_, code)) => break code,
+            Ok(WaitStatus::Signaled(_, sig, _)) => break 128 + sig as i32,
+            Ok(_) => continue,
+            Err(err) if err == Error::EINTR => continue,
+            Err(err) => return Err(err.into()),
+        }
+    };
+
+    // Restore the terminal before exiting
+    drop(winch);
+    drop(raw_mode);
+    process::exit(code);
+}
@@ -0,0 +1,166 @@
+// hinix/src/bin/hinix/rtrun.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Runs a command with real-time scheduling settings.
+//!
+//! It sets the CPU affinity, scheduling policy and priority, and nice
+//! value of the process, then execs the command, which inherits them.
+//! This combines the features of taskset(1), chrt(1), and nice(1).
+//!
+//! This is `hinix proc rt`, and the `rtrun` utility.
+
+use clap::{builder::PossibleValuesParser, Args};
+use hinix::{
+    sched::{self, CpuSet, Policy, PriorityTarget},
+    Error, Result,
+};
+use nix::{
+    sys::resource::{self, Resource},
+    unistd::Pid,
+};
+use std::{
+    os::unix::process::CommandExt,
+    process::{self, Command},
+};
+
+/// The exit code if the command can't be run.
+const EXIT_EXEC_FAILED: i32 = 127;
+
+/// Parses the name of a scheduling policy.
+fn parse_policy(s: &str) -> Option<Policy> {
+    match s.to_lowercase().as_str() {
+        "other" | "normal" => Some(Policy::Other),
+        "batch" => Some(Policy::Batch),
+        "idle" => Some(Policy::Idle),
+        "fifo" => Some(Policy::Fifo),
+        "rr" => Some(Policy::RoundRobin),
+        _ => None,
+    }
+}
+
+/// Parses the CPU list option.
+fn parse_cpus(s: &str) -> std::result::Result<CpuSet, String> {
+    match s.parse::<CpuSet>() {
+        Ok(cpus) if !cpus.is_empty() => Ok(cpus),
+        _ => Err("the CPUs must be a list like 0,2-3".into()),
+    }
+}
+
+/// Parses the nice value option.
+fn parse_nice(s: &str) -> std::result::Result<i32, String> {
+    match s.parse::<i32>() {
+        Ok(n) if (-20..=19).contains(&n) => Ok(n),
+        _ => Err("the nice value must be from -20 to 19".into()),
+    }
+}
+
+/// Run a command with CPU affinity and real-time scheduling
+#[derive(Debug, Args)]
+#[command(
+    after_help = "Real-time policies normally need the CAP_SYS_NICE capability. \
+                  Memory locks don't survive an exec, so --memlock only allows the \
+                  program to lock its own memory with mlockall()."
+)]
+pub struct Opts {
+    /// The CPUs the command may run on, as a list like 0,2-3
+    #[arg(short, long, value_name = "LIST", value_parser = parse_cpus)]
+    cpus: Option<CpuSet>,
+
+    /// The scheduling policy
+    #[arg(short, long,
+          value_parser = PossibleValuesParser::new(["other", "batch", "idle", "fifo", "rr"]))]
+    policy: Option<String>,
+
+    /// The real-time priority, for the fifo and rr policies
+    #[arg(short = 'r', long, requires = "policy")]
+    priority: Option<u32>,
+
+    /// The nice value, from -20 (highest) to 19 (lowest)
+    #[arg(short, long, allow_hyphen_values = true, value_parser = parse_nice)]
+    nice: Option<i32>,
+
+    /// Allow the command to lock all of its memory into RAM
+    #[arg(short, long)]
+    memlock: bool,
+
+    /// Print the settings before running the command
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// The program to run, and its arguments
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    cmd: Vec<String>,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let verbose = opts.verbose;
+    let me = Pid::from_raw(0);
+
+    if let Some(cpus) = opts.cpus {
+        sched::set_affinity(me, &cpus)?;
+        if verbose {
+            eprintln!("rtrun: CPUs {}", cpus);
+        }
+    }
+
+    if let Some(policy) = opts.policy.as_deref().and_then(parse_policy) {
+        // Real-time policies need a priority, so default to the lowest
+        let prio = match opts.priority {
+            Some(prio) => prio,
+            None if policy.is_realtime() => *sched::priority_range(policy)?.start(),
+            None => 0,
+        };
+        if !sched::priority_range(policy)?.contains(&prio) {
+            eprintln!("rtrun: priority {} is out of range for {:?}", prio, policy);
+            return Err(Error::EINVAL);
+        }
+        sched::set_policy(me, policy, prio)?;
+        if verbose {
+            eprintln!("rtrun: policy {:?}, priority {}", policy, prio);
+        }
+    }
+
+    if let Some(nice) = opts.nice {
+        sched::set_priority(PriorityTarget::Process(me), nice)?;
+        if verbose {
+            eprintln!("rtrun: nice {}", nice);
+        }
+    }
+
+    if opts.memlock {
+        // Raising the hard limit needs privileges, so without them, go
+        // as high as we're allowed.
+        let inf = libc::RLIM_INFINITY;
+        let limit = match resource::setrlimit(Resource::RLIMIT_MEMLOCK, inf, inf) {
+            Ok(()) => inf,
+            Err(err) if err == Error::EPERM => {
+                let (_, hard) = resource::getrlimit(Resource::RLIMIT_MEMLOCK)?;
+                resource::setrlimit(Resource::RLIMIT_MEMLOCK, hard, hard)?;
+                hard
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if verbose {
+            match limit {
+                libc::RLIM_INFINITY => eprintln!("rtrun: memlock unlimited"),
+                n => eprintln!("rtrun: memlock {} bytes", n),
+            }
+        }
+    }
+
+    let err = Command::new(&opts.cmd[0]).args(&opts.cmd[1..]).exec();
+    eprintln!("rtrun: unable to run the program: {}", err);
+    process::exit(EXIT_EXEC_FAILED);
+}
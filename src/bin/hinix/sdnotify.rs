@@ -0,0 +1,134 @@
+// hinix/src/bin/hinix/sdnotify.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Sends notifications to the systemd service manager, for shell scripts
+//! running in `Type=notify` services.
+//!
+//! Since the notification comes from this process, rather than the
+//! script, the unit typically needs `NotifyAccess=all`, and the script
+//! should identify itself with `--pid`:
+//!
+//! ```text
+//! sdnotify --ready --pid --status "Waiting for requests"
+//! ```
+//!
+//! This is `hinix sd notify`, and the `sdnotify` utility.
+
+use clap::Args;
+use hinix::{
+    systemd::{self, State},
+    Result,
+};
+use nix::unistd::{self, Pid};
+use std::process;
+
+/// The exit code when not running under systemd. Errors exit with 1.
+const EXIT_NO_SOCKET: i32 = 2;
+
+/// Parses a process ID.
+fn parse_pid(s: &str) -> std::result::Result<Pid, String> {
+    match s.parse::<i32>() {
+        Ok(n) if n > 0 => Ok(Pid::from_raw(n)),
+        _ => Err(format!("invalid PID '{}'", s)),
+    }
+}
+
+/// Checks that a variable is a `VAR=VALUE` assignment.
+fn parse_var(s: &str) -> std::result::Result<String, String> {
+    match s.find('=') {
+        Some(i) if i > 0 => Ok(s.to_string()),
+        _ => Err(format!("'{}' isn't a VAR=VALUE assignment", s)),
+    }
+}
+
+/// Send notifications to the systemd service manager
+#[derive(Debug, Args)]
+#[command(
+    after_help = "Exits with 2 if NOTIFY_SOCKET isn't set, meaning this wasn't started \
+                  by systemd, or the service isn't Type=notify."
+)]
+pub struct Opts {
+    /// The service has finished starting up
+    #[arg(short, long)]
+    ready: bool,
+
+    /// The service is reloading its configuration
+    #[arg(long)]
+    reloading: bool,
+
+    /// The service is shutting down
+    #[arg(long)]
+    stopping: bool,
+
+    /// Send a keep-alive ping to the service watchdog
+    #[arg(short, long)]
+    watchdog: bool,
+
+    /// A human-readable status string for the service
+    #[arg(short, long, value_name = "TEXT")]
+    status: Option<String>,
+
+    /// The main process of the service [default: the parent of this one]
+    #[arg(short, long, num_args = 0..=1, value_parser = parse_pid)]
+    pid: Option<Option<Pid>>,
+
+    /// Don't complain if not running under systemd
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Other variable assignments to send, like ERRNO=2
+    #[arg(value_parser = parse_var)]
+    vars: Vec<String>,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let mut states = Vec::new();
+
+    // The main PID goes first, so the manager knows who the rest is from
+    if let Some(pid) = opts.pid {
+        let pid = pid.unwrap_or_else(unistd::getppid);
+        states.push(State::MainPid(pid));
+    }
+
+    for (set, state) in [
+        (opts.reloading, State::Reloading),
+        (opts.ready, State::Ready),
+        (opts.stopping, State::Stopping),
+        (opts.watchdog, State::Watchdog),
+    ] {
+        if set {
+            states.push(state);
+        }
+    }
+
+    if let Some(status) = opts.status {
+        states.push(State::Status(status));
+    }
+
+    states.extend(opts.vars.into_iter().map(State::Other));
+
+    if states.is_empty() {
+        eprintln!("sdnotify: nothing to send");
+        process::exit(1);
+    }
+
+    if !systemd::notify(&states)? {
+        if !opts.quiet {
+            eprintln!("sdnotify: NOTIFY_SOCKET is not set");
+        }
+        process::exit(EXIT_NO_SOCKET);
+    }
+    Ok(())
+}
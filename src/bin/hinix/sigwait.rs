@@ -0,0 +1,126 @@
+// hinix/src/bin/hinix/sigwait.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Waits for a signal, and reports which one arrived and who sent it.
+//!
+//! It exits with 128 plus the signal number, like a shell reports a
+//! process killed by the signal, or 2 if the timeout expires.
+//!
+//! This is `hinix sig wait`, and the `sigwait` utility.
+
+use clap::Args;
+use hinix::{signalfd::SignalFd, Error, Result};
+use nix::{
+    sys::signal::{SigSet, Signal},
+    unistd,
+};
+use std::{convert::TryFrom, process, str::FromStr, time::Duration};
+
+/// The exit code when the timeout expires. Errors exit with 1.
+const EXIT_TIMEOUT: i32 = 2;
+
+/// Parses a signal name, like "INT" or "SIGINT", or a number.
+fn parse_signal(s: &str) -> Option<Signal> {
+    if let Ok(n) = s.parse::<i32>() {
+        return Signal::try_from(n).ok();
+    }
+    let name = s.to_uppercase();
+    if name.starts_with("SIG") {
+        Signal::from_str(&name).ok()
+    }
+    else {
+        Signal::from_str(&format!("SIG{}", name)).ok()
+    }
+}
+
+/// Parses a signal that can be caught, for clap.
+fn parse_signal_arg(s: &str) -> std::result::Result<Signal, String> {
+    match parse_signal(s) {
+        Some(Signal::SIGKILL) | Some(Signal::SIGSTOP) => {
+            Err("SIGKILL and SIGSTOP can't be caught".into())
+        }
+        Some(sig) => Ok(sig),
+        None => Err(format!("unknown signal '{}'", s)),
+    }
+}
+
+/// Parses a timeout in (possibly fractional) seconds.
+fn parse_secs(s: &str) -> std::result::Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(t) if t >= 0.0 && t.is_finite() => Ok(Duration::from_secs_f64(t)),
+        _ => Err("the timeout must be a non-negative number".into()),
+    }
+}
+
+/// Wait for a signal, and report who sent it
+#[derive(Debug, Args)]
+#[command(after_help = "Exits with 128 plus the number of the signal received, \
+                        or 2 if the timeout expires.")]
+pub struct Opts {
+    /// Seconds to wait before giving up
+    #[arg(short, long, value_name = "SECS", value_parser = parse_secs)]
+    timeout: Option<Duration>,
+
+    /// Print our process ID before waiting, to tell the sender
+    #[arg(short, long)]
+    pid: bool,
+
+    /// Don't print the signal, just exit with its code
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// The signals to wait for, by name (INT, SIGUSR1) or number
+    #[arg(required = true, value_parser = parse_signal_arg)]
+    signal: Vec<Signal>,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let mut mask = SigSet::empty();
+    for sig in opts.signal {
+        mask.add(sig);
+    }
+
+    let timeout = opts.timeout;
+
+    // Block the signals before announcing we're ready for them
+    let sfd = SignalFd::new(&mask)?;
+
+    if opts.pid {
+        println!("{}", unistd::getpid());
+    }
+
+    let info = loop {
+        let res = match timeout {
+            Some(timeout) => sfd.read_timeout(timeout),
+            None => sfd.read().map(Some),
+        };
+        match res {
+            Ok(Some(info)) => break info,
+            Ok(None) => process::exit(EXIT_TIMEOUT),
+            Err(err) if err == Error::EINTR => continue,
+            Err(err) => return Err(err),
+        }
+    };
+
+    if !opts.quiet {
+        println!(
+            "{} from pid {} (uid {})",
+            info.signal.as_str(),
+            info.pid,
+            info.uid
+        );
+    }
+    process::exit(128 + info.signal as i32);
+}
@@ -0,0 +1,199 @@
+// hinix/src/bin/hinix/tick.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Fires at a regular interval, using a timerfd, and either prints a
+//! timestamp or runs a command on each tick.
+//!
+//! When it finishes, after a count of ticks or on SIGINT/SIGTERM, it
+//! prints statistics about how late the ticks were. This is useful to
+//! check timer behavior on real-time kernels.
+//!
+//! This is `hinix timer tick`, and the `tick` utility.
+
+use clap::{builder::PossibleValuesParser, Args};
+use hinix::{
+    clock::{self, ClockId},
+    timerfd::TimerFd,
+    Error, Result,
+};
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::{
+    process::{self, Command},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+/// Set by the signal handler to stop the loop.
+static QUIT: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_quit(_: libc::c_int) {
+    QUIT.store(true, Ordering::SeqCst);
+}
+
+/// The statistics for how late each tick arrived.
+#[derive(Debug, Default)]
+struct Stats {
+    /// The number of ticks handled
+    ticks: u64,
+    /// The number of ticks that were missed
+    overruns: u64,
+    /// The smallest latency
+    min: Option<Duration>,
+    /// The largest latency
+    max: Duration,
+    /// The sum of the latencies
+    total: Duration,
+}
+
+impl Stats {
+    /// Records the latency of a tick, and the number that were missed.
+    fn add(&mut self, late: Duration, missed: u64) {
+        self.ticks += 1;
+        self.overruns += missed;
+        self.min = Some(self.min.map_or(late, |min| min.min(late)));
+        self.max = self.max.max(late);
+        self.total += late;
+    }
+
+    /// Prints a summary.
+    fn print(&self) {
+        let us = |d: Duration| d.as_secs_f64() * 1e6;
+        let mean = if self.ticks > 0 {
+            self.total / self.ticks as u32
+        }
+        else {
+            Duration::ZERO
+        };
+        eprintln!(
+            "ticks: {}, overruns: {}, latency (us) min: {:.1}, mean: {:.1}, max: {:.1}",
+            self.ticks,
+            self.overruns,
+            us(self.min.unwrap_or_default()),
+            us(mean),
+            us(self.max)
+        );
+    }
+}
+
+/// Parses the interval in (possibly fractional) seconds.
+fn parse_interval(s: &str) -> std::result::Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(t) if t > 0.0 && t.is_finite() => Ok(Duration::from_secs_f64(t)),
+        _ => Err("the interval must be a positive number".into()),
+    }
+}
+
+/// Fire at a regular interval, printing the time or running a command
+#[derive(Debug, Args)]
+pub struct Opts {
+    /// Seconds between ticks
+    #[arg(short, long, default_value = "1", value_parser = parse_interval)]
+    interval: Duration,
+
+    /// Stop after this many ticks
+    #[arg(short, long)]
+    count: Option<u64>,
+
+    /// The clock for the timer
+    #[arg(long, default_value = "monotonic",
+          value_parser = PossibleValuesParser::new(["monotonic", "realtime", "boottime"]))]
+    clock: String,
+
+    /// Don't print each tick, only the statistics
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// A command to run on each tick
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    cmd: Vec<String>,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let interval = opts.interval;
+
+    let count = opts.count.unwrap_or(u64::MAX);
+    let quiet = opts.quiet;
+
+    let clock_id = match opts.clock.as_str() {
+        "realtime" => ClockId::CLOCK_REALTIME,
+        "boottime" => ClockId::CLOCK_BOOTTIME,
+        _ => ClockId::CLOCK_MONOTONIC,
+    };
+
+    let cmd = Some(opts.cmd).filter(|cmd| !cmd.is_empty());
+
+    // No SA_RESTART, so that the wait is interrupted.
+    let sa = SigAction::new(
+        SigHandler::Handler(handle_quit),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    for sig in [Signal::SIGINT, Signal::SIGTERM] {
+        unsafe { signal::sigaction(sig, &sa) }?;
+    }
+
+    // Schedule against absolute times so the ticks don't drift
+    let timer = TimerFd::new(clock_id)?;
+    let start = clock::now(clock_id)?;
+    timer.set_absolute(start + interval, Some(interval))?;
+
+    let mut stats = Stats::default();
+    let mut n: u64 = 0;
+
+    while !QUIT.load(Ordering::SeqCst) && n < count {
+        let expirations = match timer.wait() {
+            Ok(k) => k,
+            Err(err) if err == Error::EINTR => continue,
+            Err(err) => return Err(err),
+        };
+
+        let now = clock::now(clock_id)?;
+        n += expirations;
+
+        let expected = start + Duration::from_nanos((interval.as_nanos() * n as u128) as u64);
+        let late = now.saturating_sub(expected);
+        stats.add(late, expirations - 1);
+
+        match &cmd {
+            Some(cmd) => match Command::new(&cmd[0]).args(&cmd[1..]).status() {
+                Ok(_) => (),
+                Err(err) => {
+                    eprintln!("tick: unable to run '{}': {}", cmd[0], err);
+                    process::exit(127);
+                }
+            },
+            None if !quiet => {
+                let missed = if expirations > 1 {
+                    format!(" ({} missed)", expirations - 1)
+                }
+                else {
+                    String::new()
+                };
+                println!(
+                    "{} {}.{:06} late {:.1}us{}",
+                    n,
+                    now.as_secs(),
+                    now.subsec_micros(),
+                    late.as_secs_f64() * 1e6,
+                    missed
+                );
+            }
+            None => (),
+        }
+    }
+
+    stats.print();
+    Ok(())
+}
@@ -0,0 +1,191 @@
+// hinix/src/bin/hinix/uds_recv.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Receives messages on a Unix-domain socket.
+//!
+//! It binds the socket, and prints the datagrams or seqpacket messages
+//! that arrive. Any file handles that the peer passes along with a
+//! message can be handed to a command, starting at handle 3, as in:
+//!
+//! ```text
+//! $ uds-recv /run/myapp.sock -- sh -c 'cat <&3'
+//! ```
+//!
+//! This is `hinix uds recv`, and the `uds-recv` utility.
+
+use clap::Args;
+use hinix::{fdpass, prelude::*};
+use nix::sys::socket::{self, AddressFamily, SockFlag, SockType, UnixAddr};
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::{
+        io::{AsFd, AsRawFd, FromRawFd, OwnedFd},
+        process::CommandExt,
+    },
+    process::{self, Command},
+};
+
+/// The exit code if the command could not be run.
+const EXIT_EXEC_FAILED: i32 = 127;
+
+/// Gets the address of a socket, with a leading '@' for an abstract
+/// name.
+fn socket_addr(path: &str) -> Result<UnixAddr> {
+    match path.strip_prefix('@') {
+        Some(name) => Ok(UnixAddr::new_abstract(name.as_bytes())?),
+        None => Ok(UnixAddr::new(path)?),
+    }
+}
+
+/// Receive messages and open file handles on a Unix-domain socket
+#[derive(Debug, Args)]
+#[command(
+    after_help = "A socket path starting with '@' is in the abstract namespace. \
+                  If a command is given, it's run after the first message is \
+                  received, with the handles that came with it as 3, 4, etc."
+)]
+pub struct Opts {
+    /// Accept a connection and receive seqpacket messages, rather than datagrams
+    #[arg(short, long)]
+    seqpacket: bool,
+
+    /// Keep receiving and printing messages
+    #[arg(short, long, conflicts_with_all = ["count", "cmd"])]
+    follow: bool,
+
+    /// Stop after receiving this many messages
+    #[arg(short, long, value_name = "N", conflicts_with = "cmd",
+          value_parser = clap::value_parser!(u64).range(1..))]
+    count: Option<u64>,
+
+    /// Remove a stale socket file before binding
+    #[arg(short, long)]
+    unlink: bool,
+
+    /// Report the handles that were received
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// The path of the socket
+    path: String,
+
+    /// A program to run with the received handles, and its arguments
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    cmd: Vec<String>,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let path = opts.path.as_str();
+    let addr = socket_addr(path)?;
+    let is_file = !path.starts_with('@');
+
+    if is_file && opts.unlink {
+        let _ = fs::remove_file(path);
+    }
+
+    let seqpacket = opts.seqpacket;
+    let sock_type = if seqpacket {
+        SockType::SeqPacket
+    }
+    else {
+        SockType::Datagram
+    };
+
+    let sock = socket::socket(AddressFamily::Unix, sock_type, SockFlag::SOCK_CLOEXEC, None)?;
+    let sock = unsafe { OwnedFd::from_raw_fd(sock) };
+    socket::bind(sock.as_raw_fd(), &addr)?;
+
+    // Removes the socket file, once we're done with it
+    let cleanup = || {
+        if is_file {
+            let _ = fs::remove_file(path);
+        }
+    };
+
+    let sock = if seqpacket {
+        socket::listen(sock.as_raw_fd(), 1)?;
+        let conn = socket::accept4(sock.as_raw_fd(), SockFlag::SOCK_CLOEXEC);
+        cleanup();
+        unsafe { OwnedFd::from_raw_fd(conn?) }
+    }
+    else {
+        sock
+    };
+
+    let verbose = opts.verbose;
+    let count = match opts.count {
+        Some(n) => Some(n),
+        None if opts.follow => None,
+        None => Some(1),
+    };
+
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut received = 0;
+
+    loop {
+        let (n, fds) = match fdpass::recv_with_fds(&sock, &mut buf, fdpass::MAX_FDS) {
+            Ok(res) => res,
+            Err(err) if err == Error::EINTR => continue,
+            Err(err) => {
+                cleanup();
+                return Err(err);
+            }
+        };
+
+        // A seqpacket peer hung up
+        if seqpacket && n == 0 && fds.is_empty() {
+            break;
+        }
+        received += 1;
+
+        let mut out = io::stdout().lock();
+        out.write_all(&buf[..n])
+            .and_then(|_| out.write_all(b"\n"))
+            .and_then(|_| out.flush())?;
+
+        if verbose {
+            for fd in &fds {
+                let target = fs::read_link(format!("/proc/self/fd/{}", fd.as_raw_fd()))
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_else(|_| "?".into());
+                eprintln!("uds-recv: received handle: {}", target);
+            }
+        }
+
+        if !opts.cmd.is_empty() {
+            cleanup();
+            // Move the handles into place, starting at 3. Duplicating
+            // them first keeps them from clobbering each other.
+            let fds = fds
+                .iter()
+                .map(|fd| fd.as_fd().dup())
+                .collect::<Result<Vec<_>>>()?;
+            for (i, fd) in fds.iter().enumerate() {
+                fd.dup_to(3 + i as i32)?;
+            }
+            let err = Command::new(&opts.cmd[0]).args(&opts.cmd[1..]).exec();
+            eprintln!("uds-recv: unable to run the program: {}", err);
+            process::exit(EXIT_EXEC_FAILED);
+        }
+
+        if count == Some(received) {
+            break;
+        }
+    }
+
+    cleanup();
+    Ok(())
+}
@@ -0,0 +1,125 @@
+// hinix/src/bin/hinix/uds_send.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Sends messages to a Unix-domain socket.
+//!
+//! It can send datagrams, or seqpacket messages over a connection, and
+//! can pass copies of its open file handles along with the message, so
+//! that a shell script can hand a file, pipe, or socket to another
+//! process, as in:
+//!
+//! ```text
+//! $ uds-send --pass-fd 3 /run/myapp.sock "here's a file" 3< data.bin
+//! ```
+//!
+//! This is `hinix uds send`, and the `uds-send` utility.
+
+use clap::Args;
+use hinix::{fdpass, prelude::*};
+use nix::{
+    fcntl::{self, FcntlArg},
+    sys::socket::{self, AddressFamily, SockFlag, SockType, UnixAddr},
+};
+use std::{
+    io::{self, BufRead, Read},
+    os::unix::io::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+};
+
+/// Gets the address of a socket, with a leading '@' for an abstract
+/// name.
+fn socket_addr(path: &str) -> Result<UnixAddr> {
+    match path.strip_prefix('@') {
+        Some(name) => Ok(UnixAddr::new_abstract(name.as_bytes())?),
+        None => Ok(UnixAddr::new(path)?),
+    }
+}
+
+/// Parses a file handle number.
+fn parse_fd(s: &str) -> std::result::Result<RawFd, String> {
+    match s.parse::<RawFd>() {
+        Ok(fd) if fd >= 0 => Ok(fd),
+        _ => Err("the handle must be a non-negative integer".into()),
+    }
+}
+
+/// Send messages and open file handles to a Unix-domain socket
+#[derive(Debug, Args)]
+#[command(
+    after_help = "A socket path starting with '@' is in the abstract namespace. \
+                  Any handles passed with --pass-fd are sent with the first message."
+)]
+pub struct Opts {
+    /// Connect and send seqpacket messages, rather than datagrams
+    #[arg(short, long)]
+    seqpacket: bool,
+
+    /// Pass a copy of an open file handle to the peer
+    #[arg(short = 'f', long, value_name = "FD", value_parser = parse_fd)]
+    pass_fd: Vec<RawFd>,
+
+    /// Send each line of the input as a separate message
+    #[arg(short, long)]
+    lines: bool,
+
+    /// The path of the socket
+    path: String,
+
+    /// The message to send, or '-' to read it from stdin [default: -]
+    msg: Option<String>,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let addr = socket_addr(&opts.path)?;
+
+    // Make sure the handles are open before borrowing them
+    let mut fds = Vec::new();
+    for &fd in &opts.pass_fd {
+        fcntl::fcntl(fd, FcntlArg::F_GETFD)?;
+        fds.push(unsafe { BorrowedFd::borrow_raw(fd) });
+    }
+
+    let msgs: Vec<Vec<u8>> = match opts.msg.as_deref() {
+        Some(msg) if msg != "-" => vec![msg.as_bytes().to_vec()],
+        _ if opts.lines => io::stdin().lock().split(b'\n').collect::<io::Result<_>>()?,
+        _ => {
+            let mut buf = Vec::new();
+            io::stdin().read_to_end(&mut buf)?;
+            vec![buf]
+        }
+    };
+
+    let seqpacket = opts.seqpacket;
+    let sock_type = if seqpacket {
+        SockType::SeqPacket
+    }
+    else {
+        SockType::Datagram
+    };
+
+    let sock = socket::socket(AddressFamily::Unix, sock_type, SockFlag::SOCK_CLOEXEC, None)?;
+    let sock = unsafe { OwnedFd::from_raw_fd(sock) };
+
+    if seqpacket {
+        socket::connect(sock.as_raw_fd(), &addr)?;
+    }
+    let dest = if seqpacket { None } else { Some(&addr) };
+
+    for (i, msg) in msgs.iter().enumerate() {
+        let fds: &[BorrowedFd] = if i == 0 { &fds } else { &[] };
+        fdpass::send_to_with_fds(&sock, msg, fds, dest)?;
+    }
+
+    Ok(())
+}
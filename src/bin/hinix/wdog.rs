@@ -0,0 +1,155 @@
+// hinix/src/bin/hinix/wdog.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Keeps a hardware watchdog from firing.
+//!
+//! It opens the watchdog device, optionally sets the timeout, then pets
+//! it at a regular interval until it gets SIGTERM or SIGINT, at which
+//! point it does a magic close to stop the timer. If it's killed any
+//! other way, the timer keeps running and the system resets, which is
+//! handy for testing that the watchdog is actually wired up.
+//!
+//! This is `hinix wdog`, and the `wdog` utility.
+
+use clap::Args;
+use hinix::{
+    signalfd::SignalFd,
+    watchdog::{self, Watchdog},
+    Error, Result,
+};
+use nix::sys::signal::{SigSet, Signal};
+use std::time::{Duration, Instant};
+
+/// Parses a time argument, in seconds.
+fn parse_secs(s: &str) -> std::result::Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(t) if t > 0.0 && t.is_finite() => Ok(Duration::from_secs_f64(t)),
+        _ => Err("the time must be a positive number of seconds".into()),
+    }
+}
+
+/// Keep a hardware watchdog from firing
+#[derive(Debug, Args)]
+#[command(
+    after_help = "The watchdog is pet until the program gets SIGTERM or SIGINT, \
+                  when it does a magic close to stop the timer. If it's killed \
+                  any other way, the system resets when the timeout expires."
+)]
+pub struct Opts {
+    /// The watchdog device
+    #[arg(short, long, value_name = "PATH", default_value = watchdog::DEFAULT_DEVICE)]
+    device: String,
+
+    /// Sets the watchdog timeout, in seconds
+    #[arg(short, long, value_name = "SECS", value_parser = parse_secs)]
+    timeout: Option<Duration>,
+
+    /// Seconds between pets [default: half the timeout]
+    #[arg(short, long, value_name = "SECS", value_parser = parse_secs)]
+    interval: Option<Duration>,
+
+    /// Print the watchdog information and status, then stop it
+    #[arg(short = 'I', long)]
+    info: bool,
+
+    /// Print the settings, and a line for each pet
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+// --------------------------------------------------------------------------
+
+/// Runs the command.
+pub fn run(opts: Opts) -> Result<()> {
+    let verbose = opts.verbose;
+
+    // Block the signals before opening the watchdog, so that there's no
+    // window where they would kill us and leave it running.
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGTERM);
+    mask.add(Signal::SIGINT);
+    let sfd = SignalFd::new(&mask)?;
+
+    let wdog = Watchdog::open(&opts.device)?;
+
+    if opts.info {
+        let res = wdog.info().map(|info| {
+            println!("Identity: {}", info.identity);
+            println!("Firmware: {}", info.firmware_version);
+            println!("Options:  {:?}", info.options);
+            if let Ok(status) = wdog.boot_status() {
+                println!("Boot status: {:?}", status);
+            }
+            if let Ok(timeout) = wdog.timeout() {
+                println!("Timeout: {}s", timeout.as_secs());
+            }
+            if let Ok(left) = wdog.time_left() {
+                println!("Time left: {}s", left.as_secs());
+            }
+        });
+        wdog.close()?;
+        return res;
+    }
+
+    let timeout = match opts.timeout {
+        Some(t) => wdog.set_timeout(t)?,
+        None => wdog.timeout()?,
+    };
+
+    let interval = match opts.interval {
+        Some(t) => t,
+        None => (timeout / 2).max(Duration::from_millis(500)),
+    };
+
+    if verbose {
+        println!(
+            "Petting the watchdog every {:.3}s, with a {}s timeout",
+            interval.as_secs_f64(),
+            timeout.as_secs()
+        );
+        if interval >= timeout {
+            eprintln!("Warning: the interval is not less than the timeout");
+        }
+    }
+
+    wdog.keepalive()?;
+    let mut next = Instant::now() + interval;
+
+    loop {
+        let wait = next.saturating_duration_since(Instant::now());
+        match sfd.read_timeout(wait) {
+            Ok(Some(info)) => {
+                if verbose {
+                    println!("Got {}, stopping the watchdog", info.signal.as_str());
+                }
+                break;
+            }
+            Ok(None) => {
+                wdog.keepalive()?;
+                if verbose {
+                    println!("Pet");
+                }
+                // Keep to the schedule, but don't try to catch up after
+                // a stall.
+                next += interval;
+                let now = Instant::now();
+                if next < now {
+                    next = now + interval;
+                }
+            }
+            Err(err) if err == Error::EINTR => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    wdog.close()
+}
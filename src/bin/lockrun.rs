@@ -0,0 +1,34 @@
+// hinix/src/bin/lockrun.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! This CLI application runs a command while holding a lock on a file.
+//!
+//! This is the same as `hinix fs lock`, under the utility's own name.
+
+#[path = "hinix/lockrun.rs"]
+mod lockrun;
+
+use clap::Parser;
+use hinix::Result;
+
+#[derive(Parser)]
+#[command(name = "lockrun", version)]
+struct Cli {
+    #[command(flatten)]
+    opts: lockrun::Opts,
+}
+
+// --------------------------------------------------------------------------
+
+fn main() -> Result<()> {
+    lockrun::run(Cli::parse().opts)
+}
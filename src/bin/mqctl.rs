@@ -0,0 +1,43 @@
+// hinix/src/bin/mqctl.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! This CLI application can modify an existing Posix message queue.
+//!
+//! This is the same as `hinix mq ctl`, under the utility's own name.
+
+#[cfg(target_os = "linux")]
+#[path = "hinix/mqctl.rs"]
+mod mqctl;
+
+use hinix::Result;
+
+#[cfg(target_os = "linux")]
+#[derive(clap::Parser)]
+#[command(name = "mqctl", version)]
+struct Cli {
+    #[command(flatten)]
+    opts: mqctl::Opts,
+}
+
+// --------------------------------------------------------------------------
+
+#[cfg(target_os = "linux")]
+fn main() -> Result<()> {
+    use clap::Parser;
+    mqctl::run(Cli::parse().opts)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() -> Result<()> {
+    println!("mqctl is only supported on Linux");
+    Ok(())
+}
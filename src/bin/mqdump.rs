@@ -0,0 +1,64 @@
+// hinix/src/bin/mqdump.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! This CLI application can drain all the messages from a Posix message
+//! queue, printing them or saving them to files.
+//!
+//! This is the same as `hinix mq dump`, under the utility's own name.
+
+#[cfg(any(
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd"
+))]
+#[path = "hinix/mqdump.rs"]
+mod mqdump;
+
+use hinix::Result;
+
+#[cfg(any(
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd"
+))]
+#[derive(clap::Parser)]
+#[command(name = "mqdump", version)]
+struct Cli {
+    #[command(flatten)]
+    opts: mqdump::Opts,
+}
+
+// --------------------------------------------------------------------------
+
+#[cfg(any(
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd"
+))]
+fn main() -> Result<()> {
+    use clap::Parser;
+    mqdump::run(Cli::parse().opts)
+}
+
+#[cfg(not(any(
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd"
+)))]
+fn main() -> Result<()> {
+    println!("POSIX message queues not supported on this OS");
+    Ok(())
+}
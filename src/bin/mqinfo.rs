@@ -0,0 +1,63 @@
+// hinix/src/bin/mqinfo.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! This CLI application can show information about a Posix message queue.
+//!
+//! This is the same as `hinix mq info`, under the utility's own name.
+
+#[cfg(any(
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd"
+))]
+#[path = "hinix/mqinfo.rs"]
+mod mqinfo;
+
+use hinix::Result;
+
+#[cfg(any(
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd"
+))]
+#[derive(clap::Parser)]
+#[command(name = "mqinfo", version)]
+struct Cli {
+    #[command(flatten)]
+    opts: mqinfo::Opts,
+}
+
+// --------------------------------------------------------------------------
+
+#[cfg(any(
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd"
+))]
+fn main() -> Result<()> {
+    use clap::Parser;
+    mqinfo::run(Cli::parse().opts)
+}
+
+#[cfg(not(any(
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd"
+)))]
+fn main() -> Result<()> {
+    println!("POSIX message queues not supported on this OS");
+    Ok(())
+}
@@ -0,0 +1,44 @@
+// hinix/src/bin/mqmon.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! This CLI application monitors messages arriving on several Posix
+//! message queues at once, like `iostat` for queues.
+//!
+//! This is the same as `hinix mq mon`, under the utility's own name.
+
+#[cfg(target_os = "linux")]
+#[path = "hinix/mqmon.rs"]
+mod mqmon;
+
+use hinix::Result;
+
+#[cfg(target_os = "linux")]
+#[derive(clap::Parser)]
+#[command(name = "mqmon", version)]
+struct Cli {
+    #[command(flatten)]
+    opts: mqmon::Opts,
+}
+
+// --------------------------------------------------------------------------
+
+#[cfg(target_os = "linux")]
+fn main() -> Result<()> {
+    use clap::Parser;
+    mqmon::run(Cli::parse().opts)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() -> Result<()> {
+    println!("Monitoring message queues is only supported on Linux");
+    Ok(())
+}
@@ -0,0 +1,64 @@
+// hinix/src/bin/mqrelay.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! This CLI application forwards messages between a Posix message queue
+//! and another transport.
+//!
+//! This is the same as `hinix mq relay`, under the utility's own name.
+
+#[cfg(any(
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd"
+))]
+#[path = "hinix/mqrelay.rs"]
+mod mqrelay;
+
+use hinix::Result;
+
+#[cfg(any(
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd"
+))]
+#[derive(clap::Parser)]
+#[command(name = "mqrelay", version)]
+struct Cli {
+    #[command(flatten)]
+    opts: mqrelay::Opts,
+}
+
+// --------------------------------------------------------------------------
+
+#[cfg(any(
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd"
+))]
+fn main() -> Result<()> {
+    use clap::Parser;
+    mqrelay::run(Cli::parse().opts)
+}
+
+#[cfg(not(any(
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd"
+)))]
+fn main() -> Result<()> {
+    println!("POSIX message queues not supported on this OS");
+    Ok(())
+}
@@ -0,0 +1,43 @@
+// hinix/src/bin/ns-run.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! This CLI application runs a command in new Linux namespaces.
+//!
+//! This is the same as `hinix proc ns`, under the utility's own name.
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[path = "hinix/ns_run.rs"]
+mod ns_run;
+
+use hinix::Result;
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(clap::Parser)]
+#[command(name = "ns-run", version)]
+struct Cli {
+    #[command(flatten)]
+    opts: ns_run::Opts,
+}
+
+// --------------------------------------------------------------------------
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn main() -> Result<()> {
+    use clap::Parser;
+    ns_run::run(Cli::parse().opts)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn main() -> Result<()> {
+    println!("Namespaces are only supported on Linux");
+    Ok(())
+}
@@ -0,0 +1,43 @@
+// hinix/src/bin/pidwait.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! This CLI application waits for arbitrary processes to exit.
+//!
+//! This is the same as `hinix proc wait`, under the utility's own name.
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[path = "hinix/pidwait.rs"]
+mod pidwait;
+
+use hinix::Result;
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(clap::Parser)]
+#[command(name = "pidwait", version)]
+struct Cli {
+    #[command(flatten)]
+    opts: pidwait::Opts,
+}
+
+// --------------------------------------------------------------------------
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn main() -> Result<()> {
+    use clap::Parser;
+    pidwait::run(Cli::parse().opts)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn main() -> Result<()> {
+    println!("pidfd is only supported on Linux");
+    Ok(())
+}
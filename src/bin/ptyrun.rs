@@ -0,0 +1,34 @@
+// hinix/src/bin/ptyrun.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! This CLI application runs a command under a pseudo-terminal.
+//!
+//! This is the same as `hinix pty run`, under the utility's own name.
+
+#[path = "hinix/ptyrun.rs"]
+mod ptyrun;
+
+use clap::Parser;
+use hinix::Result;
+
+#[derive(Parser)]
+#[command(name = "ptyrun", version)]
+struct Cli {
+    #[command(flatten)]
+    opts: ptyrun::Opts,
+}
+
+// --------------------------------------------------------------------------
+
+fn main() -> Result<()> {
+    ptyrun::run(Cli::parse().opts)
+}
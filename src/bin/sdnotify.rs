@@ -0,0 +1,44 @@
+// hinix/src/bin/sdnotify.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! This CLI application sends notifications to the systemd service
+//! manager, for shell scripts running in `Type=notify` services.
+//!
+//! This is the same as `hinix sd notify`, under the utility's own name.
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[path = "hinix/sdnotify.rs"]
+mod sdnotify;
+
+use hinix::Result;
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(clap::Parser)]
+#[command(name = "sdnotify", version)]
+struct Cli {
+    #[command(flatten)]
+    opts: sdnotify::Opts,
+}
+
+// --------------------------------------------------------------------------
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn main() -> Result<()> {
+    use clap::Parser;
+    sdnotify::run(Cli::parse().opts)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn main() -> Result<()> {
+    println!("systemd is only supported on Linux");
+    Ok(())
+}
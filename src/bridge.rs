@@ -0,0 +1,356 @@
+// hinix/src/bridge.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Forwarding IPC sources into channels.
+//!
+//! A [`Bridge`] is a thread that receives from a blocking IPC source, like
+//! a message queue, a FIFO, or a signalfd, and sends what it gets into a
+//! channel. An application can then fan in any number of sources into
+//! one `std::sync::mpsc` channel, and wait on just that.
+//!
+//! ```
+//! use hinix::{bridge::Bridge, fifo::{self, Fifo}};
+//! use nix::sys::stat::Mode;
+//! use std::{io::Write, sync::mpsc};
+//!
+//! let path = std::env::temp_dir().join(format!("bridge-doc-{}", std::process::id()));
+//! fifo::mkfifo(&path, Mode::from_bits_truncate(0o600))?;
+//! let rd = Fifo::open_read_nonblocking(&path)?;
+//! let mut wr = Fifo::open_write(&path)?;
+//!
+//! let (tx, rx) = mpsc::channel();
+//! let bridge = Bridge::spawn(rd, tx)?;
+//!
+//! wr.write_all(b"hello")?;
+//! assert_eq!(b"hello".to_vec(), rx.recv().unwrap());
+//!
+//! bridge.shutdown()?;
+//! # std::fs::remove_file(&path).unwrap();
+//! # Ok::<(), hinix::Error>(())
+//! ```
+//!
+//! The thread runs until the bridge is shut down, the source ends, like
+//! a FIFO with no writer, or the receiving end of the channel is
+//! dropped. A bridge is shut down by cancelling its [`CancelToken`],
+//! which can be shared by several bridges to stop them all at once.
+//! Dropping a bridge also shuts it down, and waits for its thread.
+//!
+
+use crate::{
+    cancel::CancelToken,
+    timeout::{self, Timeout},
+    Error, Result,
+};
+use nix::poll::PollFlags;
+use std::{
+    os::unix::io::BorrowedFd,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+};
+
+#[cfg(all(feature = "msgqueue", target_os = "linux"))]
+use crate::msgqueue::MsgQueue;
+
+#[cfg(feature = "fifo")]
+use crate::fifo::Fifo;
+
+#[cfg(any(feature = "fifo", feature = "signalfd"))]
+use std::os::unix::io::AsFd;
+
+#[cfg(all(
+    feature = "signalfd",
+    any(
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    )
+))]
+use crate::signalfd::{SigInfo, SignalFd};
+
+/// A blocking source of items that can be forwarded by a bridge.
+pub trait BridgeSource: Send + 'static {
+    /// The type of item received from the source
+    type Item: Send + 'static;
+
+    /// Waits for, and receives, the next item.
+    ///
+    /// Returns `None` when the source has ended, and fails with
+    /// `ECANCELED` once the token is cancelled.
+    fn recv_cancellable(&mut self, cancel: &CancelToken) -> Result<Option<Self::Item>>;
+}
+
+/// The sending end of a channel that a bridge forwards items into.
+///
+/// This is implemented for the `std::sync::mpsc` senders, and can be
+/// implemented for other channels, like crossbeam's.
+pub trait BridgeSender<T>: Send + 'static {
+    /// Sends an item into the channel.
+    ///
+    /// Returns `false` if the receiving end is gone, which stops the
+    /// bridge.
+    fn send_item(&self, item: T) -> bool;
+}
+
+impl<T: Send + 'static> BridgeSender<T> for mpsc::Sender<T> {
+    fn send_item(&self, item: T) -> bool {
+        self.send(item).is_ok()
+    }
+}
+
+/// A bounded channel blocks the bridge while it's full, and a shutdown
+/// isn't seen until there's room for the item.
+impl<T: Send + 'static> BridgeSender<T> for mpsc::SyncSender<T> {
+    fn send_item(&self, item: T) -> bool {
+        self.send(item).is_ok()
+    }
+}
+
+/// Waits for a handle to be readable, or for the token to be cancelled.
+#[allow(dead_code)]
+fn wait_readable(fd: BorrowedFd, cancel: &CancelToken) -> Result<()> {
+    timeout::poll_fd_cancel(fd, PollFlags::POLLIN, Timeout::None, Some(cancel))?;
+    Ok(())
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// A thread that forwards the items from a source into a channel.
+#[derive(Debug)]
+pub struct Bridge {
+    /// The token that stops the thread
+    cancel: CancelToken,
+    /// The thread. This is only `None` once it's been joined.
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl Bridge {
+    /// Starts a thread forwarding the items from the source into the
+    /// channel, with its own cancel token.
+    pub fn spawn<S, T>(source: S, tx: T) -> Result<Self>
+    where
+        S: BridgeSource,
+        T: BridgeSender<S::Item>,
+    {
+        Self::with_cancel(source, tx, CancelToken::new()?)
+    }
+
+    /// Starts a thread forwarding the items from the source into the
+    /// channel, which stops when the token is cancelled.
+    pub fn with_cancel<S, T>(mut source: S, tx: T, cancel: CancelToken) -> Result<Self>
+    where
+        S: BridgeSource,
+        T: BridgeSender<S::Item>,
+    {
+        let thr_cancel = cancel.clone();
+        let thread = thread::Builder::new()
+            .name("hinix-bridge".into())
+            .spawn(move || loop {
+                match source.recv_cancellable(&thr_cancel) {
+                    Ok(Some(item)) => {
+                        if !tx.send_item(item) {
+                            break Ok(());
+                        }
+                    }
+                    Ok(None) => break Ok(()),
+                    Err(err) if err == Error::ECANCELED => break Ok(()),
+                    Err(err) => break Err(err),
+                }
+            })
+            .map_err(Error::from)?;
+
+        Ok(Self {
+            cancel,
+            thread: Some(thread),
+        })
+    }
+
+    /// Gets the token that stops the bridge.
+    pub fn cancel_token(&self) -> &CancelToken {
+        &self.cancel
+    }
+
+    /// Determines if the thread has stopped, like when the source ended.
+    pub fn is_finished(&self) -> bool {
+        match self.thread {
+            Some(ref thr) => thr.is_finished(),
+            None => true,
+        }
+    }
+
+    /// Stops the bridge and waits for the thread to exit.
+    ///
+    /// Returns the error that stopped the thread, if it failed on its
+    /// own before it was cancelled.
+    pub fn shutdown(mut self) -> Result<()> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.cancel.cancel();
+        match self.thread.take() {
+            Some(thr) => thr.join().map_err(|_| Error::EIO)?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Bridge {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Sources
+
+/// Forwards each message as a vector.
+#[cfg(all(feature = "msgqueue", target_os = "linux"))]
+impl BridgeSource for MsgQueue {
+    type Item = Vec<u8>;
+
+    fn recv_cancellable(&mut self, cancel: &CancelToken) -> Result<Option<Vec<u8>>> {
+        let mut buf = vec![0u8; self.msg_size()];
+        let mut prio = 0;
+        let n = self.receive_cancellable(&mut buf, &mut prio, Timeout::None, cancel)?;
+        buf.truncate(n);
+        Ok(Some(buf))
+    }
+}
+
+/// Forwards the data as it's read, in chunks of up to `PIPE_BUF` bytes.
+/// The source ends when there's no writer.
+///
+/// The FIFO can be opened in non-blocking mode, so that it doesn't wait
+/// for a writer.
+#[cfg(feature = "fifo")]
+impl BridgeSource for Fifo {
+    type Item = Vec<u8>;
+
+    fn recv_cancellable(&mut self, cancel: &CancelToken) -> Result<Option<Vec<u8>>> {
+        use std::io::Read;
+
+        let mut buf = vec![0u8; libc::PIPE_BUF];
+        loop {
+            wait_readable(self.as_fd(), cancel)?;
+            match self.read(&mut buf).map_err(Error::from) {
+                Ok(0) => return Ok(None),
+                Ok(n) => {
+                    buf.truncate(n);
+                    return Ok(Some(buf));
+                }
+                Err(err) if err == Error::EAGAIN => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Forwards each signal.
+///
+/// The signals must also be blocked in the bridge thread, which is done
+/// by blocking them in the thread that starts it, since a new thread
+/// inherits the signal mask of its parent.
+#[cfg(all(
+    feature = "signalfd",
+    any(
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    )
+))]
+impl BridgeSource for SignalFd {
+    type Item = SigInfo;
+
+    fn recv_cancellable(&mut self, cancel: &CancelToken) -> Result<Option<SigInfo>> {
+        loop {
+            wait_readable(self.as_fd(), cancel)?;
+            match self.read_timeout(Timeout::ZERO) {
+                Ok(Some(info)) => return Ok(Some(info)),
+                Ok(None) => continue,
+                Err(err) if err == Error::EAGAIN => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A source that counts up to a limit.
+    struct Counter(u32, u32);
+
+    impl BridgeSource for Counter {
+        type Item = u32;
+
+        fn recv_cancellable(&mut self, cancel: &CancelToken) -> Result<Option<u32>> {
+            cancel.check()?;
+            self.0 += 1;
+            Ok(Some(self.0).filter(|&n| n <= self.1))
+        }
+    }
+
+    #[test]
+    fn test_source_ends() {
+        let (tx, rx) = mpsc::channel();
+        let bridge = Bridge::spawn(Counter(0, 3), tx).unwrap();
+        assert_eq!(vec![1, 2, 3], rx.iter().collect::<Vec<_>>());
+        assert!(bridge.shutdown().is_ok());
+    }
+
+    #[cfg(all(feature = "msgqueue", target_os = "linux"))]
+    #[test]
+    fn test_msgqueue() {
+        const NAME: &str = "/rust_bridge_unit_test";
+
+        // Opens a queue, returning the reading and writing handles
+        let open = |name: &str| {
+            let _ = MsgQueue::unlink(name);
+            let rd = MsgQueue::create(name, 4, 64).unwrap();
+            let wr = MsgQueue::open(name).unwrap();
+            MsgQueue::unlink(name).unwrap();
+            (rd, wr)
+        };
+        let (rd_a, wr_a) = open(NAME);
+        let (rd_b, wr_b) = open(&format!("{}_b", NAME));
+
+        // Two sources into one channel, stopped by one token
+        let cancel = CancelToken::new().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let a = Bridge::with_cancel(rd_a, tx.clone(), cancel.clone()).unwrap();
+        let b = Bridge::with_cancel(rd_b, tx, cancel.clone()).unwrap();
+
+        wr_a.send("hello").unwrap();
+        assert_eq!(b"hello".to_vec(), rx.recv().unwrap());
+        wr_b.send("world").unwrap();
+        assert_eq!(b"world".to_vec(), rx.recv().unwrap());
+
+        cancel.cancel();
+        assert!(a.shutdown().is_ok());
+        assert!(b.shutdown().is_ok());
+
+        // Both threads dropped their senders
+        assert!(rx.recv().is_err());
+    }
+}
@@ -0,0 +1,179 @@
+// hinix/src/cancel.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Cancellation of blocking waits.
+//!
+//! A [`CancelToken`] lets one thread stop the blocking waits of others,
+//! like worker threads that are parked on IPC handles when the
+//! application wants to shut down. The blocking calls that can be
+//! cancelled take a token, and fail with `ECANCELED` once it's
+//! cancelled, whether they were already waiting or start afterwards.
+//!
+//! ```
+//! use hinix::{eventfd::EventFd, CancelToken, Error, Timeout};
+//! use std::thread;
+//!
+//! let cancel = CancelToken::new()?;
+//! let evfd = EventFd::new(0)?;
+//!
+//! let worker = thread::spawn({
+//!     let cancel = cancel.clone();
+//!     move || evfd.read_cancellable(Timeout::None, &cancel)
+//! });
+//!
+//! cancel.cancel();
+//! assert_eq!(Error::ECANCELED, worker.join().unwrap().unwrap_err());
+//! # Ok::<(), hinix::Error>(())
+//! ```
+//!
+//! The token is a handle that becomes readable when it's cancelled, an
+//! eventfd on Linux and Android, or a pipe elsewhere, so it can also be
+//! added to an application's own poll set.
+//!
+
+use crate::{error::ResultExt, Error, Result};
+use std::{
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use nix::sys::eventfd::{self, EfdFlags};
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+use nix::fcntl::{self, FcntlArg, FdFlag, OFlag};
+
+/// The state shared by the clones of a token.
+#[derive(Debug)]
+struct Inner {
+    /// Whether the token was cancelled
+    cancelled: AtomicBool,
+    /// The handle that's readable once the token is cancelled
+    rd: OwnedFd,
+    /// The handle that's written to cancel the token. For an eventfd,
+    /// this is the same as the reader.
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    wr: OwnedFd,
+}
+
+/// A token to cancel blocking waits.
+///
+/// Clones of the token share the same state, so one can be given to
+/// each thread that needs to be stopped. Once cancelled, a token stays
+/// that way.
+#[derive(Debug, Clone)]
+pub struct CancelToken(Arc<Inner>);
+
+impl CancelToken {
+    /// Creates a new token that hasn't been cancelled.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub fn new() -> Result<Self> {
+        let flags = EfdFlags::EFD_CLOEXEC | EfdFlags::EFD_NONBLOCK;
+        let fd = eventfd::eventfd(0, flags).op("eventfd")?;
+        Ok(Self(Arc::new(Inner {
+            cancelled: AtomicBool::new(false),
+            rd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })))
+    }
+
+    /// Creates a new token that hasn't been cancelled.
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    pub fn new() -> Result<Self> {
+        let (rd, wr) = nix::unistd::pipe().op("pipe")?;
+        let (rd, wr) = unsafe { (OwnedFd::from_raw_fd(rd), OwnedFd::from_raw_fd(wr)) };
+        for fd in [&rd, &wr] {
+            let fd = fd.as_raw_fd();
+            fcntl::fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).op("fcntl")?;
+            fcntl::fcntl(fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).op("fcntl")?;
+        }
+        Ok(Self(Arc::new(Inner {
+            cancelled: AtomicBool::new(false),
+            rd,
+            wr,
+        })))
+    }
+
+    /// Cancels the token, waking every wait that uses it.
+    ///
+    /// This can be called from any thread, any number of times.
+    pub fn cancel(&self) {
+        if !self.0.cancelled.swap(true, Ordering::AcqRel) {
+            // The handle is never read, so it stays readable
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            let _ = nix::unistd::write(self.0.rd.as_raw_fd(), &1u64.to_ne_bytes());
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            let _ = nix::unistd::write(self.0.wr.as_raw_fd(), &[1]);
+        }
+    }
+
+    /// Determines if the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Fails with `ECANCELED` if the token was cancelled.
+    pub fn check(&self) -> Result<()> {
+        match self.is_cancelled() {
+            true => Err(Error::ECANCELED),
+            false => Ok(()),
+        }
+    }
+}
+
+impl AsFd for CancelToken {
+    /// Gets the handle that becomes readable when the token is
+    /// cancelled.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.rd.as_fd()
+    }
+}
+
+impl AsRawFd for CancelToken {
+    /// Gets the raw handle that becomes readable when the token is
+    /// cancelled.
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.rd.as_raw_fd()
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timeout;
+    use nix::poll::PollFlags;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_cancel() {
+        let cancel = CancelToken::new().unwrap();
+        assert!(!cancel.is_cancelled());
+        assert!(cancel.check().is_ok());
+
+        let clone = cancel.clone();
+        let th = thread::spawn(move || {
+            timeout::poll_fd(clone.as_fd(), PollFlags::POLLIN, Duration::from_secs(5))
+        });
+        thread::sleep(Duration::from_millis(10));
+
+        cancel.cancel();
+        cancel.cancel();
+        assert!(th.join().unwrap().unwrap());
+        assert!(cancel.is_cancelled());
+        assert_eq!(Error::ECANCELED, cancel.check().unwrap_err());
+    }
+}
@@ -0,0 +1,484 @@
+// hinix/src/caps.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Linux capabilities.
+//!
+//! Capabilities divide the privileges of the superuser into distinct
+//! units that can be granted or removed independently. Each thread has
+//! several sets of them:
+//!
+//! - _Permitted_ is the limit on what the thread can make effective.
+//! - _Effective_ is what the kernel actually checks for permission.
+//! - _Inheritable_ is what can be passed across an exec of a program
+//!   that has the same capabilities in its file inheritable set.
+//! - _Ambient_ is what gets passed across an exec of an unprivileged
+//!   program. These must be both permitted and inheritable.
+//! - _Bounding_ is the limit on what can be gained by an exec.
+//!
+//! Note that capabilities are a per-thread attribute, so changes made
+//! here only apply to the calling thread, and to threads and processes
+//! that it creates afterward. To minimize privileges for a whole process,
+//! do it early, before any other threads are started.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man7/capabilities.7.html>
+//!
+
+use crate::{error::ResultExt, Error, Result};
+use nix::errno::Errno;
+use std::{fmt, os::raw::c_int, str::FromStr};
+
+/// The version of the capget/capset API with 64-bit sets.
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// The header for the capget/capset calls.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct CapUserHeader {
+    version: u32,
+    pid: c_int,
+}
+
+/// The data for the capget/capset calls. For version 3, there are two
+/// of these for the low and high 32 bits of the sets.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+macro_rules! capabilities {
+    ($($(#[$doc:meta])* $name:ident = $val:expr,)+) => {
+        /// A Linux capability.
+        #[allow(non_camel_case_types)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[repr(u8)]
+        pub enum Capability {
+            $($(#[$doc])* $name = $val,)+
+        }
+
+        impl Capability {
+            /// All the capabilities known to this library, in order.
+            pub const ALL: &'static [Capability] = &[$(Capability::$name,)+];
+
+            /// Gets the kernel name of the capability, like "CAP_CHOWN".
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Capability::$name => stringify!($name),)+
+                }
+            }
+        }
+    };
+}
+
+capabilities! {
+    /// Make arbitrary changes to file UIDs and GIDs
+    CAP_CHOWN = 0,
+    /// Bypass file read, write, and execute permission checks
+    CAP_DAC_OVERRIDE = 1,
+    /// Bypass file read and directory read/execute permission checks
+    CAP_DAC_READ_SEARCH = 2,
+    /// Bypass permission checks on operations that require the file owner
+    CAP_FOWNER = 3,
+    /// Don't clear set-user-ID and set-group-ID bits when a file is modified
+    CAP_FSETID = 4,
+    /// Bypass permission checks for sending signals
+    CAP_KILL = 5,
+    /// Make arbitrary manipulations of process GIDs
+    CAP_SETGID = 6,
+    /// Make arbitrary manipulations of process UIDs
+    CAP_SETUID = 7,
+    /// Transfer and remove capabilities
+    CAP_SETPCAP = 8,
+    /// Set the immutable and append-only file flags
+    CAP_LINUX_IMMUTABLE = 9,
+    /// Bind a socket to a privileged port (below 1024)
+    CAP_NET_BIND_SERVICE = 10,
+    /// Unused
+    CAP_NET_BROADCAST = 11,
+    /// Perform network administration operations
+    CAP_NET_ADMIN = 12,
+    /// Use raw and packet sockets
+    CAP_NET_RAW = 13,
+    /// Lock memory
+    CAP_IPC_LOCK = 14,
+    /// Bypass permission checks for System V IPC operations
+    CAP_IPC_OWNER = 15,
+    /// Load and unload kernel modules
+    CAP_SYS_MODULE = 16,
+    /// Perform I/O port operations
+    CAP_SYS_RAWIO = 17,
+    /// Use chroot()
+    CAP_SYS_CHROOT = 18,
+    /// Trace arbitrary processes
+    CAP_SYS_PTRACE = 19,
+    /// Use acct()
+    CAP_SYS_PACCT = 20,
+    /// Perform a range of system administration operations
+    CAP_SYS_ADMIN = 21,
+    /// Use reboot() and kexec_load()
+    CAP_SYS_BOOT = 22,
+    /// Raise process nice values and set real-time scheduling
+    CAP_SYS_NICE = 23,
+    /// Override resource limits
+    CAP_SYS_RESOURCE = 24,
+    /// Set the system clock
+    CAP_SYS_TIME = 25,
+    /// Use vhangup() and privileged terminal operations
+    CAP_SYS_TTY_CONFIG = 26,
+    /// Create special files using mknod()
+    CAP_MKNOD = 27,
+    /// Establish leases on arbitrary files
+    CAP_LEASE = 28,
+    /// Write records to the kernel audit log
+    CAP_AUDIT_WRITE = 29,
+    /// Configure the kernel audit subsystem
+    CAP_AUDIT_CONTROL = 30,
+    /// Set file capabilities
+    CAP_SETFCAP = 31,
+    /// Override Mandatory Access Control
+    CAP_MAC_OVERRIDE = 32,
+    /// Configure Mandatory Access Control
+    CAP_MAC_ADMIN = 33,
+    /// Perform privileged syslog operations
+    CAP_SYSLOG = 34,
+    /// Trigger something that will wake up the system
+    CAP_WAKE_ALARM = 35,
+    /// Block system suspend
+    CAP_BLOCK_SUSPEND = 36,
+    /// Read the audit log via a multicast netlink socket
+    CAP_AUDIT_READ = 37,
+    /// Use performance monitoring
+    CAP_PERFMON = 38,
+    /// Use privileged BPF operations
+    CAP_BPF = 39,
+    /// Checkpoint and restore processes
+    CAP_CHECKPOINT_RESTORE = 40,
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Capability {
+    type Err = Error;
+
+    /// Parses a capability name, like "CAP_NET_ADMIN" or "net_admin".
+    /// The match is case-insensitive, and the "CAP_" prefix is optional.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        Self::ALL
+            .iter()
+            .find(|cap| {
+                let name = cap.name();
+                name.eq_ignore_ascii_case(s) || name[4..].eq_ignore_ascii_case(s)
+            })
+            .copied()
+            .ok_or(Error::EINVAL)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// A set of capabilities.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CapSet(u64);
+
+impl CapSet {
+    /// Creates an empty set.
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Creates a set of all the capabilities known to this library.
+    pub fn all() -> Self {
+        Capability::ALL.iter().copied().collect()
+    }
+
+    /// Creates a set from the kernel's bit mask.
+    pub fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    /// Gets the kernel's bit mask for the set.
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Adds a capability to the set.
+    pub fn insert(&mut self, cap: Capability) {
+        self.0 |= 1 << cap as u8;
+    }
+
+    /// Removes a capability from the set.
+    pub fn remove(&mut self, cap: Capability) {
+        self.0 &= !(1 << cap as u8);
+    }
+
+    /// Determines if the capability is in the set.
+    pub fn contains(&self, cap: Capability) -> bool {
+        self.0 & (1 << cap as u8) != 0
+    }
+
+    /// Determines if the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Removes all the capabilities from the set.
+    pub fn clear(&mut self) {
+        self.0 = 0;
+    }
+
+    /// Gets an iterator over the capabilities in the set.
+    pub fn iter(&self) -> impl Iterator<Item = Capability> + '_ {
+        Capability::ALL
+            .iter()
+            .copied()
+            .filter(move |&cap| self.contains(cap))
+    }
+}
+
+impl FromIterator<Capability> for CapSet {
+    fn from_iter<I: IntoIterator<Item = Capability>>(iter: I) -> Self {
+        let mut set = Self::empty();
+        for cap in iter {
+            set.insert(cap);
+        }
+        set
+    }
+}
+
+/// The effective, permitted, and inheritable capability sets of a thread.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The capabilities the kernel checks for permission.
+    pub effective: CapSet,
+    /// The capabilities that the thread may make effective.
+    pub permitted: CapSet,
+    /// The capabilities that may be inherited across an exec.
+    pub inheritable: CapSet,
+}
+
+impl Capabilities {
+    /// Gets the capabilities of the calling thread.
+    pub fn current() -> Result<Self> {
+        let mut hdr = CapUserHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let mut data = [CapUserData::default(); 2];
+        let ret = unsafe { libc::syscall(libc::SYS_capget, &mut hdr, data.as_mut_ptr()) };
+        Errno::result(ret)?;
+
+        let join = |lo: u32, hi: u32| CapSet((u64::from(hi) << 32) | u64::from(lo));
+        Ok(Self {
+            effective: join(data[0].effective, data[1].effective),
+            permitted: join(data[0].permitted, data[1].permitted),
+            inheritable: join(data[0].inheritable, data[1].inheritable),
+        })
+    }
+
+    /// Applies these capabilities to the calling thread.
+    ///
+    /// Without `CAP_SETPCAP`, the new permitted set must be a subset of
+    /// the current one, and the effective set must be a subset of the new
+    /// permitted set. Otherwise this fails with `EPERM`.
+    pub fn apply(&self) -> Result<()> {
+        let mut hdr = CapUserHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let split = |set: CapSet| (set.0 as u32, (set.0 >> 32) as u32);
+        let (eff_lo, eff_hi) = split(self.effective);
+        let (perm_lo, perm_hi) = split(self.permitted);
+        let (inh_lo, inh_hi) = split(self.inheritable);
+        let data = [
+            CapUserData {
+                effective: eff_lo,
+                permitted: perm_lo,
+                inheritable: inh_lo,
+            },
+            CapUserData {
+                effective: eff_hi,
+                permitted: perm_hi,
+                inheritable: inh_hi,
+            },
+        ];
+        let ret = unsafe { libc::syscall(libc::SYS_capset, &mut hdr, data.as_ptr()) };
+        Errno::result(ret).map(drop).op("capset")
+    }
+
+    /// Removes the capabilities from all of the sets.
+    pub fn drop_all(&mut self, caps: CapSet) {
+        self.effective.0 &= !caps.0;
+        self.permitted.0 &= !caps.0;
+        self.inheritable.0 &= !caps.0;
+    }
+
+    /// Reduces all the sets to just the specified capabilities.
+    pub fn retain(&mut self, caps: CapSet) {
+        self.effective.0 &= caps.0;
+        self.permitted.0 &= caps.0;
+        self.inheritable.0 &= caps.0;
+    }
+}
+
+/// Determines if the calling thread has the capability in its effective
+/// set.
+pub fn has(cap: Capability) -> Result<bool> {
+    Ok(Capabilities::current()?.effective.contains(cap))
+}
+
+/// Runs a prctl() operation on a capability.
+fn cap_prctl(op: c_int, arg2: c_int, cap: Option<Capability>) -> Result<c_int> {
+    let cap = cap.map(|cap| cap as libc::c_ulong).unwrap_or(0);
+    let ret = unsafe {
+        libc::prctl(
+            op,
+            arg2 as libc::c_ulong,
+            cap,
+            0 as libc::c_ulong,
+            0 as libc::c_ulong,
+        )
+    };
+    Errno::result(ret).op("prctl")
+}
+
+/// Adds a capability to the ambient set of the calling thread.
+///
+/// The capability must already be in both the permitted and inheritable
+/// sets, otherwise this fails with `EPERM`.
+pub fn ambient_raise(cap: Capability) -> Result<()> {
+    cap_prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_RAISE, Some(cap)).map(drop)
+}
+
+/// Removes a capability from the ambient set of the calling thread.
+pub fn ambient_lower(cap: Capability) -> Result<()> {
+    cap_prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_LOWER, Some(cap)).map(drop)
+}
+
+/// Determines if a capability is in the ambient set of the calling
+/// thread.
+pub fn ambient_is_set(cap: Capability) -> Result<bool> {
+    cap_prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_IS_SET, Some(cap)).map(|n| n != 0)
+}
+
+/// Removes all capabilities from the ambient set of the calling thread.
+pub fn ambient_clear() -> Result<()> {
+    cap_prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_CLEAR_ALL, None).map(drop)
+}
+
+/// Gets the ambient set of the calling thread.
+pub fn ambient() -> Result<CapSet> {
+    let mut set = CapSet::empty();
+    for &cap in Capability::ALL {
+        match ambient_is_set(cap) {
+            Ok(true) => set.insert(cap),
+            Ok(false) => (),
+            // Not supported by the running kernel
+            Err(err) if err == Error::EINVAL => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(set)
+}
+
+/// Determines if a capability is in the bounding set of the calling
+/// thread.
+pub fn bounding_is_set(cap: Capability) -> Result<bool> {
+    cap_prctl(libc::PR_CAPBSET_READ, cap as c_int, None).map(|n| n != 0)
+}
+
+/// Removes a capability from the bounding set of the calling thread.
+///
+/// This can't be undone, and requires `CAP_SETPCAP`.
+pub fn bounding_drop(cap: Capability) -> Result<()> {
+    cap_prctl(libc::PR_CAPBSET_DROP, cap as c_int, None).map(drop)
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_names() {
+        assert_eq!("CAP_NET_ADMIN", Capability::CAP_NET_ADMIN.to_string());
+        assert_eq!(Ok(Capability::CAP_NET_ADMIN), "CAP_NET_ADMIN".parse());
+        assert_eq!(Ok(Capability::CAP_SYS_NICE), "sys_nice".parse());
+        assert_eq!(Err(Error::EINVAL), "CAP_BOGUS".parse::<Capability>());
+
+        for (i, &cap) in Capability::ALL.iter().enumerate() {
+            assert_eq!(i, cap as usize);
+        }
+    }
+
+    #[test]
+    fn test_capset() {
+        let mut set: CapSet = [Capability::CAP_KILL, Capability::CAP_BPF]
+            .into_iter()
+            .collect();
+        assert!(set.contains(Capability::CAP_KILL));
+        assert!(!set.contains(Capability::CAP_CHOWN));
+        assert_eq!((1 << 5) | (1 << 39), set.bits());
+
+        set.remove(Capability::CAP_KILL);
+        assert_eq!(vec![Capability::CAP_BPF], set.iter().collect::<Vec<_>>());
+        set.clear();
+        assert!(set.is_empty());
+
+        assert_eq!(Capability::ALL.len(), CapSet::all().iter().count());
+    }
+
+    #[test]
+    fn test_current() {
+        let caps = Capabilities::current().unwrap();
+        assert_eq!(caps.effective.bits() & !caps.permitted.bits(), 0);
+
+        // Ambient capabilities must be permitted and inheritable
+        let amb = ambient().unwrap();
+        assert_eq!(amb.bits() & !caps.inheritable.bits(), 0);
+    }
+
+    #[test]
+    fn test_apply() {
+        // Capabilities are per-thread, so do this in a separate one.
+        thread::spawn(|| {
+            let mut caps = Capabilities::current().unwrap();
+            if !caps.effective.contains(Capability::CAP_SYS_TIME) {
+                return;
+            }
+
+            caps.effective.remove(Capability::CAP_SYS_TIME);
+            caps.apply().unwrap();
+            assert!(!has(Capability::CAP_SYS_TIME).unwrap());
+            assert!(Capabilities::current()
+                .unwrap()
+                .permitted
+                .contains(Capability::CAP_SYS_TIME));
+
+            // It's still permitted, so it can be restored
+            caps.effective.insert(Capability::CAP_SYS_TIME);
+            caps.apply().unwrap();
+            assert!(has(Capability::CAP_SYS_TIME).unwrap());
+        })
+        .join()
+        .unwrap();
+    }
+}
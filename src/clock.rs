@@ -0,0 +1,262 @@
+// hinix/src/clock.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Access to the system clocks.
+//!
+//! The system has several clocks that count time in different ways:
+//!
+//! - `CLOCK_REALTIME` is the wall-clock time since the Unix epoch. It
+//!   can jump forward or backward if the system time is changed.
+//! - `CLOCK_MONOTONIC` never goes backward, but it stops while the
+//!   system is suspended. This is the clock used by [`std::time::Instant`].
+//! - `CLOCK_BOOTTIME` (Linux) is like the monotonic clock, but it keeps
+//!   counting while the system is suspended.
+//! - `CLOCK_PROCESS_CPUTIME_ID` and `CLOCK_THREAD_CPUTIME_ID` count the
+//!   CPU time used by the process or the calling thread.
+//!
+//! The times are reported as a [`Duration`] since the clock's starting
+//! point, which is different for each clock.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man2/clock_gettime.2.html>
+//!
+
+use crate::{error::ResultExt, Error, Result};
+use std::{
+    ops::{Add, AddAssign, Sub, SubAssign},
+    time::Duration,
+};
+
+/// The identifier for one of the system clocks.
+pub use nix::time::ClockId;
+
+/// Reads the current time of the clock.
+pub fn now(clock: ClockId) -> Result<Duration> {
+    Ok(clock.now()?.into())
+}
+
+/// Sets the time of the clock.
+///
+/// This is only supported for `CLOCK_REALTIME`, and requires the
+/// `CAP_SYS_TIME` capability.
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "redox")))]
+pub fn set(clock: ClockId, ts: Duration) -> Result<()> {
+    clock.set_time(ts.into()).op("clock_settime")
+}
+
+/// Gets the time on the clock that's `timeout` from now, for calls that
+/// take an absolute deadline.
+///
+/// This fails with `EINVAL` if the deadline can't be represented.
+#[allow(dead_code)] // Only used by some of the features
+pub(crate) fn deadline(clock: ClockId, timeout: Duration) -> Result<Duration> {
+    now(clock)?.checked_add(timeout).ok_or(Error::EINVAL)
+}
+
+/// Converts a duration to a timespec for a system call.
+///
+/// This fails with `EINVAL` if the seconds don't fit in a `time_t`.
+#[allow(dead_code)] // Only used by some of the features
+pub(crate) fn to_timespec(d: Duration) -> Result<libc::timespec> {
+    Ok(libc::timespec {
+        tv_sec: libc::time_t::try_from(d.as_secs()).map_err(|_| Error::EINVAL)?,
+        tv_nsec: d.subsec_nanos() as _,
+    })
+}
+
+/// Gets the resolution (precision) of the clock.
+#[cfg(not(target_os = "redox"))]
+pub fn resolution(clock: ClockId) -> Result<Duration> {
+    Ok(clock.res()?.into())
+}
+
+/// Gets the time since the system booted, including any time that the
+/// system was suspended.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub fn boottime_elapsed() -> Duration {
+    // This can't fail for a clock that's known to exist.
+    now(ClockId::CLOCK_BOOTTIME).unwrap_or_default()
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// A measurement of the boot time clock, which includes any time that the
+/// system was suspended.
+///
+/// This is similar to [`std::time::Instant`], which uses the monotonic
+/// clock, and thus stops counting while the system sleeps. For a daemon
+/// that may run across a suspend, this makes timeouts measured with an
+/// `Instant` appear to last longer than they did in real time. A
+/// timeout measured with a `SuspendAwareInstant` expires on time, even
+/// if the system slept through it.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SuspendAwareInstant(Duration);
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl SuspendAwareInstant {
+    /// Gets the current time.
+    pub fn now() -> Self {
+        Self(boottime_elapsed())
+    }
+
+    /// Gets the amount of time elapsed since this instant.
+    pub fn elapsed(&self) -> Duration {
+        Self::now().saturating_duration_since(*self)
+    }
+
+    /// Gets the amount of time elapsed from another instant to this one.
+    ///
+    /// Like [`std::time::Instant`], this is zero if `earlier` is later
+    /// than this one.
+    pub fn duration_since(&self, earlier: Self) -> Duration {
+        self.saturating_duration_since(earlier)
+    }
+
+    /// Gets the amount of time elapsed from another instant to this one,
+    /// or `None` if that one is later than this one.
+    pub fn checked_duration_since(&self, earlier: Self) -> Option<Duration> {
+        self.0.checked_sub(earlier.0)
+    }
+
+    /// Gets the amount of time elapsed from another instant to this one,
+    /// or zero if that one is later than this one.
+    pub fn saturating_duration_since(&self, earlier: Self) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    /// Gets the instant a duration after this one, if it can be
+    /// represented.
+    pub fn checked_add(&self, dur: Duration) -> Option<Self> {
+        self.0.checked_add(dur).map(Self)
+    }
+
+    /// Gets the instant a duration before this one, if it can be
+    /// represented.
+    pub fn checked_sub(&self, dur: Duration) -> Option<Self> {
+        self.0.checked_sub(dur).map(Self)
+    }
+
+    /// Gets the time since the system booted, as of this instant.
+    pub fn since_boot(&self) -> Duration {
+        self.0
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl Add<Duration> for SuspendAwareInstant {
+    type Output = Self;
+
+    /// Panics if the result overflows.
+    fn add(self, dur: Duration) -> Self {
+        self.checked_add(dur)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl AddAssign<Duration> for SuspendAwareInstant {
+    fn add_assign(&mut self, dur: Duration) {
+        *self = *self + dur;
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl Sub<Duration> for SuspendAwareInstant {
+    type Output = Self;
+
+    /// Panics if the result would be before boot.
+    fn sub(self, dur: Duration) -> Self {
+        self.checked_sub(dur)
+            .expect("overflow when subtracting duration from instant")
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl SubAssign<Duration> for SuspendAwareInstant {
+    fn sub_assign(&mut self, dur: Duration) {
+        *self = *self - dur;
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl Sub for SuspendAwareInstant {
+    type Output = Duration;
+
+    /// Panics if `other` is later than this one.
+    fn sub(self, other: Self) -> Duration {
+        self.duration_since(other)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_now() {
+        let t1 = now(ClockId::CLOCK_MONOTONIC).unwrap();
+        let t2 = now(ClockId::CLOCK_MONOTONIC).unwrap();
+        assert!(t2 >= t1);
+
+        let rt = now(ClockId::CLOCK_REALTIME).unwrap();
+        let st = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        assert!(st.max(rt) - st.min(rt) < Duration::from_secs(1));
+
+        assert!(now(ClockId::CLOCK_PROCESS_CPUTIME_ID).unwrap() > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_resolution() {
+        let res = resolution(ClockId::CLOCK_MONOTONIC).unwrap();
+        assert!(res > Duration::ZERO);
+        assert!(res <= Duration::from_millis(10));
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_set_requires_privilege() {
+        // Setting a monotonic clock is never allowed.
+        assert!(set(ClockId::CLOCK_MONOTONIC, Duration::from_secs(1)).is_err());
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_boottime() {
+        // Read the monotonic clock first, since the two are the same
+        // if the system has never been suspended.
+        let mono = now(ClockId::CLOCK_MONOTONIC).unwrap();
+        let boot = boottime_elapsed();
+        assert!(boot >= mono);
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_suspend_aware_instant() {
+        let start = SuspendAwareInstant::now();
+        std::thread::sleep(Duration::from_millis(10));
+        let end = SuspendAwareInstant::now();
+
+        assert!(end > start);
+        assert!(end - start >= Duration::from_millis(10));
+        assert!(start.elapsed() >= end - start);
+        assert_eq!(None, start.checked_duration_since(end));
+        assert_eq!(Duration::ZERO, start.saturating_duration_since(end));
+
+        let later = start + Duration::from_secs(1);
+        assert_eq!(Duration::from_secs(1), later - start);
+        assert_eq!(start, later - Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,510 @@
+// hinix/src/codec.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Encoding typed messages for the packet transports.
+//!
+//! A [`Codec`] converts a value to and from the bytes of a single
+//! message. The packet transports, like message queues, seqpacket
+//! sockets, packet pipes, and FIFOs, implement [`PacketSender`] and/or
+//! [`PacketReceiver`], which can send and receive values through any
+//! codec, so the message format isn't tied to the transport.
+//!
+//! The transports have a maximum message size, and the codec is told
+//! about it, so that an oversized value fails with `EMSGSIZE` when it's
+//! encoded, rather than being truncated.
+//!
+//! A FIFO doesn't keep the boundaries between messages, so each one is
+//! sent with its length in front of it. That works with any number of
+//! writers, but only one reader. A length larger than any sender could
+//! write fails with `EPROTO`, as the stream can't be trusted after that.
+//!
+//! The serde-based codecs each require a feature:
+//!
+//! * [`BincodeCodec`] - `bincode`
+//! * [`CborCodec`] - `cbor`
+//! * [`JsonCodec`] - `json`
+//!
+
+use crate::{Error, Result};
+
+#[cfg(all(feature = "pipe", any(target_os = "android", target_os = "linux")))]
+use crate::pipe::{ReadPipe, WritePipe, PIPE_BUF};
+
+#[cfg(any(
+    feature = "fifo",
+    all(feature = "pipe", any(target_os = "android", target_os = "linux"))
+))]
+use {crate::error::ResultExt, nix::unistd, std::os::unix::io::AsRawFd};
+
+#[cfg(feature = "fifo")]
+use {crate::fifo::Fifo, nix::errno::Errno};
+
+#[cfg(all(feature = "seqpacket", any(target_os = "android", target_os = "linux")))]
+use crate::seqpacket::SeqPacket;
+
+#[cfg(all(
+    feature = "msgqueue",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "netbsd"
+    )
+))]
+use crate::msgqueue::MsgQueue;
+
+#[cfg(any(feature = "bincode", feature = "cbor", feature = "json"))]
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Converts values of a type to and from messages.
+pub trait Codec<T> {
+    /// Encodes the value into a message.
+    ///
+    /// This fails with `EMSGSIZE` if the message would be larger than
+    /// `max_size` bytes.
+    fn encode(&self, item: &T, max_size: usize) -> Result<Vec<u8>>;
+
+    /// Decodes a value from a message.
+    ///
+    /// This fails with `EINVAL` if the message isn't valid for the type.
+    fn decode(&self, buf: &[u8]) -> Result<T>;
+}
+
+/// Checks an encoded message against the maximum size.
+fn check_size(buf: Vec<u8>, max_size: usize) -> Result<Vec<u8>> {
+    if buf.len() > max_size {
+        return Err(Error::EMSGSIZE);
+    }
+    Ok(buf)
+}
+
+/// A codec that passes the bytes through unchanged.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RawCodec;
+
+impl Codec<Vec<u8>> for RawCodec {
+    fn encode(&self, item: &Vec<u8>, max_size: usize) -> Result<Vec<u8>> {
+        check_size(item.clone(), max_size)
+    }
+
+    fn decode(&self, buf: &[u8]) -> Result<Vec<u8>> {
+        Ok(buf.to_vec())
+    }
+}
+
+/// A codec for the compact bincode binary format.
+///
+/// This requires the `bincode` feature.
+#[cfg(feature = "bincode")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl<T: Serialize + DeserializeOwned> Codec<T> for BincodeCodec {
+    fn encode(&self, item: &T, max_size: usize) -> Result<Vec<u8>> {
+        // The size can be found without encoding the value
+        let n = bincode::serialized_size(item).map_err(|_| Error::EINVAL)?;
+        if n > max_size as u64 {
+            return Err(Error::EMSGSIZE);
+        }
+        bincode::serialize(item).map_err(|_| Error::EINVAL)
+    }
+
+    fn decode(&self, buf: &[u8]) -> Result<T> {
+        bincode::deserialize(buf).map_err(|_| Error::EINVAL)
+    }
+}
+
+/// A codec for the CBOR binary format (RFC 8949).
+///
+/// This requires the `cbor` feature.
+#[cfg(feature = "cbor")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CborCodec;
+
+#[cfg(feature = "cbor")]
+impl<T: Serialize + DeserializeOwned> Codec<T> for CborCodec {
+    fn encode(&self, item: &T, max_size: usize) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        ciborium::ser::into_writer(item, &mut buf).map_err(|_| Error::EINVAL)?;
+        check_size(buf, max_size)
+    }
+
+    fn decode(&self, buf: &[u8]) -> Result<T> {
+        ciborium::de::from_reader(buf).map_err(|_| Error::EINVAL)
+    }
+}
+
+/// A codec for JSON text.
+///
+/// This requires the `json` feature.
+#[cfg(feature = "json")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl<T: Serialize + DeserializeOwned> Codec<T> for JsonCodec {
+    fn encode(&self, item: &T, max_size: usize) -> Result<Vec<u8>> {
+        let buf = serde_json::to_vec(item).map_err(|_| Error::EINVAL)?;
+        check_size(buf, max_size)
+    }
+
+    fn decode(&self, buf: &[u8]) -> Result<T> {
+        serde_json::from_slice(buf).map_err(|_| Error::EINVAL)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// A transport that sends whole messages (packets).
+pub trait PacketSender {
+    /// Gets the largest message that can be sent.
+    fn max_send_size(&self) -> usize;
+
+    /// Sends a message.
+    fn send_packet(&self, buf: &[u8]) -> Result<()>;
+
+    /// Encodes the value with the codec, and sends it as a message.
+    fn send_encoded<T, C: Codec<T>>(&self, codec: &C, item: &T) -> Result<()> {
+        let buf = codec.encode(item, self.max_send_size())?;
+        self.send_packet(&buf)
+    }
+}
+
+/// A transport that receives whole messages (packets).
+pub trait PacketReceiver {
+    /// Gets the largest message that can be received.
+    fn max_recv_size(&self) -> usize;
+
+    /// Receives a message into the buffer, returning its size.
+    fn recv_packet(&self, buf: &mut [u8]) -> Result<usize>;
+
+    /// Receives a message, and decodes it with the codec.
+    fn recv_decoded<T, C: Codec<T>>(&self, codec: &C) -> Result<T> {
+        let mut buf = vec![0u8; self.max_recv_size()];
+        let n = self.recv_packet(&mut buf)?;
+        codec.decode(&buf[..n])
+    }
+}
+
+#[cfg(all(
+    feature = "msgqueue",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "netbsd"
+    )
+))]
+impl PacketSender for MsgQueue {
+    fn max_send_size(&self) -> usize {
+        self.msg_size()
+    }
+
+    fn send_packet(&self, buf: &[u8]) -> Result<()> {
+        self.send(buf)
+    }
+}
+
+#[cfg(all(
+    feature = "msgqueue",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "netbsd"
+    )
+))]
+impl PacketReceiver for MsgQueue {
+    fn max_recv_size(&self) -> usize {
+        self.msg_size()
+    }
+
+    fn recv_packet(&self, buf: &mut [u8]) -> Result<usize> {
+        self.receive(buf)
+    }
+}
+
+#[cfg(all(feature = "seqpacket", any(target_os = "android", target_os = "linux")))]
+impl PacketSender for SeqPacket {
+    fn max_send_size(&self) -> usize {
+        self.send_buffer_size().unwrap_or(libc::PIPE_BUF)
+    }
+
+    fn send_packet(&self, buf: &[u8]) -> Result<()> {
+        self.send(buf).map(drop)
+    }
+}
+
+#[cfg(all(feature = "seqpacket", any(target_os = "android", target_os = "linux")))]
+impl PacketReceiver for SeqPacket {
+    fn max_recv_size(&self) -> usize {
+        self.send_buffer_size().unwrap_or(libc::PIPE_BUF)
+    }
+
+    fn recv_packet(&self, buf: &mut [u8]) -> Result<usize> {
+        // A zero-length read is the peer hanging up
+        match self.recv(buf)? {
+            0 => Err(Error::ECONNRESET),
+            n => Ok(n),
+        }
+    }
+
+    fn recv_decoded<T, C: Codec<T>>(&self, codec: &C) -> Result<T> {
+        // Size the buffer for the actual message
+        let mut buf = vec![0u8; self.peek_size()?];
+        let n = self.recv_packet(&mut buf)?;
+        codec.decode(&buf[..n])
+    }
+}
+
+// Packet pipes only keep the message boundaries for writes up to
+// PIPE_BUF, so that's the limit in both directions. An ordinary pipe
+// doesn't keep them at all, so the calls fail with EINVAL for one.
+
+#[cfg(all(feature = "pipe", any(target_os = "android", target_os = "linux")))]
+impl PacketSender for WritePipe {
+    fn max_send_size(&self) -> usize {
+        PIPE_BUF
+    }
+
+    fn send_packet(&self, buf: &[u8]) -> Result<()> {
+        if !self.is_packet() {
+            return Err(Error::EINVAL);
+        }
+        if buf.len() > PIPE_BUF {
+            return Err(Error::EMSGSIZE);
+        }
+        let res = fault_point!(PipeWrite, unistd::write(self.as_raw_fd(), buf));
+        res.map(drop).op("write")
+    }
+}
+
+#[cfg(all(feature = "pipe", any(target_os = "android", target_os = "linux")))]
+impl PacketReceiver for ReadPipe {
+    fn max_recv_size(&self) -> usize {
+        PIPE_BUF
+    }
+
+    fn recv_packet(&self, buf: &mut [u8]) -> Result<usize> {
+        if !self.is_packet() {
+            return Err(Error::EINVAL);
+        }
+        // A zero-length read is the writer closing the pipe
+        match fault_point!(PipeRead, unistd::read(self.as_raw_fd(), buf))? {
+            0 => Err(Error::EPIPE),
+            n => Ok(n),
+        }
+    }
+}
+
+// FIFOs don't keep the message boundaries, so each message is written
+// with its length in front of it. A write of up to PIPE_BUF bytes isn't
+// split up or mixed with those of other writers, so a FIFO can have any
+// number of senders, but only one receiver.
+
+/// The size of the length in front of a message in a FIFO
+#[cfg(feature = "fifo")]
+const FIFO_HDR_LEN: usize = size_of::<u32>();
+
+/// Reads from a FIFO until the buffer is full.
+///
+/// This fails with `EPIPE` if the writers close the FIFO first.
+#[cfg(feature = "fifo")]
+fn fifo_read_exact(fifo: &Fifo, mut buf: &mut [u8]) -> Result<()> {
+    while !buf.is_empty() {
+        match fault_point!(FifoRead, unistd::read(fifo.as_raw_fd(), buf)) {
+            Ok(0) => return Err(Error::EPIPE),
+            Ok(n) => buf = &mut buf[n..],
+            Err(Errno::EINTR) => continue,
+            Err(err) => return Err(err).op("read"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "fifo")]
+impl PacketSender for Fifo {
+    fn max_send_size(&self) -> usize {
+        libc::PIPE_BUF - FIFO_HDR_LEN
+    }
+
+    fn send_packet(&self, buf: &[u8]) -> Result<()> {
+        if buf.len() > self.max_send_size() {
+            return Err(Error::EMSGSIZE);
+        }
+        // The length and message must go in a single write
+        let mut msg = Vec::with_capacity(FIFO_HDR_LEN + buf.len());
+        msg.extend_from_slice(&(buf.len() as u32).to_ne_bytes());
+        msg.extend_from_slice(buf);
+        let res = fault_point!(FifoWrite, unistd::write(self.as_raw_fd(), &msg));
+        res.map(drop).op("write")
+    }
+}
+
+#[cfg(feature = "fifo")]
+impl PacketReceiver for Fifo {
+    fn max_recv_size(&self) -> usize {
+        libc::PIPE_BUF - FIFO_HDR_LEN
+    }
+
+    fn recv_packet(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut hdr = [0u8; FIFO_HDR_LEN];
+        fifo_read_exact(self, &mut hdr)?;
+        let n = u32::from_ne_bytes(hdr) as usize;
+
+        // No sender writes more than this, so the stream is out of step,
+        // or the peer isn't using the framing. Don't trust the length.
+        if n > self.max_recv_size() {
+            return Err(Error::EPROTO);
+        }
+        if n > buf.len() {
+            // Skip over the message, to keep in step with the stream
+            let mut skip = [0u8; libc::PIPE_BUF];
+            fifo_read_exact(self, &mut skip[..n])?;
+            return Err(Error::EMSGSIZE);
+        }
+        fifo_read_exact(self, &mut buf[..n])?;
+        Ok(n)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw() {
+        let buf = RawCodec.encode(&b"hello".to_vec(), 8).unwrap();
+        assert_eq!(b"hello", buf.as_slice());
+        assert_eq!(Error::EMSGSIZE, RawCodec.encode(&buf, 4).unwrap_err());
+        assert_eq!(b"hello".to_vec(), RawCodec.decode(&buf).unwrap());
+    }
+
+    #[cfg(all(feature = "pipe", any(target_os = "android", target_os = "linux")))]
+    #[test]
+    fn test_packet_pipe() {
+        let (wr, rd) = crate::pipe::packet_pipe().unwrap();
+        wr.send_encoded(&RawCodec, &b"abc".to_vec()).unwrap();
+        wr.send_encoded(&RawCodec, &b"de".to_vec()).unwrap();
+        assert_eq!(b"abc".to_vec(), rd.recv_decoded(&RawCodec).unwrap());
+        assert_eq!(b"de".to_vec(), rd.recv_decoded(&RawCodec).unwrap());
+
+        drop(wr);
+        assert_eq!(Error::EPIPE, rd.recv_decoded(&RawCodec).unwrap_err());
+
+        // An ordinary pipe doesn't keep the message boundaries
+        let (wr, rd) = crate::pipe::pipe().unwrap();
+        assert_eq!(Error::EINVAL, wr.send_packet(b"abc").unwrap_err());
+        assert_eq!(Error::EINVAL, rd.recv_decoded(&RawCodec).unwrap_err());
+    }
+
+    #[cfg(feature = "fifo")]
+    #[test]
+    fn test_fifo() {
+        use crate::fifo;
+        use nix::sys::stat::Mode;
+        use std::{env, fs};
+
+        let path = env::temp_dir().join(format!("hinix-codec-fifo-{}", unistd::getpid()));
+        let _ = fs::remove_file(&path);
+        fifo::mkfifo(&path, Mode::from_bits_truncate(0o600)).unwrap();
+
+        let rd = Fifo::open_read_nonblocking(&path).unwrap();
+        let wr = Fifo::open_write(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        wr.send_packet(b"abc").unwrap();
+        wr.send_packet(b"").unwrap();
+        wr.send_packet(b"defgh").unwrap();
+        wr.send_encoded(&RawCodec, &b"ij".to_vec()).unwrap();
+
+        let mut buf = [0u8; 4];
+        assert_eq!(3, rd.recv_packet(&mut buf).unwrap());
+        assert_eq!(b"abc", &buf[..3]);
+        assert_eq!(0, rd.recv_packet(&mut buf).unwrap());
+
+        // Too big for the buffer, but the next one is still intact
+        assert_eq!(Error::EMSGSIZE, rd.recv_packet(&mut buf).unwrap_err());
+        assert_eq!(b"ij".to_vec(), rd.recv_decoded(&RawCodec).unwrap());
+
+        assert_eq!(Error::EAGAIN, rd.recv_packet(&mut buf).unwrap_err());
+
+        // A bogus length is rejected, without reading the message
+        unistd::write(wr.as_raw_fd(), &u32::MAX.to_ne_bytes()).unwrap();
+        assert_eq!(Error::EPROTO, rd.recv_packet(&mut buf).unwrap_err());
+
+        drop(wr);
+        assert_eq!(Error::EPIPE, rd.recv_packet(&mut buf).unwrap_err());
+    }
+
+    #[cfg(any(feature = "bincode", feature = "cbor", feature = "json"))]
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Point {
+        x: i32,
+        y: i32,
+        label: String,
+    }
+
+    #[cfg(any(feature = "bincode", feature = "cbor", feature = "json"))]
+    fn check_codec<C: Codec<Point>>(codec: C) {
+        let pt = Point {
+            x: 1,
+            y: -2,
+            label: "here".into(),
+        };
+        let buf = codec.encode(&pt, 1024).unwrap();
+        assert_eq!(pt, codec.decode(&buf).unwrap());
+        assert_eq!(Error::EMSGSIZE, codec.encode(&pt, 4).unwrap_err());
+        assert_eq!(Error::EINVAL, codec.decode(&[0xff]).unwrap_err());
+
+        // Over a seqpacket socket
+        #[cfg(feature = "seqpacket")]
+        {
+            let (a, b) = SeqPacket::pair().unwrap();
+            a.send_encoded(&codec, &pt).unwrap();
+            assert_eq!(pt, b.recv_decoded(&codec).unwrap());
+        }
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode() {
+        check_codec(BincodeCodec);
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn test_cbor() {
+        check_codec(CborCodec);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json() {
+        check_codec(JsonCodec);
+
+        // Through a message queue
+        #[cfg(feature = "msgqueue")]
+        {
+            const NAME: &str = "/hinix-codec-test";
+            let mq = MsgQueue::create(NAME, 2, 64).unwrap();
+            MsgQueue::unlink(NAME).unwrap();
+            mq.send_encoded(&JsonCodec, &vec![1, 2, 3]).unwrap();
+            let v: Vec<i32> = mq.recv_decoded(&JsonCodec).unwrap();
+            assert_eq!(vec![1, 2, 3], v);
+        }
+    }
+}
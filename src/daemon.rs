@@ -0,0 +1,311 @@
+// hinix/src/daemon.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Running the process as a daemon.
+//!
+//! A traditional daemon detaches itself from the terminal and the
+//! session that started it:
+//!
+//! 1. Fork, so that the child is not a process group leader.
+//! 2. Start a new session, which leaves the controlling terminal behind.
+//! 3. Fork again, so that the daemon is not a session leader, and can't
+//!    ever reacquire a controlling terminal.
+//! 4. Change to a directory that won't prevent a filesystem from being
+//!    unmounted, and reset the file mode mask.
+//! 5. Redirect the standard I/O handles away from the terminal.
+//!
+//! The [`Daemon`] builder does all of that, and can also create a locked
+//! [`PidFile`] and drop privileges to another user. Any failure along the
+//! way is reported back to the original process, which otherwise has no
+//! way of knowing whether the daemon actually started.
+//!
+//! ```no_run
+//! use hinix::daemon::{Daemon, Outcome};
+//!
+//! match Daemon::new().pidfile("/run/mydaemon.pid").start().unwrap() {
+//!     Outcome::Parent(pid) => println!("Started daemon with PID {}", pid),
+//!     Outcome::Daemon(_pidfile) => {
+//!         // Do the daemon's work here
+//!     }
+//! }
+//! ```
+//!
+//! Note that this should be done early, before any threads are started,
+//! since only the calling thread survives a fork.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man7/daemon.7.html>
+//!
+
+use crate::{fd::FdExt, pidfile::PidFile, pipe, Error, Result};
+use nix::{
+    fcntl::{self, OFlag},
+    sys::{
+        stat::{self, Mode},
+        wait,
+    },
+    unistd::{self, ForkResult, Pid},
+};
+use std::{
+    io::{Read, Write},
+    os::unix::io::{FromRawFd, OwnedFd, RawFd},
+    path::{Path, PathBuf},
+};
+
+/// The result of starting a daemon, which differs in each process.
+#[derive(Debug)]
+pub enum Outcome {
+    /// Returned to the original process, with the PID of the daemon,
+    /// once the daemon was successfully set up.
+    Parent(Pid),
+    /// Returned in the daemon process, with the pidfile, if one was
+    /// requested. The pidfile is removed when it is dropped.
+    Daemon(Option<PidFile>),
+}
+
+/// A builder to start the current process as a daemon.
+#[derive(Debug, Clone)]
+pub struct Daemon {
+    /// The directory to change into
+    working_dir: PathBuf,
+    /// The file mode mask
+    umask: Mode,
+    /// The file for standard output, if any
+    stdout: Option<PathBuf>,
+    /// The file for standard error, if any
+    stderr: Option<PathBuf>,
+    /// The path to the pidfile, if any
+    pidfile: Option<PathBuf>,
+    /// The user and, optionally, group to run as
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    user: Option<(String, Option<String>)>,
+}
+
+impl Daemon {
+    /// Creates a builder with the default settings.
+    ///
+    /// By default, the daemon changes to the root directory, clears the
+    /// file mode mask, and sends all the standard I/O to /dev/null.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the directory that the daemon changes into.
+    pub fn working_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.working_dir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// Sets the file mode mask for the daemon.
+    pub fn umask(mut self, mask: Mode) -> Self {
+        self.umask = mask;
+        self
+    }
+
+    /// Sends the standard output of the daemon to a file.
+    ///
+    /// The file is created if necessary, and appended to.
+    pub fn stdout<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.stdout = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sends the standard error of the daemon to a file.
+    ///
+    /// The file is created if necessary, and appended to.
+    pub fn stderr<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.stderr = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Creates a locked pidfile for the daemon.
+    ///
+    /// This fails to start the daemon with `EWOULDBLOCK` if another
+    /// process holds the pidfile.
+    pub fn pidfile<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.pidfile = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Drops the privileges of the daemon to those of the specified user
+    /// and group, after the pidfile and output files are created.
+    ///
+    /// If `group` is `None`, the user's primary group is used.
+    /// See [`drop_privileges()`](crate::security::drop_privileges).
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub fn user(mut self, user: &str, group: Option<&str>) -> Self {
+        self.user = Some((user.to_string(), group.map(|s| s.to_string())));
+        self
+    }
+
+    /// Starts the daemon.
+    ///
+    /// This returns in both the original process and the daemon, with an
+    /// [`Outcome`] to tell them apart. The original process blocks until
+    /// the daemon is set up, and gets an error if any of the setup failed,
+    /// in which case the daemon has already exited.
+    pub fn start(&self) -> Result<Outcome> {
+        let (wr, mut rd) = pipe::pipe()?;
+        wr.set_cloexec(true)?;
+        rd.set_cloexec(true)?;
+
+        match unsafe { unistd::fork() }? {
+            ForkResult::Parent { child } => {
+                drop(wr);
+                // The intermediate child exits right after forking again
+                wait::waitpid(child, None)?;
+
+                let mut status = [0u8; 4];
+                let mut pid = [0u8; 4];
+                rd.read_exact(&mut status)
+                    .and_then(|_| rd.read_exact(&mut pid))
+                    .map_err(|_| Error::EIO)?;
+
+                match i32::from_ne_bytes(status) {
+                    0 => Ok(Outcome::Parent(Pid::from_raw(i32::from_ne_bytes(pid)))),
+                    errno => Err(Error::from_i32(errno)),
+                }
+            }
+            ForkResult::Child => {
+                drop(rd);
+                match unistd::setsid().and_then(|_| unsafe { unistd::fork() }) {
+                    // Skip the exit handlers, which belong to the parent
+                    Ok(ForkResult::Parent { .. }) => unsafe { libc::_exit(0) },
+                    Ok(ForkResult::Child) => (),
+                    Err(err) => Self::fail(wr, err.into()),
+                }
+
+                match self.setup() {
+                    Ok(pidfile) => {
+                        Self::report(wr, 0, unistd::getpid());
+                        Ok(Outcome::Daemon(pidfile))
+                    }
+                    Err(err) => Self::fail(wr, err),
+                }
+            }
+        }
+    }
+
+    /// Sets up the daemon process, after it's been detached.
+    fn setup(&self) -> Result<Option<PidFile>> {
+        unistd::chdir(&self.working_dir)?;
+        stat::umask(self.umask);
+
+        let pidfile = match self.pidfile {
+            Some(ref path) => Some(PidFile::create(path)?),
+            None => None,
+        };
+
+        let null = Self::open_output(None)?;
+        null.dup_to(libc::STDIN_FILENO)?;
+
+        let out = Self::open_output(self.stdout.as_deref())?;
+        out.dup_to(libc::STDOUT_FILENO)?;
+
+        let err = Self::open_output(self.stderr.as_deref())?;
+        err.dup_to(libc::STDERR_FILENO)?;
+
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        if let Some((ref user, ref group)) = self.user {
+            crate::security::drop_privileges(user, group.as_deref())?;
+        }
+
+        Ok(pidfile)
+    }
+
+    /// Opens a file for output, or /dev/null if there is none.
+    fn open_output(path: Option<&Path>) -> Result<OwnedFd> {
+        let fd: RawFd = match path {
+            Some(path) => fcntl::open(
+                path,
+                OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_APPEND | OFlag::O_CLOEXEC,
+                Mode::from_bits_truncate(0o644),
+            )?,
+            None => fcntl::open("/dev/null", OFlag::O_RDWR | OFlag::O_CLOEXEC, Mode::empty())?,
+        };
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Reports the result of the setup to the original process, closing
+    /// the pipe.
+    fn report(mut wr: pipe::WritePipe, status: i32, pid: Pid) {
+        let mut buf = [0u8; 8];
+        buf[..4].copy_from_slice(&status.to_ne_bytes());
+        buf[4..].copy_from_slice(&pid.as_raw().to_ne_bytes());
+        let _ = wr.write_all(&buf);
+    }
+
+    /// Reports a failure to the original process, and exits.
+    fn fail(wr: pipe::WritePipe, err: Error) -> ! {
+        Self::report(wr, err.raw_os_error(), Pid::from_raw(0));
+        unsafe { libc::_exit(1) }
+    }
+}
+
+impl Default for Daemon {
+    fn default() -> Self {
+        Self {
+            working_dir: PathBuf::from("/"),
+            umask: Mode::empty(),
+            stdout: None,
+            stderr: None,
+            pidfile: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            user: None,
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::in_child;
+    use std::{env, fs, os::unix::ffi::OsStrExt, thread, time::Duration};
+
+    #[test]
+    fn test_start() {
+        let dir = env::temp_dir();
+        let path = dir.join(format!("hinix-daemon-{}.pid", unistd::getpid()));
+        let out = dir.join(format!("hinix-daemon-{}.out", unistd::getpid()));
+
+        in_child(|| {
+            let daemon = Daemon::new().working_dir(&dir).pidfile(&path).stdout(&out);
+
+            match daemon.start().unwrap() {
+                Outcome::Daemon(pidfile) => {
+                    // The test harness captures print!(), so write directly
+                    let cwd = unistd::getcwd().unwrap();
+                    unistd::write(libc::STDOUT_FILENO, cwd.as_os_str().as_bytes()).unwrap();
+                    thread::sleep(Duration::from_millis(500));
+                    drop(pidfile);
+                    unsafe { libc::_exit(0) };
+                }
+                Outcome::Parent(pid) => {
+                    assert_ne!(unistd::getpid(), pid);
+                    assert_eq!(Some(pid), PidFile::owner(&path).unwrap());
+
+                    // The daemon holds the pidfile, so another can't start
+                    assert_eq!(Error::EWOULDBLOCK, daemon.start().unwrap_err());
+                }
+            }
+        });
+
+        thread::sleep(Duration::from_millis(800));
+        assert!(!path.exists());
+        let cwd = fs::read_to_string(&out).unwrap();
+        let _ = fs::remove_file(&out);
+        assert_eq!(dir, Path::new(&cwd));
+    }
+}
@@ -0,0 +1,330 @@
+// hinix/src/error.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! The hinix error type.
+//!
+//! An [`Error`] is the system error number (errno) from a failed call,
+//! along with, when it's known, the operation that failed and the name
+//! or path of the resource it was operating on. This is displayed like:
+//!
+//! ```text
+//! mq_open(/my_queue): EACCES: Permission denied
+//! ```
+//!
+//! Errors compare equal if they have the same errno, regardless of the
+//! context, so they can be checked against the constants, like
+//! `Error::EAGAIN`, in the same way as a bare errno.
+//!
+
+use nix::errno::Errno;
+use std::{error, fmt, io};
+
+/// The operation and resource that an error came from.
+#[derive(Clone, PartialEq, Eq)]
+struct Context {
+    /// The name of the operation, typically the system call
+    op: &'static str,
+    /// The name or path of the resource, if any
+    resource: Option<String>,
+}
+
+/// Hinix Error type.
+///
+/// This is a system errno, with the context of the operation that
+/// failed, when it's known.
+#[derive(Clone)]
+pub struct Error {
+    /// The system error number
+    errno: Errno,
+    /// Where the error came from
+    ctx: Option<Box<Context>>,
+}
+
+// Creates the constants for the errno values, so that errors can be
+// created and compared like `Error::EINVAL`.
+macro_rules! errno_consts {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[doc = concat!("The `", stringify!($name), "` error, without any context.")]
+            pub const $name: Error = Error::from_errno(Errno::$name);
+        )*
+    };
+}
+
+impl Error {
+    errno_consts! {
+        E2BIG, EACCES, EADDRINUSE, EAFNOSUPPORT, EAGAIN, EBADF, EBUSY,
+        ECANCELED, ECHILD, ECONNREFUSED, ECONNRESET, EEXIST, EFAULT, EFBIG,
+        EINTR, EINVAL, EIO, EISDIR, ELOOP, EMFILE, EMSGSIZE, ENAMETOOLONG,
+        ENOBUFS, ENODEV, ENOENT, ENOMEM, ENOSPC, ENOSYS, ENOTDIR, ENOTSUP,
+        ENOTTY, ENXIO, EOPNOTSUPP, EOVERFLOW, EPERM, EPIPE, EPROTO, ERANGE,
+        ESRCH, ETIMEDOUT, EWOULDBLOCK, EXDEV,
+    }
+
+    /// Creates an error from an errno, without any context.
+    pub const fn from_errno(errno: Errno) -> Self {
+        Self { errno, ctx: None }
+    }
+
+    /// Creates an error from a raw errno value, without any context.
+    pub fn from_i32(errno: i32) -> Self {
+        Self::from_errno(Errno::from_i32(errno))
+    }
+
+    /// Creates an error from the current value of the thread's errno.
+    pub fn last() -> Self {
+        Self::from_errno(Errno::last())
+    }
+
+    /// Creates an error for a failed operation on a named resource.
+    pub fn new<R: fmt::Display>(errno: Errno, op: &'static str, resource: R) -> Self {
+        Self::from_errno(errno).with_resource(op, resource)
+    }
+
+    /// Adds the name of the operation that failed.
+    ///
+    /// If the error already has context, it's kept, since it's closer to
+    /// the actual failure.
+    pub fn with_op(mut self, op: &'static str) -> Self {
+        if self.ctx.is_none() {
+            self.ctx = Some(Box::new(Context { op, resource: None }));
+        }
+        self
+    }
+
+    /// Adds the name of the operation that failed, and the name or path
+    /// of the resource it was operating on.
+    ///
+    /// If the error already has context, it's kept, since it's closer to
+    /// the actual failure.
+    pub fn with_resource<R: fmt::Display>(mut self, op: &'static str, resource: R) -> Self {
+        if self.ctx.is_none() {
+            self.ctx = Some(Box::new(Context {
+                op,
+                resource: Some(resource.to_string()),
+            }));
+        }
+        self
+    }
+
+    /// Gets the system error number.
+    pub fn errno(&self) -> Errno {
+        self.errno
+    }
+
+    /// Gets the raw system error number.
+    pub fn raw_os_error(&self) -> i32 {
+        self.errno as i32
+    }
+
+    /// Gets the name of the operation that failed, if known.
+    pub fn op(&self) -> Option<&'static str> {
+        self.ctx.as_ref().map(|ctx| ctx.op)
+    }
+
+    /// Gets the name or path of the resource that the operation failed
+    /// on, if known.
+    pub fn resource(&self) -> Option<&str> {
+        self.ctx.as_ref().and_then(|ctx| ctx.resource.as_deref())
+    }
+
+    /// Gets a description of the errno.
+    pub fn desc(&self) -> &'static str {
+        self.errno.desc()
+    }
+}
+
+impl PartialEq for Error {
+    /// Errors are equal if they have the same errno.
+    fn eq(&self, other: &Self) -> bool {
+        self.errno == other.errno
+    }
+}
+
+impl Eq for Error {}
+
+impl PartialEq<Errno> for Error {
+    fn eq(&self, other: &Errno) -> bool {
+        self.errno == *other
+    }
+}
+
+impl PartialEq<Error> for Errno {
+    fn eq(&self, other: &Error) -> bool {
+        *self == other.errno
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(ctx) = &self.ctx {
+            match &ctx.resource {
+                Some(res) => write!(f, "{}({}): ", ctx.op, res)?,
+                None => write!(f, "{}: ", ctx.op)?,
+            }
+        }
+        write!(f, "{}", self.errno)
+    }
+}
+
+impl fmt::Debug for Error {
+    // This is what an app shows when main() returns an error, so it's
+    // kept readable, like the Display.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<Errno> for Error {
+    fn from(errno: Errno) -> Self {
+        Self::from_errno(errno)
+    }
+}
+
+impl From<io::Error> for Error {
+    /// Converts an I/O error to its errno, dropping any message.
+    ///
+    /// An I/O error that was made from one of these gets the original
+    /// back, with its context. Any other error that didn't come from the
+    /// OS becomes `EIO`.
+    fn from(err: io::Error) -> Self {
+        if let Some(err) = err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            return err.clone();
+        }
+        Self::from_i32(err.raw_os_error().unwrap_or(libc::EIO))
+    }
+}
+
+impl From<Error> for Errno {
+    fn from(err: Error) -> Self {
+        err.errno
+    }
+}
+
+impl From<Error> for io::Error {
+    /// Converts to an I/O error with the same errno.
+    ///
+    /// If there's context, it's kept as the error message, and the errno
+    /// is reflected in the kind.
+    fn from(err: Error) -> Self {
+        match err.ctx {
+            None => io::Error::from_raw_os_error(err.errno as i32),
+            Some(_) => {
+                let kind = io::Error::from_raw_os_error(err.errno as i32).kind();
+                io::Error::new(kind, err)
+            }
+        }
+    }
+}
+
+/// Adds context to the errors of a result.
+#[allow(dead_code)] // Not every method is used by every set of features
+pub(crate) trait ResultExt<T> {
+    /// Adds the name of the operation to an error.
+    fn op(self, op: &'static str) -> crate::Result<T>;
+
+    /// Adds the name of the operation and the resource to an error.
+    fn op_on<R: fmt::Display>(self, op: &'static str, resource: R) -> crate::Result<T>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for Result<T, E> {
+    fn op(self, op: &'static str) -> crate::Result<T> {
+        self.map_err(|err| err.into().with_op(op))
+    }
+
+    fn op_on<R: fmt::Display>(self, op: &'static str, resource: R) -> crate::Result<T> {
+        self.map_err(|err| err.into().with_resource(op, resource))
+    }
+}
+
+/// Gets the location of the thread's errno, so that a signal handler can
+/// restore it before returning.
+#[allow(dead_code)] // Only used by the features with signal handlers
+pub(crate) unsafe fn errno_location() -> *mut libc::c_int {
+    #[cfg(any(target_os = "freebsd", target_os = "ios", target_os = "macos"))]
+    return libc::__error();
+
+    #[cfg(any(target_os = "android", target_os = "netbsd", target_os = "openbsd"))]
+    return libc::__errno();
+
+    #[cfg(any(target_os = "dragonfly", target_os = "linux"))]
+    return libc::__errno_location();
+
+    #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+    return libc::___errno();
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context() {
+        let err = Error::from(Errno::EACCES);
+        assert_eq!(None, err.op());
+        assert_eq!("EACCES: Permission denied", err.to_string());
+
+        let err = err.with_resource("mq_open", "/my_queue");
+        assert_eq!(Some("mq_open"), err.op());
+        assert_eq!(Some("/my_queue"), err.resource());
+        assert_eq!(
+            "mq_open(/my_queue): EACCES: Permission denied",
+            err.to_string()
+        );
+
+        // The innermost context is kept
+        let err = err.with_op("open");
+        assert_eq!(Some("mq_open"), err.op());
+
+        let err = Error::ENOENT.with_op("pidfd_open");
+        assert_eq!(
+            "pidfd_open: ENOENT: No such file or directory",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_compare() {
+        let err = Error::new(Errno::EAGAIN, "read", "/dev/null");
+        assert_eq!(Error::EAGAIN, err);
+        assert_eq!(Errno::EAGAIN, err);
+        assert_ne!(Error::EINTR, err);
+        assert_eq!(Errno::EAGAIN, Errno::from(err));
+    }
+
+    #[test]
+    fn test_io_error() {
+        let err = Error::from(io::Error::from_raw_os_error(libc::EPIPE));
+        assert_eq!(Error::EPIPE, err);
+
+        let err: io::Error = Error::ENOENT.into();
+        assert_eq!(Some(libc::ENOENT), err.raw_os_error());
+
+        let err: io::Error = Error::new(Errno::ENOENT, "open", "/nowhere").into();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+        assert!(err.to_string().contains("/nowhere"));
+
+        // The round trip keeps the errno and the context
+        let err = Error::from(err);
+        assert_eq!(Error::ENOENT, err);
+        assert_eq!(Some("open"), err.op());
+        assert_eq!(Some("/nowhere"), err.resource());
+
+        let err = Error::from(io::Error::new(io::ErrorKind::InvalidData, "not from the OS"));
+        assert_eq!(Error::EIO, err);
+    }
+}
@@ -16,13 +16,18 @@
 //! <https://man7.org/linux/man-pages/man2/eventfd.2.html>
 //!
 
-use crate::{Error, Result};
-use nix::{self, sys::eventfd, unistd};
+use crate::{
+    cancel::CancelToken,
+    fd::{self, FdExt},
+    timeout::{self, Timeout},
+    Error, Result,
+};
+use nix::{self, poll::PollFlags, sys::eventfd, unistd};
 use std::{
-    mem,
+    mem::{size_of, MaybeUninit},
     os::{
         raw::c_uint,
-        unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
     },
     slice,
 };
@@ -30,7 +35,7 @@ use std::{
 /// The size, in bytes, of the value held by an eventfd.
 /// This is the required size of a buffer that is used for reads and writes,
 /// as the value is a u64.
-const EFD_VAL_SIZE: usize = mem::size_of::<u64>();
+const EFD_VAL_SIZE: usize = size_of::<u64>();
 
 /// The flags used to create an EventFd
 pub type EfdFlags = eventfd::EfdFlags;
@@ -76,43 +81,79 @@ impl EventFd {
 
     /// Create a new event object with the specified flags.
     ///
+    /// The initial value is limited to 32 bits by the system. This fails
+    /// with `EINVAL` if it's larger than that. The EFD_CLOEXEC flag is
+    /// always added.
+    ///
     /// # Parameters
     /// `initval` The initial value held by the object
     /// `flags` The flags used to create the object
     ///
     /// <http://man7.org/linux/man-pages/man2/eventfd.2.html>
     pub fn with_flags(initval: u64, flags: EfdFlags) -> Result<EventFd> {
-        let fd = eventfd::eventfd(initval as c_uint, flags)?;
+        let initval = c_uint::try_from(initval).map_err(|_| Error::EINVAL)?;
+        let fd = eventfd::eventfd(initval, flags | EfdFlags::EFD_CLOEXEC)?;
         let fd = unsafe { OwnedFd::from_raw_fd(fd) };
         Ok(EventFd(fd))
     }
 
     /// Try to clone the event object by making a dup() of the OS file handle.
     pub fn try_clone(&self) -> Result<Self> {
-        let fd = self
-            .0
-            .try_clone()
-            .map_err(|e| Error::try_from(e).unwrap_or_else(|_| Error::from_i32(0)))?;
+        let fd = self.0.dup()?;
         Ok(EventFd(fd))
     }
 
+    /// Closes the event object, reporting any error.
+    ///
+    /// Dropping the object also closes it, but ignores any error.
+    pub fn close(self) -> Result<()> {
+        fd::close(self)
+    }
+
     /// Reads the value of the event object.
     pub fn read(&self) -> Result<u64> {
-        let mut buf: [u8; 8] = [0; EFD_VAL_SIZE];
-        if unistd::read(self.0.as_raw_fd(), &mut buf)? != EFD_VAL_SIZE {
-            return Err(Error::EIO);
-        }
-        let val: u64 = unsafe { *(&buf as *const u8 as *const u64) };
+        let mut buf = [MaybeUninit::uninit(); EFD_VAL_SIZE];
+        let data = fault_point!(EventFdRead, fd::read_uninit(self.as_fd(), &mut buf))?;
+        let val = u64::from_ne_bytes(data.try_into().map_err(|_| Error::EIO)?);
+        #[cfg(feature = "metrics")]
+        crate::metrics::eventfd_wakeup();
         Ok(val)
     }
 
+    /// Waits up to the timeout for the event object to be signaled, then
+    /// reads the value.
+    ///
+    /// Returns `None` if the timeout expired first.
+    pub fn read_timeout<T: Into<Timeout>>(&self, timeout: T) -> Result<Option<u64>> {
+        match timeout::poll_fd(self.as_fd(), PollFlags::POLLIN, timeout)? {
+            true => self.read().map(Some),
+            false => Ok(None),
+        }
+    }
+
+    /// Waits up to the timeout for the event object to be signaled, then
+    /// reads the value, unless the token is cancelled first.
+    ///
+    /// Returns `None` if the timeout expired first, or fails with
+    /// `ECANCELED` if the token was cancelled.
+    pub fn read_cancellable<T: Into<Timeout>>(
+        &self,
+        timeout: T,
+        cancel: &CancelToken,
+    ) -> Result<Option<u64>> {
+        match timeout::poll_fd_cancel(self.as_fd(), PollFlags::POLLIN, timeout, Some(cancel))? {
+            true => self.read().map(Some),
+            false => Ok(None),
+        }
+    }
+
     /// Writes a value to the event object.
     ///
     /// # Parameters
     /// `val` The value to _add_ to the one held by the object.
     pub fn write(&self, val: u64) -> Result<()> {
         let buf = unsafe { slice::from_raw_parts(&val as *const u64 as *const u8, EFD_VAL_SIZE) };
-        if unistd::write(self.0.as_raw_fd(), buf)? != EFD_VAL_SIZE {
+        if fault_point!(EventFdWrite, unistd::write(self.0.as_raw_fd(), buf))? != EFD_VAL_SIZE {
             return Err(Error::EIO);
         }
         Ok(())
@@ -133,6 +174,44 @@ impl AsRawFd for EventFd {
     }
 }
 
+impl IntoRawFd for EventFd {
+    /// Gives up ownership of the file handle for the event object.
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl From<EventFd> for OwnedFd {
+    fn from(evt: EventFd) -> Self {
+        evt.0
+    }
+}
+
+impl TryFrom<OwnedFd> for EventFd {
+    type Error = Error;
+
+    /// Takes ownership of an existing event object, such as one passed from
+    /// another process.
+    ///
+    /// This fails with `EINVAL` if the handle isn't an eventfd.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        fd::check_anon_inode(fd.as_fd(), "eventfd")?;
+        Ok(Self(fd))
+    }
+}
+
+impl FromRawFd for EventFd {
+    /// Takes ownership of an existing event object from its file handle,
+    /// such as one inherited from a parent process.
+    ///
+    /// # Safety
+    ///
+    /// The handle must be an open eventfd that isn't owned elsewhere.
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self(OwnedFd::from_raw_fd(fd))
+    }
+}
+
 /////////////////////////////////////////////////////////////////////////////
 // Unit Tests
 
@@ -160,6 +239,9 @@ mod tests {
         evtfd.write(6).unwrap();
         let n = evtfd.read().unwrap();
         assert_eq!(11, n);
+
+        // The initial value is limited to 32 bits
+        assert_eq!(Error::EINVAL, EventFd::new(u64::MAX).unwrap_err());
     }
 
     #[test]
@@ -169,7 +251,7 @@ mod tests {
 
         // No value in object should get us an EAGAIN error.
         match evtfd.read() {
-            Ok(_) => assert!(false),
+            Ok(_) => unreachable!(),
             Err(err) => assert_eq!(Error::EAGAIN, err),
         }
 
@@ -180,7 +262,7 @@ mod tests {
 
         // The read should have cleared the value, so another is an error.
         match evtfd.read() {
-            Ok(_) => assert!(false),
+            Ok(_) => unreachable!(),
             Err(err) => assert_eq!(Error::EAGAIN, err),
         }
 
@@ -228,8 +310,66 @@ mod tests {
 
         // The read should have cleared the value, so another is an error.
         match evtfd.read() {
-            Ok(_) => assert!(false),
+            Ok(_) => unreachable!(),
             Err(err) => assert_eq!(Error::EAGAIN, err),
         }
     }
+
+    #[test]
+    fn test_from_raw_fd() {
+        let evtfd = EventFd::new(0).unwrap();
+        let clone = evtfd.try_clone().unwrap();
+        let fd = clone.as_raw_fd();
+        std::mem::forget(clone);
+
+        let clone = unsafe { EventFd::from_raw_fd(fd) };
+        clone.write(3).unwrap();
+        assert_eq!(3, evtfd.read().unwrap());
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        use std::time::Duration;
+
+        let evtfd = EventFd::new(0).unwrap();
+        assert_eq!(None, evtfd.read_timeout(Duration::from_millis(10)).unwrap());
+        assert_eq!(None, evtfd.read_timeout(Timeout::ZERO).unwrap());
+
+        evtfd.write(2).unwrap();
+        assert_eq!(Some(2), evtfd.read_timeout(Timeout::None).unwrap());
+    }
+
+    #[test]
+    fn test_read_cancellable() {
+        use std::{thread, time::Duration};
+
+        let evtfd = EventFd::new(0).unwrap();
+        let cancel = CancelToken::new().unwrap();
+        let timeout = Duration::from_millis(10);
+        assert_eq!(None, evtfd.read_cancellable(timeout, &cancel).unwrap());
+
+        evtfd.write(3).unwrap();
+        assert_eq!(Some(3), evtfd.read_cancellable(timeout, &cancel).unwrap());
+
+        // Cancel a wait that's already blocked
+        let th = thread::spawn({
+            let cancel = cancel.clone();
+            move || evtfd.read_cancellable(Timeout::None, &cancel)
+        });
+        thread::sleep(timeout);
+        cancel.cancel();
+        assert_eq!(Error::ECANCELED, th.join().unwrap().unwrap_err());
+    }
+
+    #[test]
+    fn test_owned_fd() {
+        let evtfd = EventFd::new(0).unwrap();
+        let fd = OwnedFd::from(evtfd);
+        let evtfd = EventFd::try_from(fd).unwrap();
+        evtfd.write(5).unwrap();
+        assert_eq!(5, evtfd.read().unwrap());
+
+        let fd = OwnedFd::from(std::fs::File::open("/dev/null").unwrap());
+        assert_eq!(Error::EINVAL, EventFd::try_from(fd).unwrap_err());
+    }
 }
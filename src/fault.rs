@@ -0,0 +1,361 @@
+// hinix/src/fault.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Fault injection, for testing the error handling of applications.
+//!
+//! A [`Fault`] makes chosen calls of an operation, like sending to a
+//! message queue, fail with an error, rather than making the system
+//! call. This lets a test drive an application down the paths for errors
+//! that are hard to cause on demand, like `EINTR`, `EAGAIN`, or
+//! `EMSGSIZE`, on exactly the call it wants.
+//!
+//! ```
+//! use hinix::{fault::{Fault, Op}, pipe, Error};
+//! use std::io::{Read, Write};
+//!
+//! let (mut wr, mut rd) = pipe::pipe()?;
+//! wr.write_all(b"hello")?;
+//!
+//! // The second read fails
+//! let _fault = Fault::new(Op::PipeRead, Error::EAGAIN).on_call(2).inject();
+//!
+//! let mut buf = [0u8; 2];
+//! assert_eq!(2, rd.read(&mut buf)?);
+//! let err = rd.read(&mut buf).unwrap_err();
+//! assert_eq!(Some(libc::EAGAIN), err.raw_os_error());
+//! assert_eq!(2, rd.read(&mut buf)?);
+//! # Ok::<(), hinix::Error>(())
+//! ```
+//!
+//! The faults belong to the thread that injects them, and only fail the
+//! calls made by that thread, so the tests run in parallel by the test
+//! harness don't trip over each other's faults. An error is injected in
+//! place of the system call, so the crate handles it like a real one. An
+//! `EINTR` injected into a call that's restarted after a signal, like a
+//! timed receive, is retried, rather than returned.
+//!
+//! The mock handles, in the `mock` module, fail with the faults for the
+//! same operations.
+//!
+
+use crate::Error;
+use nix::errno::Errno;
+use std::{cell::RefCell, collections::HashMap, marker::PhantomData};
+
+/// An operation that can have faults injected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Op {
+    /// Reading the value of an eventfd
+    EventFdRead,
+    /// Writing a value to an eventfd
+    EventFdWrite,
+    /// Reading from a FIFO
+    FifoRead,
+    /// Writing to a FIFO
+    FifoWrite,
+    /// Receiving a message from a message queue
+    MqReceive,
+    /// Sending a message to a message queue
+    MqSend,
+    /// Reading from a pipe
+    PipeRead,
+    /// Writing to a pipe
+    PipeWrite,
+    /// Waiting for a handle to be ready, in a timed or cancellable call
+    Poll,
+    /// Waiting for a timerfd to expire
+    TimerWait,
+}
+
+/// A fault that's been injected for a thread.
+#[derive(Debug)]
+struct Entry {
+    /// The ID of the guard that removes the fault
+    id: u64,
+    /// The fault
+    fault: Fault,
+    /// The number of calls it has failed
+    fired: usize,
+}
+
+/// The faults and call counts for a thread.
+#[derive(Debug, Default)]
+struct State {
+    /// The ID of the next fault
+    next_id: u64,
+    /// The faults that were injected
+    faults: Vec<Entry>,
+    /// The number of calls of each operation
+    calls: HashMap<Op, usize>,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// A fault to inject into the calls of an operation.
+///
+/// By default, this fails the next call of the operation. It can be set
+/// to fail a later call, or a number of calls in a row.
+#[derive(Debug, Clone, Copy)]
+pub struct Fault {
+    /// The operation to fail
+    op: Op,
+    /// The error to fail it with
+    errno: Errno,
+    /// The number of calls to let through before failing any
+    skip: usize,
+    /// The number of calls to fail
+    count: usize,
+}
+
+impl Fault {
+    /// Creates a fault that fails the next call of the operation with the
+    /// error.
+    pub fn new(op: Op, err: Error) -> Self {
+        Self {
+            op,
+            errno: err.errno(),
+            skip: 0,
+            count: 1,
+        }
+    }
+
+    /// Fails the n'th call of the operation, counting from the next
+    /// one, which is 1, rather than the next call.
+    pub fn on_call(mut self, n: usize) -> Self {
+        self.skip = n.saturating_sub(1);
+        self
+    }
+
+    /// Fails this many calls in a row, starting with the first one that
+    /// the fault fails.
+    pub fn times(mut self, n: usize) -> Self {
+        self.count = n;
+        self
+    }
+
+    /// Fails every call of the operation, from the first one that the
+    /// fault fails, until it's removed.
+    pub fn always(self) -> Self {
+        self.times(usize::MAX)
+    }
+
+    /// Injects the fault into the calls made by the current thread.
+    ///
+    /// The fault is removed when the guard is dropped.
+    #[must_use = "the fault is removed when the guard is dropped"]
+    pub fn inject(self) -> FaultGuard {
+        let id = STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let id = state.next_id;
+            state.next_id += 1;
+            state.faults.push(Entry {
+                id,
+                fault: self,
+                fired: 0,
+            });
+            id
+        });
+        FaultGuard {
+            id,
+            _thread: PhantomData,
+        }
+    }
+}
+
+/// A guard for an injected fault, which removes it when dropped.
+///
+/// This stays on the thread that injected the fault.
+#[derive(Debug)]
+pub struct FaultGuard {
+    /// The ID of the fault
+    id: u64,
+    /// Keeps the guard from being sent to another thread
+    _thread: PhantomData<*const ()>,
+}
+
+impl FaultGuard {
+    /// Gets the number of calls that the fault has failed.
+    pub fn fired(&self) -> usize {
+        STATE.with(|state| {
+            let state = state.borrow();
+            let entry = state.faults.iter().find(|entry| entry.id == self.id);
+            entry.map_or(0, |entry| entry.fired)
+        })
+    }
+
+    /// Determines if the fault has failed all the calls it was set to.
+    pub fn is_done(&self) -> bool {
+        STATE.with(|state| {
+            let state = state.borrow();
+            let entry = state.faults.iter().find(|entry| entry.id == self.id);
+            !matches!(entry, Some(entry) if entry.fired < entry.fault.count)
+        })
+    }
+}
+
+impl Drop for FaultGuard {
+    fn drop(&mut self) {
+        // The state is gone if the thread is exiting
+        let _ = STATE.try_with(|state| {
+            state
+                .borrow_mut()
+                .faults
+                .retain(|entry| entry.id != self.id)
+        });
+    }
+}
+
+/// Gets the number of calls of the operation made by the current thread,
+/// including the ones that failed with an injected fault.
+///
+/// This can show that a call was retried, like after an `EINTR`.
+pub fn calls(op: Op) -> usize {
+    STATE.with(|state| state.borrow().calls.get(&op).copied().unwrap_or_default())
+}
+
+/// Removes all the faults from the current thread, and resets its call
+/// counts.
+pub fn reset() {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.faults.clear();
+        state.calls.clear();
+    });
+}
+
+/// Counts a call of the operation, and fails it if a fault is due.
+///
+/// This is called in place of the system call, through the
+/// `fault_point!` macro.
+pub(crate) fn check(op: Op) -> nix::Result<()> {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        *state.calls.entry(op).or_default() += 1;
+
+        let mut res = Ok(());
+        for entry in state.faults.iter_mut().filter(|entry| entry.fault.op == op) {
+            let fault = &mut entry.fault;
+            if fault.skip > 0 {
+                fault.skip -= 1;
+            }
+            else if res.is_ok() && entry.fired < fault.count {
+                entry.fired += 1;
+                res = Err(fault.errno);
+            }
+        }
+        res
+    })
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let fault = Fault::new(Op::MqSend, Error::EMSGSIZE)
+            .on_call(2)
+            .times(2)
+            .inject();
+
+        assert!(check(Op::MqSend).is_ok());
+        assert!(check(Op::MqReceive).is_ok());
+        assert_eq!(Err(Errno::EMSGSIZE), check(Op::MqSend));
+        assert_eq!(Err(Errno::EMSGSIZE), check(Op::MqSend));
+        assert!(fault.is_done());
+        assert!(check(Op::MqSend).is_ok());
+        assert_eq!(2, fault.fired());
+        assert_eq!(4, calls(Op::MqSend));
+
+        // Faults are only seen by the thread that injected them
+        let _fault = Fault::new(Op::Poll, Error::EINTR).always().inject();
+        let th = std::thread::spawn(|| check(Op::Poll));
+        assert!(th.join().unwrap().is_ok());
+        assert_eq!(Err(Errno::EINTR), check(Op::Poll));
+
+        drop(fault);
+        reset();
+        assert!(check(Op::Poll).is_ok());
+        assert_eq!(1, calls(Op::Poll));
+    }
+
+    #[cfg(all(feature = "pipe", any(target_os = "android", target_os = "linux")))]
+    #[test]
+    fn test_pipe() {
+        use crate::pipe;
+        use std::{io::Write, time::Duration};
+
+        let (mut wr, mut rd) = pipe::pipe().unwrap();
+        let fault = Fault::new(Op::PipeWrite, Error::EAGAIN).inject();
+        assert!(wr.write(b"hello").is_err());
+        assert!(fault.is_done());
+        wr.write_all(b"hello").unwrap();
+
+        // An interrupted wait is retried
+        let start = calls(Op::Poll);
+        let _fault = Fault::new(Op::Poll, Error::EINTR).inject();
+        let mut buf = [0u8; 8];
+        let n = rd.read_timeout(&mut buf, Duration::from_secs(1)).unwrap();
+        assert_eq!(Some(5), n);
+        assert_eq!(start + 2, calls(Op::Poll));
+    }
+
+    #[cfg(all(feature = "msgqueue", target_os = "linux"))]
+    #[test]
+    fn test_msgqueue() {
+        use crate::msgqueue::MsgQueue;
+        use std::time::Duration;
+
+        const NAME: &str = "/rust_fault_unit_test";
+
+        let _ = MsgQueue::unlink(NAME);
+        let mq = MsgQueue::create(NAME, 2, 16).unwrap();
+        MsgQueue::unlink(NAME).unwrap();
+
+        let _fault = Fault::new(Op::MqSend, Error::EMSGSIZE).inject();
+        assert_eq!(Error::EMSGSIZE, mq.send("hello").unwrap_err());
+        mq.send("hello").unwrap();
+
+        // A timed receive retries after an interrupt
+        let _fault = Fault::new(Op::MqReceive, Error::EINTR).inject();
+        let mut buf = [0u8; 16];
+        let mut prio = 0;
+        let n = mq
+            .receive_timeout(&mut buf, &mut prio, Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(b"hello", &buf[..n]);
+    }
+
+    #[cfg(all(feature = "mock", any(target_os = "android", target_os = "linux")))]
+    #[test]
+    fn test_mock() {
+        use crate::mock::msgqueue::MsgQueue;
+
+        const NAME: &str = "/mock_fault_unit_test";
+
+        let mq = MsgQueue::create(NAME, 2, 16).unwrap();
+        MsgQueue::unlink(NAME).unwrap();
+
+        let _fault = Fault::new(Op::MqSend, Error::EAGAIN).inject();
+        assert_eq!(Error::EAGAIN, mq.send("hello").unwrap_err());
+        mq.send("hello").unwrap();
+        assert_eq!("hello", mq.receive_string().unwrap());
+    }
+}
@@ -0,0 +1,310 @@
+// hinix/src/fd.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Operations on file handles.
+//!
+//! The [`FdExt`] trait adds the common fcntl() operations to any type
+//! that holds an OS file handle, like a `File`, a socket, or any of the
+//! types in this crate.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man2/fcntl.2.html>
+//!
+
+use crate::{error::ResultExt, Error, Result};
+use nix::{
+    errno::Errno,
+    fcntl::{self, FcntlArg, FdFlag, OFlag},
+    sys::{
+        socket::{self, sockopt, SockType},
+        stat::{self, SFlag},
+    },
+    unistd,
+};
+use std::{
+    mem::MaybeUninit,
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    slice,
+};
+
+/// Extension methods for types that hold an OS file handle.
+///
+/// This is implemented for all types that implement `AsFd`.
+pub trait FdExt: AsFd {
+    /// Sets or clears the close-on-exec (FD_CLOEXEC) flag on the handle.
+    ///
+    /// When set, the handle is closed automatically if the process
+    /// calls one of the exec() functions.
+    fn set_cloexec(&self, on: bool) -> Result<()> {
+        let fd = self.as_fd().as_raw_fd();
+        let mut flags = FdFlag::from_bits_truncate(fcntl::fcntl(fd, FcntlArg::F_GETFD)?);
+        flags.set(FdFlag::FD_CLOEXEC, on);
+        fcntl::fcntl(fd, FcntlArg::F_SETFD(flags))?;
+        Ok(())
+    }
+
+    /// Determines if the close-on-exec (FD_CLOEXEC) flag is set on the
+    /// handle.
+    fn is_cloexec(&self) -> Result<bool> {
+        let flags = fcntl::fcntl(self.as_fd().as_raw_fd(), FcntlArg::F_GETFD)?;
+        Ok(FdFlag::from_bits_truncate(flags).contains(FdFlag::FD_CLOEXEC))
+    }
+
+    /// Puts the handle into, or takes it out of, non-blocking mode
+    /// by setting or clearing the O_NONBLOCK flag.
+    ///
+    /// Note that this is a flag on the open file description, so it
+    /// affects all the handles that were duplicated from the same open.
+    fn set_nonblocking(&self, on: bool) -> Result<()> {
+        let fd = self.as_fd().as_raw_fd();
+        let mut flags = OFlag::from_bits_truncate(fcntl::fcntl(fd, FcntlArg::F_GETFL)?);
+        flags.set(OFlag::O_NONBLOCK, on);
+        fcntl::fcntl(fd, FcntlArg::F_SETFL(flags))?;
+        Ok(())
+    }
+
+    /// Determines if the handle is in non-blocking mode.
+    fn is_nonblocking(&self) -> Result<bool> {
+        let flags = fcntl::fcntl(self.as_fd().as_raw_fd(), FcntlArg::F_GETFL)?;
+        Ok(OFlag::from_bits_truncate(flags).contains(OFlag::O_NONBLOCK))
+    }
+
+    /// Duplicates the handle.
+    ///
+    /// The new handle refers to the same open file, and is created with
+    /// the close-on-exec flag set.
+    fn dup(&self) -> Result<OwnedFd> {
+        let fd = fcntl::fcntl(self.as_fd().as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(0))?;
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Duplicates the handle onto a specific handle number, like dup2().
+    ///
+    /// If `target_fd` is already open, it is closed first. The new handle
+    /// does not have the close-on-exec flag set, so this is typically
+    /// used to redirect the standard I/O handles before an exec().
+    ///
+    /// The target handle is not owned by the returned value, and will
+    /// remain open until explicitly closed.
+    fn dup_to(&self, target_fd: RawFd) -> Result<()> {
+        let fd = self.as_fd().as_raw_fd();
+        if fd == target_fd {
+            // dup2() would leave the flags alone in this case
+            self.set_cloexec(false)
+        }
+        else {
+            unistd::dup2(fd, target_fd)?;
+            Ok(())
+        }
+    }
+}
+
+impl<T: AsFd + ?Sized> FdExt for T {}
+
+/// Closes a handle, reporting any error from the close.
+///
+/// Dropping a handle closes it too, but ignores any error, like an I/O
+/// error reported late by a device or network file system. This works
+/// with any of the handle types that can be converted into an `OwnedFd`.
+///
+/// An `EINTR` is not treated as an error. The handle is released by then
+/// on Linux, and retrying could close one that another thread just opened.
+pub fn close<T: Into<OwnedFd>>(fd: T) -> Result<()> {
+    match unistd::close(fd.into().into_raw_fd()) {
+        Err(Errno::EINTR) => Ok(()),
+        res => res.op("close"),
+    }
+}
+
+// These check that a handle is the right kind of object when it's
+// converted into one of the types in the crate, like with
+// `TryFrom<OwnedFd>`. Not all of them are used with every feature.
+
+/// Checks that a handle is for a file of the given type, like
+/// `S_IFIFO` or `S_IFCHR`.
+///
+/// This fails with `EINVAL` if it's some other type of file.
+#[allow(dead_code)]
+pub(crate) fn check_file_type(fd: BorrowedFd, ftype: SFlag) -> Result<()> {
+    let st = stat::fstat(fd.as_raw_fd()).op("fstat")?;
+    match SFlag::from_bits_truncate(st.st_mode & SFlag::S_IFMT.bits()) {
+        t if t == ftype => Ok(()),
+        _ => Err(Error::EINVAL),
+    }
+}
+
+/// Checks that a handle was opened for the access mode, `O_RDONLY` or
+/// `O_WRONLY`. A handle opened with `O_RDWR` can be used for either.
+///
+/// This fails with `EINVAL` if it was opened for the other direction.
+#[allow(dead_code)]
+pub(crate) fn check_access(fd: BorrowedFd, access: OFlag) -> Result<()> {
+    let flags = fcntl::fcntl(fd.as_raw_fd(), FcntlArg::F_GETFL).op("fcntl")?;
+    match OFlag::from_bits_truncate(flags) & OFlag::O_ACCMODE {
+        mode if mode == access || mode == OFlag::O_RDWR => Ok(()),
+        _ => Err(Error::EINVAL),
+    }
+}
+
+/// Checks whether a handle has a file status flag set, like `O_DIRECT`.
+#[allow(dead_code)]
+pub(crate) fn has_status_flag(fd: BorrowedFd, flag: OFlag) -> Result<bool> {
+    let flags = fcntl::fcntl(fd.as_raw_fd(), FcntlArg::F_GETFL).op("fcntl")?;
+    Ok(OFlag::from_bits_truncate(flags).contains(flag))
+}
+
+/// Checks that a handle is a socket in the address family, like
+/// `AF_UNIX`, and of the type.
+///
+/// This fails with `ENOTSOCK` if it's not a socket, or `EINVAL` if it's
+/// some other kind of socket.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[allow(dead_code)]
+pub(crate) fn check_socket(fd: BorrowedFd, family: libc::c_int, ty: SockType) -> Result<()> {
+    let mut domain: libc::c_int = 0;
+    let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_DOMAIN,
+            &mut domain as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    Errno::result(ret).op("getsockopt")?;
+
+    if domain == family && socket::getsockopt(fd.as_raw_fd(), sockopt::SockType)? == ty {
+        Ok(())
+    }
+    else {
+        Err(Error::EINVAL)
+    }
+}
+
+/// Checks that a handle is for an anonymous inode of the kind, like
+/// "eventfd" or "pidfd", from its link in /proc.
+///
+/// This fails with `EINVAL` if it's some other kind of file.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[allow(dead_code)]
+pub(crate) fn check_anon_inode(fd: BorrowedFd, kind: &str) -> Result<()> {
+    let link = format!("/proc/self/fd/{}", fd.as_raw_fd());
+    let target = fcntl::readlink(link.as_str()).op_on("readlink", &link)?;
+    let target = target.to_string_lossy();
+
+    // These look like "anon_inode:[eventfd]" or "anon_inode:inotify", but
+    // newer kernels have a filesystem for pidfd's, like "pidfd:[1234]"
+    let name = match target.split_once(':') {
+        Some(("anon_inode", name)) => name.trim_start_matches('[').trim_end_matches(']'),
+        Some((name, _)) => name,
+        None => "",
+    };
+
+    if name == kind {
+        Ok(())
+    }
+    else {
+        Err(Error::EINVAL)
+    }
+}
+
+/// Reads from a handle into a buffer that may be uninitialized.
+///
+/// Returns the part of the buffer that was filled, which is empty at
+/// the end of a stream.
+#[allow(dead_code)]
+pub(crate) fn read_uninit<'a>(
+    fd: BorrowedFd,
+    buf: &'a mut [MaybeUninit<u8>],
+) -> Result<&'a mut [u8]> {
+    let ptr = buf.as_mut_ptr() as *mut libc::c_void;
+    let n = unsafe { libc::read(fd.as_raw_fd(), ptr, buf.len()) };
+    let n = Errno::result(n)? as usize;
+    // The kernel initialized the first 'n' bytes
+    Ok(unsafe { slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, n) })
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(all(test, feature = "pipe"))]
+mod tests {
+    use super::*;
+    use crate::pipe;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_nonblocking() {
+        let (wr, mut rd) = pipe::pipe().unwrap();
+        assert!(!rd.is_nonblocking().unwrap());
+
+        rd.set_nonblocking(true).unwrap();
+        assert!(rd.is_nonblocking().unwrap());
+
+        let mut buf = [0u8; 4];
+        let err = rd.read(&mut buf).unwrap_err();
+        assert_eq!(std::io::ErrorKind::WouldBlock, err.kind());
+
+        rd.set_nonblocking(false).unwrap();
+        assert!(!rd.is_nonblocking().unwrap());
+        drop(wr);
+    }
+
+    #[test]
+    fn test_close() {
+        let (wr, rd) = pipe::pipe().unwrap();
+        close(wr).unwrap();
+
+        close(rd).unwrap();
+
+        // The kernel never hands out a number this high, so this can't
+        // close a handle that another test just opened.
+        let fd = unsafe { OwnedFd::from_raw_fd(RawFd::MAX) };
+        assert_eq!(Error::EBADF, close(fd).unwrap_err());
+    }
+
+    #[test]
+    fn test_cloexec() {
+        let (wr, _rd) = pipe::pipe().unwrap();
+
+        wr.set_cloexec(true).unwrap();
+        assert!(wr.is_cloexec().unwrap());
+
+        wr.set_cloexec(false).unwrap();
+        assert!(!wr.is_cloexec().unwrap());
+    }
+
+    #[test]
+    fn test_dup() {
+        let (mut wr, rd) = pipe::pipe().unwrap();
+
+        let fd = rd.dup().unwrap();
+        assert_ne!(fd.as_raw_fd(), rd.as_raw_fd());
+        assert!(fd.is_cloexec().unwrap());
+
+        // Both handles share the same open file
+        fd.set_nonblocking(true).unwrap();
+        assert!(rd.is_nonblocking().unwrap());
+        fd.set_nonblocking(false).unwrap();
+
+        let target = wr.dup().unwrap();
+        let target_fd = target.as_raw_fd();
+        rd.dup_to(target_fd).unwrap();
+        assert!(!target.is_cloexec().unwrap());
+
+        wr.write_all(b"abc").unwrap();
+        let mut buf = [0u8; 4];
+        let n = unistd::read(target_fd, &mut buf).unwrap();
+        assert_eq!(b"abc", &buf[..n]);
+    }
+}
@@ -0,0 +1,406 @@
+// hinix/src/fdinfo.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Enumeration of the open file handles of a process.
+//!
+//! This reads the /proc/<pid>/fd and /proc/<pid>/fdinfo directories to
+//! find out which handles a process has open, and what they refer to.
+//! The [`FdGuardScope`] uses this to find handles that were leaked by a
+//! section of code, which is particularly useful when testing code that
+//! forks and execs child processes.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man5/proc.5.html>
+//!
+
+use crate::{Error, Result};
+use nix::{
+    dir::Dir,
+    fcntl::OFlag,
+    sys::{
+        stat::{self, Mode, SFlag},
+        statfs::{self, FsType},
+    },
+    unistd::{self, Pid},
+};
+use std::{
+    collections::BTreeSet,
+    fmt, fs, io,
+    os::unix::io::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+};
+
+/// The magic number of the mqueue filesystem, which nix doesn't define.
+const MQUEUE_MAGIC: i64 = 0x1980_0202;
+
+/// The type of object that a file handle refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FdType {
+    /// A regular file
+    File,
+    /// A directory
+    Dir,
+    /// A character device, like a terminal
+    CharDevice,
+    /// A block device
+    BlockDevice,
+    /// A pipe or named FIFO
+    Pipe,
+    /// A socket of any kind
+    Socket,
+    /// An event counter from eventfd()
+    EventFd,
+    /// A timer from timerfd_create()
+    TimerFd,
+    /// A signal handle from signalfd()
+    SignalFd,
+    /// An epoll instance
+    Epoll,
+    /// An inotify instance
+    Inotify,
+    /// A process handle from pidfd_open()
+    PidFd,
+    /// A Posix message queue
+    MsgQueue,
+    /// An anonymous file from memfd_create()
+    MemFd,
+    /// Some other type of anonymous inode
+    AnonInode,
+    /// The type couldn't be determined
+    Unknown,
+}
+
+impl FdType {
+    /// Determines the type of an open handle from its target, looking
+    /// at the file it refers to, through the /proc link, if necessary.
+    fn classify(link: &Path, target: &Path) -> Self {
+        let name = target.to_string_lossy();
+
+        if name.starts_with("socket:") {
+            return Self::Socket;
+        }
+        if name.starts_with("pipe:") {
+            return Self::Pipe;
+        }
+        if name.starts_with("pidfd:") {
+            return Self::PidFd;
+        }
+        if name.starts_with("/memfd:") {
+            return Self::MemFd;
+        }
+        if let Some(kind) = name.strip_prefix("anon_inode:") {
+            return match kind.trim_start_matches('[').trim_end_matches(']') {
+                "eventfd" => Self::EventFd,
+                "timerfd" => Self::TimerFd,
+                "signalfd" => Self::SignalFd,
+                "eventpoll" => Self::Epoll,
+                "inotify" => Self::Inotify,
+                "pidfd" => Self::PidFd,
+                _ => Self::AnonInode,
+            };
+        }
+
+        // The link resolves to the actual file, even if it's been
+        // deleted or is in another mount namespace.
+        let mode = match stat::stat(link) {
+            Ok(st) => SFlag::from_bits_truncate(st.st_mode & SFlag::S_IFMT.bits()),
+            Err(_) => return Self::Unknown,
+        };
+
+        match mode {
+            SFlag::S_IFDIR => Self::Dir,
+            SFlag::S_IFCHR => Self::CharDevice,
+            SFlag::S_IFBLK => Self::BlockDevice,
+            SFlag::S_IFIFO => Self::Pipe,
+            SFlag::S_IFSOCK => Self::Socket,
+            SFlag::S_IFREG => match statfs::statfs(link) {
+                Ok(fs) if fs.filesystem_type() == FsType(MQUEUE_MAGIC as _) => Self::MsgQueue,
+                _ => Self::File,
+            },
+            _ => Self::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for FdType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            Self::File => "file",
+            Self::Dir => "dir",
+            Self::CharDevice => "chr",
+            Self::BlockDevice => "blk",
+            Self::Pipe => "pipe",
+            Self::Socket => "socket",
+            Self::EventFd => "eventfd",
+            Self::TimerFd => "timerfd",
+            Self::SignalFd => "signalfd",
+            Self::Epoll => "epoll",
+            Self::Inotify => "inotify",
+            Self::PidFd => "pidfd",
+            Self::MsgQueue => "mqueue",
+            Self::MemFd => "memfd",
+            Self::AnonInode => "anon",
+            Self::Unknown => "unknown",
+        };
+        f.pad(s)
+    }
+}
+
+/// Information about an open file handle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FdInfo {
+    /// The handle number
+    pub fd: RawFd,
+    /// What the handle refers to.
+    ///
+    /// For a regular file or directory, this is its path. Other types
+    /// of handles have names like "socket:[1234]" or "pipe:[5678]".
+    pub target: PathBuf,
+    /// The flags that the file was opened with, including O_CLOEXEC if
+    /// the close-on-exec flag is set on the handle.
+    pub flags: OFlag,
+    /// The current file offset
+    pub pos: u64,
+    /// The type of object the handle refers to
+    pub fd_type: FdType,
+}
+
+impl FdInfo {
+    /// Determines if the handle will be closed on an exec().
+    pub fn is_cloexec(&self) -> bool {
+        self.flags.contains(OFlag::O_CLOEXEC)
+    }
+
+    /// Reads the info for a handle from the process directory in /proc.
+    /// Returns `None` if the handle was closed in the meantime.
+    fn read(proc_dir: &Path, fd: RawFd) -> Result<Option<Self>> {
+        let link = proc_dir.join("fd").join(fd.to_string());
+        let target = match fs::read_link(&link) {
+            Ok(target) => target,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let info = match fs::read_to_string(proc_dir.join("fdinfo").join(fd.to_string())) {
+            Ok(info) => info,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut flags = OFlag::empty();
+        let mut pos = 0;
+
+        for line in info.lines() {
+            if let Some((key, val)) = line.split_once(':') {
+                match key {
+                    "pos" => pos = val.trim().parse().map_err(|_| Error::EINVAL)?,
+                    "flags" => {
+                        let bits = i32::from_str_radix(val.trim(), 8).map_err(|_| Error::EINVAL)?;
+                        flags = OFlag::from_bits_truncate(bits);
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        let fd_type = FdType::classify(&link, &target);
+
+        Ok(Some(Self {
+            fd,
+            target,
+            flags,
+            pos,
+            fd_type,
+        }))
+    }
+}
+
+/// Gets the list of file handles that are open in the calling process,
+/// sorted by handle number.
+///
+/// The handle used to read the directory is not included.
+pub fn open_fds() -> Result<Vec<FdInfo>> {
+    read_fds(Path::new("/proc/self"))
+}
+
+/// Gets the list of file handles that are open in another process,
+/// sorted by handle number.
+///
+/// This requires permission to read the /proc entries of the process,
+/// which normally means that it belongs to the same user.
+pub fn open_fds_of(pid: Pid) -> Result<Vec<FdInfo>> {
+    if pid == unistd::getpid() {
+        open_fds()
+    }
+    else {
+        read_fds(&PathBuf::from(format!("/proc/{}", pid)))
+    }
+}
+
+/// Reads the handle numbers from the fd directory of a process,
+/// skipping the one used to read the directory, if it's our own.
+fn fd_numbers(proc_dir: &Path) -> Result<BTreeSet<RawFd>> {
+    let mut dir = Dir::open(
+        &proc_dir.join("fd"),
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+    let dir_fd = dir.as_raw_fd();
+    let is_self = proc_dir == Path::new("/proc/self");
+
+    let mut fds = BTreeSet::new();
+    for entry in dir.iter() {
+        let entry = entry?;
+        if let Some(fd) = entry
+            .file_name()
+            .to_str()
+            .ok()
+            .and_then(|s| s.parse::<RawFd>().ok())
+        {
+            if !(is_self && fd == dir_fd) {
+                fds.insert(fd);
+            }
+        }
+    }
+    Ok(fds)
+}
+
+/// Gets the info for a set of handles in a process.
+fn read_infos<I>(proc_dir: &Path, fds: I) -> Result<Vec<FdInfo>>
+where
+    I: IntoIterator<Item = RawFd>,
+{
+    let mut infos = Vec::new();
+    for fd in fds {
+        if let Some(info) = FdInfo::read(proc_dir, fd)? {
+            infos.push(info);
+        }
+    }
+    Ok(infos)
+}
+
+fn read_fds(proc_dir: &Path) -> Result<Vec<FdInfo>> {
+    read_infos(proc_dir, fd_numbers(proc_dir)?)
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// A snapshot of the open file handles of the process, used to detect
+/// leaks.
+///
+/// Create the scope before running the code under test, then call
+/// [`leaked()`] afterward to get the handles that were opened, but not
+/// closed, in the meantime.
+///
+/// Note that the handles are process-wide, so any other threads that
+/// open files while the scope is active will show up as leaks. Tests
+/// that run in parallel should filter the results for the handles
+/// they're interested in.
+///
+/// [`leaked()`]: FdGuardScope::leaked
+#[derive(Debug, Clone)]
+pub struct FdGuardScope {
+    /// The handles that were open when the scope was created
+    fds: BTreeSet<RawFd>,
+}
+
+impl FdGuardScope {
+    /// Takes a snapshot of the handles currently open in the process.
+    pub fn new() -> Result<Self> {
+        let fds = fd_numbers(Path::new("/proc/self"))?;
+        Ok(Self { fds })
+    }
+
+    /// Gets the handles that are open now, but weren't when the scope
+    /// was created.
+    pub fn leaked(&self) -> Result<Vec<FdInfo>> {
+        let proc_dir = Path::new("/proc/self");
+        let fds = fd_numbers(proc_dir)?;
+        read_infos(proc_dir, fds.difference(&self.fds).copied())
+    }
+
+    /// Gets the handles that were open when the scope was created, but
+    /// have since been closed.
+    pub fn closed(&self) -> Result<Vec<RawFd>> {
+        let fds = fd_numbers(Path::new("/proc/self"))?;
+        Ok(self.fds.difference(&fds).copied().collect())
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(all(test, feature = "pipe"))]
+mod tests {
+    use super::*;
+    use crate::pipe;
+    use std::{env, fs::File};
+
+    #[test]
+    fn test_open_fds() {
+        let path = env::temp_dir().join(format!("hinix-fdinfo-{}", unistd::getpid()));
+        let file = File::create(&path).unwrap();
+        let fd = file.as_raw_fd();
+
+        let fds = open_fds().unwrap();
+        let info = fds.iter().find(|info| info.fd == fd).unwrap();
+        assert_eq!(path, info.target);
+        assert!(info.flags.contains(OFlag::O_WRONLY));
+        assert!(info.is_cloexec());
+        assert_eq!(0, info.pos);
+        assert_eq!(FdType::File, info.fd_type);
+
+        assert!(fds.windows(2).all(|w| w[0].fd < w[1].fd));
+        assert_eq!(fds, open_fds_of(unistd::getpid()).unwrap());
+
+        drop(file);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_leak_scope() {
+        let scope = FdGuardScope::new().unwrap();
+
+        let (wr, rd) = pipe::pipe().unwrap();
+        let (wr_fd, rd_fd) = (wr.as_raw_fd(), rd.as_raw_fd());
+
+        let leaked = scope.leaked().unwrap();
+        let info = leaked.iter().find(|info| info.fd == rd_fd).unwrap();
+        let target = info.target.clone();
+        assert!(target.to_string_lossy().starts_with("pipe:"));
+        assert_eq!(FdType::Pipe, info.fd_type);
+        assert!(leaked.iter().any(|info| info.fd == wr_fd));
+
+        drop(wr);
+        drop(rd);
+
+        // The handle numbers might get reused by other tests, but the
+        // pipe inode won't.
+        let leaked = scope.leaked().unwrap();
+        assert!(!leaked.iter().any(|info| info.target == target));
+    }
+
+    #[cfg(feature = "eventfd")]
+    #[test]
+    fn test_fd_type() {
+        let evt = crate::eventfd::EventFd::new(0).unwrap();
+        let dir = File::open(env::temp_dir()).unwrap();
+
+        let fds = open_fds().unwrap();
+        let info = fds.iter().find(|info| info.fd == evt.as_raw_fd()).unwrap();
+        assert_eq!(FdType::EventFd, info.fd_type);
+        assert_eq!("eventfd", info.fd_type.to_string());
+
+        let info = fds.iter().find(|info| info.fd == dir.as_raw_fd()).unwrap();
+        assert_eq!(FdType::Dir, info.fd_type);
+    }
+}
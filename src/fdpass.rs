@@ -0,0 +1,163 @@
+// hinix/src/fdpass.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Passing open file handles over Unix-domain sockets.
+//!
+//! A process can send copies of its open handles to another process with
+//! an SCM_RIGHTS control message, attached to a regular message on a
+//! Unix-domain socket. The receiver gets new handles, which refer to the
+//! same open files, much like dup() across processes.
+//!
+//! This works with stream, datagram, and seqpacket sockets, but with a
+//! stream socket, the handles are attached to the first byte of the data,
+//! so it's easiest to send them with a message that is read whole.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man7/unix.7.html>
+//!
+
+use crate::{error::ResultExt, Error, Result};
+use nix::sys::socket::{self, ControlMessage, ControlMessageOwned, MsgFlags, UnixAddr};
+use std::{
+    io::{IoSlice, IoSliceMut},
+    mem::size_of,
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+};
+
+/// The most handles that the kernel accepts in one message (SCM_MAX_FD).
+pub const MAX_FDS: usize = 253;
+
+/// Sends a message on a connected socket, along with copies of the
+/// handles.
+///
+/// The handles remain open in the sending process. Returns the number
+/// of bytes of the message that were sent.
+pub fn send_with_fds<S: AsFd>(sock: &S, buf: &[u8], fds: &[BorrowedFd]) -> Result<usize> {
+    send_to_with_fds(sock, buf, fds, None)
+}
+
+/// Sends a message, along with copies of the handles, to the address
+/// of a datagram socket, or on a connected socket if `addr` is `None`.
+pub fn send_to_with_fds<S: AsFd>(
+    sock: &S,
+    buf: &[u8],
+    fds: &[BorrowedFd],
+    addr: Option<&UnixAddr>,
+) -> Result<usize> {
+    if fds.len() > MAX_FDS {
+        return Err(Error::EINVAL);
+    }
+
+    let fds: Vec<RawFd> = fds.iter().map(|fd| fd.as_raw_fd()).collect();
+    let iov = [IoSlice::new(buf)];
+    let cmsgs = [ControlMessage::ScmRights(&fds)];
+    let cmsgs: &[ControlMessage] = if fds.is_empty() { &[] } else { &cmsgs };
+
+    socket::sendmsg(
+        sock.as_fd().as_raw_fd(),
+        &iov,
+        cmsgs,
+        MsgFlags::MSG_NOSIGNAL,
+        addr,
+    )
+    .op("sendmsg")
+}
+
+/// Receives a message, along with any handles that were sent with it.
+///
+/// Up to `max_fds` handles are accepted, and they're created with the
+/// close-on-exec flag set. Returns the number of bytes of the message
+/// that were received, and the handles.
+///
+/// If the sender passed more handles than `max_fds`, the extra ones are
+/// closed by the kernel, and this fails with `ENOBUFS`.
+pub fn recv_with_fds<S: AsFd>(
+    sock: &S,
+    buf: &mut [u8],
+    max_fds: usize,
+) -> Result<(usize, Vec<OwnedFd>)> {
+    let max_fds = max_fds.clamp(1, MAX_FDS);
+    let space = unsafe { libc::CMSG_SPACE((max_fds * size_of::<RawFd>()) as u32) };
+    let mut cmsg = Vec::with_capacity(space as usize);
+
+    let mut iov = [IoSliceMut::new(buf)];
+    let msg = socket::recvmsg::<()>(
+        sock.as_fd().as_raw_fd(),
+        &mut iov,
+        Some(&mut cmsg),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )
+    .op("recvmsg")?;
+
+    let mut fds = Vec::new();
+    for cmsg in msg.cmsgs() {
+        if let ControlMessageOwned::ScmRights(raw) = cmsg {
+            fds.extend(
+                raw.into_iter()
+                    .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }),
+            );
+        }
+    }
+
+    if msg.flags.contains(MsgFlags::MSG_CTRUNC) {
+        return Err(Error::ENOBUFS);
+    }
+    Ok((msg.bytes, fds))
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(all(test, feature = "pipe"))]
+mod tests {
+    use super::*;
+    use crate::{fd::FdExt, pipe};
+    use nix::sys::socket::{AddressFamily, SockFlag, SockType};
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_pass_fd() {
+        let (a, b) = socket::socketpair(
+            AddressFamily::Unix,
+            SockType::Datagram,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )
+        .unwrap();
+        let (a, b) = unsafe { (OwnedFd::from_raw_fd(a), OwnedFd::from_raw_fd(b)) };
+
+        let (wr, mut rd) = pipe::pipe().unwrap();
+        assert_eq!(5, send_with_fds(&a, b"hello", &[wr.as_fd()]).unwrap());
+        drop(wr);
+
+        let mut buf = [0u8; 16];
+        let (n, fds) = recv_with_fds(&b, &mut buf, 4).unwrap();
+        assert_eq!(b"hello", &buf[..n]);
+        assert_eq!(1, fds.len());
+        assert!(fds[0].is_cloexec().unwrap());
+
+        // The received handle is the write end of the pipe
+        let mut wr = std::fs::File::from(fds.into_iter().next().unwrap());
+        wr.write_all(b"abc").unwrap();
+        drop(wr);
+
+        let mut s = String::new();
+        rd.read_to_string(&mut s).unwrap();
+        assert_eq!("abc", s);
+
+        // A message without any handles
+        send_with_fds(&a, b"bye", &[]).unwrap();
+        let (n, fds) = recv_with_fds(&b, &mut buf, 4).unwrap();
+        assert_eq!(b"bye", &buf[..n]);
+        assert!(fds.is_empty());
+    }
+}
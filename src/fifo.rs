@@ -0,0 +1,345 @@
+// hinix/src/fifo.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Named pipes (FIFOs).
+//!
+//! A FIFO is a pipe that has a name in the filesystem, so that unrelated
+//! processes can open it to communicate. Like a pipe, data written to it
+//! by one process can be read by another, but nothing is stored on disk.
+//!
+//! Note that, by default, opening a FIFO blocks until the other end is
+//! also opened: a reader waits for a writer, and a writer waits for a
+//! reader. A writer that can't wait forever for a reader can use
+//! [`Fifo::open_write_nonblocking()`], which fails right away with
+//! `ENXIO` if there's no reader, or [`Fifo::open_write_timeout()`],
+//! which keeps trying until one appears.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man7/fifo.7.html>
+//!
+
+use crate::{
+    error::ResultExt,
+    fd::{self, FdExt},
+    timeout::{Deadline, Timeout},
+    Error, Result,
+};
+use nix::{
+    errno::Errno,
+    fcntl::{self, OFlag},
+    sys::stat::{self, Mode, SFlag},
+    unistd,
+};
+use std::{
+    io::{self, Read, Write},
+    mem::MaybeUninit,
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+/// The longest wait between tries to open a FIFO for a writer.
+const MAX_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Creates a FIFO at the path, with the permissions in `mode`, as
+/// modified by the process umask.
+///
+/// This succeeds if a FIFO already exists at the path, but fails with
+/// `EEXIST` if something else is there.
+pub fn mkfifo<P: AsRef<Path>>(path: P, mode: Mode) -> Result<()> {
+    let path = path.as_ref();
+    match unistd::mkfifo(path, mode) {
+        Err(Errno::EEXIST) if is_fifo(path) => Ok(()),
+        res => res.op_on("mkfifo", path.display()),
+    }
+}
+
+/// Determines if there is a FIFO at the path.
+pub fn is_fifo<P: AsRef<Path>>(path: P) -> bool {
+    match stat::stat(path.as_ref()) {
+        Ok(st) => SFlag::from_bits_truncate(st.st_mode & SFlag::S_IFMT.bits()) == SFlag::S_IFIFO,
+        Err(_) => false,
+    }
+}
+
+/// An open FIFO (named pipe).
+///
+/// This is opened for reading or writing, but not both.
+#[derive(Debug)]
+pub struct Fifo(OwnedFd);
+
+impl Fifo {
+    /// Opens the FIFO for reading.
+    ///
+    /// This blocks until a writer opens the FIFO.
+    pub fn open_read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with_flags(path, OFlag::O_RDONLY)
+    }
+
+    /// Opens the FIFO for reading, without waiting for a writer.
+    ///
+    /// The handle is left in non-blocking mode, so reads fail with
+    /// `EAGAIN` if a writer is connected but there's no data. A read
+    /// returns zero (EOF) whenever there is no writer.
+    pub fn open_read_nonblocking<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with_flags(path, OFlag::O_RDONLY | OFlag::O_NONBLOCK)
+    }
+
+    /// Opens the FIFO for writing.
+    ///
+    /// This blocks until a reader opens the FIFO.
+    pub fn open_write<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with_flags(path, OFlag::O_WRONLY)
+    }
+
+    /// Opens the FIFO for writing, without waiting for a reader.
+    ///
+    /// This fails with `ENXIO` if no process has the FIFO open for
+    /// reading. Otherwise, the handle is left in non-blocking mode, so
+    /// writes fail with `EAGAIN` when the FIFO is full.
+    pub fn open_write_nonblocking<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with_flags(path, OFlag::O_WRONLY | OFlag::O_NONBLOCK)
+    }
+
+    /// Opens the FIFO for writing, waiting up to the timeout for a
+    /// reader.
+    ///
+    /// The open is retried, with a growing delay, until there's a reader,
+    /// and fails with `ETIMEDOUT` if there isn't one in time. The handle
+    /// is in blocking mode, like with [`open_write()`](Self::open_write).
+    pub fn open_write_timeout<P, T>(path: P, timeout: T) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        T: Into<Timeout>,
+    {
+        let path = path.as_ref();
+        let deadline = Deadline::start(timeout);
+        let mut delay = Duration::from_millis(1);
+
+        loop {
+            match Self::open_write_nonblocking(path) {
+                Ok(fifo) => {
+                    fifo.set_nonblocking(false)?;
+                    return Ok(fifo);
+                }
+                Err(err) if err == Error::ENXIO => (),
+                Err(err) => return Err(err),
+            }
+            match deadline.remaining() {
+                Some(rem) if rem.is_zero() => return Err(Error::ETIMEDOUT),
+                rem => thread::sleep(rem.map_or(delay, |rem| rem.min(delay))),
+            }
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+
+    /// Opens the FIFO with the specified flags.
+    ///
+    /// The O_CLOEXEC flag is always added. This fails with `EINVAL` if
+    /// the path isn't a FIFO.
+    pub fn open_with_flags<P: AsRef<Path>>(path: P, flags: OFlag) -> Result<Self> {
+        let path = path.as_ref();
+        let fd = fcntl::open(path, flags | OFlag::O_CLOEXEC, Mode::empty())
+            .op_on("open", path.display())?;
+        // Checks the file that was actually opened, not the path, in case
+        // it was replaced in the meantime.
+        Self::try_from(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Closes the FIFO, reporting any error.
+    ///
+    /// Dropping the FIFO also closes it, but ignores any error.
+    pub fn close(self) -> Result<()> {
+        fd::close(self)
+    }
+
+    /// Reads data into a buffer that may be uninitialized, so it doesn't
+    /// need to be cleared first.
+    ///
+    /// Returns the part of the buffer that was filled, which is empty if
+    /// there is no writer.
+    pub fn read_uninit<'a>(&mut self, buf: &'a mut [MaybeUninit<u8>]) -> Result<&'a mut [u8]> {
+        fault_point!(FifoRead, fd::read_uninit(self.as_fd(), buf))
+    }
+}
+
+impl Read for Fifo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = fault_point!(FifoRead, unistd::read(self.as_raw_fd(), buf))?;
+        Ok(n)
+    }
+}
+
+impl Write for Fifo {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = fault_point!(FifoWrite, unistd::write(self.as_raw_fd(), buf))?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsFd for Fifo {
+    /// Gets the file handle for the FIFO.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for Fifo {
+    /// Gets the raw file handle for the FIFO.
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl From<Fifo> for OwnedFd {
+    fn from(fifo: Fifo) -> Self {
+        fifo.0
+    }
+}
+
+impl IntoRawFd for Fifo {
+    /// Gives up ownership of the file handle for the FIFO.
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl TryFrom<OwnedFd> for Fifo {
+    type Error = Error;
+
+    /// Takes ownership of an open FIFO, or either end of a pipe.
+    ///
+    /// This fails with `EINVAL` if the handle isn't a FIFO.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        fd::check_file_type(fd.as_fd(), SFlag::S_IFIFO)?;
+        Ok(Self(fd))
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, thread};
+
+    #[test]
+    fn test_mkfifo() {
+        let path = env::temp_dir().join(format!("hinix-mkfifo-{}", unistd::getpid()));
+        let _ = fs::remove_file(&path);
+
+        mkfifo(&path, Mode::from_bits_truncate(0o600)).unwrap();
+        assert!(is_fifo(&path));
+        // Again is OK
+        mkfifo(&path, Mode::from_bits_truncate(0o600)).unwrap();
+
+        fs::remove_file(&path).unwrap();
+        fs::write(&path, b"not a fifo").unwrap();
+        assert_eq!(
+            Error::EEXIST,
+            mkfifo(&path, Mode::from_bits_truncate(0o600)).unwrap_err()
+        );
+        assert_eq!(Error::EINVAL, Fifo::open_read(&path).unwrap_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_fifo() {
+        let path = env::temp_dir().join(format!("hinix-fifo-{}", unistd::getpid()));
+        let _ = fs::remove_file(&path);
+        mkfifo(&path, Mode::from_bits_truncate(0o600)).unwrap();
+
+        // Doesn't wait for a writer
+        let mut rd = Fifo::open_read_nonblocking(&path).unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(0, rd.read(&mut buf).unwrap());
+
+        // The reader is open, so this doesn't block
+        let mut wr = Fifo::open_write(&path).unwrap();
+        assert_eq!(
+            Some(libc::EAGAIN),
+            rd.read(&mut buf).unwrap_err().raw_os_error()
+        );
+
+        thread::spawn(move || {
+            wr.write_all(b"hello").unwrap();
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(5, rd.read(&mut buf).unwrap());
+        assert_eq!(b"hello", &buf[..5]);
+
+        // The writer closed
+        assert_eq!(0, rd.read(&mut buf).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_open_write() {
+        let path = env::temp_dir().join(format!("hinix-fifo-write-{}", unistd::getpid()));
+        let _ = fs::remove_file(&path);
+        mkfifo(&path, Mode::from_bits_truncate(0o600)).unwrap();
+
+        // No reader
+        assert_eq!(
+            Error::ENXIO,
+            Fifo::open_write_nonblocking(&path).unwrap_err()
+        );
+        let timeout = Duration::from_millis(20);
+        assert_eq!(
+            Error::ETIMEDOUT,
+            Fifo::open_write_timeout(&path, timeout).unwrap_err()
+        );
+
+        // A reader shows up while waiting
+        let th = thread::spawn({
+            let path = path.clone();
+            move || {
+                thread::sleep(timeout);
+                Fifo::open_read(path).unwrap()
+            }
+        });
+        let wr = Fifo::open_write_timeout(&path, Duration::from_secs(5)).unwrap();
+        assert!(!wr.is_nonblocking().unwrap());
+        let rd = th.join().unwrap();
+
+        let wr = Fifo::open_write_nonblocking(&path).unwrap();
+        assert!(wr.is_nonblocking().unwrap());
+        drop(rd);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_uninit() {
+        let path = env::temp_dir().join(format!("hinix-fifo-uninit-{}", unistd::getpid()));
+        let _ = fs::remove_file(&path);
+        mkfifo(&path, Mode::from_bits_truncate(0o600)).unwrap();
+
+        let mut rd = Fifo::open_read_nonblocking(&path).unwrap();
+        let mut wr = Fifo::open_write(&path).unwrap();
+        wr.write_all(b"hello").unwrap();
+
+        let mut buf = [MaybeUninit::uninit(); 16];
+        assert_eq!(b"hello", rd.read_uninit(&mut buf).unwrap());
+
+        wr.close().unwrap();
+        assert!(rd.read_uninit(&mut buf).unwrap().is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,911 @@
+// hinix/src/fs.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Linux file system extensions.
+//!
+//! Wrappers for Linux-specific file operations that aren't available
+//! through the Rust standard library.
+//!
+
+use crate::{Error, Result};
+use bitflags::bitflags;
+use nix::{
+    errno::Errno,
+    fcntl::{self, OFlag},
+    sys::{sendfile, stat::Mode},
+    unistd,
+};
+use std::{
+    cmp,
+    ffi::CString,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    mem::{self, size_of},
+    os::{
+        raw::c_int,
+        unix::{
+            ffi::OsStrExt,
+            io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, RawFd},
+        },
+    },
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The largest chunk to request from the kernel in a single call when
+/// copying data. Linux will transfer at most about 2GB at a time anyway.
+const MAX_COPY_CHUNK: u64 = 1 << 30;
+
+/// The size of the user-space buffer when copying falls back to
+/// read/write.
+const COPY_BUF_SIZE: usize = 64 * 1024;
+
+/// The operation to perform on a file with [`allocate()`].
+///
+/// See:
+/// <https://man7.org/linux/man-pages/man2/fallocate.2.html>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocateMode {
+    /// Allocates disk space for the range, extending the size of the file
+    /// if the range goes past the end of it.
+    Allocate,
+    /// Allocates disk space for the range, but without changing the size
+    /// of the file, even if the range goes past the end of it.
+    AllocateKeepSize,
+    /// Deallocates the range, creating a hole in the file. The range
+    /// subsequently reads back as zeros. The size of the file does not
+    /// change.
+    PunchHole,
+    /// Zeros the range, allocating space for it, and extending the size
+    /// of the file if the range goes past the end of it.
+    ZeroRange,
+    /// Removes the range from the file, shifting the data after it down,
+    /// without leaving a hole. The range usually needs to be aligned to
+    /// the file system block size.
+    CollapseRange,
+    /// Inserts a hole of the size of the range at the offset, shifting
+    /// the data after it up. The range usually needs to be aligned to the
+    /// file system block size.
+    InsertRange,
+}
+
+impl AllocateMode {
+    fn to_raw(self) -> c_int {
+        match self {
+            AllocateMode::Allocate => 0,
+            AllocateMode::AllocateKeepSize => libc::FALLOC_FL_KEEP_SIZE,
+            AllocateMode::PunchHole => libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            AllocateMode::ZeroRange => libc::FALLOC_FL_ZERO_RANGE,
+            AllocateMode::CollapseRange => libc::FALLOC_FL_COLLAPSE_RANGE,
+            AllocateMode::InsertRange => libc::FALLOC_FL_INSERT_RANGE,
+        }
+    }
+}
+
+/// Manipulates the disk space allocated for a range of a file.
+///
+/// Preallocating space for a file guarantees that subsequent writes to
+/// the range won't fail for lack of disk space, and typically reduces
+/// fragmentation. Not all file systems support all of the modes; those
+/// that don't fail with `EOPNOTSUPP`.
+///
+/// <https://man7.org/linux/man-pages/man2/fallocate.2.html>
+pub fn allocate<F: AsFd>(fd: &F, offset: u64, len: u64, mode: AllocateMode) -> Result<()> {
+    let offset = to_off_t(offset)?;
+    let len = to_off_t(len)?;
+    let fd = fd.as_fd().as_raw_fd();
+    Errno::result(unsafe { libc::fallocate(fd, mode.to_raw(), offset, len) })?;
+    Ok(())
+}
+
+/// Advice to the kernel about how the application intends to access a
+/// file, given to [`advise()`].
+///
+/// See:
+/// <https://man7.org/linux/man-pages/man2/posix_fadvise.2.html>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advice {
+    /// No particular access pattern. This is the default.
+    Normal,
+    /// The data will be accessed sequentially, so the kernel can read
+    /// ahead more aggressively.
+    Sequential,
+    /// The data will be accessed in random order, so read-ahead should
+    /// be disabled.
+    Random,
+    /// The data will only be accessed once.
+    NoReuse,
+    /// The data will be accessed soon, so the kernel can start reading it
+    /// into the page cache.
+    WillNeed,
+    /// The data won't be accessed again soon, so the kernel can drop it
+    /// from the page cache.
+    DontNeed,
+}
+
+impl Advice {
+    fn to_raw(self) -> c_int {
+        match self {
+            Advice::Normal => libc::POSIX_FADV_NORMAL,
+            Advice::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+            Advice::Random => libc::POSIX_FADV_RANDOM,
+            Advice::NoReuse => libc::POSIX_FADV_NOREUSE,
+            Advice::WillNeed => libc::POSIX_FADV_WILLNEED,
+            Advice::DontNeed => libc::POSIX_FADV_DONTNEED,
+        }
+    }
+}
+
+/// Gives advice to the kernel about how a range of a file will be
+/// accessed.
+///
+/// A `len` of zero means the range extends to the end of the file.
+/// This is only a hint, and doesn't change the semantics of any
+/// subsequent I/O.
+///
+/// <https://man7.org/linux/man-pages/man2/posix_fadvise.2.html>
+pub fn advise<F: AsFd>(fd: &F, offset: u64, len: u64, advice: Advice) -> Result<()> {
+    let offset = to_off_t(offset)?;
+    let len = to_off_t(len)?;
+    let fd = fd.as_fd().as_raw_fd();
+    match unsafe { libc::posix_fadvise(fd, offset, len, advice.to_raw()) } {
+        0 => Ok(()),
+        err => Err(Error::from_i32(err)),
+    }
+}
+
+/// The ways that [`copy_range()`] can move data, from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CopyMethod {
+    CopyFileRange,
+    SendFile,
+    ReadWrite,
+}
+
+/// Copies data from one file to another, from their current offsets.
+///
+/// This copies up to `len` bytes, stopping early if it reaches the end
+/// of the source, and returns the number of bytes actually copied. The
+/// offsets of both files are advanced by that amount. Pass `u64::MAX` as
+/// the length to copy everything up to the end of the source.
+///
+/// This tries to keep the data in the kernel, using copy_file_range(2)
+/// which can do server-side copies on network file systems, and reflinks
+/// on file systems that support them. If that isn't supported for the
+/// files, such as when they're on different file systems on older
+/// kernels, or when the source isn't a regular file, it falls back to
+/// sendfile(2), and then finally to a plain read/write loop through a
+/// user-space buffer.
+///
+/// <https://man7.org/linux/man-pages/man2/copy_file_range.2.html>
+pub fn copy_range<S: AsFd, D: AsFd>(src: &S, dst: &D, len: u64) -> Result<u64> {
+    let src = src.as_fd().as_raw_fd();
+    let dst = dst.as_fd().as_raw_fd();
+
+    let mut method = CopyMethod::CopyFileRange;
+    let mut buf = Vec::new();
+    let mut copied = 0u64;
+
+    while copied < len {
+        let chunk = cmp::min(len - copied, MAX_COPY_CHUNK) as usize;
+
+        let res = match method {
+            CopyMethod::CopyFileRange => fcntl::copy_file_range(src, None, dst, None, chunk),
+            CopyMethod::SendFile => sendfile::sendfile(dst, src, None, chunk),
+            CopyMethod::ReadWrite => {
+                if buf.is_empty() {
+                    buf.resize(COPY_BUF_SIZE, 0u8);
+                }
+                let n = cmp::min(chunk, buf.len());
+                read_write(src, dst, &mut buf[..n])
+            }
+        };
+
+        match res {
+            Ok(0) => break,
+            Ok(n) => copied += n as u64,
+            Err(Errno::EINTR) => (),
+            Err(Errno::EXDEV | Errno::ENOSYS | Errno::EOPNOTSUPP | Errno::EINVAL)
+                if method != CopyMethod::ReadWrite =>
+            {
+                method = match method {
+                    CopyMethod::CopyFileRange => CopyMethod::SendFile,
+                    _ => CopyMethod::ReadWrite,
+                };
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(copied)
+}
+
+/// Reads a buffer's worth of data from the source and writes all of it
+/// to the destination, returning the number of bytes copied.
+fn read_write(src: RawFd, dst: RawFd, buf: &mut [u8]) -> nix::Result<usize> {
+    let n = unistd::read(src, buf)?;
+    let mut off = 0;
+    while off < n {
+        match unistd::write(dst, &buf[off..n]) {
+            Ok(m) => off += m,
+            Err(Errno::EINTR) => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(n)
+}
+
+/// Creates an anonymous temporary file in the specified directory.
+///
+/// The file is created with O_TMPFILE, so it has no name and is not
+/// visible in the directory. If it is closed without being given a name
+/// with [`TempFile::materialize()`], it is simply deleted. This allows
+/// the "write then publish" pattern, where a file is completely written
+/// before it atomically appears in the file system, with no chance of
+/// leaving a partial file behind if the application crashes.
+///
+/// The file is created with read/write permissions for the owner only.
+/// The directory must be on a file system that supports O_TMPFILE,
+/// otherwise this fails with `EOPNOTSUPP`.
+///
+/// <https://man7.org/linux/man-pages/man2/open.2.html>
+pub fn tempfile_in<P: AsRef<Path>>(dir: P) -> Result<TempFile> {
+    tempfile_in_with_mode(dir, Mode::from_bits_truncate(0o600))
+}
+
+/// Creates an anonymous temporary file in the specified directory with
+/// the specified permissions.
+///
+/// See [`tempfile_in()`].
+pub fn tempfile_in_with_mode<P: AsRef<Path>>(dir: P, mode: Mode) -> Result<TempFile> {
+    let flags = OFlag::O_TMPFILE | OFlag::O_RDWR | OFlag::O_CLOEXEC;
+    let fd = fcntl::open(dir.as_ref(), flags, mode)?;
+    Ok(TempFile(unsafe { File::from_raw_fd(fd) }))
+}
+
+/// An anonymous temporary file, created by [`tempfile_in()`].
+#[derive(Debug)]
+pub struct TempFile(File);
+
+impl TempFile {
+    /// Gives the file a name, making it visible in the file system.
+    ///
+    /// The path must be on the same file system as the directory where
+    /// the file was created. This fails with `EEXIST` if the path already
+    /// exists. To atomically replace an existing file, materialize to a
+    /// unique name in the same directory, then rename it over the target.
+    ///
+    /// This tries linkat(2) with AT_EMPTY_PATH, which requires the
+    /// CAP_DAC_READ_SEARCH capability, and if that isn't allowed, falls
+    /// back to linking through the /proc/self/fd entry for the file.
+    pub fn materialize<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path_to_cstring(path.as_ref())?;
+        let empty = CString::default();
+
+        let res = unsafe {
+            libc::linkat(
+                self.as_raw_fd(),
+                empty.as_ptr(),
+                libc::AT_FDCWD,
+                path.as_ptr(),
+                libc::AT_EMPTY_PATH,
+            )
+        };
+
+        match Errno::result(res) {
+            Ok(_) => Ok(()),
+            Err(Errno::ENOENT | Errno::EPERM) => {
+                let proc_path = CString::new(format!("/proc/self/fd/{}", self.as_raw_fd()))
+                    .map_err(|_| Error::EINVAL)?;
+                Errno::result(unsafe {
+                    libc::linkat(
+                        libc::AT_FDCWD,
+                        proc_path.as_ptr(),
+                        libc::AT_FDCWD,
+                        path.as_ptr(),
+                        libc::AT_SYMLINK_FOLLOW,
+                    )
+                })?;
+                Ok(())
+            }
+            Err(err) => Err(Error::new(err, "linkat", path.to_string_lossy())),
+        }
+    }
+
+    /// Gets a reference to the underlying file.
+    pub fn as_file(&self) -> &File {
+        &self.0
+    }
+
+    /// Gets a mutable reference to the underlying file.
+    pub fn as_file_mut(&mut self) -> &mut File {
+        &mut self.0
+    }
+
+    /// Converts the temporary file into the underlying file.
+    pub fn into_file(self) -> File {
+        self.0
+    }
+}
+
+impl Read for TempFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for TempFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Seek for TempFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl AsFd for TempFile {
+    /// Gets the file handle for the temporary file.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for TempFile {
+    /// Gets the raw file handle for the temporary file.
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+bitflags! {
+    /// Flags that restrict how a path is resolved by [`openat2()`].
+    ///
+    /// See:
+    /// <https://man7.org/linux/man-pages/man2/openat2.2.html>
+    pub struct ResolveFlags: u64 {
+        /// Don't allow the resolution to cross any mount points.
+        const NO_XDEV = libc::RESOLVE_NO_XDEV;
+        /// Don't follow any "magic links", like the /proc/[pid]/fd entries.
+        const NO_MAGICLINKS = libc::RESOLVE_NO_MAGICLINKS;
+        /// Don't follow any symbolic links, including magic links.
+        const NO_SYMLINKS = libc::RESOLVE_NO_SYMLINKS;
+        /// Don't allow the resolution to escape the starting directory,
+        /// failing with `EXDEV` on any attempt to do so with "..",
+        /// absolute paths, or symbolic links.
+        const BENEATH = libc::RESOLVE_BENEATH;
+        /// Treat the starting directory as the root of the file system,
+        /// as if the process had done a chroot(2) to it. Absolute paths
+        /// and ".." components are clamped to the directory.
+        const IN_ROOT = libc::RESOLVE_IN_ROOT;
+        /// Only resolve the path from the kernel's lookup cache, failing
+        /// with `EAGAIN` if that isn't possible.
+        const CACHED = libc::RESOLVE_CACHED;
+    }
+}
+
+/// Opens a file relative to a directory, with control over how the path
+/// is resolved.
+///
+/// This is an extension of openat(2) which lets an application safely
+/// open paths from an untrusted source. For example, with
+/// [`ResolveFlags::BENEATH`] the kernel will refuse to let the path
+/// escape from the directory, no matter what combination of "..", and
+/// symbolic links it contains. This is done atomically as part of the
+/// lookup, so there is no race like when checking the path in user
+/// space.
+///
+/// Requires Linux 5.6 or later, otherwise fails with `ENOSYS`.
+///
+/// <https://man7.org/linux/man-pages/man2/openat2.2.html>
+pub fn openat2<D, P>(
+    dir: &D,
+    path: P,
+    flags: OFlag,
+    mode: Mode,
+    resolve: ResolveFlags,
+) -> Result<File>
+where
+    D: AsFd,
+    P: AsRef<Path>,
+{
+    let path = path_to_cstring(path.as_ref())?;
+
+    let mut how: libc::open_how = unsafe { mem::zeroed() };
+    how.flags = (flags | OFlag::O_CLOEXEC).bits() as u64;
+    how.mode = mode.bits() as u64;
+    how.resolve = resolve.bits();
+
+    let fd = Errno::result(unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            dir.as_fd().as_raw_fd(),
+            path.as_ptr(),
+            &how as *const libc::open_how,
+            size_of::<libc::open_how>(),
+        )
+    })?;
+    Ok(unsafe { File::from_raw_fd(fd as RawFd) })
+}
+
+bitflags! {
+    /// The fields requested from, and returned by, [`statx()`].
+    ///
+    /// The kernel may return more fields than requested if they're cheap
+    /// to get, and fewer if the file system doesn't support them.
+    pub struct StatxMask: u32 {
+        /// The file type
+        const TYPE = libc::STATX_TYPE;
+        /// The file permissions
+        const MODE = libc::STATX_MODE;
+        /// The number of hard links
+        const NLINK = libc::STATX_NLINK;
+        /// The owner's user ID
+        const UID = libc::STATX_UID;
+        /// The owner's group ID
+        const GID = libc::STATX_GID;
+        /// The last access time
+        const ATIME = libc::STATX_ATIME;
+        /// The last modification time
+        const MTIME = libc::STATX_MTIME;
+        /// The last status change time
+        const CTIME = libc::STATX_CTIME;
+        /// The inode number
+        const INO = libc::STATX_INO;
+        /// The file size
+        const SIZE = libc::STATX_SIZE;
+        /// The number of blocks allocated
+        const BLOCKS = libc::STATX_BLOCKS;
+        /// All the fields returned by a normal stat(2)
+        const BASIC_STATS = libc::STATX_BASIC_STATS;
+        /// The creation (birth) time
+        const BTIME = libc::STATX_BTIME;
+        /// The ID of the mount containing the file
+        const MNT_ID = libc::STATX_MNT_ID;
+    }
+}
+
+bitflags! {
+    /// The attributes of a file reported by [`statx()`].
+    pub struct StatxAttributes: u64 {
+        /// The file is compressed by the file system
+        const COMPRESSED = libc::STATX_ATTR_COMPRESSED as u64;
+        /// The file can't be modified, deleted, or renamed
+        const IMMUTABLE = libc::STATX_ATTR_IMMUTABLE as u64;
+        /// The file can only be opened in append mode for writing
+        const APPEND = libc::STATX_ATTR_APPEND as u64;
+        /// The file isn't a candidate for backup by dump(8)
+        const NODUMP = libc::STATX_ATTR_NODUMP as u64;
+        /// The file is encrypted by the file system
+        const ENCRYPTED = libc::STATX_ATTR_ENCRYPTED as u64;
+        /// The directory is an automount trigger
+        const AUTOMOUNT = libc::STATX_ATTR_AUTOMOUNT as u64;
+        /// The directory is the root of a mount
+        const MOUNT_ROOT = libc::STATX_ATTR_MOUNT_ROOT as u64;
+        /// The file has fs-verity enabled
+        const VERITY = libc::STATX_ATTR_VERITY as u64;
+        /// The file is in the DAX (cpu direct access) state
+        const DAX = libc::STATX_ATTR_DAX as u64;
+    }
+}
+
+/// Extended file metadata, as returned by [`statx()`].
+///
+/// Fields that weren't returned by the kernel, either because they
+/// weren't requested, or because the file system doesn't support them,
+/// are reported as `None`.
+#[derive(Debug, Clone, Copy)]
+pub struct Statx(libc::statx);
+
+impl Statx {
+    /// Gets the set of fields that the kernel filled in.
+    pub fn mask(&self) -> StatxMask {
+        StatxMask::from_bits_truncate(self.0.stx_mask)
+    }
+
+    fn has(&self, mask: StatxMask) -> bool {
+        self.mask().contains(mask)
+    }
+
+    /// Gets the file type and permissions, as in `st_mode`.
+    pub fn mode(&self) -> Option<u32> {
+        self.has(StatxMask::MODE)
+            .then(|| u32::from(self.0.stx_mode))
+    }
+
+    /// Gets the number of hard links to the file.
+    pub fn nlink(&self) -> Option<u32> {
+        self.has(StatxMask::NLINK).then_some(self.0.stx_nlink)
+    }
+
+    /// Gets the user ID of the owner of the file.
+    pub fn uid(&self) -> Option<u32> {
+        self.has(StatxMask::UID).then_some(self.0.stx_uid)
+    }
+
+    /// Gets the group ID of the owner of the file.
+    pub fn gid(&self) -> Option<u32> {
+        self.has(StatxMask::GID).then_some(self.0.stx_gid)
+    }
+
+    /// Gets the inode number of the file.
+    pub fn ino(&self) -> Option<u64> {
+        self.has(StatxMask::INO).then_some(self.0.stx_ino)
+    }
+
+    /// Gets the size of the file, in bytes.
+    pub fn size(&self) -> Option<u64> {
+        self.has(StatxMask::SIZE).then_some(self.0.stx_size)
+    }
+
+    /// Gets the number of 512-byte blocks allocated to the file.
+    pub fn blocks(&self) -> Option<u64> {
+        self.has(StatxMask::BLOCKS).then_some(self.0.stx_blocks)
+    }
+
+    /// Gets the preferred block size for I/O on the file.
+    pub fn block_size(&self) -> u32 {
+        self.0.stx_blksize
+    }
+
+    /// Gets the time that the file was last accessed.
+    pub fn accessed(&self) -> Option<SystemTime> {
+        self.has(StatxMask::ATIME)
+            .then(|| to_system_time(&self.0.stx_atime))
+    }
+
+    /// Gets the time that the file contents were last modified.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.has(StatxMask::MTIME)
+            .then(|| to_system_time(&self.0.stx_mtime))
+    }
+
+    /// Gets the time that the file status was last changed.
+    pub fn changed(&self) -> Option<SystemTime> {
+        self.has(StatxMask::CTIME)
+            .then(|| to_system_time(&self.0.stx_ctime))
+    }
+
+    /// Gets the time that the file was created.
+    ///
+    /// This is not supported by all file systems.
+    pub fn created(&self) -> Option<SystemTime> {
+        self.has(StatxMask::BTIME)
+            .then(|| to_system_time(&self.0.stx_btime))
+    }
+
+    /// Gets the ID of the mount containing the file.
+    ///
+    /// This matches the first field in /proc/self/mountinfo. It requires
+    /// Linux 5.8 or later.
+    pub fn mount_id(&self) -> Option<u64> {
+        self.has(StatxMask::MNT_ID).then_some(self.0.stx_mnt_id)
+    }
+
+    /// Gets the device containing the file, as (major, minor) numbers.
+    pub fn dev(&self) -> (u32, u32) {
+        (self.0.stx_dev_major, self.0.stx_dev_minor)
+    }
+
+    /// Gets the device that a special file represents, as (major, minor)
+    /// numbers.
+    pub fn rdev(&self) -> (u32, u32) {
+        (self.0.stx_rdev_major, self.0.stx_rdev_minor)
+    }
+
+    /// Gets the attributes that are set on the file.
+    pub fn attributes(&self) -> StatxAttributes {
+        StatxAttributes::from_bits_truncate(self.0.stx_attributes & self.0.stx_attributes_mask)
+    }
+
+    /// Gets the attributes that the file system supports, which are the
+    /// only ones that can be reported by [`attributes()`].
+    ///
+    /// [`attributes()`]: Statx::attributes
+    pub fn supported_attributes(&self) -> StatxAttributes {
+        StatxAttributes::from_bits_truncate(self.0.stx_attributes_mask)
+    }
+}
+
+/// Gets the extended metadata for the file at the path.
+///
+/// The `mask` is the set of fields the application is interested in.
+/// Symbolic links are followed.
+///
+/// See:
+/// <https://man7.org/linux/man-pages/man2/statx.2.html>
+pub fn statx<P: AsRef<Path>>(path: P, mask: StatxMask) -> Result<Statx> {
+    let path = path_to_cstring(path.as_ref())?;
+    do_statx(libc::AT_FDCWD, &path, 0, mask)
+}
+
+/// Gets the extended metadata for an open file.
+///
+/// The `mask` is the set of fields the application is interested in.
+pub fn fstatx<F: AsFd>(fd: &F, mask: StatxMask) -> Result<Statx> {
+    do_statx(
+        fd.as_fd().as_raw_fd(),
+        &CString::default(),
+        libc::AT_EMPTY_PATH,
+        mask,
+    )
+}
+
+fn do_statx(dirfd: RawFd, path: &CString, flags: c_int, mask: StatxMask) -> Result<Statx> {
+    let mut stx: libc::statx = unsafe { mem::zeroed() };
+    Errno::result(unsafe { libc::statx(dirfd, path.as_ptr(), flags, mask.bits(), &mut stx) })?;
+    Ok(Statx(stx))
+}
+
+/// Converts a statx timestamp to a system time.
+fn to_system_time(ts: &libc::statx_timestamp) -> SystemTime {
+    let nsec = Duration::from_nanos(u64::from(ts.tv_nsec));
+    if ts.tv_sec >= 0 {
+        UNIX_EPOCH + Duration::from_secs(ts.tv_sec as u64) + nsec
+    }
+    else {
+        UNIX_EPOCH - Duration::from_secs(ts.tv_sec.unsigned_abs()) + nsec
+    }
+}
+
+/// Converts a path to a C string, failing with `EINVAL` if it contains
+/// a NUL byte.
+fn path_to_cstring(path: &Path) -> Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| Error::EINVAL)
+}
+
+/// Converts a file offset or length to the system type, failing with
+/// `EINVAL` if it's out of range.
+fn to_off_t(n: u64) -> Result<libc::off_t> {
+    libc::off_t::try_from(n).map_err(|_| Error::EINVAL)
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::unistd;
+    use std::{
+        env,
+        fs::{self, OpenOptions},
+        os::unix::fs::MetadataExt,
+        path::PathBuf,
+    };
+
+    // Each test gets its own file, since tests may run in parallel.
+    fn test_file(name: &str) -> (PathBuf, File) {
+        let path = env::temp_dir().join(format!("hinix-fs-{}-{}", name, unistd::getpid()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        (path, file)
+    }
+
+    #[test]
+    fn test_allocate() {
+        let (path, file) = test_file("allocate");
+
+        allocate(&file, 0, 8192, AllocateMode::Allocate).unwrap();
+        assert_eq!(8192, file.metadata().unwrap().len());
+
+        allocate(&file, 8192, 8192, AllocateMode::AllocateKeepSize).unwrap();
+        assert_eq!(8192, file.metadata().unwrap().len());
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_punch_hole() {
+        let (path, mut file) = test_file("punch");
+        file.write_all(&[0xAAu8; 16384]).unwrap();
+
+        match allocate(&file, 4096, 4096, AllocateMode::PunchHole) {
+            Ok(()) => {
+                let mut buf = vec![0u8; 16384];
+                file.seek(SeekFrom::Start(0)).unwrap();
+                file.read_exact(&mut buf).unwrap();
+                assert!(buf[..4096].iter().all(|&b| b == 0xAA));
+                assert!(buf[4096..8192].iter().all(|&b| b == 0));
+                assert!(buf[8192..].iter().all(|&b| b == 0xAA));
+                assert_eq!(16384, file.metadata().unwrap().len());
+            }
+            // Not every file system supports holes
+            Err(err) => assert_eq!(Error::EOPNOTSUPP, err),
+        }
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_copy_range() {
+        let (src_path, mut src) = test_file("copy-src");
+        let (dst_path, mut dst) = test_file("copy-dst");
+
+        let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        src.write_all(&data).unwrap();
+        src.seek(SeekFrom::Start(0)).unwrap();
+
+        assert_eq!(1000, copy_range(&src, &dst, 1000).unwrap());
+        assert_eq!(99_000, copy_range(&src, &dst, u64::MAX).unwrap());
+        assert_eq!(0, copy_range(&src, &dst, u64::MAX).unwrap());
+
+        let mut buf = Vec::new();
+        dst.seek(SeekFrom::Start(0)).unwrap();
+        dst.read_to_end(&mut buf).unwrap();
+        assert_eq!(data, buf);
+
+        let _ = fs::remove_file(src_path);
+        let _ = fs::remove_file(dst_path);
+    }
+
+    #[cfg(feature = "pipe")]
+    #[test]
+    fn test_copy_range_from_pipe() {
+        // The kernel can't copy from a pipe, so this uses the fallback
+        let (path, mut file) = test_file("copy-pipe");
+        let (mut wr, rd) = crate::pipe::pipe().unwrap();
+
+        wr.write_all(b"Hello, pipe!").unwrap();
+        drop(wr);
+
+        assert_eq!(12, copy_range(&rd, &file, u64::MAX).unwrap());
+
+        let mut s = String::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_string(&mut s).unwrap();
+        assert_eq!("Hello, pipe!", s);
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_tempfile() {
+        let dir = env::temp_dir();
+        let path = dir.join(format!("hinix-fs-tmpfile-{}", unistd::getpid()));
+        let _ = fs::remove_file(&path);
+
+        let mut tmp = match tempfile_in(&dir) {
+            Ok(tmp) => tmp,
+            // Not every file system supports O_TMPFILE
+            Err(err) if err == Error::EOPNOTSUPP => return,
+            Err(err) => panic!("{}", err),
+        };
+        tmp.write_all(b"published").unwrap();
+        assert!(!path.exists());
+
+        tmp.materialize(&path).unwrap();
+        assert_eq!("published", fs::read_to_string(&path).unwrap());
+
+        // Can't materialize over an existing file
+        assert_eq!(Error::EEXIST, tmp.materialize(&path).unwrap_err());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_openat2() {
+        use std::os::unix::fs::symlink;
+
+        let base = env::temp_dir().join(format!("hinix-fs-openat2-{}", unistd::getpid()));
+        let root = base.join("root");
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.txt"), "inside").unwrap();
+        fs::write(base.join("b.txt"), "outside").unwrap();
+        symlink("../b.txt", root.join("escape")).unwrap();
+        symlink("a.txt", root.join("link")).unwrap();
+
+        let dir = File::open(&root).unwrap();
+        let open = |path: &str, resolve| {
+            openat2(&dir, path, OFlag::O_RDONLY, Mode::empty(), resolve).map(|mut f| {
+                let mut s = String::new();
+                f.read_to_string(&mut s).unwrap();
+                s
+            })
+        };
+
+        match open("a.txt", ResolveFlags::empty()) {
+            Ok(s) => assert_eq!("inside", s),
+            // Old kernel
+            Err(err) if err == Error::ENOSYS => return,
+            Err(err) => panic!("{}", err),
+        }
+
+        assert_eq!("outside", open("../b.txt", ResolveFlags::empty()).unwrap());
+        assert_eq!(
+            Error::EXDEV,
+            open("../b.txt", ResolveFlags::BENEATH).unwrap_err()
+        );
+        assert_eq!(
+            Error::EXDEV,
+            open("escape", ResolveFlags::BENEATH).unwrap_err()
+        );
+        assert_eq!(
+            "inside",
+            open("sub/../link", ResolveFlags::BENEATH).unwrap()
+        );
+        assert_eq!(
+            Error::ELOOP,
+            open("link", ResolveFlags::NO_SYMLINKS).unwrap_err()
+        );
+
+        // In root, absolute paths are relative to the directory
+        assert_eq!("inside", open("/a.txt", ResolveFlags::IN_ROOT).unwrap());
+        assert_eq!(
+            "inside",
+            open("/../../a.txt", ResolveFlags::IN_ROOT).unwrap()
+        );
+
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn test_statx() {
+        let (path, mut file) = test_file("statx");
+        file.write_all(b"hello").unwrap();
+
+        let stx = statx(
+            &path,
+            StatxMask::BASIC_STATS | StatxMask::BTIME | StatxMask::MNT_ID,
+        )
+        .unwrap();
+        let meta = file.metadata().unwrap();
+
+        assert_eq!(Some(5), stx.size());
+        assert_eq!(Some(meta.ino()), stx.ino());
+        assert_eq!(Some(meta.mode()), stx.mode());
+        assert_eq!(Some(meta.uid()), stx.uid());
+        assert_eq!(Some(1), stx.nlink());
+        assert_eq!(meta.modified().ok(), stx.modified());
+        assert!(!stx.attributes().contains(StatxAttributes::IMMUTABLE));
+
+        // Not every file system has a birth time
+        if let Some(btime) = stx.created() {
+            assert!(btime <= stx.modified().unwrap());
+        }
+
+        let fstx = fstatx(&file, StatxMask::INO | StatxMask::SIZE).unwrap();
+        assert_eq!(stx.ino(), fstx.ino());
+        assert_eq!(stx.size(), fstx.size());
+
+        let _ = fs::remove_file(&path);
+        assert_eq!(
+            Error::ENOENT,
+            statx(&path, StatxMask::BASIC_STATS).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_advise() {
+        let (path, file) = test_file("advise");
+        advise(&file, 0, 0, Advice::Sequential).unwrap();
+        advise(&file, 0, 4096, Advice::WillNeed).unwrap();
+        advise(&file, 0, 0, Advice::DontNeed).unwrap();
+
+        assert_eq!(
+            Error::EINVAL,
+            advise(&file, u64::MAX, 0, Advice::Normal).unwrap_err()
+        );
+        let _ = fs::remove_file(path);
+    }
+}
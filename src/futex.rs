@@ -0,0 +1,883 @@
+// hinix/src/futex.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Linux fast user-space mutexes (futexes).
+//!
+//! A futex is a 32-bit word in memory that threads or processes can
+//! block on, waiting for another to change its value and wake them up.
+//! It's the primitive underneath most of the synchronization objects on
+//! Linux, and is what's needed to build custom ones, particularly ones
+//! that live in memory shared between processes.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man2/futex.2.html>
+//!
+
+use crate::{
+    clock::{self, ClockId},
+    error::ResultExt,
+    timeout::{Deadline, Timeout},
+    Error, Result,
+};
+use nix::{
+    errno::Errno,
+    sys::signal,
+    unistd::{self, Pid},
+};
+use std::{
+    cell::UnsafeCell,
+    fs,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    os::raw::c_int,
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+    time::Duration,
+};
+
+/// A bitset that matches any waiter, for use with the bitset
+/// operations.
+pub const BITSET_MATCH_ANY: u32 = libc::FUTEX_BITSET_MATCH_ANY as u32;
+
+/// A futex word, and the operations on it.
+///
+/// This borrows an atomic integer which is used as the futex. The
+/// application defines what the values mean, and uses the atomic
+/// operations to change them. The futex operations only provide the
+/// means to sleep until the value changes.
+#[derive(Debug, Clone, Copy)]
+pub struct Futex<'a> {
+    /// The futex word
+    word: &'a AtomicU32,
+    /// Extra flags for each operation (i.e. FUTEX_PRIVATE_FLAG)
+    flags: c_int,
+}
+
+impl<'a> Futex<'a> {
+    /// Creates a futex that can be shared between processes.
+    ///
+    /// The word would typically be in a shared memory mapping. The kernel
+    /// identifies the futex by the underlying memory, so processes can
+    /// map it at different addresses.
+    pub fn shared(word: &'a AtomicU32) -> Self {
+        Self { word, flags: 0 }
+    }
+
+    /// Creates a futex that is only used by the threads of this process.
+    ///
+    /// This lets the kernel take some shortcuts, so is a little faster
+    /// than a shared one. But it won't work if the word is in memory that
+    /// is shared with other processes.
+    pub fn private(word: &'a AtomicU32) -> Self {
+        Self {
+            word,
+            flags: libc::FUTEX_PRIVATE_FLAG,
+        }
+    }
+
+    /// Gets the futex word.
+    pub fn word(&self) -> &'a AtomicU32 {
+        self.word
+    }
+
+    /// Blocks until woken, as long as the word has the `expected` value.
+    ///
+    /// The check and the sleep happen atomically, so a wake can't be lost
+    /// between them. This fails immediately with `EAGAIN` if the value
+    /// isn't the one expected, and with `EINTR` if a signal arrives.
+    ///
+    /// Note that wakeups can be spurious, so the caller should always
+    /// re-check the value on return.
+    pub fn wait(&self, expected: u32) -> Result<()> {
+        self.futex(libc::FUTEX_WAIT, expected, ptr::null(), 0)
+            .map(drop)
+    }
+
+    /// Blocks until woken or the timeout expires, as long as the word has
+    /// the `expected` value.
+    ///
+    /// This fails with `ETIMEDOUT` if the timeout expires. Otherwise it
+    /// behaves like [`wait()`](Futex::wait), except that a wait that's
+    /// interrupted by a signal is restarted for the time remaining.
+    pub fn wait_timeout<T: Into<Timeout>>(&self, expected: u32, timeout: T) -> Result<()> {
+        let deadline = Deadline::start(timeout);
+        loop {
+            let ts = deadline.remaining().map(clock::to_timespec).transpose()?;
+            let ts = ts.as_ref().map_or(ptr::null(), |ts| ts as *const _);
+
+            match self.futex(libc::FUTEX_WAIT, expected, ts, 0) {
+                Err(err) if err == Error::EINTR => continue,
+                res => return res.map(drop),
+            }
+        }
+    }
+
+    /// Blocks until woken by a wake with a bitset that overlaps with
+    /// `bitset`, as long as the word has the `expected` value.
+    ///
+    /// This fails with `ETIMEDOUT` if the timeout expires. A wait that's
+    /// interrupted by a signal is restarted for the time remaining. The
+    /// bitset can't be zero.
+    pub fn wait_bitset<T: Into<Timeout>>(
+        &self,
+        expected: u32,
+        bitset: u32,
+        timeout: T,
+    ) -> Result<()> {
+        let deadline = Deadline::start(timeout);
+        loop {
+            // The bitset wait takes an absolute time on the monotonic clock.
+            let ts = deadline.on_clock(ClockId::CLOCK_MONOTONIC)?;
+            let ts = ts.as_ref().map_or(ptr::null(), |ts| ts as *const _);
+
+            match self.futex(libc::FUTEX_WAIT_BITSET, expected, ts, bitset) {
+                Err(err) if err == Error::EINTR => continue,
+                res => return res.map(drop),
+            }
+        }
+    }
+
+    /// Wakes up to `count` of the waiters on the futex.
+    ///
+    /// Returns the number of waiters that were woken.
+    pub fn wake(&self, count: u32) -> Result<usize> {
+        self.futex(libc::FUTEX_WAKE, count, ptr::null(), 0)
+    }
+
+    /// Wakes all of the waiters on the futex.
+    ///
+    /// Returns the number of waiters that were woken.
+    pub fn wake_all(&self) -> Result<usize> {
+        self.wake(i32::MAX as u32)
+    }
+
+    /// Wakes up to `count` of the waiters on the futex that are waiting
+    /// with a bitset that overlaps `bitset`.
+    ///
+    /// Returns the number of waiters that were woken.
+    pub fn wake_bitset(&self, count: u32, bitset: u32) -> Result<usize> {
+        self.futex(libc::FUTEX_WAKE_BITSET, count, ptr::null(), bitset)
+    }
+
+    /// Makes the futex system call.
+    fn futex(
+        &self,
+        op: c_int,
+        val: u32,
+        timeout: *const libc::timespec,
+        val3: u32,
+    ) -> Result<usize> {
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                self.word as *const AtomicU32 as *const u32,
+                op | self.flags,
+                val,
+                timeout,
+                ptr::null::<u32>(),
+                val3,
+            )
+        };
+        Errno::result(ret).map(|n| n as usize).op("futex")
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+// The layout of the lock words follows the kernel's robust futex format,
+// with the thread ID of the owner in the low bits.
+
+/// Set when there may be threads waiting on the lock
+const WAITERS: u32 = 0x8000_0000;
+
+/// Set when the RwLock is held by a writer
+const WRITE_LOCKED: u32 = 0x4000_0000;
+
+/// The bits holding the owner's thread ID, or the count of readers
+const TID_MASK: u32 = 0x3fff_ffff;
+
+/// How long a waiter sleeps before checking whether the owner of a lock
+/// has died.
+const OWNER_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Gets the thread ID of the caller, as stored in a lock word.
+fn current_tid() -> u32 {
+    unistd::gettid().as_raw() as u32 & TID_MASK
+}
+
+/// Determines if the thread that owns a lock is still alive.
+///
+/// A thread that exits is removed immediately, but a process that exits
+/// lingers as a zombie until it's reaped, so we check for that as well.
+fn owner_alive(tid: u32) -> bool {
+    let pid = Pid::from_raw(tid as i32);
+    match signal::kill(pid, None) {
+        Err(Errno::ESRCH) => false,
+        _ => match fs::read_to_string(format!("/proc/{}/stat", tid)) {
+            Ok(stat) => !matches!(
+                stat.rsplit_once(')')
+                    .and_then(|(_, s)| s.trim_start().chars().next()),
+                Some('Z') | Some('X')
+            ),
+            Err(_) => true,
+        },
+    }
+}
+
+/// A mutual exclusion lock built on a futex that can live in memory
+/// shared between processes.
+///
+/// This works like a std `Mutex`, but is `repr(C)` and has no pointers or
+/// process-local state, so it can be placed in a shared memory mapping
+/// and used by any process that maps it. Memory that is all zeros is an
+/// unlocked mutex, so a mutex can be created in a new, zero-filled
+/// mapping simply by casting a pointer to it.
+///
+/// The lock word holds the thread ID of the owner. If a waiter finds
+/// that the owner has died while holding the lock, like a process that
+/// crashed, it takes over the lock, and reports it through
+/// [`MutexGuard::owner_died()`], so that the application can check the
+/// consistency of the protected data. This is the same idea as a robust
+/// pthread mutex, but it's detected by the waiters rather than the
+/// kernel, since the per-thread robust list belongs to the C library.
+/// A dead process is detected as soon as it exits, even while it's a
+/// zombie waiting to be reaped by its parent. Waiters check at intervals,
+/// so it can take a moment for one to notice.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Mutex<T> {
+    /// The futex word
+    state: AtomicU32,
+    /// The protected data
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    /// Creates a new, unlocked, mutex holding the value.
+    pub const fn new(val: T) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    /// Acquires the lock, blocking until it's available.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        let tid = current_tid();
+        let owner_died =
+            match self
+                .state
+                .compare_exchange(0, tid, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => false,
+                Err(_) => self.lock_contended(tid),
+            };
+        MutexGuard::new(self, owner_died)
+    }
+
+    /// Attempts to acquire the lock without blocking.
+    ///
+    /// Returns `None` if it's held by a live thread.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let tid = current_tid();
+        let v = self.state.load(Ordering::Relaxed);
+        let owner = v & TID_MASK;
+
+        if owner != 0 && owner_alive(owner) {
+            return None;
+        }
+        self.state
+            .compare_exchange(v, tid | (v & WAITERS), Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| MutexGuard::new(self, owner != 0))
+    }
+
+    /// The slow path for the lock, when there's contention.
+    /// Returns whether the lock was taken over from a dead owner.
+    fn lock_contended(&self, tid: u32) -> bool {
+        let futex = Futex::shared(&self.state);
+        let mut check_owner = false;
+
+        loop {
+            let v = self.state.load(Ordering::Relaxed);
+            let owner = v & TID_MASK;
+
+            // Whenever we take the lock from here, we set the waiters flag,
+            // since we can't tell if anyone else is still waiting.
+            if owner == 0 || (check_owner && !owner_alive(owner)) {
+                if self
+                    .state
+                    .compare_exchange(v, tid | WAITERS, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    return owner != 0;
+                }
+                continue;
+            }
+
+            if v & WAITERS == 0
+                && self
+                    .state
+                    .compare_exchange(v, v | WAITERS, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_err()
+            {
+                continue;
+            }
+            check_owner =
+                futex.wait_timeout(v | WAITERS, OWNER_CHECK_INTERVAL) == Err(Error::ETIMEDOUT);
+        }
+    }
+
+    fn unlock(&self) {
+        if self.state.swap(0, Ordering::Release) & WAITERS != 0 {
+            let _ = Futex::shared(&self.state).wake(1);
+        }
+    }
+
+    /// Gets a mutable reference to the data.
+    ///
+    /// No locking is needed, since the borrow guarantees that no one else
+    /// has access.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Consumes the mutex, returning the data.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+/// A guard that holds a [`Mutex`] locked, and unlocks it when dropped.
+#[derive(Debug)]
+pub struct MutexGuard<'a, T> {
+    /// The locked mutex
+    mutex: &'a Mutex<T>,
+    /// Whether the lock was taken over from a dead owner
+    owner_died: bool,
+    /// The guard must be released by the thread that acquired it.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T> MutexGuard<'a, T> {
+    fn new(mutex: &'a Mutex<T>, owner_died: bool) -> Self {
+        Self {
+            mutex,
+            owner_died,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Determines if the previous owner of the lock died while holding
+    /// it, in which case the protected data may be in an inconsistent
+    /// state.
+    pub fn owner_died(&self) -> bool {
+        self.owner_died
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// A reader-writer lock built on a futex that can live in memory shared
+/// between processes.
+///
+/// Like the [`Mutex`], this is `repr(C)`, and memory that is all zeros
+/// is an unlocked lock. The lock allows any number of readers, or a
+/// single writer, at one time. It makes no attempt at fairness, so a
+/// steady stream of readers can starve a writer.
+///
+/// A writer that dies while holding the lock is detected and the lock
+/// taken over, as with the mutex. The lock doesn't track individual
+/// readers, though, so a reader that dies holding the lock will block
+/// writers forever.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct RwLock<T> {
+    /// The futex word. This holds the number of readers, or the thread
+    /// ID of the writer when WRITE_LOCKED is set.
+    state: AtomicU32,
+    /// The protected data
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    /// Creates a new, unlocked, reader-writer lock holding the value.
+    pub const fn new(val: T) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    /// Acquires the lock for shared, read, access, blocking until it's
+    /// available.
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        let futex = Futex::shared(&self.state);
+        let mut check_owner = false;
+
+        loop {
+            let v = self.state.load(Ordering::Relaxed);
+
+            if v & WRITE_LOCKED == 0 {
+                if v & TID_MASK == TID_MASK {
+                    // Too many readers. Wait for some to leave.
+                    thread::yield_now();
+                }
+                else if self
+                    .state
+                    .compare_exchange_weak(v, v + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    return RwLockReadGuard::new(self, false);
+                }
+                continue;
+            }
+
+            if check_owner && !owner_alive(v & TID_MASK) {
+                if self
+                    .state
+                    .compare_exchange(v, 1 | WAITERS, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    return RwLockReadGuard::new(self, true);
+                }
+                continue;
+            }
+            check_owner = self.wait_on(&futex, v);
+        }
+    }
+
+    /// Attempts to acquire the lock for reading without blocking.
+    ///
+    /// Returns `None` if it's held by a writer.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let v = self.state.load(Ordering::Relaxed);
+        if v & WRITE_LOCKED != 0 || v & TID_MASK == TID_MASK {
+            return None;
+        }
+        self.state
+            .compare_exchange(v, v + 1, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwLockReadGuard::new(self, false))
+    }
+
+    /// Acquires the lock for exclusive, write, access, blocking until
+    /// it's available.
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let futex = Futex::shared(&self.state);
+        let locked = WRITE_LOCKED | current_tid();
+        let mut check_owner = false;
+
+        loop {
+            let v = self.state.load(Ordering::Relaxed);
+            let owner_died = check_owner && v & WRITE_LOCKED != 0 && !owner_alive(v & TID_MASK);
+
+            if v & (WRITE_LOCKED | TID_MASK) == 0 || owner_died {
+                if self
+                    .state
+                    .compare_exchange(
+                        v,
+                        locked | (v & WAITERS),
+                        Ordering::Acquire,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+                {
+                    return RwLockWriteGuard::new(self, owner_died);
+                }
+                continue;
+            }
+            check_owner = self.wait_on(&futex, v);
+        }
+    }
+
+    /// Attempts to acquire the lock for writing without blocking.
+    ///
+    /// Returns `None` if it's held by anyone else.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let v = self.state.load(Ordering::Relaxed);
+        if v & (WRITE_LOCKED | TID_MASK) != 0 {
+            return None;
+        }
+        self.state
+            .compare_exchange(
+                v,
+                v | WRITE_LOCKED | current_tid(),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .ok()
+            .map(|_| RwLockWriteGuard::new(self, false))
+    }
+
+    /// Sets the waiters flag and sleeps until the lock word changes.
+    /// Returns whether the wait timed out, and thus whether it's time to
+    /// check on the owner.
+    fn wait_on(&self, futex: &Futex, v: u32) -> bool {
+        (v & WAITERS != 0
+            || self
+                .state
+                .compare_exchange(v, v | WAITERS, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok())
+            && futex.wait_timeout(v | WAITERS, OWNER_CHECK_INTERVAL) == Err(Error::ETIMEDOUT)
+    }
+
+    fn read_unlock(&self) {
+        let v = self.state.fetch_sub(1, Ordering::Release);
+        if v & TID_MASK == 1 && v & WAITERS != 0 {
+            self.state.fetch_and(!WAITERS, Ordering::Relaxed);
+            let _ = Futex::shared(&self.state).wake_all();
+        }
+    }
+
+    fn write_unlock(&self) {
+        if self.state.swap(0, Ordering::Release) & WAITERS != 0 {
+            let _ = Futex::shared(&self.state).wake_all();
+        }
+    }
+
+    /// Gets a mutable reference to the data.
+    ///
+    /// No locking is needed, since the borrow guarantees that no one else
+    /// has access.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Consumes the lock, returning the data.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+/// A guard that holds an [`RwLock`] for reading, and releases it when
+/// dropped.
+#[derive(Debug)]
+pub struct RwLockReadGuard<'a, T> {
+    /// The locked lock
+    lock: &'a RwLock<T>,
+    /// Whether the lock was taken over from a dead writer
+    owner_died: bool,
+    /// The guard must be released by the thread that acquired it.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T> RwLockReadGuard<'a, T> {
+    fn new(lock: &'a RwLock<T>, owner_died: bool) -> Self {
+        Self {
+            lock,
+            owner_died,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Determines if a writer died while holding the lock, in which case
+    /// the protected data may be in an inconsistent state.
+    pub fn owner_died(&self) -> bool {
+        self.owner_died
+    }
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+/// A guard that holds an [`RwLock`] for writing, and releases it when
+/// dropped.
+#[derive(Debug)]
+pub struct RwLockWriteGuard<'a, T> {
+    /// The locked lock
+    lock: &'a RwLock<T>,
+    /// Whether the lock was taken over from a dead writer
+    owner_died: bool,
+    /// The guard must be released by the thread that acquired it.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T> RwLockWriteGuard<'a, T> {
+    fn new(lock: &'a RwLock<T>, owner_died: bool) -> Self {
+        Self {
+            lock,
+            owner_died,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Determines if the previous writer died while holding the lock, in
+    /// which case the protected data may be in an inconsistent state.
+    pub fn owner_died(&self) -> bool {
+        self.owner_died
+    }
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(all(test, feature = "mmap"))]
+mod tests {
+    use super::*;
+    use crate::{mmap::MmapMut, test_util::in_child, Error};
+    use nix::{sys::wait, unistd::ForkResult};
+    use std::{
+        mem::{self, size_of},
+        process,
+        sync::Arc,
+    };
+
+    // Gets a reference to a lock in a new shared, anonymous, mapping.
+    // The zero-filled memory is an unlocked lock.
+    fn shared_lock<L>(map: &mut MmapMut) -> &L {
+        assert!(map.len() >= size_of::<L>());
+        unsafe { &*(map.as_mut_ptr() as *const L) }
+    }
+
+    // Keeps waking the futex until a waiter is actually woken.
+    fn wake_one(futex: &Futex, bitset: u32) {
+        while futex.wake_bitset(1, bitset).unwrap() == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_no_wait() {
+        let word = AtomicU32::new(1);
+        let futex = Futex::private(&word);
+
+        assert_eq!(Error::EAGAIN, futex.wait(0).unwrap_err());
+        assert_eq!(0, futex.wake(1).unwrap());
+        assert_eq!(
+            Error::ETIMEDOUT,
+            futex
+                .wait_timeout(1, Duration::from_millis(10))
+                .unwrap_err()
+        );
+        assert_eq!(
+            Error::ETIMEDOUT,
+            futex
+                .wait_bitset(1, BITSET_MATCH_ANY, Some(Duration::from_millis(10)))
+                .unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_wait_wake() {
+        let word = Arc::new(AtomicU32::new(0));
+
+        let thr_word = Arc::clone(&word);
+        let thr = thread::spawn(move || {
+            let futex = Futex::private(&thr_word);
+            while thr_word.load(Ordering::Acquire) == 0 {
+                let _ = futex.wait(0);
+            }
+        });
+
+        let futex = Futex::private(&word);
+        word.store(1, Ordering::Release);
+        futex.wake_all().unwrap();
+        thr.join().unwrap();
+    }
+
+    #[test]
+    fn test_bitset() {
+        let word = Arc::new(AtomicU32::new(0));
+
+        let thr_word = Arc::clone(&word);
+        let thr = thread::spawn(move || {
+            Futex::shared(&thr_word).wait_bitset(0, 0b01, None).unwrap();
+        });
+
+        let futex = Futex::shared(&word);
+        // A wake for a different bit doesn't touch the waiter
+        assert_eq!(0, futex.wake_bitset(1, 0b10).unwrap());
+        wake_one(&futex, 0b01);
+        thr.join().unwrap();
+    }
+
+    #[test]
+    fn test_mutex() {
+        let mutex = Arc::new(Mutex::new(0u64));
+
+        let thrs: Vec<_> = (0..4)
+            .map(|_| {
+                let mutex = Arc::clone(&mutex);
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        *mutex.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for thr in thrs {
+            thr.join().unwrap();
+        }
+
+        let guard = mutex.lock();
+        assert_eq!(4000, *guard);
+        assert!(!guard.owner_died());
+        assert!(mutex.try_lock().is_none());
+        drop(guard);
+        assert!(mutex.try_lock().is_some());
+    }
+
+    #[test]
+    fn test_mutex_shared() {
+        let mut map = MmapMut::anonymous_shared(4096).unwrap();
+        let mutex: &Mutex<u64> = shared_lock(&mut map);
+
+        let guard = mutex.lock();
+        match unsafe { unistd::fork() }.unwrap() {
+            ForkResult::Child => {
+                // Blocks until the parent unlocks
+                *mutex.lock() += 1;
+                process::exit(0);
+            }
+            ForkResult::Parent { child } => {
+                thread::sleep(Duration::from_millis(10));
+                drop(guard);
+                wait::waitpid(child, None).unwrap();
+                assert_eq!(1, *mutex.lock());
+            }
+        }
+    }
+
+    #[test]
+    fn test_mutex_owner_died() {
+        let mut map = MmapMut::anonymous_shared(4096).unwrap();
+        let mutex: &Mutex<u64> = shared_lock(&mut map);
+
+        in_child(|| mem::forget(mutex.lock()));
+
+        let guard = mutex.lock();
+        assert!(guard.owner_died());
+        drop(guard);
+        assert!(!mutex.lock().owner_died());
+    }
+
+    #[test]
+    fn test_rwlock() {
+        let lock = RwLock::new(0u64);
+
+        let rd1 = lock.read();
+        let rd2 = lock.try_read().unwrap();
+        assert!(lock.try_write().is_none());
+        assert_eq!(0, *rd1 + *rd2);
+        drop(rd1);
+        drop(rd2);
+
+        let mut wr = lock.try_write().unwrap();
+        *wr = 42;
+        assert!(lock.try_read().is_none());
+        assert!(lock.try_write().is_none());
+        drop(wr);
+
+        assert_eq!(42, *lock.read());
+    }
+
+    #[test]
+    fn test_rwlock_threads() {
+        let lock = Arc::new(RwLock::new(0u64));
+
+        let thrs: Vec<_> = (0..4)
+            .map(|i| {
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || {
+                    for _ in 0..500 {
+                        if i % 2 == 0 {
+                            *lock.write() += 1;
+                        }
+                        else {
+                            let _ = *lock.read();
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for thr in thrs {
+            thr.join().unwrap();
+        }
+        assert_eq!(1000, *lock.read());
+    }
+
+    #[test]
+    fn test_rwlock_shared() {
+        let mut map = MmapMut::anonymous_shared(4096).unwrap();
+        let lock: &RwLock<u64> = shared_lock(&mut map);
+
+        in_child(|| *lock.write() = 7);
+        assert_eq!(7, *lock.read());
+
+        // A dead writer is detected
+        in_child(|| mem::forget(lock.write()));
+        let guard = lock.read();
+        assert!(guard.owner_died());
+        assert_eq!(7, *guard);
+    }
+}
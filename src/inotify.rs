@@ -0,0 +1,335 @@
+// hinix/src/inotify.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Filesystem event notification with inotify.
+//!
+//! This watches files and directories for changes, like files being
+//! created, modified, or deleted. The events are reported with the full
+//! path of the file that changed.
+//!
+//! A directory can be watched recursively, in which case watches are
+//! added for all its subdirectories, and for any new ones as they are
+//! created. Note that there's an inherent race when a new directory is
+//! created: anything that happens in it before the watch is added is
+//! missed.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man7/inotify.7.html>
+//!
+
+use crate::{error::ResultExt, fd, Error, Result};
+use nix::sys::inotify::{self, InitFlags, WatchDescriptor};
+use std::{
+    collections::HashMap,
+    fs,
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    path::{Path, PathBuf},
+};
+
+/// The events to watch for, and that are reported.
+pub use nix::sys::inotify::AddWatchFlags as WatchMask;
+
+/// The events needed to follow new directories in a recursive watch.
+const RECURSE_MASK: WatchMask =
+    WatchMask::from_bits_truncate(WatchMask::IN_CREATE.bits() | WatchMask::IN_MOVED_TO.bits());
+
+/// The events that are always reported, whether requested or not.
+const ALWAYS_MASK: WatchMask = WatchMask::from_bits_truncate(
+    WatchMask::IN_IGNORED.bits() | WatchMask::IN_Q_OVERFLOW.bits() | WatchMask::IN_UNMOUNT.bits(),
+);
+
+/// A filesystem event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// The path of the file or directory affected.
+    ///
+    /// This is empty for a queue overflow, which isn't for any path.
+    pub path: PathBuf,
+    /// The event(s) that occurred
+    pub mask: WatchMask,
+    /// Connects the IN_MOVED_FROM and IN_MOVED_TO events from a rename
+    pub cookie: u32,
+}
+
+impl Event {
+    /// Determines if the event was for a directory.
+    pub fn is_dir(&self) -> bool {
+        self.mask.contains(WatchMask::IN_ISDIR)
+    }
+
+    /// Determines if events were lost because the queue overflowed.
+    pub fn is_overflow(&self) -> bool {
+        self.mask.contains(WatchMask::IN_Q_OVERFLOW)
+    }
+}
+
+/// The information about a watch.
+#[derive(Debug)]
+struct Watch {
+    /// The path being watched
+    path: PathBuf,
+    /// The events requested by the user
+    mask: WatchMask,
+    /// Whether new subdirectories should be watched
+    recursive: bool,
+}
+
+/// A set of watches on the filesystem.
+#[derive(Debug)]
+pub struct Inotify {
+    /// The inotify instance
+    fd: OwnedFd,
+    /// The watches, by descriptor
+    watches: HashMap<WatchDescriptor, Watch>,
+}
+
+impl Inotify {
+    /// Creates a new inotify instance, with no watches.
+    pub fn new() -> Result<Self> {
+        let fd = inotify::Inotify::init(InitFlags::IN_CLOEXEC)?.as_raw_fd();
+        Ok(Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            watches: HashMap::new(),
+        })
+    }
+
+    /// Gets the nix handle, which doesn't own the descriptor.
+    fn handle(&self) -> inotify::Inotify {
+        unsafe { inotify::Inotify::from_raw_fd(self.fd.as_raw_fd()) }
+    }
+
+    /// Adds a watch on the file or directory, for the events in the mask.
+    ///
+    /// If the path is already being watched, this replaces its mask.
+    pub fn add_watch<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        mask: WatchMask,
+    ) -> Result<WatchDescriptor> {
+        self.add(path.as_ref(), mask, false)
+    }
+
+    /// Adds a watch on the directory, all of its subdirectories, and any
+    /// directories later created under it.
+    pub fn add_watch_recursive<P: AsRef<Path>>(&mut self, path: P, mask: WatchMask) -> Result<()> {
+        let path = path.as_ref();
+        self.add(path, mask, true)?;
+
+        if let Ok(entries) = fs::read_dir(path) {
+            for entry in entries.flatten() {
+                if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                    // A subdirectory might be removed before we get to it
+                    let _ = self.add_watch_recursive(entry.path(), mask);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, path: &Path, mask: WatchMask, recursive: bool) -> Result<WatchDescriptor> {
+        let kernel_mask = if recursive { mask | RECURSE_MASK } else { mask };
+        let wd = self
+            .handle()
+            .add_watch(path, kernel_mask)
+            .op_on("inotify_add_watch", path.display())?;
+        self.watches.insert(
+            wd,
+            Watch {
+                path: path.to_path_buf(),
+                mask,
+                recursive,
+            },
+        );
+        Ok(wd)
+    }
+
+    /// Removes a watch.
+    pub fn remove_watch(&mut self, wd: WatchDescriptor) -> Result<()> {
+        self.watches.remove(&wd);
+        self.handle().rm_watch(wd).op("inotify_rm_watch")
+    }
+
+    /// Gets the path for a watch.
+    pub fn path(&self, wd: WatchDescriptor) -> Option<&Path> {
+        self.watches.get(&wd).map(|w| w.path.as_path())
+    }
+
+    /// Gets the number of active watches.
+    pub fn len(&self) -> usize {
+        self.watches.len()
+    }
+
+    /// Determines if there are no active watches.
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    /// Waits for, and reads, the next batch of events.
+    ///
+    /// If the instance is in non-blocking mode, this fails with `EAGAIN`
+    /// if there are no events. Note that this can return an empty list if
+    /// the only events were ones added internally to follow new
+    /// directories.
+    pub fn read_events(&mut self) -> Result<Vec<Event>> {
+        let raw_events = self.handle().read_events()?;
+        let mut events = Vec::with_capacity(raw_events.len());
+
+        for ev in raw_events {
+            let (path, mask, recursive) = match self.watches.get(&ev.wd) {
+                Some(w) => {
+                    let path = match &ev.name {
+                        Some(name) => w.path.join(name),
+                        None => w.path.clone(),
+                    };
+                    (path, w.mask, w.recursive)
+                }
+                // An overflow, or for a watch that was just removed
+                None => (PathBuf::new(), WatchMask::all(), false),
+            };
+
+            if ev.mask.contains(WatchMask::IN_IGNORED) {
+                self.watches.remove(&ev.wd);
+            }
+
+            if recursive
+                && ev.mask.contains(WatchMask::IN_ISDIR)
+                && ev.mask.intersects(RECURSE_MASK)
+            {
+                let _ = self.add_watch_recursive(&path, mask);
+            }
+
+            // Only report the events the user asked for
+            if ev.mask.intersects(mask | ALWAYS_MASK) {
+                events.push(Event {
+                    path,
+                    mask: ev.mask,
+                    cookie: ev.cookie,
+                });
+            }
+        }
+        Ok(events)
+    }
+}
+
+impl AsFd for Inotify {
+    /// Gets the file handle for the inotify instance.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for Inotify {
+    /// Gets the raw file handle for the inotify instance.
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for Inotify {
+    /// Gives up ownership of the file handle for the inotify instance.
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+impl From<Inotify> for OwnedFd {
+    fn from(ino: Inotify) -> Self {
+        ino.fd
+    }
+}
+
+impl TryFrom<OwnedFd> for Inotify {
+    type Error = Error;
+
+    /// Takes ownership of an existing inotify instance, such as one passed
+    /// from another process.
+    ///
+    /// The new object doesn't know about any watches that were already on
+    /// the instance, so their events are reported with an empty path.
+    ///
+    /// This fails with `EINVAL` if the handle isn't an inotify instance.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        fd::check_anon_inode(fd.as_fd(), "inotify")?;
+        Ok(Self {
+            fd,
+            watches: HashMap::new(),
+        })
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fd::FdExt, Error};
+    use std::{env, process};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("hinix-inotify-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_watch() {
+        let dir = temp_dir("watch");
+        let mut ino = Inotify::new().unwrap();
+        ino.set_nonblocking(true).unwrap();
+
+        let wd = ino.add_watch(&dir, WatchMask::IN_CREATE).unwrap();
+        assert_eq!(Some(dir.as_path()), ino.path(wd));
+        assert_eq!(Error::EAGAIN, ino.read_events().unwrap_err());
+
+        fs::write(dir.join("file"), b"data").unwrap();
+        let events = ino.read_events().unwrap();
+
+        // The write wasn't asked for
+        assert_eq!(1, events.len());
+        assert_eq!(dir.join("file"), events[0].path);
+        assert!(events[0].mask.contains(WatchMask::IN_CREATE));
+        assert!(!events[0].is_dir());
+
+        ino.remove_watch(wd).unwrap();
+        assert!(ino.is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_watch_recursive() {
+        let dir = temp_dir("recursive");
+        fs::create_dir_all(dir.join("a/b")).unwrap();
+
+        let mut ino = Inotify::new().unwrap();
+        ino.set_nonblocking(true).unwrap();
+        ino.add_watch_recursive(&dir, WatchMask::IN_CLOSE_WRITE)
+            .unwrap();
+        assert_eq!(3, ino.len());
+
+        fs::write(dir.join("a/b/file"), b"data").unwrap();
+        let events = ino.read_events().unwrap();
+        assert_eq!(1, events.len());
+        assert_eq!(dir.join("a/b/file"), events[0].path);
+
+        // A new directory gets watched, but isn't reported
+        fs::create_dir(dir.join("c")).unwrap();
+        assert!(ino.read_events().unwrap().is_empty());
+        assert_eq!(4, ino.len());
+
+        fs::write(dir.join("c/file"), b"data").unwrap();
+        let events = ino.read_events().unwrap();
+        assert_eq!(dir.join("c/file"), events[0].path);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
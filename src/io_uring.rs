@@ -0,0 +1,1121 @@
+// hinix/src/io_uring.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Linux io_uring asynchronous I/O.
+//!
+//! An io_uring is a pair of ring buffers shared with the kernel. The
+//! application places I/O requests into the submission queue (SQ), then
+//! tells the kernel about all of them with a single system call. The
+//! results are placed into the completion queue (CQ) as the requests
+//! finish. This lets a busy application batch many I/O operations, like
+//! reads and writes on a number of pipes or event objects, into one
+//! system call rather than one per operation.
+//!
+//! The [`IoUring`] type is a minimal wrapper around the rings. Requests
+//! are built as [`Sqe`] entries and pushed onto the ring, and the
+//! results are read back as [`Cqe`] entries. Since the kernel accesses
+//! the buffers of a request asynchronously, pushing an entry is unsafe.
+//! The batch functions, like [`IoUring::read_batch()`], provide a safe
+//! interface by waiting for all of the requests to complete before
+//! returning.
+//!
+//! Note that Posix message queues can't be used through the ring, since
+//! there is no io_uring operation for mq_send/mq_receive; a read(2) of a
+//! message queue handle just returns its status text.
+//!
+//! This requires the `io-uring` feature.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man7/io_uring.7.html>
+//!
+
+use crate::{fd::FdExt, mmap::MmapMut, Error, Result};
+use bitflags::bitflags;
+use nix::{errno::Errno, fcntl::SpliceFFlags};
+use std::{
+    collections::HashMap,
+    mem::size_of,
+    os::{
+        raw::{c_long, c_uint},
+        unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    },
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+// Definitions from <linux/io_uring.h>, which are missing from libc.
+
+const IORING_OFF_SQ_RING: u64 = 0;
+const IORING_OFF_CQ_RING: u64 = 0x800_0000;
+const IORING_OFF_SQES: u64 = 0x1000_0000;
+
+const IORING_FEAT_SINGLE_MMAP: u32 = 1 << 0;
+
+const IORING_ENTER_GETEVENTS: c_uint = 1 << 0;
+
+const IORING_OP_NOP: u8 = 0;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+const IORING_OP_SPLICE: u8 = 30;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct UringParams {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+/// The raw submission queue entry, as shared with the kernel.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct RawSqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+bitflags! {
+    /// Flags that control how a submission entry is processed.
+    pub struct SqeFlags: u8 {
+        /// Don't start this request until all the previous ones have
+        /// completed.
+        const IO_DRAIN = 1 << 1;
+        /// Link the next request to this one, so that it doesn't start
+        /// until this one completes successfully. If this one fails, the
+        /// rest of the chain is cancelled with `ECANCELED`.
+        const IO_LINK = 1 << 2;
+        /// Like `IO_LINK`, but the chain continues even if this request
+        /// fails.
+        const IO_HARDLINK = 1 << 3;
+        /// Always issue the request from a kernel worker thread, rather
+        /// than first trying it inline.
+        const ASYNC = 1 << 4;
+    }
+}
+
+/// A submission queue entry, which describes a single I/O request.
+///
+/// The entry refers to buffers by raw pointers, which the kernel uses
+/// asynchronously after the entry is submitted. So the entries themselves
+/// can be created safely, but pushing them onto the ring is unsafe.
+#[derive(Debug, Clone, Copy)]
+pub struct Sqe(RawSqe);
+
+impl Sqe {
+    /// Creates an entry for an operation that does nothing.
+    ///
+    /// This can be used to test the ring, or to wake a thread waiting on
+    /// completions.
+    pub fn nop() -> Self {
+        Self(RawSqe {
+            opcode: IORING_OP_NOP,
+            ..RawSqe::default()
+        })
+    }
+
+    /// Creates an entry to read from a file into a buffer.
+    ///
+    /// The `offset` is the position in the file to read from. Use `None`
+    /// to read from the current position, which is required for streams
+    /// like pipes and sockets.
+    pub fn read(fd: RawFd, buf: *mut u8, len: u32, offset: Option<u64>) -> Self {
+        Self(RawSqe {
+            opcode: IORING_OP_READ,
+            fd,
+            off: offset.unwrap_or(u64::MAX),
+            addr: buf as u64,
+            len,
+            ..RawSqe::default()
+        })
+    }
+
+    /// Creates an entry to write from a buffer to a file.
+    ///
+    /// The `offset` is the position in the file to write to. Use `None`
+    /// to write at the current position, which is required for streams
+    /// like pipes and sockets.
+    pub fn write(fd: RawFd, buf: *const u8, len: u32, offset: Option<u64>) -> Self {
+        Self(RawSqe {
+            opcode: IORING_OP_WRITE,
+            fd,
+            off: offset.unwrap_or(u64::MAX),
+            addr: buf as u64,
+            len,
+            ..RawSqe::default()
+        })
+    }
+
+    /// Creates an entry to move data between two files without copying
+    /// it through user space.
+    ///
+    /// At least one of the files must be a pipe. The offsets are used for
+    /// the non-pipe side; use `None` to use the current file position.
+    pub fn splice(
+        fd_in: RawFd,
+        off_in: Option<u64>,
+        fd_out: RawFd,
+        off_out: Option<u64>,
+        len: u32,
+        flags: SpliceFFlags,
+    ) -> Self {
+        Self(RawSqe {
+            opcode: IORING_OP_SPLICE,
+            fd: fd_out,
+            off: off_out.unwrap_or(u64::MAX),
+            addr: off_in.unwrap_or(u64::MAX),
+            len,
+            op_flags: flags.bits(),
+            splice_fd_in: fd_in,
+            ..RawSqe::default()
+        })
+    }
+
+    /// Sets the user data value, which is returned in the completion
+    /// entry to identify the request.
+    pub fn user_data(mut self, user_data: u64) -> Self {
+        self.0.user_data = user_data;
+        self
+    }
+
+    /// Sets the flags for the entry.
+    pub fn flags(mut self, flags: SqeFlags) -> Self {
+        self.0.flags = flags.bits();
+        self
+    }
+}
+
+/// A completion queue entry, which holds the result of a request.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Cqe {
+    /// The user data from the submission entry
+    pub user_data: u64,
+    /// The result of the operation. This is the (positive) return value
+    /// of the equivalent system call, or a negated errno.
+    pub res: i32,
+    /// Operation-specific flags
+    pub flags: u32,
+}
+
+impl Cqe {
+    /// Gets the result of the operation, converting a failure into an
+    /// error.
+    pub fn result(&self) -> Result<u32> {
+        if self.res < 0 {
+            Err(Error::from_i32(-self.res))
+        }
+        else {
+            Ok(self.res as u32)
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// An io_uring instance.
+#[derive(Debug)]
+pub struct IoUring {
+    /// The ring handle
+    fd: OwnedFd,
+    /// The submission queue ring (and also the completion queue ring, if
+    /// the kernel supports a single mapping for both)
+    sq_ring: MmapMut,
+    /// The completion queue ring, if mapped separately
+    cq_ring: Option<MmapMut>,
+    /// The array of submission queue entries
+    sqes: MmapMut,
+    /// The parameters returned by the kernel
+    params: UringParams,
+    /// The number of requests that were pushed, but whose completions
+    /// haven't been read.
+    in_flight: usize,
+}
+
+impl IoUring {
+    /// Creates a ring with room for (at least) the specified number of
+    /// submission entries.
+    ///
+    /// The kernel rounds the size up to a power of two. The completion
+    /// queue is twice that size.
+    pub fn new(entries: u32) -> Result<Self> {
+        let mut params = UringParams::default();
+        let fd = Errno::result(unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                entries as c_long,
+                &mut params as *mut UringParams,
+            )
+        })?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * size_of::<u32>();
+        let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * size_of::<Cqe>();
+
+        let (sq_ring, cq_ring) = if params.features & IORING_FEAT_SINGLE_MMAP != 0 {
+            let len = sq_len.max(cq_len);
+            let ring = unsafe { MmapMut::map_range(&fd, IORING_OFF_SQ_RING, len)? };
+            (ring, None)
+        }
+        else {
+            let sq_ring = unsafe { MmapMut::map_range(&fd, IORING_OFF_SQ_RING, sq_len)? };
+            let cq_ring = unsafe { MmapMut::map_range(&fd, IORING_OFF_CQ_RING, cq_len)? };
+            (sq_ring, Some(cq_ring))
+        };
+
+        let sqes_len = params.sq_entries as usize * size_of::<RawSqe>();
+        let sqes = unsafe { MmapMut::map_range(&fd, IORING_OFF_SQES, sqes_len)? };
+
+        Ok(Self {
+            fd,
+            sq_ring,
+            cq_ring,
+            sqes,
+            params,
+            in_flight: 0,
+        })
+    }
+
+    /// Gets the number of entries in the submission queue.
+    pub fn sq_entries(&self) -> u32 {
+        self.params.sq_entries
+    }
+
+    /// Gets the number of entries in the completion queue.
+    pub fn cq_entries(&self) -> u32 {
+        self.params.cq_entries
+    }
+
+    /// Gets the number of requests that have been pushed, but whose
+    /// completions have not yet been retrieved.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Gets an atomic integer in the SQ ring at the specified offset.
+    fn sq_atomic(&self, off: u32) -> &AtomicU32 {
+        unsafe { &*(self.sq_ring.as_ptr().add(off as usize) as *const AtomicU32) }
+    }
+
+    /// Gets the start of the CQ ring, which may be in the SQ mapping.
+    fn cq_base(&self) -> *const u8 {
+        self.cq_ring.as_ref().unwrap_or(&self.sq_ring).as_ptr()
+    }
+
+    /// Gets an atomic integer in the CQ ring at the specified offset.
+    fn cq_atomic(&self, off: u32) -> &AtomicU32 {
+        unsafe { &*(self.cq_base().add(off as usize) as *const AtomicU32) }
+    }
+
+    /// Pushes an entry onto the submission queue.
+    ///
+    /// The request isn't seen by the kernel until the next call to
+    /// [`submit()`](IoUring::submit). This fails with `EBUSY` if the
+    /// submission queue is full, or if the completion queue could
+    /// overflow with the requests already in flight.
+    ///
+    /// # Safety
+    ///
+    /// Any buffers referenced by the entry must remain valid, and not be
+    /// accessed in a conflicting way, until the request completes.
+    pub unsafe fn push(&mut self, sqe: &Sqe) -> Result<()> {
+        let off = self.params.sq_off;
+        let head = self.sq_atomic(off.head).load(Ordering::Acquire);
+        let tail = self.sq_atomic(off.tail).load(Ordering::Relaxed);
+
+        if tail.wrapping_sub(head) >= self.params.sq_entries
+            || self.in_flight >= self.params.cq_entries as usize
+        {
+            return Err(Error::EBUSY);
+        }
+
+        let mask = *(self.sq_ring.as_ptr().add(off.ring_mask as usize) as *const u32);
+        let idx = tail & mask;
+
+        let sqes = self.sqes.as_mut_ptr() as *mut RawSqe;
+        ptr::write(sqes.add(idx as usize), sqe.0);
+
+        let array = self.sq_ring.as_mut_ptr().add(off.array as usize) as *mut u32;
+        ptr::write(array.add(idx as usize), idx);
+
+        self.sq_atomic(off.tail)
+            .store(tail.wrapping_add(1), Ordering::Release);
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    /// Removes the `n` most recently pushed entries, so they're never
+    /// run, if the kernel hasn't seen any of them yet.
+    ///
+    /// Returns `false`, leaving the entries in place, if it has.
+    fn discard(&mut self, n: usize) -> bool {
+        let off = self.params.sq_off;
+        let head = self.sq_atomic(off.head).load(Ordering::Acquire);
+        let tail = self.sq_atomic(off.tail).load(Ordering::Relaxed);
+
+        if n > tail.wrapping_sub(head) as usize {
+            return false;
+        }
+        self.sq_atomic(off.tail)
+            .store(tail.wrapping_sub(n as u32), Ordering::Release);
+        self.in_flight -= n;
+        true
+    }
+
+    /// Submits all the pushed entries to the kernel.
+    ///
+    /// Returns the number of entries that were submitted.
+    pub fn submit(&mut self) -> Result<usize> {
+        self.enter(0, 0)
+    }
+
+    /// Submits all the pushed entries to the kernel, and waits until at
+    /// least `want` completions are available.
+    ///
+    /// Returns the number of entries that were submitted.
+    pub fn submit_and_wait(&mut self, want: usize) -> Result<usize> {
+        self.enter(want as c_uint, IORING_ENTER_GETEVENTS)
+    }
+
+    fn enter(&mut self, min_complete: c_uint, flags: c_uint) -> Result<usize> {
+        let off = self.params.sq_off;
+        let head = self.sq_atomic(off.head).load(Ordering::Acquire);
+        let tail = self.sq_atomic(off.tail).load(Ordering::Relaxed);
+        let to_submit = tail.wrapping_sub(head);
+
+        let n = Errno::result(unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_enter,
+                self.fd.as_raw_fd() as c_long,
+                to_submit as c_long,
+                min_complete as c_long,
+                flags as c_long,
+                ptr::null::<libc::sigset_t>(),
+                0 as c_long,
+            )
+        })?;
+        Ok(n as usize)
+    }
+
+    /// Retrieves the next completion entry, if one is available.
+    pub fn completion(&mut self) -> Option<Cqe> {
+        let off = self.params.cq_off;
+        let head = self.cq_atomic(off.head).load(Ordering::Relaxed);
+        let tail = self.cq_atomic(off.tail).load(Ordering::Acquire);
+
+        if head == tail {
+            return None;
+        }
+
+        let cqe = unsafe {
+            let base = self.cq_base();
+            let mask = *(base.add(off.ring_mask as usize) as *const u32);
+            let cqes = base.add(off.cqes as usize) as *const Cqe;
+            ptr::read(cqes.add((head & mask) as usize))
+        };
+
+        self.cq_atomic(off.head)
+            .store(head.wrapping_add(1), Ordering::Release);
+        self.in_flight = self.in_flight.saturating_sub(1);
+        Some(cqe)
+    }
+
+    /// Blocks until a completion entry is available, then retrieves it.
+    ///
+    /// This also submits any entries that were pushed but not yet
+    /// submitted. It fails with `EINVAL` if there are no requests in
+    /// flight, since it would block forever.
+    pub fn wait_completion(&mut self) -> Result<Cqe> {
+        loop {
+            if let Some(cqe) = self.completion() {
+                return Ok(cqe);
+            }
+            if self.in_flight == 0 {
+                return Err(Error::EINVAL);
+            }
+            match self.submit_and_wait(1) {
+                Ok(_) => (),
+                Err(err) if err == Error::EINTR => (),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Pushes a set of entries, then submits them and waits for all of
+    /// them to complete.
+    ///
+    /// Returns the results in the same order as the entries. The ring
+    /// must be idle, otherwise this fails with `EBUSY`, as it does if
+    /// there are more entries than will fit in the ring.
+    ///
+    /// # Safety
+    ///
+    /// The buffers referenced by the entries must be valid for the
+    /// duration of the call.
+    unsafe fn run_batch(&mut self, sqes: &[Sqe]) -> Result<Vec<Result<usize>>> {
+        if self.in_flight != 0 || sqes.len() > self.params.sq_entries as usize {
+            return Err(Error::EBUSY);
+        }
+
+        for (i, sqe) in sqes.iter().enumerate() {
+            if let Err(err) = self.push(&sqe.user_data(i as u64)) {
+                // Can't happen on an idle ring, but if it did, the
+                // buffers must stay valid until any queued requests
+                // are done.
+                self.drain();
+                return Err(err);
+            }
+        }
+
+        let mut results: Vec<Result<usize>> = vec![Err(Error::ECANCELED); sqes.len()];
+        while self.in_flight > 0 {
+            match self.wait_completion() {
+                Ok(cqe) => {
+                    if let Some(res) = results.get_mut(cqe.user_data as usize) {
+                        *res = cqe.result().map(|n| n as usize);
+                    }
+                }
+                Err(err) if err == Error::EINTR => (),
+                Err(err) => {
+                    self.drain();
+                    return Err(err);
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// Waits for all the in-flight requests to complete, discarding the
+    /// results.
+    fn drain(&mut self) {
+        while self.in_flight > 0 {
+            if let Err(err) = self.wait_completion() {
+                if err != Errno::EINTR {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Reads into a set of buffers, each from its own file, using a
+    /// single system call.
+    ///
+    /// This is useful to read from a number of pipes or event objects at
+    /// once. Each read is from the current position of the file, and
+    /// blocks until data is available, like a normal read. The result of
+    /// each read is returned in the same order as the requests.
+    pub fn read_batch(
+        &mut self,
+        reads: &mut [(BorrowedFd<'_>, &mut [u8])],
+    ) -> Result<Vec<Result<usize>>> {
+        let sqes: Vec<_> = reads
+            .iter_mut()
+            .map(|(fd, buf)| {
+                Sqe::read(
+                    fd.as_raw_fd(),
+                    buf.as_mut_ptr(),
+                    buf.len().min(u32::MAX as usize) as u32,
+                    None,
+                )
+            })
+            .collect();
+        // The buffers are borrowed until all the requests complete.
+        unsafe { self.run_batch(&sqes) }
+    }
+
+    /// Writes a set of buffers, each to its own file, using a single
+    /// system call.
+    ///
+    /// This is useful to write to a number of pipes or event objects at
+    /// once. Each write is at the current position of the file. The
+    /// result of each write is returned in the same order as the
+    /// requests.
+    pub fn write_batch(
+        &mut self,
+        writes: &[(BorrowedFd<'_>, &[u8])],
+    ) -> Result<Vec<Result<usize>>> {
+        let sqes: Vec<_> = writes
+            .iter()
+            .map(|(fd, buf)| {
+                Sqe::write(
+                    fd.as_raw_fd(),
+                    buf.as_ptr(),
+                    buf.len().min(u32::MAX as usize) as u32,
+                    None,
+                )
+            })
+            .collect();
+        // The buffers are borrowed until all the requests complete.
+        unsafe { self.run_batch(&sqes) }
+    }
+}
+
+impl Drop for IoUring {
+    fn drop(&mut self) {
+        // The kernel could still be using the buffers of any requests in
+        // flight, so wait for them before they can be freed.
+        self.drain();
+    }
+}
+
+impl AsFd for IoUring {
+    /// Gets the file handle for the ring.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for IoUring {
+    /// Gets the raw file handle for the ring.
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// A chain of copy operations to run through an io_uring.
+///
+/// The job is a list of stages, which are linked so that each one starts
+/// only after the previous one completes successfully. This lets a relay
+/// move data from a source, through a pipe, to a destination, like
+/// socket -> pipe -> file, with a single system call rather than a loop
+/// of splice calls.
+///
+/// If a stage fails, or transfers fewer bytes than requested, the rest of
+/// the chain is cancelled and completes with `ECANCELED`.
+///
+/// The job holds its own duplicates of the handles, so the files stay
+/// open until it completes, even if the caller closes them first.
+#[derive(Debug, Default)]
+pub struct CopyJob {
+    /// The entries for each stage
+    sqes: Vec<Sqe>,
+    /// The buffers used by read and write stages
+    bufs: Vec<Vec<u8>>,
+    /// The handles used by the stages
+    fds: Vec<OwnedFd>,
+    /// An error found while building the job
+    err: Option<Error>,
+}
+
+impl CopyJob {
+    /// Creates a new, empty, job.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the number of stages in the job.
+    pub fn len(&self) -> usize {
+        self.sqes.len()
+    }
+
+    /// Determines if the job has no stages.
+    pub fn is_empty(&self) -> bool {
+        self.sqes.is_empty()
+    }
+
+    /// Keeps a duplicate of a handle for a stage, and gets its value.
+    ///
+    /// If the handle can't be duplicated, the error is saved for when the
+    /// job is submitted.
+    fn hold<F: AsFd>(&mut self, fd: &F) -> RawFd {
+        match fd.dup() {
+            Ok(fd) => {
+                let raw = fd.as_raw_fd();
+                self.fds.push(fd);
+                raw
+            }
+            Err(err) => {
+                self.err.get_or_insert(err);
+                -1
+            }
+        }
+    }
+
+    /// Adds a stage to splice `len` bytes from one file to another. One of
+    /// them must be a pipe.
+    ///
+    /// The offsets are for the non-pipe side. `None` uses the current file
+    /// position.
+    pub fn splice<I: AsFd, O: AsFd>(
+        mut self,
+        fd_in: &I,
+        off_in: Option<u64>,
+        fd_out: &O,
+        off_out: Option<u64>,
+        len: u32,
+    ) -> Self {
+        let fd_in = self.hold(fd_in);
+        let fd_out = self.hold(fd_out);
+        self.sqes.push(Sqe::splice(
+            fd_in,
+            off_in,
+            fd_out,
+            off_out,
+            len,
+            SpliceFFlags::SPLICE_F_MOVE,
+        ));
+        self
+    }
+
+    /// Adds the two stages to move `len` bytes from `src` to `dst` through
+    /// a pipe, given the write and read ends of the pipe.
+    ///
+    /// This is the common case for a relay, where neither side is a pipe.
+    pub fn relay<S, W, R, D>(
+        self,
+        src: &S,
+        pipe_wr: &W,
+        pipe_rd: &R,
+        dst: &D,
+        dst_offset: Option<u64>,
+        len: u32,
+    ) -> Self
+    where
+        S: AsFd,
+        W: AsFd,
+        R: AsFd,
+        D: AsFd,
+    {
+        self.splice(src, None, pipe_wr, None, len)
+            .splice(pipe_rd, None, dst, dst_offset, len)
+    }
+
+    /// Adds a stage to read `len` bytes from the file into a buffer owned
+    /// by the job.
+    pub fn read<F: AsFd>(mut self, fd: &F, offset: Option<u64>, len: u32) -> Self {
+        let fd = self.hold(fd);
+        let mut buf = vec![0u8; len as usize];
+        self.sqes.push(Sqe::read(fd, buf.as_mut_ptr(), len, offset));
+        // Moving the Vec doesn't move the heap buffer it points to.
+        self.bufs.push(buf);
+        self
+    }
+
+    /// Adds a stage to write the buffer from the most recent read stage
+    /// to the file.
+    ///
+    /// The job fails with `EINVAL` when submitted if there was no
+    /// previous read stage.
+    pub fn write<F: AsFd>(mut self, fd: &F, offset: Option<u64>) -> Self {
+        match self.bufs.last().map(|buf| (buf.as_ptr(), buf.len() as u32)) {
+            Some((buf, len)) => {
+                let fd = self.hold(fd);
+                self.sqes.push(Sqe::write(fd, buf, len, offset));
+            }
+            None => self.err = Some(Error::EINVAL),
+        }
+        self
+    }
+}
+
+/// The result of a completed [`CopyJob`].
+#[derive(Debug)]
+pub struct JobResult {
+    /// The ID assigned to the job when it was submitted
+    pub id: u64,
+    /// The result of each stage, in order
+    pub results: Vec<Result<usize>>,
+}
+
+impl JobResult {
+    /// Determines if all the stages of the job succeeded.
+    pub fn is_ok(&self) -> bool {
+        self.results.iter().all(|res| res.is_ok())
+    }
+
+    /// Gets the first error from the stages of the job, if any.
+    pub fn error(&self) -> Option<Error> {
+        self.results
+            .iter()
+            .find_map(|res| res.as_ref().err().cloned())
+    }
+}
+
+/// A job that has been submitted, and is waiting for its completions.
+#[derive(Debug)]
+struct ActiveJob {
+    /// The job, kept alive for its buffers and handles
+    _job: CopyJob,
+    /// The results of the stages, as they complete
+    results: Vec<Option<Result<usize>>>,
+    /// The number of stages that haven't completed
+    remaining: usize,
+}
+
+/// The number of bits of the user data used for the stage index.
+const STAGE_BITS: u32 = 16;
+
+/// A queue that runs [`CopyJob`]s through an io_uring.
+///
+/// Any number of jobs can be in progress at once, up to the capacity of
+/// the ring. Each one is assigned an ID when it's submitted, which is
+/// reported in its result when it completes.
+#[derive(Debug)]
+pub struct CopyQueue {
+    // Note that the ring is dropped first, which waits for any requests
+    // in flight, before the job buffers are released.
+    /// The ring
+    ring: IoUring,
+    /// The jobs in progress, by ID
+    jobs: HashMap<u64, ActiveJob>,
+    /// The ID for the next job
+    next_id: u64,
+}
+
+impl CopyQueue {
+    /// Creates a queue with a ring of (at least) the specified number of
+    /// entries. This limits the total number of stages for all the jobs
+    /// in progress.
+    pub fn new(entries: u32) -> Result<Self> {
+        Ok(Self {
+            ring: IoUring::new(entries)?,
+            jobs: HashMap::new(),
+            next_id: 1,
+        })
+    }
+
+    /// Gets the number of jobs in progress.
+    pub fn pending(&self) -> usize {
+        self.jobs.len()
+    }
+
+    /// Submits a job, and returns the ID assigned to it.
+    ///
+    /// This fails with `EBUSY` if there isn't room in the ring for all
+    /// the stages of the job, and `EINVAL` if the job is empty or
+    /// malformed.
+    pub fn submit(&mut self, job: CopyJob) -> Result<u64> {
+        if let Some(err) = job.err {
+            return Err(err);
+        }
+        let n = job.sqes.len();
+        if n == 0 || n >= 1 << STAGE_BITS {
+            return Err(Error::EINVAL);
+        }
+        if self.ring.in_flight() + n > self.ring.sq_entries() as usize {
+            return Err(Error::EBUSY);
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let sqes: Vec<_> = job
+            .sqes
+            .iter()
+            .enumerate()
+            .map(|(i, sqe)| {
+                let mut sqe = sqe.user_data((id << STAGE_BITS) | i as u64);
+                if i + 1 < n {
+                    sqe.0.flags |= SqeFlags::IO_LINK.bits();
+                }
+                sqe
+            })
+            .collect();
+
+        // The job is kept until all of its completions arrive, since the
+        // kernel uses its buffers and handles until then.
+        self.jobs.insert(
+            id,
+            ActiveJob {
+                _job: job,
+                results: vec![None; n],
+                remaining: n,
+            },
+        );
+
+        // If any stage can't be queued or submitted, the ones that were
+        // queued are taken back before the kernel sees them, so the job
+        // can be released.
+        for (i, sqe) in sqes.iter().enumerate() {
+            if let Err(err) = unsafe { self.ring.push(sqe) } {
+                if self.ring.discard(i) {
+                    self.jobs.remove(&id);
+                }
+                return Err(err);
+            }
+        }
+        if let Err(err) = self.ring.submit() {
+            if self.ring.discard(n) {
+                self.jobs.remove(&id);
+            }
+            return Err(err);
+        }
+        Ok(id)
+    }
+
+    /// Processes a completion entry, returning the result of the job if
+    /// it was the last stage.
+    fn complete(&mut self, cqe: Cqe) -> Option<JobResult> {
+        let id = cqe.user_data >> STAGE_BITS;
+        let stage = (cqe.user_data & ((1 << STAGE_BITS) - 1)) as usize;
+
+        let job = self.jobs.get_mut(&id)?;
+        if let Some(res) = job.results.get_mut(stage) {
+            if res.is_none() {
+                *res = Some(cqe.result().map(|n| n as usize));
+                job.remaining -= 1;
+            }
+        }
+
+        if job.remaining > 0 {
+            return None;
+        }
+        self.jobs.remove(&id).map(|job| JobResult {
+            id,
+            results: job
+                .results
+                .into_iter()
+                .map(|res| res.unwrap_or(Err(Error::ECANCELED)))
+                .collect(),
+        })
+    }
+
+    /// Gets the result of a job that has completed, if any, without
+    /// blocking.
+    pub fn poll(&mut self) -> Option<JobResult> {
+        while let Some(cqe) = self.ring.completion() {
+            if let Some(res) = self.complete(cqe) {
+                return Some(res);
+            }
+        }
+        None
+    }
+
+    /// Blocks until a job completes, and returns its result.
+    ///
+    /// This fails with `EINVAL` if there are no jobs in progress.
+    pub fn wait(&mut self) -> Result<JobResult> {
+        loop {
+            if let Some(res) = self.poll() {
+                return Ok(res);
+            }
+            if self.jobs.is_empty() {
+                return Err(Error::EINVAL);
+            }
+            let cqe = self.ring.wait_completion()?;
+            if let Some(res) = self.complete(cqe) {
+                return Ok(res);
+            }
+        }
+    }
+}
+
+// Make sure our definitions match the kernel's.
+const _: () = assert!(size_of::<RawSqe>() == 64);
+const _: () = assert!(size_of::<Cqe>() == 16);
+const _: () = assert!(size_of::<UringParams>() == 120);
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(all(test, feature = "eventfd", feature = "pipe"))]
+mod tests {
+    use super::*;
+    use crate::{eventfd::EventFd, pipe};
+    use std::{
+        env, fs,
+        io::{Read, Write},
+    };
+
+    #[test]
+    fn test_nop() {
+        let mut ring = IoUring::new(4).unwrap();
+        assert_eq!(4, ring.sq_entries());
+
+        unsafe {
+            ring.push(&Sqe::nop().user_data(42)).unwrap();
+            ring.push(&Sqe::nop().user_data(43)).unwrap();
+        }
+        assert_eq!(2, ring.in_flight());
+        assert_eq!(2, ring.submit_and_wait(2).unwrap());
+
+        assert_eq!(42, ring.completion().unwrap().user_data);
+        assert_eq!(Ok(0), ring.completion().unwrap().result());
+        assert!(ring.completion().is_none());
+        assert_eq!(0, ring.in_flight());
+        assert_eq!(Error::EINVAL, ring.wait_completion().unwrap_err());
+    }
+
+    #[test]
+    fn test_full() {
+        let mut ring = IoUring::new(2).unwrap();
+        unsafe {
+            ring.push(&Sqe::nop()).unwrap();
+            ring.push(&Sqe::nop()).unwrap();
+            assert_eq!(Error::EBUSY, ring.push(&Sqe::nop()).unwrap_err());
+        }
+
+        // Entries the kernel hasn't seen can be taken back
+        assert!(ring.discard(1));
+        assert!(!ring.discard(2));
+        assert_eq!(1, ring.in_flight());
+        unsafe { ring.push(&Sqe::nop()).unwrap() };
+        ring.submit().unwrap();
+        ring.wait_completion().unwrap();
+        ring.wait_completion().unwrap();
+    }
+
+    #[test]
+    fn test_pipe_batch() {
+        let mut ring = IoUring::new(8).unwrap();
+        let (mut wr1, rd1) = pipe::pipe().unwrap();
+        let (wr2, mut rd2) = pipe::pipe().unwrap();
+
+        wr1.write_all(b"one").unwrap();
+
+        let res = ring.write_batch(&[(wr2.as_fd(), b"two")]).unwrap();
+        assert_eq!(Ok(3), res[0]);
+
+        let mut buf1 = [0u8; 8];
+        let mut buf2 = [0u8; 8];
+        rd2.read_exact(&mut buf2[..3]).unwrap();
+        assert_eq!(b"two", &buf2[..3]);
+
+        let (wr3, rd3) = pipe::pipe().unwrap();
+        drop(wr3);
+        let res = ring
+            .read_batch(&mut [(rd1.as_fd(), &mut buf1), (rd3.as_fd(), &mut buf2)])
+            .unwrap();
+        assert_eq!(vec![Ok(3), Ok(0)], res);
+        assert_eq!(b"one", &buf1[..3]);
+    }
+
+    #[test]
+    fn test_eventfd_batch() {
+        let mut ring = IoUring::new(4).unwrap();
+        let evt1 = EventFd::new(0).unwrap();
+        let evt2 = EventFd::new(0).unwrap();
+
+        let v1 = 5u64.to_ne_bytes();
+        let v2 = 7u64.to_ne_bytes();
+        let res = ring
+            .write_batch(&[
+                (evt1.as_fd(), &v1),
+                (evt2.as_fd(), &v2),
+                (evt1.as_fd(), &v2),
+            ])
+            .unwrap();
+        assert!(res.iter().all(|r| *r == Ok(8)));
+
+        assert_eq!(12, evt1.read().unwrap());
+        assert_eq!(7, evt2.read().unwrap());
+    }
+
+    #[test]
+    fn test_copy_job_relay() {
+        let path = env::temp_dir().join(format!("hinix-uring-relay-{}", nix::unistd::getpid()));
+        let dst = fs::File::create(&path).unwrap();
+
+        let (mut src_wr, src_rd) = pipe::pipe().unwrap();
+        let (pipe_wr, pipe_rd) = pipe::pipe().unwrap();
+        src_wr.write_all(b"relayed data").unwrap();
+
+        let mut queue = CopyQueue::new(8).unwrap();
+        let job = CopyJob::new().relay(&src_rd, &pipe_wr, &pipe_rd, &dst, Some(0), 12);
+        assert_eq!(2, job.len());
+
+        let id = queue.submit(job).unwrap();
+
+        // The job holds its own handles, so the caller can close theirs
+        drop((src_rd, pipe_wr, pipe_rd, dst));
+
+        let res = queue.wait().unwrap();
+        assert_eq!(id, res.id);
+        assert_eq!(vec![Ok(12), Ok(12)], res.results);
+        assert_eq!(0, queue.pending());
+
+        assert_eq!(b"relayed data", &fs::read(&path).unwrap()[..]);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_copy_job_read_write() {
+        let path = env::temp_dir().join(format!("hinix-uring-rw-{}", nix::unistd::getpid()));
+        fs::write(&path, b"0123456789").unwrap();
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+
+        let mut queue = CopyQueue::new(8).unwrap();
+
+        // Copy the first half of the file over the second half
+        let job = CopyJob::new().read(&file, Some(0), 5).write(&file, Some(5));
+        queue.submit(job).unwrap();
+        assert!(queue.wait().unwrap().is_ok());
+        assert_eq!(b"0123401234", &fs::read(&path).unwrap()[..]);
+
+        // A short read breaks the chain
+        let job = CopyJob::new().read(&file, Some(8), 5).write(&file, Some(0));
+        queue.submit(job).unwrap();
+        let res = queue.wait().unwrap();
+        assert_eq!(Ok(2), res.results[0]);
+        assert_eq!(Some(Error::ECANCELED), res.error());
+
+        assert_eq!(
+            Error::EINVAL,
+            queue.submit(CopyJob::new().write(&file, None)).unwrap_err()
+        );
+        assert_eq!(Error::EINVAL, queue.wait().unwrap_err());
+
+        let _ = fs::remove_file(&path);
+    }
+}
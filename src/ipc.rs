@@ -0,0 +1,567 @@
+// hinix/src/ipc.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Sending and receiving messages over any of the transports.
+//!
+//! The [`IpcSender`] and [`IpcReceiver`] traits are implemented by each
+//! of the message transports in the crate:
+//!
+//! * `MsgQueue` - a POSIX message queue
+//! * `SeqPacket` - a seqpacket Unix socket
+//! * `WritePipe` and `ReadPipe` - the ends of a `packet_pipe()`. The
+//!   ends of an ordinary pipe fail with `EINVAL`.
+//! * `Fifo` - a named pipe, with each message framed by its length
+//!
+//! so an application can be written against the traits, and pick the
+//! transport at run time, like from its configuration. They can be used
+//! as trait objects, like `Box<dyn IpcSender>`, and the calls for typed
+//! values work on the box.
+//!
+//! Each trait has blocking, timed, and non-blocking ("try") calls, for
+//! raw bytes, or for values converted to and from messages by a
+//! [`Codec`]. A timed or non-blocking call returns `false` or `None` if
+//! the transport wasn't ready in time, rather than an error.
+//!
+//! Apart from message queues, which have their own timed calls, the
+//! timed and non-blocking calls wait for the handle to be ready, and
+//! then send or receive. If another thread or process is using the same
+//! end of the transport, it could take the message (or the room for one)
+//! first, which would leave a blocking handle waiting. Putting the
+//! handle into non-blocking mode keeps to the timeout in that case.
+//!
+//! ```
+//! use hinix::{codec::RawCodec, ipc::{IpcReceiver, IpcSender}, seqpacket::SeqPacket};
+//! use std::time::Duration;
+//!
+//! # fn main() -> hinix::Result<()> {
+//! let (a, b) = SeqPacket::pair()?;
+//! let (tx, rx): (Box<dyn IpcSender>, Box<dyn IpcReceiver>) = (Box::new(a), Box::new(b));
+//!
+//! tx.send_typed(&RawCodec, &b"hello".to_vec())?;
+//! let msg = rx.recv_typed_timeout(&RawCodec, Duration::from_secs(1).into())?;
+//! assert_eq!(Some(b"hello".to_vec()), msg);
+//! # Ok(())
+//! # }
+//! ```
+//!
+
+use crate::{
+    codec::Codec,
+    pool::{BufferPool, PooledBuf},
+    timeout::Timeout,
+    Result,
+};
+
+#[cfg(any(
+    feature = "fifo",
+    all(
+        any(feature = "pipe", feature = "seqpacket"),
+        any(target_os = "android", target_os = "linux")
+    )
+))]
+use {
+    crate::{
+        codec::{PacketReceiver, PacketSender},
+        timeout,
+    },
+    nix::poll::PollFlags,
+    std::os::unix::io::AsFd,
+};
+
+#[cfg(any(
+    feature = "fifo",
+    all(
+        any(feature = "pipe", feature = "seqpacket", feature = "msgqueue"),
+        any(target_os = "android", target_os = "linux")
+    ),
+    all(
+        feature = "msgqueue",
+        any(target_os = "dragonfly", target_os = "freebsd", target_os = "netbsd")
+    )
+))]
+use crate::Error;
+
+#[cfg(feature = "fifo")]
+use crate::fifo::Fifo;
+
+#[cfg(all(feature = "pipe", any(target_os = "android", target_os = "linux")))]
+use crate::pipe::{ReadPipe, WritePipe};
+
+#[cfg(all(feature = "seqpacket", any(target_os = "android", target_os = "linux")))]
+use crate::seqpacket::SeqPacket;
+
+#[cfg(all(
+    feature = "msgqueue",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "netbsd"
+    )
+))]
+use crate::msgqueue::{MsgQueue, DEFAULT_PRIO};
+
+/// A transport that sends messages.
+pub trait IpcSender {
+    /// Gets the largest message that can be sent, in bytes.
+    fn max_send_msg_size(&self) -> usize;
+
+    /// Sends a message, waiting for room in the transport if necessary.
+    fn send_msg(&self, buf: &[u8]) -> Result<()>;
+
+    /// Sends a message, waiting up to the timeout for room in the
+    /// transport.
+    ///
+    /// Returns `false` if the timeout expired first.
+    fn send_msg_timeout(&self, buf: &[u8], timeout: Timeout) -> Result<bool>;
+
+    /// Sends a message if there's room for it without waiting.
+    ///
+    /// Returns `false` if the transport is full.
+    fn try_send_msg(&self, buf: &[u8]) -> Result<bool> {
+        self.send_msg_timeout(buf, Timeout::ZERO)
+    }
+
+    /// Encodes the value with the codec, and sends it.
+    fn send_typed<T, C: Codec<T>>(&self, codec: &C, item: &T) -> Result<()>
+    where
+        Self: Sized,
+    {
+        let buf = codec.encode(item, self.max_send_msg_size())?;
+        self.send_msg(&buf)
+    }
+
+    /// Encodes the value with the codec, and sends it, waiting up to the
+    /// timeout for room in the transport.
+    ///
+    /// Returns `false` if the timeout expired first.
+    fn send_typed_timeout<T, C: Codec<T>>(
+        &self,
+        codec: &C,
+        item: &T,
+        timeout: Timeout,
+    ) -> Result<bool>
+    where
+        Self: Sized,
+    {
+        let buf = codec.encode(item, self.max_send_msg_size())?;
+        self.send_msg_timeout(&buf, timeout)
+    }
+
+    /// Encodes the value with the codec, and sends it if there's room
+    /// without waiting.
+    ///
+    /// Returns `false` if the transport is full.
+    fn try_send_typed<T, C: Codec<T>>(&self, codec: &C, item: &T) -> Result<bool>
+    where
+        Self: Sized,
+    {
+        self.send_typed_timeout(codec, item, Timeout::ZERO)
+    }
+}
+
+/// A transport that receives messages.
+pub trait IpcReceiver {
+    /// Gets the largest message that can be received, in bytes.
+    fn max_recv_msg_size(&self) -> usize;
+
+    /// Receives a message into the buffer, waiting for one if necessary.
+    ///
+    /// Returns the size of the message.
+    fn recv_msg(&self, buf: &mut [u8]) -> Result<usize>;
+
+    /// Receives a message into the buffer, waiting up to the timeout for
+    /// one to arrive.
+    ///
+    /// Returns the size of the message, or `None` if the timeout expired
+    /// first.
+    fn recv_msg_timeout(&self, buf: &mut [u8], timeout: Timeout) -> Result<Option<usize>>;
+
+    /// Receives a message into the buffer, if there is one.
+    ///
+    /// Returns the size of the message, or `None` if the transport is
+    /// empty.
+    fn try_recv_msg(&self, buf: &mut [u8]) -> Result<Option<usize>> {
+        self.recv_msg_timeout(buf, Timeout::ZERO)
+    }
+
+    /// Receives a message into a buffer from the pool, waiting for one if
+    /// necessary.
+    ///
+    /// The buffers in the pool should be able to hold the largest message
+    /// that can be received.
+    fn recv_pooled(&self, pool: &BufferPool) -> Result<PooledBuf> {
+        let mut buf = pool.get();
+        let n = self.recv_msg(buf.as_full_mut())?;
+        buf.set_len(n);
+        Ok(buf)
+    }
+
+    /// Receives a message, and decodes it with the codec.
+    fn recv_typed<T, C: Codec<T>>(&self, codec: &C) -> Result<T>
+    where
+        Self: Sized,
+    {
+        let mut buf = vec![0u8; self.max_recv_msg_size()];
+        let n = self.recv_msg(&mut buf)?;
+        codec.decode(&buf[..n])
+    }
+
+    /// Receives a message, waiting up to the timeout for one to arrive,
+    /// and decodes it with the codec.
+    ///
+    /// Returns `None` if the timeout expired first.
+    fn recv_typed_timeout<T, C: Codec<T>>(&self, codec: &C, timeout: Timeout) -> Result<Option<T>>
+    where
+        Self: Sized,
+    {
+        let mut buf = vec![0u8; self.max_recv_msg_size()];
+        match self.recv_msg_timeout(&mut buf, timeout)? {
+            Some(n) => codec.decode(&buf[..n]).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Receives a message, if there is one, and decodes it with the
+    /// codec.
+    ///
+    /// Returns `None` if the transport is empty.
+    fn try_recv_typed<T, C: Codec<T>>(&self, codec: &C) -> Result<Option<T>>
+    where
+        Self: Sized,
+    {
+        self.recv_typed_timeout(codec, Timeout::ZERO)
+    }
+}
+
+// Boxes and references forward to the transport, so that the calls for
+// typed values can be made on a trait object.
+
+impl<S: IpcSender + ?Sized> IpcSender for Box<S> {
+    fn max_send_msg_size(&self) -> usize {
+        (**self).max_send_msg_size()
+    }
+
+    fn send_msg(&self, buf: &[u8]) -> Result<()> {
+        (**self).send_msg(buf)
+    }
+
+    fn send_msg_timeout(&self, buf: &[u8], timeout: Timeout) -> Result<bool> {
+        (**self).send_msg_timeout(buf, timeout)
+    }
+}
+
+impl<S: IpcSender + ?Sized> IpcSender for &S {
+    fn max_send_msg_size(&self) -> usize {
+        (**self).max_send_msg_size()
+    }
+
+    fn send_msg(&self, buf: &[u8]) -> Result<()> {
+        (**self).send_msg(buf)
+    }
+
+    fn send_msg_timeout(&self, buf: &[u8], timeout: Timeout) -> Result<bool> {
+        (**self).send_msg_timeout(buf, timeout)
+    }
+}
+
+impl<R: IpcReceiver + ?Sized> IpcReceiver for Box<R> {
+    fn max_recv_msg_size(&self) -> usize {
+        (**self).max_recv_msg_size()
+    }
+
+    fn recv_msg(&self, buf: &mut [u8]) -> Result<usize> {
+        (**self).recv_msg(buf)
+    }
+
+    fn recv_msg_timeout(&self, buf: &mut [u8], timeout: Timeout) -> Result<Option<usize>> {
+        (**self).recv_msg_timeout(buf, timeout)
+    }
+}
+
+impl<R: IpcReceiver + ?Sized> IpcReceiver for &R {
+    fn max_recv_msg_size(&self) -> usize {
+        (**self).max_recv_msg_size()
+    }
+
+    fn recv_msg(&self, buf: &mut [u8]) -> Result<usize> {
+        (**self).recv_msg(buf)
+    }
+
+    fn recv_msg_timeout(&self, buf: &mut [u8], timeout: Timeout) -> Result<Option<usize>> {
+        (**self).recv_msg_timeout(buf, timeout)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// Sends a packet when the handle is ready for it, waiting up to the
+/// timeout.
+///
+/// A non-blocking handle that loses the room to another sender goes back
+/// to waiting.
+#[cfg(any(
+    feature = "fifo",
+    all(
+        any(feature = "pipe", feature = "seqpacket"),
+        any(target_os = "android", target_os = "linux")
+    )
+))]
+fn send_when_ready<P>(tx: &P, buf: &[u8], timeout: Timeout) -> Result<bool>
+where
+    P: PacketSender + AsFd,
+{
+    // Fix the deadline for all the tries
+    let timeout = timeout.deadline().map_or(Timeout::None, Timeout::Deadline);
+    loop {
+        if !timeout::poll_fd(tx.as_fd(), PollFlags::POLLOUT, timeout)? {
+            return Ok(false);
+        }
+        match tx.send_packet(buf) {
+            Err(err) if err == Error::EAGAIN => continue,
+            res => return res.map(|_| true),
+        }
+    }
+}
+
+/// Receives a packet when the handle has one, waiting up to the timeout.
+///
+/// A non-blocking handle that loses the packet to another receiver goes
+/// back to waiting.
+#[cfg(any(
+    feature = "fifo",
+    all(
+        any(feature = "pipe", feature = "seqpacket"),
+        any(target_os = "android", target_os = "linux")
+    )
+))]
+fn recv_when_ready<P>(rx: &P, buf: &mut [u8], timeout: Timeout) -> Result<Option<usize>>
+where
+    P: PacketReceiver + AsFd,
+{
+    let timeout = timeout.deadline().map_or(Timeout::None, Timeout::Deadline);
+    loop {
+        if !timeout::poll_fd(rx.as_fd(), PollFlags::POLLIN, timeout)? {
+            return Ok(None);
+        }
+        match rx.recv_packet(buf) {
+            Err(err) if err == Error::EAGAIN => continue,
+            res => return res.map(Some),
+        }
+    }
+}
+
+// Implements the traits for a transport that's a file handle, through
+// its packet traits.
+
+#[allow(unused_macros)]
+macro_rules! impl_ipc_sender {
+    ($ty:ty) => {
+        impl IpcSender for $ty {
+            fn max_send_msg_size(&self) -> usize {
+                self.max_send_size()
+            }
+
+            fn send_msg(&self, buf: &[u8]) -> Result<()> {
+                self.send_packet(buf)
+            }
+
+            fn send_msg_timeout(&self, buf: &[u8], timeout: Timeout) -> Result<bool> {
+                send_when_ready(self, buf, timeout)
+            }
+        }
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! impl_ipc_receiver {
+    ($ty:ty) => {
+        impl IpcReceiver for $ty {
+            fn max_recv_msg_size(&self) -> usize {
+                self.max_recv_size()
+            }
+
+            fn recv_msg(&self, buf: &mut [u8]) -> Result<usize> {
+                self.recv_packet(buf)
+            }
+
+            fn recv_msg_timeout(&self, buf: &mut [u8], timeout: Timeout) -> Result<Option<usize>> {
+                recv_when_ready(self, buf, timeout)
+            }
+        }
+    };
+}
+
+#[cfg(all(feature = "seqpacket", any(target_os = "android", target_os = "linux")))]
+impl_ipc_sender!(SeqPacket);
+
+#[cfg(all(feature = "seqpacket", any(target_os = "android", target_os = "linux")))]
+impl_ipc_receiver!(SeqPacket);
+
+#[cfg(all(feature = "pipe", any(target_os = "android", target_os = "linux")))]
+impl_ipc_sender!(WritePipe);
+
+#[cfg(all(feature = "pipe", any(target_os = "android", target_os = "linux")))]
+impl_ipc_receiver!(ReadPipe);
+
+#[cfg(feature = "fifo")]
+impl_ipc_sender!(Fifo);
+
+#[cfg(feature = "fifo")]
+impl_ipc_receiver!(Fifo);
+
+// Message queues have their own timed calls, which work on the systems
+// where the queue isn't a file handle.
+
+#[cfg(all(
+    feature = "msgqueue",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "netbsd"
+    )
+))]
+impl IpcSender for MsgQueue {
+    fn max_send_msg_size(&self) -> usize {
+        self.msg_size()
+    }
+
+    fn send_msg(&self, buf: &[u8]) -> Result<()> {
+        self.send(buf)
+    }
+
+    fn send_msg_timeout(&self, buf: &[u8], timeout: Timeout) -> Result<bool> {
+        match self.send_timeout(buf, DEFAULT_PRIO, timeout) {
+            Ok(()) => Ok(true),
+            Err(err) if err == Error::ETIMEDOUT || err == Error::EAGAIN => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(all(
+    feature = "msgqueue",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "netbsd"
+    )
+))]
+impl IpcReceiver for MsgQueue {
+    fn max_recv_msg_size(&self) -> usize {
+        self.msg_size()
+    }
+
+    fn recv_msg(&self, buf: &mut [u8]) -> Result<usize> {
+        self.receive(buf)
+    }
+
+    fn recv_msg_timeout(&self, buf: &mut [u8], timeout: Timeout) -> Result<Option<usize>> {
+        let mut prio = 0;
+        match self.receive_timeout(buf, &mut prio, timeout) {
+            Ok(n) => Ok(Some(n)),
+            Err(err) if err == Error::ETIMEDOUT || err == Error::EAGAIN => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::RawCodec;
+    use std::time::Duration;
+
+    // Runs the same checks over any pair of transport ends.
+    #[allow(dead_code)]
+    fn check_transport<'a>(tx: Box<dyn IpcSender + 'a>, rx: Box<dyn IpcReceiver + 'a>) {
+        let timeout = Timeout::from(Duration::from_millis(10));
+        let mut buf = vec![0u8; rx.max_recv_msg_size()];
+
+        assert_eq!(None, rx.try_recv_msg(&mut buf).unwrap());
+        assert_eq!(None, rx.recv_msg_timeout(&mut buf, timeout).unwrap());
+
+        tx.send_msg(b"one").unwrap();
+        assert!(tx.try_send_msg(b"two").unwrap());
+        assert!(tx
+            .send_typed_timeout(&RawCodec, &b"three".to_vec(), timeout)
+            .unwrap());
+
+        let n = rx.recv_msg(&mut buf).unwrap();
+        assert_eq!(b"one", &buf[..n]);
+        assert_eq!(Some(3), rx.try_recv_msg(&mut buf).unwrap());
+        assert_eq!(b"two", &buf[..3]);
+        assert_eq!(
+            Some(b"three".to_vec()),
+            rx.recv_typed_timeout(&RawCodec, timeout).unwrap()
+        );
+        assert_eq!(None, rx.try_recv_typed(&RawCodec).unwrap());
+
+        let pool = BufferPool::new(rx.max_recv_msg_size(), 1);
+        tx.send_msg(b"four").unwrap();
+        assert_eq!(b"four", &*rx.recv_pooled(&pool).unwrap());
+    }
+
+    #[cfg(all(feature = "seqpacket", any(target_os = "android", target_os = "linux")))]
+    #[test]
+    fn test_seqpacket() {
+        let (a, b) = SeqPacket::pair().unwrap();
+        check_transport(Box::new(a), Box::new(b));
+    }
+
+    #[cfg(all(feature = "pipe", any(target_os = "android", target_os = "linux")))]
+    #[test]
+    fn test_packet_pipe() {
+        let (wr, rd) = crate::pipe::packet_pipe().unwrap();
+        check_transport(Box::new(wr), Box::new(rd));
+    }
+
+    #[cfg(feature = "fifo")]
+    #[test]
+    fn test_fifo() {
+        use crate::fifo;
+        use nix::{sys::stat::Mode, unistd};
+        use std::{env, fs};
+
+        let path = env::temp_dir().join(format!("hinix-ipc-fifo-{}", unistd::getpid()));
+        let _ = fs::remove_file(&path);
+        fifo::mkfifo(&path, Mode::from_bits_truncate(0o600)).unwrap();
+
+        let rd = Fifo::open_read_nonblocking(&path).unwrap();
+        let wr = Fifo::open_write(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        check_transport(Box::new(wr), Box::new(rd));
+    }
+
+    #[cfg(all(feature = "msgqueue", target_os = "linux"))]
+    #[test]
+    fn test_msgqueue() {
+        const NAME: &str = "/hinix-ipc-test";
+        let _ = MsgQueue::unlink(NAME);
+        let tx = MsgQueue::create(NAME, 3, 64).unwrap();
+        let rx = MsgQueue::open(NAME).unwrap();
+        MsgQueue::unlink(NAME).unwrap();
+
+        check_transport(Box::new(&tx), Box::new(rx));
+
+        // The queue only holds three messages
+        for _ in 0..3 {
+            assert!(tx.try_send_msg(b"x").unwrap());
+        }
+        assert!(!tx.try_send_msg(b"x").unwrap());
+    }
+}
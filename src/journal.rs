@@ -0,0 +1,351 @@
+// hinix/src/journal.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Structured logging to the systemd journal.
+//!
+//! This speaks the journald native protocol directly, without linking to
+//! libsystemd. Each record is a set of KEY=value fields, which are
+//! stored, and can be queried, individually:
+//!
+//! ```no_run
+//! use hinix::{journal::{Journal, Record}, syslog::Level};
+//!
+//! let journal = Journal::new().unwrap();
+//! let rec = Record::new(Level::Warning, "Disk almost full")
+//!     .field("DEVICE", "/dev/sda1")
+//!     .field("PERCENT_USED", "97");
+//! journal.send(&rec).unwrap();
+//! ```
+//!
+//! Records that are too large to send in a single datagram are written
+//! to a sealed memfd, which is passed to journald instead.
+//!
+//! See:
+//! <https://systemd.io/JOURNAL_NATIVE_PROTOCOL/>
+//!
+
+use crate::{error::ResultExt, fd, syslog::Level, Error, Result};
+use nix::{
+    errno::Errno,
+    fcntl::{self, FcntlArg, SealFlag},
+    sys::{
+        memfd::{self, MemFdCreateFlag},
+        socket::{self, AddressFamily, ControlMessage, MsgFlags, SockFlag, SockType, UnixAddr},
+    },
+    unistd,
+};
+use std::{
+    ffi::CString,
+    io::IoSlice,
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    path::Path,
+};
+
+/// The path to the journald socket for native messages.
+pub const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// The maximum length of a field name.
+const MAX_NAME_LEN: usize = 64;
+
+/// Determines if the string is a valid name for a field.
+///
+/// A name is made of uppercase letters, digits, and underscores, but
+/// can't start with a digit. Names starting with an underscore are
+/// reserved for trusted fields added by journald.
+fn is_valid_name(name: &str) -> bool {
+    let b = name.as_bytes();
+    !b.is_empty()
+        && b.len() <= MAX_NAME_LEN
+        && !b[0].is_ascii_digit()
+        && b[0] != b'_'
+        && b.iter()
+            .all(|&c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == b'_')
+}
+
+/// A structured record for the journal.
+///
+/// The record is built up with a chain of calls to [`Record::field()`].
+/// An invalid field name is reported as `EINVAL` when the record is sent.
+#[derive(Debug, Clone, Default)]
+pub struct Record {
+    /// The fields, in order
+    fields: Vec<(String, Vec<u8>)>,
+    /// Whether any of the field names was invalid
+    invalid: bool,
+}
+
+impl Record {
+    /// Creates a record with a MESSAGE and PRIORITY.
+    pub fn new(level: Level, msg: &str) -> Self {
+        Self::default()
+            .field("PRIORITY", level.as_raw().to_string())
+            .field("MESSAGE", msg)
+    }
+
+    /// Adds a field to the record.
+    ///
+    /// The value can contain any data, including newlines or binary.
+    /// A field can be added more than once, to give it multiple values.
+    pub fn field<V: AsRef<[u8]>>(mut self, name: &str, val: V) -> Self {
+        if is_valid_name(name) {
+            self.fields.push((name.to_string(), val.as_ref().to_vec()));
+        }
+        else {
+            self.invalid = true;
+        }
+        self
+    }
+
+    /// Gets the (first) value of a field in the record.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, val)| val.as_slice())
+    }
+
+    /// Encodes the record into the native journal format.
+    ///
+    /// A value without newlines is sent as "KEY=value\n". Otherwise it's
+    /// sent as "KEY\n" followed by the length as a 64-bit little endian
+    /// integer, then the value, and a newline.
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for (name, val) in &self.fields {
+            buf.extend_from_slice(name.as_bytes());
+            if val.contains(&b'\n') {
+                buf.push(b'\n');
+                buf.extend_from_slice(&(val.len() as u64).to_le_bytes());
+            }
+            else {
+                buf.push(b'=');
+            }
+            buf.extend_from_slice(val);
+            buf.push(b'\n');
+        }
+        buf
+    }
+}
+
+/// A connection to the journal.
+#[derive(Debug)]
+pub struct Journal {
+    /// The unbound datagram socket
+    fd: OwnedFd,
+    /// The address of the journal socket
+    addr: UnixAddr,
+}
+
+impl Journal {
+    /// Opens a connection to the system journal.
+    pub fn new() -> Result<Self> {
+        Self::with_path(JOURNAL_SOCKET)
+    }
+
+    /// Opens a connection to a journal listening on the socket at the
+    /// specified path.
+    pub fn with_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let addr = UnixAddr::new(path.as_ref())?;
+        let fd = socket::socket(
+            AddressFamily::Unix,
+            SockType::Datagram,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        Ok(Self { fd, addr })
+    }
+
+    /// Sends a record to the journal.
+    pub fn send(&self, rec: &Record) -> Result<()> {
+        if rec.invalid || rec.fields.is_empty() {
+            return Err(Error::EINVAL);
+        }
+        let buf = rec.encode();
+
+        match socket::sendto(
+            self.fd.as_raw_fd(),
+            &buf,
+            &self.addr,
+            MsgFlags::MSG_NOSIGNAL,
+        ) {
+            Err(Errno::EMSGSIZE) | Err(Errno::ENOBUFS) => self.send_memfd(&buf),
+            res => res.map(|_| ()).op("sendto"),
+        }
+    }
+
+    /// Sends a large record by writing it to a sealed memfd, and passing
+    /// the file descriptor to the journal.
+    fn send_memfd(&self, buf: &[u8]) -> Result<()> {
+        let name = CString::new("hinix-journal").unwrap();
+        let fd = memfd::memfd_create(
+            &name,
+            MemFdCreateFlag::MFD_CLOEXEC | MemFdCreateFlag::MFD_ALLOW_SEALING,
+        )?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut off = 0;
+        while off < buf.len() {
+            off += unistd::write(fd.as_raw_fd(), &buf[off..])?;
+        }
+
+        // journald only accepts a memfd that can't be changed
+        let seals = SealFlag::F_SEAL_SHRINK
+            | SealFlag::F_SEAL_GROW
+            | SealFlag::F_SEAL_WRITE
+            | SealFlag::F_SEAL_SEAL;
+        fcntl::fcntl(fd.as_raw_fd(), FcntlArg::F_ADD_SEALS(seals))?;
+
+        let fds = [fd.as_raw_fd()];
+        let iov: [IoSlice; 0] = [];
+        socket::sendmsg(
+            self.fd.as_raw_fd(),
+            &iov,
+            &[ControlMessage::ScmRights(&fds)],
+            MsgFlags::MSG_NOSIGNAL,
+            Some(&self.addr),
+        )?;
+        Ok(())
+    }
+}
+
+impl AsFd for Journal {
+    /// Gets the file handle for the socket.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for Journal {
+    /// Gets the raw file handle for the socket.
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for Journal {
+    /// Gives up ownership of the file handle for the socket.
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+impl From<Journal> for OwnedFd {
+    fn from(journal: Journal) -> Self {
+        journal.fd
+    }
+}
+
+impl TryFrom<OwnedFd> for Journal {
+    type Error = Error;
+
+    /// Uses an unbound Unix datagram socket to send to the system
+    /// journal.
+    ///
+    /// This fails with `EINVAL` if the handle is some other kind of socket.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        fd::check_socket(fd.as_fd(), libc::AF_UNIX, SockType::Datagram)?;
+        Ok(Self {
+            fd,
+            addr: UnixAddr::new(JOURNAL_SOCKET)?,
+        })
+    }
+}
+
+/// Sends a simple message to the system journal.
+pub fn send(level: Level, msg: &str) -> Result<()> {
+    Journal::new()?.send(&Record::new(level, msg))
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::{cmsg_space, sys::socket::ControlMessageOwned};
+    use std::{
+        env, fs,
+        io::IoSliceMut,
+        os::{
+            raw::c_int,
+            unix::{io::RawFd, net::UnixDatagram},
+        },
+        process,
+    };
+
+    #[test]
+    fn test_names() {
+        assert!(is_valid_name("MESSAGE"));
+        assert!(is_valid_name("CODE_LINE2"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("_PID"));
+        assert!(!is_valid_name("2FAST"));
+        assert!(!is_valid_name("lower"));
+        assert!(!is_valid_name(&"X".repeat(MAX_NAME_LEN + 1)));
+    }
+
+    #[test]
+    fn test_encode() {
+        let rec = Record::new(Level::Err, "oops").field("DATA", "two\nlines");
+        assert_eq!(Some(&b"3"[..]), rec.get("PRIORITY"));
+
+        let mut expected = b"PRIORITY=3\nMESSAGE=oops\nDATA\n".to_vec();
+        expected.extend_from_slice(&9u64.to_le_bytes());
+        expected.extend_from_slice(b"two\nlines\n");
+        assert_eq!(expected, rec.encode());
+    }
+
+    #[test]
+    fn test_send() {
+        let path = env::temp_dir().join(format!("hinix-journal-{}", process::id()));
+        let _ = fs::remove_file(&path);
+        let sock = UnixDatagram::bind(&path).unwrap();
+        let journal = Journal::with_path(&path).unwrap();
+
+        let rec = Record::new(Level::Info, "hello").field("bad", "name");
+        assert_eq!(Error::EINVAL, journal.send(&rec).unwrap_err());
+
+        let rec = Record::new(Level::Info, "hello");
+        journal.send(&rec).unwrap();
+        let mut buf = vec![0u8; 1024];
+        let n = sock.recv(&mut buf).unwrap();
+        assert_eq!(b"PRIORITY=6\nMESSAGE=hello\n", &buf[..n]);
+
+        // Too big for a datagram, so it's sent in a memfd
+        let big = "x".repeat(1 << 22);
+        let rec = Record::new(Level::Info, &big);
+        journal.send(&rec).unwrap();
+
+        let mut iov = [IoSliceMut::new(&mut buf)];
+        let mut cmsg = cmsg_space!([RawFd; 1]);
+        let msg = socket::recvmsg::<()>(
+            sock.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg),
+            MsgFlags::empty(),
+        )
+        .unwrap();
+        assert_eq!(0, msg.bytes);
+
+        let fd = match msg.cmsgs().next() {
+            Some(ControlMessageOwned::ScmRights(fds)) => fds[0],
+            other => panic!("Unexpected control message: {:?}", other),
+        };
+        let file = fs::File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+        assert_eq!(rec.encode().len() as u64, file.metadata().unwrap().len());
+
+        let seals = fcntl::fcntl(fd, FcntlArg::F_GET_SEALS).unwrap();
+        assert_ne!(0, seals & SealFlag::F_SEAL_WRITE.bits() as c_int);
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,146 @@
+// hinix/src/kqueue.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! kqueue-based equivalents of the Linux timer, signal, and process
+//! handles.
+//!
+//! The BSDs and macOS don't have timerfd, signalfd, or pidfd, but a
+//! kqueue can watch for the same things with its `EVFILT_TIMER`,
+//! `EVFILT_SIGNAL`, and `EVFILT_PROC` filters. On those systems, the
+//! crate's `timerfd`, `signalfd`, and `pidfd` modules are the ones in
+//! here. They have the same types and calls as on Linux, each built on a
+//! kqueue that watches the single source. The kqueue handle becomes
+//! readable when there's an event, so it can be waited on in a poll or
+//! select call like the Linux handles.
+//!
+//! The state that Linux keeps in the kernel object, like a timer's
+//! schedule, is kept in the Rust object here, so these handles can't be
+//! taken from, or given up as, a raw file descriptor.
+//!
+
+use crate::{
+    clock,
+    error::ResultExt,
+    timeout::{Deadline, Timeout},
+    Error, Result,
+};
+use nix::{
+    errno::Errno,
+    fcntl::{self, FcntlArg, FdFlag, OFlag},
+};
+use std::{
+    mem,
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    ptr,
+};
+
+#[cfg(feature = "pidfd")]
+pub mod pidfd;
+
+#[cfg(feature = "signalfd")]
+pub mod signalfd;
+
+#[cfg(feature = "timerfd")]
+pub mod timerfd;
+
+// NetBSD uses wider types for the filter and flags of an event.
+
+#[cfg(target_os = "netbsd")]
+type Filter = u32;
+#[cfg(not(target_os = "netbsd"))]
+type Filter = i16;
+
+#[cfg(target_os = "netbsd")]
+type Flags = u32;
+#[cfg(not(target_os = "netbsd"))]
+type Flags = u16;
+
+/// A kqueue that watches a single event source.
+#[derive(Debug)]
+pub(crate) struct Kqueue(OwnedFd);
+
+impl Kqueue {
+    /// Creates a new, empty, kqueue.
+    pub fn new() -> Result<Self> {
+        let fd = Errno::result(unsafe { libc::kqueue() }).op("kqueue")?;
+        let kq = Self(unsafe { OwnedFd::from_raw_fd(fd) });
+        fcntl::fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).op("fcntl")?;
+        Ok(kq)
+    }
+
+    /// Adds, changes, or removes an event source.
+    pub fn change(
+        &self,
+        ident: usize,
+        filter: Filter,
+        flags: Flags,
+        fflags: u32,
+        data: i64,
+    ) -> Result<()> {
+        let mut ev: libc::kevent = unsafe { mem::zeroed() };
+        ev.ident = ident as _;
+        ev.filter = filter;
+        ev.flags = flags;
+        ev.fflags = fflags;
+        ev.data = data as _;
+
+        let ret =
+            unsafe { libc::kevent(self.0.as_raw_fd(), &ev, 1, ptr::null_mut(), 0, ptr::null()) };
+        Errno::result(ret).map(drop).op("kevent")
+    }
+
+    /// Waits until the deadline for an event.
+    ///
+    /// Returns `None` if the deadline passed first. A wait that's
+    /// interrupted by a signal is restarted for the time remaining.
+    pub fn wait(&self, deadline: &Deadline) -> Result<Option<libc::kevent>> {
+        let mut ev: libc::kevent = unsafe { mem::zeroed() };
+        loop {
+            let ts = deadline.remaining().map(clock::to_timespec).transpose()?;
+            let ts = ts.as_ref().map_or(ptr::null(), |ts| ts as *const _);
+
+            let ret = unsafe { libc::kevent(self.0.as_raw_fd(), ptr::null(), 0, &mut ev, 1, ts) };
+            match Errno::result(ret) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(ev)),
+                Err(Errno::EINTR) => continue,
+                Err(err) => return Err(err).op("kevent"),
+            }
+        }
+    }
+
+    /// Waits for an event, honoring the non-blocking flag of the handle,
+    /// which kevent() itself ignores.
+    ///
+    /// In non-blocking mode, this fails with `EAGAIN` if there is no
+    /// event ready.
+    pub fn wait_event(&self) -> Result<libc::kevent> {
+        let flags = fcntl::fcntl(self.0.as_raw_fd(), FcntlArg::F_GETFL).op("fcntl")?;
+        let timeout = match OFlag::from_bits_truncate(flags).contains(OFlag::O_NONBLOCK) {
+            true => Timeout::ZERO,
+            false => Timeout::None,
+        };
+        self.wait(&Deadline::start(timeout))?.ok_or(Error::EAGAIN)
+    }
+}
+
+impl AsFd for Kqueue {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for Kqueue {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
@@ -0,0 +1,227 @@
+// hinix/src/kqueue/pidfd.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Process handles, using kqueue.
+//!
+//! This has the same interface as the Linux pidfd module. The handle is a
+//! kqueue with an `EVFILT_PROC` event for the exit of the process, so it
+//! becomes readable when the process exits, and can be used in a poll
+//! loop along with other handles.
+//!
+//! Unlike a Linux pidfd, the handle doesn't pin the identity of the
+//! process. The calls that act on it by PID, like sending a signal or
+//! waiting for it, are only free of races for a child of the calling
+//! process, since its PID can't be reused until it's reaped. The handle
+//! keeps track of whether it reaped the process itself, and refuses to
+//! act on the PID after that.
+//!
+//! See:
+//! <https://man.freebsd.org/cgi/man.cgi?query=kqueue&sektion=2>
+//!
+
+use super::Kqueue;
+use crate::{
+    error::ResultExt,
+    timeout::{Deadline, Timeout},
+    Error, Result,
+};
+use nix::{
+    errno::Errno,
+    sys::{
+        signal::{self, Signal},
+        wait::{self, WaitPidFlag, WaitStatus},
+    },
+    unistd::Pid,
+};
+use std::{
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// A handle that refers to a process.
+#[derive(Debug)]
+pub struct PidFd {
+    /// The kqueue with the exit event for the process
+    kq: Kqueue,
+    /// The process
+    pid: Pid,
+    /// Whether the process is known to have exited
+    exited: AtomicBool,
+    /// Whether the process was reaped through this handle
+    reaped: AtomicBool,
+}
+
+impl PidFd {
+    /// Opens a handle to an existing process.
+    ///
+    /// Note that there's an inherent race in opening a handle from a PID,
+    /// since the process could exit and the PID be reused before the call.
+    /// This is safe for a child of the calling process that hasn't been
+    /// waited on, since its PID can't be reused until then.
+    pub fn open(pid: Pid) -> Result<Self> {
+        let kq = Kqueue::new()?;
+        let exited = match kq.change(
+            pid.as_raw() as usize,
+            libc::EVFILT_PROC,
+            libc::EV_ADD | libc::EV_ONESHOT,
+            libc::NOTE_EXIT,
+            0,
+        ) {
+            Ok(()) => false,
+            // Some systems won't watch a zombie, but it can still be
+            // signaled and reaped.
+            Err(err) if err == Error::ESRCH => {
+                signal::kill(pid, None).op("kill")?;
+                true
+            }
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            kq,
+            pid,
+            exited: AtomicBool::new(exited),
+            reaped: AtomicBool::new(false),
+        })
+    }
+
+    /// Gets the PID of the process.
+    ///
+    /// Fails with `ESRCH` if the process was reaped through this handle.
+    pub fn pid(&self) -> Result<Pid> {
+        match self.reaped.load(Ordering::Acquire) {
+            true => Err(Error::ESRCH),
+            false => Ok(self.pid),
+        }
+    }
+
+    /// Sends a signal to the process.
+    ///
+    /// Fails with `ESRCH` if the process was reaped through this handle.
+    pub fn send_signal(&self, sig: Signal) -> Result<()> {
+        signal::kill(self.pid()?, sig).op("kill")
+    }
+
+    /// Waits for the process to exit, with an optional timeout.
+    ///
+    /// This works for any process, not just children of the caller, but
+    /// doesn't reap it or get its exit status.
+    ///
+    /// Returns `true` if the process exited, or `false` on a timeout.
+    pub fn poll_exit<T: Into<Timeout>>(&self, timeout: T) -> Result<bool> {
+        if self.exited.load(Ordering::Acquire) {
+            return Ok(true);
+        }
+        // The exit event is only reported once
+        let exited = self.kq.wait(&Deadline::start(timeout))?.is_some();
+        if exited {
+            self.exited.store(true, Ordering::Release);
+        }
+        Ok(exited)
+    }
+
+    /// Waits for the process to exit and reaps it, returning its status.
+    ///
+    /// The process must be a child of the caller, otherwise this fails
+    /// with `ECHILD`.
+    pub fn wait(&self) -> Result<WaitStatus> {
+        self.waitpid(None)
+    }
+
+    /// Checks whether the process has exited, and if so, reaps it and
+    /// returns its status.
+    ///
+    /// The process must be a child of the caller, otherwise this fails
+    /// with `ECHILD`.
+    pub fn try_wait(&self) -> Result<Option<WaitStatus>> {
+        match self.waitpid(Some(WaitPidFlag::WNOHANG))? {
+            WaitStatus::StillAlive => Ok(None),
+            status => Ok(Some(status)),
+        }
+    }
+
+    fn waitpid(&self, flags: Option<WaitPidFlag>) -> Result<WaitStatus> {
+        let pid = self.pid().map_err(|_| Error::ECHILD)?;
+        let status = loop {
+            match wait::waitpid(pid, flags) {
+                Err(Errno::EINTR) => continue,
+                res => break res.op("waitpid")?,
+            }
+        };
+        if status != WaitStatus::StillAlive {
+            self.exited.store(true, Ordering::Release);
+            self.reaped.store(true, Ordering::Release);
+        }
+        Ok(status)
+    }
+}
+
+impl AsFd for PidFd {
+    /// Gets the file handle for the process.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.kq.as_fd()
+    }
+}
+
+impl AsRawFd for PidFd {
+    /// Gets the raw file handle for the process.
+    fn as_raw_fd(&self) -> RawFd {
+        self.kq.as_raw_fd()
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::unistd::{self, ForkResult};
+    use std::{process, thread, time::Duration};
+
+    // Forks a child that runs the function, then exits with the code.
+    fn spawn_child<F: FnOnce()>(f: F, code: i32) -> Pid {
+        match unsafe { unistd::fork() }.unwrap() {
+            ForkResult::Child => {
+                f();
+                process::exit(code);
+            }
+            ForkResult::Parent { child } => child,
+        }
+    }
+
+    #[test]
+    fn test_wait() {
+        let child = spawn_child(|| thread::sleep(Duration::from_millis(50)), 3);
+        let pidfd = PidFd::open(child).unwrap();
+        assert_eq!(child, pidfd.pid().unwrap());
+
+        assert_eq!(None, pidfd.try_wait().unwrap());
+        assert!(!pidfd.poll_exit(Some(Duration::ZERO)).unwrap());
+
+        assert!(pidfd.poll_exit(None).unwrap());
+        assert!(pidfd.poll_exit(None).unwrap());
+        assert_eq!(WaitStatus::Exited(child, 3), pidfd.wait().unwrap());
+        assert_eq!(Error::ESRCH, pidfd.pid().unwrap_err());
+    }
+
+    #[test]
+    fn test_signal() {
+        let child = spawn_child(|| thread::sleep(Duration::from_secs(10)), 0);
+        let pidfd = PidFd::open(child).unwrap();
+
+        pidfd.send_signal(Signal::SIGTERM).unwrap();
+        assert_eq!(
+            WaitStatus::Signaled(child, Signal::SIGTERM, false),
+            pidfd.wait().unwrap()
+        );
+    }
+}
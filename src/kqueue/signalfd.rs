@@ -0,0 +1,205 @@
+// hinix/src/kqueue/signalfd.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Receiving signals through a file handle, using kqueue.
+//!
+//! This has the same interface as the Linux signalfd module. Each signal
+//! in the set is an `EVFILT_SIGNAL` event in a kqueue, which records
+//! every attempt to deliver the signal to the process.
+//!
+//! As on Linux, the signals must be blocked so that they aren't delivered
+//! in the normal way. [`SignalFd::new()`] blocks them for the calling
+//! thread, but they should also be blocked in every other thread, which
+//! is easiest to do in the main thread before any others are started.
+//! When a signal is read, it's also taken off the list of signals pending
+//! for the process, if it's there.
+//!
+//! kqueue doesn't report who sent a signal, or why, so the signal number
+//! is the only information in a [`SigInfo`].
+//!
+//! See:
+//! <https://man.freebsd.org/cgi/man.cgi?query=kqueue&sektion=2>
+//!
+
+use super::Kqueue;
+use crate::{
+    timeout::{Deadline, Timeout},
+    Result,
+};
+use nix::{
+    sys::signal::{SigSet, Signal},
+    unistd::{Pid, Uid},
+};
+use std::{
+    mem,
+    os::{
+        raw::c_int,
+        unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd},
+    },
+};
+
+/// Information about a signal that was received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SigInfo {
+    /// The signal
+    pub signal: Signal,
+    /// The process that sent the signal. This is always zero.
+    pub pid: Pid,
+    /// The real user ID of the sender. This is always zero.
+    pub uid: Uid,
+    /// The reason the signal was sent. This is always zero.
+    pub code: i32,
+    /// The exit status or signal, for SIGCHLD. This is always zero.
+    pub status: i32,
+}
+
+impl SigInfo {
+    fn from_signal(signal: Signal) -> Self {
+        Self {
+            signal,
+            pid: Pid::from_raw(0),
+            uid: Uid::from_raw(0),
+            code: 0,
+            status: 0,
+        }
+    }
+}
+
+/// A handle that receives signals.
+#[derive(Debug)]
+pub struct SignalFd {
+    /// The kqueue with an event for each signal
+    kq: Kqueue,
+    /// The signals being received
+    mask: SigSet,
+}
+
+impl SignalFd {
+    /// Creates a handle to receive the set of signals, and blocks them in
+    /// the calling thread.
+    pub fn new(signals: &SigSet) -> Result<Self> {
+        let mut sfd = Self {
+            kq: Kqueue::new()?,
+            mask: SigSet::empty(),
+        };
+        sfd.set_mask(signals)?;
+        Ok(sfd)
+    }
+
+    /// Changes the set of signals received by the handle, and blocks them
+    /// in the calling thread.
+    ///
+    /// Signals that were removed from the set are not unblocked.
+    pub fn set_mask(&mut self, signals: &SigSet) -> Result<()> {
+        signals.thread_block()?;
+        for sig in Signal::iterator() {
+            match (self.mask.contains(sig), signals.contains(sig)) {
+                (false, true) => {
+                    self.kq
+                        .change(sig as usize, libc::EVFILT_SIGNAL, libc::EV_ADD, 0, 0)?;
+                    self.mask.add(sig);
+                }
+                (true, false) => {
+                    self.kq
+                        .change(sig as usize, libc::EVFILT_SIGNAL, libc::EV_DELETE, 0, 0)?;
+                    self.mask.remove(sig);
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets the information for a signal event, and takes the signal off
+    /// the pending list, so that it isn't delivered when it's unblocked.
+    fn received(&self, ev: libc::kevent) -> Result<SigInfo> {
+        let signal = Signal::try_from(ev.ident as c_int)?;
+
+        let mut pending: libc::sigset_t = unsafe { mem::zeroed() };
+        if unsafe { libc::sigpending(&mut pending) } == 0
+            && unsafe { libc::sigismember(&pending, signal as c_int) } == 1
+        {
+            let mut set = SigSet::empty();
+            set.add(signal);
+            set.wait()?;
+        }
+        Ok(SigInfo::from_signal(signal))
+    }
+
+    /// Waits for, and reads, the next signal.
+    ///
+    /// If the handle is in non-blocking mode, this fails with `EAGAIN` if
+    /// there is no pending signal.
+    pub fn read(&self) -> Result<SigInfo> {
+        let ev = self.kq.wait_event()?;
+        self.received(ev)
+    }
+
+    /// Waits up to the timeout for the next signal.
+    ///
+    /// Returns `None` if the timeout expired first.
+    pub fn read_timeout<T: Into<Timeout>>(&self, timeout: T) -> Result<Option<SigInfo>> {
+        match self.kq.wait(&Deadline::start(timeout))? {
+            Some(ev) => self.received(ev).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl AsFd for SignalFd {
+    /// Gets the file handle for the signals.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.kq.as_fd()
+    }
+}
+
+impl AsRawFd for SignalFd {
+    /// Gets the raw file handle for the signals.
+    fn as_raw_fd(&self) -> RawFd {
+        self.kq.as_raw_fd()
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fd::FdExt, test_util::in_child, Error};
+    use nix::sys::signal;
+    use std::time::Duration;
+
+    #[test]
+    fn test_signalfd() {
+        in_child(|| {
+            let mut mask = SigSet::empty();
+            mask.add(Signal::SIGUSR1);
+            let mut sfd = SignalFd::new(&mask).unwrap();
+
+            assert_eq!(None, sfd.read_timeout(Duration::from_millis(10)).unwrap());
+
+            signal::raise(Signal::SIGUSR1).unwrap();
+            let info = sfd.read().unwrap();
+            assert_eq!(Signal::SIGUSR1, info.signal);
+
+            mask.add(Signal::SIGUSR2);
+            sfd.set_mask(&mask).unwrap();
+            sfd.set_nonblocking(true).unwrap();
+            assert_eq!(Error::EAGAIN, sfd.read().unwrap_err());
+
+            signal::raise(Signal::SIGUSR2).unwrap();
+            let info = sfd.read_timeout(Duration::from_secs(1)).unwrap();
+            assert_eq!(Some(Signal::SIGUSR2), info.map(|i| i.signal));
+        });
+    }
+}
@@ -0,0 +1,279 @@
+// hinix/src/kqueue/timerfd.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Timers that are notified through a file handle, using kqueue.
+//!
+//! This has the same interface as the Linux timerfd module. The timer is
+//! an `EVFILT_TIMER` event in a kqueue, which is armed for one expiration
+//! at a time, then re-armed for the next one after it's read, so that
+//! periodic and absolute timers keep to their schedule. Each read returns
+//! the number of times the timer expired since the last read.
+//!
+//! The kqueue timers have a resolution of a millisecond.
+//!
+//! See:
+//! <https://man.freebsd.org/cgi/man.cgi?query=kqueue&sektion=2>
+//!
+
+use super::Kqueue;
+use crate::{
+    clock::{self, ClockId},
+    fd::FdExt,
+    Error, Result,
+};
+use bitflags::bitflags;
+use std::{
+    os::{
+        raw::c_int,
+        unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd},
+    },
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+bitflags! {
+    /// The flags used to create a TimerFd
+    pub struct TimerFlags: c_int {
+        /// Create the timer in non-blocking mode
+        const TFD_NONBLOCK = libc::O_NONBLOCK;
+        /// Close the timer handle on exec. This is always set.
+        const TFD_CLOEXEC = libc::O_CLOEXEC;
+    }
+}
+
+/// The times that a timer is set to expire.
+#[derive(Debug, Default, Clone, Copy)]
+struct Schedule {
+    /// The next expiration, or `None` if the timer is disarmed
+    next: Option<Instant>,
+    /// The period after that, if the timer repeats
+    interval: Option<Duration>,
+}
+
+impl Schedule {
+    /// Gets the number of times the timer expired by the time given.
+    fn expirations(&self, now: Instant) -> u64 {
+        match (self.next, self.interval) {
+            (Some(next), Some(ival)) if next <= now => {
+                1 + ((now - next).as_nanos() / ival.as_nanos()) as u64
+            }
+            (Some(next), None) if next <= now => 1,
+            _ => 0,
+        }
+    }
+
+    /// Moves the schedule past a number of expirations.
+    fn advance(&mut self, n: u64) {
+        if n > 0 {
+            self.next = match self.interval {
+                Some(ival) => self.next.and_then(|next| {
+                    let dur = ival.as_nanos().saturating_mul(u128::from(n));
+                    next.checked_add(Duration::from_nanos(dur.try_into().ok()?))
+                }),
+                None => None,
+            };
+        }
+    }
+}
+
+/// A timer that is read through a file handle.
+#[derive(Debug)]
+pub struct TimerFd {
+    /// The kqueue with the timer event
+    kq: Kqueue,
+    /// The clock for absolute times
+    clock: ClockId,
+    /// When the timer is set to expire
+    sched: Mutex<Schedule>,
+}
+
+impl TimerFd {
+    /// Creates a new, disarmed, timer using the specified clock.
+    ///
+    /// The clock is only used to convert the deadlines of absolute
+    /// timers. The kqueue measures the delays on its own clock.
+    pub fn new(clock: ClockId) -> Result<Self> {
+        Self::with_flags(clock, TimerFlags::empty())
+    }
+
+    /// Creates a new, disarmed, timer with the specified flags.
+    pub fn with_flags(clock: ClockId, flags: TimerFlags) -> Result<Self> {
+        let kq = Kqueue::new()?;
+        if flags.contains(TimerFlags::TFD_NONBLOCK) {
+            kq.set_nonblocking(true)?;
+        }
+        Ok(Self {
+            kq,
+            clock,
+            sched: Mutex::new(Schedule::default()),
+        })
+    }
+
+    /// Arms the kqueue for the next expiration in the schedule, or
+    /// removes the timer event if there isn't one.
+    fn arm(&self, sched: &Schedule) -> Result<()> {
+        match sched.next {
+            Some(next) => {
+                // Round up to the timer resolution, so it's never early
+                let delay = next.saturating_duration_since(Instant::now());
+                let ms = delay.as_millis() + u128::from(delay.subsec_nanos() % 1_000_000 != 0);
+                let ms = i64::try_from(ms).unwrap_or(i64::MAX).max(1);
+                let flags = libc::EV_ADD | libc::EV_ONESHOT;
+                self.kq.change(0, libc::EVFILT_TIMER, flags, 0, ms)
+            }
+            None => match self.kq.change(0, libc::EVFILT_TIMER, libc::EV_DELETE, 0, 0) {
+                Err(err) if err == Error::ENOENT => Ok(()),
+                res => res,
+            },
+        }
+    }
+
+    /// Sets a new schedule for the timer.
+    fn set(&self, next: Option<Duration>, interval: Option<Duration>) -> Result<()> {
+        let sched = Schedule {
+            next: next.and_then(|dur| Instant::now().checked_add(dur)),
+            interval: interval.filter(|ival| !ival.is_zero()),
+        };
+        let mut cur = self.sched.lock().unwrap();
+        self.arm(&sched)?;
+        *cur = sched;
+        Ok(())
+    }
+
+    /// Arms the timer to expire once, after the delay.
+    pub fn set_oneshot(&self, delay: Duration) -> Result<()> {
+        self.set(Some(delay), None)
+    }
+
+    /// Arms the timer to expire periodically, starting one interval
+    /// from now.
+    ///
+    /// This fails with `EINVAL` if the interval is zero.
+    pub fn set_periodic(&self, interval: Duration) -> Result<()> {
+        if interval.is_zero() {
+            return Err(Error::EINVAL);
+        }
+        self.set(Some(interval), Some(interval))
+    }
+
+    /// Arms the timer to expire at an absolute time on its clock, and
+    /// then, optionally, periodically after that.
+    ///
+    /// The deadline is converted to a delay when the timer is armed, so
+    /// later changes to the clock, like setting the realtime clock, don't
+    /// affect it.
+    pub fn set_absolute(&self, deadline: Duration, interval: Option<Duration>) -> Result<()> {
+        let delay = deadline.saturating_sub(clock::now(self.clock)?);
+        self.set(Some(delay), interval)
+    }
+
+    /// Disarms the timer.
+    pub fn disarm(&self) -> Result<()> {
+        self.set(None, None)
+    }
+
+    /// Gets the time until the timer next expires, or `None` if it's
+    /// disarmed.
+    pub fn remaining(&self) -> Result<Option<Duration>> {
+        let now = Instant::now();
+        let mut sched = *self.sched.lock().unwrap();
+        sched.advance(sched.expirations(now));
+        Ok(sched
+            .next
+            .map(|next| next.saturating_duration_since(now))
+            .filter(|dur| !dur.is_zero()))
+    }
+
+    /// Waits for the timer to expire, and returns the number of times it
+    /// expired since it was armed, or since the last read.
+    ///
+    /// A result greater than one means that periods were missed. If the
+    /// timer is non-blocking, this fails with `EAGAIN` if it hasn't yet
+    /// expired.
+    pub fn wait(&self) -> Result<u64> {
+        loop {
+            fault_point!(TimerWait, self.kq.wait_event())?;
+
+            let mut sched = self.sched.lock().unwrap();
+            let n = sched.expirations(Instant::now());
+            sched.advance(n);
+            self.arm(&sched)?;
+
+            // The event may be stale if the timer was reset while waiting
+            if n > 0 {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+impl AsFd for TimerFd {
+    /// Gets the file handle for the timer.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.kq.as_fd()
+    }
+}
+
+impl AsRawFd for TimerFd {
+    /// Gets the raw file handle for the timer.
+    fn as_raw_fd(&self) -> RawFd {
+        self.kq.as_raw_fd()
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_oneshot() {
+        let tfd = TimerFd::new(ClockId::CLOCK_MONOTONIC).unwrap();
+        assert_eq!(None, tfd.remaining().unwrap());
+
+        let start = Instant::now();
+        tfd.set_oneshot(Duration::from_millis(20)).unwrap();
+        assert!(tfd.remaining().unwrap().is_some());
+
+        assert_eq!(1, tfd.wait().unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(None, tfd.remaining().unwrap());
+    }
+
+    #[test]
+    fn test_periodic() {
+        let tfd = TimerFd::with_flags(ClockId::CLOCK_MONOTONIC, TimerFlags::TFD_NONBLOCK).unwrap();
+        assert_eq!(Error::EINVAL, tfd.set_periodic(Duration::ZERO).unwrap_err());
+        assert_eq!(Error::EAGAIN, tfd.wait().unwrap_err());
+
+        tfd.set_periodic(Duration::from_millis(5)).unwrap();
+        thread::sleep(Duration::from_millis(30));
+
+        // Several periods were missed
+        assert!(tfd.wait().unwrap() >= 2);
+
+        tfd.disarm().unwrap();
+        assert_eq!(None, tfd.remaining().unwrap());
+    }
+
+    #[test]
+    fn test_absolute() {
+        let tfd = TimerFd::new(ClockId::CLOCK_MONOTONIC).unwrap();
+        let deadline = clock::now(ClockId::CLOCK_MONOTONIC).unwrap() + Duration::from_millis(10);
+        tfd.set_absolute(deadline, None).unwrap();
+        assert_eq!(1, tfd.wait().unwrap());
+        assert!(clock::now(ClockId::CLOCK_MONOTONIC).unwrap() >= deadline);
+    }
+}
@@ -0,0 +1,311 @@
+// hinix/src/lease.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Linux file leases.
+//!
+//! A lease lets a process be notified when another process tries to
+//! open or truncate a file that it's holding. The kernel sends the lease
+//! holder a signal (SIGIO) and then blocks the other process for a time
+//! (/proc/sys/fs/lease-break-time, typically 45 sec) to give the holder
+//! a chance to flush any cached data and then release or downgrade the
+//! lease.
+//!
+//! - A _read_ lease is broken when another process opens the file for
+//!   writing or truncates it.
+//! - A _write_ lease is broken when another process opens the file for
+//!   reading or writing.
+//!
+//! Note that the default action for SIGIO is to terminate the process,
+//! so an application that takes a lease should have a handler for it,
+//! such as a [`LeaseBreakHandler`].
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man2/fcntl.2.html>
+//!
+
+use crate::{fd::FdExt, pipe, Error, Result};
+use nix::{
+    errno::Errno,
+    fcntl::{self, OFlag},
+    sys::{
+        signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal},
+        stat::Mode,
+    },
+    unistd,
+};
+use std::{
+    io::Read,
+    os::{
+        raw::{c_int, c_long, c_void},
+        unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    },
+    path::Path,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+/// The fcntl() command to set the signal sent for I/O events.
+/// This is missing from libc.
+const F_SETSIG: c_int = 10;
+
+/// The type of a lease.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaseType {
+    /// A read lease, broken when another process opens the file for
+    /// writing.
+    Read,
+    /// A write lease, broken when another process opens the file at all.
+    Write,
+}
+
+impl LeaseType {
+    fn to_raw(self) -> c_int {
+        match self {
+            LeaseType::Read => libc::F_RDLCK,
+            LeaseType::Write => libc::F_WRLCK,
+        }
+    }
+}
+
+/// A lease held on an open file.
+///
+/// The lease is released when the object is dropped.
+#[derive(Debug)]
+pub struct Lease(OwnedFd);
+
+impl Lease {
+    /// Opens the file and takes a lease on it.
+    ///
+    /// The file is opened read-only, which is all that's required for
+    /// either type of lease. A write lease can only be taken if no other
+    /// process has the file open.
+    pub fn open<P: AsRef<Path>>(path: P, lease_type: LeaseType) -> Result<Self> {
+        let fd = fcntl::open(
+            path.as_ref(),
+            OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        Self::acquire(fd, lease_type)
+    }
+
+    /// Takes a lease on an already-open file.
+    ///
+    /// A read lease requires that the file was opened read-only.
+    pub fn acquire<F: Into<OwnedFd>>(fd: F, lease_type: LeaseType) -> Result<Self> {
+        let lease = Self(fd.into());
+
+        // Ask for the handle to be sent with the signal on a lease break.
+        Errno::result(unsafe { libc::fcntl(lease.as_raw_fd(), F_SETSIG, Signal::SIGIO as c_int) })?;
+
+        lease.set_type(lease_type)?;
+        Ok(lease)
+    }
+
+    /// Gets the type of lease currently held, if any.
+    ///
+    /// If the lease is in the process of being broken, this reports the
+    /// type that the lease must be downgraded to; `None` if it must be
+    /// released.
+    pub fn lease_type(&self) -> Result<Option<LeaseType>> {
+        let typ = Errno::result(unsafe { libc::fcntl(self.as_raw_fd(), libc::F_GETLEASE) })?;
+        Ok(match typ {
+            libc::F_RDLCK => Some(LeaseType::Read),
+            libc::F_WRLCK => Some(LeaseType::Write),
+            _ => None,
+        })
+    }
+
+    /// Changes the type of the lease.
+    ///
+    /// This can be used to downgrade a write lease to a read lease when
+    /// it is being broken by a reader.
+    pub fn set_type(&self, lease_type: LeaseType) -> Result<()> {
+        self.set_lease(lease_type.to_raw())
+    }
+
+    /// Releases the lease.
+    ///
+    /// This lets the process that broke the lease continue with its
+    /// open. The file handle remains open.
+    pub fn release(&self) -> Result<()> {
+        self.set_lease(libc::F_UNLCK)
+    }
+
+    fn set_lease(&self, arg: c_int) -> Result<()> {
+        Errno::result(unsafe { libc::fcntl(self.as_raw_fd(), libc::F_SETLEASE, arg) })?;
+        Ok(())
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let _ = self.release();
+    }
+}
+
+impl AsFd for Lease {
+    /// Gets the file handle for the leased file.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for Lease {
+    /// Gets the raw file handle for the leased file.
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// The front of the siginfo_t struct as filled in for SIGIO/SIGPOLL.
+/// The libc crate doesn't expose the si_fd field.
+#[repr(C)]
+struct SigPollInfo {
+    si_signo: c_int,
+    si_errno: c_int,
+    si_code: c_int,
+    si_band: c_long,
+    si_fd: c_int,
+}
+
+/// The write end of the pipe used by the SIGIO handler to report lease
+/// breaks, or -1 if there is no active handler.
+static BREAK_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// The SIGIO handler. This sends the handle of the broken lease down the
+/// pipe.
+extern "C" fn on_lease_break(_: c_int, info: *mut libc::siginfo_t, _: *mut c_void) {
+    let fd = BREAK_FD.load(Ordering::Relaxed);
+    if fd >= 0 && !info.is_null() {
+        let lease_fd = unsafe { (*(info as *const SigPollInfo)).si_fd };
+        let _ = unistd::write(fd, &lease_fd.to_ne_bytes());
+    }
+}
+
+/// A handler for lease-break signals.
+///
+/// This installs a handler for SIGIO that reports the file handle of each
+/// lease that is being broken. The application can block on [`wait()`],
+/// or poll the handler, to learn about the breaks, then flush any data
+/// and release or downgrade the lease.
+///
+/// Since the signal handling is process-wide, only one handler can be
+/// active at a time. Trying to create a second one fails with `EBUSY`.
+/// The default SIGIO handling is restored when the handler is dropped.
+///
+/// [`wait()`]: LeaseBreakHandler::wait
+#[derive(Debug)]
+pub struct LeaseBreakHandler {
+    /// The read end of the notification pipe
+    rd_pipe: pipe::ReadPipe,
+    /// The write end of the notification pipe
+    _wr_pipe: pipe::WritePipe,
+}
+
+impl LeaseBreakHandler {
+    /// Installs the lease-break signal handler.
+    pub fn new() -> Result<Self> {
+        let (wr_pipe, rd_pipe) = pipe::pipe()?;
+        wr_pipe.set_nonblocking(true)?;
+        let wr_fd = wr_pipe.as_raw_fd();
+
+        if BREAK_FD
+            .compare_exchange(-1, wr_fd, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(Error::EBUSY);
+        }
+
+        let action = SigAction::new(
+            SigHandler::SigAction(on_lease_break),
+            SaFlags::SA_RESTART | SaFlags::SA_SIGINFO,
+            SigSet::empty(),
+        );
+        if let Err(err) = unsafe { signal::sigaction(Signal::SIGIO, &action) } {
+            BREAK_FD.store(-1, Ordering::Release);
+            return Err(err.into());
+        }
+
+        Ok(Self {
+            rd_pipe,
+            _wr_pipe: wr_pipe,
+        })
+    }
+
+    /// Blocks until a lease is being broken, returning the raw file
+    /// handle of the lease.
+    pub fn wait(&mut self) -> Result<RawFd> {
+        let mut buf = [0u8; 4];
+        self.rd_pipe.read_exact(&mut buf)?;
+        Ok(RawFd::from_ne_bytes(buf))
+    }
+}
+
+impl Drop for LeaseBreakHandler {
+    fn drop(&mut self) {
+        let action = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+        let _ = unsafe { signal::sigaction(Signal::SIGIO, &action) };
+        BREAK_FD.store(-1, Ordering::Release);
+    }
+}
+
+impl AsFd for LeaseBreakHandler {
+    /// Gets a file handle that becomes readable when a lease is broken.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.rd_pipe.as_fd()
+    }
+}
+
+impl AsRawFd for LeaseBreakHandler {
+    /// Gets a raw file handle that becomes readable when a lease is
+    /// broken.
+    fn as_raw_fd(&self) -> RawFd {
+        self.rd_pipe.as_raw_fd()
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, thread};
+
+    #[test]
+    fn test_lease_break() {
+        let path = env::temp_dir().join(format!("hinix-lease-{}", unistd::getpid()));
+        fs::write(&path, b"data").unwrap();
+
+        let mut handler = LeaseBreakHandler::new().unwrap();
+        let lease = Lease::open(&path, LeaseType::Read).unwrap();
+        assert_eq!(Some(LeaseType::Read), lease.lease_type().unwrap());
+
+        // Opening for write blocks until we release the lease
+        let thr_path = path.clone();
+        let thr = thread::spawn(move || {
+            fs::OpenOptions::new().write(true).open(thr_path).unwrap();
+        });
+
+        let fd = handler.wait().unwrap();
+        assert_eq!(lease.as_raw_fd(), fd);
+
+        // The read lease must be released entirely
+        assert_eq!(None, lease.lease_type().unwrap());
+        lease.release().unwrap();
+        thr.join().unwrap();
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -16,10 +16,84 @@
 //!
 //! # Crate Features
 //!
+//! Each subsystem module is behind a cargo feature of the same name
+//! (with dashes for underscores, like **process-vm**), and all of them
+//! are enabled by default. To build only some of them, turn off the
+//! default features and pick the ones you need:
+//!
+//! ```toml
+//! hinix = { version = "0.3", default-features = false, features = ["msgqueue", "eventfd"] }
+//! ```
+//!
+//! A module's feature brings in the features of any other modules that
+//! it uses, like **pty** needs **term**. The `clock`, `error`, `fd`,
+//! `system`, and `timeout` modules are always available.
+//!
+//! Most of the modules are only for Linux (and Android). The **timerfd**,
+//! **signalfd**, and **pidfd** modules are also available on the BSDs
+//! and macOS, where they're built on kqueue, with the same types and
+//! calls as on Linux.
+//!
+//! The optional features, which are off by default, are:
+//!
+//! * **async-io** -
+//!   Runtime-agnostic async wrappers for the handle types, using
+//!   [async-io](https://docs.rs/async-io/latest/async_io/), in the
+//!   `async_io` module. These work with smol, async-std, or any other
+//!   executor.
+//!
+//! * **bincode** -
+//!   The [bincode](https://docs.rs/bincode/latest/bincode/) message codec,
+//!   `codec::BincodeCodec`.
+//!
+//! * **cbor** -
+//!   The CBOR message codec, `codec::CborCodec`, using
+//!   [ciborium](https://docs.rs/ciborium/latest/ciborium/).
+//!
+//! * **fault** -
+//!   Fault injection for tests, in the `fault` module, which makes chosen
+//!   calls, like sends to a message queue, fail with a chosen error, to
+//!   exercise the error handling of an application.
+//!
+//! * **io-uring** -
+//!   Support for Linux io_uring asynchronous I/O, in the `io_uring` module.
+//!
+//! * **json** -
+//!   The JSON message codec, `codec::JsonCodec`, using
+//!   [serde_json](https://docs.rs/serde_json/latest/serde_json/).
+//!
+//! * **log** -
+//!   A backend for the [log](https://docs.rs/log/latest/log/) facade that
+//!   sends the messages to the system logger, with `syslog::init()`.
+//!   This implies the **syslog** feature.
+//!
+//! * **metrics** -
+//!   Counters and histograms of the IPC traffic, like the messages through
+//!   each queue and the bytes through pipes, recorded through the
+//!   [metrics](https://docs.rs/metrics/latest/metrics/) facade. The names
+//!   are in the `metrics` module.
+//!
+//! * **mock** -
+//!   In-memory versions of the message queue, eventfd, pipe, and timerfd
+//!   handles, with the same calls as the real ones, in the `mock` module.
+//!   These let the unit tests of an application run without creating
+//!   kernel objects.
+//!
+//! * **polling** -
+//!   Safe registration of the handle types with a
+//!   [polling](https://docs.rs/polling/latest/polling/) `Poller`, in the
+//!   `polling` module.
+//!
+//! * **tokio** -
+//!   Async wrappers for the handle types, for use with the
+//!   [tokio](https://docs.rs/tokio/latest/tokio/) runtime, in the
+//!   `tokio` module.
+//!
 //! * **utils** -
-//!   Whether to build command-line utilities. This brings in additional
-//!   dependencies like [anyhow](https://docs.rs/anyhow/latest/anyhow/) and
-//!   [clap](https://docs.rs/clap/latest/clap/)
+//!   Whether to build command-line utilities: the `hinix` program, with
+//!   the tools as subcommands, and each tool under its own name. This
+//!   brings in [clap](https://docs.rs/clap/latest/clap/) for parsing the
+//!   command line.
 //!
 
 // Lints
@@ -38,23 +112,255 @@
 /// of the underlying library.
 pub use nix;
 
+// Makes a call at a point where the `fault` module can inject an error in
+// its place. The call is made as is when the feature is off. With no
+// call, this gives the injected error, if there is one.
+
+#[cfg(feature = "fault")]
+macro_rules! fault_point {
+    ($op:ident) => {
+        crate::fault::check(crate::fault::Op::$op).map_err(crate::Error::from)
+    };
+    ($op:ident, $call:expr) => {
+        match crate::fault::check(crate::fault::Op::$op) {
+            Ok(()) => $call,
+            Err(err) => Err(err.into()),
+        }
+    };
+}
+
+#[cfg(not(feature = "fault"))]
+macro_rules! fault_point {
+    ($op:ident) => {
+        crate::Result::Ok(())
+    };
+    ($op:ident, $call:expr) => {
+        $call
+    };
+}
+
+pub mod cancel;
+pub mod clock;
+pub mod error;
+pub mod fd;
+pub mod pool;
+pub mod prelude;
+pub mod system;
+pub mod timeout;
+
+#[cfg(test)]
+pub(crate) mod test_util;
+
+#[cfg(feature = "bridge")]
+pub mod bridge;
+
+#[cfg(feature = "codec")]
+pub mod codec;
+
+#[cfg(feature = "daemon")]
+pub mod daemon;
+
+#[cfg(feature = "fault")]
+pub mod fault;
+
+#[cfg(feature = "fifo")]
+pub mod fifo;
+
+#[cfg(feature = "ipc")]
+pub mod ipc;
+
+#[cfg(feature = "lock")]
+pub mod lock;
+
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
+#[cfg(feature = "mmap")]
+pub mod mmap;
+
+#[cfg(feature = "pidfile")]
+pub mod pidfile;
+
+#[cfg(feature = "pipe")]
 pub mod pipe;
 
-#[cfg(any(target_os = "android", target_os = "linux"))]
+#[cfg(feature = "polling")]
+pub mod polling;
+
+#[cfg(feature = "pty")]
+pub mod pty;
+
+#[cfg(feature = "serial")]
+pub mod serial;
+
+#[cfg(feature = "syslog")]
+pub mod syslog;
+
+#[cfg(feature = "term")]
+pub mod term;
+
+#[cfg(all(feature = "async-io", any(target_os = "android", target_os = "linux")))]
+pub mod async_io;
+
+#[cfg(all(feature = "caps", any(target_os = "android", target_os = "linux")))]
+pub mod caps;
+
+#[cfg(all(feature = "eventfd", any(target_os = "android", target_os = "linux")))]
 pub mod eventfd;
 
-#[cfg(any(
-    target_os = "dragonfly",
-    target_os = "freebsd",
-    target_os = "linux",
-    target_os = "netbsd"
+#[cfg(all(feature = "fdinfo", any(target_os = "android", target_os = "linux")))]
+pub mod fdinfo;
+
+#[cfg(all(feature = "fdpass", any(target_os = "android", target_os = "linux")))]
+pub mod fdpass;
+
+#[cfg(all(feature = "fs", any(target_os = "android", target_os = "linux")))]
+pub mod fs;
+
+#[cfg(all(feature = "futex", any(target_os = "android", target_os = "linux")))]
+pub mod futex;
+
+#[cfg(all(feature = "inotify", any(target_os = "android", target_os = "linux")))]
+pub mod inotify;
+
+#[cfg(all(feature = "io-uring", any(target_os = "android", target_os = "linux")))]
+pub mod io_uring;
+
+#[cfg(all(feature = "journal", any(target_os = "android", target_os = "linux")))]
+pub mod journal;
+
+#[cfg(all(feature = "lease", any(target_os = "android", target_os = "linux")))]
+pub mod lease;
+
+#[cfg(all(feature = "mock", any(target_os = "android", target_os = "linux")))]
+pub mod mock;
+
+#[cfg(all(feature = "mount", any(target_os = "android", target_os = "linux")))]
+pub mod mount;
+
+#[cfg(all(feature = "netif", any(target_os = "android", target_os = "linux")))]
+pub mod netif;
+
+#[cfg(all(feature = "ns", any(target_os = "android", target_os = "linux")))]
+pub mod ns;
+
+#[cfg(all(feature = "pidfd", any(target_os = "android", target_os = "linux")))]
+pub mod pidfd;
+
+#[cfg(all(feature = "process", any(target_os = "android", target_os = "linux")))]
+pub mod process;
+
+#[cfg(all(
+    feature = "process-vm",
+    any(target_os = "android", target_os = "linux")
+))]
+pub mod process_vm;
+
+#[cfg(all(feature = "random", any(target_os = "android", target_os = "linux")))]
+pub mod random;
+
+#[cfg(all(feature = "sched", any(target_os = "android", target_os = "linux")))]
+pub mod sched;
+
+#[cfg(all(feature = "security", any(target_os = "android", target_os = "linux")))]
+pub mod security;
+
+#[cfg(all(
+    feature = "seccomp",
+    any(target_os = "android", target_os = "linux"),
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub mod seccomp;
+
+#[cfg(all(feature = "seqpacket", any(target_os = "android", target_os = "linux")))]
+pub mod seqpacket;
+
+#[cfg(all(feature = "shm", any(target_os = "android", target_os = "linux")))]
+pub mod shm;
+
+#[cfg(all(feature = "shutdown", any(target_os = "android", target_os = "linux")))]
+pub mod shutdown;
+
+#[cfg(all(feature = "signalfd", any(target_os = "android", target_os = "linux")))]
+pub mod signalfd;
+
+#[cfg(all(feature = "systemd", any(target_os = "android", target_os = "linux")))]
+pub mod systemd;
+
+#[cfg(all(feature = "timerfd", any(target_os = "android", target_os = "linux")))]
+pub mod timerfd;
+
+#[cfg(all(feature = "tokio", any(target_os = "android", target_os = "linux")))]
+pub mod tokio;
+
+#[cfg(all(feature = "uevent", any(target_os = "android", target_os = "linux")))]
+pub mod uevent;
+
+#[cfg(all(feature = "watchdog", any(target_os = "android", target_os = "linux")))]
+pub mod watchdog;
+
+#[cfg(all(
+    feature = "msgqueue",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "netbsd"
+    )
 ))]
 pub mod msgqueue;
 
+#[cfg(all(
+    any(feature = "pidfd", feature = "signalfd", feature = "timerfd"),
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    )
+))]
+mod kqueue;
+
+#[cfg(all(
+    feature = "pidfd",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    )
+))]
+pub use kqueue::pidfd;
+
+#[cfg(all(
+    feature = "signalfd",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    )
+))]
+pub use kqueue::signalfd;
+
+#[cfg(all(
+    feature = "timerfd",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    )
+))]
+pub use kqueue::timerfd;
+
 /// Hinix Result type
-/// This is simply a re-export of the nix Result type.
-pub type Result<T> = nix::Result<T>;
+pub type Result<T> = std::result::Result<T, Error>;
 
-/// Hinix Error type.
-/// This is simply a re-export of the nix Error type.
-pub type Error = nix::Error;
+pub use cancel::CancelToken;
+pub use error::Error;
+pub use timeout::Timeout;
@@ -0,0 +1,603 @@
+// hinix/src/lock.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Advisory file locks.
+//!
+//! These are cooperative locks that processes can use for mutual
+//! exclusion on a file. They are advisory, meaning that they don't
+//! prevent anyone from reading or writing the file; they only block
+//! other processes that are trying to take the same lock.
+//!
+//! A [`FileLock`] uses flock(2), which locks the whole file. Locks are
+//! associated with the open file description, so two separate opens of
+//! the same file conflict with each other, even in the same process.
+//! The lock is released automatically when the file is closed, including
+//! when the process dies.
+//!
+//! On Linux, a [`RangeLock`] uses open file description (OFD) record
+//! locks to lock byte ranges within a file. These are like the
+//! traditional Posix fcntl(2) record locks, but they are associated with
+//! the open file description rather than the process. This means that
+//! they work properly between threads in the same process, and are not
+//! released when the process happens to close some other handle to the
+//! same file.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man2/flock.2.html>
+//! <https://man7.org/linux/man-pages/man2/fcntl.2.html>
+//!
+
+use crate::{
+    error::ResultExt,
+    timeout::{Deadline, Timeout},
+    Error, Result,
+};
+use nix::{
+    errno::Errno,
+    fcntl::{self, FlockArg, OFlag},
+    sys::stat::Mode,
+};
+use std::{
+    fs::File,
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use std::{mem, ops::Bound, ops::RangeBounds, os::raw::c_int};
+
+/// A file that can be locked with flock(2).
+///
+/// The locking functions return a [`FileLockGuard`] which releases the
+/// lock when it is dropped.
+#[derive(Debug)]
+pub struct FileLock(OwnedFd);
+
+impl FileLock {
+    /// Opens a file to use as a lock, creating it if it doesn't already
+    /// exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let flags = OFlag::O_RDWR | OFlag::O_CREAT | OFlag::O_CLOEXEC;
+        let mode = Mode::from_bits_truncate(0o644);
+        let path = path.as_ref();
+        let fd = fcntl::open(path, flags, mode).op_on("open", path.display())?;
+        Ok(Self(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    /// Takes a shared (read) lock on the file, blocking until it's
+    /// available.
+    ///
+    /// Any number of processes can hold a shared lock at the same time.
+    pub fn lock_shared(&self) -> Result<FileLockGuard<'_>> {
+        self.lock(FlockArg::LockShared)
+    }
+
+    /// Takes an exclusive (write) lock on the file, blocking until it's
+    /// available.
+    ///
+    /// Only one process can hold an exclusive lock at any time.
+    pub fn lock_exclusive(&self) -> Result<FileLockGuard<'_>> {
+        self.lock(FlockArg::LockExclusive)
+    }
+
+    /// Tries to take a shared (read) lock on the file without blocking.
+    ///
+    /// This fails with `EWOULDBLOCK` if another process holds an exclusive
+    /// lock.
+    pub fn try_lock_shared(&self) -> Result<FileLockGuard<'_>> {
+        self.lock(FlockArg::LockSharedNonblock)
+    }
+
+    /// Tries to take an exclusive (write) lock on the file without
+    /// blocking.
+    ///
+    /// This fails with `EWOULDBLOCK` if another process holds any lock on
+    /// the file.
+    pub fn try_lock_exclusive(&self) -> Result<FileLockGuard<'_>> {
+        self.lock(FlockArg::LockExclusiveNonblock)
+    }
+
+    /// Takes a shared (read) lock on the file, waiting no longer than
+    /// the specified timeout.
+    ///
+    /// This fails with `ETIMEDOUT` if the lock couldn't be taken in time.
+    pub fn lock_shared_timeout<T: Into<Timeout>>(&self, timeout: T) -> Result<FileLockGuard<'_>> {
+        self.lock_timeout(FlockArg::LockSharedNonblock, timeout)
+    }
+
+    /// Takes an exclusive (write) lock on the file, waiting no longer
+    /// than the specified timeout.
+    ///
+    /// This fails with `ETIMEDOUT` if the lock couldn't be taken in time.
+    pub fn lock_exclusive_timeout<T: Into<Timeout>>(
+        &self,
+        timeout: T,
+    ) -> Result<FileLockGuard<'_>> {
+        self.lock_timeout(FlockArg::LockExclusiveNonblock, timeout)
+    }
+
+    // There's no timed flock(), so this polls with a non-blocking lock,
+    // backing off a little each time the lock is busy.
+    fn lock_timeout<T: Into<Timeout>>(
+        &self,
+        arg: FlockArg,
+        timeout: T,
+    ) -> Result<FileLockGuard<'_>> {
+        const MAX_DELAY: Duration = Duration::from_millis(100);
+
+        let deadline = Deadline::start(timeout);
+        let mut delay = Duration::from_millis(1);
+
+        loop {
+            match self.lock(arg) {
+                Err(err) if err == Error::EWOULDBLOCK => (),
+                res => return res,
+            }
+            let remaining = deadline.remaining().unwrap_or(delay);
+            if remaining.is_zero() {
+                return Err(Error::ETIMEDOUT);
+            }
+            thread::sleep(delay.min(remaining));
+            delay = (delay * 2).min(MAX_DELAY);
+        }
+    }
+
+    fn lock(&self, arg: FlockArg) -> Result<FileLockGuard<'_>> {
+        fcntl::flock(self.as_raw_fd(), arg)?;
+        Ok(FileLockGuard { lock: self })
+    }
+
+    /// Releases any lock held on the file.
+    fn unlock(&self) -> Result<()> {
+        fcntl::flock(self.as_raw_fd(), FlockArg::Unlock).op("flock")
+    }
+}
+
+impl From<File> for FileLock {
+    /// Uses an already-open file as a lock.
+    fn from(file: File) -> Self {
+        Self(file.into())
+    }
+}
+
+impl From<OwnedFd> for FileLock {
+    /// Uses an already-open file handle as a lock.
+    fn from(fd: OwnedFd) -> Self {
+        Self(fd)
+    }
+}
+
+impl AsFd for FileLock {
+    /// Gets the file handle for the lock file.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for FileLock {
+    /// Gets the raw file handle for the lock file.
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for FileLock {
+    /// Gives up ownership of the file handle for the lock file.
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl From<FileLock> for OwnedFd {
+    fn from(lock: FileLock) -> Self {
+        lock.0
+    }
+}
+
+/// A held lock on a file.
+///
+/// The lock is released when the guard is dropped.
+#[derive(Debug)]
+pub struct FileLockGuard<'a> {
+    lock: &'a FileLock,
+}
+
+impl Drop for FileLockGuard<'_> {
+    fn drop(&mut self) {
+        let _ = self.lock.unlock();
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// The type of a record lock.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockType {
+    /// A shared (read) lock
+    Shared,
+    /// An exclusive (write) lock
+    Exclusive,
+}
+
+/// Information about a conflicting lock, as reported by
+/// [`RangeLock::get_lock()`].
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockInfo {
+    /// The type of the lock
+    pub lock_type: LockType,
+    /// The offset of the start of the locked range
+    pub start: u64,
+    /// The length of the locked range, where zero means that the lock
+    /// extends to the end of the file (including any future growth)
+    pub len: u64,
+}
+
+/// A file that can be locked by byte ranges with open file description
+/// (OFD) locks.
+///
+/// Ranges are specified as Rust ranges of byte offsets, so `0..100` is
+/// the first 100 bytes of the file, `100..` is everything from offset 100
+/// to the end of the file (including any future growth), and `..` is the
+/// whole file.
+///
+/// The locking functions return a [`RangeLockGuard`] which releases the
+/// lock on the range when it is dropped.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(Debug)]
+pub struct RangeLock(OwnedFd);
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl RangeLock {
+    /// Opens a file to lock, creating it if it doesn't already exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let flags = OFlag::O_RDWR | OFlag::O_CREAT | OFlag::O_CLOEXEC;
+        let mode = Mode::from_bits_truncate(0o644);
+        let path = path.as_ref();
+        let fd = fcntl::open(path, flags, mode).op_on("open", path.display())?;
+        Ok(Self(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    /// Locks a range of the file, blocking until it's available.
+    pub fn lock<R>(&self, lock_type: LockType, range: R) -> Result<RangeLockGuard<'_>>
+    where
+        R: RangeBounds<u64>,
+    {
+        self.set_lock(libc::F_OFD_SETLKW, lock_type, range)
+    }
+
+    /// Tries to lock a range of the file, without blocking.
+    ///
+    /// This fails with `EAGAIN` if a conflicting lock is held on any part
+    /// of the range.
+    pub fn try_lock<R>(&self, lock_type: LockType, range: R) -> Result<RangeLockGuard<'_>>
+    where
+        R: RangeBounds<u64>,
+    {
+        self.set_lock(libc::F_OFD_SETLK, lock_type, range)
+    }
+
+    /// Determines if the requested lock could be placed on the range.
+    ///
+    /// This returns information about one of the conflicting locks, if
+    /// there are any, or `None` if the range could be locked.
+    pub fn get_lock<R>(&self, lock_type: LockType, range: R) -> Result<Option<LockInfo>>
+    where
+        R: RangeBounds<u64>,
+    {
+        let (start, len) = Self::range_to_offsets(range)?;
+        let mut fl = Self::flock(Self::lock_type_to_raw(lock_type), start, len);
+        self.fcntl(libc::F_OFD_GETLK, &mut fl)?;
+
+        let lock_type = match fl.l_type as c_int {
+            libc::F_UNLCK => return Ok(None),
+            libc::F_RDLCK => LockType::Shared,
+            _ => LockType::Exclusive,
+        };
+        Ok(Some(LockInfo {
+            lock_type,
+            start: fl.l_start as u64,
+            len: fl.l_len as u64,
+        }))
+    }
+
+    /// Unlocks a range of the file.
+    ///
+    /// This doesn't need to match a range that was previously locked.
+    /// Any locks that overlap the range are trimmed or split as needed.
+    pub fn unlock<R>(&self, range: R) -> Result<()>
+    where
+        R: RangeBounds<u64>,
+    {
+        let (start, len) = Self::range_to_offsets(range)?;
+        self.unlock_offsets(start, len)
+    }
+
+    fn set_lock<R>(&self, cmd: c_int, lock_type: LockType, range: R) -> Result<RangeLockGuard<'_>>
+    where
+        R: RangeBounds<u64>,
+    {
+        let (start, len) = Self::range_to_offsets(range)?;
+        let mut fl = Self::flock(Self::lock_type_to_raw(lock_type), start, len);
+        self.fcntl(cmd, &mut fl)?;
+        Ok(RangeLockGuard {
+            lock: self,
+            start,
+            len,
+        })
+    }
+
+    fn unlock_offsets(&self, start: u64, len: u64) -> Result<()> {
+        let mut fl = Self::flock(libc::F_UNLCK, start, len);
+        self.fcntl(libc::F_OFD_SETLK, &mut fl)
+    }
+
+    fn fcntl(&self, cmd: c_int, fl: &mut libc::flock) -> Result<()> {
+        Errno::result(unsafe { libc::fcntl(self.as_raw_fd(), cmd, fl as *mut libc::flock) })?;
+        Ok(())
+    }
+
+    fn lock_type_to_raw(lock_type: LockType) -> c_int {
+        match lock_type {
+            LockType::Shared => libc::F_RDLCK,
+            LockType::Exclusive => libc::F_WRLCK,
+        }
+    }
+
+    /// Creates a lock struct for the range.
+    /// Note that the pid must be zero for OFD locks.
+    fn flock(l_type: c_int, start: u64, len: u64) -> libc::flock {
+        let mut fl: libc::flock = unsafe { mem::zeroed() };
+        fl.l_type = l_type as _;
+        fl.l_whence = libc::SEEK_SET as _;
+        fl.l_start = start as _;
+        fl.l_len = len as _;
+        fl
+    }
+
+    /// Converts a range into the (start, len) values used by the
+    /// system, where a zero length means "to the end of the file".
+    fn range_to_offsets<R: RangeBounds<u64>>(range: R) -> Result<(u64, u64)> {
+        let start = match range.start_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n.checked_add(1).ok_or(Error::EINVAL)?,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&n) => Some(n.checked_add(1).ok_or(Error::EINVAL)?),
+            Bound::Excluded(&n) => Some(n),
+            Bound::Unbounded => None,
+        };
+        let len = match end {
+            None => 0,
+            Some(end) if end > start => end - start,
+            Some(_) => return Err(Error::EINVAL),
+        };
+        // The system offsets are signed
+        if libc::off_t::try_from(start).is_err() || libc::off_t::try_from(len).is_err() {
+            return Err(Error::EINVAL);
+        }
+        Ok((start, len))
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl From<File> for RangeLock {
+    /// Uses an already-open file for range locks.
+    fn from(file: File) -> Self {
+        Self(file.into())
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl From<OwnedFd> for RangeLock {
+    /// Uses an already-open file handle for range locks.
+    fn from(fd: OwnedFd) -> Self {
+        Self(fd)
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl AsFd for RangeLock {
+    /// Gets the file handle for the locked file.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl AsRawFd for RangeLock {
+    /// Gets the raw file handle for the locked file.
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for RangeLock {
+    /// Gives up ownership of the file handle for the locked file.
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl From<RangeLock> for OwnedFd {
+    fn from(lock: RangeLock) -> Self {
+        lock.0
+    }
+}
+
+/// A held lock on a range of a file.
+///
+/// The lock on the range is released when the guard is dropped.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(Debug)]
+pub struct RangeLockGuard<'a> {
+    lock: &'a RangeLock,
+    start: u64,
+    len: u64,
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl Drop for RangeLockGuard<'_> {
+    fn drop(&mut self) {
+        let _ = self.lock.unlock_offsets(self.start, self.len);
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+    use nix::unistd;
+    use std::{env, path::PathBuf, sync::mpsc, time::Instant};
+
+    // Each test gets its own file, since tests may run in parallel.
+    fn test_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("hinix-{}-{}.lock", name, unistd::getpid()))
+    }
+
+    #[test]
+    fn test_exclusive() {
+        let path = test_path("exclusive");
+        let lock1 = FileLock::open(&path).unwrap();
+        let lock2 = FileLock::open(&path).unwrap();
+
+        let guard = lock1.try_lock_exclusive().unwrap();
+        assert_eq!(Error::EWOULDBLOCK, lock2.try_lock_exclusive().unwrap_err());
+        assert_eq!(Error::EWOULDBLOCK, lock2.try_lock_shared().unwrap_err());
+
+        // Dropping the guard releases the lock
+        drop(guard);
+        let _guard = lock2.try_lock_exclusive().unwrap();
+        assert_eq!(Error::EWOULDBLOCK, lock1.try_lock_shared().unwrap_err());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_shared() {
+        let path = test_path("shared");
+        let lock1 = FileLock::open(&path).unwrap();
+        let lock2 = FileLock::open(&path).unwrap();
+
+        let _guard1 = lock1.lock_shared().unwrap();
+        let _guard2 = lock2.try_lock_shared().unwrap();
+
+        let lock3 = FileLock::open(&path).unwrap();
+        assert_eq!(Error::EWOULDBLOCK, lock3.try_lock_exclusive().unwrap_err());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_blocking() {
+        let path = test_path("blocking");
+        let lock1 = FileLock::open(&path).unwrap();
+        let guard = lock1.lock_exclusive().unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let thr_path = path.clone();
+        let thr = thread::spawn(move || {
+            let lock2 = FileLock::open(&thr_path).unwrap();
+            let _guard = lock2.lock_exclusive().unwrap();
+            tx.send(()).unwrap();
+        });
+
+        // The thread should be stuck until we let go
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        drop(guard);
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        thr.join().unwrap();
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let path = test_path("timeout");
+        let lock1 = FileLock::open(&path).unwrap();
+        let lock2 = FileLock::open(&path).unwrap();
+
+        let guard = lock1.lock_exclusive().unwrap();
+
+        let timeout = Duration::from_millis(50);
+        let start = Instant::now();
+        assert_eq!(
+            Error::ETIMEDOUT,
+            lock2.lock_shared_timeout(timeout).unwrap_err()
+        );
+        assert!(start.elapsed() >= timeout);
+
+        drop(guard);
+        let _guard = lock2.lock_exclusive_timeout(timeout).unwrap();
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_range_lock() {
+        let path = test_path("range");
+        let lock1 = RangeLock::open(&path).unwrap();
+        let lock2 = RangeLock::open(&path).unwrap();
+
+        let guard = lock1.try_lock(LockType::Exclusive, 0..10).unwrap();
+
+        // OFD locks conflict between opens, even in the same process
+        assert_eq!(
+            Error::EAGAIN,
+            lock2.try_lock(LockType::Exclusive, 5..15).unwrap_err()
+        );
+        assert_eq!(
+            Error::EAGAIN,
+            lock2.try_lock(LockType::Shared, ..=0).unwrap_err()
+        );
+        let _guard2 = lock2.try_lock(LockType::Exclusive, 10..20).unwrap();
+
+        let info = lock2.get_lock(LockType::Shared, 0..5).unwrap();
+        assert_eq!(
+            Some(LockInfo {
+                lock_type: LockType::Exclusive,
+                start: 0,
+                len: 10
+            }),
+            info
+        );
+        assert_eq!(None, lock2.get_lock(LockType::Shared, 20..).unwrap());
+
+        // Dropping the guard releases the range
+        drop(guard);
+        assert_eq!(None, lock2.get_lock(LockType::Exclusive, 0..10).unwrap());
+        let _guard = lock2.try_lock(LockType::Shared, ..10).unwrap();
+
+        // Empty ranges are invalid
+        assert_eq!(
+            Error::EINVAL,
+            lock1.try_lock(LockType::Shared, 5..5).unwrap_err()
+        );
+        // As are offsets past the largest file
+        assert_eq!(
+            Error::EINVAL,
+            lock1
+                .try_lock(LockType::Shared, u64::MAX - 1..)
+                .unwrap_err()
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
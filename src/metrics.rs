@@ -0,0 +1,232 @@
+// hinix/src/metrics.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Metrics for the IPC objects.
+//!
+//! With the `metrics` feature, the crate records counters and histograms
+//! for its IPC traffic through the [metrics](https://docs.rs/metrics)
+//! facade. They go to whatever recorder the application installs, like
+//! a Prometheus exporter, and cost next to nothing if there isn't one.
+//!
+//! The metrics are:
+//!
+//! | Name | Type | Labels | What it counts |
+//! |------|------|--------|----------------|
+//! | [`MQ_MESSAGES_SENT`] | counter | `queue` | Messages sent to a message queue |
+//! | [`MQ_MESSAGES_RECEIVED`] | counter | `queue` | Messages received from a message queue |
+//! | [`PIPE_BYTES_WRITTEN`] | counter | | Bytes written to pipes |
+//! | [`PIPE_BYTES_READ`] | counter | | Bytes read from pipes |
+//! | [`EVENTFD_WAKEUPS`] | counter | | Reads of an event object that returned a value |
+//! | [`POLL_WAIT_SECONDS`] | histogram | | Time spent in timed waits for a handle |
+//!
+//! The `queue` label is the name the queue was opened with, or empty if
+//! it was adopted from a file handle.
+//!
+
+use metrics::{counter, histogram};
+use std::time::Duration;
+
+/// The number of messages sent to a message queue.
+pub const MQ_MESSAGES_SENT: &str = "hinix_mq_messages_sent";
+
+/// The number of messages received from a message queue.
+pub const MQ_MESSAGES_RECEIVED: &str = "hinix_mq_messages_received";
+
+/// The number of bytes written to pipes.
+pub const PIPE_BYTES_WRITTEN: &str = "hinix_pipe_bytes_written";
+
+/// The number of bytes read from pipes.
+pub const PIPE_BYTES_READ: &str = "hinix_pipe_bytes_read";
+
+/// The number of reads of an event object that returned a value.
+pub const EVENTFD_WAKEUPS: &str = "hinix_eventfd_wakeups";
+
+/// The time, in seconds, spent in timed waits for a handle to be ready.
+pub const POLL_WAIT_SECONDS: &str = "hinix_poll_wait_seconds";
+
+// These are called from the objects to record the metrics.
+
+#[allow(dead_code)]
+pub(crate) fn mq_sent(queue: Option<&str>) {
+    counter!(MQ_MESSAGES_SENT, "queue" => queue.unwrap_or_default().to_string()).increment(1);
+}
+
+#[allow(dead_code)]
+pub(crate) fn mq_received(queue: Option<&str>) {
+    counter!(MQ_MESSAGES_RECEIVED, "queue" => queue.unwrap_or_default().to_string()).increment(1);
+}
+
+#[allow(dead_code)]
+pub(crate) fn pipe_written(n: usize) {
+    counter!(PIPE_BYTES_WRITTEN).increment(n as u64);
+}
+
+#[allow(dead_code)]
+pub(crate) fn pipe_read(n: usize) {
+    counter!(PIPE_BYTES_READ).increment(n as u64);
+}
+
+#[allow(dead_code)]
+pub(crate) fn eventfd_wakeup() {
+    counter!(EVENTFD_WAKEUPS).increment(1);
+}
+
+pub(crate) fn poll_wait(elapsed: Duration) {
+    histogram!(POLL_WAIT_SECONDS).record(elapsed);
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics::{
+        Counter, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder, SharedString,
+        Unit,
+    };
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    /// The values recorded into a histogram.
+    #[derive(Default)]
+    struct Samples(Mutex<Vec<f64>>);
+
+    impl HistogramFn for Samples {
+        fn record(&self, value: f64) {
+            self.0.lock().unwrap().push(value);
+        }
+    }
+
+    /// A recorder that keeps the values, so the tests can check them.
+    #[derive(Default)]
+    struct TestRecorder {
+        counters: Mutex<HashMap<Key, Arc<AtomicU64>>>,
+        histograms: Mutex<HashMap<Key, Arc<Samples>>>,
+    }
+
+    #[allow(dead_code)] // Not every feature uses all of these
+    impl TestRecorder {
+        fn counter(&self, key: &Key) -> u64 {
+            self.counters
+                .lock()
+                .unwrap()
+                .get(key)
+                .map_or(0, |n| n.load(Ordering::Relaxed))
+        }
+
+        fn histogram(&self, key: &Key) -> Vec<f64> {
+            self.histograms
+                .lock()
+                .unwrap()
+                .get(key)
+                .map_or_else(Vec::new, |s| s.0.lock().unwrap().clone())
+        }
+    }
+
+    impl Recorder for TestRecorder {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            let mut counters = self.counters.lock().unwrap();
+            Counter::from_arc(counters.entry(key.clone()).or_default().clone())
+        }
+
+        fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::noop()
+        }
+
+        fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+            let mut histograms = self.histograms.lock().unwrap();
+            Histogram::from_arc(histograms.entry(key.clone()).or_default().clone())
+        }
+    }
+
+    #[test]
+    fn test_poll_wait() {
+        use crate::timeout;
+        use nix::{poll::PollFlags, unistd};
+        use std::os::unix::io::{AsFd, FromRawFd, OwnedFd};
+
+        let (rd, wr) = unistd::pipe().unwrap();
+        let (rd, _wr) = unsafe { (OwnedFd::from_raw_fd(rd), OwnedFd::from_raw_fd(wr)) };
+
+        let rec = TestRecorder::default();
+        let ready = metrics::with_local_recorder(&rec, || {
+            timeout::poll_fd(rd.as_fd(), PollFlags::POLLIN, Duration::from_millis(10))
+        });
+        assert!(!ready.unwrap());
+
+        let waits = rec.histogram(&Key::from_name(POLL_WAIT_SECONDS));
+        assert_eq!(1, waits.len());
+        assert!(waits[0] >= 0.01);
+    }
+
+    #[cfg(feature = "pipe")]
+    #[test]
+    fn test_pipe() {
+        use std::io::{Read, Write};
+
+        let rec = TestRecorder::default();
+        metrics::with_local_recorder(&rec, || {
+            let (mut wr, mut rd) = crate::pipe::pipe().unwrap();
+            wr.write_all(b"hello").unwrap();
+            let mut buf = [0u8; 3];
+            rd.read_exact(&mut buf).unwrap();
+        });
+
+        assert_eq!(5, rec.counter(&Key::from_name(PIPE_BYTES_WRITTEN)));
+        assert_eq!(3, rec.counter(&Key::from_name(PIPE_BYTES_READ)));
+    }
+
+    #[cfg(all(feature = "eventfd", any(target_os = "android", target_os = "linux")))]
+    #[test]
+    fn test_eventfd() {
+        let rec = TestRecorder::default();
+        metrics::with_local_recorder(&rec, || {
+            let evt = crate::eventfd::EventFd::new(0).unwrap();
+            evt.write(2).unwrap();
+            assert_eq!(2, evt.read().unwrap());
+        });
+
+        assert_eq!(1, rec.counter(&Key::from_name(EVENTFD_WAKEUPS)));
+    }
+
+    #[cfg(all(feature = "msgqueue", target_os = "linux"))]
+    #[test]
+    fn test_msgqueue() {
+        use crate::msgqueue::MsgQueue;
+        use metrics::Label;
+
+        const NAME: &str = "/rust_metrics_unit_test";
+
+        let rec = TestRecorder::default();
+        metrics::with_local_recorder(&rec, || {
+            let mq = MsgQueue::create(NAME, 4, 64).unwrap();
+            MsgQueue::unlink(NAME).unwrap();
+            mq.send(b"one").unwrap();
+            mq.send(b"two").unwrap();
+            mq.receive_bytes().unwrap();
+        });
+
+        let key = |name| Key::from_parts(name, vec![Label::new("queue", NAME)]);
+        assert_eq!(2, rec.counter(&key(MQ_MESSAGES_SENT)));
+        assert_eq!(1, rec.counter(&key(MQ_MESSAGES_RECEIVED)));
+    }
+}
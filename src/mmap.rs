@@ -0,0 +1,615 @@
+// hinix/src/mmap.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Memory-mapped files and anonymous memory.
+//!
+//! The [`Mmap`] and [`MmapMut`] types own a region of mapped memory,
+//! which is unmapped when they're dropped. They dereference to byte
+//! slices, so the contents can be used like any other buffer.
+//!
+//! Mapping a file is inherently unsafe in Rust terms: if the file is
+//! modified or truncated by another process (or another part of this
+//! one) while it's mapped, the contents of the slice can change out from
+//! under the borrow checker, or accessing it can raise a SIGBUS.
+//! Therefore the functions that map files are `unsafe`, and it's up to
+//! the application to make sure that doesn't happen. Anonymous mappings
+//! have no such problem.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man2/mmap.2.html>
+//!
+
+use crate::{error::ResultExt, Error, Result};
+use nix::sys::{
+    mman::{self, MapFlags, MsFlags},
+    stat,
+};
+use std::{
+    num::NonZeroUsize,
+    ops::{Deref, DerefMut},
+    os::{
+        raw::c_void,
+        unix::io::{AsFd, AsRawFd, RawFd},
+    },
+    slice,
+};
+
+/// Advice to the kernel about how a mapping will be used.
+pub use nix::sys::mman::MmapAdvise as Advice;
+
+/// The memory protection of a mapping.
+pub use nix::sys::mman::ProtFlags;
+
+pub use crate::system::page_size;
+
+/// Determines if the advice only affects how the pages are managed, and
+/// never their contents, so it's safe to give while the memory is
+/// borrowed.
+fn is_safe_advice(advice: Advice) -> bool {
+    use Advice::*;
+    match advice {
+        MADV_NORMAL | MADV_RANDOM | MADV_SEQUENTIAL | MADV_WILLNEED => true,
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        MADV_DOFORK | MADV_MERGEABLE | MADV_UNMERGEABLE | MADV_HUGEPAGE | MADV_NOHUGEPAGE
+        | MADV_DONTDUMP | MADV_DODUMP => true,
+        _ => false,
+    }
+}
+
+/// Gets the size of an open file.
+fn file_len(fd: RawFd) -> Result<usize> {
+    let st = stat::fstat(fd)?;
+    usize::try_from(st.st_size).map_err(|_| Error::EFBIG)
+}
+
+/// The common implementation of the mapping types.
+#[derive(Debug)]
+struct MmapInner {
+    /// The page-aligned start of the mapping
+    ptr: *mut c_void,
+    /// The full length of the mapping, including the alignment padding
+    len: usize,
+    /// The distance from the start of the mapping to the requested offset
+    pad: usize,
+}
+
+// The mapping is just memory, which can be used from any thread.
+unsafe impl Send for MmapInner {}
+unsafe impl Sync for MmapInner {}
+
+impl MmapInner {
+    /// Creates a new mapping.
+    ///
+    /// The offset doesn't need to be page-aligned. The mapping is
+    /// started at the page boundary below it, and the difference hidden
+    /// from the user.
+    unsafe fn new(
+        len: usize,
+        prot: ProtFlags,
+        flags: MapFlags,
+        fd: RawFd,
+        offset: u64,
+    ) -> Result<Self> {
+        let pad = (offset % page_size() as u64) as usize;
+        let map_len = NonZeroUsize::new(len)
+            .and_then(|n| n.checked_add(pad))
+            .ok_or(Error::EINVAL)?;
+        let map_off = libc::off_t::try_from(offset - pad as u64).map_err(|_| Error::EOVERFLOW)?;
+
+        let ptr = mman::mmap(None, map_len, prot, flags, fd, map_off)?;
+        Ok(Self {
+            ptr,
+            len: map_len.get(),
+            pad,
+        })
+    }
+
+    /// Creates an anonymous mapping, initialized to zero.
+    fn anonymous(len: usize, shared: bool) -> Result<Self> {
+        let flags = MapFlags::MAP_ANONYMOUS
+            | if shared {
+                MapFlags::MAP_SHARED
+            }
+            else {
+                MapFlags::MAP_PRIVATE
+            };
+        unsafe {
+            Self::new(
+                len,
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                flags,
+                -1,
+                0,
+            )
+        }
+    }
+
+    fn as_ptr(&self) -> *mut u8 {
+        unsafe { (self.ptr as *mut u8).add(self.pad) }
+    }
+
+    fn len(&self) -> usize {
+        self.len - self.pad
+    }
+
+    /// Syncs part of the mapping to the file.
+    fn flush(&self, offset: usize, len: usize, flags: MsFlags) -> Result<()> {
+        if !matches!(offset.checked_add(len), Some(end) if end <= self.len()) {
+            return Err(Error::EINVAL);
+        }
+        // msync() requires a page-aligned address
+        let start = self.pad + offset;
+        let align = start % page_size();
+        let addr = unsafe { (self.ptr as *mut u8).add(start - align) };
+        unsafe { mman::msync(addr as *mut c_void, len + align, flags) }.op("msync")
+    }
+
+    fn advise(&self, advice: Advice) -> Result<()> {
+        unsafe { mman::madvise(self.ptr, self.len, advice) }.op("madvise")
+    }
+
+    fn lock(&self) -> Result<()> {
+        unsafe { mman::mlock(self.ptr, self.len) }.op("mlock")
+    }
+
+    fn unlock(&self) -> Result<()> {
+        unsafe { mman::munlock(self.ptr, self.len) }.op("munlock")
+    }
+
+    unsafe fn protect(&self, prot: ProtFlags) -> Result<()> {
+        mman::mprotect(self.ptr, self.len, prot).op("mprotect")
+    }
+}
+
+impl Drop for MmapInner {
+    fn drop(&mut self) {
+        let _ = unsafe { mman::munmap(self.ptr, self.len) };
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// A read-only memory map.
+#[derive(Debug)]
+pub struct Mmap(MmapInner);
+
+impl Mmap {
+    /// Maps the whole of a file into memory, read-only.
+    ///
+    /// The file must have been opened for reading. Fails with `EINVAL`
+    /// if the file is empty.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated while it's mapped.
+    pub unsafe fn map<F: AsFd>(file: &F) -> Result<Self> {
+        let fd = file.as_fd().as_raw_fd();
+        Self::map_range(file, 0, file_len(fd)?)
+    }
+
+    /// Maps part of a file into memory, read-only.
+    ///
+    /// The offset does not need to be a multiple of the page size.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated while it's mapped.
+    pub unsafe fn map_range<F: AsFd>(file: &F, offset: u64, len: usize) -> Result<Self> {
+        let inner = MmapInner::new(
+            len,
+            ProtFlags::PROT_READ,
+            MapFlags::MAP_SHARED,
+            file.as_fd().as_raw_fd(),
+            offset,
+        )?;
+        Ok(Self(inner))
+    }
+
+    /// Gets the length of the mapping, in bytes.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Determines if the mapping is empty.
+    /// This is always false, since empty mappings can't be created.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Gets a pointer to the start of the mapped memory.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.0.as_ptr()
+    }
+
+    /// Gives the kernel advice about how the memory will be used.
+    ///
+    /// Only advice that leaves the contents alone can be given this way,
+    /// like `MADV_SEQUENTIAL` or `MADV_WILLNEED`. This fails with `EINVAL`
+    /// for any that could discard or change the pages, like
+    /// `MADV_DONTNEED` or `MADV_FREE`. Those need
+    /// [`advise_unchecked()`](Self::advise_unchecked).
+    pub fn advise(&self, advice: Advice) -> Result<()> {
+        if !is_safe_advice(advice) {
+            return Err(Error::EINVAL);
+        }
+        self.0.advise(advice)
+    }
+
+    /// Gives the kernel any advice about how the memory will be used,
+    /// including advice that discards or changes the pages.
+    ///
+    /// # Safety
+    ///
+    /// Advice like `MADV_DONTNEED`, `MADV_FREE`, or `MADV_REMOVE` can
+    /// zero the contents, or make them read back as zeros later, and
+    /// `MADV_DONTFORK` leaves the mapping missing in a forked child. The
+    /// caller must make sure that no slices borrowed from the mapping are
+    /// in use, and that the program can cope with the changed contents.
+    pub unsafe fn advise_unchecked(&self, advice: Advice) -> Result<()> {
+        self.0.advise(advice)
+    }
+
+    /// Locks the pages of the mapping into RAM, preventing them from
+    /// being swapped out.
+    pub fn lock(&self) -> Result<()> {
+        self.0.lock()
+    }
+
+    /// Unlocks the pages of the mapping.
+    pub fn unlock(&self) -> Result<()> {
+        self.0.unlock()
+    }
+
+    /// Changes the memory protection of the mapping.
+    ///
+    /// # Safety
+    ///
+    /// Removing read access causes any access to the contents of the
+    /// map to fault.
+    pub unsafe fn protect(&self, prot: ProtFlags) -> Result<()> {
+        self.0.protect(prot)
+    }
+
+    /// Converts the map into a mutable one.
+    ///
+    /// This requires the file to have been opened for writing, otherwise
+    /// fails with `EACCES`.
+    pub fn make_mut(self) -> Result<MmapMut> {
+        unsafe {
+            self.0
+                .protect(ProtFlags::PROT_READ | ProtFlags::PROT_WRITE)?
+        };
+        Ok(MmapMut(self.0))
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.0.as_ptr(), self.0.len()) }
+    }
+}
+
+impl AsRef<[u8]> for Mmap {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// A writable memory map.
+///
+/// Changes to a file mapping are written back to the file by the kernel
+/// at some point after they're made, or immediately with a [`flush()`].
+///
+/// [`flush()`]: MmapMut::flush
+#[derive(Debug)]
+pub struct MmapMut(MmapInner);
+
+impl MmapMut {
+    /// Maps the whole of a file into memory for reading and writing.
+    ///
+    /// The file must have been opened for reading and writing. Fails
+    /// with `EINVAL` if the file is empty.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated by anything else while
+    /// it's mapped.
+    pub unsafe fn map<F: AsFd>(file: &F) -> Result<Self> {
+        let fd = file.as_fd().as_raw_fd();
+        Self::map_range(file, 0, file_len(fd)?)
+    }
+
+    /// Maps part of a file into memory for reading and writing.
+    ///
+    /// The offset does not need to be a multiple of the page size.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated by anything else while
+    /// it's mapped.
+    pub unsafe fn map_range<F: AsFd>(file: &F, offset: u64, len: usize) -> Result<Self> {
+        let inner = MmapInner::new(
+            len,
+            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+            MapFlags::MAP_SHARED,
+            file.as_fd().as_raw_fd(),
+            offset,
+        )?;
+        Ok(Self(inner))
+    }
+
+    /// Creates an anonymous mapping that is private to this process.
+    ///
+    /// The memory is initialized to zero.
+    pub fn anonymous(len: usize) -> Result<Self> {
+        MmapInner::anonymous(len, false).map(Self)
+    }
+
+    /// Creates an anonymous mapping that is shared with any child
+    /// processes created with fork() after this.
+    ///
+    /// The memory is initialized to zero.
+    pub fn anonymous_shared(len: usize) -> Result<Self> {
+        MmapInner::anonymous(len, true).map(Self)
+    }
+
+    /// Gets the length of the mapping, in bytes.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Determines if the mapping is empty.
+    /// This is always false, since empty mappings can't be created.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Gets a pointer to the start of the mapped memory.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.0.as_ptr()
+    }
+
+    /// Gets a mutable pointer to the start of the mapped memory.
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.0.as_ptr()
+    }
+
+    /// Writes any changes to the underlying file, waiting for the write
+    /// to complete.
+    pub fn flush(&self) -> Result<()> {
+        self.0.flush(0, self.len(), MsFlags::MS_SYNC)
+    }
+
+    /// Starts writing any changes to the underlying file, but doesn't
+    /// wait for it to complete.
+    pub fn flush_async(&self) -> Result<()> {
+        self.0.flush(0, self.len(), MsFlags::MS_ASYNC)
+    }
+
+    /// Writes any changes in part of the mapping to the underlying file,
+    /// waiting for the write to complete.
+    ///
+    /// Fails with `EINVAL` if the range is outside the mapping.
+    pub fn flush_range(&self, offset: usize, len: usize) -> Result<()> {
+        self.0.flush(offset, len, MsFlags::MS_SYNC)
+    }
+
+    /// Gives the kernel advice about how the memory will be used.
+    ///
+    /// Only advice that leaves the contents alone can be given this way,
+    /// like `MADV_SEQUENTIAL` or `MADV_WILLNEED`. This fails with `EINVAL`
+    /// for any that could discard or change the pages, like
+    /// `MADV_DONTNEED` or `MADV_FREE`. Those need
+    /// [`advise_unchecked()`](Self::advise_unchecked).
+    pub fn advise(&self, advice: Advice) -> Result<()> {
+        if !is_safe_advice(advice) {
+            return Err(Error::EINVAL);
+        }
+        self.0.advise(advice)
+    }
+
+    /// Gives the kernel any advice about how the memory will be used,
+    /// including advice that discards or changes the pages.
+    ///
+    /// # Safety
+    ///
+    /// Advice like `MADV_DONTNEED`, `MADV_FREE`, or `MADV_REMOVE` can
+    /// zero the contents, or make them read back as zeros later, and
+    /// `MADV_DONTFORK` leaves the mapping missing in a forked child. The
+    /// caller must make sure that no slices borrowed from the mapping are
+    /// in use, and that the program can cope with the changed contents.
+    pub unsafe fn advise_unchecked(&self, advice: Advice) -> Result<()> {
+        self.0.advise(advice)
+    }
+
+    /// Locks the pages of the mapping into RAM, preventing them from
+    /// being swapped out.
+    pub fn lock(&self) -> Result<()> {
+        self.0.lock()
+    }
+
+    /// Unlocks the pages of the mapping.
+    pub fn unlock(&self) -> Result<()> {
+        self.0.unlock()
+    }
+
+    /// Changes the memory protection of the mapping.
+    ///
+    /// # Safety
+    ///
+    /// Removing read or write access causes any such access to the
+    /// contents of the map to fault.
+    pub unsafe fn protect(&self, prot: ProtFlags) -> Result<()> {
+        self.0.protect(prot)
+    }
+
+    /// Converts the map into a read-only one.
+    pub fn make_read_only(self) -> Result<Mmap> {
+        unsafe { self.0.protect(ProtFlags::PROT_READ)? };
+        Ok(Mmap(self.0))
+    }
+}
+
+impl Deref for MmapMut {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.0.as_ptr(), self.0.len()) }
+    }
+}
+
+impl DerefMut for MmapMut {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.0.as_ptr(), self.0.len()) }
+    }
+}
+
+impl AsRef<[u8]> for MmapMut {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl AsMut<[u8]> for MmapMut {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::{
+        sys::wait::{self, WaitStatus},
+        unistd::{self, ForkResult},
+    };
+    use std::{
+        env,
+        fs::{self, File, OpenOptions},
+        io::Read,
+        path::PathBuf,
+        process,
+    };
+
+    fn test_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!("hinix-mmap-{}-{}", name, unistd::getpid()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_anonymous() {
+        let mut map = MmapMut::anonymous(10000).unwrap();
+        assert_eq!(10000, map.len());
+        assert!(map.iter().all(|&b| b == 0));
+
+        map[..5].copy_from_slice(b"hello");
+        map[9999] = 42;
+        assert_eq!(b"hello", &map[..5]);
+
+        let map = map.make_read_only().unwrap();
+        assert_eq!(42, map[9999]);
+
+        assert_eq!(Error::EINVAL, MmapMut::anonymous(0).unwrap_err());
+    }
+
+    #[test]
+    fn test_advise() {
+        let mut map = MmapMut::anonymous(page_size()).unwrap();
+        map.advise(Advice::MADV_WILLNEED).unwrap();
+        map[0] = 42;
+
+        // Discarding the pages is only allowed through the unsafe call
+        assert_eq!(
+            Error::EINVAL,
+            map.advise(Advice::MADV_DONTNEED).unwrap_err()
+        );
+        assert_eq!(42, map[0]);
+
+        unsafe { map.advise_unchecked(Advice::MADV_DONTNEED).unwrap() };
+        assert_eq!(0, map[0]);
+    }
+
+    #[test]
+    fn test_map_file() {
+        let path = test_file("read", b"the quick brown fox");
+        let file = File::open(&path).unwrap();
+
+        let map = unsafe { Mmap::map(&file) }.unwrap();
+        assert_eq!(b"the quick brown fox", &map[..]);
+        map.advise(Advice::MADV_SEQUENTIAL).unwrap();
+
+        // An unaligned offset
+        let map = unsafe { Mmap::map_range(&file, 4, 5) }.unwrap();
+        assert_eq!(b"quick", &map[..]);
+
+        // Can't write to a read-only file
+        assert_eq!(Error::EACCES, map.make_mut().unwrap_err());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_map_file_mut() {
+        let path = test_file("write", b"the quick brown fox");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+
+        let mut map = unsafe { MmapMut::map(&file) }.unwrap();
+        map[4..9].copy_from_slice(b"QUICK");
+        map.flush_range(4, 5).unwrap();
+        map.flush().unwrap();
+        assert_eq!(Error::EINVAL, map.flush_range(10, 100).unwrap_err());
+
+        let mut s = String::new();
+        File::open(&path).unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!("the QUICK brown fox", s);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_lock() {
+        let map = MmapMut::anonymous(page_size()).unwrap();
+        map.lock().unwrap();
+        map.unlock().unwrap();
+    }
+
+    #[test]
+    fn test_shared_with_child() {
+        let mut map = MmapMut::anonymous_shared(16).unwrap();
+
+        match unsafe { unistd::fork() }.unwrap() {
+            ForkResult::Child => {
+                map[0] = 0xA5;
+                process::exit(0);
+            }
+            ForkResult::Parent { child } => {
+                assert_eq!(
+                    WaitStatus::Exited(child, 0),
+                    wait::waitpid(child, None).unwrap()
+                );
+                assert_eq!(0xA5, map[0]);
+            }
+        }
+    }
+}
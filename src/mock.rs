@@ -0,0 +1,107 @@
+// hinix/src/mock.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! In-process mock versions of the message queue, eventfd, pipe, and
+//! timer handles, for testing.
+//!
+//! The modules in here have the same types and calls as the crate's
+//! `msgqueue`, `eventfd`, `pipe`, and `timerfd` modules, but keep their
+//! state in memory rather than in kernel objects. The unit tests of an
+//! application can then run where the real ones can't be created, like
+//! in a CI container without `/dev/mqueue`, or without the permissions
+//! to raise the queue limits.
+//!
+//! An application can pick the module to use when it's built for its
+//! tests:
+//!
+//! ```ignore
+//! #[cfg(test)]
+//! use hinix::mock::msgqueue::MsgQueue;
+//! #[cfg(not(test))]
+//! use hinix::msgqueue::MsgQueue;
+//! ```
+//!
+//! and the rest of its code is the same either way:
+//!
+//! ```
+//! use hinix::mock::msgqueue::MsgQueue;
+//!
+//! let mq = MsgQueue::create("/mock_doc_test", 4, 64)?;
+//! mq.send("hello")?;
+//! assert_eq!("hello", mq.receive_string()?);
+//! mq.close_and_unlink()?;
+//! # Ok::<(), hinix::Error>(())
+//! ```
+//!
+//! The mocks block, time out, and fail with the same errors as the real
+//! handles. Named queues live in a registry for the process, so handles
+//! opened by name in different threads reach the same queue, but they
+//! can't be shared with another process.
+//!
+//! With the **fault** feature, the mocks also fail with the faults that
+//! are injected for their operations.
+//!
+//! Since there's no file handle behind a mock, it can't be added to a
+//! poll set, or used with the async wrappers. Calls that only make sense
+//! for a kernel object, like changing the owner of a queue, are left out.
+//!
+
+use crate::{cancel::CancelToken, timeout::Deadline, Result};
+use std::{
+    sync::{Condvar, MutexGuard},
+    time::Duration,
+};
+
+pub mod eventfd;
+pub mod msgqueue;
+pub mod pipe;
+pub mod timerfd;
+
+/// How often a cancellable wait checks its token.
+const CANCEL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Waits on the condition variable for as long as the state is blocked,
+/// up to the deadline, or until the token is cancelled.
+///
+/// Returns the guard, and `false` if the deadline passed while the state
+/// was still blocked. Fails with `ECANCELED` if the token was cancelled.
+fn wait_while<'a, T, F>(
+    cond: &Condvar,
+    mut guard: MutexGuard<'a, T>,
+    deadline: Deadline,
+    cancel: Option<&CancelToken>,
+    mut blocked: F,
+) -> Result<(MutexGuard<'a, T>, bool)>
+where
+    F: FnMut(&mut T) -> bool,
+{
+    loop {
+        if let Some(cancel) = cancel {
+            cancel.check()?;
+        }
+        if !blocked(&mut guard) {
+            return Ok((guard, true));
+        }
+        let mut wait = deadline.remaining();
+        if wait == Some(Duration::ZERO) {
+            return Ok((guard, false));
+        }
+        // The token doesn't wake the condition, so check it now and then
+        if cancel.is_some() {
+            wait = Some(wait.map_or(CANCEL_INTERVAL, |dur| dur.min(CANCEL_INTERVAL)));
+        }
+        guard = match wait {
+            Some(dur) => cond.wait_timeout(guard, dur).unwrap().0,
+            None => cond.wait(guard).unwrap(),
+        };
+    }
+}
@@ -0,0 +1,205 @@
+// hinix/src/mock/eventfd.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! An in-memory mock of the eventfd event object.
+//!
+//! This has the same interface as the `eventfd` module. The counter is
+//! shared by the clones of an object, as it is by the duplicates of a
+//! real eventfd handle, along with the flags.
+//!
+
+use crate::{
+    cancel::CancelToken,
+    timeout::{Deadline, Timeout},
+    Error, Result,
+};
+use std::{
+    os::raw::c_uint,
+    sync::{Arc, Condvar, Mutex},
+};
+
+pub use nix::sys::eventfd::EfdFlags;
+
+/// The largest value the counter can hold.
+const MAX_VALUE: u64 = u64::MAX - 1;
+
+/// The state shared by the clones of an event object.
+#[derive(Debug)]
+struct Inner {
+    /// The flags the object was created with
+    flags: EfdFlags,
+    /// The counter
+    value: Mutex<u64>,
+    /// Signaled when the counter changes
+    cond: Condvar,
+}
+
+/// A mock event object.
+#[derive(Debug)]
+pub struct EventFd(Arc<Inner>);
+
+impl EventFd {
+    /// Create a new event object.
+    ///
+    /// When read, the value is returned and the count is reset to zero.
+    pub fn new(initval: u64) -> Result<EventFd> {
+        Self::with_flags(initval, EfdFlags::empty())
+    }
+
+    /// Create a new event object with the semaphore option.
+    ///
+    /// When read, the value returned is 1, and the value is decremented
+    /// by 1.
+    pub fn new_semaphore(initval: u64) -> Result<EventFd> {
+        Self::with_flags(initval, EfdFlags::EFD_SEMAPHORE)
+    }
+
+    /// Create a new event object with the specified flags.
+    ///
+    /// As with the system call, the initial value is limited to 32 bits.
+    /// This fails with `EINVAL` if it's larger than that.
+    pub fn with_flags(initval: u64, flags: EfdFlags) -> Result<EventFd> {
+        c_uint::try_from(initval).map_err(|_| Error::EINVAL)?;
+        Ok(EventFd(Arc::new(Inner {
+            flags,
+            value: Mutex::new(initval),
+            cond: Condvar::new(),
+        })))
+    }
+
+    /// Clones the event object, sharing its counter.
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(EventFd(self.0.clone()))
+    }
+
+    /// Closes the event object.
+    ///
+    /// This can't fail, but is here to match the real object.
+    pub fn close(self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Waits for the counter to be non-zero, then reads it.
+    fn read_until(&self, deadline: Deadline, cancel: Option<&CancelToken>) -> Result<Option<u64>> {
+        fault_point!(EventFdRead)?;
+        let nonblocking = self.0.flags.contains(EfdFlags::EFD_NONBLOCK);
+        let value = self.0.value.lock().unwrap();
+        let (mut value, ready) = super::wait_while(&self.0.cond, value, deadline, cancel, |val| {
+            *val == 0 && !nonblocking
+        })?;
+        if !ready {
+            return Ok(None);
+        }
+        let val = match *value {
+            0 => return Err(Error::EAGAIN),
+            _ if self.0.flags.contains(EfdFlags::EFD_SEMAPHORE) => 1,
+            val => val,
+        };
+        *value -= val;
+        self.0.cond.notify_all();
+        Ok(Some(val))
+    }
+
+    /// Reads the value of the event object.
+    pub fn read(&self) -> Result<u64> {
+        self.read_until(Deadline::start(Timeout::None), None)
+            .map(Option::unwrap_or_default)
+    }
+
+    /// Waits up to the timeout for the event object to be signaled, then
+    /// reads the value.
+    ///
+    /// Returns `None` if the timeout expired first.
+    pub fn read_timeout<T: Into<Timeout>>(&self, timeout: T) -> Result<Option<u64>> {
+        self.read_until(Deadline::start(timeout), None)
+    }
+
+    /// Waits up to the timeout for the event object to be signaled, then
+    /// reads the value, unless the token is cancelled first.
+    ///
+    /// Returns `None` if the timeout expired first, or fails with
+    /// `ECANCELED` if the token was cancelled.
+    pub fn read_cancellable<T: Into<Timeout>>(
+        &self,
+        timeout: T,
+        cancel: &CancelToken,
+    ) -> Result<Option<u64>> {
+        self.read_until(Deadline::start(timeout), Some(cancel))
+    }
+
+    /// Writes a value to the event object.
+    ///
+    /// # Parameters
+    /// `val` The value to _add_ to the one held by the object.
+    ///
+    /// This blocks while the sum would overflow the counter, or fails with
+    /// `EAGAIN` if the object is non-blocking.
+    pub fn write(&self, val: u64) -> Result<()> {
+        fault_point!(EventFdWrite)?;
+        if val == u64::MAX {
+            return Err(Error::EINVAL);
+        }
+        let nonblocking = self.0.flags.contains(EfdFlags::EFD_NONBLOCK);
+        let value = self.0.value.lock().unwrap();
+        let deadline = Deadline::start(Timeout::None);
+        let (mut value, _) = super::wait_while(&self.0.cond, value, deadline, None, |cur| {
+            *cur > MAX_VALUE - val && !nonblocking
+        })?;
+        if *value > MAX_VALUE - val {
+            return Err(Error::EAGAIN);
+        }
+        *value += val;
+        self.0.cond.notify_all();
+        Ok(())
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_eventfd() {
+        let evfd = EventFd::new(0).unwrap();
+        assert_eq!(None, evfd.read_timeout(Duration::from_millis(1)).unwrap());
+
+        let clone = evfd.try_clone().unwrap();
+        let th = thread::spawn(move || clone.read());
+        thread::sleep(Duration::from_millis(10));
+
+        evfd.write(3).unwrap();
+        assert_eq!(3, th.join().unwrap().unwrap());
+
+        let evfd =
+            EventFd::with_flags(2, EfdFlags::EFD_SEMAPHORE | EfdFlags::EFD_NONBLOCK).unwrap();
+        assert_eq!(1, evfd.read().unwrap());
+        assert_eq!(1, evfd.read().unwrap());
+        assert_eq!(Error::EAGAIN, evfd.read().unwrap_err());
+
+        evfd.write(MAX_VALUE).unwrap();
+        assert_eq!(Error::EAGAIN, evfd.write(1).unwrap_err());
+        assert!(EventFd::new(1 << 32).is_err());
+    }
+
+    #[test]
+    fn test_cancel() {
+        let evfd = EventFd::new(0).unwrap();
+        let cancel = CancelToken::new().unwrap();
+        cancel.cancel();
+        let res = evfd.read_cancellable(Timeout::None, &cancel);
+        assert_eq!(Error::ECANCELED, res.unwrap_err());
+    }
+}
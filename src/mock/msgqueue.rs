@@ -0,0 +1,645 @@
+// hinix/src/mock/msgqueue.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! An in-memory mock of the Posix Message Queue.
+//!
+//! This has the same interface as the `msgqueue` module. The queues are
+//! kept in a registry for the process, by name, so a queue can be opened
+//! by name from anywhere in the process until it's unlinked. As with the
+//! real queues, an unlinked queue lives on until its last handle is
+//! dropped.
+//!
+//! The names are checked like Linux does, and messages are received in
+//! order of priority, and then in the order they were sent. The system
+//! limits on the number and size of messages aren't enforced.
+//!
+
+use crate::{
+    cancel::CancelToken,
+    pool::{BufferPool, PooledBuf},
+    timeout::{Deadline, Timeout},
+    Error, Result,
+};
+use nix::{
+    mqueue::{mq_attr_member_t, MQ_OFlag},
+    sys::stat::Mode,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    mem::MaybeUninit,
+    slice,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, OnceLock,
+    },
+};
+
+#[cfg(feature = "ipc")]
+use crate::ipc::{IpcReceiver, IpcSender};
+
+/// Export the MqAttr struct from the nix crate.
+pub use nix::mqueue::MqAttr;
+
+/// The default priority for the Message Queue send operation.
+pub const DEFAULT_PRIO: u32 = 0;
+
+/// The number of message priorities.
+const MQ_PRIO_MAX: u32 = 32768;
+
+/// The size of a queue created without attributes, as on Linux.
+const DEFAULT_MAX_MSG: usize = 10;
+
+/// The message size of a queue created without attributes, as on Linux.
+const DEFAULT_MSG_SIZE: usize = 8192;
+
+/// A queue, which is shared by all the handles to it.
+#[derive(Debug)]
+struct Queue {
+    /// Max number of messages
+    max_msg: usize,
+    /// The size of each message
+    msg_size: usize,
+    /// The messages, with their priorities, in the order they're received
+    msgs: Mutex<VecDeque<(u32, Vec<u8>)>>,
+    /// Signaled when a message is sent or received
+    cond: Condvar,
+}
+
+/// Gets the registry of the queues in the process, by name.
+fn registry() -> &'static Mutex<HashMap<String, Arc<Queue>>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<String, Arc<Queue>>>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+/// Checks the name of a queue.
+///
+/// Like Linux, the name must be a slash followed by one or more
+/// characters, none of which are slashes. This fails with `EINVAL`
+/// otherwise.
+fn check_name(name: &str) -> Result<()> {
+    match name.strip_prefix('/') {
+        Some(s) if !s.is_empty() && !s.contains(&['/', '\0'][..]) => Ok(()),
+        _ => Err(Error::EINVAL),
+    }
+}
+
+/// A mock Posix Message Queue
+#[derive(Debug)]
+pub struct MsgQueue {
+    /// The queue
+    queue: Arc<Queue>,
+    /// The name the queue was opened with
+    name: Option<String>,
+    /// Whether the handle can receive messages
+    readable: bool,
+    /// Whether the handle can send messages
+    writable: bool,
+    /// Whether the handle is in non-blocking mode
+    nonblocking: AtomicBool,
+}
+
+impl MsgQueue {
+    /// Open an existing message queue for reading and writing.
+    ///
+    /// This fails with `ENOENT` if there's no queue with the name.
+    pub fn open(name: &str) -> Result<Self> {
+        Self::open_with_flags(name, MQ_OFlag::O_RDWR)
+    }
+
+    /// Open an existing message queue with the specified flags.
+    ///
+    /// If the flags include O_CREAT, a queue that doesn't exist is
+    /// created with the Linux default sizes.
+    pub fn open_with_flags(name: &str, flags: MQ_OFlag) -> Result<Self> {
+        if flags.contains(MQ_OFlag::O_CREAT) {
+            return Self::create_with_flags(
+                name,
+                flags,
+                Mode::empty(),
+                DEFAULT_MAX_MSG,
+                DEFAULT_MSG_SIZE,
+            );
+        }
+        check_name(name)?;
+        let queue = registry()
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or(Error::ENOENT)?;
+        Ok(Self::with_queue(queue, name, flags))
+    }
+
+    /// Create a new message queue for reading and writing with the
+    /// specified sizes.
+    ///
+    /// If the queue already exists, it's opened, and keeps its sizes.
+    pub fn create(name: &str, nmsg: usize, maxsz: usize) -> Result<Self> {
+        Self::create_with_flags(name, MQ_OFlag::O_RDWR, Mode::empty(), nmsg, maxsz)
+    }
+
+    /// Create a new message queue for reading and writing with the
+    /// specified sizes, failing with `EEXIST` if it already exists.
+    pub fn create_exclusive(name: &str, nmsg: usize, maxsz: usize) -> Result<Self> {
+        let flags = MQ_OFlag::O_RDWR | MQ_OFlag::O_EXCL;
+        Self::create_with_flags(name, flags, Mode::empty(), nmsg, maxsz)
+    }
+
+    /// Create a new message queue for reading and writing with the
+    /// specified flags and modes.
+    ///
+    /// The mode is ignored, since the queue isn't a file. This fails with
+    /// `EINVAL` if either of the sizes is zero.
+    pub fn create_with_flags(
+        name: &str,
+        flags: MQ_OFlag,
+        _mode: Mode,
+        max_msg: usize,
+        msg_size: usize,
+    ) -> Result<Self> {
+        check_name(name)?;
+        if max_msg == 0 || msg_size == 0 {
+            return Err(Error::EINVAL);
+        }
+
+        let mut registry = registry().lock().unwrap();
+        let queue = match registry.get(name) {
+            Some(_) if flags.contains(MQ_OFlag::O_EXCL) => return Err(Error::EEXIST),
+            Some(queue) => queue.clone(),
+            None => {
+                let queue = Arc::new(Queue {
+                    max_msg,
+                    msg_size,
+                    msgs: Mutex::new(VecDeque::with_capacity(max_msg)),
+                    cond: Condvar::new(),
+                });
+                registry.insert(name.to_string(), queue.clone());
+                queue
+            }
+        };
+        Ok(Self::with_queue(queue, name, flags))
+    }
+
+    /// Creates a handle to the queue.
+    fn with_queue(queue: Arc<Queue>, name: &str, flags: MQ_OFlag) -> Self {
+        let access = flags.bits() & libc::O_ACCMODE;
+        Self {
+            queue,
+            name: Some(name.to_string()),
+            readable: access != libc::O_WRONLY,
+            writable: access != libc::O_RDONLY,
+            nonblocking: AtomicBool::new(flags.contains(MQ_OFlag::O_NONBLOCK)),
+        }
+    }
+
+    /// Removes a message queue from the registry.
+    ///
+    /// The name is removed immediately, but the queue itself lives on
+    /// until all its handles are dropped. This fails with `ENOENT` if
+    /// there is no queue with the name.
+    pub fn unlink(name: &str) -> Result<()> {
+        check_name(name)?;
+        match registry().lock().unwrap().remove(name) {
+            Some(_) => Ok(()),
+            None => Err(Error::ENOENT),
+        }
+    }
+
+    /// Closes the queue.
+    ///
+    /// This can't fail, but is here to match the real queue. The queue
+    /// itself stays in the registry until it's unlinked.
+    pub fn close(self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Closes the queue, and removes it from the registry.
+    ///
+    /// This fails with `ENOENT` if the queue was already unlinked.
+    pub fn close_and_unlink(self) -> Result<()> {
+        match self.name {
+            Some(ref name) => Self::unlink(name),
+            None => Err(Error::ENOENT),
+        }
+    }
+
+    /// Gets the name the queue was opened with.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Gets the maximum number of messages that can be held in the queue
+    pub fn max_msg(&self) -> usize {
+        self.queue.max_msg
+    }
+
+    /// Gets the maxium size of each message in the queue
+    pub fn msg_size(&self) -> usize {
+        self.queue.msg_size
+    }
+
+    /// Sets the queue into non-blocking mode.
+    ///
+    /// This returns the previous attributes.
+    pub fn set_nonblock(&self) -> Result<MqAttr> {
+        self.set_nonblocking_mode(true)
+    }
+
+    /// Removes the queue from non-blocking mode.
+    ///
+    /// This returns the previous attributes.
+    pub fn remove_nonblock(&self) -> Result<MqAttr> {
+        self.set_nonblocking_mode(false)
+    }
+
+    /// Determines if the queue is in non-blocking mode.
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Acquire)
+    }
+
+    fn set_nonblocking_mode(&self, on: bool) -> Result<MqAttr> {
+        let attr = self.get_attr()?;
+        self.nonblocking.store(on, Ordering::Release);
+        Ok(attr)
+    }
+
+    /// Sets the attributes for the message queue, returning the previous
+    /// ones.
+    ///
+    /// Only the O_NONBLOCK flag can be changed. The other values are
+    /// ignored.
+    pub fn set_attr(&self, attr: &MqAttr) -> Result<MqAttr> {
+        let on = attr.flags() & libc::O_NONBLOCK as mq_attr_member_t != 0;
+        self.set_nonblocking_mode(on)
+    }
+
+    /// Gets the attributes for the message queue
+    pub fn get_attr(&self) -> Result<MqAttr> {
+        let flags = match self.is_nonblocking() {
+            true => libc::O_NONBLOCK as mq_attr_member_t,
+            false => 0,
+        };
+        let curmsgs = self.queue.msgs.lock().unwrap().len();
+        Ok(MqAttr::new(
+            flags,
+            self.queue.max_msg as mq_attr_member_t,
+            self.queue.msg_size as mq_attr_member_t,
+            curmsgs as mq_attr_member_t,
+        ))
+    }
+
+    /// Sends a message to the queue with the default priority
+    pub fn send<M>(&self, msg: M) -> Result<()>
+    where
+        M: AsRef<[u8]>,
+    {
+        self.send_with_priority(msg.as_ref(), DEFAULT_PRIO)
+    }
+
+    /// Sends a message to the queue
+    pub fn send_with_priority<M>(&self, msg: M, prio: u32) -> Result<()>
+    where
+        M: AsRef<[u8]>,
+    {
+        self.send_timeout(msg, prio, Timeout::None)
+    }
+
+    /// Sends a message to the queue with priority, waiting no longer than
+    /// the specified timeout for room in the queue.
+    ///
+    /// This fails with `ETIMEDOUT` if the queue stayed full, or `EAGAIN`
+    /// if the queue is in non-blocking mode and is full.
+    pub fn send_timeout<M, T>(&self, msg: M, prio: u32, timeout: T) -> Result<()>
+    where
+        M: AsRef<[u8]>,
+        T: Into<Timeout>,
+    {
+        let msg = msg.as_ref();
+        fault_point!(MqSend)?;
+        if !self.writable {
+            return Err(Error::EBADF);
+        }
+        if msg.len() > self.queue.msg_size {
+            return Err(Error::EMSGSIZE);
+        }
+        if prio >= MQ_PRIO_MAX {
+            return Err(Error::EINVAL);
+        }
+
+        let (deadline, timeout_err) = self.deadline(timeout);
+        let max_msg = self.queue.max_msg;
+        let msgs = self.queue.msgs.lock().unwrap();
+        let (mut msgs, ready) =
+            super::wait_while(&self.queue.cond, msgs, deadline, None, |msgs| {
+                msgs.len() >= max_msg
+            })?;
+        if !ready {
+            return Err(timeout_err);
+        }
+
+        // After all the messages of the same or higher priority
+        let pos = msgs
+            .iter()
+            .position(|(p, _)| *p < prio)
+            .unwrap_or(msgs.len());
+        msgs.insert(pos, (prio, msg.to_vec()));
+        self.queue.cond.notify_all();
+        Ok(())
+    }
+
+    /// Gets the deadline for a timed call, and the error when it passes.
+    fn deadline<T: Into<Timeout>>(&self, timeout: T) -> (Deadline, Error) {
+        match self.is_nonblocking() {
+            true => (Deadline::start(Timeout::ZERO), Error::EAGAIN),
+            false => (Deadline::start(timeout), Error::ETIMEDOUT),
+        }
+    }
+
+    /// Receive a message
+    pub fn receive(&self, msg: &mut [u8]) -> Result<usize> {
+        let mut prio = 0;
+        self.receive_with_priority(msg, &mut prio)
+    }
+
+    /// Receive a message
+    pub fn receive_bytes(&self) -> Result<Vec<u8>> {
+        let mut prio = 0;
+        let mut buf = vec![0u8; self.queue.msg_size];
+        let n = self.receive_with_priority(&mut buf, &mut prio)?;
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    /// Receives a message as a UTF-8 string
+    pub fn receive_string(&self) -> Result<String> {
+        let v = self.receive_bytes()?;
+        let s = String::from_utf8(v).map_err(|_| Error::EINVAL)?;
+        Ok(s)
+    }
+
+    /// Receives a message from the queue with priority
+    pub fn receive_with_priority(&self, msg: &mut [u8], prio: &mut u32) -> Result<usize> {
+        self.receive_timeout(msg, prio, Timeout::None)
+    }
+
+    /// Receives a message into a buffer from the pool.
+    ///
+    /// The buffers in the pool must be able to hold the largest message
+    /// in the queue, otherwise this fails with `EMSGSIZE`.
+    pub fn receive_pooled(&self, pool: &BufferPool) -> Result<PooledBuf> {
+        let mut prio = 0;
+        let mut buf = pool.get();
+        let n = self.receive_with_priority(buf.as_full_mut(), &mut prio)?;
+        buf.set_len(n);
+        Ok(buf)
+    }
+
+    /// Receives a message from the queue with priority, into a buffer
+    /// that may be uninitialized, so it doesn't need to be cleared first.
+    ///
+    /// Returns the part of the buffer that holds the message.
+    pub fn receive_uninit<'a>(
+        &self,
+        msg: &'a mut [MaybeUninit<u8>],
+        prio: &mut u32,
+    ) -> Result<&'a mut [u8]> {
+        let deadline = self.deadline(Timeout::None);
+        let data = self.receive_until(msg.len(), prio, deadline, None)?;
+        for (dst, src) in msg.iter_mut().zip(&data) {
+            dst.write(*src);
+        }
+        // The first bytes were copied from the message
+        Ok(unsafe { slice::from_raw_parts_mut(msg.as_mut_ptr() as *mut u8, data.len()) })
+    }
+
+    /// Receives a message from the queue with priority, waiting no longer
+    /// than the specified timeout.
+    ///
+    /// This fails with `ETIMEDOUT` if no message arrived in time, or
+    /// `EAGAIN` if the queue is in non-blocking mode and is empty.
+    pub fn receive_timeout<T: Into<Timeout>>(
+        &self,
+        msg: &mut [u8],
+        prio: &mut u32,
+        timeout: T,
+    ) -> Result<usize> {
+        let data = self.receive_until(msg.len(), prio, self.deadline(timeout), None)?;
+        msg[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    /// Receives a message from the queue with priority, waiting no longer
+    /// than the specified timeout, unless the token is cancelled first.
+    ///
+    /// This fails with `ETIMEDOUT` if no message arrived in time, or
+    /// `ECANCELED` if the token was cancelled.
+    pub fn receive_cancellable<T: Into<Timeout>>(
+        &self,
+        msg: &mut [u8],
+        prio: &mut u32,
+        timeout: T,
+        cancel: &CancelToken,
+    ) -> Result<usize> {
+        let deadline = (Deadline::start(timeout), Error::ETIMEDOUT);
+        let data = self.receive_until(msg.len(), prio, deadline, Some(cancel))?;
+        msg[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    /// Waits for a message, then takes it from the queue.
+    ///
+    /// Like the real queue, this fails with `EMSGSIZE` if the buffer is
+    /// smaller than the message size of the queue.
+    fn receive_until(
+        &self,
+        len: usize,
+        prio: &mut u32,
+        (deadline, timeout_err): (Deadline, Error),
+        cancel: Option<&CancelToken>,
+    ) -> Result<Vec<u8>> {
+        fault_point!(MqReceive)?;
+        if !self.readable {
+            return Err(Error::EBADF);
+        }
+        if len < self.queue.msg_size {
+            return Err(Error::EMSGSIZE);
+        }
+
+        let msgs = self.queue.msgs.lock().unwrap();
+        let (mut msgs, _) = super::wait_while(&self.queue.cond, msgs, deadline, cancel, |msgs| {
+            msgs.is_empty()
+        })?;
+        let (msg_prio, data) = msgs.pop_front().ok_or(timeout_err)?;
+        self.queue.cond.notify_all();
+        *prio = msg_prio;
+        Ok(data)
+    }
+}
+
+// The mock is a message transport, like the real queue.
+
+#[cfg(feature = "ipc")]
+impl IpcSender for MsgQueue {
+    fn max_send_msg_size(&self) -> usize {
+        self.msg_size()
+    }
+
+    fn send_msg(&self, buf: &[u8]) -> Result<()> {
+        self.send(buf)
+    }
+
+    fn send_msg_timeout(&self, buf: &[u8], timeout: Timeout) -> Result<bool> {
+        match self.send_timeout(buf, DEFAULT_PRIO, timeout) {
+            Ok(()) => Ok(true),
+            Err(err) if err == Error::ETIMEDOUT || err == Error::EAGAIN => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(feature = "ipc")]
+impl IpcReceiver for MsgQueue {
+    fn max_recv_msg_size(&self) -> usize {
+        self.msg_size()
+    }
+
+    fn recv_msg(&self, buf: &mut [u8]) -> Result<usize> {
+        self.receive(buf)
+    }
+
+    fn recv_msg_timeout(&self, buf: &mut [u8], timeout: Timeout) -> Result<Option<usize>> {
+        let mut prio = 0;
+        match self.receive_timeout(buf, &mut prio, timeout) {
+            Ok(n) => Ok(Some(n)),
+            Err(err) if err == Error::ETIMEDOUT || err == Error::EAGAIN => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_names() {
+        assert_eq!(
+            Error::EINVAL,
+            MsgQueue::create("no_slash", 4, 64).unwrap_err()
+        );
+        assert_eq!(Error::EINVAL, MsgQueue::create("/", 4, 64).unwrap_err());
+        assert_eq!(Error::EINVAL, MsgQueue::create("/a/b", 4, 64).unwrap_err());
+        assert_eq!(
+            Error::ENOENT,
+            MsgQueue::open("/mock_mq_missing").unwrap_err()
+        );
+        assert_eq!(
+            Error::ENOENT,
+            MsgQueue::unlink("/mock_mq_missing").unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_send_receive() {
+        const NAME: &str = "/mock_mq_send_receive";
+
+        let mq = MsgQueue::create_exclusive(NAME, 4, 16).unwrap();
+        assert_eq!(
+            Error::EEXIST,
+            MsgQueue::create_exclusive(NAME, 4, 16).unwrap_err()
+        );
+
+        // Opened by name, it's the same queue
+        let tx = MsgQueue::open_with_flags(NAME, MQ_OFlag::O_WRONLY).unwrap();
+        assert_eq!(16, tx.msg_size());
+        tx.send_with_priority("low", 1).unwrap();
+        tx.send_with_priority("high", 5).unwrap();
+        tx.send_with_priority("low2", 1).unwrap();
+        assert_eq!(3, mq.get_attr().unwrap().curmsgs());
+
+        let mut prio = 0;
+        let mut buf = [0u8; 16];
+        assert_eq!(4, mq.receive_with_priority(&mut buf, &mut prio).unwrap());
+        assert_eq!((b"high".as_slice(), 5), (&buf[..4], prio));
+        assert_eq!("low", mq.receive_string().unwrap());
+        assert_eq!(b"low2".to_vec(), mq.receive_bytes().unwrap());
+
+        assert_eq!(Error::EMSGSIZE, tx.send([0u8; 17]).unwrap_err());
+        assert_eq!(Error::EMSGSIZE, mq.receive(&mut [0u8; 8]).unwrap_err());
+        assert_eq!(Error::EBADF, tx.receive(&mut buf).unwrap_err());
+
+        // Unlinked, it lives on for the open handles
+        mq.close_and_unlink().unwrap();
+        tx.send("hello").unwrap();
+        assert_eq!(Error::ENOENT, MsgQueue::open(NAME).unwrap_err());
+    }
+
+    #[test]
+    fn test_blocking() {
+        const NAME: &str = "/mock_mq_blocking";
+
+        let mq = MsgQueue::create(NAME, 1, 16).unwrap();
+        MsgQueue::unlink(NAME).unwrap();
+
+        let mut prio = 0;
+        let mut buf = [0u8; 16];
+        let timeout = Duration::from_millis(1);
+        let res = mq.receive_timeout(&mut buf, &mut prio, timeout);
+        assert_eq!(Error::ETIMEDOUT, res.unwrap_err());
+
+        mq.send("one").unwrap();
+        let res = mq.send_timeout("two", 0, timeout);
+        assert_eq!(Error::ETIMEDOUT, res.unwrap_err());
+
+        mq.set_nonblock().unwrap();
+        assert!(mq.is_nonblocking());
+        assert_eq!(Error::EAGAIN, mq.send("two").unwrap_err());
+        mq.remove_nonblock().unwrap();
+
+        // A full queue blocks the sender until there's room
+        let mq = Arc::new(mq);
+        let th = thread::spawn({
+            let mq = mq.clone();
+            move || mq.send("two")
+        });
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!("one", mq.receive_string().unwrap());
+        th.join().unwrap().unwrap();
+        assert_eq!("two", mq.receive_string().unwrap());
+
+        let cancel = CancelToken::new().unwrap();
+        cancel.cancel();
+        let res = mq.receive_cancellable(&mut buf, &mut prio, Timeout::None, &cancel);
+        assert_eq!(Error::ECANCELED, res.unwrap_err());
+    }
+
+    #[cfg(feature = "ipc")]
+    #[test]
+    fn test_ipc() {
+        const NAME: &str = "/mock_mq_ipc";
+
+        let tx = MsgQueue::create(NAME, 1, 16).unwrap();
+        let rx = MsgQueue::open(NAME).unwrap();
+        MsgQueue::unlink(NAME).unwrap();
+
+        let mut buf = [0u8; 16];
+        assert_eq!(None, rx.try_recv_msg(&mut buf).unwrap());
+        assert!(tx.try_send_msg(b"hello").unwrap());
+        assert!(!tx.try_send_msg(b"world").unwrap());
+        assert_eq!(5, rx.recv_msg(&mut buf).unwrap());
+        assert_eq!(b"hello", &buf[..5]);
+    }
+}
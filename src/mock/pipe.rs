@@ -0,0 +1,397 @@
+// hinix/src/mock/pipe.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! An in-memory mock of the pipe.
+//!
+//! This has the same interface as the `pipe` module. The pipe holds up
+//! to 64kB, the default capacity of a pipe in Linux, and a write blocks
+//! while it's full. The reader sees the end of the stream once the write
+//! end is dropped, and a write fails with `EPIPE` once the read end is
+//! dropped.
+//!
+
+use crate::{
+    cancel::CancelToken,
+    pool::{BufferPool, PooledBuf},
+    timeout::{Deadline, Timeout},
+    Error, Result,
+};
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    mem::MaybeUninit,
+    slice,
+    sync::{Arc, Condvar, Mutex},
+};
+
+#[cfg(feature = "ipc")]
+use crate::ipc::{IpcReceiver, IpcSender};
+
+/// The largest message that can be written to a packet pipe.
+///
+/// Larger writes are split into multiple packets.
+pub const PIPE_BUF: usize = libc::PIPE_BUF;
+
+/// The number of bytes the pipe can hold.
+const PIPE_CAPACITY: usize = 65536;
+
+/// The contents of the pipe, and the ends that are still open.
+#[derive(Debug, Default)]
+struct State {
+    /// The data in the pipe
+    data: VecDeque<u8>,
+    /// The size of each packet in the data, in packet mode
+    packets: VecDeque<usize>,
+    /// Whether the read end is open
+    reader: bool,
+    /// Whether the write end is open
+    writer: bool,
+}
+
+/// The state shared by the two ends of the pipe.
+#[derive(Debug)]
+struct Inner {
+    /// Whether the pipe is in packet mode
+    packet: bool,
+    /// The contents of the pipe
+    state: Mutex<State>,
+    /// Signaled when the contents change, or an end is closed
+    cond: Condvar,
+}
+
+/// Creates the two ends of a pipe.
+fn new_pipe(packet: bool) -> (WritePipe, ReadPipe) {
+    let inner = Arc::new(Inner {
+        packet,
+        state: Mutex::new(State {
+            reader: true,
+            writer: true,
+            ..State::default()
+        }),
+        cond: Condvar::new(),
+    });
+    (WritePipe(inner.clone()), ReadPipe(inner))
+}
+
+/// Creates a pipe.
+pub fn pipe() -> Result<(WritePipe, ReadPipe)> {
+    Ok(new_pipe(false))
+}
+
+/// Creates a pipe in packet mode.
+///
+/// Each write of up to [`PIPE_BUF`] bytes is a separate packet, and each
+/// read returns at most one packet. If the read buffer is smaller than
+/// the packet, the rest of it is discarded.
+pub fn packet_pipe() -> Result<(WritePipe, ReadPipe)> {
+    Ok(new_pipe(true))
+}
+
+/// Read-end of a mock pipe.
+#[derive(Debug)]
+pub struct ReadPipe(Arc<Inner>);
+
+impl ReadPipe {
+    /// Closes the read end of the pipe.
+    ///
+    /// This can't fail, but is here to match the real pipe.
+    pub fn close(self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Waits for data, or for the write end to close, and then reads
+    /// what's there into the buffer.
+    ///
+    /// Returns `None` if the deadline passed first, or `Some(0)` if the
+    /// write end was closed.
+    fn read_until(
+        &self,
+        buf: &mut [MaybeUninit<u8>],
+        deadline: Deadline,
+        cancel: Option<&CancelToken>,
+    ) -> Result<Option<usize>> {
+        fault_point!(PipeRead)?;
+        let state = self.0.state.lock().unwrap();
+        let (mut state, ready) = super::wait_while(&self.0.cond, state, deadline, cancel, |st| {
+            st.data.is_empty() && st.writer
+        })?;
+        if !ready {
+            return Ok(None);
+        }
+
+        // A packet is taken whole, even if it doesn't all fit
+        let take = match self.0.packet {
+            true => state.packets.pop_front().unwrap_or_default(),
+            false => state.data.len().min(buf.len()),
+        };
+        let n = take.min(buf.len());
+        for (dst, src) in buf.iter_mut().zip(state.data.drain(..take)) {
+            dst.write(src);
+        }
+        self.0.cond.notify_all();
+        Ok(Some(n))
+    }
+
+    /// Waits up to the timeout for data, then reads it into the buffer.
+    ///
+    /// Returns `None` if the timeout expired first, or `Some(0)` if the
+    /// write end of the pipe was closed.
+    pub fn read_timeout<T: Into<Timeout>>(
+        &mut self,
+        buf: &mut [u8],
+        timeout: T,
+    ) -> Result<Option<usize>> {
+        self.read_until(as_uninit(buf), Deadline::start(timeout), None)
+    }
+
+    /// Waits up to the timeout for data, then reads it into the buffer,
+    /// unless the token is cancelled first.
+    ///
+    /// Returns `None` if the timeout expired first, or `Some(0)` if the
+    /// write end of the pipe was closed. Fails with `ECANCELED` if the
+    /// token was cancelled.
+    pub fn read_cancellable<T: Into<Timeout>>(
+        &mut self,
+        buf: &mut [u8],
+        timeout: T,
+        cancel: &CancelToken,
+    ) -> Result<Option<usize>> {
+        self.read_until(as_uninit(buf), Deadline::start(timeout), Some(cancel))
+    }
+
+    /// Reads data into a buffer that may be uninitialized, so it doesn't
+    /// need to be cleared first.
+    ///
+    /// Returns the part of the buffer that was filled, which is empty if
+    /// the write end of the pipe was closed.
+    pub fn read_uninit<'a>(&mut self, buf: &'a mut [MaybeUninit<u8>]) -> Result<&'a mut [u8]> {
+        let n = self
+            .read_until(buf, Deadline::start(Timeout::None), None)?
+            .unwrap_or_default();
+        // The first 'n' bytes were copied from the pipe
+        Ok(unsafe { slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, n) })
+    }
+
+    /// Reads data into a buffer from the pool.
+    ///
+    /// The buffer is empty if the write end of the pipe was closed.
+    pub fn read_pooled(&mut self, pool: &BufferPool) -> Result<PooledBuf> {
+        let mut buf = pool.get();
+        let n = self.read(buf.as_full_mut())?;
+        buf.set_len(n);
+        Ok(buf)
+    }
+}
+
+impl Read for ReadPipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.read_until(as_uninit(buf), Deadline::start(Timeout::None), None)?;
+        Ok(n.unwrap_or_default())
+    }
+}
+
+impl Drop for ReadPipe {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().reader = false;
+        self.0.cond.notify_all();
+    }
+}
+
+/// Write-end of a mock pipe.
+#[derive(Debug)]
+pub struct WritePipe(Arc<Inner>);
+
+impl WritePipe {
+    /// Closes the write end of the pipe.
+    ///
+    /// This can't fail, but is here to match the real pipe. Either way,
+    /// the reader sees the end of the stream once the write end is closed.
+    pub fn close(self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Waits for room in the pipe, then writes as much of the buffer as
+    /// fits, or one whole packet in packet mode.
+    fn write_until(&self, buf: &[u8], deadline: Deadline) -> Result<Option<usize>> {
+        fault_point!(PipeWrite)?;
+        if buf.is_empty() {
+            return Ok(Some(0));
+        }
+        let (len, room) = match self.0.packet {
+            true => (buf.len().min(PIPE_BUF), buf.len().min(PIPE_BUF)),
+            false => (buf.len(), 1),
+        };
+        let state = self.0.state.lock().unwrap();
+        let (mut state, ready) = super::wait_while(&self.0.cond, state, deadline, None, |st| {
+            st.reader && PIPE_CAPACITY - st.data.len() < room
+        })?;
+        if !state.reader {
+            return Err(Error::EPIPE);
+        }
+        if !ready {
+            return Ok(None);
+        }
+
+        let n = match self.0.packet {
+            true => {
+                state.packets.push_back(len);
+                len
+            }
+            false => len.min(PIPE_CAPACITY - state.data.len()),
+        };
+        state.data.extend(&buf[..n]);
+        self.0.cond.notify_all();
+        Ok(Some(n))
+    }
+}
+
+impl Write for WritePipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.write_until(buf, Deadline::start(Timeout::None))?;
+        Ok(n.unwrap_or_default())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for WritePipe {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().writer = false;
+        self.0.cond.notify_all();
+    }
+}
+
+/// Gets an initialized buffer as one that may not be.
+fn as_uninit(buf: &mut [u8]) -> &mut [MaybeUninit<u8>] {
+    // Only initialized bytes are ever written to the buffer
+    unsafe { slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut MaybeUninit<u8>, buf.len()) }
+}
+
+// The ends of a packet pipe are a message transport, like the real ones.
+
+#[cfg(feature = "ipc")]
+impl IpcSender for WritePipe {
+    fn max_send_msg_size(&self) -> usize {
+        PIPE_BUF
+    }
+
+    fn send_msg(&self, buf: &[u8]) -> Result<()> {
+        self.send_msg_timeout(buf, Timeout::None).map(drop)
+    }
+
+    fn send_msg_timeout(&self, buf: &[u8], timeout: Timeout) -> Result<bool> {
+        if buf.len() > PIPE_BUF {
+            return Err(Error::EMSGSIZE);
+        }
+        Ok(self.write_until(buf, Deadline::start(timeout))?.is_some())
+    }
+}
+
+#[cfg(feature = "ipc")]
+impl IpcReceiver for ReadPipe {
+    fn max_recv_msg_size(&self) -> usize {
+        PIPE_BUF
+    }
+
+    fn recv_msg(&self, buf: &mut [u8]) -> Result<usize> {
+        self.recv_msg_timeout(buf, Timeout::None)
+            .map(Option::unwrap_or_default)
+    }
+
+    fn recv_msg_timeout(&self, buf: &mut [u8], timeout: Timeout) -> Result<Option<usize>> {
+        // A zero-length read is the writer closing the pipe
+        match self.read_until(as_uninit(buf), Deadline::start(timeout), None)? {
+            Some(0) => Err(Error::EPIPE),
+            res => Ok(res),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_pipe() {
+        let (mut wr, mut rd) = pipe().unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            None,
+            rd.read_timeout(&mut buf, Duration::from_millis(1)).unwrap()
+        );
+
+        wr.write_all(b"hello").unwrap();
+        wr.write_all(b"world").unwrap();
+        assert_eq!(10, rd.read(&mut buf).unwrap());
+        assert_eq!(b"helloworld", &buf[..10]);
+
+        // A full pipe blocks the writer until there's room
+        let th = thread::spawn(move || {
+            wr.write_all(&[0u8; PIPE_CAPACITY + 16]).unwrap();
+            wr
+        });
+        thread::sleep(Duration::from_millis(10));
+        let mut data = vec![0u8; PIPE_CAPACITY + 16];
+        rd.read_exact(&mut data).unwrap();
+        let wr = th.join().unwrap();
+
+        // The reader sees the end of the stream
+        drop(wr);
+        assert_eq!(0, rd.read(&mut buf).unwrap());
+
+        let (mut wr, rd) = pipe().unwrap();
+        drop(rd);
+        let err = wr.write(b"hello").unwrap_err();
+        assert_eq!(Some(libc::EPIPE), err.raw_os_error());
+    }
+
+    #[test]
+    fn test_packet_pipe() {
+        let (mut wr, mut rd) = packet_pipe().unwrap();
+        wr.write_all(b"hello").unwrap();
+        wr.write_all(b"world").unwrap();
+
+        let mut buf = [0u8; 16];
+        assert_eq!(5, rd.read(&mut buf).unwrap());
+        assert_eq!(b"hello", &buf[..5]);
+
+        // The rest of a packet that doesn't fit is discarded
+        let mut buf = [0u8; 3];
+        assert_eq!(3, rd.read(&mut buf).unwrap());
+        assert_eq!(b"wor", &buf);
+
+        wr.write_all(b"abc").unwrap();
+        let mut buf = [MaybeUninit::uninit(); 8];
+        assert_eq!(b"abc", rd.read_uninit(&mut buf).unwrap());
+    }
+
+    #[cfg(feature = "ipc")]
+    #[test]
+    fn test_ipc() {
+        let (wr, rd) = packet_pipe().unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(None, rd.try_recv_msg(&mut buf).unwrap());
+
+        wr.send_msg(b"hello").unwrap();
+        assert_eq!(5, rd.recv_msg(&mut buf).unwrap());
+        assert_eq!(b"hello", &buf[..5]);
+
+        drop(wr);
+        assert_eq!(Error::EPIPE, rd.recv_msg(&mut buf).unwrap_err());
+    }
+}
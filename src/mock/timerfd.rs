@@ -0,0 +1,215 @@
+// hinix/src/mock/timerfd.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! An in-memory mock of the timerfd timer.
+//!
+//! This has the same interface as the `timerfd` module. The timer's
+//! schedule is kept in the object, and runs on the monotonic clock of
+//! `std::time::Instant`, whichever clock it was created with. The clock
+//! is only read to convert an absolute time to a delay from now.
+//!
+
+use crate::{
+    clock::{self, ClockId},
+    Error, Result,
+};
+use nix::sys::timerfd;
+use std::{
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The flags used to create a TimerFd
+pub type TimerFlags = timerfd::TimerFlags;
+
+/// The schedule of the timer.
+#[derive(Debug, Default, Clone, Copy)]
+struct Schedule {
+    /// The next time the timer expires, or `None` if it's disarmed
+    next: Option<Instant>,
+    /// The period of the timer, which is zero for a one-shot timer
+    interval: Duration,
+}
+
+/// A mock timer.
+#[derive(Debug)]
+pub struct TimerFd {
+    /// The clock for absolute times
+    clock: ClockId,
+    /// Whether a wait is non-blocking
+    nonblocking: bool,
+    /// The schedule
+    sched: Mutex<Schedule>,
+    /// Signaled when the timer is armed or disarmed
+    cond: Condvar,
+}
+
+impl TimerFd {
+    /// Creates a new, disarmed, timer using the specified clock.
+    pub fn new(clock: ClockId) -> Result<Self> {
+        Self::with_flags(clock, TimerFlags::empty())
+    }
+
+    /// Creates a new, disarmed, timer with the specified flags.
+    pub fn with_flags(clock: ClockId, flags: TimerFlags) -> Result<Self> {
+        Ok(Self {
+            clock,
+            nonblocking: flags.contains(TimerFlags::TFD_NONBLOCK),
+            sched: Mutex::new(Schedule::default()),
+            cond: Condvar::new(),
+        })
+    }
+
+    fn settime(&self, next: Option<Instant>, interval: Duration) -> Result<()> {
+        *self.sched.lock().unwrap() = Schedule { next, interval };
+        self.cond.notify_all();
+        Ok(())
+    }
+
+    /// Arms the timer to expire once, after the delay.
+    pub fn set_oneshot(&self, delay: Duration) -> Result<()> {
+        self.settime(Some(Instant::now() + delay), Duration::ZERO)
+    }
+
+    /// Arms the timer to expire periodically, starting one interval
+    /// from now.
+    ///
+    /// This fails with `EINVAL` if the interval is zero.
+    pub fn set_periodic(&self, interval: Duration) -> Result<()> {
+        if interval.is_zero() {
+            return Err(Error::EINVAL);
+        }
+        self.settime(Some(Instant::now() + interval), interval)
+    }
+
+    /// Arms the timer to expire at an absolute time on its clock, and
+    /// then, optionally, periodically after that.
+    pub fn set_absolute(&self, deadline: Duration, interval: Option<Duration>) -> Result<()> {
+        let delay = deadline.saturating_sub(clock::now(self.clock)?);
+        self.settime(Some(Instant::now() + delay), interval.unwrap_or_default())
+    }
+
+    /// Disarms the timer.
+    pub fn disarm(&self) -> Result<()> {
+        self.settime(None, Duration::ZERO)
+    }
+
+    /// Gets the time until the timer next expires, or `None` if it's
+    /// disarmed.
+    ///
+    /// As with the real timer, a one-shot timer that expired is disarmed,
+    /// even before it's waited on.
+    pub fn remaining(&self) -> Result<Option<Duration>> {
+        let sched = self.sched.lock().unwrap();
+        let now = Instant::now();
+        Ok(match sched.next {
+            Some(next) if next > now => Some(next - now),
+            Some(next) if !sched.interval.is_zero() => {
+                let since = (now - next).as_nanos() % sched.interval.as_nanos();
+                Some(sched.interval - Duration::from_nanos(since as u64))
+            }
+            _ => None,
+        })
+    }
+
+    /// Waits for the timer to expire, and returns the number of times it
+    /// expired since it was armed, or since the last read.
+    ///
+    /// A result greater than one means that periods were missed. If the
+    /// timer is non-blocking, this fails with `EAGAIN` if it hasn't yet
+    /// expired.
+    pub fn wait(&self) -> Result<u64> {
+        fault_point!(TimerWait)?;
+        let mut sched = self.sched.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let wait = match sched.next {
+                Some(next) if next <= now => break,
+                Some(next) => Some(next - now),
+                None => None,
+            };
+            if self.nonblocking {
+                return Err(Error::EAGAIN);
+            }
+            // Re-arming the timer wakes the wait to start over
+            sched = match wait {
+                Some(dur) => self.cond.wait_timeout(sched, dur).unwrap().0,
+                None => self.cond.wait(sched).unwrap(),
+            };
+        }
+
+        let now = Instant::now();
+        let next = sched.next.unwrap_or(now);
+        if sched.interval.is_zero() {
+            sched.next = None;
+            return Ok(1);
+        }
+        let missed = (now - next).as_nanos() / sched.interval.as_nanos();
+        let count = u32::try_from(missed + 1).unwrap_or(u32::MAX);
+        sched.next = Some(next + sched.interval * count);
+        Ok(u64::from(count))
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn test_oneshot() {
+        let tfd = TimerFd::new(ClockId::CLOCK_MONOTONIC).unwrap();
+        assert_eq!(None, tfd.remaining().unwrap());
+
+        let start = Instant::now();
+        tfd.set_oneshot(Duration::from_millis(20)).unwrap();
+        assert!(tfd.remaining().unwrap().is_some());
+
+        assert_eq!(1, tfd.wait().unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(None, tfd.remaining().unwrap());
+    }
+
+    #[test]
+    fn test_periodic() {
+        let tfd = TimerFd::with_flags(ClockId::CLOCK_MONOTONIC, TimerFlags::TFD_NONBLOCK).unwrap();
+        assert_eq!(Error::EINVAL, tfd.set_periodic(Duration::ZERO).unwrap_err());
+        assert_eq!(Error::EAGAIN, tfd.wait().unwrap_err());
+
+        tfd.set_periodic(Duration::from_millis(5)).unwrap();
+        thread::sleep(Duration::from_millis(30));
+
+        // Several periods were missed
+        assert!(tfd.wait().unwrap() >= 2);
+
+        tfd.disarm().unwrap();
+        assert_eq!(None, tfd.remaining().unwrap());
+    }
+
+    #[test]
+    fn test_rearm() {
+        let tfd = Arc::new(TimerFd::new(ClockId::CLOCK_MONOTONIC).unwrap());
+
+        // A disarmed timer waits until it's armed, and then expires
+        let th = thread::spawn({
+            let tfd = tfd.clone();
+            move || tfd.wait()
+        });
+        thread::sleep(Duration::from_millis(10));
+        let deadline = clock::now(ClockId::CLOCK_MONOTONIC).unwrap() + Duration::from_millis(5);
+        tfd.set_absolute(deadline, None).unwrap();
+        assert_eq!(1, th.join().unwrap().unwrap());
+    }
+}
@@ -0,0 +1,207 @@
+// hinix/src/mount.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Mounting and unmounting filesystems.
+//!
+//! These are helpers for the common mount operations needed to assemble
+//! a filesystem view for a sandbox or container, typically after moving
+//! into a new mount namespace with [`crate::ns::unshare()`].
+//!
+//! Note that, by default, mounts in a new namespace still propagate to
+//! and from the parent namespace if they are shared. Use
+//! [`make_private()`] on the root first to stop that.
+//!
+//! These require the `CAP_SYS_ADMIN` capability in the user namespace
+//! that owns the mount namespace.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man2/mount.2.html>
+//!
+
+use crate::{error::ResultExt, Result};
+use nix::{mount, sys::stat::Mode};
+use std::path::Path;
+
+/// Flags for mount operations.
+pub use nix::mount::MsFlags;
+
+/// Flags for unmount operations.
+pub use nix::mount::MntFlags;
+
+/// Mounts a filesystem.
+///
+/// This is a direct wrapper around the mount() system call. The
+/// `source`, `fstype`, and `data` arguments are optional, depending on
+/// the operation specified in the flags.
+pub fn mount<P1, P2>(
+    source: Option<&P1>,
+    target: &P2,
+    fstype: Option<&str>,
+    flags: MsFlags,
+    data: Option<&str>,
+) -> Result<()>
+where
+    P1: AsRef<Path> + ?Sized,
+    P2: AsRef<Path> + ?Sized,
+{
+    let target = target.as_ref();
+    mount::mount(source.map(|p| p.as_ref()), target, fstype, flags, data)
+        .op_on("mount", target.display())
+}
+
+/// Unmounts a filesystem.
+pub fn umount<P: AsRef<Path> + ?Sized>(target: &P) -> Result<()> {
+    let target = target.as_ref();
+    mount::umount(target).op_on("umount", target.display())
+}
+
+/// Unmounts a filesystem, with options.
+///
+/// For example, use `MntFlags::MNT_DETACH` to do a "lazy" unmount, which
+/// detaches it immediately, but cleans up when it's no longer busy.
+pub fn umount2<P: AsRef<Path> + ?Sized>(target: &P, flags: MntFlags) -> Result<()> {
+    let target = target.as_ref();
+    mount::umount2(target, flags).op_on("umount2", target.display())
+}
+
+/// Makes a file or directory tree visible at another location.
+///
+/// If `recursive` is set, any mounts under `source` are also bound.
+pub fn bind_mount<P1, P2>(source: &P1, target: &P2, recursive: bool) -> Result<()>
+where
+    P1: AsRef<Path> + ?Sized,
+    P2: AsRef<Path> + ?Sized,
+{
+    let mut flags = MsFlags::MS_BIND;
+    if recursive {
+        flags |= MsFlags::MS_REC;
+    }
+    mount(Some(source), target, None, flags, None)
+}
+
+/// Makes an existing mount point read-only.
+///
+/// This only changes the mount point at `target`, not the underlying
+/// filesystem, so it can be used on a bind mount to make a read-only
+/// view of a directory.
+pub fn remount_readonly<P: AsRef<Path> + ?Sized>(target: &P) -> Result<()> {
+    let flags = MsFlags::MS_REMOUNT | MsFlags::MS_BIND | MsFlags::MS_RDONLY;
+    mount(None::<&Path>, target, None, flags, None)
+}
+
+/// Mounts a new tmpfs (memory-backed) filesystem.
+///
+/// The `size` is the maximum size of the filesystem, in bytes, and `mode`
+/// is the permissions for its root directory. If not specified, the
+/// kernel defaults are half of RAM, and 01777.
+pub fn mount_tmpfs<P: AsRef<Path> + ?Sized>(
+    target: &P,
+    size: Option<usize>,
+    mode: Option<Mode>,
+) -> Result<()> {
+    let mut opts = Vec::new();
+    if let Some(size) = size {
+        opts.push(format!("size={}", size));
+    }
+    if let Some(mode) = mode {
+        opts.push(format!("mode={:o}", mode.bits()));
+    }
+    let data = opts.join(",");
+    let data = if data.is_empty() {
+        None
+    }
+    else {
+        Some(data.as_str())
+    };
+
+    mount(
+        Some("tmpfs"),
+        target,
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        data,
+    )
+}
+
+/// Stops mount and unmount events from propagating to or from other
+/// mount namespaces.
+///
+/// This is typically applied, recursively, to the root directory after
+/// creating a new mount namespace.
+pub fn make_private<P: AsRef<Path> + ?Sized>(target: &P, recursive: bool) -> Result<()> {
+    let mut flags = MsFlags::MS_PRIVATE;
+    if recursive {
+        flags |= MsFlags::MS_REC;
+    }
+    mount(None::<&Path>, target, None, flags, None)
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ns::{self, Namespaces},
+        Error,
+    };
+    use nix::{
+        sys::wait::{self, WaitStatus},
+        unistd::{self, ForkResult},
+    };
+    use std::{env, fs, panic, process};
+
+    #[test]
+    fn test_mounts() {
+        let base = env::temp_dir().join(format!("hinix-mount-{}", unistd::getpid()));
+        let (tmp, view) = (base.join("tmp"), base.join("view"));
+        fs::create_dir_all(&tmp).unwrap();
+        fs::create_dir_all(&view).unwrap();
+
+        let child = match unsafe { unistd::fork() }.unwrap() {
+            ForkResult::Child => {
+                let res = panic::catch_unwind(|| {
+                    match ns::unshare(Namespaces::MOUNT) {
+                        Ok(()) => (),
+                        // Not privileged
+                        Err(err) if err == Error::EPERM => return,
+                        Err(err) => panic!("{}", err),
+                    }
+                    make_private("/", true).unwrap();
+
+                    mount_tmpfs(&tmp, Some(1 << 20), Some(Mode::from_bits_truncate(0o755)))
+                        .unwrap();
+                    fs::write(tmp.join("file"), b"hello").unwrap();
+
+                    bind_mount(&tmp, &view, false).unwrap();
+                    remount_readonly(&view).unwrap();
+                    assert_eq!(b"hello", &fs::read(view.join("file")).unwrap()[..]);
+
+                    let err = fs::write(view.join("other"), b"nope").unwrap_err();
+                    assert_eq!(Some(libc::EROFS), err.raw_os_error());
+
+                    umount(&view).unwrap();
+                    umount2(&tmp, MntFlags::MNT_DETACH).unwrap();
+                    assert!(!tmp.join("file").exists());
+                });
+                process::exit(if res.is_ok() { 0 } else { 1 });
+            }
+            ForkResult::Parent { child } => child,
+        };
+
+        let status = wait::waitpid(child, None).unwrap();
+        // The mounts were only in the child's namespace
+        assert!(!tmp.join("file").exists());
+        let _ = fs::remove_dir_all(&base);
+        assert_eq!(WaitStatus::Exited(child, 0), status);
+    }
+}
@@ -16,14 +16,42 @@
 //! <https://man7.org/linux/man-pages/man7/mq_overview.7.html>
 //!
 
-use crate::{Error, Result};
+use crate::{
+    clock::ClockId,
+    error::ResultExt,
+    pool::{BufferPool, PooledBuf},
+    timeout::{Deadline, Timeout},
+    Error, Result,
+};
 use nix::{
     self,
     errno::Errno,
     mqueue::{self, mq_attr_member_t, MQ_OFlag, MqdT},
     sys::stat::Mode,
 };
-use std::ffi::CString;
+use std::{
+    ffi::CString,
+    mem::MaybeUninit,
+    ptr, slice,
+    sync::Mutex,
+};
+
+#[cfg(target_os = "linux")]
+use crate::{cancel::CancelToken, timeout};
+#[cfg(target_os = "linux")]
+use nix::{
+    poll::PollFlags,
+    sys::{
+        signal::{SigEvent, SigevNotify, Signal},
+        stat,
+    },
+    unistd::{self, Gid, Uid},
+};
+#[cfg(target_os = "linux")]
+use std::{
+    mem,
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+};
 
 /// Export the MqAttr struct from the nix crate.
 pub use nix::mqueue::MqAttr;
@@ -31,6 +59,18 @@ pub use nix::mqueue::MqAttr;
 /// The default priority for the Message Queue send operation.
 pub const DEFAULT_PRIO: u32 = 0;
 
+/// Converts the name of a queue for the system.
+///
+/// This fails with `EINVAL` if the name contains a nul byte.
+fn queue_name(name: &str) -> Result<CString> {
+    CString::new(name).map_err(|_| Error::EINVAL)
+}
+
+/// Determines if the attributes have the O_NONBLOCK flag.
+fn is_nonblock_attr(attr: &MqAttr) -> bool {
+    attr.flags() & libc::O_NONBLOCK as mq_attr_member_t != 0
+}
+
 /// A Posix Message Queue
 #[derive(Debug)]
 pub struct MsgQueue {
@@ -40,6 +80,11 @@ pub struct MsgQueue {
     max_msg: usize,
     /// The size of each message
     msg_size: usize,
+    /// The name the queue was opened with, if known
+    name: Option<String>,
+    /// Whether the handle is in non-blocking mode. The lock is held
+    /// while the mode is changed, so this always matches the last change.
+    nonblocking: Mutex<bool>,
 }
 
 impl MsgQueue {
@@ -62,14 +107,16 @@ impl MsgQueue {
     /// Note that this will fail if the application does not have the proper
     /// permissions to access the queue.
     pub fn open_with_flags(name: &str, flags: MQ_OFlag) -> Result<Self> {
-        let name = CString::new(name).unwrap();
-        let mq = mqueue::mq_open(&name, flags, Mode::empty(), None)?;
+        let cname = queue_name(name)?;
+        let mq = mqueue::mq_open(&cname, flags, Mode::empty(), None).op_on("mq_open", name)?;
         // TODO: Here for local
-        let attr = mqueue::mq_getattr(&mq)?;
+        let attr = mqueue::mq_getattr(&mq).op_on("mq_getattr", name)?;
         Ok(Self {
             mq: Some(mq),
             max_msg: attr.maxmsg() as usize,
             msg_size: attr.msgsize() as usize,
+            name: Some(name.to_string()),
+            nonblocking: Mutex::new(is_nonblock_attr(&attr)),
         })
     }
 
@@ -112,22 +159,76 @@ impl MsgQueue {
         max_msg: usize,
         msg_size: usize,
     ) -> Result<Self> {
-        let name = CString::new(name).unwrap();
+        let cname = queue_name(name)?;
         let flags = flags | MQ_OFlag::O_CREAT;
-        let attr = mqueue::MqAttr::new(
+        let attr = MqAttr::new(
             0,
-            max_msg as mq_attr_member_t,
-            msg_size as mq_attr_member_t,
+            mq_attr_member_t::try_from(max_msg).map_err(|_| Error::EINVAL)?,
+            mq_attr_member_t::try_from(msg_size).map_err(|_| Error::EINVAL)?,
             0,
         );
-        let mq = mqueue::mq_open(&name, flags, mode, Some(&attr))?;
+        let mq = mqueue::mq_open(&cname, flags, mode, Some(&attr)).op_on("mq_open", name)?;
         Ok(Self {
             mq: Some(mq),
             max_msg,
             msg_size,
+            name: Some(name.to_string()),
+            nonblocking: Mutex::new(flags.contains(MQ_OFlag::O_NONBLOCK)),
         })
     }
 
+    /// Removes a message queue from the system.
+    ///
+    /// The name is removed immediately, but the queue itself is only
+    /// destroyed after all the processes that have it open close it.
+    /// This fails with `ENOENT` if there is no queue with the name.
+    pub fn unlink(name: &str) -> Result<()> {
+        let cname = queue_name(name)?;
+        mqueue::mq_unlink(&cname).op_on("mq_unlink", name)
+    }
+
+    /// Closes the queue, reporting any error.
+    ///
+    /// Dropping the queue also closes it, but ignores any error. The
+    /// queue itself stays in the system until it's unlinked.
+    pub fn close(mut self) -> Result<()> {
+        match self.mq.take() {
+            Some(mq) => mqueue::mq_close(mq).op("mq_close"),
+            None => Ok(()),
+        }
+    }
+
+    /// Closes the queue, and removes it from the system, reporting any
+    /// error.
+    ///
+    /// The queue is destroyed once every other process that has it open
+    /// closes it. This fails with `ENOENT` if the queue doesn't have a
+    /// name, like one taken from a file handle, though it's still closed.
+    pub fn close_and_unlink(mut self) -> Result<()> {
+        let name = self.name.take();
+        let res = self.close();
+        match name {
+            Some(name) => res.and(Self::unlink(&name)),
+            None => res.and(Err(Error::ENOENT)),
+        }
+    }
+
+    /// Gets the raw OS handle for the queue.
+    fn raw(&self) -> Option<libc::mqd_t> {
+        // MqdT is a transparent wrapper around the mqd_t, with no
+        // accessor for it in this version of nix.
+        self.mq
+            .as_ref()
+            .map(|mq| unsafe { *(mq as *const MqdT as *const libc::mqd_t) })
+    }
+
+    /// Gets the name the queue was opened with.
+    ///
+    /// This is `None` for a queue that was taken from a file handle.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
     /// Gets the maximum number of messages that can be held in the queue
     pub fn max_msg(&self) -> usize {
         self.max_msg
@@ -141,31 +242,60 @@ impl MsgQueue {
     /// Sets the queue into non-blocking mode.
     ///
     /// This is a convenience function to set the O_NONBLOCK flag on the
-    /// queue.
-    pub fn set_nonblock(&mut self) -> Result<MqAttr> {
-        match self.mq {
-            Some(ref mq) => mqueue::mq_set_nonblock(mq),
-            None => Err(Errno::ENOENT),
-        }
+    /// queue. It returns the previous attributes.
+    pub fn set_nonblock(&self) -> Result<MqAttr> {
+        self.set_nonblocking_mode(true)
     }
 
     /// Removes the queue from non-blocking mode.
     ///
     /// This is a convenience function to clear the O_NONBLOCK flag on the
-    /// queue.
-    pub fn remove_nonblock(&mut self) -> Result<MqAttr> {
-        match self.mq {
-            Some(ref mq) => mqueue::mq_remove_nonblock(mq),
-            None => Err(Errno::ENOENT),
-        }
+    /// queue. It returns the previous attributes.
+    pub fn remove_nonblock(&self) -> Result<MqAttr> {
+        self.set_nonblocking_mode(false)
+    }
+
+    /// Determines if the queue is in non-blocking mode.
+    ///
+    /// This is the mode last set through this handle. The mode belongs to
+    /// the open handle, so it's only changed from somewhere else if the
+    /// handle was shared with another process.
+    pub fn is_nonblocking(&self) -> bool {
+        *self.nonblocking.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn set_nonblocking_mode(&self, on: bool) -> Result<MqAttr> {
+        let mq = self.mq.as_ref().ok_or(Error::ENOENT)?;
+        let mut nonblocking = self.nonblocking.lock().unwrap_or_else(|e| e.into_inner());
+        let res = match on {
+            true => mqueue::mq_set_nonblock(mq),
+            false => mqueue::mq_remove_nonblock(mq),
+        };
+        let attr = res.op("mq_setattr")?;
+        *nonblocking = on;
+        Ok(attr)
+    }
+
+    /// Sets the attributes for the message queue, returning the previous
+    /// ones.
+    ///
+    /// Only the flags can be changed, and the only flag that can be set
+    /// is O_NONBLOCK. The other values are ignored. Note that the flags
+    /// belong to this open handle, not the queue itself.
+    pub fn set_attr(&self, attr: &MqAttr) -> Result<MqAttr> {
+        let mq = self.mq.as_ref().ok_or(Error::ENOENT)?;
+        let mut nonblocking = self.nonblocking.lock().unwrap_or_else(|e| e.into_inner());
+        let prev = mqueue::mq_setattr(mq, attr).op("mq_setattr")?;
+        *nonblocking = is_nonblock_attr(attr);
+        Ok(prev)
     }
 
     /// Gets the attributes for the message queue
     pub fn get_attr(&self) -> Result<MqAttr> {
         // TODO: Here for local
         match &self.mq {
-            Some(mq) => mqueue::mq_getattr(mq),
-            None => Err(Errno::ENOENT),
+            Some(mq) => mqueue::mq_getattr(mq).op("mq_getattr"),
+            None => Err(Error::ENOENT),
         }
     }
 
@@ -183,8 +313,50 @@ impl MsgQueue {
         M: AsRef<[u8]>,
     {
         match self.mq {
-            Some(ref mq) => mqueue::mq_send(mq, msg.as_ref(), prio),
-            None => Err(Errno::ENOENT),
+            Some(ref mq) => {
+                fault_point!(MqSend, mqueue::mq_send(mq, msg.as_ref(), prio)).op("mq_send")?
+            }
+            None => return Err(Error::ENOENT),
+        }
+        #[cfg(feature = "metrics")]
+        crate::metrics::mq_sent(self.name());
+        Ok(())
+    }
+
+    /// Sends a message to the queue with priority, waiting no longer than
+    /// the specified timeout for room in the queue.
+    ///
+    /// This fails with `ETIMEDOUT` if the queue stayed full, or `EAGAIN`
+    /// if the queue is in non-blocking mode and is full.
+    pub fn send_timeout<M, T>(&self, msg: M, prio: u32, timeout: T) -> Result<()>
+    where
+        M: AsRef<[u8]>,
+        T: Into<Timeout>,
+    {
+        let mq = self.raw().ok_or(Error::ENOENT)?;
+        let msg = msg.as_ref();
+        let deadline = Deadline::start(timeout);
+
+        loop {
+            // The timeout is an absolute time on the realtime clock
+            let ts = deadline.on_clock(ClockId::CLOCK_REALTIME)?;
+            let ts = ts.as_ref().map_or(ptr::null(), |ts| ts as *const _);
+
+            let res = fault_point!(MqSend, {
+                let ret = unsafe {
+                    libc::mq_timedsend(mq, msg.as_ptr() as *const libc::c_char, msg.len(), prio, ts)
+                };
+                Errno::result(ret)
+            });
+            match res {
+                Ok(_) => {
+                    #[cfg(feature = "metrics")]
+                    crate::metrics::mq_sent(self.name());
+                    return Ok(());
+                }
+                Err(Errno::EINTR) => continue,
+                Err(err) => return Err(err).op("mq_timedsend"),
+            }
         }
     }
 
@@ -197,9 +369,12 @@ impl MsgQueue {
     /// Receive a message
     pub fn receive_bytes(&self) -> Result<Vec<u8>> {
         let mut prio = 0;
-        let mut buf = vec![0u8; self.msg_size];
-        let n = self.receive_with_priority(&mut buf, &mut prio)?;
-        buf.truncate(n);
+        let mut buf = Vec::with_capacity(self.msg_size);
+        let n = self
+            .receive_uninit(buf.spare_capacity_mut(), &mut prio)?
+            .len();
+        // The message was received into the spare capacity
+        unsafe { buf.set_len(n) };
         Ok(buf)
     }
 
@@ -212,11 +387,174 @@ impl MsgQueue {
 
     /// Receives a message from the queue with priority
     pub fn receive_with_priority(&self, msg: &mut [u8], prio: &mut u32) -> Result<usize> {
-        match self.mq {
-            Some(ref mq) => mqueue::mq_receive(mq, msg, prio),
-            None => Err(Errno::ENOENT),
+        let n = match self.mq {
+            Some(ref mq) => {
+                fault_point!(MqReceive, mqueue::mq_receive(mq, msg, prio)).op("mq_receive")?
+            }
+            None => return Err(Error::ENOENT),
+        };
+        #[cfg(feature = "metrics")]
+        crate::metrics::mq_received(self.name());
+        Ok(n)
+    }
+
+    /// Receives a message into a buffer from the pool.
+    ///
+    /// The buffers in the pool must be able to hold the largest message
+    /// in the queue, otherwise this fails with `EMSGSIZE`.
+    pub fn receive_pooled(&self, pool: &BufferPool) -> Result<PooledBuf> {
+        let mut prio = 0;
+        let mut buf = pool.get();
+        let n = self.receive_with_priority(buf.as_full_mut(), &mut prio)?;
+        buf.set_len(n);
+        Ok(buf)
+    }
+
+    /// Receives a message from the queue with priority, into a buffer
+    /// that may be uninitialized, so it doesn't need to be cleared first.
+    ///
+    /// Returns the part of the buffer that holds the message.
+    pub fn receive_uninit<'a>(
+        &self,
+        msg: &'a mut [MaybeUninit<u8>],
+        prio: &mut u32,
+    ) -> Result<&'a mut [u8]> {
+        let mq = self.raw().ok_or(Error::ENOENT)?;
+        let ptr = msg.as_mut_ptr() as *mut libc::c_char;
+        let n = fault_point!(MqReceive, {
+            Errno::result(unsafe { libc::mq_receive(mq, ptr, msg.len(), prio) })
+        });
+        let n = n.op("mq_receive")? as usize;
+        #[cfg(feature = "metrics")]
+        crate::metrics::mq_received(self.name());
+        // The kernel initialized the first 'n' bytes
+        Ok(unsafe { slice::from_raw_parts_mut(msg.as_mut_ptr() as *mut u8, n) })
+    }
+
+    /// Receives a message from the queue with priority, waiting no longer
+    /// than the specified timeout.
+    ///
+    /// This fails with `ETIMEDOUT` if no message arrived in time, or
+    /// `EAGAIN` if the queue is in non-blocking mode and is empty.
+    pub fn receive_timeout<T: Into<Timeout>>(
+        &self,
+        msg: &mut [u8],
+        prio: &mut u32,
+        timeout: T,
+    ) -> Result<usize> {
+        let mq = self.raw().ok_or(Error::ENOENT)?;
+        let deadline = Deadline::start(timeout);
+
+        loop {
+            // The timeout is an absolute time on the realtime clock
+            let ts = deadline.on_clock(ClockId::CLOCK_REALTIME)?;
+            let ts = ts.as_ref().map_or(ptr::null(), |ts| ts as *const _);
+
+            let res = fault_point!(MqReceive, {
+                let n = unsafe {
+                    libc::mq_timedreceive(
+                        mq,
+                        msg.as_mut_ptr() as *mut libc::c_char,
+                        msg.len(),
+                        prio,
+                        ts,
+                    )
+                };
+                Errno::result(n)
+            });
+            match res {
+                Ok(n) => {
+                    #[cfg(feature = "metrics")]
+                    crate::metrics::mq_received(self.name());
+                    return Ok(n as usize);
+                }
+                Err(Errno::EINTR) => continue,
+                Err(err) => return Err(err).op("mq_timedreceive"),
+            }
         }
     }
+
+    /// Receives a message from the queue with priority, waiting no longer
+    /// than the specified timeout, unless the token is cancelled first.
+    ///
+    /// This fails with `ETIMEDOUT` if no message arrived in time, or
+    /// `ECANCELED` if the token was cancelled.
+    #[cfg(target_os = "linux")]
+    pub fn receive_cancellable<T: Into<Timeout>>(
+        &self,
+        msg: &mut [u8],
+        prio: &mut u32,
+        timeout: T,
+        cancel: &CancelToken,
+    ) -> Result<usize> {
+        self.raw().ok_or(Error::ENOENT)?;
+        let timeout = timeout
+            .into()
+            .deadline()
+            .map_or(Timeout::None, Timeout::Deadline);
+
+        loop {
+            if !timeout::poll_fd_cancel(self.as_fd(), PollFlags::POLLIN, timeout, Some(cancel))? {
+                return Err(Error::ETIMEDOUT);
+            }
+            // Another reader may have taken the message first
+            match self.receive_timeout(msg, prio, Timeout::ZERO) {
+                Err(err) if err == Error::ETIMEDOUT || err == Error::EAGAIN => continue,
+                res => return res,
+            }
+        }
+    }
+
+    /// Changes the permissions of the queue.
+    ///
+    /// On Linux, queues live in the mqueue filesystem, and have the same
+    /// permission bits as a file.
+    #[cfg(target_os = "linux")]
+    pub fn chmod(&self, mode: Mode) -> Result<()> {
+        let fd = self.raw().ok_or(Error::ENOENT)?;
+        stat::fchmod(fd, mode).op("fchmod")
+    }
+
+    /// Changes the owner and/or group of the queue.
+    ///
+    /// Either value can be `None` to leave it unchanged.
+    #[cfg(target_os = "linux")]
+    pub fn chown(&self, owner: Option<Uid>, group: Option<Gid>) -> Result<()> {
+        let fd = self.raw().ok_or(Error::ENOENT)?;
+        unistd::fchown(fd, owner, group).op("fchown")
+    }
+
+    /// Registers the calling process to receive a signal when a message
+    /// arrives on the queue while it is empty.
+    ///
+    /// Only one process can be registered at a time. Others fail with
+    /// `EBUSY`. The registration is removed after the signal is sent,
+    /// so it must be renewed to get the next one. It's also removed if
+    /// the queue is closed, or a thread is already waiting in a receive
+    /// call when the message arrives.
+    #[cfg(target_os = "linux")]
+    pub fn notify(&self, sig: Signal) -> Result<()> {
+        let evt = SigEvent::new(SigevNotify::SigevSignal {
+            signal: sig,
+            si_value: 0,
+        });
+        self.mq_notify(&evt.sigevent())
+    }
+
+    /// Removes the calling process's notification registration, if any.
+    #[cfg(target_os = "linux")]
+    pub fn remove_notify(&self) -> Result<()> {
+        self.mq_notify(ptr::null())
+    }
+
+    // The C library doesn't export mq_notify() to the libc crate, but on
+    // Linux, signal notification is a direct system call.
+    #[cfg(target_os = "linux")]
+    fn mq_notify(&self, evt: *const libc::sigevent) -> Result<()> {
+        let mq = self.raw().ok_or(Error::ENOENT)?;
+        let ret = unsafe { libc::syscall(libc::SYS_mq_notify, mq, evt) };
+        Errno::result(ret).map(drop).op("mq_notify")
+    }
 }
 
 impl Drop for MsgQueue {
@@ -227,22 +565,77 @@ impl Drop for MsgQueue {
     }
 }
 
-// TODO: Restore this on platforms that support it (upstream)?
-/*
+// On Linux, the queue descriptor is a file descriptor, and can be used
+// with select, poll, or epoll to wait for messages.
+#[cfg(target_os = "linux")]
 impl AsRawFd for MsgQueue {
     /// Gets the raw file handle for the message queue
     fn as_raw_fd(&self) -> RawFd {
-        self.mq as RawFd
+        self.raw().unwrap_or(-1)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AsFd for MsgQueue {
+    /// Gets the file handle for the message queue
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        // The queue is only closed when it's dropped, so the handle is
+        // valid for the lifetime of the borrow.
+        unsafe { BorrowedFd::borrow_raw(self.as_raw_fd()) }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl IntoRawFd for MsgQueue {
+    /// Gives up ownership of the file handle for the message queue.
+    fn into_raw_fd(mut self) -> RawFd {
+        let fd = self.as_raw_fd();
+        // Keep the queue from being closed when this is dropped
+        self.mq = None;
+        fd
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<MsgQueue> for OwnedFd {
+    fn from(mq: MsgQueue) -> Self {
+        unsafe { OwnedFd::from_raw_fd(mq.into_raw_fd()) }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl TryFrom<OwnedFd> for MsgQueue {
+    type Error = Error;
+
+    /// Takes ownership of an open message queue, such as one passed from
+    /// another process, reading the sizes from the queue.
+    ///
+    /// This fails with `EBADF` if the handle isn't a message queue.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        let mut attr = MaybeUninit::<libc::mq_attr>::uninit();
+        let ret = unsafe { libc::mq_getattr(fd.as_raw_fd(), attr.as_mut_ptr()) };
+        Errno::result(ret).op("mq_getattr")?;
+        let attr = unsafe { attr.assume_init() };
+
+        // MqdT is a transparent wrapper around the mqd_t, with no
+        // constructor for it in this version of nix.
+        let mq = unsafe { mem::transmute::<libc::mqd_t, MqdT>(fd.into_raw_fd()) };
+        Ok(Self {
+            mq: Some(mq),
+            max_msg: attr.mq_maxmsg as usize,
+            msg_size: attr.mq_msgsize as usize,
+            name: None,
+            nonblocking: Mutex::new(attr.mq_flags & libc::O_NONBLOCK as libc::c_long != 0),
+        })
     }
 }
-*/
 
 /////////////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    //use std::os::unix::io::AsRawFd;
+    use std::time::Duration;
 
     // Be careful that multiple tests are not reading/writing to the same
     // queue, since tests may be running in parallel.
@@ -268,6 +661,17 @@ mod tests {
 
         assert_eq!(N, mq.max_msg());
         assert_eq!(SZ, mq.msg_size());
+        assert_eq!(Some(NAME), mq.name());
+        mq.close().unwrap();
+    }
+
+    #[test]
+    fn test_close_and_unlink() {
+        const NAME: &str = "/rust_close_unlink_unit_test";
+
+        let mq = MsgQueue::create(NAME, N, SZ).unwrap();
+        mq.close_and_unlink().unwrap();
+        assert_eq!(Errno::ENOENT, MsgQueue::open(NAME).unwrap_err());
     }
 
     #[test]
@@ -275,8 +679,8 @@ mod tests {
         let mut wr_arr = [0u8; SZ];
         let mut rd_arr = [0u8; SZ];
 
-        for i in 0..SZ {
-            wr_arr[i] = i as u8;
+        for (i, b) in wr_arr.iter_mut().enumerate() {
+            *b = i as u8;
         }
 
         let mq = MsgQueue::create(NAME, N, SZ).unwrap();
@@ -292,7 +696,7 @@ mod tests {
         let attr = mq.get_attr().unwrap();
         assert_eq!(attr.curmsgs(), 0);
 
-        mq.send(&wr_arr).unwrap();
+        mq.send(wr_arr).unwrap();
 
         let n = mq.receive(&mut rd_arr).unwrap();
         assert_eq!(n, SZ);
@@ -318,4 +722,276 @@ mod tests {
         let msg = mq.receive_string().unwrap();
         assert_eq!(MSG.to_string(), msg);
     }
+
+    #[test]
+    fn test_unlink() {
+        const NAME: &str = "/rust_unlink_unit_test";
+
+        let mq = MsgQueue::create(NAME, N, SZ).unwrap();
+        MsgQueue::unlink(NAME).unwrap();
+
+        // Still usable while open, but no longer reachable by name
+        mq.send("hi").unwrap();
+        assert_eq!(Errno::ENOENT, MsgQueue::open(NAME).unwrap_err());
+        assert_eq!(Errno::ENOENT, MsgQueue::unlink(NAME).unwrap_err());
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(Errno::EINVAL, MsgQueue::open("/bad\0name").unwrap_err());
+        assert_eq!(
+            Errno::EINVAL,
+            MsgQueue::create("/rust_invalid_unit_test", usize::MAX, SZ).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_receive_uninit() {
+        const NAME: &str = "/rust_uninit_unit_test";
+
+        let _ = MsgQueue::unlink(NAME);
+        let mq = MsgQueue::create(NAME, N, SZ).unwrap();
+
+        let mut buf = [MaybeUninit::uninit(); SZ];
+        let mut prio = 0;
+        mq.send_with_priority("hello", 2).unwrap();
+        assert_eq!(b"hello", mq.receive_uninit(&mut buf, &mut prio).unwrap());
+        assert_eq!(2, prio);
+
+        mq.send("world").unwrap();
+        assert_eq!(b"world", mq.receive_bytes().unwrap().as_slice());
+
+        let pool = BufferPool::new(SZ, 1);
+        mq.send("pooled").unwrap();
+        assert_eq!(b"pooled", &*mq.receive_pooled(&pool).unwrap());
+        assert_eq!(1, pool.available());
+
+        // The buffer must be able to hold the largest message
+        let mut buf = [MaybeUninit::uninit(); 4];
+        assert_eq!(
+            Errno::EMSGSIZE,
+            mq.receive_uninit(&mut buf, &mut prio).unwrap_err()
+        );
+
+        MsgQueue::unlink(NAME).unwrap();
+    }
+
+    #[test]
+    fn test_receive_timeout() {
+        const NAME: &str = "/rust_timeout_unit_test";
+
+        let _ = MsgQueue::unlink(NAME);
+        let mq = MsgQueue::create(NAME, N, SZ).unwrap();
+
+        let mut buf = [0u8; SZ];
+        let mut prio = 0;
+        let timeout = Duration::from_millis(20);
+        assert_eq!(
+            Errno::ETIMEDOUT,
+            mq.receive_timeout(&mut buf, &mut prio, timeout)
+                .unwrap_err()
+        );
+
+        mq.send_with_priority("hi", 3).unwrap();
+        let n = mq.receive_timeout(&mut buf, &mut prio, timeout).unwrap();
+        assert_eq!(b"hi", &buf[..n]);
+        assert_eq!(3, prio);
+
+        // Fill the queue, so the next send times out
+        for _ in 0..N {
+            mq.send_timeout("x", 1, timeout).unwrap();
+        }
+        assert_eq!(
+            Errno::ETIMEDOUT,
+            mq.send_timeout("x", 1, timeout).unwrap_err()
+        );
+
+        MsgQueue::unlink(NAME).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_receive_cancellable() {
+        use std::thread;
+        const NAME: &str = "/rust_cancel_unit_test";
+
+        let _ = MsgQueue::unlink(NAME);
+        let mq = MsgQueue::create(NAME, N, SZ).unwrap();
+        let cancel = CancelToken::new().unwrap();
+
+        let mut buf = [0u8; SZ];
+        let mut prio = 0;
+        let timeout = Duration::from_millis(20);
+        assert_eq!(
+            Errno::ETIMEDOUT,
+            mq.receive_cancellable(&mut buf, &mut prio, timeout, &cancel)
+                .unwrap_err()
+        );
+
+        mq.send_with_priority("hi", 3).unwrap();
+        let n = mq
+            .receive_cancellable(&mut buf, &mut prio, timeout, &cancel)
+            .unwrap();
+        assert_eq!(b"hi", &buf[..n]);
+        assert_eq!(3, prio);
+
+        let th = thread::spawn({
+            let cancel = cancel.clone();
+            move || {
+                let mut buf = [0u8; SZ];
+                let mut prio = 0;
+                mq.receive_cancellable(&mut buf, &mut prio, Timeout::None, &cancel)
+            }
+        });
+        thread::sleep(timeout);
+        cancel.cancel();
+        assert_eq!(Error::ECANCELED, th.join().unwrap().unwrap_err());
+
+        MsgQueue::unlink(NAME).unwrap();
+    }
+
+    #[test]
+    fn test_set_attr() {
+        const NAME: &str = "/rust_attr_unit_test";
+
+        let _ = MsgQueue::unlink(NAME);
+        let mq = MsgQueue::create(NAME, N, SZ).unwrap();
+        assert!(!mq.is_nonblocking());
+
+        let nonblock = MqAttr::new(MQ_OFlag::O_NONBLOCK.bits() as _, 0, 0, 0);
+        mq.set_attr(&nonblock).unwrap();
+        assert!(mq.is_nonblocking());
+        assert_eq!(Errno::EAGAIN, mq.receive_bytes().unwrap_err());
+
+        // The sizes can't be changed
+        let attr = mq.get_attr().unwrap();
+        assert_eq!(N, attr.maxmsg() as usize);
+        assert_eq!(
+            MQ_OFlag::O_NONBLOCK.bits() as mq_attr_member_t,
+            attr.flags()
+        );
+
+        MsgQueue::unlink(NAME).unwrap();
+    }
+
+    #[test]
+    fn test_nonblock_shared() {
+        use std::{sync::Arc, thread};
+        const NAME: &str = "/rust_nonblock_unit_test";
+
+        let _ = MsgQueue::unlink(NAME);
+        let mq = Arc::new(MsgQueue::create(NAME, N, SZ).unwrap());
+        MsgQueue::unlink(NAME).unwrap();
+
+        // The mode can be changed from any thread sharing the queue
+        thread::spawn({
+            let mq = mq.clone();
+            move || mq.set_nonblock().unwrap()
+        })
+        .join()
+        .unwrap();
+
+        assert!(mq.is_nonblocking());
+        assert_eq!(Errno::EAGAIN, mq.receive_bytes().unwrap_err());
+
+        mq.remove_nonblock().unwrap();
+        assert!(!mq.is_nonblocking());
+
+        // Racing changes still leave the cached mode matching the queue
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let mq = mq.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        match i % 2 {
+                            0 => mq.set_nonblock().unwrap(),
+                            _ => mq.remove_nonblock().unwrap(),
+                        };
+                    }
+                })
+            })
+            .collect();
+        for thr in threads {
+            thr.join().unwrap();
+        }
+        let attr = mq.get_attr().unwrap();
+        assert_eq!(is_nonblock_attr(&attr), mq.is_nonblocking());
+
+        // The mode is read from the queue when it's opened
+        let mq = MsgQueue::create_with_flags(
+            "/rust_nonblock_unit_test_2",
+            MQ_OFlag::O_RDWR | MQ_OFlag::O_NONBLOCK,
+            Mode::from_bits_truncate(0o600),
+            N,
+            SZ,
+        )
+        .unwrap();
+        assert!(mq.is_nonblocking());
+        mq.close_and_unlink().unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_chmod_notify() {
+        const NAME: &str = "/rust_chmod_unit_test";
+
+        let _ = MsgQueue::unlink(NAME);
+        let mq = MsgQueue::create(NAME, N, SZ).unwrap();
+
+        mq.chmod(Mode::from_bits_truncate(0o640)).unwrap();
+        let st = stat::fstat(mq.as_raw_fd()).unwrap();
+        assert_eq!(0o640, st.st_mode & 0o777);
+
+        // Leaving the owner unchanged is always allowed
+        mq.chown(None, None).unwrap();
+
+        // Only one registration at a time
+        mq.notify(Signal::SIGUSR2).unwrap();
+        assert_eq!(Errno::EBUSY, mq.notify(Signal::SIGUSR2).unwrap_err());
+        mq.remove_notify().unwrap();
+        mq.notify(Signal::SIGUSR2).unwrap();
+        mq.remove_notify().unwrap();
+
+        MsgQueue::unlink(NAME).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_poll() {
+        use nix::poll::{self, PollFd, PollFlags};
+
+        const NAME: &str = "/rust_poll_unit_test";
+
+        let _ = MsgQueue::unlink(NAME);
+        let mq = MsgQueue::create(NAME, N, SZ).unwrap();
+
+        let mut fds = [PollFd::new(mq.as_raw_fd(), PollFlags::POLLIN)];
+        assert_eq!(Ok(0), poll::poll(&mut fds, 0));
+
+        mq.send("hi").unwrap();
+        assert_eq!(Ok(1), poll::poll(&mut fds, 0));
+
+        MsgQueue::unlink(NAME).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_owned_fd() {
+        const NAME: &str = "/rust_owned_fd_unit_test";
+
+        let _ = MsgQueue::unlink(NAME);
+        let mq = MsgQueue::create(NAME, N, SZ).unwrap();
+        MsgQueue::unlink(NAME).unwrap();
+        mq.send("hi").unwrap();
+
+        // The sizes are read back from the queue
+        let mq = MsgQueue::try_from(OwnedFd::from(mq)).unwrap();
+        assert_eq!(N, mq.max_msg());
+        assert_eq!(SZ, mq.msg_size());
+        assert_eq!(None, mq.name());
+        assert_eq!("hi", mq.receive_string().unwrap());
+
+        let fd = OwnedFd::from(std::fs::File::open("/dev/null").unwrap());
+        assert_eq!(Error::EBADF, MsgQueue::try_from(fd).unwrap_err());
+    }
 }
@@ -0,0 +1,308 @@
+// hinix/src/netif.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Network interface configuration.
+//!
+//! These are wrappers around the classic socket ioctl() calls to query
+//! and configure network interfaces, such as bringing them up or down,
+//! and reading their MAC addresses.
+//!
+//! Changing the configuration of an interface requires the
+//! `CAP_NET_ADMIN` capability.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man7/netdevice.7.html>
+//!
+
+use crate::{Error, Result};
+use nix::{
+    errno::Errno,
+    net::if_,
+    sys::socket::{self, AddressFamily, SockFlag, SockType},
+};
+use std::{
+    ffi::CStr,
+    fmt,
+    os::{
+        raw::{c_char, c_int, c_short},
+        unix::io::{AsRawFd, FromRawFd, OwnedFd},
+    },
+};
+
+/// The flags of a network interface, like `IFF_UP` or `IFF_LOOPBACK`.
+pub use nix::net::if_::InterfaceFlags;
+
+/// The maximum length of an interface name, including the NUL.
+const IFNAMSIZ: usize = 16;
+
+/// The request structure for the interface ioctl() calls.
+/// This is defined here, since it's not available in all libc versions.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct IfReq {
+    name: [c_char; IFNAMSIZ],
+    data: IfReqData,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+union IfReqData {
+    flags: c_short,
+    mtu: c_int,
+    hwaddr: libc::sockaddr,
+    _pad: [u8; 24],
+}
+
+/// A hardware (MAC) address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            b[0], b[1], b[2], b[3], b[4], b[5]
+        )
+    }
+}
+
+/// A network interface on the system.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Interface {
+    /// The name of the interface, like "eth0"
+    name: String,
+    /// The kernel index of the interface
+    index: u32,
+}
+
+impl Interface {
+    /// Gets the interface with the specified name.
+    ///
+    /// Fails with `ENODEV` if there is no such interface.
+    pub fn by_name(name: &str) -> Result<Self> {
+        if name.is_empty() || name.len() >= IFNAMSIZ {
+            return Err(Error::EINVAL);
+        }
+        let index = if_::if_nametoindex(name)?;
+        Ok(Self {
+            name: name.to_string(),
+            index,
+        })
+    }
+
+    /// Gets the interface with the specified index.
+    ///
+    /// Fails with `ENXIO` if there is no such interface.
+    pub fn by_index(index: u32) -> Result<Self> {
+        let mut buf = [0 as c_char; IFNAMSIZ];
+        let ret = unsafe { libc::if_indextoname(index, buf.as_mut_ptr()) };
+        if ret.is_null() {
+            return Err(Error::last());
+        }
+        let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+        Ok(Self {
+            name: name.to_string_lossy().into_owned(),
+            index,
+        })
+    }
+
+    /// Gets a list of all the network interfaces on the system.
+    pub fn list() -> Result<Vec<Self>> {
+        let ifs = if_::if_nameindex()?;
+        Ok(ifs
+            .iter()
+            .map(|iface| Self {
+                name: iface.name().to_string_lossy().into_owned(),
+                index: iface.index(),
+            })
+            .collect())
+    }
+
+    /// Gets the name of the interface.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Gets the kernel index of the interface.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Gets the flags of the interface.
+    pub fn flags(&self) -> Result<InterfaceFlags> {
+        let req = self.ioctl(libc::SIOCGIFFLAGS as _, self.request())?;
+        let flags = unsafe { req.data.flags } as u16;
+        Ok(InterfaceFlags::from_bits_truncate(flags as c_int))
+    }
+
+    /// Sets the flags of the interface.
+    pub fn set_flags(&self, flags: InterfaceFlags) -> Result<()> {
+        let mut req = self.request();
+        req.data.flags = flags.bits() as c_short;
+        self.ioctl(libc::SIOCSIFFLAGS as _, req).map(drop)
+    }
+
+    /// Determines if the interface is administratively up.
+    pub fn is_up(&self) -> Result<bool> {
+        Ok(self.flags()?.contains(InterfaceFlags::IFF_UP))
+    }
+
+    /// Determines if the interface has a carrier (link) and is ready to
+    /// pass traffic.
+    pub fn is_running(&self) -> Result<bool> {
+        Ok(self.flags()?.contains(InterfaceFlags::IFF_RUNNING))
+    }
+
+    /// Brings the interface up.
+    pub fn up(&self) -> Result<()> {
+        self.set_flags(self.flags()? | InterfaceFlags::IFF_UP)
+    }
+
+    /// Takes the interface down.
+    pub fn down(&self) -> Result<()> {
+        self.set_flags(self.flags()? - InterfaceFlags::IFF_UP)
+    }
+
+    /// Gets the MTU of the interface.
+    pub fn mtu(&self) -> Result<u32> {
+        let req = self.ioctl(libc::SIOCGIFMTU as _, self.request())?;
+        Ok(unsafe { req.data.mtu } as u32)
+    }
+
+    /// Sets the MTU of the interface.
+    pub fn set_mtu(&self, mtu: u32) -> Result<()> {
+        let mut req = self.request();
+        req.data.mtu = c_int::try_from(mtu).map_err(|_| Error::EINVAL)?;
+        self.ioctl(libc::SIOCSIFMTU as _, req).map(drop)
+    }
+
+    /// Gets the hardware (MAC) address of the interface.
+    ///
+    /// Interfaces without one, like the loopback, report all zeros.
+    pub fn hw_addr(&self) -> Result<MacAddr> {
+        let req = self.ioctl(libc::SIOCGIFHWADDR as _, self.request())?;
+        let data = unsafe { req.data.hwaddr.sa_data };
+        let mut addr = MacAddr::default();
+        for (b, &d) in addr.0.iter_mut().zip(data.iter()) {
+            *b = d as u8;
+        }
+        Ok(addr)
+    }
+
+    /// Creates a request for this interface.
+    fn request(&self) -> IfReq {
+        let mut req = IfReq {
+            name: [0; IFNAMSIZ],
+            data: IfReqData { _pad: [0; 24] },
+        };
+        // The name length was checked on creation
+        for (d, &s) in req.name.iter_mut().zip(self.name.as_bytes()) {
+            *d = s as c_char;
+        }
+        req
+    }
+
+    /// Performs an ioctl() request on the interface, returning the updated
+    /// request.
+    fn ioctl(&self, op: libc::Ioctl, mut req: IfReq) -> Result<IfReq> {
+        let sock = socket::socket(
+            AddressFamily::Inet,
+            SockType::Datagram,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        let sock = unsafe { OwnedFd::from_raw_fd(sock) };
+        let ret = unsafe { libc::ioctl(sock.as_raw_fd(), op, &mut req as *mut IfReq) };
+        Errno::result(ret)?;
+        Ok(req)
+    }
+}
+
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(all(test, feature = "ns"))]
+mod tests {
+    use super::*;
+    use crate::ns::{self, Namespaces};
+    use nix::{
+        sys::wait::{self, WaitStatus},
+        unistd::{self, ForkResult},
+    };
+    use std::{panic, process};
+
+    #[test]
+    fn test_loopback() {
+        let lo = Interface::by_name("lo").unwrap();
+        assert!(lo.index() > 0);
+        assert_eq!(lo, Interface::by_index(lo.index()).unwrap());
+        assert!(Interface::list().unwrap().contains(&lo));
+
+        let flags = lo.flags().unwrap();
+        assert!(flags.contains(InterfaceFlags::IFF_LOOPBACK));
+
+        assert!(lo.mtu().unwrap() > 0);
+        assert_eq!(MacAddr::default(), lo.hw_addr().unwrap());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Error::ENODEV, Interface::by_name("nosuchif0").unwrap_err());
+        assert_eq!(
+            Error::EINVAL,
+            Interface::by_name("a-very-long-interface-name").unwrap_err()
+        );
+        assert_eq!(
+            "01:23:45:ab:cd:ef",
+            MacAddr([0x01, 0x23, 0x45, 0xab, 0xcd, 0xef]).to_string()
+        );
+    }
+
+    #[test]
+    fn test_configure() {
+        // Use the loopback in a new network namespace, if we can.
+        match unsafe { unistd::fork() }.unwrap() {
+            ForkResult::Child => {
+                let res = panic::catch_unwind(|| {
+                    if ns::unshare(Namespaces::NET).is_err() {
+                        return;
+                    }
+                    let lo = Interface::by_name("lo").unwrap();
+                    assert!(!lo.is_up().unwrap());
+
+                    lo.up().unwrap();
+                    assert!(lo.is_up().unwrap());
+
+                    lo.set_mtu(1500).unwrap();
+                    assert_eq!(1500, lo.mtu().unwrap());
+
+                    lo.down().unwrap();
+                    assert!(!lo.is_up().unwrap());
+                });
+                process::exit(if res.is_ok() { 0 } else { 1 });
+            }
+            ForkResult::Parent { child } => {
+                assert_eq!(
+                    WaitStatus::Exited(child, 0),
+                    wait::waitpid(child, None).unwrap()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,281 @@
+// hinix/src/ns.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Linux namespaces.
+//!
+//! A namespace wraps a global system resource so that the processes
+//! within it appear to have their own isolated instance of it. For
+//! example, processes in a separate network namespace have their own
+//! interfaces, routing tables, and firewall rules.
+//!
+//! A process can move itself into new namespaces with [`unshare()`], or
+//! join the existing namespaces of another process with [`setns()`],
+//! using a handle opened with [`open()`].
+//!
+//! Most operations require the `CAP_SYS_ADMIN` capability, with the
+//! exception of creating a user namespace, which can be done by an
+//! unprivileged process, and which then grants it full capabilities
+//! within that namespace.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man7/namespaces.7.html>
+//!
+
+use crate::{error::ResultExt, Error, Result};
+use bitflags::bitflags;
+use nix::{
+    fcntl::{self, OFlag},
+    sched::{self, CloneFlags},
+    sys::stat::{self, Mode},
+    unistd::{Gid, Pid, Uid},
+};
+use std::{
+    fs,
+    os::{
+        raw::c_int,
+        unix::io::{AsFd, AsRawFd, FromRawFd, OwnedFd},
+    },
+};
+
+bitflags! {
+    /// A set of namespace types.
+    pub struct Namespaces: c_int {
+        /// Mount points
+        const MOUNT = libc::CLONE_NEWNS;
+        /// Host and domain names
+        const UTS = libc::CLONE_NEWUTS;
+        /// System V IPC and POSIX message queues
+        const IPC = libc::CLONE_NEWIPC;
+        /// Network devices, stacks, ports, etc
+        const NET = libc::CLONE_NEWNET;
+        /// Process IDs
+        const PID = libc::CLONE_NEWPID;
+        /// User and group IDs
+        const USER = libc::CLONE_NEWUSER;
+        /// Cgroup root directory
+        const CGROUP = libc::CLONE_NEWCGROUP;
+    }
+}
+
+/// A single type of namespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Namespace {
+    /// Mount points
+    Mount,
+    /// Host and domain names
+    Uts,
+    /// System V IPC and POSIX message queues
+    Ipc,
+    /// Network devices, stacks, ports, etc
+    Net,
+    /// Process IDs
+    Pid,
+    /// User and group IDs
+    User,
+    /// Cgroup root directory
+    Cgroup,
+}
+
+impl Namespace {
+    /// All the types of namespaces.
+    pub const ALL: [Namespace; 7] = [
+        Namespace::Mount,
+        Namespace::Uts,
+        Namespace::Ipc,
+        Namespace::Net,
+        Namespace::Pid,
+        Namespace::User,
+        Namespace::Cgroup,
+    ];
+
+    /// Gets the flag for the namespace type.
+    pub fn flag(&self) -> Namespaces {
+        use Namespace::*;
+        match self {
+            Mount => Namespaces::MOUNT,
+            Uts => Namespaces::UTS,
+            Ipc => Namespaces::IPC,
+            Net => Namespaces::NET,
+            Pid => Namespaces::PID,
+            User => Namespaces::USER,
+            Cgroup => Namespaces::CGROUP,
+        }
+    }
+
+    /// Gets the name of the namespace's file in the /proc/<pid>/ns/
+    /// directory.
+    pub fn proc_name(&self) -> &'static str {
+        use Namespace::*;
+        match self {
+            Mount => "mnt",
+            Uts => "uts",
+            Ipc => "ipc",
+            Net => "net",
+            Pid => "pid",
+            User => "user",
+            Cgroup => "cgroup",
+        }
+    }
+}
+
+impl From<Namespace> for Namespaces {
+    fn from(ns: Namespace) -> Self {
+        ns.flag()
+    }
+}
+
+impl From<Namespaces> for CloneFlags {
+    fn from(ns: Namespaces) -> Self {
+        CloneFlags::from_bits_truncate(ns.bits())
+    }
+}
+
+/// Moves the calling thread into new namespaces of the specified types.
+///
+/// For a new PID namespace, the calling process isn't moved; its next
+/// child becomes the first process (PID 1) in the new namespace.
+pub fn unshare(namespaces: Namespaces) -> Result<()> {
+    sched::unshare(namespaces.into()).op("unshare")
+}
+
+/// Maps a user and group from the parent namespace to root in a new
+/// user namespace, after the calling process has unshared one.
+///
+/// This is usually the caller's own IDs, which lets an unprivileged
+/// user act as root inside the namespace. Supplementary groups can't be
+/// changed in the namespace afterwards.
+///
+/// The mapping can only be set once per namespace.
+pub fn map_root(uid: Uid, gid: Gid) -> Result<()> {
+    // An unprivileged process must deny setgroups() before it can
+    // write the group map. Older kernels don't have the file.
+    match write_proc("/proc/self/setgroups", "deny") {
+        Ok(()) => (),
+        Err(err) if err == Error::ENOENT => (),
+        Err(err) => return Err(err),
+    }
+    write_proc("/proc/self/uid_map", &format!("0 {} 1", uid))?;
+    write_proc("/proc/self/gid_map", &format!("0 {} 1", gid))
+}
+
+fn write_proc(path: &str, s: &str) -> Result<()> {
+    fs::write(path, s).op_on("write", path)
+}
+
+/// Moves the calling thread into an existing namespace, given a handle
+/// to it.
+///
+/// The handle is typically opened with [`open()`]. Fails with `EINVAL`
+/// if it doesn't refer to a namespace of the specified type.
+pub fn setns<F: AsFd>(fd: &F, kind: Namespace) -> Result<()> {
+    sched::setns(fd.as_fd().as_raw_fd(), kind.flag().into()).op("setns")
+}
+
+/// Opens a handle to a namespace of a process.
+pub fn open(pid: Pid, kind: Namespace) -> Result<OwnedFd> {
+    let path = format!("/proc/{}/ns/{}", pid, kind.proc_name());
+    open_path(&path)
+}
+
+/// Opens a handle to a namespace of the calling thread.
+pub fn open_self(kind: Namespace) -> Result<OwnedFd> {
+    open_path(&format!("/proc/thread-self/ns/{}", kind.proc_name()))
+}
+
+fn open_path(path: &str) -> Result<OwnedFd> {
+    let fd = fcntl::open(path, OFlag::O_RDONLY | OFlag::O_CLOEXEC, Mode::empty())?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Gets a unique identifier for a namespace of a process.
+///
+/// This is the inode number of the namespace file, which can be compared
+/// to determine if two processes are in the same namespace.
+pub fn id(pid: Pid, kind: Namespace) -> Result<u64> {
+    let path = format!("/proc/{}/ns/{}", pid, kind.proc_name());
+    Ok(stat::stat(path.as_str())?.st_ino as u64)
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::in_child, Error};
+    use nix::unistd;
+    use std::panic;
+
+    #[test]
+    fn test_open() {
+        let pid = unistd::getpid();
+        for kind in Namespace::ALL {
+            // Some kernels may not support all the types
+            if let Ok(fd) = open(pid, kind) {
+                let st = stat::fstat(fd.as_raw_fd()).unwrap();
+                assert_eq!(id(pid, kind).unwrap(), st.st_ino as u64);
+            }
+        }
+        assert!(open_self(Namespace::Net).is_ok());
+        assert_eq!(
+            Namespaces::NET | Namespaces::UTS,
+            Namespace::Net.flag() | Namespace::Uts.into()
+        );
+    }
+
+    #[test]
+    fn test_unshare_uts() {
+        let parent = unistd::gethostname().unwrap();
+        let orig_ns = id(unistd::getpid(), Namespace::Uts).unwrap();
+
+        in_child(move || {
+            match unshare(Namespaces::UTS) {
+                Ok(()) => (),
+                // Not privileged, or in a restricted container
+                Err(err) if err == Error::EPERM || err == Error::EINVAL => return,
+                Err(err) => panic!("{}", err),
+            }
+            assert_ne!(orig_ns, id(unistd::getpid(), Namespace::Uts).unwrap());
+
+            unistd::sethostname("hinix-test").unwrap();
+            assert_eq!("hinix-test", unistd::gethostname().unwrap());
+
+            // Join the original one again
+            let fd = open(unistd::getppid(), Namespace::Uts).unwrap();
+            setns(&fd, Namespace::Uts).unwrap();
+            assert_eq!(orig_ns, id(unistd::getpid(), Namespace::Uts).unwrap());
+
+            assert_eq!(Error::EINVAL, setns(&fd, Namespace::Net).unwrap_err());
+        });
+
+        assert_eq!(parent, unistd::gethostname().unwrap());
+    }
+
+    #[test]
+    fn test_map_root() {
+        let (uid, gid) = (unistd::getuid(), unistd::getgid());
+
+        in_child(move || {
+            match unshare(Namespaces::USER) {
+                Ok(()) => (),
+                // User namespaces disabled, or in a restricted container
+                Err(err) if [Error::EPERM, Error::EINVAL, Error::ENOSPC].contains(&err) => return,
+                Err(err) => panic!("{}", err),
+            }
+            map_root(uid, gid).unwrap();
+            assert!(unistd::geteuid().is_root());
+            assert_eq!(0, unistd::getegid().as_raw());
+
+            // Can only be mapped once
+            assert!(map_root(uid, gid).is_err());
+        });
+    }
+}
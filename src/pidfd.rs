@@ -0,0 +1,274 @@
+// hinix/src/pidfd.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Process file descriptors.
+//!
+//! A pidfd is a file handle that refers to a specific process. Unlike a
+//! numeric PID, it can't be recycled to refer to a different process
+//! after the original one exits, so it can be used to signal or wait on
+//! a process without races.
+//!
+//! The handle becomes readable when the process exits, so it can be used
+//! in a poll loop along with other handles.
+//!
+//! This requires Linux 5.3 or later.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man2/pidfd_open.2.html>
+//!
+
+use crate::{
+    error::ResultExt,
+    fd,
+    timeout::{self, Timeout},
+    Error, Result,
+};
+use nix::{
+    errno::Errno,
+    poll::PollFlags,
+    sys::{signal::Signal, wait::WaitStatus},
+    unistd::Pid,
+};
+use std::{
+    fs, mem,
+    os::{
+        raw::c_int,
+        unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    },
+    ptr,
+};
+
+/// A file handle that refers to a process.
+#[derive(Debug)]
+pub struct PidFd(OwnedFd);
+
+impl PidFd {
+    /// Opens a handle to an existing process.
+    ///
+    /// Note that there's an inherent race in opening a handle from a PID,
+    /// since the process could exit and the PID be reused before the call.
+    /// This is safe for a child of the calling process that hasn't been
+    /// waited on, since its PID can't be reused until then.
+    pub fn open(pid: Pid) -> Result<Self> {
+        let ret = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0 as c_int) };
+        let fd = Errno::result(ret).op("pidfd_open")? as RawFd;
+        Ok(Self(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    /// Creates a handle from a raw file descriptor that refers to a process.
+    ///
+    /// # Safety
+    ///
+    /// The descriptor must be an open pidfd, which is then owned by the
+    /// new object.
+    pub unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self(OwnedFd::from_raw_fd(fd))
+    }
+
+    /// Gets the PID of the process, as seen from the PID namespace of the
+    /// calling process.
+    ///
+    /// Fails with `ESRCH` if the process has exited and been reaped.
+    pub fn pid(&self) -> Result<Pid> {
+        let info = fs::read_to_string(format!("/proc/self/fdinfo/{}", self.as_raw_fd()))?;
+
+        let pid = info
+            .lines()
+            .find_map(|line| line.strip_prefix("Pid:"))
+            .and_then(|s| s.trim().parse::<libc::pid_t>().ok())
+            .ok_or(Error::EINVAL)?;
+
+        match pid {
+            // The kernel reports -1 once the process is gone
+            n if n > 0 => Ok(Pid::from_raw(n)),
+            _ => Err(Error::ESRCH),
+        }
+    }
+
+    /// Sends a signal to the process.
+    pub fn send_signal(&self, sig: Signal) -> Result<()> {
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                self.as_raw_fd(),
+                sig as c_int,
+                ptr::null::<libc::siginfo_t>(),
+                0 as c_int,
+            )
+        };
+        Errno::result(ret).map(drop).op("pidfd_send_signal")
+    }
+
+    /// Waits for the process to exit, with an optional timeout.
+    ///
+    /// This works for any process, not just children of the caller, but
+    /// doesn't reap it or get its exit status.
+    ///
+    /// Returns `true` if the process exited, or `false` on a timeout.
+    pub fn poll_exit<T: Into<Timeout>>(&self, timeout: T) -> Result<bool> {
+        timeout::poll_fd(self.as_fd(), PollFlags::POLLIN, timeout)
+    }
+
+    /// Waits for the process to exit and reaps it, returning its status.
+    ///
+    /// The process must be a child of the caller, otherwise this fails
+    /// with `ECHILD`.
+    pub fn wait(&self) -> Result<WaitStatus> {
+        self.waitid(0)
+    }
+
+    /// Checks whether the process has exited, and if so, reaps it and
+    /// returns its status.
+    ///
+    /// The process must be a child of the caller, otherwise this fails
+    /// with `ECHILD`.
+    pub fn try_wait(&self) -> Result<Option<WaitStatus>> {
+        match self.waitid(libc::WNOHANG)? {
+            WaitStatus::StillAlive => Ok(None),
+            status => Ok(Some(status)),
+        }
+    }
+
+    fn waitid(&self, flags: c_int) -> Result<WaitStatus> {
+        // The handle identifies a single process, so wait for it
+        // regardless of the signal it sends on exit (__WALL).
+        let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+        loop {
+            let ret = unsafe {
+                libc::waitid(
+                    libc::P_PIDFD,
+                    self.as_raw_fd() as libc::id_t,
+                    &mut info,
+                    libc::WEXITED | libc::__WALL | flags,
+                )
+            };
+            match Errno::result(ret) {
+                Ok(_) => break,
+                Err(Errno::EINTR) => continue,
+                Err(err) => return Err(Error::from(err).with_op("waitid")),
+            }
+        }
+
+        let (pid, status) = unsafe { (info.si_pid(), info.si_status()) };
+        if pid == 0 {
+            return Ok(WaitStatus::StillAlive);
+        }
+        let pid = Pid::from_raw(pid);
+
+        match info.si_code {
+            libc::CLD_EXITED => Ok(WaitStatus::Exited(pid, status)),
+            libc::CLD_KILLED | libc::CLD_DUMPED => Ok(WaitStatus::Signaled(
+                pid,
+                Signal::try_from(status)?,
+                info.si_code == libc::CLD_DUMPED,
+            )),
+            _ => Err(Error::EINVAL),
+        }
+    }
+}
+
+impl AsFd for PidFd {
+    /// Gets the file handle for the process.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for PidFd {
+    /// Gets the raw file handle for the process.
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl From<PidFd> for OwnedFd {
+    fn from(pidfd: PidFd) -> Self {
+        pidfd.0
+    }
+}
+
+impl IntoRawFd for PidFd {
+    /// Gives up ownership of the file handle for the process.
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl TryFrom<OwnedFd> for PidFd {
+    type Error = Error;
+
+    /// Takes ownership of an existing process handle, such as one passed from
+    /// another process.
+    ///
+    /// This fails with `EINVAL` if the handle isn't a pidfd.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        fd::check_anon_inode(fd.as_fd(), "pidfd")?;
+        Ok(Self(fd))
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::unistd::{self, ForkResult};
+    use std::{process, thread, time::Duration};
+
+    // Forks a child that runs the function, then exits with the code.
+    fn spawn_child<F: FnOnce()>(f: F, code: i32) -> Pid {
+        match unsafe { unistd::fork() }.unwrap() {
+            ForkResult::Child => {
+                f();
+                process::exit(code);
+            }
+            ForkResult::Parent { child } => child,
+        }
+    }
+
+    #[test]
+    fn test_wait() {
+        let child = spawn_child(|| thread::sleep(Duration::from_millis(50)), 3);
+        let pidfd = PidFd::open(child).unwrap();
+        assert_eq!(child, pidfd.pid().unwrap());
+
+        assert_eq!(None, pidfd.try_wait().unwrap());
+        assert!(!pidfd.poll_exit(Some(Duration::ZERO)).unwrap());
+
+        assert!(pidfd.poll_exit(None).unwrap());
+        assert_eq!(WaitStatus::Exited(child, 3), pidfd.wait().unwrap());
+        assert_eq!(Error::ESRCH, pidfd.pid().unwrap_err());
+    }
+
+    #[test]
+    fn test_owned_fd() {
+        let pid = unistd::getpid();
+        let pidfd = PidFd::open(pid).unwrap();
+        let pidfd = PidFd::try_from(OwnedFd::from(pidfd)).unwrap();
+        assert_eq!(pid, pidfd.pid().unwrap());
+
+        let fd = OwnedFd::from(fs::File::open("/dev/null").unwrap());
+        assert_eq!(Error::EINVAL, PidFd::try_from(fd).unwrap_err());
+    }
+
+    #[test]
+    fn test_signal() {
+        let child = spawn_child(|| thread::sleep(Duration::from_secs(10)), 0);
+        let pidfd = PidFd::open(child).unwrap();
+
+        pidfd.send_signal(Signal::SIGTERM).unwrap();
+        assert_eq!(
+            WaitStatus::Signaled(child, Signal::SIGTERM, false),
+            pidfd.wait().unwrap()
+        );
+    }
+}
@@ -0,0 +1,251 @@
+// hinix/src/pidfile.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Process ID (pid) files.
+//!
+//! A pidfile is a small text file, typically under /run or /var/run, that
+//! holds the process ID of a running daemon. It is used by scripts and
+//! service managers to find the daemon, and by the daemon itself to
+//! make sure that only one instance is running at a time.
+//!
+//! The [`PidFile`] holds an exclusive advisory lock (flock) on the file
+//! for as long as it lives. This makes it easy to tell a live pidfile
+//! from a stale one that was left behind by a process that crashed: a
+//! stale file is not locked by anyone.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man2/flock.2.html>
+//!
+
+use crate::{error::ResultExt, Error, Result};
+use nix::{
+    errno::Errno,
+    fcntl::{self, FlockArg, OFlag},
+    sys::{
+        signal,
+        stat::{self, Mode},
+        uio,
+    },
+    unistd::{self, Pid},
+};
+use std::{
+    fs,
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    path::{Path, PathBuf},
+};
+
+/// A locked pidfile for the current process.
+///
+/// The file is created (if necessary), locked, and written with the
+/// process ID of the caller. It is removed when the object is dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    /// The open, locked, file
+    fd: OwnedFd,
+    /// The path to the file
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Creates and locks a pidfile, writing the current process ID to it.
+    ///
+    /// If the file already exists, but is stale (i.e. not locked by a
+    /// running process), it is taken over and overwritten.
+    ///
+    /// This fails with `EWOULDBLOCK` if another process currently holds
+    /// the pidfile.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let fd = Self::open_locked(path)?;
+
+        let pidfile = Self {
+            fd,
+            path: path.to_path_buf(),
+        };
+        pidfile.write_pid(unistd::getpid())?;
+        Ok(pidfile)
+    }
+
+    /// Opens and locks the file at the path, creating it if necessary.
+    ///
+    /// Another process can remove or replace the file between the open
+    /// and the lock, like when it cleans up its own pidfile, so this
+    /// retries until the locked file is still the one at the path.
+    fn open_locked(path: &Path) -> Result<OwnedFd> {
+        let flags = OFlag::O_RDWR | OFlag::O_CREAT | OFlag::O_CLOEXEC;
+        let mode = Mode::from_bits_truncate(0o644);
+
+        loop {
+            let fd = fcntl::open(path, flags, mode).op_on("open", path.display())?;
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+            fcntl::flock(fd.as_raw_fd(), FlockArg::LockExclusiveNonblock)
+                .op_on("flock", path.display())?;
+
+            let locked = stat::fstat(fd.as_raw_fd()).op_on("fstat", path.display())?;
+            match stat::stat(path) {
+                Ok(st) if st.st_dev == locked.st_dev && st.st_ino == locked.st_ino => {
+                    return Ok(fd)
+                }
+                Ok(_) | Err(Errno::ENOENT) => continue,
+                Err(err) => return Err(Error::new(err, "stat", path.display())),
+            }
+        }
+    }
+
+    /// Gets the path to the pidfile.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes the process ID into the file, replacing any previous
+    /// contents.
+    fn write_pid(&self, pid: Pid) -> Result<()> {
+        let fd = self.fd.as_raw_fd();
+        let buf = format!("{}\n", pid);
+
+        unistd::ftruncate(fd, 0).op_on("ftruncate", self.path.display())?;
+        let n = uio::pwrite(fd, buf.as_bytes(), 0).op_on("pwrite", self.path.display())?;
+        if n != buf.len() {
+            return Err(Error::EIO);
+        }
+        unistd::fsync(fd).op("fsync")
+    }
+
+    /// Reads the process ID from an existing pidfile.
+    ///
+    /// This only reads the contents of the file. It does not check if the
+    /// file is locked, or if the process is still running.
+    pub fn read_pid<P: AsRef<Path>>(path: P) -> Result<Pid> {
+        let path = path.as_ref();
+        let s = fs::read_to_string(path).op_on("read", path.display())?;
+        let pid = s.trim().parse::<i32>().map_err(|_| Error::EINVAL)?;
+        Ok(Pid::from_raw(pid))
+    }
+
+    /// Gets the process ID of the live process holding the pidfile, if
+    /// any.
+    ///
+    /// This returns `None` if the file does not exist or is stale.
+    pub fn owner<P: AsRef<Path>>(path: P) -> Result<Option<Pid>> {
+        let path = path.as_ref();
+        match Self::is_stale(path) {
+            Ok(true) => Ok(None),
+            Ok(false) => Self::read_pid(path).map(Some),
+            Err(err) if err == Error::ENOENT => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Determines if an existing pidfile is stale.
+    ///
+    /// A file is considered stale if no process holds a lock on it, or
+    /// if the process ID that it contains is no longer running.
+    pub fn is_stale<P: AsRef<Path>>(path: P) -> Result<bool> {
+        let path = path.as_ref();
+
+        let fd = fcntl::open(path, OFlag::O_RDONLY | OFlag::O_CLOEXEC, Mode::empty())
+            .op_on("open", path.display())?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        match fcntl::flock(fd.as_raw_fd(), FlockArg::LockSharedNonblock) {
+            Ok(()) => return Ok(true),
+            Err(Errno::EWOULDBLOCK) => (),
+            Err(err) => return Err(Error::new(err, "flock", path.display())),
+        }
+
+        let pid = match Self::read_pid(path) {
+            Ok(pid) => pid,
+            Err(err) if err == Error::EINVAL => return Ok(true),
+            Err(err) => return Err(err),
+        };
+
+        match signal::kill(pid, None) {
+            Ok(()) | Err(Errno::EPERM) => Ok(false),
+            Err(Errno::ESRCH) => Ok(true),
+            Err(err) => Err(Error::from(err).with_op("kill")),
+        }
+    }
+}
+
+impl Drop for PidFile {
+    /// Removes the pidfile.
+    ///
+    /// The file is unlinked while it is still locked, then the lock is
+    /// released when the handle is closed.
+    fn drop(&mut self) {
+        let _ = unistd::unlink(&self.path);
+    }
+}
+
+impl AsFd for PidFile {
+    /// Gets the file handle for the pidfile.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for PidFile {
+    /// Gets the raw file handle for the pidfile.
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    // Each test gets its own file, since tests may run in parallel.
+    fn test_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("hinix-{}-{}.pid", name, unistd::getpid()))
+    }
+
+    #[test]
+    fn test_create() {
+        let path = test_path("create");
+
+        let pidfile = PidFile::create(&path).unwrap();
+        assert_eq!(path, pidfile.path());
+        assert_eq!(unistd::getpid(), PidFile::read_pid(&path).unwrap());
+
+        assert!(!PidFile::is_stale(&path).unwrap());
+        assert_eq!(Some(unistd::getpid()), PidFile::owner(&path).unwrap());
+
+        // The lock is per open file, so a second one should fail
+        assert_eq!(Errno::EWOULDBLOCK, PidFile::create(&path).unwrap_err());
+
+        // The file should get removed when dropped
+        drop(pidfile);
+        assert!(!path.exists());
+        assert_eq!(None, PidFile::owner(&path).unwrap());
+    }
+
+    #[test]
+    fn test_stale() {
+        let path = test_path("stale");
+
+        // A file left behind by a dead process, and never locked.
+        fs::write(&path, "2147483647\n").unwrap();
+        assert!(PidFile::is_stale(&path).unwrap());
+        assert_eq!(None, PidFile::owner(&path).unwrap());
+
+        // We should be able to take it over
+        let pidfile = PidFile::create(&path).unwrap();
+        assert_eq!(unistd::getpid(), PidFile::read_pid(&path).unwrap());
+        drop(pidfile);
+    }
+}
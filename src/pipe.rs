@@ -19,66 +19,314 @@
 //! write end of the pipe is closed, any in-progress or subsequent read
 //! will return immediately with an EOF (successful read of zero bytes).
 //!
+//! On Linux, a pipe can also be created in "packet" mode, where each
+//! write is read back as a separate message, much like a datagram.
+//!
 //! See:
 //! <https://man7.org/linux/man-pages/man2/pipe.2.html>
 //!
 
-use crate::Result;
-use nix::unistd;
+use crate::{
+    cancel::CancelToken,
+    fd,
+    pool::{BufferPool, PooledBuf},
+    timeout::{self, Timeout},
+    Error, Result,
+};
+use nix::{fcntl::OFlag, poll::PollFlags, sys::stat::SFlag, unistd};
 use std::{
     io::{self, Read, Write},
-    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    mem::MaybeUninit,
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
 };
 
 /// Creates a pipe.
+///
+/// Both ends are close-on-exec. To pass one to a child process, use
+/// `FdExt::dup_to()` or `process::CommandExt::fd_map()`.
 pub fn pipe() -> Result<(WritePipe, ReadPipe)> {
-    let (rd_fd, wr_fd) = unistd::pipe()?;
-    let rd_pipe = unsafe { ReadPipe::from_raw_fd(rd_fd) };
-    let wr_pipe = unsafe { WritePipe::from_raw_fd(wr_fd) };
+    #[cfg(any(
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "illumos",
+        target_os = "linux",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "solaris"
+    ))]
+    let (rd_fd, wr_fd) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+
+    // Without pipe2(), there's a window where another thread could fork
+    // and exec before the flags are set.
+    #[cfg(not(any(
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "illumos",
+        target_os = "linux",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "solaris"
+    )))]
+    let (rd_fd, wr_fd) = {
+        use nix::fcntl::{self, FcntlArg, FdFlag};
+
+        let (rd_fd, wr_fd) = unistd::pipe()?;
+        for fd in [rd_fd, wr_fd] {
+            fcntl::fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        }
+        (rd_fd, wr_fd)
+    };
+
+    let rd_pipe = unsafe { ReadPipe::from_raw_fd(rd_fd, false) };
+    let wr_pipe = unsafe { WritePipe::from_raw_fd(wr_fd, false) };
+    Ok((wr_pipe, rd_pipe))
+}
+
+/// The largest message that can be written to a packet pipe.
+///
+/// Larger writes are split into multiple packets.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub const PIPE_BUF: usize = libc::PIPE_BUF;
+
+/// Creates a pipe in packet mode (O_DIRECT).
+///
+/// Each write of up to [`PIPE_BUF`] bytes is a separate packet, and each
+/// read returns at most one packet. If the read buffer is smaller than
+/// the packet, the rest of it is discarded.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub fn packet_pipe() -> Result<(WritePipe, ReadPipe)> {
+    use nix::fcntl::OFlag;
+
+    let (rd_fd, wr_fd) = unistd::pipe2(OFlag::O_DIRECT | OFlag::O_CLOEXEC)?;
+    let rd_pipe = unsafe { ReadPipe::from_raw_fd(rd_fd, true) };
+    let wr_pipe = unsafe { WritePipe::from_raw_fd(wr_fd, true) };
     Ok((wr_pipe, rd_pipe))
 }
 
+/// Determines if the write end of a pipe is in packet mode.
+///
+/// The kernel only records the mode on the write end, and it can't be
+/// changed after the pipe is created, so this only needs to be checked
+/// once.
+fn is_packet_fd(fd: BorrowedFd) -> Result<bool> {
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    return fd::has_status_flag(fd, OFlag::O_DIRECT);
+
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    {
+        let _ = fd;
+        Ok(false)
+    }
+}
+
 /// Read-end of a pipe.
-pub struct ReadPipe(OwnedFd);
+#[derive(Debug)]
+pub struct ReadPipe {
+    fd: OwnedFd,
+    /// Whether the pipe is in packet mode (O_DIRECT)
+    packet: bool,
+}
 
 impl ReadPipe {
-    unsafe fn from_raw_fd(fd: RawFd) -> Self {
-        Self(OwnedFd::from_raw_fd(fd))
+    unsafe fn from_raw_fd(fd: RawFd, packet: bool) -> Self {
+        Self {
+            fd: OwnedFd::from_raw_fd(fd),
+            packet,
+        }
+    }
+
+    /// Determines if the pipe is in packet mode, from `packet_pipe()`.
+    ///
+    /// Only a packet pipe keeps the boundaries between messages, so it's
+    /// the only kind that can be used as a message transport.
+    pub fn is_packet(&self) -> bool {
+        self.packet
+    }
+
+    /// Marks the read end as being from a packet pipe.
+    ///
+    /// The kernel only records the mode on the write end of the pipe, so
+    /// it can't be checked for a read end taken from a handle, like one
+    /// that was inherited. The application has to know it.
+    pub fn into_packet(self) -> Self {
+        Self {
+            packet: true,
+            ..self
+        }
+    }
+
+    /// Closes the read end of the pipe, reporting any error.
+    ///
+    /// Dropping the pipe also closes it, but ignores any error.
+    pub fn close(self) -> Result<()> {
+        fd::close(self)
+    }
+
+    /// Waits up to the timeout for data, then reads it into the buffer.
+    ///
+    /// Returns `None` if the timeout expired first, or `Some(0)` if the
+    /// write end of the pipe was closed.
+    pub fn read_timeout<T: Into<Timeout>>(
+        &mut self,
+        buf: &mut [u8],
+        timeout: T,
+    ) -> Result<Option<usize>> {
+        match timeout::poll_fd(self.as_fd(), PollFlags::POLLIN, timeout)? {
+            true => Ok(Some(self.read(buf)?)),
+            false => Ok(None),
+        }
+    }
+
+    /// Waits up to the timeout for data, then reads it into the buffer,
+    /// unless the token is cancelled first.
+    ///
+    /// Returns `None` if the timeout expired first, or `Some(0)` if the
+    /// write end of the pipe was closed. Fails with `ECANCELED` if the
+    /// token was cancelled.
+    pub fn read_cancellable<T: Into<Timeout>>(
+        &mut self,
+        buf: &mut [u8],
+        timeout: T,
+        cancel: &CancelToken,
+    ) -> Result<Option<usize>> {
+        match timeout::poll_fd_cancel(self.as_fd(), PollFlags::POLLIN, timeout, Some(cancel))? {
+            true => Ok(Some(self.read(buf)?)),
+            false => Ok(None),
+        }
+    }
+
+    /// Reads data into a buffer that may be uninitialized, so it doesn't
+    /// need to be cleared first.
+    ///
+    /// Returns the part of the buffer that was filled, which is empty if
+    /// the write end of the pipe was closed.
+    pub fn read_uninit<'a>(&mut self, buf: &'a mut [MaybeUninit<u8>]) -> Result<&'a mut [u8]> {
+        let data = fault_point!(PipeRead, fd::read_uninit(self.as_fd(), buf))?;
+        #[cfg(feature = "metrics")]
+        crate::metrics::pipe_read(data.len());
+        Ok(data)
+    }
+
+    /// Reads data into a buffer from the pool.
+    ///
+    /// The buffer is empty if the write end of the pipe was closed.
+    pub fn read_pooled(&mut self, pool: &BufferPool) -> Result<PooledBuf> {
+        let mut buf = pool.get();
+        let n = self.read(buf.as_full_mut())?;
+        buf.set_len(n);
+        Ok(buf)
     }
 }
 
 impl Read for ReadPipe {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        Ok(unistd::read(self.as_raw_fd(), buf)?)
+        let n = fault_point!(PipeRead, unistd::read(self.as_raw_fd(), buf))?;
+        #[cfg(feature = "metrics")]
+        crate::metrics::pipe_read(n);
+        Ok(n)
     }
 }
 
 impl AsFd for ReadPipe {
     /// Gets the raw file handle for the read pipe.
     fn as_fd(&self) -> BorrowedFd<'_> {
-        self.0.as_fd()
+        self.fd.as_fd()
     }
 }
 
 impl AsRawFd for ReadPipe {
     /// Gets the raw file handle for the read pipe
     fn as_raw_fd(&self) -> RawFd {
-        self.0.as_raw_fd()
+        self.fd.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for ReadPipe {
+    /// Gives up ownership of the file handle for the read pipe.
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+impl From<ReadPipe> for OwnedFd {
+    fn from(pipe: ReadPipe) -> Self {
+        pipe.fd
+    }
+}
+
+impl TryFrom<OwnedFd> for ReadPipe {
+    type Error = Error;
+
+    /// Takes ownership of the read end of a pipe, or a FIFO that was
+    /// opened for reading.
+    ///
+    /// The pipe isn't treated as being in packet mode. Use
+    /// `into_packet()` if it is.
+    ///
+    /// This fails with `EINVAL` if the handle isn't a pipe, or can't be
+    /// read.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        fd::check_file_type(fd.as_fd(), SFlag::S_IFIFO)?;
+        fd::check_access(fd.as_fd(), OFlag::O_RDONLY)?;
+        // See into_packet()
+        Ok(Self { fd, packet: false })
     }
 }
 
 /// Write-end of a pipe.
-pub struct WritePipe(OwnedFd);
+#[derive(Debug)]
+pub struct WritePipe {
+    fd: OwnedFd,
+    /// Whether the pipe is in packet mode (O_DIRECT)
+    packet: bool,
+}
 
 impl WritePipe {
-    unsafe fn from_raw_fd(fd: RawFd) -> Self {
-        Self(OwnedFd::from_raw_fd(fd))
+    unsafe fn from_raw_fd(fd: RawFd, packet: bool) -> Self {
+        Self {
+            fd: OwnedFd::from_raw_fd(fd),
+            packet,
+        }
+    }
+
+    /// Determines if the pipe is in packet mode, from `packet_pipe()`.
+    ///
+    /// Only a packet pipe keeps the boundaries between messages, so it's
+    /// the only kind that can be used as a message transport.
+    pub fn is_packet(&self) -> bool {
+        self.packet
+    }
+
+    /// Marks the read end as being from a packet pipe.
+    ///
+    /// The kernel only records the mode on the write end of the pipe, so
+    /// it can't be checked for a read end taken from a handle, like one
+    /// that was inherited. The application has to know it.
+    pub fn into_packet(self) -> Self {
+        Self {
+            packet: true,
+            ..self
+        }
+    }
+
+    /// Closes the write end of the pipe, reporting any error.
+    ///
+    /// Dropping the pipe also closes it, but ignores any error. Either
+    /// way, the reader sees the end of the stream once every write handle
+    /// is closed.
+    pub fn close(self) -> Result<()> {
+        fd::close(self)
     }
 }
 
 impl Write for WritePipe {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        Ok(unistd::write(self.as_raw_fd(), buf)?)
+        let n = fault_point!(PipeWrite, unistd::write(self.as_raw_fd(), buf))?;
+        #[cfg(feature = "metrics")]
+        crate::metrics::pipe_written(n);
+        Ok(n)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -90,14 +338,43 @@ impl Write for WritePipe {
 impl AsFd for WritePipe {
     /// Gets the raw file handle for the read pipe.
     fn as_fd(&self) -> BorrowedFd<'_> {
-        self.0.as_fd()
+        self.fd.as_fd()
     }
 }
 
 impl AsRawFd for WritePipe {
     /// Gets the raw file handle for the read pipe
     fn as_raw_fd(&self) -> RawFd {
-        self.0.as_raw_fd()
+        self.fd.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for WritePipe {
+    /// Gives up ownership of the file handle for the write pipe.
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+impl From<WritePipe> for OwnedFd {
+    fn from(pipe: WritePipe) -> Self {
+        pipe.fd
+    }
+}
+
+impl TryFrom<OwnedFd> for WritePipe {
+    type Error = Error;
+
+    /// Takes ownership of the write end of a pipe, or a FIFO that was
+    /// opened for writing.
+    ///
+    /// This fails with `EINVAL` if the handle isn't a pipe, or can't be
+    /// written.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        fd::check_file_type(fd.as_fd(), SFlag::S_IFIFO)?;
+        fd::check_access(fd.as_fd(), OFlag::O_WRONLY)?;
+        let packet = is_packet_fd(fd.as_fd())?;
+        Ok(Self { fd, packet })
     }
 }
 
@@ -114,7 +391,7 @@ mod tests {
         let (mut wr_pipe, mut rd_pipe) = pipe().unwrap();
 
         thread::spawn(move || {
-            wr_pipe.write(&[0x55u8]).unwrap();
+            wr_pipe.write_all(&[0x55u8]).unwrap();
         });
 
         let mut buf = [0u8; 1];
@@ -122,6 +399,28 @@ mod tests {
         assert_eq!(0x55, buf[0]);
     }
 
+    #[test]
+    fn test_cloexec() {
+        use crate::fd::FdExt;
+
+        let (wr_pipe, rd_pipe) = pipe().unwrap();
+        assert!(wr_pipe.is_cloexec().unwrap());
+        assert!(rd_pipe.is_cloexec().unwrap());
+    }
+
+    #[test]
+    fn test_read_uninit() {
+        let (mut wr_pipe, mut rd_pipe) = pipe().unwrap();
+        wr_pipe.write_all(b"abc").unwrap();
+
+        let mut buf = [MaybeUninit::<u8>::uninit(); 8];
+        assert_eq!(b"abc", rd_pipe.read_uninit(&mut buf).unwrap());
+
+        wr_pipe.close().unwrap();
+        assert!(rd_pipe.read_uninit(&mut buf).unwrap().is_empty());
+        rd_pipe.close().unwrap();
+    }
+
     #[test]
     fn test_eof_on_drop() {
         let (wr_pipe, mut rd_pipe) = pipe().unwrap();
@@ -134,4 +433,83 @@ mod tests {
         // Should get an EOF from a read when write-side drops
         assert_eq!(0, rd_pipe.read(&mut buf).unwrap());
     }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_packet_pipe() {
+        let (mut wr_pipe, mut rd_pipe) = packet_pipe().unwrap();
+        wr_pipe.write_all(b"abc").unwrap();
+        wr_pipe.write_all(b"defg").unwrap();
+
+        // Each read gets a single packet
+        let mut buf = [0u8; 16];
+        let n = rd_pipe.read(&mut buf).unwrap();
+        assert_eq!(b"abc", &buf[..n]);
+        let n = rd_pipe.read(&mut buf).unwrap();
+        assert_eq!(b"defg", &buf[..n]);
+
+        assert!(wr_pipe.is_packet());
+        assert!(rd_pipe.is_packet());
+        let wr_pipe = WritePipe::try_from(OwnedFd::from(wr_pipe)).unwrap();
+        assert!(wr_pipe.is_packet());
+        let rd_pipe = ReadPipe::try_from(OwnedFd::from(rd_pipe)).unwrap();
+        assert!(rd_pipe.into_packet().is_packet());
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        let (mut wr_pipe, mut rd_pipe) = pipe().unwrap();
+        let mut buf = [0u8; 4];
+        let timeout = std::time::Duration::from_millis(10);
+        assert_eq!(None, rd_pipe.read_timeout(&mut buf, timeout).unwrap());
+
+        wr_pipe.write_all(b"abc").unwrap();
+        assert_eq!(Some(3), rd_pipe.read_timeout(&mut buf, timeout).unwrap());
+
+        drop(wr_pipe);
+        let n = rd_pipe.read_timeout(&mut buf, Timeout::None).unwrap();
+        assert_eq!(Some(0), n);
+    }
+
+    #[test]
+    fn test_read_cancellable() {
+        let (mut wr_pipe, mut rd_pipe) = pipe().unwrap();
+        let cancel = CancelToken::new().unwrap();
+        let mut buf = [0u8; 4];
+        let timeout = std::time::Duration::from_millis(10);
+        let n = rd_pipe
+            .read_cancellable(&mut buf, timeout, &cancel)
+            .unwrap();
+        assert_eq!(None, n);
+
+        wr_pipe.write_all(b"abc").unwrap();
+        let n = rd_pipe
+            .read_cancellable(&mut buf, timeout, &cancel)
+            .unwrap();
+        assert_eq!(Some(3), n);
+
+        // Cancelling wins, even with data waiting
+        wr_pipe.write_all(b"abc").unwrap();
+        cancel.cancel();
+        let err = rd_pipe
+            .read_cancellable(&mut buf, Timeout::None, &cancel)
+            .unwrap_err();
+        assert_eq!(Error::ECANCELED, err);
+    }
+
+    #[test]
+    fn test_owned_fd() {
+        let (wr_pipe, rd_pipe) = pipe().unwrap();
+        let mut wr_pipe = WritePipe::try_from(OwnedFd::from(wr_pipe)).unwrap();
+        let mut rd_pipe = ReadPipe::try_from(OwnedFd::from(rd_pipe)).unwrap();
+        assert!(!wr_pipe.is_packet() && !rd_pipe.is_packet());
+
+        wr_pipe.write_all(b"x").unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(1, rd_pipe.read(&mut buf).unwrap());
+
+        // Each end can only be used in one direction
+        let fd = OwnedFd::from(rd_pipe);
+        assert_eq!(Error::EINVAL, WritePipe::try_from(fd).unwrap_err());
+    }
 }
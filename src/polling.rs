@@ -0,0 +1,312 @@
+// hinix/src/polling.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Registration of the handle types with a `polling` crate `Poller`.
+//!
+//! The polling crate is the portable event notification layer that's
+//! used under smol and async-io. Any type in this crate that implements
+//! `AsFd` can be a polling source, but adding one to a poller is unsafe,
+//! since the handle has to be removed from the poller before it's closed.
+//!
+//! A [`Registered`] source takes care of that. It owns the handle, and
+//! removes it from the poller when it's dropped, or when the handle is
+//! taken back with `into_inner()`:
+//!
+//! ```
+//! use hinix::{
+//!     pipe,
+//!     polling::{Event, Events, Poller, PollerExt, Registered},
+//!     Timeout,
+//! };
+//! use std::io::Write;
+//!
+//! let poller = Poller::new()?;
+//! let (mut wr, rd) = pipe::pipe()?;
+//! let rd = Registered::new(&poller, rd, Event::readable(7))?;
+//!
+//! wr.write_all(b"hello")?;
+//!
+//! let mut events = Events::new();
+//! poller.wait_timeout(&mut events, Timeout::None)?;
+//! assert_eq!(7, events.iter().next().unwrap().key);
+//!
+//! // Events are oneshot by default, so the source needs to be re-armed
+//! rd.modify(Event::readable(7))?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! This requires the `polling` feature.
+//!
+
+use crate::{cancel::CancelToken, timeout::Timeout, Error, Result};
+use std::{
+    borrow::Borrow,
+    fmt, io,
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd},
+    time::Instant,
+};
+
+pub use polling::{Event, Events, PollMode, Poller};
+
+/// Extension methods for a `Poller`.
+pub trait PollerExt {
+    /// Waits up to the timeout for events, and adds them to the list.
+    ///
+    /// Returns the number of events that were added. This can return
+    /// zero before the timeout if the poller was woken with `notify()`.
+    /// A wait that's interrupted by a signal is restarted for the time
+    /// remaining.
+    fn wait_timeout<T: Into<Timeout>>(&self, events: &mut Events, timeout: T) -> Result<usize>;
+
+    /// Waits up to the timeout for events, like `wait_timeout()`, unless
+    /// the token is cancelled first.
+    ///
+    /// Fails with `ECANCELED` if the token was cancelled. The token is
+    /// added to the poller for the wait, with the key [`CANCEL_KEY`], so
+    /// that key can't be used for other sources, and only one cancellable
+    /// wait can use the poller at a time.
+    fn wait_cancellable<T: Into<Timeout>>(
+        &self,
+        events: &mut Events,
+        timeout: T,
+        cancel: &CancelToken,
+    ) -> Result<usize>;
+}
+
+/// The key of the cancel token in a cancellable wait.
+///
+/// The poller reserves `usize::MAX` for itself, so this is the one below.
+pub const CANCEL_KEY: usize = usize::MAX - 1;
+
+impl PollerExt for Poller {
+    fn wait_timeout<T: Into<Timeout>>(&self, events: &mut Events, timeout: T) -> Result<usize> {
+        let deadline = timeout.into().deadline();
+        #[cfg(feature = "metrics")]
+        let start = Instant::now();
+        let res = loop {
+            let res = match deadline {
+                Some(deadline) => self.wait_deadline(events, deadline),
+                None => self.wait(events, None),
+            };
+            match res {
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                    if deadline.is_some_and(|d| Instant::now() >= d) {
+                        break Ok(0);
+                    }
+                }
+                res => break res.map_err(|err| Error::from(err).with_op("poll")),
+            }
+        };
+        #[cfg(feature = "metrics")]
+        crate::metrics::poll_wait(start.elapsed());
+        res
+    }
+
+    fn wait_cancellable<T: Into<Timeout>>(
+        &self,
+        events: &mut Events,
+        timeout: T,
+        cancel: &CancelToken,
+    ) -> Result<usize> {
+        cancel.check()?;
+        // The token is removed again before this returns
+        unsafe { self.add(&cancel.as_fd(), Event::readable(CANCEL_KEY)) }
+            .map_err(|err| Error::from(err).with_op("poll_add"))?;
+        let res = self.wait_timeout(events, timeout);
+        let _ = self.delete(cancel.as_fd());
+
+        // If the token's event is in the list, it was cancelled
+        cancel.check()?;
+        res
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// A handle that's registered with a poller for as long as it lives.
+///
+/// The poller can be borrowed, or held in a shared pointer, like an
+/// `Arc<Poller>`.
+pub struct Registered<T: AsFd, P: Borrow<Poller>> {
+    /// The poller the handle is registered with
+    poller: P,
+    /// The handle. This is only `None` after it's been taken back.
+    source: Option<T>,
+}
+
+impl<T: AsFd, P: Borrow<Poller>> Registered<T, P> {
+    /// Adds the handle to the poller, interested in the event, in the
+    /// default oneshot mode.
+    pub fn new(poller: P, source: T, interest: Event) -> Result<Self> {
+        Self::with_mode(poller, source, interest, PollMode::Oneshot)
+    }
+
+    /// Adds the handle to the poller, interested in the event, with the
+    /// polling mode.
+    ///
+    /// This fails with `ENOTSUP` if the poller doesn't support the mode.
+    pub fn with_mode(poller: P, source: T, interest: Event, mode: PollMode) -> Result<Self> {
+        // The registration is owned with the handle, so that the handle
+        // is always removed from the poller before it's closed.
+        unsafe {
+            poller
+                .borrow()
+                .add_with_mode(&source.as_fd(), interest, mode)
+        }
+        .map_err(|err| Error::from(err).with_op("poll_add"))?;
+
+        Ok(Self {
+            poller,
+            source: Some(source),
+        })
+    }
+
+    /// Changes the event of interest, in the default oneshot mode.
+    ///
+    /// In oneshot mode, this needs to be called after each event to
+    /// re-arm the source.
+    pub fn modify(&self, interest: Event) -> Result<()> {
+        self.modify_with_mode(interest, PollMode::Oneshot)
+    }
+
+    /// Changes the event of interest and the polling mode.
+    pub fn modify_with_mode(&self, interest: Event, mode: PollMode) -> Result<()> {
+        self.poller()
+            .modify_with_mode(self.as_fd(), interest, mode)
+            .map_err(|err| Error::from(err).with_op("poll_modify"))
+    }
+
+    /// Gets the poller that the handle is registered with.
+    pub fn poller(&self) -> &Poller {
+        self.poller.borrow()
+    }
+
+    /// Gets a reference to the handle.
+    pub fn get_ref(&self) -> &T {
+        self.source.as_ref().unwrap()
+    }
+
+    /// Gets a mutable reference to the handle.
+    ///
+    /// # Safety
+    ///
+    /// The handle must not be closed or replaced through the reference,
+    /// since it would still be registered with the poller.
+    pub unsafe fn get_mut(&mut self) -> &mut T {
+        self.source.as_mut().unwrap()
+    }
+
+    /// Removes the handle from the poller, and gives it back.
+    pub fn into_inner(mut self) -> Result<T> {
+        let source = self.source.take().unwrap();
+        self.poller()
+            .delete(source.as_fd())
+            .map_err(|err| Error::from(err).with_op("poll_delete"))?;
+        Ok(source)
+    }
+}
+
+impl<T: AsFd, P: Borrow<Poller>> Drop for Registered<T, P> {
+    fn drop(&mut self) {
+        if let Some(source) = self.source.take() {
+            let _ = self.poller.borrow().delete(source.as_fd());
+        }
+    }
+}
+
+impl<T: AsFd, P: Borrow<Poller>> AsFd for Registered<T, P> {
+    /// Gets the file handle of the registered source.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.get_ref().as_fd()
+    }
+}
+
+impl<T: AsFd, P: Borrow<Poller>> AsRawFd for Registered<T, P> {
+    /// Gets the raw file handle of the registered source.
+    fn as_raw_fd(&self) -> RawFd {
+        self.as_fd().as_raw_fd()
+    }
+}
+
+impl<T: AsFd + fmt::Debug, P: Borrow<Poller>> fmt::Debug for Registered<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registered")
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(all(test, feature = "pipe"))]
+mod tests {
+    use super::*;
+    use crate::pipe;
+    use std::{io::Write, sync::Arc, time::Duration};
+
+    #[test]
+    fn test_registered() {
+        let poller = Arc::new(Poller::new().unwrap());
+        let (mut wr, rd) = pipe::pipe().unwrap();
+        let rd = Registered::new(poller.clone(), rd, Event::readable(3)).unwrap();
+
+        let mut events = Events::new();
+        let timeout = Duration::from_millis(10);
+        assert_eq!(0, poller.wait_timeout(&mut events, timeout).unwrap());
+
+        wr.write_all(b"x").unwrap();
+        assert_eq!(1, poller.wait_timeout(&mut events, timeout).unwrap());
+        let ev = events.iter().next().unwrap();
+        assert_eq!(3, ev.key);
+        assert!(ev.readable);
+
+        // Once taken back, the handle can be added again
+        let rd = rd.into_inner().unwrap();
+        let rd = Registered::new(&*poller, rd, Event::readable(4)).unwrap();
+        events.clear();
+        assert_eq!(1, poller.wait_timeout(&mut events, Timeout::ZERO).unwrap());
+        assert_eq!(4, events.iter().next().unwrap().key);
+        drop(rd);
+    }
+
+    #[test]
+    fn test_wait_cancellable() {
+        let poller = Arc::new(Poller::new().unwrap());
+        let cancel = CancelToken::new().unwrap();
+        let (mut wr, rd) = pipe::pipe().unwrap();
+        let rd = Registered::new(poller.clone(), rd, Event::readable(3)).unwrap();
+
+        let mut events = Events::new();
+        let timeout = Duration::from_millis(10);
+        let n = poller
+            .wait_cancellable(&mut events, timeout, &cancel)
+            .unwrap();
+        assert_eq!(0, n);
+
+        wr.write_all(b"x").unwrap();
+        let n = poller
+            .wait_cancellable(&mut events, timeout, &cancel)
+            .unwrap();
+        assert_eq!(1, n);
+        assert_eq!(3, events.iter().next().unwrap().key);
+
+        let th = std::thread::spawn({
+            let (poller, cancel) = (poller.clone(), cancel.clone());
+            move || poller.wait_cancellable(&mut Events::new(), Timeout::None, &cancel)
+        });
+        std::thread::sleep(timeout);
+        cancel.cancel();
+        assert_eq!(Error::ECANCELED, th.join().unwrap().unwrap_err());
+        drop(rd);
+    }
+}
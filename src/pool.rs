@@ -0,0 +1,261 @@
+// hinix/src/pool.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! A pool of reusable receive buffers.
+//!
+//! A long-running consumer that allocates a buffer for each message it
+//! receives spends much of its time in the allocator. A [`BufferPool`]
+//! holds a set of fixed-size buffers that are taken for a receive, and
+//! go back to the pool when the [`PooledBuf`] guard is dropped, so that
+//! once the pool is warmed up, the receive path doesn't allocate.
+//!
+//! The receive calls that draw from a pool, like
+//! `MsgQueue::receive_pooled()`, return the guard holding the message.
+//!
+//! ```
+//! use hinix::{pipe, pool::BufferPool};
+//! use std::io::Write;
+//!
+//! let pool = BufferPool::new(512, 4);
+//! let (mut wr, mut rd) = pipe::pipe()?;
+//!
+//! wr.write_all(b"hello")?;
+//! let msg = rd.read_pooled(&pool)?;
+//! assert_eq!(b"hello", &*msg);
+//!
+//! // The buffer goes back to the pool
+//! drop(msg);
+//! assert_eq!(1, pool.available());
+//! # Ok::<(), hinix::Error>(())
+//! ```
+//!
+//! The pool is shared by its clones, and can be used from any number of
+//! threads.
+//!
+
+use std::{
+    fmt, mem,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+/// The state shared by the clones of a pool.
+#[derive(Debug)]
+struct Inner {
+    /// The size of each buffer
+    buf_size: usize,
+    /// The most buffers kept in the pool
+    capacity: usize,
+    /// The buffers that aren't in use
+    free: Mutex<Vec<Vec<u8>>>,
+}
+
+/// A pool of fixed-size buffers.
+///
+/// Buffers are allocated as needed, when the pool is empty, and up to
+/// `capacity` of them are kept for reuse when they're returned. Any more
+/// than that are freed.
+#[derive(Debug, Clone)]
+pub struct BufferPool(Arc<Inner>);
+
+impl BufferPool {
+    /// Creates a pool of buffers of the size, which keeps up to
+    /// `capacity` of them for reuse.
+    ///
+    /// The buffers are allocated as they're needed.
+    pub fn new(buf_size: usize, capacity: usize) -> Self {
+        Self(Arc::new(Inner {
+            buf_size,
+            capacity,
+            free: Mutex::new(Vec::with_capacity(capacity)),
+        }))
+    }
+
+    /// Creates a pool of buffers of the size, with `capacity` of them
+    /// allocated up front.
+    pub fn preallocated(buf_size: usize, capacity: usize) -> Self {
+        let pool = Self::new(buf_size, capacity);
+        pool.0
+            .free
+            .lock()
+            .unwrap()
+            .extend((0..capacity).map(|_| vec![0u8; buf_size]));
+        pool
+    }
+
+    /// Gets the size of each buffer.
+    pub fn buf_size(&self) -> usize {
+        self.0.buf_size
+    }
+
+    /// Gets the most buffers that the pool keeps for reuse.
+    pub fn capacity(&self) -> usize {
+        self.0.capacity
+    }
+
+    /// Gets the number of buffers in the pool that aren't in use.
+    pub fn available(&self) -> usize {
+        self.0.free.lock().unwrap().len()
+    }
+
+    /// Takes a buffer from the pool, or allocates a new one if the pool
+    /// is empty.
+    ///
+    /// The buffer starts out empty, with its full size available to be
+    /// filled through [`PooledBuf::as_full_mut()`]. A reused buffer isn't
+    /// cleared, so it can hold data from its last use.
+    pub fn get(&self) -> PooledBuf {
+        let buf = self.0.free.lock().unwrap().pop();
+        PooledBuf {
+            buf: buf.unwrap_or_else(|| vec![0u8; self.0.buf_size]),
+            len: 0,
+            pool: Some(self.0.clone()),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// A buffer taken from a pool, which goes back to the pool when dropped.
+///
+/// This dereferences to the part of the buffer that holds data, like a
+/// received message.
+pub struct PooledBuf {
+    /// The buffer, which is always the full size
+    buf: Vec<u8>,
+    /// The length of the data in the buffer
+    len: usize,
+    /// The pool to return the buffer to. This is `None` once it's taken
+    /// out of the pool for good.
+    pool: Option<Arc<Inner>>,
+}
+
+impl PooledBuf {
+    /// Gets the whole buffer, to be filled, like by a receive call.
+    ///
+    /// Set the length of the data afterward with [`set_len()`](Self::set_len).
+    pub fn as_full_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+
+    /// Gets the full size of the buffer.
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Sets the length of the data in the buffer.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the length is greater than the size of the buffer.
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= self.buf.len(), "length is larger than the buffer");
+        self.len = len;
+    }
+
+    /// Takes the data out of the pool, as a vector.
+    ///
+    /// The pool replaces the buffer with a new one when it's needed.
+    pub fn into_vec(mut self) -> Vec<u8> {
+        self.pool = None;
+        let mut buf = mem::take(&mut self.buf);
+        buf.truncate(self.len);
+        buf
+    }
+}
+
+impl Deref for PooledBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl DerefMut for PooledBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf[..self.len]
+    }
+}
+
+impl AsRef<[u8]> for PooledBuf {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl fmt::Debug for PooledBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledBuf")
+            .field("len", &self.len)
+            .field("capacity", &self.buf.len())
+            .finish()
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            let mut free = pool.free.lock().unwrap();
+            if free.len() < pool.capacity {
+                free.push(mem::take(&mut self.buf));
+            }
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool() {
+        let pool = BufferPool::new(16, 2);
+        assert_eq!(16, pool.buf_size());
+        assert_eq!(0, pool.available());
+
+        let mut buf = pool.get();
+        assert!(buf.is_empty());
+        assert_eq!(16, buf.capacity());
+
+        buf.as_full_mut()[..3].copy_from_slice(b"abc");
+        buf.set_len(3);
+        assert_eq!(b"abc", &*buf);
+
+        // The same buffer is reused
+        let ptr = buf.as_ptr();
+        drop(buf);
+        assert_eq!(1, pool.available());
+        let buf = pool.get();
+        assert_eq!(ptr, buf.as_ptr());
+        assert!(buf.is_empty());
+        drop(buf);
+
+        // Only up to the capacity are kept
+        let bufs: Vec<_> = (0..3).map(|_| pool.get()).collect();
+        assert_eq!(0, pool.available());
+        drop(bufs);
+        assert_eq!(2, pool.available());
+
+        // A buffer taken out of the pool isn't returned
+        let mut buf = pool.get();
+        buf.set_len(4);
+        assert_eq!(4, buf.into_vec().len());
+        assert_eq!(1, pool.available());
+
+        let pool = BufferPool::preallocated(8, 3);
+        assert_eq!(3, pool.available());
+    }
+}
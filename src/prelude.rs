@@ -0,0 +1,46 @@
+// hinix/src/prelude.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! The hinix prelude.
+//!
+//! This brings in the most commonly used types and traits with a single
+//! import:
+//!
+//! ```
+//! use hinix::prelude::*;
+//! ```
+//!
+
+pub use crate::{fd::FdExt, CancelToken, Error, Result, Timeout};
+
+#[cfg(feature = "codec")]
+pub use crate::codec::{Codec, PacketReceiver, PacketSender};
+
+#[cfg(feature = "fifo")]
+pub use crate::fifo::Fifo;
+
+#[cfg(feature = "pipe")]
+pub use crate::pipe::{pipe, ReadPipe, WritePipe};
+
+#[cfg(all(feature = "eventfd", any(target_os = "android", target_os = "linux")))]
+pub use crate::eventfd::EventFd;
+
+#[cfg(all(
+    feature = "msgqueue",
+    any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "netbsd"
+    )
+))]
+pub use crate::msgqueue::MsgQueue;
@@ -0,0 +1,499 @@
+// hinix/src/process.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Process creation.
+//!
+//! The clone3() system call is a more capable version of fork(). It can
+//! return a [`PidFd`] for the child, atomically with its creation, so
+//! there's no window in which the child could exit and its PID be reused
+//! before a handle to it is opened. It can also place the child in new
+//! namespaces, and choose the signal that the parent receives when the
+//! child exits.
+//!
+//! This requires Linux 5.3 or later.
+//!
+//! For programs started with `std::process::Command`, the [`CommandExt`]
+//! trait sets up the child before it runs the program, like setting its
+//! CPU affinity, or the handles it inherits, without the application
+//! having to write an unsafe `pre_exec()` closure.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man2/clone3.2.html>
+//!
+
+use crate::{
+    ns::Namespaces,
+    pidfd::PidFd,
+    sched::{CpuSet, Policy, RawPolicy},
+    Error, Result,
+};
+use nix::{
+    errno::Errno,
+    fcntl::{self, FcntlArg},
+    sys::{
+        signal::{self, Signal},
+        stat::{self, Mode},
+    },
+    unistd::{self, Pid},
+};
+use std::{
+    io,
+    mem::size_of,
+    os::{
+        raw::c_int,
+        unix::{
+            io::{AsRawFd, OwnedFd, RawFd},
+            process::CommandExt as _,
+        },
+    },
+    process::Command,
+};
+
+/// Reset all signal handlers to their defaults in the child.
+/// This is missing from libc for some targets.
+const CLONE_CLEAR_SIGHAND: u64 = 0x1_0000_0000;
+
+/// The arguments to clone3(), as of the first version of the call.
+#[repr(C)]
+#[derive(Debug, Default)]
+struct CloneArgs {
+    flags: u64,
+    pidfd: u64,
+    child_tid: u64,
+    parent_tid: u64,
+    exit_signal: u64,
+    stack: u64,
+    stack_size: u64,
+    tls: u64,
+}
+
+/// The result of a successful clone.
+#[derive(Debug)]
+pub enum CloneResult {
+    /// This is the parent process, with the ID of and handle to the
+    /// new child process.
+    Parent {
+        /// The ID of the child, in the PID namespace of the parent
+        child: Pid,
+        /// A handle to the child
+        pidfd: PidFd,
+    },
+    /// This is the new child process.
+    Child,
+}
+
+impl CloneResult {
+    /// Determines if this is the child process.
+    pub fn is_child(&self) -> bool {
+        matches!(self, CloneResult::Child)
+    }
+
+    /// Determines if this is the parent process.
+    pub fn is_parent(&self) -> bool {
+        !self.is_child()
+    }
+}
+
+/// Options for creating a child process with clone3().
+///
+/// The child is created like fork(), as a copy of the calling process,
+/// and always with a [`PidFd`] returned to the parent.
+///
+/// ```no_run
+/// # use hinix::{ns::Namespaces, process::{CloneOptions, CloneResult}};
+/// let res = unsafe {
+///     CloneOptions::new()
+///         .namespaces(Namespaces::PID | Namespaces::MOUNT)
+///         .clone3()
+/// }
+/// .unwrap();
+///
+/// match res {
+///     CloneResult::Parent { child, pidfd } => {
+///         println!("Child {} exited: {:?}", child, pidfd.wait().unwrap());
+///     }
+///     CloneResult::Child => {
+///         // The child is PID 1 in its own namespace
+///         unsafe { nix::libc::_exit(0) };
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct CloneOptions {
+    /// The new namespaces for the child
+    namespaces: Namespaces,
+    /// The signal sent to the parent when the child exits
+    exit_signal: Option<Signal>,
+    /// Whether to reset the signal handlers in the child
+    clear_sighand: bool,
+}
+
+impl CloneOptions {
+    /// Creates the default options, which create a child like fork(),
+    /// that sends SIGCHLD to the parent when it exits.
+    pub fn new() -> Self {
+        Self {
+            namespaces: Namespaces::empty(),
+            exit_signal: Some(Signal::SIGCHLD),
+            clear_sighand: false,
+        }
+    }
+
+    /// Sets the new namespaces in which to place the child.
+    ///
+    /// Creating namespaces other than a user namespace requires the
+    /// `CAP_SYS_ADMIN` capability.
+    pub fn namespaces(mut self, namespaces: Namespaces) -> Self {
+        self.namespaces = namespaces;
+        self
+    }
+
+    /// Sets the signal sent to the parent when the child exits, if any.
+    ///
+    /// Note that a child that doesn't send SIGCHLD can't be waited on
+    /// with a plain waitpid(), but can still be waited on through its
+    /// [`PidFd`].
+    pub fn exit_signal(mut self, sig: Option<Signal>) -> Self {
+        self.exit_signal = sig;
+        self
+    }
+
+    /// Sets whether to reset all the signal handlers to their defaults
+    /// in the child.
+    ///
+    /// This requires Linux 5.5 or later.
+    pub fn clear_sighand(mut self, on: bool) -> Self {
+        self.clear_sighand = on;
+        self
+    }
+
+    /// Creates the child process.
+    ///
+    /// # Safety
+    ///
+    /// This has the same restrictions as fork(). In a multi-threaded
+    /// program, the child may only make async-signal-safe calls until
+    /// it calls exec or exits. In addition, since this bypasses the C
+    /// library's fork(), any `pthread_atfork()` handlers aren't run, and
+    /// the child should exit with `_exit()`, not `exit()`.
+    pub unsafe fn clone3(&self) -> Result<CloneResult> {
+        let mut pidfd: c_int = -1;
+
+        let mut flags = libc::CLONE_PIDFD as u64 | self.namespaces.bits() as u64;
+        if self.clear_sighand {
+            flags |= CLONE_CLEAR_SIGHAND;
+        }
+
+        let mut args = CloneArgs {
+            flags,
+            pidfd: &mut pidfd as *mut c_int as u64,
+            exit_signal: self.exit_signal.map(|sig| sig as u64).unwrap_or(0),
+            ..CloneArgs::default()
+        };
+
+        let ret = libc::syscall(
+            libc::SYS_clone3,
+            &mut args as *mut CloneArgs,
+            size_of::<CloneArgs>(),
+        );
+        match Errno::result(ret)? {
+            0 => Ok(CloneResult::Child),
+            pid => Ok(CloneResult::Parent {
+                child: Pid::from_raw(pid as libc::pid_t),
+                pidfd: PidFd::from_raw_fd(pidfd),
+            }),
+        }
+    }
+}
+
+impl Default for CloneOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// Converts an error in the child to an I/O error, without allocating.
+fn child_err<E: Into<Error>>(err: E) -> io::Error {
+    io::Error::from_raw_os_error(err.into().raw_os_error())
+}
+
+/// Extensions to `std::process::Command` to set up the child process.
+///
+/// Each setting is applied in the child after it's forked, and before
+/// it runs the program, in the order in which they were set, and after
+/// the settings of the `Command` itself, like its stdio. If one fails,
+/// the spawn fails with its error.
+///
+/// ```no_run
+/// # use hinix::{process::CommandExt, sched::CpuSet};
+/// # use nix::sys::signal::Signal;
+/// # use std::process::Command;
+/// let child = Command::new("worker")
+///     .parent_death_signal(Signal::SIGTERM)
+///     .cpu_affinity(&CpuSet::new().with(2))
+///     .new_session()
+///     .spawn()
+///     .unwrap();
+/// ```
+pub trait CommandExt {
+    /// Sets a signal for the child to receive when the parent exits.
+    ///
+    /// Strictly, the signal is sent when the thread that spawned the
+    /// child exits, so the command should be spawned from a thread that
+    /// lives as long as the parent. If the parent already exited by the
+    /// time the child is set up, the child is sent the signal right away.
+    fn parent_death_signal(&mut self, sig: Signal) -> &mut Self;
+
+    /// Sets the CPUs that the child is allowed to run on.
+    fn cpu_affinity(&mut self, cpus: &CpuSet) -> &mut Self;
+
+    /// Sets the scheduling policy and priority of the child.
+    ///
+    /// See [`set_policy()`](crate::sched::set_policy) for the values and permissions
+    /// required.
+    fn sched_policy(&mut self, policy: Policy, priority: u32) -> &mut Self;
+
+    /// Passes handles to the child, as the file descriptor numbers given.
+    ///
+    /// Each item is the number the handle should have in the child, and
+    /// the handle, which is closed in the parent when the command is
+    /// dropped. A handle that's already at a number used by the map is
+    /// moved out of the way first, so the order doesn't matter. The
+    /// spawn fails with `EINVAL` if a number is used twice.
+    ///
+    /// Handles opened by the standard library and this crate are closed
+    /// on exec, so only the ones in the map, and stdio, are inherited.
+    fn fd_map<I>(&mut self, fds: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (RawFd, OwnedFd)>;
+
+    /// Runs the child in a new session, detached from the controlling
+    /// terminal of the parent.
+    fn new_session(&mut self) -> &mut Self;
+
+    /// Sets the file mode creation mask of the child.
+    fn umask(&mut self, mask: Mode) -> &mut Self;
+}
+
+impl CommandExt for Command {
+    fn parent_death_signal(&mut self, sig: Signal) -> &mut Self {
+        let parent = unistd::getpid();
+        let f = move || {
+            let ret = unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, sig as libc::c_ulong) };
+            Errno::result(ret).map_err(child_err)?;
+            // The parent may have exited before the signal was set
+            if unistd::getppid() != parent {
+                signal::raise(sig).map_err(child_err)?;
+            }
+            Ok(())
+        };
+        unsafe { self.pre_exec(f) }
+    }
+
+    fn cpu_affinity(&mut self, cpus: &CpuSet) -> &mut Self {
+        // Converted in the parent, so the child only makes the call
+        let mask = cpus.raw_mask().map_err(|err| err.raw_os_error());
+        let f = move || {
+            let mask = mask.map_err(io::Error::from_raw_os_error)?;
+            let ret =
+                unsafe { libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &mask) };
+            if ret < 0 {
+                return Err(io::Error::from_raw_os_error(Errno::last() as i32));
+            }
+            Ok(())
+        };
+        unsafe { self.pre_exec(f) }
+    }
+
+    fn sched_policy(&mut self, policy: Policy, priority: u32) -> &mut Self {
+        let policy = RawPolicy::new(policy, priority).map_err(|err| err.raw_os_error());
+        let f = move || {
+            policy
+                .map_err(io::Error::from_raw_os_error)?
+                .apply(Pid::from_raw(0))
+                .map_err(|(_, err)| io::Error::from_raw_os_error(err as i32))
+        };
+        unsafe { self.pre_exec(f) }
+    }
+
+    fn fd_map<I>(&mut self, fds: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (RawFd, OwnedFd)>,
+    {
+        let fds: Vec<(RawFd, OwnedFd)> = fds.into_iter().collect();
+        // Space for the moved handles, allocated before the fork
+        let mut moved: Vec<RawFd> = vec![-1; fds.len()];
+
+        let f = move || {
+            let Some(top) = fds.iter().map(|&(n, _)| n).max()
+            else {
+                return Ok(());
+            };
+            for (i, (n, _)) in fds.iter().enumerate() {
+                if *n < 0 || fds[..i].iter().any(|(m, _)| m == n) {
+                    return Err(child_err(Error::EINVAL));
+                }
+            }
+
+            // Move all the handles above the numbers in the map, so none
+            // are overwritten. The copies are closed on exec.
+            for ((_, fd), mv) in fds.iter().zip(moved.iter_mut()) {
+                *mv = fcntl::fcntl(fd.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(top + 1))
+                    .map_err(child_err)?;
+            }
+            // The new handles aren't closed on exec
+            for ((n, _), mv) in fds.iter().zip(moved.iter()) {
+                unistd::dup2(*mv, *n).map_err(child_err)?;
+            }
+            Ok(())
+        };
+        unsafe { self.pre_exec(f) }
+    }
+
+    fn new_session(&mut self) -> &mut Self {
+        let f = || unistd::setsid().map(drop).map_err(child_err);
+        unsafe { self.pre_exec(f) }
+    }
+
+    fn umask(&mut self, mask: Mode) -> &mut Self {
+        let f = move || {
+            stat::umask(mask);
+            Ok(())
+        };
+        unsafe { self.pre_exec(f) }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+    use nix::sys::wait::WaitStatus;
+    use std::os::unix::io::FromRawFd;
+
+    #[test]
+    fn test_clone3() {
+        let res = unsafe { CloneOptions::new().clone3() }.unwrap();
+        match res {
+            CloneResult::Child => unsafe { libc::_exit(7) },
+            CloneResult::Parent { child, pidfd } => {
+                assert_eq!(child, pidfd.pid().unwrap());
+                assert_eq!(WaitStatus::Exited(child, 7), pidfd.wait().unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_clone3_pid_namespace() {
+        let opts = CloneOptions::new()
+            .namespaces(Namespaces::PID)
+            .exit_signal(None);
+
+        match unsafe { opts.clone3() } {
+            Ok(CloneResult::Child) => {
+                // The child is init in the new namespace
+                let code = if unistd::getpid().as_raw() == 1 { 0 } else { 1 };
+                unsafe { libc::_exit(code) };
+            }
+            Ok(CloneResult::Parent { child, pidfd }) => {
+                assert_eq!(WaitStatus::Exited(child, 0), pidfd.wait().unwrap());
+            }
+            // Not privileged
+            Err(err) => assert_eq!(Error::EPERM, err),
+        }
+    }
+
+    #[test]
+    fn test_command_sched() {
+        let out = Command::new("cat")
+            .arg("/proc/self/stat")
+            .parent_death_signal(Signal::SIGKILL)
+            .cpu_affinity(&CpuSet::new().with(0))
+            .sched_policy(Policy::Batch, 0)
+            .new_session()
+            .output()
+            .unwrap();
+        assert!(out.status.success());
+
+        // The fields after the command name, which is in parentheses
+        let stat = String::from_utf8(out.stdout).unwrap();
+        let (pid, rest) = stat.split_once(" (").unwrap();
+        let fields: Vec<&str> = rest.rsplit_once(") ").unwrap().1.split(' ').collect();
+
+        // The session is the child's own
+        assert_eq!(pid, fields[3]);
+        // It only ran on CPU 0, with the batch policy
+        assert_eq!("0", fields[36]);
+        assert_eq!(libc::SCHED_BATCH.to_string(), fields[38]);
+
+        // Bad settings are reported from the child by the spawn
+        let err = Command::new("true")
+            .cpu_affinity(&CpuSet::new().with(CpuSet::capacity()))
+            .status()
+            .unwrap_err();
+        assert_eq!(Some(libc::EINVAL), err.raw_os_error());
+
+        let err = Command::new("true")
+            .sched_policy(Policy::Batch, 1)
+            .status()
+            .unwrap_err();
+        assert_eq!(Some(libc::EINVAL), err.raw_os_error());
+    }
+
+    #[test]
+    fn test_command_fds() {
+        let out = Command::new("sh")
+            .args(["-c", "umask"])
+            .umask(Mode::from_bits_truncate(0o027))
+            .output()
+            .unwrap();
+        assert_eq!("0027", String::from_utf8_lossy(&out.stdout).trim());
+
+        // Swap the ends of two pipes, so each is at the other's number
+        let (rd1, wr1) = unistd::pipe().unwrap();
+        let (rd2, wr2) = unistd::pipe().unwrap();
+        let (rd1, wr1, rd2, wr2) = unsafe {
+            (
+                OwnedFd::from_raw_fd(rd1),
+                OwnedFd::from_raw_fd(wr1),
+                OwnedFd::from_raw_fd(rd2),
+                OwnedFd::from_raw_fd(wr2),
+            )
+        };
+        let (n1, n2) = (wr1.as_raw_fd(), wr2.as_raw_fd());
+        let script = format!("echo one >&{} && echo two >&{}", n2, n1);
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", &script]).fd_map([(n2, wr1), (n1, wr2)]);
+        assert!(cmd.status().unwrap().success());
+        drop(cmd);
+
+        let mut buf = [0u8; 8];
+        let n = unistd::read(rd1.as_raw_fd(), &mut buf).unwrap();
+        assert_eq!(b"one\n", &buf[..n]);
+        let n = unistd::read(rd2.as_raw_fd(), &mut buf).unwrap();
+        assert_eq!(b"two\n", &buf[..n]);
+
+        // A number can't be used twice
+        let fd1 = rd1.try_clone().unwrap();
+        let fd2 = rd2.try_clone().unwrap();
+        let err = Command::new("true")
+            .fd_map([(10, fd1), (10, fd2)])
+            .status()
+            .unwrap_err();
+        assert_eq!(Some(libc::EINVAL), err.raw_os_error());
+    }
+}
@@ -0,0 +1,217 @@
+// hinix/src/process_vm.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Access to the memory of another process.
+//!
+//! This uses process_vm_readv(2) and process_vm_writev(2) to copy data
+//! directly between the address spaces of two processes, without the
+//! target needing to be stopped, or attached with ptrace. It's useful for
+//! debuggers, profilers, and checkpoint tools.
+//!
+//! Access requires the same permission as tracing the process: the
+//! caller must have the `CAP_SYS_PTRACE` capability, or run as the same
+//! user as the target, subject to the Yama ptrace_scope setting.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man2/process_vm_readv.2.html>
+//!
+
+use crate::{error::ResultExt, Error, Result};
+use nix::{sys::uio, unistd::Pid};
+use std::io::{IoSlice, IoSliceMut};
+
+/// A region of memory in the remote process.
+pub use nix::sys::uio::RemoteIoVec;
+
+/// A handle for reading and writing the memory of a process.
+///
+/// The transfers are not atomic with respect to the target. If it's
+/// running, it may be changing the memory while it's being read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessMemory {
+    /// The process ID of the target
+    pid: Pid,
+}
+
+impl ProcessMemory {
+    /// Creates a handle for the memory of the specified process.
+    ///
+    /// This doesn't check that the process exists, or that the caller
+    /// has permission to access it. That happens on each transfer.
+    pub fn new(pid: Pid) -> Self {
+        Self { pid }
+    }
+
+    /// Gets the process ID of the target.
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Reads memory from the process, starting at the address, `addr`,
+    /// into the buffer.
+    ///
+    /// This can return fewer bytes than requested if part of the range
+    /// is not mapped in the target.
+    pub fn read_at(&self, addr: usize, buf: &mut [u8]) -> Result<usize> {
+        let remote = [RemoteIoVec {
+            base: addr,
+            len: buf.len(),
+        }];
+        uio::process_vm_readv(self.pid, &mut [IoSliceMut::new(buf)], &remote).op("process_vm_readv")
+    }
+
+    /// Reads memory from the process, filling the whole buffer.
+    ///
+    /// Fails with `EFAULT` if the full range could not be read.
+    pub fn read_exact_at(&self, addr: usize, buf: &mut [u8]) -> Result<()> {
+        match self.read_at(addr, buf)? {
+            n if n == buf.len() => Ok(()),
+            _ => Err(Error::EFAULT),
+        }
+    }
+
+    /// Writes the buffer into the memory of the process, starting at the
+    /// address, `addr`.
+    ///
+    /// Note that this can't write to read-only memory in the target,
+    /// such as its code.
+    pub fn write_at(&self, addr: usize, buf: &[u8]) -> Result<usize> {
+        let remote = [RemoteIoVec {
+            base: addr,
+            len: buf.len(),
+        }];
+        uio::process_vm_writev(self.pid, &[IoSlice::new(buf)], &remote).op("process_vm_writev")
+    }
+
+    /// Writes the whole buffer into the memory of the process.
+    ///
+    /// Fails with `EFAULT` if the full range could not be written.
+    pub fn write_all_at(&self, addr: usize, buf: &[u8]) -> Result<()> {
+        match self.write_at(addr, buf)? {
+            n if n == buf.len() => Ok(()),
+            _ => Err(Error::EFAULT),
+        }
+    }
+
+    /// Reads multiple regions of the remote process into multiple local
+    /// buffers in a single call.
+    ///
+    /// The data is gathered from the remote regions in order, and
+    /// scattered into the local buffers in order. The two sets don't
+    /// need to line up. This returns the total number of bytes read,
+    /// which will stop short at the first remote region that can't be
+    /// read.
+    pub fn read_vectored(
+        &self,
+        local: &mut [IoSliceMut<'_>],
+        remote: &[RemoteIoVec],
+    ) -> Result<usize> {
+        uio::process_vm_readv(self.pid, local, remote).op("process_vm_readv")
+    }
+
+    /// Writes multiple local buffers into multiple regions of the remote
+    /// process in a single call.
+    ///
+    /// The data is gathered from the local buffers in order, and
+    /// scattered into the remote regions in order. This returns the total
+    /// number of bytes written.
+    pub fn write_vectored(&self, local: &[IoSlice<'_>], remote: &[RemoteIoVec]) -> Result<usize> {
+        uio::process_vm_writev(self.pid, local, remote).op("process_vm_writev")
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(all(test, feature = "pipe"))]
+mod tests {
+    use super::*;
+    use crate::pipe;
+    use nix::{
+        sys::{
+            signal::{self, Signal},
+            wait,
+        },
+        unistd::{self, ForkResult},
+    };
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_self() {
+        let mem = ProcessMemory::new(unistd::getpid());
+        let src = *b"hello, world";
+        let addr = src.as_ptr() as usize;
+
+        let mut buf = [0u8; 5];
+        mem.read_exact_at(addr + 7, &mut buf).unwrap();
+        assert_eq!(b"world", &buf);
+
+        let mut a = [0u8; 3];
+        let mut b = [0u8; 4];
+        let remote = [
+            RemoteIoVec { base: addr, len: 5 },
+            RemoteIoVec {
+                base: addr + 10,
+                len: 2,
+            },
+        ];
+        let n = mem
+            .read_vectored(
+                &mut [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)],
+                &remote,
+            )
+            .unwrap();
+        assert_eq!(7, n);
+        assert_eq!(b"hel", &a);
+        assert_eq!(b"lold", &b);
+
+        // Address zero is never mapped
+        assert_eq!(Error::EFAULT, mem.read_at(0, &mut buf).unwrap_err());
+    }
+
+    #[test]
+    fn test_child() {
+        let mut data = *b"parent";
+        let addr = data.as_mut_ptr() as usize;
+
+        let (mut wr, mut rd) = pipe::pipe().unwrap();
+
+        match unsafe { unistd::fork() }.unwrap() {
+            ForkResult::Child => {
+                data.copy_from_slice(b"child!");
+                // Tell the parent we're ready, then wait to be killed
+                let _ = wr.write_all(b"x");
+                loop {
+                    unistd::pause();
+                }
+            }
+            ForkResult::Parent { child } => {
+                let mut buf = [0u8; 1];
+                rd.read_exact(&mut buf).unwrap();
+
+                // The child has the buffer at the same address
+                let mem = ProcessMemory::new(child);
+                let mut buf = [0u8; 6];
+                mem.read_exact_at(addr, &mut buf).unwrap();
+                assert_eq!(b"child!", &buf);
+
+                mem.write_all_at(addr, b"poked!").unwrap();
+                mem.read_exact_at(addr, &mut buf).unwrap();
+                assert_eq!(b"poked!", &buf);
+                assert_eq!(b"parent", &data);
+
+                signal::kill(child, Signal::SIGKILL).unwrap();
+                wait::waitpid(child, None).unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,561 @@
+// hinix/src/pty.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Pseudo-terminals (pty).
+//!
+//! A pseudo-terminal is a pair of connected virtual character devices.
+//! The slave end looks and acts like a real terminal to the program
+//! that uses it, such as a shell. The master end is used by the
+//! controlling application, like a terminal emulator or a script driving
+//! an interactive program, to read the program's output and feed it
+//! input.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man7/pty.7.html>
+//!
+
+use crate::{
+    error::ResultExt,
+    fd::FdExt,
+    term::{self, ResizeWatcher},
+    Error, Result,
+};
+use nix::{
+    errno::Errno,
+    pty,
+    unistd::{self, Pid},
+};
+use std::{
+    io::{self, Read, Write},
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    path::PathBuf,
+};
+
+/// The size of a terminal window.
+pub use nix::pty::Winsize;
+
+/// The terminal settings.
+pub use nix::sys::termios::Termios;
+
+/// A pseudo-terminal pair.
+#[derive(Debug)]
+pub struct Pty {
+    /// The master end
+    master: PtyMaster,
+    /// The slave end
+    slave: PtySlave,
+}
+
+impl Pty {
+    /// Opens a new pseudo-terminal with the default settings.
+    pub fn open() -> Result<Self> {
+        Self::with_config(None, None)
+    }
+
+    /// Opens a new pseudo-terminal with the specified window size.
+    pub fn with_size(winsize: &Winsize) -> Result<Self> {
+        Self::with_config(winsize, None)
+    }
+
+    /// Opens a new pseudo-terminal with an optional window size and
+    /// terminal settings.
+    ///
+    /// Both ends are close-on-exec. The slave is normally passed to a
+    /// child as its standard I/O, which clears the flag on the copies.
+    ///
+    /// <https://man7.org/linux/man-pages/man3/openpty.3.html>
+    pub fn with_config<'a, 'b, W, T>(winsize: W, termios: T) -> Result<Self>
+    where
+        W: Into<Option<&'a Winsize>>,
+        T: Into<Option<&'b Termios>>,
+    {
+        let res = pty::openpty(winsize, termios)?;
+        let (master, slave) = unsafe {
+            (
+                PtyMaster::from_raw_fd(res.master),
+                PtySlave::from_raw_fd(res.slave),
+            )
+        };
+        // openpty() has no flag to set this atomically
+        master.set_cloexec(true)?;
+        slave.set_cloexec(true)?;
+        Ok(Self { master, slave })
+    }
+
+    /// Gets a reference to the master end of the pty.
+    pub fn master(&self) -> &PtyMaster {
+        &self.master
+    }
+
+    /// Gets a mutable reference to the master end of the pty.
+    pub fn master_mut(&mut self) -> &mut PtyMaster {
+        &mut self.master
+    }
+
+    /// Gets a reference to the slave end of the pty.
+    pub fn slave(&self) -> &PtySlave {
+        &self.slave
+    }
+
+    /// Gets a mutable reference to the slave end of the pty.
+    pub fn slave_mut(&mut self) -> &mut PtySlave {
+        &mut self.slave
+    }
+
+    /// Makes the slave the controlling terminal and standard I/O of the
+    /// calling process, and closes the master.
+    ///
+    /// This is normally called in a child process after a fork, just
+    /// before exec'ing the program that should run under the pty.
+    /// See [`PtySlave::login_tty()`].
+    pub fn login_tty(self) -> Result<()> {
+        let (master, slave) = self.split();
+        drop(master);
+        slave.login_tty()
+    }
+
+    /// Splits the pty into the separate master and slave ends.
+    pub fn split(self) -> (PtyMaster, PtySlave) {
+        (self.master, self.slave)
+    }
+
+    /// Gets the window size of the pty.
+    pub fn window_size(&self) -> Result<Winsize> {
+        self.master.window_size()
+    }
+
+    /// Sets the window size of the pty.
+    ///
+    /// The kernel sends a SIGWINCH to the foreground process group of the
+    /// slave if the size changes.
+    pub fn set_window_size(&self, winsize: &Winsize) -> Result<()> {
+        self.master.set_window_size(winsize)
+    }
+}
+
+/// The result of forking a process with a new pseudo-terminal.
+#[derive(Debug)]
+pub enum ForkPty {
+    /// Returned in the parent process, with the master end of the pty
+    /// and the process ID of the child.
+    Parent {
+        /// The process ID of the child
+        child: Pid,
+        /// The master end of the pty
+        master: PtyMaster,
+    },
+    /// Returned in the child process, which has the slave end of the
+    /// pty as its controlling terminal and standard I/O.
+    Child,
+}
+
+/// Forks the process, running the child under a new pseudo-terminal.
+///
+/// The child gets a new session with the slave end of the pty as its
+/// controlling terminal, and with it's standard input, output, and error
+/// all connected to the slave.
+///
+/// <https://man7.org/linux/man-pages/man3/forkpty.3.html>
+///
+/// # Safety
+///
+/// This has the same restrictions as `fork()`. In a multithreaded program
+/// only async-signal-safe functions may be called by the child, typically
+/// up until it calls one of the `exec` functions.
+pub unsafe fn fork_pty<'a, 'b, W, T>(winsize: W, termios: T) -> Result<ForkPty>
+where
+    W: Into<Option<&'a Winsize>>,
+    T: Into<Option<&'b Termios>>,
+{
+    let res = pty::forkpty(winsize, termios)?;
+    Ok(match res.fork_result {
+        unistd::ForkResult::Parent { child } => ForkPty::Parent {
+            child,
+            master: PtyMaster::from_raw_fd(res.master),
+        },
+        unistd::ForkResult::Child => ForkPty::Child,
+    })
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// The master end of a pseudo-terminal.
+#[derive(Debug)]
+pub struct PtyMaster(OwnedFd);
+
+impl PtyMaster {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self(OwnedFd::from_raw_fd(fd))
+    }
+
+    /// Try to clone the master by making a dup() of the OS file handle.
+    pub fn try_clone(&self) -> Result<Self> {
+        let fd = self.0.dup()?;
+        Ok(Self(fd))
+    }
+
+    /// Gets the window size of the pty.
+    pub fn window_size(&self) -> Result<Winsize> {
+        term::size(self)
+    }
+
+    /// Sets the window size of the pty.
+    ///
+    /// The kernel sends a SIGWINCH to the foreground process group of the
+    /// slave if the size changes.
+    pub fn set_window_size(&self, winsize: &Winsize) -> Result<()> {
+        term::set_size(self, winsize)
+    }
+}
+
+impl Read for PtyMaster {
+    /// Reads the output from the program running on the slave.
+    ///
+    /// On Linux, once all the handles to the slave are closed, a read
+    /// from the master fails with an `EIO` error. This reports that
+    /// condition as an EOF, just like a pipe would.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match unistd::read(self.as_raw_fd(), buf) {
+            Err(Errno::EIO) => Ok(0),
+            res => Ok(res?),
+        }
+    }
+}
+
+impl Write for PtyMaster {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(unistd::write(self.as_raw_fd(), buf)?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsFd for PtyMaster {
+    /// Gets the file handle for the master end of the pty.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for PtyMaster {
+    /// Gets the raw file handle for the master end of the pty.
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for PtyMaster {
+    /// Gives up ownership of the file handle for the master end of the pty.
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl From<PtyMaster> for OwnedFd {
+    fn from(master: PtyMaster) -> Self {
+        master.0
+    }
+}
+
+impl TryFrom<OwnedFd> for PtyMaster {
+    type Error = Error;
+
+    /// Takes ownership of the master end of a pty, such as one passed
+    /// from another process.
+    ///
+    /// This fails with `ENOTTY` if the handle isn't a terminal.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        if !unistd::isatty(fd.as_raw_fd())? {
+            return Err(Error::ENOTTY);
+        }
+        Ok(Self(fd))
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// The slave end of a pseudo-terminal.
+#[derive(Debug)]
+pub struct PtySlave(OwnedFd);
+
+impl PtySlave {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self(OwnedFd::from_raw_fd(fd))
+    }
+
+    /// Gets the path name of the slave device, like "/dev/pts/3".
+    pub fn name(&self) -> Result<PathBuf> {
+        unistd::ttyname(self.as_raw_fd()).op("ttyname")
+    }
+
+    /// Makes the slave the controlling terminal of the calling process.
+    ///
+    /// This starts a new session, with the calling process as the session
+    /// leader, then acquires the slave as the controlling terminal of the
+    /// session. It fails with `EPERM` if the process is already a process
+    /// group leader, which is why it is normally done in a freshly forked
+    /// child.
+    ///
+    /// This only makes async-signal-safe calls, and so can be used in the
+    /// child after a fork in a multithreaded program.
+    pub fn make_controlling_terminal(&self) -> Result<()> {
+        unistd::setsid()?;
+        Errno::result(unsafe { libc::ioctl(self.as_raw_fd(), libc::TIOCSCTTY as _, 0) })?;
+        Ok(())
+    }
+
+    /// Prepares the calling process to run under the pty, like the BSD
+    /// `login_tty()` function.
+    ///
+    /// This makes the slave the controlling terminal of a new session,
+    /// then duplicates it onto the standard input, output, and error
+    /// handles of the process. The original slave handle is then closed.
+    ///
+    /// This only makes async-signal-safe calls, and so can be used in the
+    /// child after a fork in a multithreaded program.
+    ///
+    /// <https://man7.org/linux/man-pages/man3/login_tty.3.html>
+    pub fn login_tty(self) -> Result<()> {
+        self.make_controlling_terminal()?;
+
+        for stdfd in 0..=2 {
+            self.dup_to(stdfd)?;
+        }
+
+        // Don't close the handle if it happened to be one of stdio
+        if self.as_raw_fd() <= 2 {
+            let _ = self.0.into_raw_fd();
+        }
+        Ok(())
+    }
+}
+
+impl Read for PtySlave {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(unistd::read(self.as_raw_fd(), buf)?)
+    }
+}
+
+impl Write for PtySlave {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(unistd::write(self.as_raw_fd(), buf)?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsFd for PtySlave {
+    /// Gets the file handle for the slave end of the pty.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for PtySlave {
+    /// Gets the raw file handle for the slave end of the pty.
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for PtySlave {
+    /// Gives up ownership of the file handle for the slave end of the pty.
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl From<PtySlave> for OwnedFd {
+    fn from(slave: PtySlave) -> Self {
+        slave.0
+    }
+}
+
+impl TryFrom<OwnedFd> for PtySlave {
+    type Error = Error;
+
+    /// Takes ownership of the slave end of a pty, such as the controlling
+    /// terminal of the process.
+    ///
+    /// This fails with `ENOTTY` if the handle isn't a terminal.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        if !unistd::isatty(fd.as_raw_fd())? {
+            return Err(Error::ENOTTY);
+        }
+        Ok(Self(fd))
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// Forwards window size changes from a terminal to a pty.
+///
+/// This is what a terminal multiplexer or any program that runs a child
+/// under a pty needs to do to keep the child's idea of the window size in
+/// sync with the real terminal. The size of the terminal is copied to the
+/// pty when the forwarder is created, and then again whenever the process
+/// receives a SIGWINCH, which the kernel sends when the size of the
+/// controlling terminal changes.
+///
+/// This is built on a [`ResizeWatcher`], and so has the same restriction
+/// that only one can be active in the process at a time.
+#[derive(Debug)]
+pub struct WinchForwarder {
+    /// The watcher that does the forwarding, for as long as it lives
+    _watcher: ResizeWatcher,
+}
+
+impl WinchForwarder {
+    /// Creates a forwarder that copies the size of the `tty` terminal,
+    /// typically stdin, to the pty with the `master` end.
+    pub fn new<T: AsFd>(tty: &T, master: &PtyMaster) -> Result<Self> {
+        let master = master.try_clone()?;
+        let watcher = ResizeWatcher::new(tty, move |winsize| {
+            let _ = master.set_window_size(&winsize);
+        })?;
+        Ok(Self { _watcher: watcher })
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+    use nix::sys::{
+        signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal},
+        wait::{waitpid, WaitStatus},
+    };
+    use std::{thread, time::Duration};
+
+    fn winsize(rows: u16, cols: u16) -> Winsize {
+        Winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        }
+    }
+
+    #[test]
+    fn test_open() {
+        let mut pty = Pty::open().unwrap();
+        assert!(pty.slave().name().unwrap().starts_with("/dev/"));
+        assert!(pty.master().is_cloexec().unwrap());
+        assert!(pty.slave().is_cloexec().unwrap());
+
+        // In canonical mode, the slave gets a line at a time
+        pty.master_mut().write_all(b"hello\n").unwrap();
+
+        let mut buf = [0u8; 16];
+        let n = pty.slave_mut().read(&mut buf).unwrap();
+        assert_eq!(b"hello\n", &buf[..n]);
+    }
+
+    #[test]
+    fn test_fork() {
+        match unsafe { fork_pty(None, None) }.unwrap() {
+            ForkPty::Child => {
+                let _ = unistd::write(1, b"child");
+                unsafe { libc::_exit(0) };
+            }
+            ForkPty::Parent { child, mut master } => {
+                let mut s = String::new();
+                master.read_to_string(&mut s).unwrap();
+                assert_eq!("child", s);
+                assert_eq!(WaitStatus::Exited(child, 0), waitpid(child, None).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_login_tty() {
+        let pty = Pty::open().unwrap();
+
+        match unsafe { unistd::fork() }.unwrap() {
+            unistd::ForkResult::Child => {
+                let ok = pty.login_tty().is_ok()
+                    && unistd::isatty(0) == Ok(true)
+                    && unistd::isatty(1) == Ok(true)
+                    && nix::sys::termios::tcgetsid(0) == Ok(unistd::getpid());
+                let _ = unistd::write(1, if ok { b"Y" } else { b"N" });
+                unsafe { libc::_exit(0) };
+            }
+            unistd::ForkResult::Parent { child } => {
+                let (mut master, slave) = pty.split();
+                drop(slave);
+
+                let mut s = String::new();
+                master.read_to_string(&mut s).unwrap();
+                assert_eq!("Y", s);
+                assert_eq!(WaitStatus::Exited(child, 0), waitpid(child, None).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_window_size() {
+        let pty = Pty::with_size(&winsize(24, 80)).unwrap();
+
+        let ws = pty.window_size().unwrap();
+        assert_eq!((24, 80), (ws.ws_row, ws.ws_col));
+
+        pty.set_window_size(&winsize(50, 132)).unwrap();
+        let ws = pty.window_size().unwrap();
+        assert_eq!((50, 132), (ws.ws_row, ws.ws_col));
+    }
+
+    #[test]
+    fn test_winch_forwarder() {
+        // Use the slave of one pty as the stand-in for the real terminal
+        let tty = Pty::with_size(&winsize(30, 100)).unwrap();
+        let pty = Pty::with_size(&winsize(24, 80)).unwrap();
+
+        // Some other disposition, that should be put back afterwards
+        let ign = SigAction::new(SigHandler::SigIgn, SaFlags::empty(), SigSet::empty());
+        let orig = unsafe { signal::sigaction(Signal::SIGWINCH, &ign) }.unwrap();
+
+        let fwd = WinchForwarder::new(tty.slave(), pty.master()).unwrap();
+        assert_eq!(
+            Error::EBUSY,
+            WinchForwarder::new(tty.slave(), pty.master()).unwrap_err()
+        );
+
+        // The initial size gets copied right away
+        let ws = pty.window_size().unwrap();
+        assert_eq!((30, 100), (ws.ws_row, ws.ws_col));
+
+        // Then on each SIGWINCH
+        tty.set_window_size(&winsize(40, 120)).unwrap();
+        signal::raise(Signal::SIGWINCH).unwrap();
+
+        let mut ws = pty.window_size().unwrap();
+        for _ in 0..100 {
+            if ws.ws_row == 40 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+            ws = pty.window_size().unwrap();
+        }
+        assert_eq!((40, 120), (ws.ws_row, ws.ws_col));
+        drop(fwd);
+
+        let prev = unsafe { signal::sigaction(Signal::SIGWINCH, &orig) }.unwrap();
+        assert_eq!(SigHandler::SigIgn, prev.handler());
+    }
+}
@@ -0,0 +1,135 @@
+// hinix/src/random.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Random bytes from the kernel.
+//!
+//! This wraps the getrandom(2) system call, which gets random bytes from
+//! the kernel's entropy pool without needing to open /dev/urandom. That
+//! makes it usable in a chroot, or when out of file descriptors.
+//!
+//! Early in the boot, before the pool has been initialized, a normal
+//! request will block. The functions here make the choice explicit:
+//! [`fill()`] waits for the pool, [`fill_nonblocking()`] fails with
+//! `EAGAIN` rather than waiting, and [`fill_insecure()`] returns bytes
+//! that may not be suitable for cryptographic use, but never blocks.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man2/getrandom.2.html>
+//!
+
+use crate::{error::ResultExt, Error, Result};
+use bitflags::bitflags;
+use nix::errno::Errno;
+use std::os::raw::c_uint;
+
+bitflags! {
+    /// Flags for the getrandom(2) call.
+    pub struct GetRandomFlags: c_uint {
+        /// Fail with EAGAIN rather than block if no entropy is available
+        const GRND_NONBLOCK = libc::GRND_NONBLOCK;
+        /// Use the (legacy) blocking random pool rather than urandom
+        const GRND_RANDOM = libc::GRND_RANDOM;
+        /// Return bytes even if the pool isn't initialized (Linux 5.6+)
+        const GRND_INSECURE = libc::GRND_INSECURE;
+    }
+}
+
+/// Gets up to `buf.len()` random bytes from the kernel.
+///
+/// This is the raw system call, which can return fewer bytes than
+/// requested, or fail with `EINTR` if interrupted by a signal. Returns
+/// the number of bytes written to the buffer.
+pub fn getrandom(buf: &mut [u8], flags: GetRandomFlags) -> Result<usize> {
+    let n = unsafe {
+        libc::syscall(
+            libc::SYS_getrandom,
+            buf.as_mut_ptr(),
+            buf.len(),
+            flags.bits(),
+        )
+    };
+    Errno::result(n).map(|n| n as usize).op("getrandom")
+}
+
+/// Fills the whole buffer, retrying after short reads or interruptions.
+fn fill_with(buf: &mut [u8], flags: GetRandomFlags) -> Result<()> {
+    let mut off = 0;
+    while off < buf.len() {
+        match getrandom(&mut buf[off..], flags) {
+            Ok(n) => off += n,
+            Err(err) if err == Error::EINTR => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Fills the buffer with random bytes, suitable for cryptographic use.
+///
+/// This blocks if the kernel's entropy pool has not yet been initialized,
+/// which can only happen early in the boot.
+pub fn fill(buf: &mut [u8]) -> Result<()> {
+    fill_with(buf, GetRandomFlags::empty())
+}
+
+/// Fills the buffer with random bytes, suitable for cryptographic use,
+/// without blocking.
+///
+/// This fails with `EAGAIN` if the kernel's entropy pool has not yet
+/// been initialized.
+pub fn fill_nonblocking(buf: &mut [u8]) -> Result<()> {
+    fill_with(buf, GetRandomFlags::GRND_NONBLOCK)
+}
+
+/// Fills the buffer with random bytes, without blocking, even if the
+/// kernel's entropy pool has not been initialized.
+///
+/// The bytes are not guaranteed to be suitable for cryptographic use.
+/// This requires Linux 5.6 or later, and fails with `EINVAL` on older
+/// kernels.
+pub fn fill_insecure(buf: &mut [u8]) -> Result<()> {
+    fill_with(buf, GetRandomFlags::GRND_INSECURE)
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill() {
+        let mut a = [0u8; 64];
+        let mut b = [0u8; 64];
+        fill(&mut a).unwrap();
+        fill_nonblocking(&mut b).unwrap();
+        // Not impossible, but not likely
+        assert_ne!(a, b);
+        assert_ne!([0u8; 64], a);
+
+        let mut buf = [0u8; 0];
+        assert_eq!(Ok(0), getrandom(&mut buf, GetRandomFlags::empty()));
+
+        let mut big = vec![0u8; 1 << 20];
+        fill(&mut big).unwrap();
+    }
+
+    #[test]
+    fn test_insecure() {
+        let mut buf = [0u8; 32];
+        match fill_insecure(&mut buf) {
+            Ok(()) => assert_ne!([0u8; 32], buf),
+            // Kernel older than 5.6
+            Err(err) => assert_eq!(Errno::EINVAL, err),
+        }
+    }
+}
@@ -0,0 +1,723 @@
+// hinix/src/sched.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Scheduling control for processes and threads.
+//!
+//! On Linux, scheduling attributes apply to individual threads. The
+//! functions here take a process or thread ID, where a `Pid` of zero
+//! refers to the calling thread.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man7/sched.7.html>
+//!
+
+use crate::{error::ResultExt, system, Error, Result};
+use nix::{
+    errno::Errno,
+    sched,
+    sys::mman::{self, MlockAllFlags},
+    unistd::{Pid, Uid},
+};
+use std::{
+    fmt,
+    mem::size_of,
+    ops::{Bound, RangeBounds, RangeInclusive},
+    os::raw::c_int,
+    str::FromStr,
+    time::Duration,
+};
+
+/// The deadline scheduling policy. This is missing from libc.
+const SCHED_DEADLINE: c_int = 6;
+
+/// The targets for ioprio_set() and ioprio_get().
+const IOPRIO_WHO_PROCESS: c_int = 1;
+const IOPRIO_WHO_PGRP: c_int = 2;
+const IOPRIO_WHO_USER: c_int = 3;
+
+/// The bit offset of the class in an I/O priority value.
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+
+/// The attributes for the sched_setattr() and sched_getattr() system
+/// calls. This is missing from libc.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct SchedAttr {
+    size: u32,
+    sched_policy: u32,
+    sched_flags: u64,
+    sched_nice: i32,
+    sched_priority: u32,
+    sched_runtime: u64,
+    sched_deadline: u64,
+    sched_period: u64,
+}
+
+/// A set of CPUs, used for a thread's CPU affinity.
+///
+/// This is a builder-style wrapper around the kernel's CPU mask, to
+/// make it easy to describe the CPUs a thread may run on:
+///
+/// ```
+/// # use hinix::sched::CpuSet;
+/// let cpus = CpuSet::new().with(0).with_range(4..8);
+/// assert_eq!(5, cpus.len());
+///
+/// let others = CpuSet::all_but(&[0]);
+/// assert!(!others.contains(0));
+/// ```
+///
+/// CPU numbers beyond the capacity of the mask make the set invalid,
+/// and it will be rejected with `EINVAL` when used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuSet {
+    /// The kernel mask
+    set: sched::CpuSet,
+    /// Whether an out-of-range CPU was added
+    invalid: bool,
+}
+
+impl CpuSet {
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        Self {
+            set: sched::CpuSet::new(),
+            invalid: false,
+        }
+    }
+
+    /// Creates a set of all the CPUs configured in the system.
+    pub fn all() -> Self {
+        Self::new().with_range(0..system::cpu_count())
+    }
+
+    /// Creates a set of all the CPUs configured in the system, except
+    /// for the ones listed.
+    pub fn all_but(cpus: &[usize]) -> Self {
+        cpus.iter().fold(Self::all(), |set, &cpu| set.without(cpu))
+    }
+
+    /// Gets the maximum number of CPUs that the set can hold.
+    pub fn capacity() -> usize {
+        sched::CpuSet::count()
+    }
+
+    /// Adds a CPU to the set.
+    pub fn with(mut self, cpu: usize) -> Self {
+        self.insert(cpu);
+        self
+    }
+
+    /// Adds a range of CPUs to the set.
+    pub fn with_range<R: RangeBounds<usize>>(mut self, cpus: R) -> Self {
+        let start = match cpus.start_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match cpus.end_bound() {
+            Bound::Included(&n) => n + 1,
+            Bound::Excluded(&n) => n,
+            Bound::Unbounded => Self::capacity(),
+        };
+        for cpu in start..end {
+            self.insert(cpu);
+        }
+        self
+    }
+
+    /// Removes a CPU from the set.
+    pub fn without(mut self, cpu: usize) -> Self {
+        self.remove(cpu);
+        self
+    }
+
+    /// Adds a CPU to the set, in place.
+    pub fn insert(&mut self, cpu: usize) {
+        if self.set.set(cpu).is_err() {
+            self.invalid = true;
+        }
+    }
+
+    /// Removes a CPU from the set, in place.
+    pub fn remove(&mut self, cpu: usize) {
+        let _ = self.set.unset(cpu);
+    }
+
+    /// Determines if the CPU is in the set.
+    pub fn contains(&self, cpu: usize) -> bool {
+        self.set.is_set(cpu).unwrap_or(false)
+    }
+
+    /// Gets the number of CPUs in the set.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Determines if the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Gets an iterator over the CPU numbers in the set, in order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..Self::capacity()).filter(move |&cpu| self.contains(cpu))
+    }
+
+    /// Gets the kernel mask, or an error if the set is invalid.
+    fn mask(&self) -> Result<&sched::CpuSet> {
+        if self.invalid {
+            Err(Error::EINVAL)
+        }
+        else {
+            Ok(&self.set)
+        }
+    }
+
+    /// Gets the C library mask, for a raw sched_setaffinity() call, or
+    /// an error if the set is invalid.
+    #[cfg(feature = "process")]
+    pub(crate) fn raw_mask(&self) -> Result<libc::cpu_set_t> {
+        self.mask()?;
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        for cpu in self.iter() {
+            unsafe { libc::CPU_SET(cpu, &mut set) };
+        }
+        Ok(set)
+    }
+}
+
+impl Default for CpuSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FromIterator<usize> for CpuSet {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        iter.into_iter().fold(Self::new(), |set, cpu| set.with(cpu))
+    }
+}
+
+impl FromStr for CpuSet {
+    type Err = Error;
+
+    /// Parses a CPU list, in the format used by the kernel and tools like
+    /// taskset, such as "0,2,4-7".
+    fn from_str(s: &str) -> Result<Self> {
+        let mut cpus = Self::new();
+        for item in s.trim().split(',').filter(|item| !item.is_empty()) {
+            let parse = |n: &str| n.trim().parse::<usize>().map_err(|_| Error::EINVAL);
+            match item.split_once('-') {
+                Some((lo, hi)) => {
+                    let (lo, hi) = (parse(lo)?, parse(hi)?);
+                    if lo > hi {
+                        return Err(Error::EINVAL);
+                    }
+                    cpus = cpus.with_range(lo..=hi);
+                }
+                None => cpus.insert(parse(item)?),
+            }
+        }
+        Ok(cpus)
+    }
+}
+
+impl fmt::Display for CpuSet {
+    /// Formats the set as a CPU list, like "0,2,4-7".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut cpus = self.iter().peekable();
+        let mut first = true;
+        while let Some(lo) = cpus.next() {
+            let mut hi = lo;
+            while cpus.peek() == Some(&(hi + 1)) {
+                hi = cpus.next().unwrap_or(hi);
+            }
+            if !first {
+                write!(f, ",")?;
+            }
+            first = false;
+            if hi > lo {
+                write!(f, "{}-{}", lo, hi)?;
+            }
+            else {
+                write!(f, "{}", lo)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sets the CPUs that a thread is allowed to run on.
+///
+/// A `pid` of zero sets the affinity of the calling thread. Fails with
+/// `EINVAL` if the set is invalid, or contains no CPUs that are online.
+pub fn set_affinity(pid: Pid, cpus: &CpuSet) -> Result<()> {
+    sched::sched_setaffinity(pid, cpus.mask()?).op("sched_setaffinity")
+}
+
+/// Gets the CPUs that a thread is allowed to run on.
+///
+/// A `pid` of zero gets the affinity of the calling thread.
+pub fn get_affinity(pid: Pid) -> Result<CpuSet> {
+    Ok(CpuSet {
+        set: sched::sched_getaffinity(pid).op("sched_getaffinity")?,
+        invalid: false,
+    })
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// A scheduling policy for a thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// The default, time-sharing, policy (`SCHED_OTHER`)
+    Other,
+    /// Time-sharing for non-interactive, CPU-intensive, work
+    /// (`SCHED_BATCH`)
+    Batch,
+    /// For very low priority background work (`SCHED_IDLE`)
+    Idle,
+    /// Real-time, first-in, first-out (`SCHED_FIFO`)
+    Fifo,
+    /// Real-time, round-robin (`SCHED_RR`)
+    RoundRobin,
+    /// Real-time, earliest deadline first (`SCHED_DEADLINE`)
+    ///
+    /// The thread is guaranteed `runtime` of CPU time within `deadline`
+    /// of the start of each `period`.
+    Deadline {
+        /// The CPU time needed in each period
+        runtime: Duration,
+        /// The time from the start of the period by which the work
+        /// must be done
+        deadline: Duration,
+        /// The length of each period
+        period: Duration,
+    },
+}
+
+impl Policy {
+    /// Gets the kernel's value for the policy.
+    fn as_raw(&self) -> c_int {
+        use Policy::*;
+        match self {
+            Other => libc::SCHED_OTHER,
+            Batch => libc::SCHED_BATCH,
+            Idle => libc::SCHED_IDLE,
+            Fifo => libc::SCHED_FIFO,
+            RoundRobin => libc::SCHED_RR,
+            Deadline { .. } => SCHED_DEADLINE,
+        }
+    }
+
+    /// Determines if this is one of the real-time policies.
+    pub fn is_realtime(&self) -> bool {
+        matches!(
+            self,
+            Policy::Fifo | Policy::RoundRobin | Policy::Deadline { .. }
+        )
+    }
+}
+
+/// Converts a duration to nanoseconds for the deadline scheduler.
+fn nanos(d: Duration) -> Result<u64> {
+    u64::try_from(d.as_nanos()).map_err(|_| Error::EINVAL)
+}
+
+/// A policy and priority, checked and converted for the system calls,
+/// so that they can be applied later without allocating, like in a
+/// child process after a fork.
+#[derive(Clone, Copy)]
+pub(crate) enum RawPolicy {
+    /// The deadline policy, for sched_setattr()
+    Attr(SchedAttr),
+    /// The other policies, for sched_setscheduler()
+    Param(c_int, libc::sched_param),
+}
+
+impl RawPolicy {
+    /// Checks and converts the policy and priority.
+    pub(crate) fn new(policy: Policy, priority: u32) -> Result<Self> {
+        if let Policy::Deadline {
+            runtime,
+            deadline,
+            period,
+        } = policy
+        {
+            if priority != 0 {
+                return Err(Error::EINVAL);
+            }
+            return Ok(RawPolicy::Attr(SchedAttr {
+                size: size_of::<SchedAttr>() as u32,
+                sched_policy: SCHED_DEADLINE as u32,
+                sched_runtime: nanos(runtime)?,
+                sched_deadline: nanos(deadline)?,
+                sched_period: nanos(period)?,
+                ..SchedAttr::default()
+            }));
+        }
+
+        let param = libc::sched_param {
+            sched_priority: c_int::try_from(priority).map_err(|_| Error::EINVAL)?,
+        };
+        Ok(RawPolicy::Param(policy.as_raw(), param))
+    }
+
+    /// Sets the policy of a thread, with the raw system call. This is
+    /// async-signal-safe.
+    ///
+    /// Returns the name of the call, and its errno, on failure.
+    pub(crate) fn apply(&self, pid: Pid) -> std::result::Result<(), (&'static str, Errno)> {
+        match self {
+            RawPolicy::Attr(attr) => {
+                let ret = unsafe {
+                    libc::syscall(
+                        libc::SYS_sched_setattr,
+                        pid.as_raw(),
+                        attr as *const SchedAttr,
+                        0 as c_int,
+                    )
+                };
+                Errno::result(ret).map(drop).map_err(|e| ("sched_setattr", e))
+            }
+            RawPolicy::Param(policy, param) => {
+                let ret = unsafe { libc::sched_setscheduler(pid.as_raw(), *policy, param) };
+                Errno::result(ret).map(drop).map_err(|e| ("sched_setscheduler", e))
+            }
+        }
+    }
+}
+
+/// Sets the scheduling policy and priority of a thread.
+///
+/// A `pid` of zero sets the policy of the calling thread.
+///
+/// The priority is only used for the `Fifo` and `RoundRobin` policies,
+/// where it must be in the range given by [`priority_range()`],
+/// typically 1-99. It must be zero for the others.
+///
+/// Setting a real-time policy normally requires the `CAP_SYS_NICE`
+/// capability, or an `RLIMIT_RTPRIO` limit that allows it.
+pub fn set_policy(pid: Pid, policy: Policy, priority: u32) -> Result<()> {
+    match RawPolicy::new(policy, priority)?.apply(pid) {
+        Ok(()) => Ok(()),
+        Err((call, err)) => Err(err).op(call),
+    }
+}
+
+/// Gets the scheduling policy and priority of a thread.
+///
+/// A `pid` of zero gets the policy of the calling thread.
+pub fn get_policy(pid: Pid) -> Result<(Policy, u32)> {
+    let mut attr = SchedAttr::default();
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_sched_getattr,
+            pid.as_raw(),
+            &mut attr as *mut SchedAttr,
+            size_of::<SchedAttr>() as u32,
+            0 as c_int,
+        )
+    };
+    Errno::result(ret).op("sched_getattr")?;
+
+    let policy = match attr.sched_policy as c_int {
+        libc::SCHED_OTHER => Policy::Other,
+        libc::SCHED_BATCH => Policy::Batch,
+        libc::SCHED_IDLE => Policy::Idle,
+        libc::SCHED_FIFO => Policy::Fifo,
+        libc::SCHED_RR => Policy::RoundRobin,
+        SCHED_DEADLINE => Policy::Deadline {
+            runtime: Duration::from_nanos(attr.sched_runtime),
+            deadline: Duration::from_nanos(attr.sched_deadline),
+            period: Duration::from_nanos(attr.sched_period),
+        },
+        _ => return Err(Error::EINVAL),
+    };
+    Ok((policy, attr.sched_priority))
+}
+
+/// Gets the range of priorities allowed for a scheduling policy.
+pub fn priority_range(policy: Policy) -> Result<RangeInclusive<u32>> {
+    let min = Errno::result(unsafe { libc::sched_get_priority_min(policy.as_raw()) })?;
+    let max = Errno::result(unsafe { libc::sched_get_priority_max(policy.as_raw()) })?;
+    Ok(min as u32..=max as u32)
+}
+
+/// Locks all of the current and future memory of the process into RAM,
+/// preventing it from being paged out.
+///
+/// This is commonly done by real-time applications to avoid the latency
+/// of page faults. It requires the `CAP_IPC_LOCK` capability, or an
+/// `RLIMIT_MEMLOCK` limit large enough to hold the process.
+pub fn lock_memory() -> Result<()> {
+    mman::mlockall(MlockAllFlags::MCL_CURRENT | MlockAllFlags::MCL_FUTURE).op("mlockall")
+}
+
+/// Unlocks all of the memory of the process.
+pub fn unlock_memory() -> Result<()> {
+    mman::munlockall().op("munlockall")
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// The target of a process priority change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriorityTarget {
+    /// A single process or thread. Zero is the calling thread.
+    Process(Pid),
+    /// All the processes in a process group. Zero is the group of the
+    /// calling process.
+    ProcessGroup(Pid),
+    /// All the processes owned by a user. Zero is the real user of the
+    /// calling process.
+    User(Uid),
+}
+
+impl PriorityTarget {
+    /// Gets the target as the (which, who) arguments for setpriority()
+    fn prio_args(&self) -> (c_int, libc::id_t) {
+        match *self {
+            PriorityTarget::Process(pid) => {
+                (libc::PRIO_PROCESS as c_int, pid.as_raw() as libc::id_t)
+            }
+            PriorityTarget::ProcessGroup(pid) => {
+                (libc::PRIO_PGRP as c_int, pid.as_raw() as libc::id_t)
+            }
+            PriorityTarget::User(uid) => (libc::PRIO_USER as c_int, uid.as_raw()),
+        }
+    }
+
+    /// Gets the target as the (which, who) arguments for ioprio_set()
+    fn ioprio_args(&self) -> (c_int, c_int) {
+        match *self {
+            PriorityTarget::Process(pid) => (IOPRIO_WHO_PROCESS, pid.as_raw()),
+            PriorityTarget::ProcessGroup(pid) => (IOPRIO_WHO_PGRP, pid.as_raw()),
+            PriorityTarget::User(uid) => (IOPRIO_WHO_USER, uid.as_raw() as c_int),
+        }
+    }
+}
+
+/// Sets the nice value of the target, in the range -20 (highest priority)
+/// to 19 (lowest priority).
+///
+/// Any user can lower the priority of their own processes, but raising
+/// it requires the `CAP_SYS_NICE` capability or a suitable
+/// `RLIMIT_NICE` limit.
+pub fn set_priority(target: PriorityTarget, nice: i32) -> Result<()> {
+    let (which, who) = target.prio_args();
+    Errno::result(unsafe { libc::setpriority(which as _, who, nice) })
+        .map(drop)
+        .op("setpriority")
+}
+
+/// Gets the nice value of the target.
+///
+/// For a group or user, this is the highest priority (lowest nice value)
+/// of any of its processes.
+pub fn get_priority(target: PriorityTarget) -> Result<i32> {
+    let (which, who) = target.prio_args();
+    // -1 is a valid return value, so we need to check errno.
+    Errno::clear();
+    let ret = unsafe { libc::getpriority(which as _, who) };
+    match Errno::last() {
+        Errno::UnknownErrno => Ok(ret),
+        err => Err(Error::from(err).with_op("getpriority")),
+    }
+}
+
+/// The I/O scheduling class and priority for a process.
+///
+/// Within the real-time and best-effort classes, there are eight
+/// priority levels, from 0 (highest) to 7 (lowest).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    /// No class has been set. The I/O priority is derived from the
+    /// process's nice value.
+    None,
+    /// Real-time, which gets first access to the disk. This requires
+    /// the `CAP_SYS_ADMIN` capability.
+    RealTime(u8),
+    /// Best-effort, the default class.
+    BestEffort(u8),
+    /// Idle, which only gets disk time when no other process needs it.
+    Idle,
+}
+
+impl IoPriority {
+    /// Gets the kernel's value for the priority.
+    fn as_raw(&self) -> Result<c_int> {
+        let (class, level) = match *self {
+            IoPriority::None => (0, 0),
+            IoPriority::RealTime(level) => (1, level),
+            IoPriority::BestEffort(level) => (2, level),
+            IoPriority::Idle => (3, 0),
+        };
+        if level > 7 {
+            return Err(Error::EINVAL);
+        }
+        Ok((class << IOPRIO_CLASS_SHIFT) | c_int::from(level))
+    }
+
+    /// Creates a priority from the kernel's value.
+    fn from_raw(ioprio: c_int) -> Result<Self> {
+        let level = (ioprio & ((1 << IOPRIO_CLASS_SHIFT) - 1)) as u8;
+        match ioprio >> IOPRIO_CLASS_SHIFT {
+            0 => Ok(IoPriority::None),
+            1 => Ok(IoPriority::RealTime(level)),
+            2 => Ok(IoPriority::BestEffort(level)),
+            3 => Ok(IoPriority::Idle),
+            _ => Err(Error::EINVAL),
+        }
+    }
+}
+
+/// Sets the I/O scheduling class and priority of the target.
+///
+/// This only has an effect with I/O schedulers that support it, like
+/// BFQ. Fails with `EINVAL` if the priority level is out of range.
+pub fn set_io_priority(target: PriorityTarget, prio: IoPriority) -> Result<()> {
+    let (which, who) = target.ioprio_args();
+    let ioprio = prio.as_raw()?;
+    let ret = unsafe { libc::syscall(libc::SYS_ioprio_set, which, who, ioprio) };
+    Errno::result(ret).map(drop).op("ioprio_set")
+}
+
+/// Gets the I/O scheduling class and priority of the target.
+pub fn get_io_priority(target: PriorityTarget) -> Result<IoPriority> {
+    let (which, who) = target.ioprio_args();
+    let ret = unsafe { libc::syscall(libc::SYS_ioprio_get, which, who) };
+    IoPriority::from_raw(Errno::result(ret).op("ioprio_get")? as c_int)
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_cpuset_builder() {
+        let cpus = CpuSet::new().with(1).with_range(4..=6).without(5);
+        assert_eq!(vec![1, 4, 6], cpus.iter().collect::<Vec<_>>());
+        assert_eq!(3, cpus.len());
+        assert!(!CpuSet::new().with(0).is_empty());
+        assert!(CpuSet::new().is_empty());
+
+        assert_eq!(cpus, [1, 4, 6].into_iter().collect());
+
+        let all = CpuSet::all();
+        assert_eq!(system::cpu_count(), all.len());
+        assert_eq!(all.len() - 1, CpuSet::all_but(&[0]).len());
+
+        let bad = CpuSet::new().with(CpuSet::capacity());
+        assert_eq!(
+            Error::EINVAL,
+            set_affinity(Pid::from_raw(0), &bad).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_cpuset_list() {
+        let cpus: CpuSet = "0,2,4-7".parse().unwrap();
+        assert_eq!(vec![0, 2, 4, 5, 6, 7], cpus.iter().collect::<Vec<_>>());
+        assert_eq!("0,2,4-7", cpus.to_string());
+
+        assert_eq!(CpuSet::new(), "".parse().unwrap());
+        assert_eq!("", CpuSet::new().to_string());
+        assert_eq!("3", CpuSet::new().with(3).to_string());
+
+        assert_eq!(Error::EINVAL, "1,x".parse::<CpuSet>().unwrap_err());
+        assert_eq!(Error::EINVAL, "5-2".parse::<CpuSet>().unwrap_err());
+    }
+
+    #[test]
+    fn test_affinity() {
+        // Run in a separate thread, so as not to affect the test harness
+        thread::spawn(|| {
+            let pid = Pid::from_raw(0);
+            let orig = get_affinity(pid).unwrap();
+            let cpu = orig.iter().next().unwrap();
+
+            set_affinity(pid, &CpuSet::new().with(cpu)).unwrap();
+            let cpus = get_affinity(pid).unwrap();
+            assert_eq!(vec![cpu], cpus.iter().collect::<Vec<_>>());
+
+            set_affinity(pid, &orig).unwrap();
+            assert_eq!(orig, get_affinity(pid).unwrap());
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_policy() {
+        assert_eq!(1..=99, priority_range(Policy::Fifo).unwrap());
+        assert_eq!(0..=0, priority_range(Policy::Other).unwrap());
+
+        thread::spawn(|| {
+            let pid = Pid::from_raw(0);
+            assert_eq!((Policy::Other, 0), get_policy(pid).unwrap());
+
+            set_policy(pid, Policy::Batch, 0).unwrap();
+            assert_eq!((Policy::Batch, 0), get_policy(pid).unwrap());
+
+            assert_eq!(Error::EINVAL, set_policy(pid, Policy::Fifo, 0).unwrap_err());
+
+            // This needs privileges that we might not have
+            match set_policy(pid, Policy::Fifo, 1) {
+                Ok(()) => assert_eq!((Policy::Fifo, 1), get_policy(pid).unwrap()),
+                Err(err) => assert_eq!(Error::EPERM, err),
+            }
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_priority() {
+        thread::spawn(|| {
+            let me = PriorityTarget::Process(Pid::from_raw(0));
+            let nice = get_priority(me).unwrap();
+
+            // Anyone can lower their own priority
+            let lower = (nice + 1).min(19);
+            set_priority(me, lower).unwrap();
+            assert_eq!(lower, get_priority(me).unwrap());
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_io_priority() {
+        assert_eq!(
+            Error::EINVAL,
+            IoPriority::BestEffort(8).as_raw().unwrap_err()
+        );
+
+        thread::spawn(|| {
+            let me = PriorityTarget::Process(Pid::from_raw(0));
+            get_io_priority(me).unwrap();
+
+            set_io_priority(me, IoPriority::BestEffort(7)).unwrap();
+            assert_eq!(IoPriority::BestEffort(7), get_io_priority(me).unwrap());
+
+            set_io_priority(me, IoPriority::Idle).unwrap();
+            assert_eq!(IoPriority::Idle, get_io_priority(me).unwrap());
+        })
+        .join()
+        .unwrap();
+    }
+}
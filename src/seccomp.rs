@@ -0,0 +1,406 @@
+// hinix/src/seccomp.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Basic seccomp system call filtering.
+//!
+//! A seccomp filter restricts the system calls that a thread (and any
+//! threads or processes it creates afterward) can make. This is a simple
+//! builder for the common cases of an allow-list, where everything not
+//! listed is rejected, or a deny-list, where only the listed calls are
+//! rejected:
+//!
+//! ```no_run
+//! # use hinix::seccomp::{Action, Filter};
+//! # use nix::errno::Errno;
+//! Filter::new(Action::Allow)
+//!     .deny("ptrace", Action::Errno(Errno::EPERM))
+//!     .deny("mount", Action::KillProcess)
+//!     .apply()
+//!     .unwrap();
+//! ```
+//!
+//! Once applied, a filter can't be removed. Additional filters can be
+//! added, and the most restrictive action of all of them is taken.
+//!
+//! System calls are identified by name, using the kernel names for the
+//! native architecture, like "openat" or "newfstatat". Keep in mind that
+//! the C library may use different calls than the function names
+//! suggest. For example, `open()` is implemented with `openat` on modern
+//! systems.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man2/seccomp.2.html>
+//!
+
+use crate::{Error, Result};
+use nix::errno::Errno;
+use std::os::raw::{c_long, c_ulong};
+
+/// The audit architecture value for the native system call ABI.
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH_NATIVE: u32 = 0xC000_003E;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH_NATIVE: u32 = 0xC000_00B7;
+
+/// On x86_64, system calls with this bit set use the x32 ABI, which
+/// reports the same architecture value. These must be rejected, or
+/// a deny-list could be bypassed.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+// Offsets into struct seccomp_data
+const DATA_NR_OFFSET: u32 = 0;
+const DATA_ARCH_OFFSET: u32 = 4;
+
+// Classic BPF instruction codes
+const BPF_LD_W_ABS: u16 = (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16;
+const BPF_JMP_JEQ_K: u16 = (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16;
+#[cfg(target_arch = "x86_64")]
+const BPF_JMP_JGE_K: u16 = (libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K) as u16;
+const BPF_RET_K: u16 = (libc::BPF_RET | libc::BPF_K) as u16;
+
+// Builds a table of system call names and numbers from the libc
+// constants. The names keep the "SYS_" prefix, which is stripped
+// when they're looked up.
+macro_rules! syscalls {
+    ($($name:ident,)+) => {
+        &[$((stringify!($name), libc::$name),)+]
+    };
+}
+
+/// The system calls common to the supported architectures.
+const SYSCALLS: &[(&str, c_long)] = syscalls! {
+    SYS_read, SYS_write, SYS_openat, SYS_close, SYS_fstat, SYS_lseek,
+    SYS_mmap, SYS_mprotect, SYS_munmap, SYS_brk, SYS_rt_sigaction,
+    SYS_rt_sigprocmask, SYS_rt_sigreturn, SYS_ioctl, SYS_pread64,
+    SYS_pwrite64, SYS_readv, SYS_writev, SYS_sched_yield, SYS_mremap,
+    SYS_msync, SYS_mincore, SYS_madvise, SYS_dup, SYS_dup3, SYS_nanosleep,
+    SYS_getitimer, SYS_setitimer, SYS_getpid, SYS_sendfile, SYS_socket,
+    SYS_connect, SYS_accept, SYS_accept4, SYS_sendto, SYS_recvfrom,
+    SYS_sendmsg, SYS_recvmsg, SYS_sendmmsg, SYS_recvmmsg, SYS_shutdown,
+    SYS_bind, SYS_listen, SYS_getsockname, SYS_getpeername, SYS_socketpair,
+    SYS_setsockopt, SYS_getsockopt, SYS_clone, SYS_clone3, SYS_execve,
+    SYS_execveat, SYS_exit, SYS_exit_group, SYS_wait4, SYS_waitid, SYS_kill,
+    SYS_tkill, SYS_tgkill, SYS_uname, SYS_fcntl, SYS_flock, SYS_fsync,
+    SYS_fdatasync, SYS_truncate, SYS_ftruncate, SYS_getcwd, SYS_chdir,
+    SYS_fchdir, SYS_fchmod, SYS_fchmodat, SYS_fchown, SYS_fchownat,
+    SYS_umask, SYS_gettimeofday, SYS_settimeofday, SYS_prlimit64,
+    SYS_getrusage, SYS_sysinfo, SYS_times, SYS_ptrace, SYS_getuid,
+    SYS_getgid, SYS_setuid, SYS_setgid, SYS_geteuid, SYS_getegid,
+    SYS_setpgid, SYS_getpgid, SYS_getppid, SYS_setsid, SYS_getsid,
+    SYS_setreuid, SYS_setregid, SYS_getgroups, SYS_setgroups, SYS_setresuid,
+    SYS_getresuid, SYS_setresgid, SYS_getresgid, SYS_setfsuid, SYS_setfsgid,
+    SYS_capget, SYS_capset, SYS_rt_sigpending, SYS_rt_sigtimedwait,
+    SYS_rt_sigqueueinfo, SYS_rt_sigsuspend, SYS_sigaltstack,
+    SYS_personality, SYS_statfs, SYS_fstatfs, SYS_getpriority,
+    SYS_setpriority, SYS_sched_setparam, SYS_sched_getparam,
+    SYS_sched_setscheduler, SYS_sched_getscheduler,
+    SYS_sched_get_priority_max, SYS_sched_get_priority_min,
+    SYS_sched_rr_get_interval, SYS_sched_setaffinity, SYS_sched_getaffinity,
+    SYS_sched_setattr, SYS_sched_getattr, SYS_mlock, SYS_mlock2,
+    SYS_munlock, SYS_mlockall, SYS_munlockall, SYS_vhangup, SYS_pivot_root,
+    SYS_prctl, SYS_adjtimex, SYS_chroot, SYS_sync, SYS_syncfs, SYS_acct,
+    SYS_mount, SYS_umount2, SYS_swapon, SYS_swapoff, SYS_reboot,
+    SYS_sethostname, SYS_setdomainname, SYS_init_module, SYS_finit_module,
+    SYS_delete_module, SYS_quotactl, SYS_gettid, SYS_readahead,
+    SYS_setxattr, SYS_lsetxattr, SYS_fsetxattr, SYS_getxattr, SYS_lgetxattr,
+    SYS_fgetxattr, SYS_listxattr, SYS_llistxattr, SYS_flistxattr,
+    SYS_removexattr, SYS_lremovexattr, SYS_fremovexattr, SYS_getdents64,
+    SYS_set_tid_address, SYS_restart_syscall, SYS_futex,
+    SYS_set_robust_list, SYS_get_robust_list, SYS_timer_create,
+    SYS_timer_settime, SYS_timer_gettime, SYS_timer_getoverrun,
+    SYS_timer_delete, SYS_clock_gettime, SYS_clock_settime,
+    SYS_clock_getres, SYS_clock_nanosleep, SYS_epoll_create1, SYS_epoll_ctl,
+    SYS_epoll_pwait, SYS_inotify_init1, SYS_inotify_add_watch,
+    SYS_inotify_rm_watch, SYS_mkdirat, SYS_mknodat, SYS_newfstatat,
+    SYS_unlinkat, SYS_renameat2, SYS_linkat, SYS_symlinkat, SYS_readlinkat,
+    SYS_faccessat, SYS_faccessat2, SYS_pselect6, SYS_ppoll, SYS_unshare,
+    SYS_setns, SYS_splice, SYS_tee, SYS_vmsplice, SYS_utimensat,
+    SYS_signalfd4, SYS_timerfd_create, SYS_timerfd_settime,
+    SYS_timerfd_gettime, SYS_eventfd2, SYS_fallocate, SYS_pipe2, SYS_preadv,
+    SYS_pwritev, SYS_preadv2, SYS_pwritev2, SYS_name_to_handle_at,
+    SYS_open_by_handle_at, SYS_getcpu, SYS_process_vm_readv,
+    SYS_process_vm_writev, SYS_kcmp, SYS_seccomp, SYS_getrandom,
+    SYS_memfd_create, SYS_bpf, SYS_membarrier, SYS_copy_file_range,
+    SYS_statx, SYS_io_setup, SYS_io_destroy, SYS_io_getevents,
+    SYS_io_submit, SYS_io_cancel, SYS_io_uring_setup, SYS_io_uring_enter,
+    SYS_io_uring_register, SYS_pidfd_open, SYS_pidfd_send_signal,
+    SYS_pidfd_getfd, SYS_openat2, SYS_close_range, SYS_mq_open,
+    SYS_mq_unlink, SYS_mq_timedsend, SYS_mq_timedreceive, SYS_mq_notify,
+    SYS_mq_getsetattr, SYS_msgget, SYS_msgsnd, SYS_msgrcv, SYS_msgctl,
+    SYS_semget, SYS_semop, SYS_semctl, SYS_semtimedop, SYS_shmget,
+    SYS_shmat, SYS_shmdt, SYS_shmctl, SYS_fanotify_init, SYS_fanotify_mark,
+    SYS_perf_event_open, SYS_userfaultfd,
+};
+
+/// The legacy system calls that are only on x86_64.
+#[cfg(target_arch = "x86_64")]
+const ARCH_SYSCALLS: &[(&str, c_long)] = syscalls! {
+    SYS_open, SYS_stat, SYS_lstat, SYS_poll, SYS_access, SYS_pipe,
+    SYS_select, SYS_dup2, SYS_pause, SYS_alarm, SYS_fork, SYS_vfork,
+    SYS_creat, SYS_mkdir, SYS_rmdir, SYS_unlink, SYS_rename, SYS_link,
+    SYS_symlink, SYS_readlink, SYS_chmod, SYS_chown, SYS_lchown,
+    SYS_getdents, SYS_epoll_create, SYS_epoll_wait, SYS_time,
+    SYS_arch_prctl, SYS_inotify_init, SYS_eventfd, SYS_signalfd, SYS_utimes,
+    SYS_getpgrp, SYS_renameat, SYS_getrlimit, SYS_setrlimit,
+    SYS_sync_file_range,
+};
+
+#[cfg(not(target_arch = "x86_64"))]
+const ARCH_SYSCALLS: &[(&str, c_long)] = &[];
+
+/// Looks up the number of a system call by name, for the native
+/// architecture.
+pub fn syscall_number(name: &str) -> Option<c_long> {
+    SYSCALLS
+        .iter()
+        .chain(ARCH_SYSCALLS)
+        .find(|(sc, _)| sc.strip_prefix("SYS_") == Some(name))
+        .map(|&(_, nr)| nr)
+}
+
+/// The action to take when a filter rule matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Allow the system call.
+    Allow,
+    /// Fail the system call with the error, without running it.
+    Errno(Errno),
+    /// Allow the system call, but log it.
+    Log,
+    /// Send SIGSYS to the thread, without running the call.
+    Trap,
+    /// Kill the thread that made the call.
+    KillThread,
+    /// Kill the whole process.
+    KillProcess,
+}
+
+impl Action {
+    /// Gets the filter return value for the action.
+    fn ret(&self) -> u32 {
+        match *self {
+            Action::Allow => libc::SECCOMP_RET_ALLOW,
+            Action::Errno(err) => libc::SECCOMP_RET_ERRNO | (err as u32 & libc::SECCOMP_RET_DATA),
+            Action::Log => libc::SECCOMP_RET_LOG,
+            Action::Trap => libc::SECCOMP_RET_TRAP,
+            Action::KillThread => libc::SECCOMP_RET_KILL_THREAD,
+            Action::KillProcess => libc::SECCOMP_RET_KILL_PROCESS,
+        }
+    }
+}
+
+/// A builder for a simple seccomp filter.
+///
+/// The filter has a default action, and a list of system calls with
+/// their own actions. For an allow-list, use a default action that
+/// rejects the call, and `allow()` the calls that are needed. For a
+/// deny-list, use a default of `Action::Allow`, and `deny()` the calls
+/// that aren't wanted.
+///
+/// Any unknown system call names make the filter invalid, and it will
+/// fail with `EINVAL` when applied.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    /// The action for calls that don't match a rule
+    default: Action,
+    /// The system call numbers and their actions
+    rules: Vec<(c_long, Action)>,
+    /// An error found while building the filter
+    err: Option<Error>,
+}
+
+impl Filter {
+    /// Creates a new filter with the action to take for system calls
+    /// that aren't otherwise listed.
+    pub fn new(default: Action) -> Self {
+        Self {
+            default,
+            rules: Vec::new(),
+            err: None,
+        }
+    }
+
+    /// Adds a rule to take the action for the system call.
+    pub fn rule(mut self, name: &str, action: Action) -> Self {
+        match syscall_number(name) {
+            Some(nr) => self.rules.push((nr, action)),
+            None => self.err = Some(Error::EINVAL),
+        }
+        self
+    }
+
+    /// Adds a rule to take the action for a system call, by number.
+    ///
+    /// This can be used for calls that aren't in the table of names.
+    pub fn rule_nr(mut self, nr: c_long, action: Action) -> Self {
+        self.rules.push((nr, action));
+        self
+    }
+
+    /// Adds a rule to allow the system call.
+    pub fn allow(self, name: &str) -> Self {
+        self.rule(name, Action::Allow)
+    }
+
+    /// Adds rules to allow all of the system calls.
+    pub fn allow_all(self, names: &[&str]) -> Self {
+        names.iter().fold(self, |filter, name| filter.allow(name))
+    }
+
+    /// Adds a rule to reject the system call with the action.
+    pub fn deny(self, name: &str, action: Action) -> Self {
+        self.rule(name, action)
+    }
+
+    /// Builds the BPF program for the filter.
+    fn program(&self) -> Result<Vec<libc::sock_filter>> {
+        if let Some(err) = &self.err {
+            return Err(err.clone());
+        }
+
+        let stmt = |code, k| libc::sock_filter {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        };
+        let jump = |code, k, jt, jf| libc::sock_filter { code, jt, jf, k };
+
+        let mut prog = vec![
+            // Kill the process if the call isn't from the native ABI
+            stmt(BPF_LD_W_ABS, DATA_ARCH_OFFSET),
+            jump(BPF_JMP_JEQ_K, AUDIT_ARCH_NATIVE, 1, 0),
+            stmt(BPF_RET_K, libc::SECCOMP_RET_KILL_PROCESS),
+            stmt(BPF_LD_W_ABS, DATA_NR_OFFSET),
+        ];
+
+        #[cfg(target_arch = "x86_64")]
+        prog.extend([
+            jump(BPF_JMP_JGE_K, X32_SYSCALL_BIT, 0, 1),
+            stmt(BPF_RET_K, libc::SECCOMP_RET_KILL_PROCESS),
+        ]);
+
+        for &(nr, action) in &self.rules {
+            prog.push(jump(BPF_JMP_JEQ_K, nr as u32, 0, 1));
+            prog.push(stmt(BPF_RET_K, action.ret()));
+        }
+        prog.push(stmt(BPF_RET_K, self.default.ret()));
+
+        if prog.len() > libc::BPF_MAXINSNS as usize {
+            return Err(Error::E2BIG);
+        }
+        Ok(prog)
+    }
+
+    /// Installs the filter for the calling thread.
+    ///
+    /// This also sets the "no new privileges" flag for the thread, as
+    /// the kernel requires of unprivileged processes.
+    pub fn apply(&self) -> Result<()> {
+        self.install(0)
+    }
+
+    /// Installs the filter for all the threads in the process.
+    ///
+    /// Fails with `ESRCH` (rather than the thread ID the kernel reports)
+    /// if another thread can't be synchronized, such as if it has an
+    /// incompatible filter of its own.
+    pub fn apply_all_threads(&self) -> Result<()> {
+        self.install(libc::SECCOMP_FILTER_FLAG_TSYNC)
+    }
+
+    fn install(&self, flags: c_ulong) -> Result<()> {
+        let mut prog = self.program()?;
+        let fprog = libc::sock_fprog {
+            len: prog.len() as u16,
+            filter: prog.as_mut_ptr(),
+        };
+
+        let ret = unsafe {
+            libc::prctl(
+                libc::PR_SET_NO_NEW_PRIVS,
+                1 as c_ulong,
+                0 as c_ulong,
+                0 as c_ulong,
+                0 as c_ulong,
+            )
+        };
+        Errno::result(ret)?;
+
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                flags,
+                &fprog as *const libc::sock_fprog,
+            )
+        };
+        match Errno::result(ret)? {
+            0 => Ok(()),
+            _ => Err(Error::ESRCH),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::child_status;
+    use nix::{
+        sys::{signal::Signal, wait::WaitStatus},
+        unistd,
+    };
+    use std::fs;
+
+    #[test]
+    fn test_syscall_number() {
+        assert_eq!(Some(libc::SYS_read), syscall_number("read"));
+        assert_eq!(Some(libc::SYS_openat), syscall_number("openat"));
+        assert_eq!(None, syscall_number("no_such_call"));
+
+        let filter = Filter::new(Action::Allow).deny("no_such_call", Action::KillProcess);
+        assert_eq!(Error::EINVAL, filter.apply().unwrap_err());
+    }
+
+    #[test]
+    fn test_deny_errno() {
+        let status = child_status(|| {
+            Filter::new(Action::Allow)
+                .deny("openat", Action::Errno(Errno::EACCES))
+                .apply()
+                .unwrap();
+
+            let err = fs::File::open("/dev/null").unwrap_err();
+            assert_eq!(Some(libc::EACCES), err.raw_os_error());
+            assert!(unistd::getpid().as_raw() > 0);
+        });
+        assert!(matches!(status, WaitStatus::Exited(_, 0)));
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let status = child_status(|| {
+            Filter::new(Action::KillProcess)
+                .allow_all(&["exit_group", "exit", "getpid"])
+                .apply()
+                .unwrap();
+
+            unistd::getpid();
+            unsafe { libc::getppid() };
+            unsafe { libc::_exit(0) };
+        });
+        assert!(matches!(status, WaitStatus::Signaled(_, Signal::SIGSYS, _)));
+    }
+}
@@ -0,0 +1,312 @@
+// hinix/src/security.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Process security and privilege management.
+//!
+//! A daemon that needs to start as root, perhaps to bind to a privileged
+//! port or open a device, should give up those privileges as soon as it
+//! no longer needs them. Doing that correctly requires a number of calls
+//! in a specific order, each of which must be checked:
+//!
+//! 1. Set the supplementary groups, while we still have the privilege
+//!    to do so.
+//! 2. Set the real, effective, and saved group IDs.
+//! 3. Set the real, effective, and saved user IDs. This must be last,
+//!    since it removes the privilege to do the others.
+//! 4. Verify that the privileges can't be regained.
+//!
+//! There are also some process attributes that harden a daemon against
+//! misuse, which can be applied together with [`harden()`].
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man7/credentials.7.html>
+//! <https://man7.org/linux/man-pages/man2/prctl.2.html>
+//!
+
+use crate::{error::ResultExt, Error, Result};
+use bitflags::bitflags;
+use nix::{
+    errno::Errno,
+    unistd::{self, Gid, Group, Uid, User},
+};
+use std::{
+    ffi::CString,
+    os::raw::{c_int, c_ulong},
+};
+
+/// Permanently drops the privileges of the process to those of the
+/// specified user and group.
+///
+/// If `group` is `None`, the user's primary group is used. The
+/// supplementary groups are set to the groups that the user belongs to,
+/// replacing any that the process inherited.
+///
+/// Fails with `EINVAL` if the user or group doesn't exist.
+pub fn drop_privileges(user: &str, group: Option<&str>) -> Result<()> {
+    let user = User::from_name(user)?.ok_or(Error::EINVAL)?;
+    let gid = match group {
+        Some(group) => Group::from_name(group)?.ok_or(Error::EINVAL)?.gid,
+        None => user.gid,
+    };
+    let name = CString::new(user.name).map_err(|_| Error::EINVAL)?;
+
+    unistd::initgroups(&name, gid)?;
+    set_ids(user.uid, gid)
+}
+
+/// Permanently drops the privileges of the process to those of the
+/// specified user and group IDs.
+///
+/// Since there's no user name with which to look up group membership,
+/// the supplementary groups are cleared.
+pub fn drop_privileges_to(uid: Uid, gid: Gid) -> Result<()> {
+    unistd::setgroups(&[])?;
+    set_ids(uid, gid)
+}
+
+/// Sets all the group and user IDs, in that order, then verifies that
+/// the change is permanent.
+fn set_ids(uid: Uid, gid: Gid) -> Result<()> {
+    let was_root = unistd::geteuid().is_root();
+
+    unistd::setresgid(gid, gid, gid)?;
+    unistd::setresuid(uid, uid, uid)?;
+
+    let ids = unistd::getresgid()?;
+    if ids.real != gid || ids.effective != gid || ids.saved != gid {
+        return Err(Error::EPERM);
+    }
+    let ids = unistd::getresuid()?;
+    if ids.real != uid || ids.effective != uid || ids.saved != uid {
+        return Err(Error::EPERM);
+    }
+
+    // If we gave up root, make sure we can't get it back.
+    if was_root && !uid.is_root() && unistd::setuid(Uid::from_raw(0)).is_ok() {
+        return Err(Error::EPERM);
+    }
+    Ok(())
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+bitflags! {
+    /// The "securebits" flags, which control how the kernel treats the
+    /// root user with respect to capabilities.
+    ///
+    /// Each flag has a "locked" counterpart which, when set, prevents
+    /// the flag from being changed again.
+    pub struct SecureBits: u32 {
+        /// Don't grant capabilities to root when it execs a program.
+        const NOROOT = 1 << 0;
+        /// Lock the NOROOT flag.
+        const NOROOT_LOCKED = 1 << 1;
+        /// Don't adjust capabilities when switching to or from UID 0.
+        const NO_SETUID_FIXUP = 1 << 2;
+        /// Lock the NO_SETUID_FIXUP flag.
+        const NO_SETUID_FIXUP_LOCKED = 1 << 3;
+        /// Keep the permitted capabilities when switching from UID 0 to
+        /// a non-zero UID.
+        const KEEP_CAPS = 1 << 4;
+        /// Lock the KEEP_CAPS flag.
+        const KEEP_CAPS_LOCKED = 1 << 5;
+        /// Don't allow ambient capabilities to be raised.
+        const NO_CAP_AMBIENT_RAISE = 1 << 6;
+        /// Lock the NO_CAP_AMBIENT_RAISE flag.
+        const NO_CAP_AMBIENT_RAISE_LOCKED = 1 << 7;
+    }
+}
+
+/// Makes a prctl() call with a single argument.
+fn prctl(op: c_int, arg: c_ulong) -> Result<c_int> {
+    let ret = unsafe { libc::prctl(op, arg, 0 as c_ulong, 0 as c_ulong, 0 as c_ulong) };
+    Errno::result(ret).op("prctl")
+}
+
+/// Sets the "no new privileges" flag for the calling thread.
+///
+/// After this, exec can't grant privileges, such as through set-user-ID
+/// programs or file capabilities. The flag is inherited by children, and
+/// can't be cleared.
+pub fn set_no_new_privs() -> Result<()> {
+    prctl(libc::PR_SET_NO_NEW_PRIVS, 1).map(drop)
+}
+
+/// Determines if the "no new privileges" flag is set for the calling
+/// thread.
+pub fn no_new_privs() -> Result<bool> {
+    prctl(libc::PR_GET_NO_NEW_PRIVS, 0).map(|n| n != 0)
+}
+
+/// Sets whether the process is "dumpable".
+///
+/// A process that isn't dumpable won't produce a core dump, and can't
+/// be attached with ptrace by other processes of the same user. Its
+/// /proc files are also owned by root.
+pub fn set_dumpable(dumpable: bool) -> Result<()> {
+    prctl(libc::PR_SET_DUMPABLE, c_ulong::from(dumpable)).map(drop)
+}
+
+/// Determines if the process is "dumpable".
+pub fn is_dumpable() -> Result<bool> {
+    prctl(libc::PR_GET_DUMPABLE, 0).map(|n| n != 0)
+}
+
+/// Sets the securebits flags for the calling thread.
+///
+/// This requires the `CAP_SETPCAP` capability.
+pub fn set_securebits(bits: SecureBits) -> Result<()> {
+    prctl(libc::PR_SET_SECUREBITS, c_ulong::from(bits.bits())).map(drop)
+}
+
+/// Gets the securebits flags for the calling thread.
+pub fn securebits() -> Result<SecureBits> {
+    prctl(libc::PR_GET_SECUREBITS, 0).map(|n| SecureBits::from_bits_truncate(n as u32))
+}
+
+/// A builder to apply a set of hardening attributes to the process.
+///
+/// This is created by [`harden()`] with the standard settings for a
+/// daemon: "no new privileges" is set, and the process is not
+/// dumpable. The securebits are left unchanged, unless specified.
+///
+/// ```no_run
+/// # use hinix::security::{self, SecureBits};
+/// security::harden()
+///     .securebits(SecureBits::NOROOT | SecureBits::NOROOT_LOCKED)
+///     .apply()
+///     .unwrap();
+/// ```
+///
+/// The securebits require privileges to set, so this should be done
+/// before dropping them.
+#[derive(Debug, Clone, Copy)]
+pub struct Hardening {
+    /// Whether to set "no new privileges"
+    no_new_privs: bool,
+    /// The dumpable setting, if it should be changed
+    dumpable: Option<bool>,
+    /// The securebits, if they should be changed
+    securebits: Option<SecureBits>,
+}
+
+/// Creates a builder to apply the standard hardening attributes to the
+/// process.
+pub fn harden() -> Hardening {
+    Hardening {
+        no_new_privs: true,
+        dumpable: Some(false),
+        securebits: None,
+    }
+}
+
+impl Hardening {
+    /// Sets whether to apply the "no new privileges" flag.
+    pub fn no_new_privs(mut self, on: bool) -> Self {
+        self.no_new_privs = on;
+        self
+    }
+
+    /// Sets whether the process should be dumpable.
+    pub fn dumpable(mut self, on: bool) -> Self {
+        self.dumpable = Some(on);
+        self
+    }
+
+    /// Sets the securebits to apply.
+    pub fn securebits(mut self, bits: SecureBits) -> Self {
+        self.securebits = Some(bits);
+        self
+    }
+
+    /// Applies the settings to the process, stopping at the first one
+    /// that fails.
+    ///
+    /// The "no new privileges" flag and securebits are per-thread, so
+    /// they're only applied to the calling thread and any it creates
+    /// afterward.
+    pub fn apply(&self) -> Result<()> {
+        if let Some(bits) = self.securebits {
+            set_securebits(bits)?;
+        }
+        if let Some(dumpable) = self.dumpable {
+            set_dumpable(dumpable)?;
+        }
+        if self.no_new_privs {
+            set_no_new_privs()?;
+        }
+        Ok(())
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::in_child;
+
+    #[test]
+    fn test_unknown_user() {
+        assert_eq!(
+            Error::EINVAL,
+            drop_privileges("no-such-user-hinix", None).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_drop_privileges_to() {
+        if !unistd::geteuid().is_root() {
+            return;
+        }
+        in_child(|| {
+            let (uid, gid) = (Uid::from_raw(65534), Gid::from_raw(65534));
+            drop_privileges_to(uid, gid).unwrap();
+
+            assert_eq!(uid, unistd::getuid());
+            assert_eq!(uid, unistd::geteuid());
+            assert_eq!(gid, unistd::getgid());
+            assert!(unistd::getgroups().unwrap().is_empty());
+
+            assert!(unistd::setuid(Uid::from_raw(0)).is_err());
+            assert!(unistd::seteuid(Uid::from_raw(0)).is_err());
+        });
+    }
+
+    #[test]
+    fn test_harden() {
+        in_child(|| {
+            harden().apply().unwrap();
+            assert!(no_new_privs().unwrap());
+            assert!(!is_dumpable().unwrap());
+
+            harden().dumpable(true).apply().unwrap();
+            assert!(is_dumpable().unwrap());
+        });
+    }
+
+    #[test]
+    fn test_securebits() {
+        in_child(|| {
+            let res = harden()
+                .no_new_privs(false)
+                .securebits(SecureBits::KEEP_CAPS)
+                .apply();
+
+            // This needs CAP_SETPCAP
+            match res {
+                Ok(()) => assert_eq!(SecureBits::KEEP_CAPS, securebits().unwrap()),
+                Err(err) => assert_eq!(Error::EPERM, err),
+            }
+        });
+    }
+}
@@ -0,0 +1,285 @@
+// hinix/src/seqpacket.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Unix-domain sequenced-packet sockets.
+//!
+//! A seqpacket socket is connection-oriented, like a stream socket, but
+//! preserves message boundaries, like a datagram socket. Each send is
+//! received whole by a single read, in order, so it's a simple way to
+//! pass discrete messages between two processes.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man7/unix.7.html>
+//!
+
+use crate::{error::ResultExt, fd, Error, Result};
+use nix::sys::socket::{self, sockopt, AddressFamily, MsgFlags, SockFlag, SockType, UnixAddr};
+use std::{
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    path::Path,
+};
+
+/// The default number of pending connections for a listener.
+const DEFAULT_BACKLOG: usize = 16;
+
+/// Creates a new seqpacket socket.
+fn new_socket() -> Result<OwnedFd> {
+    let fd = socket::socket(
+        AddressFamily::Unix,
+        SockType::SeqPacket,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// A connected seqpacket socket.
+#[derive(Debug)]
+pub struct SeqPacket(OwnedFd);
+
+impl SeqPacket {
+    /// Creates a pair of sockets that are connected to each other.
+    pub fn pair() -> Result<(Self, Self)> {
+        let (a, b) = socket::socketpair(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )?;
+        unsafe { Ok((Self::from_raw_fd(a), Self::from_raw_fd(b))) }
+    }
+
+    /// Connects to a listening socket at the path.
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let fd = new_socket()?;
+        socket::connect(fd.as_raw_fd(), &UnixAddr::new(path)?).op_on("connect", path.display())?;
+        Ok(Self(fd))
+    }
+
+    /// Sends a message.
+    ///
+    /// The message is sent whole, or not at all. This fails with
+    /// `EMSGSIZE` if it's larger than the socket's send buffer.
+    pub fn send(&self, buf: &[u8]) -> Result<usize> {
+        socket::send(self.0.as_raw_fd(), buf, MsgFlags::MSG_NOSIGNAL).op("send")
+    }
+
+    /// Receives the next message.
+    ///
+    /// If the buffer is too small, the rest of the message is discarded.
+    /// Returns zero when the peer has closed the connection.
+    pub fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        socket::recv(self.0.as_raw_fd(), buf, MsgFlags::empty()).op("recv")
+    }
+
+    /// Waits for the next message, and gets its size without removing it
+    /// from the socket.
+    pub fn peek_size(&self) -> Result<usize> {
+        // With MSG_TRUNC, Linux returns the full size of the message
+        socket::recv(
+            self.0.as_raw_fd(),
+            &mut [],
+            MsgFlags::MSG_PEEK | MsgFlags::MSG_TRUNC,
+        )
+        .op("recv")
+    }
+
+    /// Gets the size of the send buffer, which limits the size of a
+    /// message.
+    pub fn send_buffer_size(&self) -> Result<usize> {
+        socket::getsockopt(self.0.as_raw_fd(), sockopt::SndBuf).op("getsockopt")
+    }
+}
+
+impl AsFd for SeqPacket {
+    /// Gets the file handle for the socket.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for SeqPacket {
+    /// Gets the raw file handle for the socket.
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl FromRawFd for SeqPacket {
+    /// Takes ownership of a connected socket from its file handle, such
+    /// as one inherited from a parent process.
+    ///
+    /// # Safety
+    ///
+    /// The handle must be an open seqpacket socket that isn't owned
+    /// elsewhere.
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self(OwnedFd::from_raw_fd(fd))
+    }
+}
+
+impl From<SeqPacket> for OwnedFd {
+    fn from(sock: SeqPacket) -> Self {
+        sock.0
+    }
+}
+
+impl IntoRawFd for SeqPacket {
+    /// Gives up ownership of the file handle for the socket.
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl TryFrom<OwnedFd> for SeqPacket {
+    type Error = Error;
+
+    /// Takes ownership of a connected seqpacket socket, such as one passed
+    /// from another process.
+    ///
+    /// This fails with `EINVAL` if the handle is some other kind of socket,
+    /// or is listening for connections.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        fd::check_socket(fd.as_fd(), libc::AF_UNIX, SockType::SeqPacket)?;
+        if socket::getsockopt(fd.as_raw_fd(), sockopt::AcceptConn)? {
+            return Err(Error::EINVAL);
+        }
+        Ok(Self(fd))
+    }
+}
+
+/// A seqpacket socket that listens for connections.
+#[derive(Debug)]
+pub struct SeqPacketListener(OwnedFd);
+
+impl SeqPacketListener {
+    /// Creates a socket bound to the path, and listens on it.
+    ///
+    /// This fails with `EADDRINUSE` if the path already exists.
+    pub fn bind<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let fd = new_socket()?;
+        socket::bind(fd.as_raw_fd(), &UnixAddr::new(path)?).op_on("bind", path.display())?;
+        socket::listen(fd.as_raw_fd(), DEFAULT_BACKLOG)?;
+        Ok(Self(fd))
+    }
+
+    /// Waits for, and accepts, a connection.
+    pub fn accept(&self) -> Result<SeqPacket> {
+        let fd = socket::accept4(self.0.as_raw_fd(), SockFlag::SOCK_CLOEXEC)?;
+        Ok(unsafe { SeqPacket::from_raw_fd(fd) })
+    }
+}
+
+impl AsFd for SeqPacketListener {
+    /// Gets the file handle for the socket.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for SeqPacketListener {
+    /// Gets the raw file handle for the socket.
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for SeqPacketListener {
+    /// Gives up ownership of the file handle for the socket.
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl From<SeqPacketListener> for OwnedFd {
+    fn from(listener: SeqPacketListener) -> Self {
+        listener.0
+    }
+}
+
+impl TryFrom<OwnedFd> for SeqPacketListener {
+    type Error = Error;
+
+    /// Takes ownership of a listening seqpacket socket, such as one passed
+    /// in by systemd socket activation.
+    ///
+    /// This fails with `EINVAL` if the handle is some other kind of socket,
+    /// or isn't listening for connections.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        fd::check_socket(fd.as_fd(), libc::AF_UNIX, SockType::SeqPacket)?;
+        if !socket::getsockopt(fd.as_raw_fd(), sockopt::AcceptConn)? {
+            return Err(Error::EINVAL);
+        }
+        Ok(Self(fd))
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process, thread};
+
+    #[test]
+    fn test_pair() {
+        let (a, b) = SeqPacket::pair().unwrap();
+        a.send(b"hello").unwrap();
+        a.send(b"world!").unwrap();
+
+        // Messages keep their boundaries
+        assert_eq!(5, b.peek_size().unwrap());
+        let mut buf = [0u8; 32];
+        let n = b.recv(&mut buf).unwrap();
+        assert_eq!(b"hello", &buf[..n]);
+        let n = b.recv(&mut buf).unwrap();
+        assert_eq!(b"world!", &buf[..n]);
+
+        drop(a);
+        assert_eq!(0, b.recv(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn test_listener() {
+        let path = env::temp_dir().join(format!("hinix-seqpacket-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = SeqPacketListener::bind(&path).unwrap();
+
+        let cli_path = path.clone();
+        let th = thread::spawn(move || {
+            let sock = SeqPacket::connect(&cli_path).unwrap();
+            sock.send(b"ping").unwrap();
+        });
+
+        let sock = listener.accept().unwrap();
+        let mut buf = [0u8; 8];
+        let n = sock.recv(&mut buf).unwrap();
+        assert_eq!(b"ping", &buf[..n]);
+
+        th.join().unwrap();
+
+        // A listener isn't a connected socket, and vice versa
+        let fd = OwnedFd::from(listener);
+        let listener = SeqPacketListener::try_from(fd).unwrap();
+        let fd = unsafe { OwnedFd::from_raw_fd(listener.into_raw_fd()) };
+        assert_eq!(Error::EINVAL, SeqPacket::try_from(fd).unwrap_err());
+        assert_eq!(
+            Error::EINVAL,
+            SeqPacketListener::try_from(OwnedFd::from(sock)).unwrap_err()
+        );
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,414 @@
+// hinix/src/serial.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Serial ports.
+//!
+//! Configuration of serial tty devices, like /dev/ttyS0 or /dev/ttyUSB0,
+//! through the termios interface. The port is put into raw mode, so that
+//! data passes through unaltered, with the line settings (baud rate,
+//! data bits, parity, etc) given by a [`SerialConfig`].
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man3/termios.3.html>
+//!
+
+use crate::{error::ResultExt, Error, Result};
+use nix::{
+    fcntl::{self, OFlag},
+    sys::{
+        stat::Mode,
+        termios::{self, ControlFlags, InputFlags, SetArg, SpecialCharacterIndices},
+    },
+    unistd,
+};
+use std::{
+    io::{self, Read, Write},
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    path::Path,
+};
+
+/// The line speed of a serial port.
+pub use nix::sys::termios::BaudRate;
+
+/// The number of data bits in each character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataBits {
+    /// 5 bits per character
+    Five,
+    /// 6 bits per character
+    Six,
+    /// 7 bits per character
+    Seven,
+    /// 8 bits per character
+    Eight,
+}
+
+/// The parity checking mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    /// No parity bit
+    None,
+    /// Odd parity
+    Odd,
+    /// Even parity
+    Even,
+}
+
+/// The number of stop bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    /// One stop bit
+    One,
+    /// Two stop bits
+    Two,
+}
+
+/// The flow control mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowControl {
+    /// No flow control
+    None,
+    /// Software flow control with XON/XOFF characters
+    Software,
+    /// Hardware flow control with the RTS/CTS lines
+    Hardware,
+}
+
+/// The line settings for a serial port.
+///
+/// The default is the ubiquitous 9600 8N1, with no flow control, and
+/// reads that block until at least one byte is available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConfig {
+    /// The line speed
+    pub baud_rate: BaudRate,
+    /// The number of data bits per character
+    pub data_bits: DataBits,
+    /// The parity mode
+    pub parity: Parity,
+    /// The number of stop bits
+    pub stop_bits: StopBits,
+    /// The flow control mode
+    pub flow_control: FlowControl,
+    /// The minimum number of bytes for a read to return (VMIN)
+    pub vmin: u8,
+    /// The read timeout, in tenths of a second (VTIME)
+    pub vtime: u8,
+}
+
+impl SerialConfig {
+    /// Creates a configuration with the specified baud rate, and the
+    /// defaults for everything else.
+    pub fn new(baud_rate: BaudRate) -> Self {
+        Self {
+            baud_rate,
+            ..Self::default()
+        }
+    }
+
+    /// Sets the number of data bits.
+    pub fn data_bits(mut self, data_bits: DataBits) -> Self {
+        self.data_bits = data_bits;
+        self
+    }
+
+    /// Sets the parity mode.
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    /// Sets the number of stop bits.
+    pub fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    /// Sets the flow control mode.
+    pub fn flow_control(mut self, flow_control: FlowControl) -> Self {
+        self.flow_control = flow_control;
+        self
+    }
+
+    /// Sets the read conditions.
+    ///
+    /// `vmin` is the minimum number of bytes for a read to return, and
+    /// `vtime` is the timeout, in tenths of a second. See the termios man
+    /// page for the interaction between the two.
+    pub fn read_timing(mut self, vmin: u8, vtime: u8) -> Self {
+        self.vmin = vmin;
+        self.vtime = vtime;
+        self
+    }
+
+    /// Reads the current configuration of a tty device.
+    pub fn from_tty<T: AsFd>(tty: &T) -> Result<Self> {
+        let tio = termios::tcgetattr(tty.as_fd().as_raw_fd())?;
+        let cflags = tio.control_flags;
+
+        let data_bits = match cflags & ControlFlags::CSIZE {
+            ControlFlags::CS5 => DataBits::Five,
+            ControlFlags::CS6 => DataBits::Six,
+            ControlFlags::CS7 => DataBits::Seven,
+            _ => DataBits::Eight,
+        };
+
+        let parity = if !cflags.contains(ControlFlags::PARENB) {
+            Parity::None
+        }
+        else if cflags.contains(ControlFlags::PARODD) {
+            Parity::Odd
+        }
+        else {
+            Parity::Even
+        };
+
+        let stop_bits = if cflags.contains(ControlFlags::CSTOPB) {
+            StopBits::Two
+        }
+        else {
+            StopBits::One
+        };
+
+        let flow_control = if cflags.contains(ControlFlags::CRTSCTS) {
+            FlowControl::Hardware
+        }
+        else if tio.input_flags.contains(InputFlags::IXON) {
+            FlowControl::Software
+        }
+        else {
+            FlowControl::None
+        };
+
+        Ok(Self {
+            baud_rate: termios::cfgetospeed(&tio),
+            data_bits,
+            parity,
+            stop_bits,
+            flow_control,
+            vmin: tio.control_chars[SpecialCharacterIndices::VMIN as usize],
+            vtime: tio.control_chars[SpecialCharacterIndices::VTIME as usize],
+        })
+    }
+
+    /// Applies the configuration to a tty device.
+    ///
+    /// This puts the device into raw mode, then sets the line parameters.
+    /// The changes take effect immediately.
+    pub fn apply<T: AsFd>(&self, tty: &T) -> Result<()> {
+        let fd = tty.as_fd().as_raw_fd();
+        let mut tio = termios::tcgetattr(fd)?;
+
+        termios::cfmakeraw(&mut tio);
+        termios::cfsetspeed(&mut tio, self.baud_rate)?;
+
+        let cflags = &mut tio.control_flags;
+        cflags.insert(ControlFlags::CLOCAL | ControlFlags::CREAD);
+
+        cflags.remove(ControlFlags::CSIZE);
+        cflags.insert(match self.data_bits {
+            DataBits::Five => ControlFlags::CS5,
+            DataBits::Six => ControlFlags::CS6,
+            DataBits::Seven => ControlFlags::CS7,
+            DataBits::Eight => ControlFlags::CS8,
+        });
+
+        cflags.remove(ControlFlags::PARENB | ControlFlags::PARODD);
+        tio.input_flags.remove(InputFlags::INPCK);
+        match self.parity {
+            Parity::None => (),
+            Parity::Odd => {
+                cflags.insert(ControlFlags::PARENB | ControlFlags::PARODD);
+                tio.input_flags.insert(InputFlags::INPCK);
+            }
+            Parity::Even => {
+                cflags.insert(ControlFlags::PARENB);
+                tio.input_flags.insert(InputFlags::INPCK);
+            }
+        }
+
+        cflags.set(ControlFlags::CSTOPB, self.stop_bits == StopBits::Two);
+
+        cflags.set(
+            ControlFlags::CRTSCTS,
+            self.flow_control == FlowControl::Hardware,
+        );
+        tio.input_flags.set(
+            InputFlags::IXON | InputFlags::IXOFF,
+            self.flow_control == FlowControl::Software,
+        );
+
+        tio.control_chars[SpecialCharacterIndices::VMIN as usize] = self.vmin;
+        tio.control_chars[SpecialCharacterIndices::VTIME as usize] = self.vtime;
+
+        termios::tcsetattr(fd, SetArg::TCSANOW, &tio).op("tcsetattr")
+    }
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self {
+            baud_rate: BaudRate::B9600,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            vmin: 1,
+            vtime: 0,
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// An open serial port.
+#[derive(Debug)]
+pub struct SerialPort(OwnedFd);
+
+impl SerialPort {
+    /// Opens a serial device and applies the configuration to it.
+    ///
+    /// The device is opened for reading and writing, and does not become
+    /// the controlling terminal of the process.
+    pub fn open<P: AsRef<Path>>(path: P, config: &SerialConfig) -> Result<Self> {
+        let flags = OFlag::O_RDWR | OFlag::O_NOCTTY | OFlag::O_CLOEXEC;
+        let path = path.as_ref();
+        let fd = fcntl::open(path, flags, Mode::empty()).op_on("open", path.display())?;
+        let port = Self(unsafe { OwnedFd::from_raw_fd(fd) });
+        config.apply(&port)?;
+        Ok(port)
+    }
+
+    /// Gets the current configuration of the port.
+    pub fn config(&self) -> Result<SerialConfig> {
+        SerialConfig::from_tty(self)
+    }
+
+    /// Changes the configuration of the port.
+    pub fn set_config(&self, config: &SerialConfig) -> Result<()> {
+        config.apply(self)
+    }
+
+    /// Waits until all the output has been transmitted.
+    pub fn drain(&self) -> Result<()> {
+        termios::tcdrain(self.as_raw_fd()).op("tcdrain")
+    }
+
+    /// Discards any data that was received but not yet read, and any
+    /// data that was written but not yet transmitted.
+    pub fn discard(&self) -> Result<()> {
+        termios::tcflush(self.as_raw_fd(), termios::FlushArg::TCIOFLUSH).op("tcflush")
+    }
+}
+
+impl Read for SerialPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(unistd::read(self.as_raw_fd(), buf)?)
+    }
+}
+
+impl Write for SerialPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(unistd::write(self.as_raw_fd(), buf)?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(self.drain()?)
+    }
+}
+
+impl AsFd for SerialPort {
+    /// Gets the file handle for the serial port.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for SerialPort {
+    /// Gets the raw file handle for the serial port.
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for SerialPort {
+    /// Gives up ownership of the file handle for the serial port.
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl From<SerialPort> for OwnedFd {
+    fn from(port: SerialPort) -> Self {
+        port.0
+    }
+}
+
+impl TryFrom<OwnedFd> for SerialPort {
+    type Error = Error;
+
+    /// Takes ownership of an open serial port.
+    ///
+    /// This fails with `ENOTTY` if the handle isn't a terminal.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        if !unistd::isatty(fd.as_raw_fd())? {
+            return Err(Error::ENOTTY);
+        }
+        Ok(Self(fd))
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(all(test, feature = "pty"))]
+mod tests {
+    use super::*;
+    use crate::pty::Pty;
+
+    // A pty slave makes a reasonable stand-in for a serial device, as it
+    // keeps most of the line settings even though it doesn't use them.
+    // Linux does force a pty to 8 data bits with no parity, though.
+
+    #[test]
+    fn test_apply() {
+        let pty = Pty::open().unwrap();
+
+        let config = SerialConfig::new(BaudRate::B115200)
+            .stop_bits(StopBits::Two)
+            .flow_control(FlowControl::Software)
+            .read_timing(0, 10);
+
+        config.apply(pty.slave()).unwrap();
+        assert_eq!(config, SerialConfig::from_tty(pty.slave()).unwrap());
+
+        let config = SerialConfig::default();
+        config.apply(pty.slave()).unwrap();
+        assert_eq!(config, SerialConfig::from_tty(pty.slave()).unwrap());
+    }
+
+    #[test]
+    fn test_open() {
+        let mut pty = Pty::open().unwrap();
+        let path = pty.slave().name().unwrap();
+
+        let config = SerialConfig::new(BaudRate::B57600).flow_control(FlowControl::Hardware);
+        let mut port = SerialPort::open(&path, &config).unwrap();
+        assert_eq!(config, port.config().unwrap());
+
+        // Raw mode, so no waiting for a newline
+        pty.master_mut().write_all(b"abc").unwrap();
+        let mut buf = [0u8; 8];
+        let n = port.read(&mut buf).unwrap();
+        assert_eq!(b"abc", &buf[..n]);
+    }
+}
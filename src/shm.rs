@@ -0,0 +1,400 @@
+// hinix/src/shm.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Anonymous shared memory that can be passed between processes.
+//!
+//! A [`SharedMemory`] region is a file handle for a block of memory that
+//! isn't in any file system. The handle can be inherited by a child, or
+//! sent to another process over a Unix socket, and each process that has
+//! it can map the memory into its own address space.
+//!
+//! On Linux, the region is a memfd. Android apps have traditionally used
+//! ashmem for this, since memfd is missing from older kernels, so there
+//! the region is made by the first of these that works:
+//!
+//! * memfd_create()
+//! * ASharedMemory_create() from libandroid, on API level 26 and later
+//! * The /dev/ashmem device
+//!
+//! The size of a region is fixed when it's created. A memfd is sealed
+//! against resizing, and ashmem doesn't allow it once the region is
+//! mapped, so unlike a file, a map of the region can't be truncated out
+//! from under the application. But the whole point of the region is
+//! that other maps of it, in this process or another one, can change
+//! the memory at any time, so mapping it is still `unsafe`. The
+//! application has to coordinate the access, like with atomics in the
+//! memory, or by only using raw pointers to it.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man2/memfd_create.2.html>
+//! <https://developer.android.com/ndk/reference/group/memory>
+//!
+
+use crate::{
+    error::ResultExt,
+    mmap::{Mmap, MmapMut},
+    Error, Result,
+};
+use nix::{errno::Errno, sys::stat, unistd};
+use std::{
+    ffi::{CStr, CString},
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+};
+
+/// Creates a memfd of the requested size, which can be sealed.
+fn memfd(name: &CStr, size: usize) -> Result<OwnedFd> {
+    let flags = libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING;
+    let fd =
+        Errno::result(unsafe { libc::memfd_create(name.as_ptr(), flags) }).op("memfd_create")?;
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let len = libc::off_t::try_from(size).map_err(|_| Error::EFBIG)?;
+    unistd::ftruncate(fd.as_raw_fd(), len).op("ftruncate")?;
+    Ok(fd)
+}
+
+/// Seals a memfd so that its size can't change.
+///
+/// This is fine if it was already sealed that way, but fails with
+/// `EINVAL` if the handle isn't a memfd, or `EPERM` if its size could
+/// still change and it can't be sealed.
+fn seal_size(fd: &OwnedFd) -> Result<()> {
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW;
+    let ret = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_ADD_SEALS, seals) };
+    match Errno::result(ret) {
+        Ok(_) => Ok(()),
+        Err(Errno::EPERM) => {
+            // Already sealed, which is fine if it can't shrink.
+            let ret = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GET_SEALS) };
+            match Errno::result(ret).op("fcntl")? & libc::F_SEAL_SHRINK {
+                0 => Err(Error::EPERM),
+                _ => Ok(()),
+            }
+        }
+        Err(err) => Err(err).op("fcntl"),
+    }
+}
+
+/// A region of shared memory.
+#[derive(Debug)]
+pub struct SharedMemory {
+    /// The handle to the memory
+    fd: OwnedFd,
+    /// The size of the region, in bytes
+    size: usize,
+    /// Whether the region is ashmem, rather than a memfd
+    #[cfg(target_os = "android")]
+    ashmem: bool,
+}
+
+impl SharedMemory {
+    /// Creates a new region of shared memory, initialized to zero.
+    ///
+    /// The name is only used for debugging, like in /proc/self/maps,
+    /// and doesn't need to be unique. This fails with `EINVAL` if the
+    /// size is zero.
+    pub fn create(name: &str, size: usize) -> Result<Self> {
+        if size == 0 {
+            return Err(Error::EINVAL);
+        }
+        let name = CString::new(name).map_err(|_| Error::EINVAL)?;
+
+        #[cfg(target_os = "android")]
+        let fd = match memfd(&name, size) {
+            // memfd is missing from older kernels, and may be denied to apps
+            Err(err) if err == Error::ENOSYS || err == Error::EPERM || err == Error::EACCES => {
+                ashmem::create(&name, size)?
+            }
+            res => res?,
+        };
+
+        #[cfg(not(target_os = "android"))]
+        let fd = memfd(&name, size)?;
+
+        Self::try_from(fd)
+    }
+
+    /// Gets the size of the region, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Maps the whole region into memory for reading and writing.
+    ///
+    /// This fails with `EPERM` if the region was made read-only.
+    ///
+    /// # Safety
+    ///
+    /// The map dereferences to a byte slice, which Rust assumes can't
+    /// change while it's borrowed, and that a mutable one isn't aliased.
+    /// Any other map of the region, from this process or another one,
+    /// breaks that if it's used at the same time. The caller must make
+    /// sure the memory isn't read while another map writes to it, or
+    /// written while another map uses it, or else only access it through
+    /// atomics or raw pointers.
+    pub unsafe fn map(&self) -> Result<MmapMut> {
+        // The size of the region is fixed, so it can't be truncated.
+        MmapMut::map_range(&self.fd, 0, self.size)
+    }
+
+    /// Maps the whole region into memory, read-only.
+    ///
+    /// # Safety
+    ///
+    /// The same as for [`map()`](Self::map). The memory can change
+    /// through any writable map of the region.
+    pub unsafe fn map_read_only(&self) -> Result<Mmap> {
+        Mmap::map_range(&self.fd, 0, self.size)
+    }
+
+    /// Prevents the region from being mapped for writing from now on,
+    /// through any handle to it, in any process.
+    ///
+    /// This is typically done before passing the region to another
+    /// process that should only read it. Maps that are already writable
+    /// are not affected. It can't be undone.
+    ///
+    /// A memfd requires Linux 5.1 or later for this, and fails with
+    /// `EINVAL` on older kernels.
+    pub fn set_read_only(&self) -> Result<()> {
+        #[cfg(target_os = "android")]
+        if self.ashmem {
+            return ashmem::set_read_only(&self.fd);
+        }
+
+        let seal = libc::F_SEAL_FUTURE_WRITE;
+        let ret = unsafe { libc::fcntl(self.fd.as_raw_fd(), libc::F_ADD_SEALS, seal) };
+        Errno::result(ret).map(drop).op("fcntl")
+    }
+}
+
+impl AsFd for SharedMemory {
+    /// Gets the file handle for the region.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for SharedMemory {
+    /// Gets the raw file handle for the region.
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for SharedMemory {
+    /// Gives up ownership of the handle for the region.
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+impl From<SharedMemory> for OwnedFd {
+    fn from(shm: SharedMemory) -> Self {
+        shm.fd
+    }
+}
+
+impl TryFrom<OwnedFd> for SharedMemory {
+    type Error = Error;
+
+    /// Takes ownership of a region of shared memory, such as one passed
+    /// from another process, reading its size from the handle.
+    ///
+    /// A memfd is sealed against resizing, if it wasn't already. This
+    /// fails with `EINVAL` if the handle isn't shared memory, or is
+    /// empty, or `EPERM` if its size can't be fixed.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        #[cfg(target_os = "android")]
+        if let Some(size) = ashmem::size(&fd) {
+            if size == 0 {
+                return Err(Error::EINVAL);
+            }
+            return Ok(Self {
+                fd,
+                size,
+                ashmem: true,
+            });
+        }
+
+        seal_size(&fd)?;
+        let st = stat::fstat(fd.as_raw_fd()).op("fstat")?;
+        let size = match usize::try_from(st.st_size) {
+            Ok(0) => return Err(Error::EINVAL),
+            Ok(size) => size,
+            Err(_) => return Err(Error::EFBIG),
+        };
+        Ok(Self {
+            fd,
+            size,
+            #[cfg(target_os = "android")]
+            ashmem: false,
+        })
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// The Android ashmem device, and the libandroid API on top of it.
+#[cfg(target_os = "android")]
+mod ashmem {
+    use crate::{error::ResultExt, Result};
+    use nix::{
+        errno::Errno,
+        fcntl::{self, OFlag},
+        request_code_none, request_code_write,
+        sys::stat::Mode,
+    };
+    use std::{
+        ffi::CStr,
+        mem,
+        os::{
+            raw::{c_char, c_int, c_ulong, c_void},
+            unix::io::{AsRawFd, FromRawFd, OwnedFd},
+        },
+        sync::OnceLock,
+    };
+
+    /// The ashmem device
+    const DEVICE: &str = "/dev/ashmem";
+
+    // The ioctl requests from <linux/ashmem.h>
+    const ASHMEM_NAME_LEN: usize = 256;
+    const ASHMEM_SET_NAME: u32 = request_code_write!(0x77, 1, ASHMEM_NAME_LEN) as u32;
+    const ASHMEM_SET_SIZE: u32 = request_code_write!(0x77, 3, size_of::<usize>()) as u32;
+    const ASHMEM_GET_SIZE: u32 = request_code_none!(0x77, 4) as u32;
+    const ASHMEM_SET_PROT_MASK: u32 = request_code_write!(0x77, 5, size_of::<c_ulong>()) as u32;
+
+    // The library and function names, as C strings
+    const LIBANDROID: &[u8] = b"libandroid.so\0";
+    const CREATE_FN: &[u8] = b"ASharedMemory_create\0";
+
+    /// The signature of ASharedMemory_create()
+    type CreateFn = unsafe extern "C" fn(*const c_char, usize) -> c_int;
+
+    /// Looks up ASharedMemory_create() in libandroid, which only has it
+    /// on API level 26 and later. The library is left loaded.
+    fn create_fn() -> Option<CreateFn> {
+        static CREATE: OnceLock<Option<CreateFn>> = OnceLock::new();
+        *CREATE.get_or_init(|| unsafe {
+            let lib = libc::dlopen(LIBANDROID.as_ptr() as _, libc::RTLD_NOW);
+            if lib.is_null() {
+                return None;
+            }
+            let sym = libc::dlsym(lib, CREATE_FN.as_ptr() as _);
+            match sym.is_null() {
+                true => None,
+                false => Some(mem::transmute::<*mut c_void, CreateFn>(sym)),
+            }
+        })
+    }
+
+    /// Creates an ashmem region, through libandroid if it's available,
+    /// otherwise with the device.
+    pub fn create(name: &CStr, size: usize) -> Result<OwnedFd> {
+        if let Some(create) = create_fn() {
+            let fd = unsafe { create(name.as_ptr(), size) };
+            if fd >= 0 {
+                return Ok(unsafe { OwnedFd::from_raw_fd(fd) });
+            }
+        }
+
+        let fd = fcntl::open(DEVICE, OFlag::O_RDWR | OFlag::O_CLOEXEC, Mode::empty())
+            .op_on("open", DEVICE)?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // The kernel copies a fixed-size buffer, and truncates the name
+        let mut buf = [0u8; ASHMEM_NAME_LEN];
+        let name = name.to_bytes();
+        let n = name.len().min(ASHMEM_NAME_LEN - 1);
+        buf[..n].copy_from_slice(&name[..n]);
+
+        let ret = unsafe { libc::ioctl(fd.as_raw_fd(), ASHMEM_SET_NAME as _, buf.as_ptr()) };
+        Errno::result(ret).op("ioctl")?;
+
+        // The size is passed by value, despite the request code
+        let ret = unsafe { libc::ioctl(fd.as_raw_fd(), ASHMEM_SET_SIZE as _, size) };
+        Errno::result(ret).op("ioctl")?;
+        Ok(fd)
+    }
+
+    /// Gets the size of the region, or `None` if the handle isn't ashmem.
+    pub fn size(fd: &OwnedFd) -> Option<usize> {
+        let ret = unsafe { libc::ioctl(fd.as_raw_fd(), ASHMEM_GET_SIZE as _) };
+        usize::try_from(ret).ok()
+    }
+
+    /// Restricts new maps of the region to read-only.
+    pub fn set_read_only(fd: &OwnedFd) -> Result<()> {
+        let prot = libc::PROT_READ as c_ulong;
+        let ret = unsafe { libc::ioctl(fd.as_raw_fd(), ASHMEM_SET_PROT_MASK as _, prot) };
+        Errno::result(ret).map(drop).op("ioctl")
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create() {
+        assert_eq!(
+            Error::EINVAL,
+            SharedMemory::create("hinix-test", 0).unwrap_err()
+        );
+
+        let shm = SharedMemory::create("hinix-test", 4096).unwrap();
+        assert_eq!(4096, shm.size());
+
+        let mut map = unsafe { shm.map() }.unwrap();
+        assert_eq!(4096, map.len());
+        assert!(map.iter().all(|&b| b == 0));
+        map[..5].copy_from_slice(b"hello");
+
+        // A second map sees the same memory. The first one isn't used
+        // after this.
+        let map2 = unsafe { shm.map_read_only() }.unwrap();
+        assert_eq!(b"hello", &map2[..5]);
+    }
+
+    #[test]
+    fn test_from_fd() {
+        let shm = SharedMemory::create("hinix-test", 100).unwrap();
+        unsafe { shm.map() }.unwrap()[0] = 42;
+
+        let fd = shm.as_fd().try_clone_to_owned().unwrap();
+        let shm2 = SharedMemory::try_from(fd).unwrap();
+        assert_eq!(100, shm2.size());
+        assert_eq!(42, unsafe { shm2.map_read_only() }.unwrap()[0]);
+
+        // Something that isn't shared memory
+        let (rd, _wr) = unistd::pipe().unwrap();
+        let fd = unsafe { OwnedFd::from_raw_fd(rd) };
+        assert_eq!(Error::EINVAL, SharedMemory::try_from(fd).unwrap_err());
+    }
+
+    #[test]
+    fn test_read_only() {
+        let shm = SharedMemory::create("hinix-test", 100).unwrap();
+        let mut map = unsafe { shm.map() }.unwrap();
+
+        shm.set_read_only().unwrap();
+        assert_eq!(Error::EPERM, unsafe { shm.map() }.unwrap_err());
+
+        // The existing map is still writable
+        map[0] = 7;
+        assert_eq!(7, unsafe { shm.map_read_only() }.unwrap()[0]);
+    }
+}
@@ -0,0 +1,383 @@
+// hinix/src/shutdown.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! A signal for an application to shut down gracefully.
+//!
+//! A [`ShutdownSignal`] is triggered when the process receives one of the
+//! usual termination signals, `SIGINT` or `SIGTERM`, or when the
+//! application calls [`ShutdownSignal::trigger()`] itself, like from a
+//! command to exit. A daemon can then block on it in the main thread,
+//! poll it along with its other handles, or await it in an async task.
+//!
+//! ```no_run
+//! use hinix::shutdown::ShutdownSignal;
+//! use std::thread;
+//!
+//! let shutdown = ShutdownSignal::new()?;
+//!
+//! let worker = thread::spawn({
+//!     let shutdown = shutdown.clone();
+//!     move || {
+//!         while !shutdown.is_triggered() {
+//!             // ...do some work...
+//!         }
+//!     }
+//! });
+//!
+//! shutdown.wait()?;
+//! println!("Exiting on {:?}", shutdown.signal());
+//! worker.join().unwrap();
+//! # Ok::<(), hinix::Error>(())
+//! ```
+//!
+//! The signals are caught with a handler that writes to an eventfd, in
+//! the style of the self-pipe trick, rather than through a signalfd. So
+//! they don't have to be blocked in every thread of the process, and no
+//! other thread will be killed by them in the meantime. The handlers are
+//! installed while any signal object is using them, and the previous
+//! dispositions are restored when the last one is dropped.
+//!
+//! The handle is readable once the signal is triggered, and stays that
+//! way, so it can be added to a poll set, or to an async reactor.
+//!
+
+use crate::{
+    error::{errno_location, ResultExt},
+    timeout::{self, Timeout},
+    Error, Result,
+};
+use nix::{
+    poll::PollFlags,
+    sys::{
+        eventfd::{self, EfdFlags},
+        signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal},
+    },
+};
+use std::{
+    hint,
+    os::{
+        raw::{c_int, c_void},
+        unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    },
+    sync::{
+        atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// The number of signal objects that can exist at the same time.
+const MAX_SLOTS: usize = 16;
+
+/// The value the signal handler adds to an eventfd.
+const ONE: [u8; 8] = 1u64.to_ne_bytes();
+
+/// The state of a signal object that the signal handler can reach.
+///
+/// Everything here is atomic, since the handler can't take a lock.
+#[derive(Debug)]
+struct Slot {
+    /// The eventfd to write, or -1 if the slot is free
+    fd: AtomicI32,
+    /// The signals that trigger the object, as a bit for each number
+    mask: AtomicU64,
+    /// The first signal that was caught, or zero
+    caught: AtomicI32,
+    /// The number of handlers using the eventfd right now
+    busy: AtomicUsize,
+}
+
+// A `const` item is the only way to repeat an atomic in a static array.
+#[allow(clippy::declare_interior_mutable_const)]
+const FREE_SLOT: Slot = Slot {
+    fd: AtomicI32::new(-1),
+    mask: AtomicU64::new(0),
+    caught: AtomicI32::new(0),
+    busy: AtomicUsize::new(0),
+};
+
+/// The slots for the signal objects.
+static SLOTS: [Slot; MAX_SLOTS] = [FREE_SLOT; MAX_SLOTS];
+
+/// The handlers that are installed, as the signal, the number of objects
+/// using it, and the action it replaced.
+static HANDLERS: Mutex<Vec<(Signal, usize, SigAction)>> = Mutex::new(Vec::new());
+
+/// Gets the bit for a signal in a slot's mask.
+fn signal_bit(sig: c_int) -> u64 {
+    match sig {
+        1..=63 => 1 << sig,
+        _ => 0,
+    }
+}
+
+/// The signal handler.
+///
+/// This only uses atomics and `write()`, which are safe to call from a
+/// handler, and leaves errno as it found it.
+extern "C" fn on_signal(sig: c_int) {
+    let errno = unsafe { *errno_location() };
+    for slot in &SLOTS {
+        // Marks the slot busy before looking at it, so that a release
+        // can't close the pipe between the checks and the write.
+        slot.busy.fetch_add(1, Ordering::SeqCst);
+        let fd = slot.fd.load(Ordering::SeqCst);
+        if slot.mask.load(Ordering::SeqCst) & signal_bit(sig) != 0 && fd >= 0 {
+            let _ = slot
+                .caught
+                .compare_exchange(0, sig, Ordering::SeqCst, Ordering::SeqCst);
+            unsafe { libc::write(fd, ONE.as_ptr() as *const c_void, ONE.len()) };
+        }
+        slot.busy.fetch_sub(1, Ordering::SeqCst);
+    }
+    unsafe { *errno_location() = errno };
+}
+
+/// Installs the handler for the signals, or adds a user to the ones that
+/// are already installed.
+fn install(signals: &[Signal]) -> Result<()> {
+    let mut handlers = HANDLERS.lock().unwrap();
+    let action = SigAction::new(
+        SigHandler::Handler(on_signal),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for (i, &sig) in signals.iter().enumerate() {
+        if let Some(entry) = handlers.iter_mut().find(|entry| entry.0 == sig) {
+            entry.1 += 1;
+            continue;
+        }
+        match unsafe { signal::sigaction(sig, &action) }.op("sigaction") {
+            Ok(prev) => handlers.push((sig, 1, prev)),
+            Err(err) => {
+                drop(handlers);
+                uninstall(&signals[..i]);
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Removes a user from the handlers for the signals, restoring the
+/// previous action of any that are no longer used.
+fn uninstall(signals: &[Signal]) {
+    let mut handlers = HANDLERS.lock().unwrap();
+    for sig in signals {
+        if let Some(i) = handlers.iter().position(|entry| entry.0 == *sig) {
+            handlers[i].1 -= 1;
+            if handlers[i].1 == 0 {
+                let (sig, _, prev) = handlers.swap_remove(i);
+                let _ = unsafe { signal::sigaction(sig, &prev) };
+            }
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// The state shared by the clones of a signal object.
+#[derive(Debug)]
+struct Inner {
+    /// The eventfd that's readable once the object is triggered
+    fd: OwnedFd,
+    /// The index of the object's slot
+    slot: usize,
+    /// The signals that trigger the object
+    signals: Vec<Signal>,
+    /// Whether the object was triggered by the application
+    triggered: AtomicBool,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        uninstall(&self.signals);
+
+        // Wait out any handler that's already writing to the eventfd,
+        // before it gets closed.
+        let slot = &SLOTS[self.slot];
+        slot.mask.store(0, Ordering::SeqCst);
+        slot.fd.store(-1, Ordering::SeqCst);
+        while slot.busy.load(Ordering::SeqCst) != 0 {
+            hint::spin_loop();
+        }
+    }
+}
+
+/// A signal for an application to shut down.
+///
+/// This is triggered by any of a set of signals, or by a call to
+/// [`trigger()`](Self::trigger), and stays triggered once it is. Clones
+/// of the object share the same state, so one can be given to each
+/// thread or task that needs to know when to stop.
+#[derive(Debug, Clone)]
+pub struct ShutdownSignal(Arc<Inner>);
+
+impl ShutdownSignal {
+    /// Creates a signal object that's triggered by `SIGINT` or `SIGTERM`.
+    pub fn new() -> Result<Self> {
+        Self::with_signals(&[Signal::SIGINT, Signal::SIGTERM])
+    }
+
+    /// Creates a signal object that's triggered by any of the signals.
+    ///
+    /// With no signals, it's only triggered by the application. Up to 16
+    /// objects can exist at the same time. This fails with `EBUSY` if
+    /// there are already that many, or with `EINVAL` for a signal that
+    /// can't be caught, like `SIGKILL`.
+    pub fn with_signals(signals: &[Signal]) -> Result<Self> {
+        if signals
+            .iter()
+            .any(|sig| matches!(sig, Signal::SIGKILL | Signal::SIGSTOP))
+        {
+            return Err(Error::EINVAL);
+        }
+
+        let mut sigs = Vec::with_capacity(signals.len());
+        for sig in signals {
+            if !sigs.contains(sig) {
+                sigs.push(*sig);
+            }
+        }
+        let signals = sigs;
+
+        let flags = EfdFlags::EFD_CLOEXEC | EfdFlags::EFD_NONBLOCK;
+        let fd = eventfd::eventfd(0, flags).op("eventfd")?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        install(&signals)?;
+
+        // Claim a free slot, and fill it in before it's seen by the handler
+        let slot = SLOTS.iter().position(|slot| {
+            slot.fd
+                .compare_exchange(-1, -2, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        });
+        let Some(slot) = slot
+        else {
+            uninstall(&signals);
+            return Err(Error::EBUSY);
+        };
+
+        let mask = signals
+            .iter()
+            .fold(0, |mask, sig| mask | signal_bit(*sig as c_int));
+        SLOTS[slot].caught.store(0, Ordering::SeqCst);
+        SLOTS[slot].mask.store(mask, Ordering::SeqCst);
+        SLOTS[slot].fd.store(fd.as_raw_fd(), Ordering::SeqCst);
+
+        Ok(Self(Arc::new(Inner {
+            fd,
+            slot,
+            signals,
+            triggered: AtomicBool::new(false),
+        })))
+    }
+
+    /// Triggers the signal object, as if one of its signals was received.
+    ///
+    /// This can be called from any thread, any number of times.
+    pub fn trigger(&self) {
+        if !self.0.triggered.swap(true, Ordering::AcqRel) {
+            // The handle is never read, so it stays readable
+            let _ = nix::unistd::write(self.0.fd.as_raw_fd(), &ONE);
+        }
+    }
+
+    /// Determines if the signal object was triggered, by a signal or by
+    /// the application.
+    pub fn is_triggered(&self) -> bool {
+        self.0.triggered.load(Ordering::Acquire) || self.signal().is_some()
+    }
+
+    /// Gets the signal that triggered the object.
+    ///
+    /// This is the first one received, or `None` if no signal was
+    /// received, even if the object was triggered by the application.
+    pub fn signal(&self) -> Option<Signal> {
+        let sig = SLOTS[self.0.slot].caught.load(Ordering::SeqCst);
+        Signal::try_from(sig).ok()
+    }
+
+    /// Blocks until the signal object is triggered.
+    pub fn wait(&self) -> Result<()> {
+        while !self.wait_timeout(Timeout::None)? {}
+        Ok(())
+    }
+
+    /// Waits up to the timeout for the signal object to be triggered.
+    ///
+    /// Returns `false` if the timeout expired first.
+    pub fn wait_timeout<T: Into<Timeout>>(&self, timeout: T) -> Result<bool> {
+        timeout::poll_fd(self.as_fd(), PollFlags::POLLIN, timeout)
+    }
+}
+
+impl AsFd for ShutdownSignal {
+    /// Gets the handle that becomes readable when the signal object is
+    /// triggered.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.fd.as_fd()
+    }
+}
+
+impl AsRawFd for ShutdownSignal {
+    /// Gets the raw handle that becomes readable when the signal object
+    /// is triggered.
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.fd.as_raw_fd()
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_trigger() {
+        let shutdown = ShutdownSignal::with_signals(&[]).unwrap();
+        assert!(!shutdown.is_triggered());
+        assert!(!shutdown.wait_timeout(Duration::from_millis(1)).unwrap());
+
+        let th = thread::spawn({
+            let shutdown = shutdown.clone();
+            move || shutdown.wait()
+        });
+        thread::sleep(Duration::from_millis(10));
+        shutdown.trigger();
+        th.join().unwrap().unwrap();
+
+        assert!(shutdown.is_triggered());
+        assert!(shutdown.wait_timeout(Duration::ZERO).unwrap());
+        assert_eq!(None, shutdown.signal());
+    }
+
+    #[test]
+    fn test_signal() {
+        let shutdown = ShutdownSignal::with_signals(&[Signal::SIGHUP]).unwrap();
+        let other = ShutdownSignal::with_signals(&[Signal::SIGHUP]).unwrap();
+        drop(other);
+
+        // The handler is still installed for the remaining object
+        signal::raise(Signal::SIGHUP).unwrap();
+        shutdown.wait().unwrap();
+        assert!(shutdown.is_triggered());
+        assert_eq!(Some(Signal::SIGHUP), shutdown.signal());
+
+        assert_eq!(
+            Error::EINVAL,
+            ShutdownSignal::with_signals(&[Signal::SIGKILL]).unwrap_err()
+        );
+    }
+}
@@ -0,0 +1,200 @@
+// hinix/src/signalfd.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Receiving signals through a file handle.
+//!
+//! A signalfd turns asynchronous signals into data that can be read from
+//! a handle, and waited on along with other handles in a poll/epoll or
+//! select call. This avoids all the restrictions of signal handlers.
+//!
+//! The signals must be blocked so that they aren't delivered in the
+//! normal way. [`SignalFd::new()`] blocks them for the calling thread,
+//! but they should also be blocked in every other thread, which is
+//! easiest to do in the main thread before any others are started.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man2/signalfd.2.html>
+//!
+
+use crate::{
+    error::ResultExt,
+    fd,
+    timeout::{self, Timeout},
+    Error, Result,
+};
+use nix::{
+    errno::Errno,
+    poll::PollFlags,
+    sys::signal::{SigSet, Signal},
+    unistd::{self, Pid, Uid},
+};
+use std::{
+    mem::{self, size_of},
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+};
+
+/// Information about a signal that was received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SigInfo {
+    /// The signal
+    pub signal: Signal,
+    /// The process that sent the signal, if any
+    pub pid: Pid,
+    /// The real user ID of the sender
+    pub uid: Uid,
+    /// The reason the signal was sent (the si_code)
+    pub code: i32,
+    /// The exit status or signal, for SIGCHLD
+    pub status: i32,
+}
+
+impl SigInfo {
+    fn from_raw(info: &libc::signalfd_siginfo) -> Result<Self> {
+        Ok(Self {
+            signal: Signal::try_from(info.ssi_signo as i32)?,
+            pid: Pid::from_raw(info.ssi_pid as libc::pid_t),
+            uid: Uid::from_raw(info.ssi_uid),
+            code: info.ssi_code,
+            status: info.ssi_status,
+        })
+    }
+}
+
+/// A handle that receives signals.
+#[derive(Debug)]
+pub struct SignalFd(OwnedFd);
+
+impl SignalFd {
+    /// Creates a handle to receive the set of signals, and blocks them in
+    /// the calling thread.
+    pub fn new(signals: &SigSet) -> Result<Self> {
+        signals.thread_block()?;
+        let fd = unsafe { libc::signalfd(-1, signals.as_ref(), libc::SFD_CLOEXEC) };
+        let fd = Errno::result(fd)?;
+        Ok(Self(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    /// Changes the set of signals received by the handle, and blocks them
+    /// in the calling thread.
+    ///
+    /// Signals that were removed from the set are not unblocked.
+    pub fn set_mask(&mut self, signals: &SigSet) -> Result<()> {
+        signals.thread_block()?;
+        let fd = unsafe { libc::signalfd(self.0.as_raw_fd(), signals.as_ref(), 0) };
+        Errno::result(fd).map(drop).op("signalfd")
+    }
+
+    /// Waits for, and reads, the next signal.
+    ///
+    /// If the handle is in non-blocking mode, this fails with `EAGAIN` if
+    /// there is no pending signal.
+    pub fn read(&self) -> Result<SigInfo> {
+        let mut info: libc::signalfd_siginfo = unsafe { mem::zeroed() };
+        let buf = unsafe {
+            std::slice::from_raw_parts_mut(
+                &mut info as *mut _ as *mut u8,
+                size_of::<libc::signalfd_siginfo>(),
+            )
+        };
+        if unistd::read(self.0.as_raw_fd(), buf)? != buf.len() {
+            return Err(Error::EIO);
+        }
+        SigInfo::from_raw(&info)
+    }
+
+    /// Waits up to the timeout for the next signal.
+    ///
+    /// Returns `None` if the timeout expired first.
+    pub fn read_timeout<T: Into<Timeout>>(&self, timeout: T) -> Result<Option<SigInfo>> {
+        match timeout::poll_fd(self.as_fd(), PollFlags::POLLIN, timeout)? {
+            true => self.read().map(Some),
+            false => Ok(None),
+        }
+    }
+}
+
+impl AsFd for SignalFd {
+    /// Gets the file handle for the signals.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for SignalFd {
+    /// Gets the raw file handle for the signals.
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for SignalFd {
+    /// Gives up ownership of the file handle for the signals.
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl From<SignalFd> for OwnedFd {
+    fn from(sfd: SignalFd) -> Self {
+        sfd.0
+    }
+}
+
+impl TryFrom<OwnedFd> for SignalFd {
+    type Error = Error;
+
+    /// Takes ownership of an existing signal handle, such as one passed from
+    /// another process.
+    ///
+    /// This fails with `EINVAL` if the handle isn't a signalfd.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        fd::check_anon_inode(fd.as_fd(), "signalfd")?;
+        Ok(Self(fd))
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fd::FdExt, test_util::in_child};
+    use nix::sys::signal;
+    use std::time::Duration;
+
+    #[test]
+    fn test_signalfd() {
+        in_child(|| {
+            let mut mask = SigSet::empty();
+            mask.add(Signal::SIGUSR1);
+            let mut sfd = SignalFd::new(&mask).unwrap();
+
+            assert_eq!(None, sfd.read_timeout(Duration::from_millis(10)).unwrap());
+
+            signal::raise(Signal::SIGUSR1).unwrap();
+            let info = sfd.read().unwrap();
+            assert_eq!(Signal::SIGUSR1, info.signal);
+            assert_eq!(unistd::getpid(), info.pid);
+            assert_eq!(unistd::getuid(), info.uid);
+
+            mask.add(Signal::SIGUSR2);
+            sfd.set_mask(&mask).unwrap();
+            sfd.set_nonblocking(true).unwrap();
+            assert_eq!(Error::EAGAIN, sfd.read().unwrap_err());
+
+            signal::raise(Signal::SIGUSR2).unwrap();
+            let info = sfd.read_timeout(Duration::from_secs(1)).unwrap();
+            assert_eq!(Some(Signal::SIGUSR2), info.map(|i| i.signal));
+        });
+    }
+}
@@ -0,0 +1,371 @@
+// hinix/src/syslog.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Logging to the system logger.
+//!
+//! This is a wrapper around the C library's syslog(3) functions, which
+//! send messages to the system logger (syslogd, rsyslog, journald, etc),
+//! typically over the /dev/log socket. This lets a daemon that has no
+//! terminal report what it's doing.
+//!
+//! ```no_run
+//! use hinix::syslog::{self, Facility, Level, LogOptions};
+//!
+//! syslog::open("mydaemon", LogOptions::LOG_PID, Facility::Daemon).unwrap();
+//! syslog::log(Level::Info, "Starting up");
+//! ```
+//!
+//! The logger is global to the process. If it isn't opened explicitly,
+//! it's opened on the first message with default settings.
+//!
+//! With the `log` feature, the system logger can also be installed as
+//! the backend for the [log](https://docs.rs/log/latest/log/) crate, so
+//! that the `log::info!()`, etc, macros go to it. See `init()`.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man3/syslog.3.html>
+//!
+
+use crate::{Error, Result};
+use bitflags::bitflags;
+use std::{
+    ffi::CString,
+    os::raw::{c_char, c_int},
+    sync::Mutex,
+};
+
+/// The identity string given to openlog().
+/// The C library keeps a pointer to it, so it must outlive the logger.
+static IDENT: Mutex<Option<CString>> = Mutex::new(None);
+
+bitflags! {
+    /// Options for the system logger.
+    pub struct LogOptions: c_int {
+        /// Include the PID with each message
+        const LOG_PID = libc::LOG_PID;
+        /// Write to the console if the message can't be sent to the logger
+        const LOG_CONS = libc::LOG_CONS;
+        /// Open the connection immediately, rather than on the first message
+        const LOG_NDELAY = libc::LOG_NDELAY;
+        /// Delay opening the connection until the first message (default)
+        const LOG_ODELAY = libc::LOG_ODELAY;
+        /// Also write each message to stderr
+        const LOG_PERROR = libc::LOG_PERROR;
+    }
+}
+
+/// The type of program logging a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Facility {
+    /// Kernel messages. These can't be generated from user processes.
+    Kern,
+    /// Generic user-level messages (default)
+    User,
+    /// The mail system
+    Mail,
+    /// System daemons without a separate facility
+    Daemon,
+    /// Security and authorization
+    Auth,
+    /// Messages generated internally by syslogd
+    Syslog,
+    /// The line printer subsystem
+    Lpr,
+    /// The network news subsystem
+    News,
+    /// The UUCP subsystem
+    Uucp,
+    /// The clock daemon (cron and at)
+    Cron,
+    /// Private security and authorization
+    AuthPriv,
+    /// The FTP daemon
+    Ftp,
+    /// Reserved for local use
+    Local0,
+    /// Reserved for local use
+    Local1,
+    /// Reserved for local use
+    Local2,
+    /// Reserved for local use
+    Local3,
+    /// Reserved for local use
+    Local4,
+    /// Reserved for local use
+    Local5,
+    /// Reserved for local use
+    Local6,
+    /// Reserved for local use
+    Local7,
+}
+
+impl Facility {
+    /// Gets the C library value for the facility.
+    pub fn as_raw(&self) -> c_int {
+        use Facility::*;
+        match self {
+            Kern => libc::LOG_KERN,
+            User => libc::LOG_USER,
+            Mail => libc::LOG_MAIL,
+            Daemon => libc::LOG_DAEMON,
+            Auth => libc::LOG_AUTH,
+            Syslog => libc::LOG_SYSLOG,
+            Lpr => libc::LOG_LPR,
+            News => libc::LOG_NEWS,
+            Uucp => libc::LOG_UUCP,
+            Cron => libc::LOG_CRON,
+            AuthPriv => libc::LOG_AUTHPRIV,
+            Ftp => libc::LOG_FTP,
+            Local0 => libc::LOG_LOCAL0,
+            Local1 => libc::LOG_LOCAL1,
+            Local2 => libc::LOG_LOCAL2,
+            Local3 => libc::LOG_LOCAL3,
+            Local4 => libc::LOG_LOCAL4,
+            Local5 => libc::LOG_LOCAL5,
+            Local6 => libc::LOG_LOCAL6,
+            Local7 => libc::LOG_LOCAL7,
+        }
+    }
+}
+
+/// The severity of a message, from the most to the least severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    /// The system is unusable
+    Emerg,
+    /// Action must be taken immediately
+    Alert,
+    /// Critical conditions
+    Crit,
+    /// Error conditions
+    Err,
+    /// Warning conditions
+    Warning,
+    /// Normal, but significant, condition
+    Notice,
+    /// Informational message
+    Info,
+    /// Debug-level message
+    Debug,
+}
+
+impl Level {
+    /// Gets the C library value for the level.
+    pub fn as_raw(&self) -> c_int {
+        use Level::*;
+        match self {
+            Emerg => libc::LOG_EMERG,
+            Alert => libc::LOG_ALERT,
+            Crit => libc::LOG_CRIT,
+            Err => libc::LOG_ERR,
+            Warning => libc::LOG_WARNING,
+            Notice => libc::LOG_NOTICE,
+            Info => libc::LOG_INFO,
+            Debug => libc::LOG_DEBUG,
+        }
+    }
+}
+
+/// The format for messages, so that they're never interpreted as one.
+const FMT: [c_char; 3] = [b'%' as c_char, b's' as c_char, 0];
+
+/// Converts a message to a C string, dropping any interior NULs rather
+/// than losing the message.
+fn to_cstring(msg: &str) -> CString {
+    CString::new(msg).unwrap_or_else(|_| {
+        let bytes: Vec<u8> = msg.bytes().filter(|&b| b != 0).collect();
+        CString::new(bytes).unwrap_or_default()
+    })
+}
+
+/// Opens a connection to the system logger.
+///
+/// The `ident` is prepended to every message, and is typically the name
+/// of the program. The `facility` is the default for messages that
+/// don't specify one.
+pub fn open(ident: &str, opts: LogOptions, facility: Facility) -> Result<()> {
+    let ident = CString::new(ident).map_err(|_| Error::EINVAL)?;
+    let mut cur = IDENT.lock().unwrap_or_else(|e| e.into_inner());
+    unsafe { libc::openlog(ident.as_ptr(), opts.bits(), facility.as_raw()) };
+    // The previous string is only dropped after the logger has the new one
+    *cur = Some(ident);
+    Ok(())
+}
+
+/// Sends a message to the system logger, with the default facility.
+pub fn log(level: Level, msg: &str) {
+    log_raw(level.as_raw(), msg);
+}
+
+/// Sends a message to the system logger, with the specified facility.
+pub fn log_with(facility: Facility, level: Level, msg: &str) {
+    log_raw(facility.as_raw() | level.as_raw(), msg);
+}
+
+fn log_raw(prio: c_int, msg: &str) {
+    let msg = to_cstring(msg);
+    // Hold the lock so the ident can't be replaced during the call
+    let _ident = IDENT.lock().unwrap_or_else(|e| e.into_inner());
+    unsafe { libc::syslog(prio, FMT.as_ptr(), msg.as_ptr()) };
+}
+
+/// Sets the lowest-priority level that will be logged. Messages that are
+/// less severe are discarded.
+///
+/// Returns the previous mask, as a raw value.
+pub fn set_max_level(level: Level) -> c_int {
+    // This is the LOG_UPTO() macro
+    let mask = (1 << (level.as_raw() + 1)) - 1;
+    unsafe { libc::setlogmask(mask) }
+}
+
+/// Closes the connection to the system logger.
+pub fn close() {
+    let mut ident = IDENT.lock().unwrap_or_else(|e| e.into_inner());
+    unsafe { libc::closelog() };
+    *ident = None;
+}
+
+// --------------------------------------------------------------------------
+
+#[cfg(feature = "log")]
+impl From<::log::Level> for Level {
+    /// Maps a `log` level to the system logger. There's nothing below
+    /// debug, so trace messages are sent at the debug level.
+    fn from(level: ::log::Level) -> Self {
+        match level {
+            ::log::Level::Error => Level::Err,
+            ::log::Level::Warn => Level::Warning,
+            ::log::Level::Info => Level::Info,
+            ::log::Level::Debug | ::log::Level::Trace => Level::Debug,
+        }
+    }
+}
+
+/// A backend for the `log` crate that sends messages to the system logger.
+///
+/// Most applications just use [`init()`] to install it.
+#[cfg(feature = "log")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Logger;
+
+#[cfg(feature = "log")]
+impl ::log::Log for Logger {
+    fn enabled(&self, metadata: &::log::Metadata) -> bool {
+        metadata.level() <= ::log::max_level()
+    }
+
+    fn log(&self, record: &::log::Record) {
+        if self.enabled(record.metadata()) {
+            log(Level::from(record.level()), &record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(feature = "log")]
+static LOGGER: Logger = Logger;
+
+/// Opens the system logger and installs it as the backend for the `log`
+/// crate, discarding messages less severe than `level`.
+///
+/// This fails with `EBUSY` if the application already set a logger.
+///
+/// ```no_run
+/// use hinix::syslog::{self, Facility, LogOptions};
+///
+/// syslog::init(
+///     "mydaemon",
+///     LogOptions::LOG_PID,
+///     Facility::Daemon,
+///     log::LevelFilter::Info,
+/// )
+/// .unwrap();
+/// log::info!("Starting up");
+/// ```
+#[cfg(feature = "log")]
+pub fn init(
+    ident: &str,
+    opts: LogOptions,
+    facility: Facility,
+    level: ::log::LevelFilter,
+) -> Result<()> {
+    open(ident, opts, facility)?;
+    ::log::set_logger(&LOGGER).map_err(|_| Error::EBUSY)?;
+    ::log::set_max_level(level);
+    Ok(())
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        assert!(Level::Err < Level::Info);
+        assert_eq!(3, Level::Err.as_raw());
+        assert_eq!(3 << 3, Facility::Daemon.as_raw());
+        assert_eq!(b"ab", to_cstring("a\0b").as_bytes());
+    }
+
+    #[test]
+    fn test_log() {
+        assert_eq!(
+            Error::EINVAL,
+            open("bad\0ident", LogOptions::empty(), Facility::User).unwrap_err()
+        );
+
+        open("hinix-test", LogOptions::LOG_PID, Facility::User).unwrap();
+        let prev = set_max_level(Level::Debug);
+        log(Level::Debug, "hinix syslog test");
+        log_with(
+            Facility::Local0,
+            Level::Info,
+            "hinix syslog test with facility",
+        );
+        unsafe { libc::setlogmask(prev) };
+        close();
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_log_backend() {
+        assert_eq!(Level::Err, Level::from(::log::Level::Error));
+        assert_eq!(Level::Debug, Level::from(::log::Level::Trace));
+
+        init(
+            "hinix-test",
+            LogOptions::LOG_PID,
+            Facility::User,
+            ::log::LevelFilter::Debug,
+        )
+        .unwrap();
+        assert!(::log::log_enabled!(::log::Level::Debug));
+        assert!(!::log::log_enabled!(::log::Level::Trace));
+        ::log::debug!("hinix log backend test");
+
+        // Only one logger can be installed
+        assert_eq!(
+            Error::EBUSY,
+            init(
+                "hinix-test",
+                LogOptions::empty(),
+                Facility::User,
+                ::log::LevelFilter::Info
+            )
+            .unwrap_err()
+        );
+    }
+}
@@ -0,0 +1,222 @@
+// hinix/src/system.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Information about the running system.
+//!
+//! These are thin, typed, wrappers around `sysconf()`, `uname()` and,
+//! on Linux, `sysinfo()`, for the handful of values that applications
+//! commonly need, like the number of CPUs, the amount of memory, or the
+//! version of the kernel.
+//!
+//! The `sysconf()` values are fixed for the life of the system (or at
+//! least the process), so the functions that read them don't fail. If
+//! the value can't be determined, a reasonable default is returned.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man3/sysconf.3.html>
+//! <https://man7.org/linux/man-pages/man2/sysinfo.2.html>
+//! <https://man7.org/linux/man-pages/man2/uname.2.html>
+//!
+
+use crate::{Error, Result};
+use nix::{
+    sys::utsname,
+    unistd::{self, SysconfVar},
+};
+use std::ffi::OsStr;
+
+/// The information returned by [`sysinfo()`].
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub use nix::sys::sysinfo::{sysinfo, SysInfo};
+
+/// Reads a sysconf value, using the default if it's unavailable.
+fn sysconf_or(var: SysconfVar, def: u64) -> u64 {
+    unistd::sysconf(var)
+        .ok()
+        .flatten()
+        .and_then(|n| u64::try_from(n).ok())
+        .unwrap_or(def)
+}
+
+/// Gets the size of a memory page on the system.
+pub fn page_size() -> usize {
+    sysconf_or(SysconfVar::PAGE_SIZE, 4096) as usize
+}
+
+/// Gets the number of clock ticks per second.
+///
+/// This is the unit for the process times reported by the kernel, such
+/// as in `times()` and the /proc filesystem.
+pub fn clock_ticks() -> u64 {
+    sysconf_or(SysconfVar::CLK_TCK, 100)
+}
+
+/// Gets the number of CPUs configured in the system.
+///
+/// This includes any that are currently offline.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub fn cpu_count() -> usize {
+    sysconf_or(SysconfVar::_NPROCESSORS_CONF, 1) as usize
+}
+
+/// Gets the number of CPUs that are currently online.
+///
+/// Note that the process may be restricted to a subset of these by
+/// its CPU affinity mask.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub fn online_cpus() -> usize {
+    sysconf_or(SysconfVar::_NPROCESSORS_ONLN, 1) as usize
+}
+
+/// Gets the total amount of usable RAM in the system, in bytes.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub fn total_memory() -> Result<u64> {
+    Ok(sysinfo()?.ram_total())
+}
+
+/// Gets the amount of RAM that is currently unused, in bytes.
+///
+/// Note that this doesn't include memory used by the kernel for caches
+/// and buffers, which could be reclaimed if needed.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub fn free_memory() -> Result<u64> {
+    Ok(sysinfo()?.ram_unused())
+}
+
+/// Gets the system load averages over the last 1, 5, and 15 minutes.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub fn load_average() -> Result<(f64, f64, f64)> {
+    Ok(sysinfo()?.load_average())
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// Identifying information about the running system and kernel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UtsName {
+    /// The name of the OS, like "Linux"
+    pub sysname: String,
+    /// The host name of the system on the network
+    pub nodename: String,
+    /// The kernel release, like "6.1.0-13-amd64"
+    pub release: String,
+    /// The kernel version, typically the build number and date
+    pub version: String,
+    /// The hardware type, like "x86_64" or "aarch64"
+    pub machine: String,
+}
+
+impl UtsName {
+    /// Gets the (major, minor, patch) numbers of the kernel release.
+    ///
+    /// Any missing numbers are reported as zero, so "6.1" gives
+    /// (6, 1, 0). Anything after the numbers, like a distribution
+    /// suffix, is ignored.
+    pub fn kernel_version(&self) -> Option<(u32, u32, u32)> {
+        parse_version(&self.release)
+    }
+}
+
+/// Gets identifying information about the running system and kernel.
+pub fn uname() -> Result<UtsName> {
+    let uts = utsname::uname()?;
+    let s = |val: &OsStr| val.to_string_lossy().into_owned();
+    Ok(UtsName {
+        sysname: s(uts.sysname()),
+        nodename: s(uts.nodename()),
+        release: s(uts.release()),
+        version: s(uts.version()),
+        machine: s(uts.machine()),
+    })
+}
+
+/// Gets the (major, minor, patch) version of the running kernel.
+///
+/// This is useful to determine whether newer kernel features are
+/// available, like:
+///
+/// ```
+/// # use hinix::system;
+/// let has_pidfd = system::kernel_version().unwrap() >= (5, 3, 0);
+/// ```
+///
+/// Fails with `EINVAL` if the kernel release can't be parsed.
+pub fn kernel_version() -> Result<(u32, u32, u32)> {
+    uname()?.kernel_version().ok_or(Error::EINVAL)
+}
+
+/// Parses the leading "major.minor.patch" from a release string.
+fn parse_version(release: &str) -> Option<(u32, u32, u32)> {
+    let end = release
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(release.len());
+
+    let mut nums = release[..end].split('.').map(|n| n.parse::<u32>().ok());
+    let major = nums.next()??;
+    let minor = nums.next().unwrap_or(Some(0))?;
+    let patch = nums.next().unwrap_or(Some(0)).unwrap_or(0);
+    Some((major, minor, patch))
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sysconf() {
+        let sz = page_size();
+        assert!(sz >= 4096);
+        assert!(sz.is_power_of_two());
+        assert!(clock_ticks() > 0);
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_sysinfo() {
+        let n = online_cpus();
+        assert!(n >= 1);
+        assert!(cpu_count() >= n);
+
+        let total = total_memory().unwrap();
+        assert!(total > 0);
+        assert!(free_memory().unwrap() <= total);
+
+        let (one, five, fifteen) = load_average().unwrap();
+        assert!(one >= 0.0 && five >= 0.0 && fifteen >= 0.0);
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(Some((6, 1, 0)), parse_version("6.1.0-13-amd64"));
+        assert_eq!(
+            Some((5, 15, 90)),
+            parse_version("5.15.90.1-microsoft-standard-WSL2")
+        );
+        assert_eq!(Some((6, 8, 0)), parse_version("6.8-rc3"));
+        assert_eq!(Some((4, 0, 0)), parse_version("4"));
+        assert_eq!(None, parse_version("Darwin"));
+        assert_eq!(None, parse_version(""));
+    }
+
+    #[test]
+    fn test_uname() {
+        let uts = uname().unwrap();
+        assert!(!uts.sysname.is_empty());
+        assert!(!uts.machine.is_empty());
+
+        let ver = kernel_version().unwrap();
+        assert_eq!(uts.kernel_version(), Some(ver));
+        assert!(ver.0 > 0);
+    }
+}
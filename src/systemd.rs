@@ -0,0 +1,427 @@
+// hinix/src/systemd.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Integration with the systemd service manager.
+//!
+//! This implements the simple, documented, protocols that systemd uses to
+//! talk to the services that it starts, without linking to libsystemd.
+//!
+//! A service with `Type=notify` tells the manager when it's finished
+//! starting up, when it's reloading or stopping, and can send it a free
+//! form status string and keep-alive pings for the watchdog:
+//!
+//! ```no_run
+//! use hinix::systemd::{self, State};
+//!
+//! // ...initialize...
+//! systemd::notify(&[State::Ready, State::Status("Running".into())]).unwrap();
+//! ```
+//!
+//! A socket-activated service inherits its listening sockets (or FIFOs)
+//! from the manager, which are adopted with [`listen_fds()`].
+//!
+//! See:
+//! <https://www.freedesktop.org/software/systemd/man/sd_notify.html>
+//! <https://www.freedesktop.org/software/systemd/man/sd_listen_fds.html>
+//!
+
+use crate::{
+    clock::{self, ClockId},
+    fd::FdExt,
+    Error, Result,
+};
+use nix::{
+    sys::{
+        socket::{
+            self, AddressFamily, MsgFlags, SockFlag, SockType, SockaddrLike, SockaddrStorage,
+            UnixAddr,
+        },
+        stat::{self, SFlag},
+    },
+    unistd::{self, Pid},
+};
+use std::{
+    env, fmt,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    },
+};
+
+/// The environment variable with the address of the notification socket.
+const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+
+/// The first file descriptor passed by socket activation.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// A state change or other notification to send to the service manager.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum State {
+    /// The service has finished starting up (READY=1)
+    Ready,
+    /// The service is reloading its configuration (RELOADING=1).
+    /// It should send [`State::Ready`] when it's done.
+    Reloading,
+    /// The service is shutting down (STOPPING=1)
+    Stopping,
+    /// A human-readable status string for the service (STATUS=...)
+    Status(String),
+    /// A keep-alive ping for the service watchdog (WATCHDOG=1)
+    Watchdog,
+    /// The main process of the service, if it's not the one sending the
+    /// notification (MAINPID=...)
+    MainPid(Pid),
+    /// Any other variable assignment, like "ERRNO=2"
+    Other(String),
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use State::*;
+        match self {
+            Ready => write!(f, "READY=1"),
+            Reloading => {
+                // Newer versions of systemd want the time of the reload
+                // request, to keep from mixing up multiple reloads.
+                let ts = clock::now(ClockId::CLOCK_MONOTONIC).unwrap_or_default();
+                write!(f, "RELOADING=1\nMONOTONIC_USEC={}", ts.as_micros())
+            }
+            Stopping => write!(f, "STOPPING=1"),
+            // A status can't span lines, or it would be parsed as more
+            // than one assignment.
+            Status(s) => write!(f, "STATUS={}", s.replace('\n', " ")),
+            Watchdog => write!(f, "WATCHDOG=1"),
+            MainPid(pid) => write!(f, "MAINPID={}", pid),
+            Other(s) => write!(f, "{}", s.replace('\n', " ")),
+        }
+    }
+}
+
+/// Sends the state changes to the service manager.
+///
+/// The address of the manager's socket is taken from the NOTIFY_SOCKET
+/// environment variable. This returns `false` if it isn't set, which
+/// means the process wasn't started by systemd, or the service isn't of
+/// `Type=notify`, and the states were not sent.
+pub fn notify(states: &[State]) -> Result<bool> {
+    let path = match env::var_os(NOTIFY_SOCKET) {
+        Some(path) if !path.is_empty() => path,
+        _ => return Ok(false),
+    };
+
+    // A leading '@' is an abstract socket
+    let path = path.as_bytes();
+    let addr = match path.strip_prefix(b"@") {
+        Some(name) => UnixAddr::new_abstract(name)?,
+        None if path.starts_with(b"/") => UnixAddr::new(path)?,
+        None => return Err(Error::EAFNOSUPPORT),
+    };
+
+    let msg = states
+        .iter()
+        .map(|state| state.to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let fd = socket::socket(
+        AddressFamily::Unix,
+        SockType::Datagram,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )?;
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let n = socket::sendto(
+        fd.as_raw_fd(),
+        msg.as_bytes(),
+        &addr,
+        MsgFlags::MSG_NOSIGNAL,
+    )?;
+    if n != msg.len() {
+        return Err(Error::EMSGSIZE);
+    }
+    Ok(true)
+}
+
+/// The type of a file descriptor passed in by socket activation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FdKind {
+    /// A Unix-domain socket
+    Unix,
+    /// An IPv4 or IPv6 socket
+    Inet,
+    /// A socket of some other address family, like netlink
+    Socket,
+    /// A FIFO (named pipe)
+    Fifo,
+    /// Anything else, like a regular file or character device
+    Other,
+}
+
+impl FdKind {
+    /// Determines the type of the open file.
+    fn of(fd: RawFd) -> Result<Self> {
+        let st = stat::fstat(fd)?;
+        let kind = match SFlag::from_bits_truncate(st.st_mode & SFlag::S_IFMT.bits()) {
+            SFlag::S_IFSOCK => {
+                let addr: SockaddrStorage = socket::getsockname(fd)?;
+                match addr.family() {
+                    Some(AddressFamily::Unix) => FdKind::Unix,
+                    Some(AddressFamily::Inet) | Some(AddressFamily::Inet6) => FdKind::Inet,
+                    _ => FdKind::Socket,
+                }
+            }
+            SFlag::S_IFIFO => FdKind::Fifo,
+            _ => FdKind::Other,
+        };
+        Ok(kind)
+    }
+
+    /// Determines if this is any type of socket.
+    pub fn is_socket(&self) -> bool {
+        matches!(self, FdKind::Unix | FdKind::Inet | FdKind::Socket)
+    }
+}
+
+/// A file descriptor passed in by socket activation.
+#[derive(Debug)]
+pub struct ListenFd {
+    /// The file descriptor
+    fd: OwnedFd,
+    /// The type of the file
+    kind: FdKind,
+    /// The name given in the unit's FileDescriptorName=, if any
+    name: Option<String>,
+}
+
+impl ListenFd {
+    /// Gets the type of the file.
+    pub fn kind(&self) -> FdKind {
+        self.kind
+    }
+
+    /// Gets the name assigned to the descriptor by the service manager.
+    ///
+    /// This defaults to the name of the socket unit, but might not be
+    /// passed by older versions of systemd.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+}
+
+impl AsFd for ListenFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for ListenFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl From<ListenFd> for OwnedFd {
+    fn from(fd: ListenFd) -> Self {
+        fd.fd
+    }
+}
+
+impl IntoRawFd for ListenFd {
+    /// Gives up ownership of the file handle for the descriptor.
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+impl TryFrom<OwnedFd> for ListenFd {
+    type Error = Error;
+
+    /// Uses an open file as a listen descriptor, without a name, such as
+    /// one that was passed to the process in some other way.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        let kind = FdKind::of(fd.as_raw_fd())?;
+        Ok(Self {
+            fd,
+            kind,
+            name: None,
+        })
+    }
+}
+
+/// Adopts the file descriptors passed to the process by socket
+/// activation.
+///
+/// This checks the LISTEN_PID, LISTEN_FDS, and LISTEN_FDNAMES environment
+/// variables set by the service manager. If they're not set, or were
+/// intended for a different process, this returns an empty list. If they
+/// are set, but are malformed, or name descriptors that aren't open, this
+/// fails with `EINVAL` or `EBADF`.
+///
+/// The descriptors start at 3, and are marked close-on-exec.
+///
+/// The variables are removed from the environment so that the
+/// descriptors can't be adopted twice, and aren't passed on to any
+/// child processes. So this should be called once, early, in the
+/// program, before any other threads are started.
+pub fn listen_fds() -> Result<Vec<ListenFd>> {
+    let vars = (
+        env::var("LISTEN_PID").ok(),
+        env::var("LISTEN_FDS").ok(),
+        env::var("LISTEN_FDNAMES").ok(),
+    );
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(var);
+    }
+
+    let (pid, n, names) = match vars {
+        (Some(pid), Some(n), names) => (pid, n, names),
+        _ => return Ok(Vec::new()),
+    };
+
+    let pid: libc::pid_t = pid.trim().parse().map_err(|_| Error::EINVAL)?;
+    if pid != unistd::getpid().as_raw() {
+        return Ok(Vec::new());
+    }
+
+    let n: RawFd = n.trim().parse().map_err(|_| Error::EINVAL)?;
+    if !(0..=RawFd::MAX - LISTEN_FDS_START).contains(&n) {
+        return Err(Error::EINVAL);
+    }
+
+    let names: Vec<Option<String>> = match names {
+        Some(names) => {
+            let names: Vec<_> = names.split(':').map(|s| Some(s.to_string())).collect();
+            if names.len() != n as usize {
+                return Err(Error::EINVAL);
+            }
+            names
+        }
+        None => vec![None; n as usize],
+    };
+
+    // Check them all before taking ownership of any
+    let fds: Vec<RawFd> = (LISTEN_FDS_START..LISTEN_FDS_START + n).collect();
+    let kinds = fds
+        .iter()
+        .map(|&fd| FdKind::of(fd))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut listen_fds = Vec::with_capacity(fds.len());
+    for ((fd, kind), name) in fds.into_iter().zip(kinds).zip(names) {
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        fd.set_cloexec(true)?;
+        listen_fds.push(ListenFd { fd, kind, name });
+    }
+    Ok(listen_fds)
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::in_child;
+    use std::{
+        fs,
+        os::unix::{
+            io::IntoRawFd,
+            net::{UnixDatagram, UnixListener},
+        },
+        process,
+    };
+
+    #[test]
+    fn test_state_display() {
+        assert_eq!("READY=1", State::Ready.to_string());
+        assert_eq!("STOPPING=1", State::Stopping.to_string());
+        assert_eq!("WATCHDOG=1", State::Watchdog.to_string());
+        assert_eq!("STATUS=a b", State::Status("a\nb".into()).to_string());
+        assert_eq!("MAINPID=42", State::MainPid(Pid::from_raw(42)).to_string());
+        assert_eq!("ERRNO=2", State::Other("ERRNO=2".into()).to_string());
+        assert!(State::Reloading
+            .to_string()
+            .starts_with("RELOADING=1\nMONOTONIC_USEC="));
+    }
+
+    #[test]
+    fn test_notify() {
+        let path = env::temp_dir().join(format!("hinix-notify-{}", process::id()));
+        let _ = fs::remove_file(&path);
+        let sock = UnixDatagram::bind(&path).unwrap();
+
+        env::set_var(NOTIFY_SOCKET, &path);
+        let sent = notify(&[State::Ready, State::Status("Up".into())]);
+        env::remove_var(NOTIFY_SOCKET);
+        assert_eq!(Ok(true), sent);
+
+        let mut buf = [0u8; 256];
+        let n = sock.recv(&mut buf).unwrap();
+        assert_eq!(b"READY=1\nSTATUS=Up", &buf[..n]);
+
+        assert_eq!(Ok(false), notify(&[State::Watchdog]));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_listen_fds() {
+        in_child(|| {
+            let path = env::temp_dir().join(format!("hinix-listen-{}", process::id()));
+            let _ = fs::remove_file(&path);
+            let sock = UnixListener::bind(&path).unwrap().into_raw_fd();
+            let (rd, wr) = unistd::pipe().unwrap();
+
+            // Move them into place, as the service manager would
+            for (fd, tmp) in [(sock, 100), (wr, 101)] {
+                unistd::dup2(fd, tmp).unwrap();
+            }
+            for fd in [sock, rd, wr] {
+                unistd::close(fd).unwrap();
+            }
+            for (tmp, fd) in [(100, 3), (101, 4)] {
+                unistd::dup2(tmp, fd).unwrap();
+                unistd::close(tmp).unwrap();
+            }
+
+            // Meant for someone else
+            env::set_var("LISTEN_PID", "1");
+            env::set_var("LISTEN_FDS", "2");
+            assert!(listen_fds().unwrap().is_empty());
+
+            // Names don't match the count
+            env::set_var("LISTEN_PID", unistd::getpid().to_string());
+            env::set_var("LISTEN_FDS", "2");
+            env::set_var("LISTEN_FDNAMES", "web");
+            assert_eq!(Error::EINVAL, listen_fds().unwrap_err());
+
+            // Already removed
+            assert!(listen_fds().unwrap().is_empty());
+
+            env::set_var("LISTEN_PID", unistd::getpid().to_string());
+            env::set_var("LISTEN_FDS", "2");
+            env::set_var("LISTEN_FDNAMES", "web:log");
+            let fds = listen_fds().unwrap();
+            assert!(env::var_os("LISTEN_FDS").is_none());
+
+            assert_eq!(2, fds.len());
+            assert_eq!(3, fds[0].as_raw_fd());
+            assert_eq!(FdKind::Unix, fds[0].kind());
+            assert!(fds[0].kind().is_socket());
+            assert_eq!(Some("web"), fds[0].name());
+            assert_eq!(FdKind::Fifo, fds[1].kind());
+            assert_eq!(Some("log"), fds[1].name());
+            assert!(fds[1].is_cloexec().unwrap());
+
+            let _ = fs::remove_file(&path);
+        });
+    }
+}
@@ -0,0 +1,370 @@
+// hinix/src/term.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Terminal control.
+//!
+//! Utilities for working with terminal (tty) devices through the termios
+//! interface.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man3/termios.3.html>
+//!
+
+use crate::{error::errno_location, fd::FdExt, pipe, Error, Result};
+use nix::{
+    errno::Errno,
+    sys::{
+        signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal},
+        termios::{self, LocalFlags, SetArg, SpecialCharacterIndices},
+    },
+    unistd,
+};
+use std::{
+    io::{self, Read},
+    mem::MaybeUninit,
+    os::{
+        raw::c_int,
+        unix::io::{AsFd, AsRawFd, OwnedFd},
+    },
+    hint,
+    sync::atomic::{AtomicI32, AtomicUsize, Ordering},
+    thread::{self, JoinHandle},
+};
+
+/// The size of a terminal window.
+pub use nix::pty::Winsize;
+
+/// The terminal settings.
+pub use nix::sys::termios::Termios;
+
+/// Determines if the handle refers to a terminal.
+pub fn is_tty<T: AsFd>(fd: &T) -> bool {
+    unistd::isatty(fd.as_fd().as_raw_fd()).unwrap_or(false)
+}
+
+/// Gets the window size of a terminal.
+///
+/// This fails with `ENOTTY` if the handle does not refer to a terminal.
+pub fn size<T: AsFd>(tty: &T) -> Result<Winsize> {
+    let fd = tty.as_fd().as_raw_fd();
+    let mut winsize = MaybeUninit::<Winsize>::zeroed();
+    Errno::result(unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, winsize.as_mut_ptr()) })?;
+    Ok(unsafe { winsize.assume_init() })
+}
+
+/// Sets the window size of a terminal.
+///
+/// This is normally only done by whatever is emulating the terminal,
+/// such as the master side of a pty. The kernel sends a SIGWINCH to the
+/// foreground process group of the terminal if the size changes.
+pub fn set_size<T: AsFd>(tty: &T, winsize: &Winsize) -> Result<()> {
+    let fd = tty.as_fd().as_raw_fd();
+    Errno::result(unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, winsize as *const Winsize) })?;
+    Ok(())
+}
+
+/// The input modes that can be applied to a terminal by a [`RawModeGuard`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputMode {
+    /// Raw mode.
+    ///
+    /// Input is available a character at a time, with no echo, and no
+    /// special processing of any characters. Signals are not generated
+    /// for interrupt keys like Ctrl-C. Output processing is also
+    /// disabled.
+    Raw,
+    /// Cbreak (rare) mode.
+    ///
+    /// Input is available a character at a time with no echo, but the
+    /// interrupt keys still generate signals, and output is processed
+    /// normally.
+    Cbreak,
+}
+
+/// A guard that puts a terminal into raw or cbreak mode, and restores the
+/// original settings when it goes out of scope.
+///
+/// The settings are restored when the guard is dropped, which includes
+/// when the stack unwinds from a panic. This prevents leaving the user's
+/// terminal in an unusable state if the application exits unexpectedly.
+#[derive(Debug)]
+pub struct RawModeGuard {
+    /// A handle to the terminal
+    fd: OwnedFd,
+    /// The original terminal settings
+    orig: Termios,
+}
+
+impl RawModeGuard {
+    /// Puts the standard input terminal into raw mode.
+    pub fn new() -> Result<Self> {
+        Self::with_mode(&io::stdin(), InputMode::Raw)
+    }
+
+    /// Puts the standard input terminal into cbreak mode.
+    pub fn cbreak() -> Result<Self> {
+        Self::with_mode(&io::stdin(), InputMode::Cbreak)
+    }
+
+    /// Puts the specified terminal into the requested mode.
+    ///
+    /// This fails with `ENOTTY` if the handle does not refer to a
+    /// terminal.
+    pub fn with_mode<T: AsFd>(tty: &T, mode: InputMode) -> Result<Self> {
+        let fd = tty.dup()?;
+
+        let orig = termios::tcgetattr(fd.as_raw_fd())?;
+        let mut tio = orig.clone();
+
+        match mode {
+            InputMode::Raw => termios::cfmakeraw(&mut tio),
+            InputMode::Cbreak => {
+                tio.local_flags
+                    .remove(LocalFlags::ICANON | LocalFlags::ECHO);
+                tio.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+                tio.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+            }
+        }
+
+        termios::tcsetattr(fd.as_raw_fd(), SetArg::TCSAFLUSH, &tio)?;
+        Ok(Self { fd, orig })
+    }
+
+    /// Gets the original settings of the terminal, which will be restored
+    /// when the guard is dropped.
+    pub fn original(&self) -> &Termios {
+        &self.orig
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        let _ = termios::tcsetattr(self.fd.as_raw_fd(), SetArg::TCSAFLUSH, &self.orig);
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// The write end of the pipe used by the SIGWINCH handler to wake up the
+/// watcher thread, or -1 if there is no active watcher.
+static WINCH_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// The number of handlers that are using `WINCH_FD`, so the pipe isn't
+/// closed out from under one of them.
+static WINCH_BUSY: AtomicUsize = AtomicUsize::new(0);
+
+/// The SIGWINCH handler. This just pokes the watcher thread, and leaves
+/// errno as it found it.
+extern "C" fn on_winch(_: c_int) {
+    let errno = unsafe { *errno_location() };
+    WINCH_BUSY.fetch_add(1, Ordering::SeqCst);
+    let fd = WINCH_FD.load(Ordering::SeqCst);
+    if fd >= 0 {
+        let buf = [0u8];
+        unsafe { libc::write(fd, buf.as_ptr() as *const libc::c_void, buf.len()) };
+    }
+    WINCH_BUSY.fetch_sub(1, Ordering::SeqCst);
+    unsafe { *errno_location() = errno };
+}
+
+/// Watches for changes to the size of a terminal.
+///
+/// The kernel sends a SIGWINCH to the process when the size of its
+/// controlling terminal changes. The watcher installs a handler for the
+/// signal, and calls the user's callback with the new size of the
+/// terminal each time it arrives. The callback is also called once with
+/// the current size when the watcher is created, so the application can
+/// do its initial layout in the same place as the later redraws.
+///
+/// The callback runs in a background thread, since very little is safe
+/// to do in a signal handler. The thread is stopped and the previous
+/// SIGWINCH handling restored when the watcher is dropped.
+///
+/// Since the signal handling is process-wide, only one watcher can be
+/// active at a time. Trying to create a second one fails with `EBUSY`.
+#[derive(Debug)]
+pub struct ResizeWatcher {
+    /// The write end of the wakeup pipe
+    wr_pipe: Option<pipe::WritePipe>,
+    /// The watcher thread
+    thread: Option<JoinHandle<()>>,
+    /// The SIGWINCH action that was replaced
+    prev: SigAction,
+}
+
+impl ResizeWatcher {
+    /// Creates a watcher that calls `on_resize` with the size of the
+    /// `tty` terminal, typically stdin, whenever it changes.
+    pub fn new<T, F>(tty: &T, mut on_resize: F) -> Result<Self>
+    where
+        T: AsFd,
+        F: FnMut(Winsize) + Send + 'static,
+    {
+        let tty = tty.dup()?;
+
+        let (wr_pipe, mut rd_pipe) = pipe::pipe()?;
+        wr_pipe.set_nonblocking(true)?;
+        let wr_fd = wr_pipe.as_raw_fd();
+
+        if WINCH_FD
+            .compare_exchange(-1, wr_fd, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(Error::EBUSY);
+        }
+
+        let action = SigAction::new(
+            SigHandler::Handler(on_winch),
+            SaFlags::SA_RESTART,
+            SigSet::empty(),
+        );
+        let prev = match unsafe { signal::sigaction(Signal::SIGWINCH, &action) } {
+            Ok(prev) => prev,
+            Err(err) => {
+                WINCH_FD.store(-1, Ordering::SeqCst);
+                return Err(err.into());
+            }
+        };
+
+        if let Ok(winsize) = size(&tty) {
+            on_resize(winsize);
+        }
+
+        let thread = thread::spawn(move || {
+            let mut buf = [0u8; 16];
+            while let Ok(n) = rd_pipe.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                if let Ok(winsize) = size(&tty) {
+                    on_resize(winsize);
+                }
+            }
+        });
+
+        Ok(Self {
+            wr_pipe: Some(wr_pipe),
+            thread: Some(thread),
+            prev,
+        })
+    }
+}
+
+impl Drop for ResizeWatcher {
+    fn drop(&mut self) {
+        let _ = unsafe { signal::sigaction(Signal::SIGWINCH, &self.prev) };
+
+        // Wait out any handler that's already writing to the pipe, before
+        // it gets closed and the number reused.
+        WINCH_FD.store(-1, Ordering::SeqCst);
+        while WINCH_BUSY.load(Ordering::SeqCst) != 0 {
+            hint::spin_loop();
+        }
+
+        // Closing the pipe signals the thread to exit.
+        drop(self.wr_pipe.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(all(test, feature = "pty"))]
+mod tests {
+    use super::*;
+    use crate::pty::Pty;
+    use std::panic;
+
+    fn lflags(pty: &Pty) -> LocalFlags {
+        termios::tcgetattr(pty.slave().as_raw_fd())
+            .unwrap()
+            .local_flags
+    }
+
+    #[test]
+    fn test_raw_mode() {
+        let pty = Pty::open().unwrap();
+        assert!(lflags(&pty).contains(LocalFlags::ICANON | LocalFlags::ISIG));
+
+        let guard = RawModeGuard::with_mode(pty.slave(), InputMode::Raw).unwrap();
+        assert!(!lflags(&pty).intersects(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG));
+
+        drop(guard);
+        assert!(lflags(&pty).contains(LocalFlags::ICANON | LocalFlags::ISIG));
+    }
+
+    #[test]
+    fn test_cbreak_mode() {
+        let pty = Pty::open().unwrap();
+
+        let guard = RawModeGuard::with_mode(pty.slave(), InputMode::Cbreak).unwrap();
+        let flags = lflags(&pty);
+        assert!(!flags.intersects(LocalFlags::ICANON | LocalFlags::ECHO));
+        assert!(flags.contains(LocalFlags::ISIG));
+
+        drop(guard);
+        assert!(lflags(&pty).contains(LocalFlags::ICANON | LocalFlags::ECHO));
+    }
+
+    #[test]
+    fn test_restore_on_panic() {
+        let pty = Pty::open().unwrap();
+
+        let res = panic::catch_unwind(|| {
+            let _guard = RawModeGuard::with_mode(pty.slave(), InputMode::Raw).unwrap();
+            panic!("oops");
+        });
+        assert!(res.is_err());
+        assert!(lflags(&pty).contains(LocalFlags::ICANON));
+    }
+
+    #[test]
+    fn test_is_tty() {
+        let pty = Pty::open().unwrap();
+        assert!(is_tty(pty.slave()));
+        assert!(is_tty(pty.master()));
+
+        let (wr, rd) = pipe::pipe().unwrap();
+        assert!(!is_tty(&wr));
+        assert!(!is_tty(&rd));
+    }
+
+    #[test]
+    fn test_size() {
+        let pty = Pty::open().unwrap();
+        let ws = Winsize {
+            ws_row: 33,
+            ws_col: 99,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        set_size(pty.master(), &ws).unwrap();
+
+        let ws = size(pty.slave()).unwrap();
+        assert_eq!((33, 99), (ws.ws_row, ws.ws_col));
+
+        let (_wr, rd) = pipe::pipe().unwrap();
+        assert_eq!(Error::ENOTTY, size(&rd).unwrap_err());
+    }
+
+    #[test]
+    fn test_not_a_tty() {
+        let (_wr, rd) = pipe::pipe().unwrap();
+        assert_eq!(
+            Error::ENOTTY,
+            RawModeGuard::with_mode(&rd, InputMode::Raw).unwrap_err()
+        );
+    }
+}
@@ -0,0 +1,47 @@
+// hinix/src/test_util.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Helpers shared by the unit tests.
+//!
+
+// Not every feature uses all of these
+#![allow(dead_code)]
+
+use nix::{
+    sys::wait::{waitpid, WaitStatus},
+    unistd::{fork, ForkResult},
+};
+use std::{panic, process};
+
+/// Runs the function in a forked child process, returning how the child
+/// ended. The child exits with 0 if the function returns, or 1 if it
+/// panics. This is for tests that change process-wide state, like the
+/// signal mask or the security settings, which would otherwise leak
+/// into the other tests.
+pub(crate) fn child_status<F: FnOnce()>(f: F) -> WaitStatus {
+    match unsafe { fork() }.unwrap() {
+        ForkResult::Child => {
+            let res = panic::catch_unwind(panic::AssertUnwindSafe(f));
+            process::exit(if res.is_ok() { 0 } else { 1 });
+        }
+        ForkResult::Parent { child } => waitpid(child, None).unwrap(),
+    }
+}
+
+/// Runs the function in a forked child process, and asserts that it
+/// returned without panicking.
+pub(crate) fn in_child<F: FnOnce()>(f: F) {
+    match child_status(f) {
+        WaitStatus::Exited(_, 0) => (),
+        status => panic!("Child failed: {:?}", status),
+    }
+}
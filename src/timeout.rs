@@ -0,0 +1,250 @@
+// hinix/src/timeout.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Timeouts for blocking operations.
+//!
+//! The operations in the crate that can wait for something take a
+//! [`Timeout`], which can be a time relative to the start of the call,
+//! an absolute deadline, or no timeout at all. Anything that converts
+//! into one can be used, like a `Duration`, an `Instant`, or an
+//! `Option<Duration>`:
+//!
+//! ```
+//! use hinix::Timeout;
+//! use std::time::{Duration, Instant};
+//!
+//! let after = Timeout::from(Duration::from_millis(100));
+//! let until = Timeout::from(Instant::now() + Duration::from_secs(1));
+//! let forever = Timeout::from(None);
+//! ```
+//!
+//! A timed wait that's interrupted by a signal (`EINTR`) is restarted
+//! for the time that remains, so the timeout is the total time spent
+//! waiting, no matter how many signals arrive.
+//!
+
+use crate::{
+    cancel::CancelToken,
+    clock::{self, ClockId},
+    error::ResultExt,
+    Error, Result,
+};
+use nix::poll::{self, PollFd, PollFlags};
+use std::{
+    os::{
+        raw::c_int,
+        unix::io::{AsRawFd, BorrowedFd},
+    },
+    time::{Duration, Instant},
+};
+
+/// How long a blocking operation can wait.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Timeout {
+    /// Wait forever
+    #[default]
+    None,
+    /// Wait for up to the duration, from the start of the operation
+    Duration(Duration),
+    /// Wait until the deadline
+    Deadline(Instant),
+}
+
+impl Timeout {
+    /// A timeout that expires immediately, to check for something
+    /// without waiting.
+    pub const ZERO: Self = Self::Duration(Duration::ZERO);
+
+    /// Gets the deadline for an operation that starts now, or `None` if
+    /// it can wait forever.
+    ///
+    /// A duration too long to be represented as a deadline is treated
+    /// like no timeout.
+    pub fn deadline(&self) -> Option<Instant> {
+        match *self {
+            Self::None => None,
+            Self::Duration(dur) => Instant::now().checked_add(dur),
+            Self::Deadline(deadline) => Some(deadline),
+        }
+    }
+}
+
+impl From<Duration> for Timeout {
+    fn from(dur: Duration) -> Self {
+        Self::Duration(dur)
+    }
+}
+
+impl From<Instant> for Timeout {
+    fn from(deadline: Instant) -> Self {
+        Self::Deadline(deadline)
+    }
+}
+
+impl From<Option<Duration>> for Timeout {
+    /// Converts an optional duration, where `None` means to wait forever.
+    fn from(dur: Option<Duration>) -> Self {
+        dur.map_or(Self::None, Self::Duration)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// The deadline of an operation in progress.
+///
+/// This is fixed when the operation starts, so that it can be restarted
+/// after an interrupt with the time remaining.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline(Option<Instant>);
+
+#[allow(dead_code)] // Not every feature uses all of these
+impl Deadline {
+    /// Starts the clock for an operation with the timeout.
+    pub fn start<T: Into<Timeout>>(timeout: T) -> Self {
+        Self(timeout.into().deadline())
+    }
+
+    /// Gets the time remaining, or `None` if there's no timeout.
+    ///
+    /// This is zero once the deadline has passed.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.0
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Determines if the deadline has passed.
+    pub fn is_expired(&self) -> bool {
+        self.remaining() == Some(Duration::ZERO)
+    }
+
+    /// Gets the time remaining as a timeout for poll(), in milliseconds,
+    /// or -1 to wait forever.
+    ///
+    /// This rounds up, so that the poll doesn't return just before the
+    /// deadline. A long timeout is clamped to the largest that poll()
+    /// takes, so a poll that times out should check `is_expired()` and
+    /// go around again if it isn't.
+    pub fn poll_ms(&self) -> c_int {
+        match self.remaining() {
+            Some(dur) => {
+                let ms = dur.as_millis() + u128::from(dur.subsec_nanos() % 1_000_000 != 0);
+                c_int::try_from(ms).unwrap_or(c_int::MAX)
+            }
+            None => -1,
+        }
+    }
+
+    /// Gets the deadline as an absolute time on the clock, for the calls
+    /// that take one, or `None` if there's no timeout.
+    pub fn on_clock(&self, clock: ClockId) -> Result<Option<libc::timespec>> {
+        match self.remaining() {
+            Some(dur) => Ok(Some(clock::to_timespec(clock::deadline(clock, dur)?)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Waits up to the timeout for a handle to be ready for any of the
+/// events, like `POLLIN`.
+///
+/// Returns `false` if the timeout expired first.
+#[allow(dead_code)]
+pub(crate) fn poll_fd<T: Into<Timeout>>(
+    fd: BorrowedFd,
+    events: PollFlags,
+    timeout: T,
+) -> Result<bool> {
+    poll_fd_cancel(fd, events, timeout, None)
+}
+
+/// Waits up to the timeout for a handle to be ready for any of the
+/// events, or for the token to be cancelled.
+///
+/// Returns `false` if the timeout expired first. Fails with `ECANCELED`
+/// if the token was cancelled, even if the handle is also ready.
+#[allow(dead_code)]
+pub(crate) fn poll_fd_cancel<T: Into<Timeout>>(
+    fd: BorrowedFd,
+    events: PollFlags,
+    timeout: T,
+    cancel: Option<&CancelToken>,
+) -> Result<bool> {
+    let deadline = Deadline::start(timeout);
+    // poll() skips a negative handle, for when there's no token
+    let cancel_fd = cancel.map_or(-1, |cancel| cancel.as_raw_fd());
+    let mut fds = [
+        PollFd::new(fd.as_raw_fd(), events),
+        PollFd::new(cancel_fd, PollFlags::POLLIN),
+    ];
+    #[cfg(feature = "metrics")]
+    let start = Instant::now();
+    let res = loop {
+        match fault_point!(Poll, poll::poll(&mut fds, deadline.poll_ms())) {
+            Ok(_) if cancel.is_some_and(|cancel| cancel.is_cancelled()) => {
+                break Err(Error::ECANCELED)
+            }
+            // The timeout might have been clamped to fit poll()
+            Ok(0) if !deadline.is_expired() => continue,
+            Ok(n) => break Ok(n > 0),
+            Err(err) if err == Error::EINTR => continue,
+            Err(err) => break Err(err).op("poll"),
+        }
+    };
+    #[cfg(feature = "metrics")]
+    crate::metrics::poll_wait(start.elapsed());
+    res
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let dur = Duration::from_millis(10);
+        assert_eq!(Timeout::Duration(dur), Timeout::from(dur));
+        assert_eq!(Timeout::Duration(dur), Timeout::from(Some(dur)));
+        assert_eq!(Timeout::None, Timeout::from(None));
+        assert_eq!(Timeout::None, Timeout::default());
+
+        let deadline = Instant::now();
+        assert_eq!(Timeout::Deadline(deadline), Timeout::from(deadline));
+        assert_eq!(Some(deadline), Timeout::from(deadline).deadline());
+
+        // Too long to be a deadline
+        assert_eq!(None, Timeout::from(Duration::MAX).deadline());
+    }
+
+    #[test]
+    fn test_deadline() {
+        let deadline = Deadline::start(Timeout::None);
+        assert_eq!(None, deadline.remaining());
+        assert_eq!(-1, deadline.poll_ms());
+
+        let deadline = Deadline::start(Timeout::ZERO);
+        assert!(deadline.is_expired());
+        assert_eq!(0, deadline.poll_ms());
+
+        let deadline = Deadline::start(Duration::from_secs(10));
+        assert!(!deadline.is_expired());
+        assert!(deadline.poll_ms() > 9000);
+        let ts = deadline.on_clock(ClockId::CLOCK_REALTIME).unwrap();
+        assert!(ts.is_some());
+
+        // Longer than poll() can wait
+        let deadline = Deadline::start(Duration::from_secs(30 * 24 * 3600));
+        assert_eq!(c_int::MAX, deadline.poll_ms());
+    }
+}
@@ -0,0 +1,224 @@
+// hinix/src/timerfd.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Timers that are notified through a file handle.
+//!
+//! A timerfd is a timer that can be waited on by reading its handle, or
+//! in combination with other handles in a poll/epoll/select call. Each
+//! read returns the number of times the timer expired since the last
+//! read, so a slow consumer can tell how many periods it missed.
+//!
+//! See:
+//! <https://man7.org/linux/man-pages/man2/timerfd_create.2.html>
+//!
+
+use crate::{
+    clock::{self, ClockId},
+    error::ResultExt,
+    fd, Error, Result,
+};
+use nix::{errno::Errno, sys::timerfd, unistd};
+use std::{
+    mem,
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    ptr,
+    time::Duration,
+};
+
+/// The flags used to create a TimerFd
+pub type TimerFlags = timerfd::TimerFlags;
+
+/// Converts a timespec to a duration.
+fn from_timespec(ts: &libc::timespec) -> Duration {
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// A timer that is read through a file handle.
+#[derive(Debug)]
+pub struct TimerFd(OwnedFd);
+
+impl TimerFd {
+    /// Creates a new, disarmed, timer using the specified clock.
+    ///
+    /// The clock can be CLOCK_REALTIME, CLOCK_MONOTONIC, or
+    /// CLOCK_BOOTTIME, which keeps counting while the system is
+    /// suspended.
+    pub fn new(clock: ClockId) -> Result<Self> {
+        Self::with_flags(clock, TimerFlags::empty())
+    }
+
+    /// Creates a new, disarmed, timer with the specified flags.
+    ///
+    /// The close-on-exec flag is always added.
+    pub fn with_flags(clock: ClockId, flags: TimerFlags) -> Result<Self> {
+        let flags = flags | TimerFlags::TFD_CLOEXEC;
+        let fd = unsafe { libc::timerfd_create(clock.as_raw(), flags.bits()) };
+        let fd = Errno::result(fd)?;
+        Ok(Self(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    fn settime(&self, value: Duration, interval: Duration, flags: i32) -> Result<()> {
+        let spec = libc::itimerspec {
+            it_value: clock::to_timespec(value)?,
+            it_interval: clock::to_timespec(interval)?,
+        };
+        let ret =
+            unsafe { libc::timerfd_settime(self.0.as_raw_fd(), flags, &spec, ptr::null_mut()) };
+        Errno::result(ret).map(drop).op("timerfd_settime")
+    }
+
+    /// Arms the timer to expire once, after the delay.
+    pub fn set_oneshot(&self, delay: Duration) -> Result<()> {
+        // A zero value would disarm the timer
+        self.settime(delay.max(Duration::from_nanos(1)), Duration::ZERO, 0)
+    }
+
+    /// Arms the timer to expire periodically, starting one interval
+    /// from now.
+    ///
+    /// This fails with `EINVAL` if the interval is zero.
+    pub fn set_periodic(&self, interval: Duration) -> Result<()> {
+        if interval.is_zero() {
+            return Err(Error::EINVAL);
+        }
+        self.settime(interval, interval, 0)
+    }
+
+    /// Arms the timer to expire at an absolute time on its clock, and
+    /// then, optionally, periodically after that.
+    ///
+    /// Scheduling against absolute times keeps a periodic timer from
+    /// drifting when it's restarted.
+    pub fn set_absolute(&self, deadline: Duration, interval: Option<Duration>) -> Result<()> {
+        let deadline = deadline.max(Duration::from_nanos(1));
+        let interval = interval.unwrap_or_default();
+        self.settime(deadline, interval, libc::TFD_TIMER_ABSTIME)
+    }
+
+    /// Disarms the timer.
+    pub fn disarm(&self) -> Result<()> {
+        self.settime(Duration::ZERO, Duration::ZERO, 0)
+    }
+
+    /// Gets the time until the timer next expires, or `None` if it's
+    /// disarmed.
+    pub fn remaining(&self) -> Result<Option<Duration>> {
+        let mut spec: libc::itimerspec = unsafe { mem::zeroed() };
+        let ret = unsafe { libc::timerfd_gettime(self.0.as_raw_fd(), &mut spec) };
+        Errno::result(ret)?;
+        let value = from_timespec(&spec.it_value);
+        Ok(if value.is_zero() { None } else { Some(value) })
+    }
+
+    /// Waits for the timer to expire, and returns the number of times it
+    /// expired since it was armed, or since the last read.
+    ///
+    /// A result greater than one means that periods were missed. If the
+    /// timer is non-blocking, this fails with `EAGAIN` if it hasn't yet
+    /// expired.
+    pub fn wait(&self) -> Result<u64> {
+        let mut buf = [0u8; 8];
+        if fault_point!(TimerWait, unistd::read(self.0.as_raw_fd(), &mut buf))? != buf.len() {
+            return Err(Error::EIO);
+        }
+        Ok(u64::from_ne_bytes(buf))
+    }
+}
+
+impl AsFd for TimerFd {
+    /// Gets the file handle for the timer.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for TimerFd {
+    /// Gets the raw file handle for the timer.
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for TimerFd {
+    /// Gives up ownership of the file handle for the timer.
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl From<TimerFd> for OwnedFd {
+    fn from(timer: TimerFd) -> Self {
+        timer.0
+    }
+}
+
+impl TryFrom<OwnedFd> for TimerFd {
+    type Error = Error;
+
+    /// Takes ownership of an existing timer, such as one passed from
+    /// another process.
+    ///
+    /// This fails with `EINVAL` if the handle isn't a timerfd.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        fd::check_anon_inode(fd.as_fd(), "timerfd")?;
+        Ok(Self(fd))
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock;
+    use std::{thread, time::Instant};
+
+    #[test]
+    fn test_oneshot() {
+        let tfd = TimerFd::new(ClockId::CLOCK_MONOTONIC).unwrap();
+        assert_eq!(None, tfd.remaining().unwrap());
+
+        let start = Instant::now();
+        tfd.set_oneshot(Duration::from_millis(20)).unwrap();
+        assert!(tfd.remaining().unwrap().is_some());
+
+        assert_eq!(1, tfd.wait().unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(None, tfd.remaining().unwrap());
+    }
+
+    #[test]
+    fn test_periodic() {
+        let tfd = TimerFd::with_flags(ClockId::CLOCK_MONOTONIC, TimerFlags::TFD_NONBLOCK).unwrap();
+        assert_eq!(Error::EINVAL, tfd.set_periodic(Duration::ZERO).unwrap_err());
+        assert_eq!(Error::EAGAIN, tfd.wait().unwrap_err());
+
+        tfd.set_periodic(Duration::from_millis(5)).unwrap();
+        thread::sleep(Duration::from_millis(30));
+
+        // Several periods were missed
+        assert!(tfd.wait().unwrap() >= 2);
+
+        tfd.disarm().unwrap();
+        assert_eq!(None, tfd.remaining().unwrap());
+    }
+
+    #[test]
+    fn test_absolute() {
+        let tfd = TimerFd::new(ClockId::CLOCK_MONOTONIC).unwrap();
+        let deadline = clock::now(ClockId::CLOCK_MONOTONIC).unwrap() + Duration::from_millis(10);
+        tfd.set_absolute(deadline, None).unwrap();
+        assert_eq!(1, tfd.wait().unwrap());
+        assert!(clock::now(ClockId::CLOCK_MONOTONIC).unwrap() >= deadline);
+    }
+}
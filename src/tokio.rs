@@ -0,0 +1,979 @@
+// hinix/src/tokio.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Async wrappers for use with the tokio runtime.
+//!
+//! Each of the types here takes ownership of one of the crate's handle
+//! types, puts it into non-blocking mode, and registers it with the
+//! tokio reactor through an `AsyncFd`. The blocking calls of the
+//! original type then become async functions that wait for the handle
+//! to be ready, rather than blocking the thread.
+//!
+//! The wrappers are all named for the type they hold, like
+//! [`AsyncEventFd`] for an [`EventFd`]. The original object can be
+//! reached with `get_ref()` to use the calls that don't block, like
+//! arming a timer, or taken back with `into_inner()`.
+//!
+//! The pipe and FIFO wrappers implement tokio's `AsyncRead` and
+//! `AsyncWrite` traits, so they can be used with the tokio I/O
+//! utilities. The event sources, like timers, signals, filesystem watches,
+//! and message queues, implement the futures `Stream` trait, so they can
+//! be combined with the usual stream adapters. In the other direction,
+//! the message queue and the [`AsyncPacketWriter`] implement the futures
+//! `Sink` trait, so they can be fed from a stream with `send_all()`.
+//!
+//! This requires the `tokio` feature, and must be used from within a
+//! tokio runtime.
+//!
+
+use crate::{
+    codec::PacketSender,
+    error::ResultExt,
+    eventfd::EventFd,
+    fd::{self, FdExt},
+    fifo::Fifo,
+    inotify::{Event, Inotify},
+    pidfd::PidFd,
+    pipe::{self, ReadPipe, WritePipe},
+    shutdown::ShutdownSignal,
+    signalfd::{SigInfo, SignalFd},
+    timerfd::TimerFd,
+    Error, Result,
+};
+use futures_core::Stream;
+use futures_sink::Sink;
+use nix::{
+    sys::{signal::SigSet, wait::WaitStatus},
+    unistd,
+};
+use std::{
+    collections::VecDeque,
+    future, io,
+    os::unix::io::{AsFd, AsRawFd},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use tokio::io::{unix::AsyncFd, AsyncRead, AsyncWrite, ReadBuf};
+
+#[cfg(target_os = "linux")]
+use crate::msgqueue::MsgQueue;
+
+/// Registers a handle, that's already non-blocking, with the reactor.
+fn register<T: AsRawFd>(inner: T) -> Result<AsyncFd<T>> {
+    AsyncFd::new(inner).map_err(Error::from)
+}
+
+/// Waits for the handle to be readable, then runs the operation on it,
+/// retrying if it would block.
+fn poll_read_with<T, R, F>(fd: &AsyncFd<T>, cx: &mut Context<'_>, mut f: F) -> Poll<Result<R>>
+where
+    T: AsRawFd,
+    F: FnMut(&T) -> Result<R>,
+{
+    loop {
+        let mut guard = ready!(fd.poll_read_ready(cx)).map_err(Error::from)?;
+        match f(fd.get_ref()) {
+            Err(err) if err == Error::EAGAIN => guard.clear_ready(),
+            res => return Poll::Ready(res),
+        }
+    }
+}
+
+/// Waits for the handle to be readable, then runs an operation that
+/// needs mutable access to it, retrying if it would block.
+fn poll_read_with_mut<T, R, F>(
+    fd: &mut AsyncFd<T>,
+    cx: &mut Context<'_>,
+    mut f: F,
+) -> Poll<Result<R>>
+where
+    T: AsRawFd,
+    F: FnMut(&mut T) -> Result<R>,
+{
+    loop {
+        let mut guard = ready!(fd.poll_read_ready_mut(cx)).map_err(Error::from)?;
+        match f(guard.get_inner_mut()) {
+            Err(err) if err == Error::EAGAIN => guard.clear_ready(),
+            res => return Poll::Ready(res),
+        }
+    }
+}
+
+/// Waits for the handle to be writable, then runs the operation on it,
+/// retrying if it would block.
+fn poll_write_with<T, R, F>(fd: &AsyncFd<T>, cx: &mut Context<'_>, mut f: F) -> Poll<Result<R>>
+where
+    T: AsRawFd,
+    F: FnMut(&T) -> Result<R>,
+{
+    loop {
+        let mut guard = ready!(fd.poll_write_ready(cx)).map_err(Error::from)?;
+        match f(fd.get_ref()) {
+            Err(err) if err == Error::EAGAIN => guard.clear_ready(),
+            res => return Poll::Ready(res),
+        }
+    }
+}
+
+/// Sends the message held by a sink, if there is one, waiting for the
+/// handle to be writable.
+///
+/// The message is dropped once it's sent, or if sending it fails.
+fn poll_send_pending<T>(
+    fd: &AsyncFd<T>,
+    pending: &mut Option<Vec<u8>>,
+    cx: &mut Context<'_>,
+) -> Poll<Result<()>>
+where
+    T: AsRawFd + PacketSender,
+{
+    if let Some(msg) = pending.as_deref() {
+        let res = ready!(poll_write_with(fd, cx, |inner| inner.send_packet(msg)));
+        *pending = None;
+        res?;
+    }
+    Poll::Ready(Ok(()))
+}
+
+/// Reads from the handle into the unfilled part of the buffer.
+fn poll_read_buf<T: AsFd + AsRawFd>(
+    fd: &AsyncFd<T>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+) -> Poll<io::Result<()>> {
+    // The unfilled part is read into without clearing it first. The read
+    // only ever writes to it, so nothing is de-initialized.
+    let n = ready!(poll_read_with(fd, cx, |inner| {
+        let unfilled = unsafe { buf.unfilled_mut() };
+        fd::read_uninit(inner.as_fd(), unfilled)
+            .map(|data| data.len())
+            .op("read")
+    }))?;
+    unsafe { buf.assume_init(n) };
+    buf.advance(n);
+    Poll::Ready(Ok(()))
+}
+
+/// Writes the buffer to the handle.
+fn poll_write_buf<T: AsRawFd>(
+    fd: &AsyncFd<T>,
+    cx: &mut Context<'_>,
+    buf: &[u8],
+) -> Poll<io::Result<usize>> {
+    let n = ready!(poll_write_with(fd, cx, |inner| {
+        unistd::write(inner.as_raw_fd(), buf).op("write")
+    }))?;
+    Poll::Ready(Ok(n))
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// An async event object.
+#[derive(Debug)]
+pub struct AsyncEventFd(AsyncFd<EventFd>);
+
+impl AsyncEventFd {
+    /// Creates an async event object from an existing one.
+    ///
+    /// This puts the handle into non-blocking mode.
+    pub fn new(evtfd: EventFd) -> Result<Self> {
+        evtfd.set_nonblocking(true)?;
+        Ok(Self(register(evtfd)?))
+    }
+
+    /// Waits for the event object to have a value, and reads it.
+    pub async fn read(&self) -> Result<u64> {
+        future::poll_fn(|cx| self.poll_read(cx)).await
+    }
+
+    /// Polls for a value from the event object.
+    pub fn poll_read(&self, cx: &mut Context<'_>) -> Poll<Result<u64>> {
+        poll_read_with(&self.0, cx, EventFd::read)
+    }
+
+    /// Writes a value to the event object.
+    ///
+    /// This only waits if the value would overflow the counter.
+    pub async fn write(&self, val: u64) -> Result<()> {
+        future::poll_fn(|cx| poll_write_with(&self.0, cx, |evtfd| evtfd.write(val))).await
+    }
+
+    /// Gets a reference to the event object.
+    pub fn get_ref(&self) -> &EventFd {
+        self.0.get_ref()
+    }
+
+    /// Unregisters the event object, and returns it.
+    ///
+    /// It is left in non-blocking mode.
+    pub fn into_inner(self) -> EventFd {
+        self.0.into_inner()
+    }
+}
+
+/// An async timer.
+#[derive(Debug)]
+pub struct AsyncTimerFd(AsyncFd<TimerFd>);
+
+impl AsyncTimerFd {
+    /// Creates an async timer from an existing one.
+    ///
+    /// This puts the handle into non-blocking mode. The timer can be
+    /// armed before or after, through [`AsyncTimerFd::get_ref()`].
+    pub fn new(timer: TimerFd) -> Result<Self> {
+        timer.set_nonblocking(true)?;
+        Ok(Self(register(timer)?))
+    }
+
+    /// Waits for the timer to expire, and returns the number of times it
+    /// expired since the last wait.
+    pub async fn wait(&self) -> Result<u64> {
+        future::poll_fn(|cx| self.poll_wait(cx)).await
+    }
+
+    /// Polls for the timer to expire.
+    pub fn poll_wait(&self, cx: &mut Context<'_>) -> Poll<Result<u64>> {
+        poll_read_with(&self.0, cx, TimerFd::wait)
+    }
+
+    /// Gets a reference to the timer.
+    pub fn get_ref(&self) -> &TimerFd {
+        self.0.get_ref()
+    }
+
+    /// Unregisters the timer, and returns it.
+    ///
+    /// It is left in non-blocking mode.
+    pub fn into_inner(self) -> TimerFd {
+        self.0.into_inner()
+    }
+}
+
+/// An async signal handle.
+#[derive(Debug)]
+pub struct AsyncSignalFd(AsyncFd<SignalFd>);
+
+impl AsyncSignalFd {
+    /// Creates an async signal handle from an existing one.
+    ///
+    /// This puts the handle into non-blocking mode. Note that the signals
+    /// must be blocked in all the runtime's threads, not just the one
+    /// that created the handle.
+    pub fn new(sfd: SignalFd) -> Result<Self> {
+        sfd.set_nonblocking(true)?;
+        Ok(Self(register(sfd)?))
+    }
+
+    /// Waits for, and reads, the next signal.
+    pub async fn read(&self) -> Result<SigInfo> {
+        future::poll_fn(|cx| self.poll_read(cx)).await
+    }
+
+    /// Polls for the next signal.
+    pub fn poll_read(&self, cx: &mut Context<'_>) -> Poll<Result<SigInfo>> {
+        poll_read_with(&self.0, cx, SignalFd::read)
+    }
+
+    /// Changes the set of signals received by the handle.
+    pub fn set_mask(&mut self, signals: &SigSet) -> Result<()> {
+        self.0.get_mut().set_mask(signals)
+    }
+
+    /// Gets a reference to the signal handle.
+    pub fn get_ref(&self) -> &SignalFd {
+        self.0.get_ref()
+    }
+
+    /// Unregisters the signal handle, and returns it.
+    ///
+    /// It is left in non-blocking mode.
+    pub fn into_inner(self) -> SignalFd {
+        self.0.into_inner()
+    }
+}
+
+/// An async set of filesystem watches.
+#[derive(Debug)]
+pub struct AsyncInotify {
+    /// The registered watches
+    ino: AsyncFd<Inotify>,
+    /// Events read but not yet returned by the stream
+    pending: VecDeque<Event>,
+}
+
+impl AsyncInotify {
+    /// Creates an async set of watches from an existing one.
+    ///
+    /// This puts the handle into non-blocking mode.
+    pub fn new(ino: Inotify) -> Result<Self> {
+        ino.set_nonblocking(true)?;
+        Ok(Self {
+            ino: register(ino)?,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Waits for, and reads, the next batch of events.
+    ///
+    /// As with [`Inotify::read_events()`], the batch can be empty.
+    pub async fn read_events(&mut self) -> Result<Vec<Event>> {
+        future::poll_fn(|cx| self.poll_read_events(cx)).await
+    }
+
+    /// Polls for the next batch of events.
+    pub fn poll_read_events(&mut self, cx: &mut Context<'_>) -> Poll<Result<Vec<Event>>> {
+        // Any left over from the stream come first
+        if !self.pending.is_empty() {
+            return Poll::Ready(Ok(self.pending.drain(..).collect()));
+        }
+        poll_read_with_mut(&mut self.ino, cx, Inotify::read_events)
+    }
+
+    /// Gets a reference to the watches.
+    pub fn get_ref(&self) -> &Inotify {
+        self.ino.get_ref()
+    }
+
+    /// Gets a mutable reference to the watches, to add or remove them.
+    pub fn get_mut(&mut self) -> &mut Inotify {
+        self.ino.get_mut()
+    }
+
+    /// Unregisters the watches, and returns them.
+    ///
+    /// The handle is left in non-blocking mode.
+    pub fn into_inner(self) -> Inotify {
+        self.ino.into_inner()
+    }
+}
+
+/// An async process handle.
+#[derive(Debug)]
+pub struct AsyncPidFd(AsyncFd<PidFd>);
+
+impl AsyncPidFd {
+    /// Creates an async process handle from an existing one.
+    pub fn new(pidfd: PidFd) -> Result<Self> {
+        Ok(Self(register(pidfd)?))
+    }
+
+    /// Opens an async handle to an existing process.
+    pub fn open(pid: unistd::Pid) -> Result<Self> {
+        Self::new(PidFd::open(pid)?)
+    }
+
+    /// Waits for the process to exit, without reaping it.
+    ///
+    /// This works for any process, not just children of the caller.
+    pub async fn exited(&self) -> Result<()> {
+        future::poll_fn(|cx| self.poll_exited(cx)).await
+    }
+
+    /// Polls for the process to exit.
+    pub fn poll_exited(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        // The handle stays readable once the process exits, so there's
+        // no need to clear the readiness.
+        let _guard = ready!(self.0.poll_read_ready(cx)).map_err(Error::from)?;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Waits for the process to exit and reaps it, returning its status.
+    ///
+    /// The process must be a child of the caller, otherwise this fails
+    /// with `ECHILD`.
+    pub async fn wait(&self) -> Result<WaitStatus> {
+        self.exited().await?;
+        self.0.get_ref().wait()
+    }
+
+    /// Gets a reference to the process handle.
+    pub fn get_ref(&self) -> &PidFd {
+        self.0.get_ref()
+    }
+
+    /// Unregisters the process handle, and returns it.
+    pub fn into_inner(self) -> PidFd {
+        self.0.into_inner()
+    }
+}
+
+/// An async signal to shut down.
+#[derive(Debug)]
+pub struct AsyncShutdownSignal(AsyncFd<ShutdownSignal>);
+
+impl AsyncShutdownSignal {
+    /// Creates an async shutdown signal from an existing one.
+    ///
+    /// A clone of the signal can be kept to trigger it.
+    pub fn new(shutdown: ShutdownSignal) -> Result<Self> {
+        Ok(Self(register(shutdown)?))
+    }
+
+    /// Waits for the signal to be triggered.
+    pub async fn wait(&self) -> Result<()> {
+        future::poll_fn(|cx| self.poll_wait(cx)).await
+    }
+
+    /// Polls for the signal to be triggered.
+    pub fn poll_wait(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        // The handle stays readable once the signal is triggered, so
+        // there's no need to clear the readiness.
+        let _guard = ready!(self.0.poll_read_ready(cx)).map_err(Error::from)?;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Gets a reference to the shutdown signal.
+    pub fn get_ref(&self) -> &ShutdownSignal {
+        self.0.get_ref()
+    }
+
+    /// Unregisters the shutdown signal, and returns it.
+    pub fn into_inner(self) -> ShutdownSignal {
+        self.0.into_inner()
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// Creates a pipe with async ends.
+pub fn pipe() -> Result<(AsyncWritePipe, AsyncReadPipe)> {
+    let (wr, rd) = pipe::pipe()?;
+    Ok((AsyncWritePipe::new(wr)?, AsyncReadPipe::new(rd)?))
+}
+
+/// The async read-end of a pipe.
+#[derive(Debug)]
+pub struct AsyncReadPipe(AsyncFd<ReadPipe>);
+
+impl AsyncReadPipe {
+    /// Creates an async read pipe from an existing one.
+    ///
+    /// This puts the handle into non-blocking mode.
+    pub fn new(rd: ReadPipe) -> Result<Self> {
+        rd.set_nonblocking(true)?;
+        Ok(Self(register(rd)?))
+    }
+
+    /// Unregisters the pipe, and returns it.
+    ///
+    /// It is left in non-blocking mode.
+    pub fn into_inner(self) -> ReadPipe {
+        self.0.into_inner()
+    }
+}
+
+impl AsyncRead for AsyncReadPipe {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        poll_read_buf(&self.0, cx, buf)
+    }
+}
+
+/// The async write-end of a pipe.
+#[derive(Debug)]
+pub struct AsyncWritePipe(AsyncFd<WritePipe>);
+
+impl AsyncWritePipe {
+    /// Creates an async write pipe from an existing one.
+    ///
+    /// This puts the handle into non-blocking mode.
+    pub fn new(wr: WritePipe) -> Result<Self> {
+        wr.set_nonblocking(true)?;
+        Ok(Self(register(wr)?))
+    }
+
+    /// Unregisters the pipe, and returns it.
+    ///
+    /// It is left in non-blocking mode.
+    pub fn into_inner(self) -> WritePipe {
+        self.0.into_inner()
+    }
+}
+
+impl AsyncWrite for AsyncWritePipe {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        poll_write_buf(&self.0, cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// An async FIFO (named pipe).
+#[derive(Debug)]
+pub struct AsyncFifo(AsyncFd<Fifo>);
+
+impl AsyncFifo {
+    /// Creates an async FIFO from one that's already open.
+    ///
+    /// This puts the handle into non-blocking mode.
+    pub fn new(fifo: Fifo) -> Result<Self> {
+        fifo.set_nonblocking(true)?;
+        Ok(Self(register(fifo)?))
+    }
+
+    /// Unregisters the FIFO, and returns it.
+    ///
+    /// It is left in non-blocking mode.
+    pub fn into_inner(self) -> Fifo {
+        self.0.into_inner()
+    }
+}
+
+impl AsyncRead for AsyncFifo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        poll_read_buf(&self.0, cx, buf)
+    }
+}
+
+impl AsyncWrite for AsyncFifo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        poll_write_buf(&self.0, cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Creates a packet pipe with async ends.
+///
+/// Each message sent through the writer is read back as a single packet.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub fn packet_pipe() -> Result<(AsyncPacketWriter, AsyncReadPipe)> {
+    let (wr, rd) = pipe::packet_pipe()?;
+    Ok((AsyncPacketWriter::new(wr)?, AsyncReadPipe::new(rd)?))
+}
+
+/// The async write-end of a packet pipe, as a `Sink` of messages.
+///
+/// Each message is written as a single packet, so it can be no larger
+/// than [`PIPE_BUF`](crate::pipe::PIPE_BUF).
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[derive(Debug)]
+pub struct AsyncPacketWriter {
+    /// The registered write end of the pipe
+    pipe: AsyncFd<WritePipe>,
+    /// A message accepted by the sink, but not yet written
+    pending: Option<Vec<u8>>,
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl AsyncPacketWriter {
+    /// Creates an async packet writer from the write end of a packet
+    /// pipe.
+    ///
+    /// This puts the handle into non-blocking mode.
+    pub fn new(wr: WritePipe) -> Result<Self> {
+        wr.set_nonblocking(true)?;
+        Ok(Self {
+            pipe: register(wr)?,
+            pending: None,
+        })
+    }
+
+    /// Gets a reference to the pipe.
+    pub fn get_ref(&self) -> &WritePipe {
+        self.pipe.get_ref()
+    }
+
+    /// Unregisters the pipe, and returns it.
+    ///
+    /// It is left in non-blocking mode. A message given to the sink that
+    /// wasn't flushed is dropped.
+    pub fn into_inner(self) -> WritePipe {
+        self.pipe.into_inner()
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// An async Posix message queue.
+///
+/// This relies on the queue descriptor being a file handle, which is
+/// only the case on Linux.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct AsyncMsgQueue {
+    /// The registered queue
+    mq: AsyncFd<MsgQueue>,
+    /// A message accepted by the sink, but not yet sent
+    pending: Option<Vec<u8>>,
+}
+
+#[cfg(target_os = "linux")]
+impl AsyncMsgQueue {
+    /// Creates an async message queue from one that's already open.
+    ///
+    /// This puts the queue into non-blocking mode.
+    pub fn new(mq: MsgQueue) -> Result<Self> {
+        mq.set_nonblock()?;
+        Ok(Self {
+            mq: register(mq)?,
+            pending: None,
+        })
+    }
+
+    /// Sends a message to the queue with the default priority, waiting
+    /// for space if the queue is full.
+    pub async fn send<M: AsRef<[u8]>>(&self, msg: M) -> Result<()> {
+        self.send_with_priority(msg, crate::msgqueue::DEFAULT_PRIO)
+            .await
+    }
+
+    /// Sends a message to the queue, waiting for space if the queue is
+    /// full.
+    pub async fn send_with_priority<M: AsRef<[u8]>>(&self, msg: M, prio: u32) -> Result<()> {
+        let msg = msg.as_ref();
+        future::poll_fn(|cx| poll_write_with(&self.mq, cx, |mq| mq.send_with_priority(msg, prio)))
+            .await
+    }
+
+    /// Waits for, and receives, a message into the buffer.
+    pub async fn receive(&self, msg: &mut [u8]) -> Result<usize> {
+        let mut prio = 0;
+        self.receive_with_priority(msg, &mut prio).await
+    }
+
+    /// Waits for, and receives, a message and its priority.
+    pub async fn receive_with_priority(&self, msg: &mut [u8], prio: &mut u32) -> Result<usize> {
+        future::poll_fn(|cx| poll_read_with(&self.mq, cx, |mq| mq.receive_with_priority(msg, prio)))
+            .await
+    }
+
+    /// Waits for, and receives, a message as a byte vector.
+    pub async fn receive_bytes(&self) -> Result<Vec<u8>> {
+        future::poll_fn(|cx| self.poll_receive_bytes(cx)).await
+    }
+
+    /// Polls for a message as a byte vector.
+    pub fn poll_receive_bytes(&self, cx: &mut Context<'_>) -> Poll<Result<Vec<u8>>> {
+        poll_read_with(&self.mq, cx, MsgQueue::receive_bytes)
+    }
+
+    /// Gets a reference to the queue.
+    pub fn get_ref(&self) -> &MsgQueue {
+        self.mq.get_ref()
+    }
+
+    /// Unregisters the queue, and returns it.
+    ///
+    /// It is left in non-blocking mode. A message given to the sink that
+    /// wasn't flushed is dropped.
+    pub fn into_inner(self) -> MsgQueue {
+        self.mq.into_inner()
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Streams
+
+impl Stream for AsyncTimerFd {
+    /// The number of times the timer expired since the last item.
+    type Item = Result<u64>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_wait(cx).map(Some)
+    }
+}
+
+impl Stream for AsyncSignalFd {
+    type Item = Result<SigInfo>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_read(cx).map(Some)
+    }
+}
+
+impl Stream for AsyncInotify {
+    /// The events, one at a time, rather than in batches.
+    type Item = Result<Event>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        while this.pending.is_empty() {
+            match ready!(this.poll_read_events(cx)) {
+                Ok(events) => this.pending.extend(events),
+                Err(err) => return Poll::Ready(Some(Err(err))),
+            }
+        }
+        Poll::Ready(this.pending.pop_front().map(Ok))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Stream for AsyncMsgQueue {
+    /// The messages received from the queue.
+    type Item = Result<Vec<u8>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_receive_bytes(cx).map(Some)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Sinks
+//
+// A sink holds one message at a time. It's copied when it's accepted,
+// then sent when the sink is flushed, or before the next one is taken.
+
+#[cfg(target_os = "linux")]
+impl<M: AsRef<[u8]>> Sink<M> for AsyncMsgQueue {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        poll_send_pending(&this.mq, &mut this.pending, cx)
+    }
+
+    /// Accepts a message to send with the default priority.
+    ///
+    /// This fails with `EMSGSIZE` if the message is larger than the
+    /// queue allows.
+    fn start_send(self: Pin<&mut Self>, msg: M) -> Result<()> {
+        let msg = msg.as_ref();
+        if msg.len() > self.mq.get_ref().max_send_size() {
+            return Err(Error::EMSGSIZE);
+        }
+        self.get_mut().pending = Some(msg.to_vec());
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        poll_send_pending(&this.mq, &mut this.pending, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        poll_send_pending(&this.mq, &mut this.pending, cx)
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+impl<M: AsRef<[u8]>> Sink<M> for AsyncPacketWriter {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        poll_send_pending(&this.pipe, &mut this.pending, cx)
+    }
+
+    /// Accepts a message to write as a single packet.
+    ///
+    /// This fails with `EMSGSIZE` if the message is larger than
+    /// `PIPE_BUF`.
+    fn start_send(self: Pin<&mut Self>, msg: M) -> Result<()> {
+        let msg = msg.as_ref();
+        if msg.len() > self.pipe.get_ref().max_send_size() {
+            return Err(Error::EMSGSIZE);
+        }
+        self.get_mut().pending = Some(msg.to_vec());
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        poll_send_pending(&this.pipe, &mut this.pending, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        poll_send_pending(&this.pipe, &mut this.pending, cx)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ClockId;
+    use futures_lite::StreamExt;
+    use std::{
+        env, fs, process,
+        time::{Duration, Instant},
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_eventfd() {
+        let evtfd = AsyncEventFd::new(EventFd::new(0).unwrap()).unwrap();
+        let other = evtfd.get_ref().try_clone().unwrap();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            other.write(5).unwrap();
+        });
+        assert_eq!(5, evtfd.read().await.unwrap());
+
+        evtfd.write(2).await.unwrap();
+        assert_eq!(2, evtfd.read().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_timerfd() {
+        let timer = AsyncTimerFd::new(TimerFd::new(ClockId::CLOCK_MONOTONIC).unwrap()).unwrap();
+        let start = Instant::now();
+        timer
+            .get_ref()
+            .set_oneshot(Duration::from_millis(20))
+            .unwrap();
+        assert_eq!(1, timer.wait().await.unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn test_timerfd_stream() {
+        let timer = AsyncTimerFd::new(TimerFd::new(ClockId::CLOCK_MONOTONIC).unwrap()).unwrap();
+        timer
+            .get_ref()
+            .set_periodic(Duration::from_millis(5))
+            .unwrap();
+
+        let ticks: Vec<_> = timer.take(3).collect().await;
+        assert_eq!(3, ticks.len());
+        assert!(ticks.iter().all(|n| *n.as_ref().unwrap() >= 1));
+    }
+
+    #[tokio::test]
+    async fn test_inotify_stream() {
+        use crate::inotify::WatchMask;
+
+        let dir = env::temp_dir().join(format!("hinix-tokio-ino-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut ino = AsyncInotify::new(Inotify::new().unwrap()).unwrap();
+        ino.get_mut().add_watch(&dir, WatchMask::IN_CREATE).unwrap();
+
+        fs::write(dir.join("a"), b"").unwrap();
+        fs::write(dir.join("b"), b"").unwrap();
+
+        let a = ino.next().await.unwrap().unwrap();
+        let b = ino.next().await.unwrap().unwrap();
+        assert_eq!(dir.join("a"), a.path);
+        assert_eq!(dir.join("b"), b.path);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let shutdown =
+            AsyncShutdownSignal::new(ShutdownSignal::with_signals(&[]).unwrap()).unwrap();
+        let other = shutdown.get_ref().clone();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            other.trigger();
+        });
+        shutdown.wait().await.unwrap();
+        assert!(shutdown.get_ref().is_triggered());
+    }
+
+    #[tokio::test]
+    async fn test_pipe() {
+        let (mut wr, mut rd) = pipe().unwrap();
+
+        tokio::spawn(async move {
+            wr.write_all(b"hello").await.unwrap();
+        });
+
+        let mut buf = Vec::new();
+        rd.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(b"hello", buf.as_slice());
+    }
+
+    #[tokio::test]
+    async fn test_msgqueue() {
+        const NAME: &str = "/hinix-tokio-test";
+        let mq = AsyncMsgQueue::new(MsgQueue::create(NAME, 2, 64).unwrap()).unwrap();
+        MsgQueue::unlink(NAME).unwrap();
+
+        mq.send(b"one").await.unwrap();
+        mq.send_with_priority(b"two", 1).await.unwrap();
+
+        let mut prio = 0;
+        let mut buf = [0u8; 64];
+        let n = mq.receive_with_priority(&mut buf, &mut prio).await.unwrap();
+        assert_eq!((b"two".as_slice(), 1), (&buf[..n], prio));
+        assert_eq!(b"one", mq.receive_bytes().await.unwrap().as_slice());
+    }
+
+    #[tokio::test]
+    async fn test_msgqueue_sink() {
+        use futures_util::SinkExt;
+
+        const NAME: &str = "/hinix-tokio-sink-test";
+        let mut mq = AsyncMsgQueue::new(MsgQueue::create(NAME, 2, 8).unwrap()).unwrap();
+        let rx = MsgQueue::open(NAME).unwrap();
+        MsgQueue::unlink(NAME).unwrap();
+
+        // More messages than the queue holds, so the sink has to wait
+        let reader = tokio::task::spawn_blocking(move || {
+            (0..5)
+                .map(|_| rx.receive_string().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        let mut msgs = futures_lite::stream::iter(["a", "b", "c", "d", "e"].map(Ok));
+        mq.send_all(&mut msgs).await.unwrap();
+        assert_eq!(vec!["a", "b", "c", "d", "e"], reader.await.unwrap());
+
+        assert_eq!(Error::EMSGSIZE, mq.send("too long!").await.unwrap_err());
+    }
+
+    #[tokio::test]
+    async fn test_packet_pipe_sink() {
+        use futures_util::SinkExt;
+
+        let (mut wr, mut rd) = packet_pipe().unwrap();
+        wr.send(b"one").await.unwrap();
+        wr.send(b"two").await.unwrap();
+
+        // Each message comes back as its own packet
+        let mut buf = [0u8; 16];
+        assert_eq!(3, rd.read(&mut buf).await.unwrap());
+        assert_eq!(b"one", &buf[..3]);
+        assert_eq!(3, rd.read(&mut buf).await.unwrap());
+        assert_eq!(b"two", &buf[..3]);
+
+        let big = vec![0u8; pipe::PIPE_BUF + 1];
+        assert_eq!(Error::EMSGSIZE, wr.send(big).await.unwrap_err());
+    }
+}
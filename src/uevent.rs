@@ -0,0 +1,379 @@
+// hinix/src/uevent.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Kernel device events (uevents).
+//!
+//! The kernel broadcasts a message over a netlink socket whenever a
+//! device is added, removed, or changed. This is what udev listens to in
+//! order to manage /dev, but any process can listen to them as well, to
+//! react to hotplug events without depending on libudev.
+//!
+//! Each event is a set of KEY=value properties, such as ACTION, DEVPATH,
+//! SUBSYSTEM, and DEVNAME, which are parsed into a [`UEvent`].
+//!
+//! Note that these are the raw kernel events, which are sent before udev
+//! has processed them, so any device node might not yet have been
+//! created or had its permissions set.
+//!
+//! See:
+//! <https://www.kernel.org/doc/html/latest/driver-api/driver-model/design-patterns.html>
+//!
+
+use crate::{
+    fd::{self, FdExt},
+    Error, Result,
+};
+use nix::{
+    errno::Errno,
+    sys::socket::{self, AddressFamily, MsgFlags, NetlinkAddr, SockFlag, SockProtocol, SockType},
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    mem::{self, size_of},
+    os::{
+        raw::c_void,
+        unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    },
+    str,
+};
+
+/// The netlink multicast group for kernel uevents.
+const KERNEL_GROUP: u32 = 1;
+
+/// The maximum size of a uevent message.
+const MAX_MSG_SIZE: usize = 8192;
+
+/// The type of a device event.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// A device was added
+    Add,
+    /// A device was removed
+    Remove,
+    /// A device changed state
+    Change,
+    /// A device was renamed or moved
+    Move,
+    /// A device, such as a CPU, was brought online
+    Online,
+    /// A device, such as a CPU, was taken offline
+    Offline,
+    /// A driver was bound to a device
+    Bind,
+    /// A driver was unbound from a device
+    Unbind,
+    /// Any other action
+    Other(String),
+}
+
+impl From<&str> for Action {
+    fn from(s: &str) -> Self {
+        use Action::*;
+        match s {
+            "add" => Add,
+            "remove" => Remove,
+            "change" => Change,
+            "move" => Move,
+            "online" => Online,
+            "offline" => Offline,
+            "bind" => Bind,
+            "unbind" => Unbind,
+            s => Other(s.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Action::*;
+        let s = match self {
+            Add => "add",
+            Remove => "remove",
+            Change => "change",
+            Move => "move",
+            Online => "online",
+            Offline => "offline",
+            Bind => "bind",
+            Unbind => "unbind",
+            Other(s) => s,
+        };
+        f.write_str(s)
+    }
+}
+
+/// A kernel device event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UEvent {
+    /// The type of event
+    pub action: Action,
+    /// The path of the device in sysfs, relative to /sys
+    pub devpath: String,
+    /// All the properties of the event, including those above
+    pub env: HashMap<String, String>,
+}
+
+impl UEvent {
+    /// Parses a kernel uevent message.
+    ///
+    /// The message is a header line of "action@devpath", followed by the
+    /// properties, each terminated by a NUL. Returns `None` if the
+    /// message isn't a valid kernel event. That includes the events
+    /// that udev re-broadcasts, which have a binary header.
+    pub fn parse(msg: &[u8]) -> Option<Self> {
+        let mut fields = msg
+            .split(|&b| b == 0)
+            .filter(|field| !field.is_empty())
+            .map(str::from_utf8);
+
+        let header = fields.next()?.ok()?;
+        let (action, devpath) = header.split_once('@')?;
+
+        let env = fields
+            .filter_map(|field| {
+                let (key, val) = field.ok()?.split_once('=')?;
+                Some((key.to_string(), val.to_string()))
+            })
+            .collect::<HashMap<_, _>>();
+
+        Some(Self {
+            action: Action::from(env.get("ACTION").map_or(action, String::as_str)),
+            devpath: env
+                .get("DEVPATH")
+                .map_or(devpath, String::as_str)
+                .to_string(),
+            env,
+        })
+    }
+
+    /// Gets the value of a property of the event.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.env.get(key).map(String::as_str)
+    }
+
+    /// Gets the subsystem of the device, like "block", "net", or "usb".
+    pub fn subsystem(&self) -> Option<&str> {
+        self.get("SUBSYSTEM")
+    }
+
+    /// Gets the type of device within the subsystem, like "disk" or
+    /// "partition".
+    pub fn devtype(&self) -> Option<&str> {
+        self.get("DEVTYPE")
+    }
+
+    /// Gets the name of the device node, relative to /dev, if it has one.
+    pub fn devname(&self) -> Option<&str> {
+        self.get("DEVNAME")
+    }
+
+    /// Gets the sequence number of the event.
+    pub fn seqnum(&self) -> Option<u64> {
+        self.get("SEQNUM").and_then(|s| s.parse().ok())
+    }
+}
+
+/// A listener for kernel device events.
+///
+/// The monitor is a netlink socket that can be used in a poll loop
+/// along with other handles. It becomes readable when an event arrives.
+#[derive(Debug)]
+pub struct Monitor {
+    /// The netlink socket
+    fd: OwnedFd,
+    /// The subsystems to report, or all of them if empty
+    subsystems: Vec<String>,
+}
+
+impl Monitor {
+    /// Creates a monitor for all kernel device events.
+    pub fn new() -> Result<Self> {
+        let fd = socket::socket(
+            AddressFamily::Netlink,
+            SockType::Datagram,
+            SockFlag::SOCK_CLOEXEC,
+            SockProtocol::NetlinkKObjectUEvent,
+        )?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        socket::bind(fd.as_raw_fd(), &NetlinkAddr::new(0, KERNEL_GROUP))?;
+        Ok(Self {
+            fd,
+            subsystems: Vec::new(),
+        })
+    }
+
+    /// Only reports events for devices in the subsystem.
+    ///
+    /// This can be called more than once to report events for several
+    /// subsystems.
+    pub fn match_subsystem(mut self, subsystem: &str) -> Self {
+        self.subsystems.push(subsystem.to_string());
+        self
+    }
+
+    /// Sets the monitor to non-blocking mode, so that [`Monitor::recv()`]
+    /// fails with `EAGAIN` if there's no event ready.
+    pub fn set_nonblocking(&self, on: bool) -> Result<()> {
+        self.fd.set_nonblocking(on)
+    }
+
+    /// Waits for the next event.
+    pub fn recv(&self) -> Result<UEvent> {
+        self.recv_with(MsgFlags::empty())
+    }
+
+    /// Gets the next event, if one is ready, without blocking.
+    pub fn try_recv(&self) -> Result<Option<UEvent>> {
+        match self.recv_with(MsgFlags::MSG_DONTWAIT) {
+            Ok(ev) => Ok(Some(ev)),
+            Err(err) if err == Error::EAGAIN => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Gets an iterator that waits for and returns events.
+    pub fn iter(&self) -> impl Iterator<Item = Result<UEvent>> + '_ {
+        std::iter::repeat_with(move || self.recv())
+    }
+
+    fn recv_with(&self, flags: MsgFlags) -> Result<UEvent> {
+        let mut buf = vec![0u8; MAX_MSG_SIZE];
+        loop {
+            let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+            let mut addr_len = size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+            let ret = unsafe {
+                libc::recvfrom(
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut c_void,
+                    buf.len(),
+                    flags.bits(),
+                    &mut addr as *mut libc::sockaddr_nl as *mut libc::sockaddr,
+                    &mut addr_len,
+                )
+            };
+            let n = match Errno::result(ret) {
+                Ok(n) => n as usize,
+                Err(Errno::EINTR) => continue,
+                Err(err) => return Err(Error::from(err).with_op("recvfrom")),
+            };
+
+            // Only accept messages that come from the kernel itself,
+            // which has a netlink port ID of zero.
+            if addr.nl_pid != 0 {
+                continue;
+            }
+
+            if let Some(ev) = UEvent::parse(&buf[..n]) {
+                if self.matches(&ev) {
+                    return Ok(ev);
+                }
+            }
+        }
+    }
+
+    fn matches(&self, ev: &UEvent) -> bool {
+        match ev.subsystem() {
+            _ if self.subsystems.is_empty() => true,
+            Some(sub) => self.subsystems.iter().any(|s| s == sub),
+            None => false,
+        }
+    }
+}
+
+impl AsFd for Monitor {
+    /// Gets the file handle for the monitor socket.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for Monitor {
+    /// Gets the raw file handle for the monitor socket.
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for Monitor {
+    /// Gives up ownership of the file handle for the monitor socket.
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+impl From<Monitor> for OwnedFd {
+    fn from(mon: Monitor) -> Self {
+        mon.fd
+    }
+}
+
+impl TryFrom<OwnedFd> for Monitor {
+    type Error = Error;
+
+    /// Takes ownership of an open uevent netlink socket, that reports all
+    /// the device events, like one passed from another process.
+    ///
+    /// This fails with `EINVAL` if the handle is some other kind of socket.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        fd::check_socket(fd.as_fd(), libc::AF_NETLINK, SockType::Datagram)?;
+        Ok(Self {
+            fd,
+            subsystems: Vec::new(),
+        })
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_parse() {
+        let msg = b"add@/devices/virtual/block/loop0\0ACTION=add\0\
+            DEVPATH=/devices/virtual/block/loop0\0SUBSYSTEM=block\0\
+            MAJOR=7\0MINOR=0\0DEVNAME=loop0\0DEVTYPE=disk\0SEQNUM=1234\0";
+
+        let ev = UEvent::parse(msg).unwrap();
+        assert_eq!(Action::Add, ev.action);
+        assert_eq!("/devices/virtual/block/loop0", ev.devpath);
+        assert_eq!(Some("block"), ev.subsystem());
+        assert_eq!(Some("disk"), ev.devtype());
+        assert_eq!(Some("loop0"), ev.devname());
+        assert_eq!(Some(1234), ev.seqnum());
+        assert_eq!(Some("7"), ev.get("MAJOR"));
+
+        assert_eq!(Action::Other("dock".into()), Action::from("dock"));
+        assert_eq!("unbind", Action::Unbind.to_string());
+
+        // A udev message has a binary header
+        assert!(UEvent::parse(b"libudev\0\xfe\xed\xca\xfe").is_none());
+        assert!(UEvent::parse(b"").is_none());
+    }
+
+    #[test]
+    fn test_monitor() {
+        let mon = Monitor::new().unwrap().match_subsystem("mem");
+
+        // Try to trigger a synthetic event. This needs root and a
+        // writable sysfs, so skip it if we can't.
+        if fs::write("/sys/class/mem/null/uevent", "change").is_err() {
+            return;
+        }
+        let ev = mon.recv().unwrap();
+        assert_eq!(Action::Change, ev.action);
+        assert_eq!(Some("mem"), ev.subsystem());
+        assert_eq!(Some("null"), ev.devname());
+    }
+}
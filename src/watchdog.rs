@@ -0,0 +1,276 @@
+// hinix/src/watchdog.rs
+//
+// This is part of the Rust 'hinix' crate
+//
+// Copyright (c) 2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Hardware watchdog timers.
+//!
+//! A watchdog resets the system if it isn't "pet" regularly. Opening the
+//! device, like /dev/watchdog, starts the timer, after which the
+//! application must call [`Watchdog::keepalive()`] more often than the
+//! timeout, or the system reboots.
+//!
+//! If the driver supports it, the watchdog is stopped by a "magic close",
+//! which writes a 'V' to the device just before closing it. This is done
+//! by [`Watchdog::close()`]. Simply dropping the watchdog closes it
+//! without the magic character, which leaves the timer running, so that
+//! an application that crashes still triggers a reset.
+//!
+//! See:
+//! <https://www.kernel.org/doc/html/latest/watchdog/watchdog-api.html>
+//!
+
+use crate::{fd, Error, Result};
+use bitflags::bitflags;
+use nix::{
+    errno::Errno,
+    fcntl::{self, OFlag},
+    request_code_read, request_code_readwrite,
+    sys::stat::{Mode, SFlag},
+    unistd,
+};
+use std::{
+    mem::{self, size_of},
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    path::Path,
+    time::Duration,
+};
+
+/// The default watchdog device.
+pub const DEFAULT_DEVICE: &str = "/dev/watchdog";
+
+// The ioctl requests from <linux/watchdog.h>
+const WDIOC_GETSUPPORT: u32 = request_code_read!(b'W', 0, size_of::<RawInfo>()) as u32;
+const WDIOC_GETSTATUS: u32 = request_code_read!(b'W', 1, size_of::<libc::c_int>()) as u32;
+const WDIOC_GETBOOTSTATUS: u32 = request_code_read!(b'W', 2, size_of::<libc::c_int>()) as u32;
+const WDIOC_KEEPALIVE: u32 = request_code_read!(b'W', 5, size_of::<libc::c_int>()) as u32;
+const WDIOC_SETTIMEOUT: u32 = request_code_readwrite!(b'W', 6, size_of::<libc::c_int>()) as u32;
+const WDIOC_GETTIMEOUT: u32 = request_code_read!(b'W', 7, size_of::<libc::c_int>()) as u32;
+const WDIOC_GETTIMELEFT: u32 = request_code_read!(b'W', 10, size_of::<libc::c_int>()) as u32;
+
+/// The kernel's `struct watchdog_info`
+#[repr(C)]
+struct RawInfo {
+    options: u32,
+    firmware_version: u32,
+    identity: [u8; 32],
+}
+
+bitflags! {
+    /// The capabilities of a watchdog, and the reasons it last fired.
+    pub struct WatchdogOptions: u32 {
+        /// The card overheated
+        const OVERHEAT = 0x0001;
+        /// The fan failed
+        const FANFAULT = 0x0002;
+        /// External relay 1
+        const EXTERN1 = 0x0004;
+        /// External relay 2
+        const EXTERN2 = 0x0008;
+        /// The power was bad, or the power supply failed
+        const POWERUNDER = 0x0010;
+        /// The card previously reset the CPU
+        const CARDRESET = 0x0020;
+        /// The power supply was over voltage
+        const POWEROVER = 0x0040;
+        /// The timeout can be set
+        const SETTIMEOUT = 0x0080;
+        /// Supports the magic close character
+        const MAGICCLOSE = 0x0100;
+        /// Supports a pre-timeout
+        const PRETIMEOUT = 0x0200;
+        /// The watchdog triggers a management or other external alarm,
+        /// not a reboot
+        const ALARMONLY = 0x0400;
+        /// Supports the keep-alive ping
+        const KEEPALIVEPING = 0x8000;
+    }
+}
+
+/// Information about a watchdog device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchdogInfo {
+    /// The name of the watchdog driver
+    pub identity: String,
+    /// The firmware version of the card, if applicable
+    pub firmware_version: u32,
+    /// The features that the watchdog supports
+    pub options: WatchdogOptions,
+}
+
+/// An open hardware watchdog timer.
+#[derive(Debug)]
+pub struct Watchdog(OwnedFd);
+
+impl Watchdog {
+    /// Opens the default watchdog device, which starts the timer.
+    pub fn new() -> Result<Self> {
+        Self::open(DEFAULT_DEVICE)
+    }
+
+    /// Opens a watchdog device, like /dev/watchdog1, which starts the
+    /// timer.
+    ///
+    /// Most drivers only allow the device to be opened by one process at
+    /// a time, and fail with `EBUSY` otherwise.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let fd = fcntl::open(
+            path.as_ref(),
+            OFlag::O_WRONLY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )?;
+        Ok(Self(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    /// Performs an ioctl() request that reads or writes an integer.
+    fn ioctl_int(&self, op: u32, val: libc::c_int) -> Result<libc::c_int> {
+        let mut val = val;
+        let ret = unsafe { libc::ioctl(self.0.as_raw_fd(), op as _, &mut val as *mut libc::c_int) };
+        Errno::result(ret)?;
+        Ok(val)
+    }
+
+    /// Gets information about the watchdog and the features it supports.
+    pub fn info(&self) -> Result<WatchdogInfo> {
+        let mut info: RawInfo = unsafe { mem::zeroed() };
+        let ret = unsafe {
+            libc::ioctl(
+                self.0.as_raw_fd(),
+                WDIOC_GETSUPPORT as _,
+                &mut info as *mut RawInfo,
+            )
+        };
+        Errno::result(ret)?;
+
+        let n = info.identity.iter().position(|&b| b == 0).unwrap_or(32);
+        Ok(WatchdogInfo {
+            identity: String::from_utf8_lossy(&info.identity[..n]).into_owned(),
+            firmware_version: info.firmware_version,
+            options: WatchdogOptions::from_bits_truncate(info.options),
+        })
+    }
+
+    /// Pets the watchdog, restarting the timer.
+    pub fn keepalive(&self) -> Result<()> {
+        self.ioctl_int(WDIOC_KEEPALIVE, 0).map(drop)
+    }
+
+    /// Gets the timeout, after which the watchdog fires if it isn't pet.
+    pub fn timeout(&self) -> Result<Duration> {
+        let secs = self.ioctl_int(WDIOC_GETTIMEOUT, 0)?;
+        Ok(Duration::from_secs(secs as u64))
+    }
+
+    /// Sets the timeout, which also pets the watchdog.
+    ///
+    /// The hardware only supports whole seconds, and may not support the
+    /// requested value at all, so this returns the timeout that was
+    /// actually set. A timeout less than a second is rounded up.
+    pub fn set_timeout(&self, timeout: Duration) -> Result<Duration> {
+        let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() != 0);
+        let secs = libc::c_int::try_from(secs.max(1)).map_err(|_| Error::EINVAL)?;
+        let secs = self.ioctl_int(WDIOC_SETTIMEOUT, secs)?;
+        Ok(Duration::from_secs(secs as u64))
+    }
+
+    /// Gets the time left before the watchdog fires.
+    ///
+    /// Not all drivers support this.
+    pub fn time_left(&self) -> Result<Duration> {
+        let secs = self.ioctl_int(WDIOC_GETTIMELEFT, 0)?;
+        Ok(Duration::from_secs(secs as u64))
+    }
+
+    /// Gets the current status of the watchdog.
+    pub fn status(&self) -> Result<WatchdogOptions> {
+        let bits = self.ioctl_int(WDIOC_GETSTATUS, 0)?;
+        Ok(WatchdogOptions::from_bits_truncate(bits as u32))
+    }
+
+    /// Gets the status of the watchdog at the last boot, like
+    /// `CARDRESET` if the watchdog caused the last reboot.
+    pub fn boot_status(&self) -> Result<WatchdogOptions> {
+        let bits = self.ioctl_int(WDIOC_GETBOOTSTATUS, 0)?;
+        Ok(WatchdogOptions::from_bits_truncate(bits as u32))
+    }
+
+    /// Closes the watchdog with the magic close character, which stops
+    /// the timer if the driver supports it.
+    ///
+    /// If the driver doesn't support the magic close, or the kernel was
+    /// built with CONFIG_WATCHDOG_NOWAYOUT, the timer keeps running and
+    /// the system resets when it expires.
+    pub fn close(self) -> Result<()> {
+        unistd::write(self.0.as_raw_fd(), b"V")?;
+        Ok(())
+    }
+}
+
+impl AsFd for Watchdog {
+    /// Gets the file handle for the watchdog.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for Watchdog {
+    /// Gets the raw file handle for the watchdog.
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for Watchdog {
+    /// Gives up ownership of the file handle for the watchdog.
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl From<Watchdog> for OwnedFd {
+    fn from(wdog: Watchdog) -> Self {
+        wdog.0
+    }
+}
+
+impl TryFrom<OwnedFd> for Watchdog {
+    type Error = Error;
+
+    /// Takes ownership of an open watchdog device.
+    ///
+    /// This fails with `EINVAL` if the handle isn't a character device.
+    fn try_from(fd: OwnedFd) -> Result<Self> {
+        fd::check_file_type(fd.as_fd(), SFlag::S_IFCHR)?;
+        Ok(Self(fd))
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_not_a_watchdog() {
+        // There's no watchdog on a test machine, but the requests should
+        // be rejected by any other device.
+        let wdog = Watchdog::open("/dev/null").unwrap();
+        assert_eq!(Error::ENOTTY, wdog.keepalive().unwrap_err());
+        assert_eq!(Error::ENOTTY, wdog.info().unwrap_err());
+        wdog.close().unwrap();
+
+        assert_eq!(
+            Error::ENOENT,
+            Watchdog::open("/dev/hinix-no-such-watchdog").unwrap_err()
+        );
+    }
+}
@@ -0,0 +1,138 @@
+// hinix/xtask/src/main.rs
+//
+// This utility application is part of the Rust 'hinix' package.
+//
+// Copyright (c) 2021-2023, Frank Pagliughi
+//
+// Licensed under the MIT license:
+//   <LICENSE or http://opensource.org/licenses/MIT>
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+//
+
+//! Build tasks for the hinix package, run as `cargo xtask <task>`.
+//!
+//! The `dist` task builds the utilities, then has the `hinix` program
+//! generate the man pages and shell completions for itself and all the
+//! utilities, for packaging:
+//!
+//! ```text
+//! target/dist/man/man1/hinix.1, hinix-mq-send.1, mqsend.1, ...
+//! target/dist/completions/bash/hinix, mqsend, ...
+//! target/dist/completions/zsh/_hinix, _mqsend, ...
+//! ```
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{self, Command},
+};
+
+/// The result type for the tasks.
+type Result<T> = std::result::Result<T, String>;
+
+/// The shells to generate completions for.
+const SHELLS: &[&str] = &["bash", "elvish", "fish", "powershell", "zsh"];
+
+/// Gets the conventional file name for a program's completions.
+fn completion_file(shell: &str, prog: &str) -> String {
+    match shell {
+        "elvish" => format!("{}.elv", prog),
+        "fish" => format!("{}.fish", prog),
+        "powershell" => format!("_{}.ps1", prog),
+        "zsh" => format!("_{}", prog),
+        _ => prog.to_string(),
+    }
+}
+
+/// Gets the top-level directory of the workspace.
+fn root_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap()
+        .to_path_buf()
+}
+
+/// Runs a command, returning its output, or an error if it fails.
+fn run(cmd: &mut Command) -> Result<Vec<u8>> {
+    let out = cmd
+        .output()
+        .map_err(|err| format!("unable to run {:?}: {}", cmd, err))?;
+    if !out.status.success() {
+        return Err(format!(
+            "{:?} failed: {}",
+            cmd,
+            String::from_utf8_lossy(&out.stderr)
+        ));
+    }
+    Ok(out.stdout)
+}
+
+/// Builds the utilities, and generates the man pages and completions.
+fn dist() -> Result<()> {
+    let root = root_dir();
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+
+    run(Command::new(&cargo).current_dir(&root).args([
+        "build",
+        "--release",
+        "--all-features",
+        "--bins",
+    ]))?;
+
+    let hinix = root.join("target/release/hinix");
+    let dist = root.join("target/dist");
+
+    let man_dir = dist.join("man/man1");
+    run(Command::new(&hinix).arg("man").arg("--dir").arg(&man_dir))?;
+    println!("Wrote the man pages to {}", man_dir.display());
+
+    // The man pages include one for each of the utilities that was built
+    let mut progs = vec!["hinix".to_string()];
+    for entry in fs::read_dir(&man_dir).map_err(|err| err.to_string())? {
+        let name = entry.map_err(|err| err.to_string())?.file_name();
+        let name = name.to_string_lossy();
+        if let Some(prog) = name.strip_suffix(".1") {
+            if !prog.starts_with("hinix") && root.join("target/release").join(prog).exists() {
+                progs.push(prog.to_string());
+            }
+        }
+    }
+    progs.sort();
+
+    for shell in SHELLS {
+        let dir = dist.join("completions").join(shell);
+        fs::create_dir_all(&dir).map_err(|err| err.to_string())?;
+        for prog in &progs {
+            let out = run(Command::new(&hinix).args(["completions", shell, prog]))?;
+            fs::write(dir.join(completion_file(shell, prog)), out)
+                .map_err(|err| err.to_string())?;
+        }
+    }
+    println!(
+        "Wrote the completions to {}",
+        dist.join("completions").display()
+    );
+    Ok(())
+}
+
+// --------------------------------------------------------------------------
+
+fn main() {
+    let task = env::args().nth(1);
+
+    let res = match task.as_deref() {
+        Some("dist") => dist(),
+        _ => {
+            eprintln!("Usage: cargo xtask <task>\n");
+            eprintln!("Tasks:");
+            eprintln!("  dist    Build the utilities, their man pages, and shell completions");
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = res {
+        eprintln!("xtask: {}", err);
+        process::exit(1);
+    }
+}